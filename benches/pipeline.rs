@@ -0,0 +1,65 @@
+//! A message's trip through the bot short of Twilio: parsing, the conversation and state
+//! machine, the backend calls (against the mock, served in-process) and the rendered
+//! reply. `cargo bench --bench pipeline`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::time::Duration;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::model::UserSessions;
+use kharon_pay_whatsapp::parser::parse_command;
+use kharon_pay_whatsapp::twilio;
+
+const INPUTS: &[&str] = &[
+    "hi",
+    "help",
+    "bal usdt",
+    "withdraw 1,000 USDT to Opay",
+    "send 25.5 usdc to access bank",
+    "what is this",
+];
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_command");
+    for input in INPUTS {
+        group.bench_with_input(BenchmarkId::from_parameter(input), input, |b, input| {
+            b.iter(|| parse_command(std::hint::black_box(input)))
+        });
+    }
+    group.finish();
+}
+
+/// Someone with a wallet, so every command goes as far as it can.
+fn account_holder() -> UserSessions {
+    let mut session = UserSessions::new("+2348099999999");
+    session.controller_address = Some("0x0bench".to_string());
+    session
+}
+
+fn dispatch(c: &mut Criterion) {
+    let base = common::spawn_app();
+    let runtime = tokio::runtime::Runtime::new().expect("runtime");
+    runtime.block_on(async {
+        let client = reqwest::Client::new();
+        while client.get(format!("{}/livez", base)).send().await.is_err() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    });
+
+    let mut group = c.benchmark_group("dispatch_message");
+    for input in ["help", "quote 100 USDT", "balance", "withdraw 100 USDT"] {
+        group.bench_with_input(BenchmarkId::from_parameter(input), input, |b, input| {
+            b.to_async(&runtime).iter(|| async {
+                let mut session = account_holder();
+                twilio::capture(dispatch_message(input, &mut session), Duration::ZERO).await
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, dispatch);
+criterion_main!(benches);
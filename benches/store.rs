@@ -0,0 +1,115 @@
+//! Session stores under concurrent traffic from distinct phones, with a background scan
+//! running alongside the way reminders and reconciliation do. One shard is the old
+//! single-map store. Contention only shows with as many cores as `THREADS`.
+//! `cargo bench --bench store`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::store::{MemorySessionStore, SessionStore, SqliteSessionStore};
+
+const THREADS: usize = 8;
+const PHONES_PER_THREAD: usize = 200;
+
+/// Seeds every phone the threads will touch, so loads hit and scans have work.
+fn seed(store: &dyn SessionStore) {
+    for thread in 0..THREADS {
+        for i in 0..PHONES_PER_THREAD {
+            let mut session = UserSessions::new(&phone(thread, i));
+            session.state = UserState::OfframpConfirmation;
+            store.save(&session).expect("seeded");
+        }
+    }
+}
+
+fn phone(thread: usize, i: usize) -> String {
+    format!("+234800{:02}{:05}", thread, i)
+}
+
+/// `ops` load-then-save turns split across `THREADS`, each on its own phones, while one
+/// more thread keeps scanning for stalled sessions. Returns the wall time.
+fn run(store: &Arc<dyn SessionStore>, ops: u64) -> Duration {
+    let per_thread = ops.div_ceil(THREADS as u64);
+    let done = Arc::new(AtomicBool::new(false));
+
+    let scanner = {
+        let (store, done) = (store.clone(), done.clone());
+        std::thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                let _ = store.stalled(Duration::ZERO);
+            }
+        })
+    };
+
+    let started = Instant::now();
+    let workers: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                for op in 0..per_thread {
+                    let phone = phone(thread, op as usize % PHONES_PER_THREAD);
+                    let mut session = store
+                        .load(&phone)
+                        .expect("loaded")
+                        .unwrap_or_else(|| UserSessions::new(&phone));
+                    session.amount_confirmation_attempts += 1;
+                    store.save(&session).expect("saved");
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("worker");
+    }
+    let elapsed = started.elapsed();
+
+    done.store(true, Ordering::Relaxed);
+    scanner.join().expect("scanner");
+    elapsed
+}
+
+fn stores(c: &mut Criterion) {
+    common::setup();
+    let sqlite_path = common::scratch_dir().join("bench-sessions.db");
+    let _ = std::fs::remove_file(&sqlite_path);
+
+    let candidates: Vec<(&str, Arc<dyn SessionStore>)> = vec![
+        (
+            "memory_1_shard",
+            Arc::new(MemorySessionStore::with_shards(1)),
+        ),
+        (
+            "memory_16_shards",
+            Arc::new(MemorySessionStore::with_shards(16)),
+        ),
+        (
+            "memory_64_shards",
+            Arc::new(MemorySessionStore::with_shards(64)),
+        ),
+        (
+            "sqlite",
+            Arc::new(
+                SqliteSessionStore::open(&sqlite_path.to_string_lossy()).expect("sqlite store"),
+            ),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("session_store");
+    group.sample_size(10);
+    for (name, store) in candidates {
+        seed(store.as_ref());
+        group.bench_with_input(BenchmarkId::from_parameter(name), &store, |b, store| {
+            b.iter_custom(|iters| run(store, iters))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, stores);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+use std::process::Command;
+
+/// Embeds the commit being built as `GIT_SHA`. A `GIT_SHA` set in the build environment
+/// wins (the Docker build has no `.git`); outside a checkout it's "unknown".
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let sha = std::env::var("GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.trim().is_empty())
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "--short=12", "HEAD"])
+                .output()
+                .ok()?;
+            let sha = String::from_utf8(output.stdout).ok()?;
+            (output.status.success() && !sha.trim().is_empty()).then(|| sha.trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_SHA={}", sha.trim());
+}
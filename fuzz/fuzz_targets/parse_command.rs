@@ -0,0 +1,10 @@
+#![no_main]
+
+use kharon_pay_whatsapp::parser;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    let _ = parser::parse_command(input);
+    let _ = parser::is_status_nudge(input);
+    let _ = parser::parse_amount(input);
+});
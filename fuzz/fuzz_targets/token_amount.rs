@@ -0,0 +1,20 @@
+#![no_main]
+
+use kharon_pay_whatsapp::money::TokenAmount;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (&str, u8)| {
+    let (raw, decimals) = input;
+    let decimals = u32::from(decimals);
+    for parsed in [
+        TokenAmount::from_backend(raw, decimals),
+        TokenAmount::parse(raw, decimals),
+    ] {
+        if let Ok(amount) = parsed {
+            let _ = amount.display();
+            let _ = amount.display_money("USDT");
+            let _ = amount.whole_tokens();
+            let _ = amount.at_most_f64();
+        }
+    }
+});
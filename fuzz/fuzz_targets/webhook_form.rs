@@ -0,0 +1,13 @@
+#![no_main]
+
+use std::collections::HashMap;
+
+use kharon_pay_whatsapp::inbound;
+use libfuzzer_sys::fuzz_target;
+
+// The same decoding `TwilioForm` does, then everything the webhook does before a session
+fuzz_target!(|body: &[u8]| {
+    if let Ok(form) = serde_urlencoded::from_bytes::<HashMap<String, String>>(body) {
+        let _ = inbound::classify(&form);
+    }
+});
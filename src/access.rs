@@ -0,0 +1,225 @@
+use std::collections::BTreeSet;
+use std::sync::{LazyLock, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::parser::{Command, parse_command};
+
+pub const WAITLIST_MESSAGE: &str = "👋 Thanks for your interest in KharonPay!\n\n\
+    We're in private beta and your number isn't on the list yet. \
+    We're opening up to more users soon; type `help` to see what KharonPay can do.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    Deny,
+    Allow,
+}
+
+/// What the bot may do for a sender, decided before any session is loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Allowed,
+    /// Denylisted: no reply at all.
+    Blocked,
+    /// Not invited while `ACCESS_MODE=allowlist`; only `hi`/`help` get through.
+    Waitlisted,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AccessLists {
+    #[serde(default)]
+    pub denylist: BTreeSet<String>,
+    #[serde(default)]
+    pub allowlist: BTreeSet<String>,
+}
+
+impl AccessLists {
+    fn list_mut(&mut self, kind: ListKind) -> &mut BTreeSet<String> {
+        match kind {
+            ListKind::Deny => &mut self.denylist,
+            ListKind::Allow => &mut self.allowlist,
+        }
+    }
+
+    /// `phone` is already normalized.
+    fn check(&self, phone: &str, message: &str, allowlist_mode: bool) -> Access {
+        if self.denylist.contains(phone) {
+            return Access::Blocked;
+        }
+        if allowlist_mode
+            && !self.allowlist.contains(phone)
+            && !matches!(parse_command(message), Command::Greet | Command::Help)
+        {
+            return Access::Waitlisted;
+        }
+        Access::Allowed
+    }
+}
+
+pub fn access_list_path() -> String {
+    std::env::var("ACCESS_LIST_PATH").unwrap_or_else(|_| "access_lists.json".to_string())
+}
+
+static LISTS: LazyLock<Mutex<AccessLists>> = LazyLock::new(|| {
+    let path = access_list_path();
+    let lists = match std::fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!(
+                "ALERT: Access lists at {} are unreadable ({}); starting empty",
+                path, e
+            );
+            AccessLists::default()
+        }),
+        Err(_) => AccessLists::default(),
+    };
+    Mutex::new(lists)
+});
+
+/// `ACCESS_MODE=allowlist` limits financial commands to invited numbers; the default,
+/// `open`, only applies the denylist.
+fn allowlist_mode() -> bool {
+    std::env::var("ACCESS_MODE").is_ok_and(|mode| mode.eq_ignore_ascii_case("allowlist"))
+}
+
+/// `whatsapp:+234 801…` and `234801…` are the same number; lists store `+234801…`.
+pub fn normalize_phone(phone: &str) -> Option<String> {
+    let digits: String = phone
+        .trim()
+        .trim_start_matches("whatsapp:")
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect();
+    (!digits.is_empty()).then(|| format!("+{}", digits))
+}
+
+pub fn check(phone: &str, message: &str) -> Access {
+    let Some(phone) = normalize_phone(phone) else {
+        return Access::Allowed;
+    };
+    let lists = LISTS.lock().unwrap_or_else(|e| e.into_inner());
+    lists.check(&phone, message, allowlist_mode())
+}
+
+pub fn entries(kind: ListKind) -> Vec<String> {
+    let lists = LISTS.lock().unwrap_or_else(|e| e.into_inner());
+    let list = match kind {
+        ListKind::Deny => &lists.denylist,
+        ListKind::Allow => &lists.allowlist,
+    };
+    list.iter().cloned().collect()
+}
+
+/// Adds `phone` and persists the lists. Returns whether it was newly added.
+pub fn add(kind: ListKind, phone: &str) -> Result<bool, String> {
+    let phone = normalize_phone(phone).ok_or_else(|| "phone has no digits".to_string())?;
+    let mut lists = LISTS.lock().unwrap_or_else(|e| e.into_inner());
+    let added = lists.list_mut(kind).insert(phone.clone());
+    if added && let Err(e) = persist(&lists) {
+        lists.list_mut(kind).remove(&phone);
+        return Err(e);
+    }
+    Ok(added)
+}
+
+/// Removes `phone` and persists the lists. Returns whether it was on the list.
+pub fn remove(kind: ListKind, phone: &str) -> Result<bool, String> {
+    let phone = normalize_phone(phone).ok_or_else(|| "phone has no digits".to_string())?;
+    let mut lists = LISTS.lock().unwrap_or_else(|e| e.into_inner());
+    let removed = lists.list_mut(kind).remove(&phone);
+    if removed && let Err(e) = persist(&lists) {
+        lists.list_mut(kind).insert(phone);
+        return Err(e);
+    }
+    Ok(removed)
+}
+
+/// Written to a temporary file and renamed, so a crash never leaves half a list behind.
+fn persist(lists: &AccessLists) -> Result<(), String> {
+    let path = access_list_path();
+    let tmp = format!("{}.tmp", path);
+    let json = serde_json::to_string_pretty(lists)
+        .map_err(|e| format!("Failed to serialize access lists: {}", e))?;
+    std::fs::write(&tmp, json).map_err(|e| format!("Failed to write {}: {}", tmp, e))?;
+    std::fs::rename(&tmp, &path).map_err(|e| format!("Failed to replace {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lists() -> AccessLists {
+        AccessLists {
+            denylist: ["+2348090001401".to_string()].into(),
+            allowlist: ["+2348090001402".to_string()].into(),
+        }
+    }
+
+    #[test]
+    fn open_mode_only_turns_away_the_denylist() {
+        let lists = lists();
+        for message in ["hi", "help", "balance", "withdraw 10 USDT"] {
+            assert_eq!(
+                lists.check("+2348090001401", message, false),
+                Access::Blocked,
+                "{}",
+                message
+            );
+            for phone in ["+2348090001402", "+2348090001403"] {
+                assert_eq!(
+                    lists.check(phone, message, false),
+                    Access::Allowed,
+                    "{} {}",
+                    phone,
+                    message
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn allowlist_mode_waitlists_strangers_but_lets_them_say_hi() {
+        let lists = lists();
+        for message in ["balance", "withdraw 10 USDT", "status KP-1"] {
+            assert_eq!(
+                lists.check("+2348090001403", message, true),
+                Access::Waitlisted,
+                "{}",
+                message
+            );
+            assert_eq!(
+                lists.check("+2348090001402", message, true),
+                Access::Allowed,
+                "{}",
+                message
+            );
+        }
+        for message in ["hi", "Hello", "help"] {
+            assert_eq!(
+                lists.check("+2348090001403", message, true),
+                Access::Allowed,
+                "{}",
+                message
+            );
+        }
+        // Even a greeting from a denylisted number goes unanswered
+        assert_eq!(lists.check("+2348090001401", "hi", true), Access::Blocked);
+    }
+
+    #[test]
+    fn every_way_of_writing_a_number_is_the_same_entry() {
+        for written in [
+            "+2348090001401",
+            "whatsapp:+2348090001401",
+            "2348090001401",
+            " +234 809 000 1401 ",
+        ] {
+            assert_eq!(
+                normalize_phone(written).as_deref(),
+                Some("+2348090001401"),
+                "{}",
+                written
+            );
+        }
+        assert_eq!(normalize_phone("whatsapp:"), None);
+    }
+}
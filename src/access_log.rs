@@ -0,0 +1,172 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use actix_web::{
+    Error, HttpMessage, HttpRequest,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+};
+use serde::Serialize;
+
+use crate::metrics;
+use crate::privacy::{mask_digit_runs, mask_phone};
+
+/// Upper bounds of the latency buckets, in milliseconds; anything slower is the last one.
+const LATENCY_BUCKETS_MS: &[(u64, &str)] = &[
+    (50, "<50ms"),
+    (200, "50-200ms"),
+    (1000, "200ms-1s"),
+    (5000, "1-5s"),
+];
+
+fn latency_bucket(ms: u64) -> &'static str {
+    LATENCY_BUCKETS_MS
+        .iter()
+        .find(|(bound, _)| ms < *bound)
+        .map(|(_, label)| *label)
+        .unwrap_or(">5s")
+}
+
+/// How the webhook handler dealt with a request.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Handling {
+    /// Handled before the response went out.
+    Inline,
+    /// Still running when the request deadline answered Twilio.
+    Background,
+    /// Acknowledged and ignored (empty, throttled, our own number...).
+    Dropped,
+}
+
+impl Handling {
+    fn as_str(self) -> &'static str {
+        match self {
+            Handling::Inline => "inline",
+            Handling::Background => "background",
+            Handling::Dropped => "dropped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct WebhookInfo {
+    kind: Option<String>,
+    phone: Option<String>,
+    handling: Option<Handling>,
+}
+
+/// What the webhook handler found out about a request, filled in as it goes. It's shared
+/// rather than returned so the access event still has it when the deadline cuts the
+/// request short.
+#[derive(Debug, Clone, Default)]
+pub struct WebhookNote(Arc<Mutex<WebhookInfo>>);
+
+impl WebhookNote {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        req.extensions()
+            .get::<WebhookNote>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn update(&self, change: impl FnOnce(&mut WebhookInfo)) {
+        change(&mut self.0.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    /// `status_callback`, `media`, `command:<name>`, `reply:<state>` and so on.
+    pub fn kind(&self, kind: impl Into<String>) {
+        let kind = kind.into();
+        self.update(|info| info.kind = Some(kind));
+    }
+
+    pub fn phone(&self, phone: &str) {
+        let phone = mask_phone(phone);
+        self.update(|info| info.phone = Some(phone));
+    }
+
+    pub fn handling(&self, handling: Handling) {
+        self.update(|info| info.handling = Some(handling));
+    }
+
+    fn snapshot(&self) -> WebhookInfo {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AccessEvent<'a> {
+    event: &'static str,
+    method: &'a str,
+    route: String,
+    status: u16,
+    latency_ms: u64,
+    latency_bucket: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook: Option<WebhookInfo>,
+}
+
+/// One JSON line per request, replacing actix's combined log. Routes are logged by their
+/// pattern (`/admin/sessions/{phone}`) rather than the raw path, so numbers in the URL
+/// stay out of the log. The latencies also go to the `metrics` histograms, which
+/// `ACCESS_LOG=off` leaves running while turning the log lines off.
+pub async fn log(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let path = mask_digit_runs(req.path());
+    // Unmatched paths are whatever a scanner tried, so they share one metrics series
+    let pattern = req.match_pattern();
+    let metrics_route = pattern.clone().unwrap_or_else(|| "unmatched".to_string());
+    let note = (req.path() == "/webhook").then(WebhookNote::default);
+    if let Some(note) = &note {
+        req.extensions_mut().insert(note.clone());
+    }
+
+    let result = next.call(req).await;
+    let elapsed = started.elapsed();
+    let latency_ms = elapsed.as_millis() as u64;
+
+    let status = match &result {
+        Ok(res) => res.status().as_u16(),
+        Err(e) => e.as_response_error().status_code().as_u16(),
+    };
+    let route = pattern.unwrap_or(path);
+    let webhook = note.map(|note| {
+        if result.is_err() {
+            // Only the deadline errors out of the webhook, and the message task lives on
+            note.handling(Handling::Background);
+        }
+        note.snapshot()
+    });
+
+    let status_label = status.to_string();
+    metrics::HTTP_REQUESTS.observe(&[&method, &metrics_route, &status_label], elapsed);
+    if let Some(webhook) = &webhook {
+        let kind = webhook.kind.as_deref().unwrap_or("none");
+        let handling = webhook
+            .handling
+            .map_or("none", |handling| handling.as_str());
+        metrics::WEBHOOK_REQUESTS.observe(&[kind, handling], elapsed);
+    }
+    if std::env::var("ACCESS_LOG").is_ok_and(|v| v.eq_ignore_ascii_case("off")) {
+        return result;
+    }
+
+    let event = AccessEvent {
+        event: "access",
+        method: &method,
+        route,
+        status,
+        latency_ms,
+        latency_bucket: latency_bucket(latency_ms),
+        webhook,
+    };
+    if let Ok(line) = serde_json::to_string(&event) {
+        println!("{}", line);
+    }
+    result
+}
@@ -0,0 +1,408 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+
+use crate::access::{self, ListKind};
+use crate::features::{self, FeatureFlag};
+use crate::privacy::mask_phone;
+use crate::store::{self, SessionStore};
+use crate::{audit, backend, callbacks, config, message_log, proxy, recording, state, summary};
+
+#[derive(Deserialize)]
+pub struct SummaryQuery {
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Deserialize)]
+pub struct MessagesQuery {
+    pub phone: String,
+    pub since: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct TrafficQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ListEntry {
+    pub phone: String,
+}
+
+/// Admin routes are disabled unless `ADMIN_API_KEY` is set, and then require it in `x-admin-key`.
+pub fn authorize(req: &HttpRequest) -> Option<HttpResponse> {
+    let expected = match config::secret("ADMIN_API_KEY") {
+        Ok(Some(key)) => key,
+        Ok(None) => return Some(HttpResponse::NotFound().finish()),
+        Err(e) => {
+            eprintln!("{}", e);
+            return Some(HttpResponse::NotFound().finish());
+        }
+    };
+
+    let provided = req
+        .headers()
+        .get("x-admin-key")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    // Constant time, so response timing doesn't leak how much of a guess was right
+    if !bool::from(provided.as_bytes().ct_eq(expected.as_bytes())) {
+        if let Some(ip) = proxy::client(req).ip {
+            eprintln!("Rejected admin request to {} from {}", req.path(), ip);
+        }
+        return Some(HttpResponse::Unauthorized().json(serde_json::json!({
+            "success": false,
+            "message": "Invalid admin key",
+        })));
+    }
+
+    None
+}
+
+pub async fn get_audit_trail(
+    req: HttpRequest,
+    reference: web::Path<String>,
+) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(&req) {
+        return Ok(denied);
+    }
+
+    match audit::trail_for_reference(&reference).await {
+        Ok(entries) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "reference": reference.as_str(),
+            "entries": entries,
+        }))),
+        Err(e) => {
+            eprintln!("Failed to load audit trail for {}: {}", reference, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "success": false,
+                "message": "Failed to load audit trail",
+            })))
+        }
+    }
+}
+
+/// Current conversation state for a phone number, with account numbers masked.
+pub async fn get_session(
+    req: HttpRequest,
+    phone: web::Path<String>,
+    sessions: web::Data<dyn SessionStore>,
+) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(&req) {
+        return Ok(denied);
+    }
+
+    let store = sessions.into_inner();
+    let key = phone.to_string();
+    match store::blocking(move || store.load(&key)).await {
+        Ok(Some(session)) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "session": session.redacted(),
+            "feature_flags": features::for_phone(&phone),
+        }))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "No session for this phone",
+        }))),
+        Err(e) => {
+            eprintln!("Failed to load session for admin view: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "success": false,
+                "message": "Failed to load session",
+            })))
+        }
+    }
+}
+
+/// Outbound messages sent to a phone, newest first, with their delivery status.
+pub async fn get_messages(
+    req: HttpRequest,
+    query: web::Query<MessagesQuery>,
+) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(&req) {
+        return Ok(denied);
+    }
+
+    match message_log::messages_for(&query.phone, query.since).await {
+        Ok(messages) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "messages": messages,
+        }))),
+        Err(e) => {
+            eprintln!("Failed to load message log: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "success": false,
+                "message": "Failed to load message log",
+            })))
+        }
+    }
+}
+
+/// The last `limit` (default 20, at most 200) backend exchanges recorded for a
+/// correlation id. Empty unless `RECORD_BACKEND_TRAFFIC` was on when they happened.
+pub async fn get_backend_traffic(
+    req: HttpRequest,
+    correlation_id: web::Path<String>,
+    query: web::Query<TrafficQuery>,
+) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(&req) {
+        return Ok(denied);
+    }
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 200);
+    match recording::for_correlation(&correlation_id, limit).await {
+        Ok(exchanges) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "correlation_id": correlation_id.as_str(),
+            "recording": recording::enabled(),
+            "exchanges": exchanges,
+        }))),
+        Err(e) => {
+            eprintln!(
+                "Failed to load backend traffic for {}: {}",
+                correlation_id, e
+            );
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "success": false,
+                "message": "Failed to load backend traffic",
+            })))
+        }
+    }
+}
+
+/// How often each state transition has been taken since startup, keyed `From->To`.
+pub async fn get_transitions(req: HttpRequest) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(&req) {
+        return Ok(denied);
+    }
+    Ok(HttpResponse::Ok().json(state::transition_counts()))
+}
+
+/// Aggregates the audit log for one day; defaults to yesterday in the summary timezone.
+pub async fn get_summary(
+    req: HttpRequest,
+    query: web::Query<SummaryQuery>,
+) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(&req) {
+        return Ok(denied);
+    }
+
+    let offset = summary::summary_offset();
+    let date = query.date.unwrap_or_else(|| summary::yesterday(offset));
+
+    match audit::read_entries().await {
+        Ok(entries) => Ok(HttpResponse::Ok().json(summary::summarize(&entries, date, offset))),
+        Err(e) => {
+            eprintln!("Failed to build summary for {}: {}", date, e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "success": false,
+                "message": "Failed to build summary",
+            })))
+        }
+    }
+}
+
+/// Re-reads `HMAC_KEY`/`HMAC_KEY_SECONDARY` and the `CALLBACK_HMAC_KEY` pair (or their
+/// `_FILE` variants) without a restart.
+pub async fn reload_keys(req: HttpRequest) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(&req) {
+        return Ok(denied);
+    }
+
+    let reloaded =
+        backend::reload_hmac_keys().and_then(|keys| Ok((keys, callbacks::reload_keys()?)));
+    match reloaded {
+        Ok((keys, callback_keys)) => {
+            println!("HMAC keys reloaded");
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "secondary_configured": keys.secondary.is_some(),
+                "callback_secondary_configured": callback_keys.secondary.is_some(),
+            })))
+        }
+        Err(e) => {
+            eprintln!("HMAC key reload failed: {}", e);
+            Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "success": false,
+                "message": e,
+            })))
+        }
+    }
+}
+
+/// Every configured feature flag; anything not listed is on for everyone.
+pub async fn get_feature_flags(req: HttpRequest) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(&req) {
+        return Ok(denied);
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "flags": features::snapshot(),
+    })))
+}
+
+/// Replaces a flag's rollout until the next restart.
+pub async fn set_feature_flag(
+    req: HttpRequest,
+    name: web::Path<String>,
+    flag: web::Json<FeatureFlag>,
+) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(&req) {
+        return Ok(denied);
+    }
+
+    let flag = flag.into_inner();
+    println!(
+        "Feature flag {} set: enabled={}, {}%, {} allowlisted",
+        name,
+        flag.enabled,
+        flag.percent,
+        flag.allowlist.len()
+    );
+    features::set(&name, flag);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "flags": features::snapshot(),
+    })))
+}
+
+/// Drops a flag, so its feature is on for everyone until the next restart.
+pub async fn remove_feature_flag(
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(&req) {
+        return Ok(denied);
+    }
+
+    if !features::remove(&name) {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "No such feature flag",
+        })));
+    }
+    println!("Feature flag {} removed; on for everyone", name);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "success": true })))
+}
+
+pub async fn get_blocklist(req: HttpRequest) -> Result<HttpResponse> {
+    list_entries(&req, ListKind::Deny)
+}
+
+pub async fn add_to_blocklist(
+    req: HttpRequest,
+    entry: web::Json<ListEntry>,
+) -> Result<HttpResponse> {
+    add_entry(&req, ListKind::Deny, &entry.phone)
+}
+
+pub async fn remove_from_blocklist(
+    req: HttpRequest,
+    phone: web::Path<String>,
+) -> Result<HttpResponse> {
+    remove_entry(&req, ListKind::Deny, &phone)
+}
+
+pub async fn get_allowlist(req: HttpRequest) -> Result<HttpResponse> {
+    list_entries(&req, ListKind::Allow)
+}
+
+pub async fn add_to_allowlist(
+    req: HttpRequest,
+    entry: web::Json<ListEntry>,
+) -> Result<HttpResponse> {
+    add_entry(&req, ListKind::Allow, &entry.phone)
+}
+
+pub async fn remove_from_allowlist(
+    req: HttpRequest,
+    phone: web::Path<String>,
+) -> Result<HttpResponse> {
+    remove_entry(&req, ListKind::Allow, &phone)
+}
+
+fn list_entries(req: &HttpRequest, kind: ListKind) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(req) {
+        return Ok(denied);
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "phones": access::entries(kind),
+    })))
+}
+
+fn add_entry(req: &HttpRequest, kind: ListKind, phone: &str) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(req) {
+        return Ok(denied);
+    }
+
+    match access::add(kind, phone) {
+        Ok(added) => {
+            println!("{:?} list: added {}", kind, mask_phone(phone));
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "added": added,
+            })))
+        }
+        Err(e) => {
+            eprintln!("Failed to update {:?} list: {}", kind, e);
+            Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "success": false,
+                "message": e,
+            })))
+        }
+    }
+}
+
+fn remove_entry(req: &HttpRequest, kind: ListKind, phone: &str) -> Result<HttpResponse> {
+    if let Some(denied) = authorize(req) {
+        return Ok(denied);
+    }
+
+    match access::remove(kind, phone) {
+        Ok(true) => {
+            println!("{:?} list: removed {}", kind, mask_phone(phone));
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "success": true })))
+        }
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Phone is not on the list",
+        }))),
+        Err(e) => {
+            eprintln!("Failed to update {:?} list: {}", kind, e);
+            Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "success": false,
+                "message": e,
+            })))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn only_the_exact_admin_key_is_let_through() {
+        // SAFETY: no other test in this binary reads ADMIN_API_KEY
+        unsafe { std::env::set_var("ADMIN_API_KEY", "admin-secret") };
+        let with_key = |key: &str| {
+            TestRequest::default()
+                .insert_header(("x-admin-key", key))
+                .to_http_request()
+        };
+
+        assert!(authorize(&with_key("admin-secret")).is_none());
+        for wrong in ["admin-secreT", "admin-secre", "admin-secret2", ""] {
+            let denied = authorize(&with_key(wrong)).expect("refused");
+            assert_eq!(denied.status(), 401, "{:?}", wrong);
+        }
+        let missing = authorize(&TestRequest::default().to_http_request()).expect("refused");
+        assert_eq!(missing.status(), 401);
+    }
+}
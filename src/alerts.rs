@@ -0,0 +1,150 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+use crate::money;
+use crate::privacy::mask_phone;
+use crate::server::send_twilio_message;
+
+/// A payout that needs someone on our side to look at it.
+#[derive(Debug, Clone, Serialize)]
+pub struct PayoutAlert {
+    pub reference: String,
+    pub phone: String,
+    pub amount: Option<f64>,
+    pub currency: Option<String>,
+    pub bank_name: Option<String>,
+    pub status: String,
+}
+
+impl PayoutAlert {
+    pub fn new(reference: &str, user_phone: &str, status: &str) -> Self {
+        PayoutAlert {
+            reference: reference.to_string(),
+            phone: mask_phone(user_phone),
+            amount: None,
+            currency: None,
+            bank_name: None,
+            status: status.to_string(),
+        }
+    }
+
+    fn text(&self) -> String {
+        let amount = match self.amount {
+            Some(amount) => money::format_money(amount, self.currency.as_deref().unwrap_or("")),
+            None => "unknown".to_string(),
+        };
+
+        format!(
+            "🚨 *Payout failed*\n\
+            🔢 Reference: {}\n\
+            📱 User: {}\n\
+            💰 Amount: {}\n\
+            🏦 Bank: {}\n\
+            ❌ Status: {}",
+            self.reference,
+            self.phone,
+            amount,
+            self.bank_name.as_deref().unwrap_or("unknown"),
+            self.status
+        )
+    }
+}
+
+/// When each reference was last alerted on, so poller retries don't repeat the alert.
+static LAST_ALERTED: LazyLock<Mutex<HashMap<String, DateTime<Utc>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn dedupe_window() -> Duration {
+    Duration::seconds(
+        std::env::var("OPS_ALERT_DEDUPE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60 * 60),
+    )
+}
+
+/// `OPS_ALERT_PHONES`, falling back to the `ADMIN_PHONES` that receive the daily digest.
+fn alert_phones() -> Vec<String> {
+    std::env::var("OPS_ALERT_PHONES")
+        .or_else(|_| std::env::var("ADMIN_PHONES"))
+        .unwrap_or_default()
+        .split(',')
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+/// Sends `alert` to `OPS_SLACK_WEBHOOK_URL` and the ops phones, at most once per reference
+/// per `OPS_ALERT_DEDUPE_SECS` (default 1h). Always logs, even with no destination set.
+pub async fn payout_failed(alert: PayoutAlert) {
+    eprintln!(
+        "ALERT: Payout {} failed for {} with status {}",
+        alert.reference, alert.phone, alert.status
+    );
+
+    {
+        let now = Utc::now();
+        let mut last_alerted = LAST_ALERTED.lock().unwrap_or_else(|e| e.into_inner());
+        last_alerted.retain(|_, at| now - *at < dedupe_window());
+        if last_alerted.contains_key(&alert.reference) {
+            return;
+        }
+        last_alerted.insert(alert.reference.clone(), now);
+    }
+
+    let text = alert.text();
+
+    if let Ok(webhook_url) = std::env::var("OPS_SLACK_WEBHOOK_URL")
+        && !webhook_url.is_empty()
+    {
+        let result = reqwest::Client::new()
+            .post(&webhook_url)
+            .timeout(std::time::Duration::from_secs(10))
+            .json(&serde_json::json!({
+                "text": text,
+                "alert": &alert,
+            }))
+            .send()
+            .await;
+
+        match result {
+            Ok(res) if res.status().is_success() => {}
+            Ok(res) => eprintln!(
+                "Slack alert for {} rejected: {}",
+                alert.reference,
+                res.status()
+            ),
+            Err(e) => eprintln!("Slack alert for {} failed: {}", alert.reference, e),
+        }
+    }
+
+    for phone in alert_phones() {
+        send_twilio_message(&phone, &text).await;
+    }
+}
+
+/// A sender tripped the reply throttle. Logged always; sent to the ops phones only when
+/// `SPAM_NOTIFY_ADMINS=true`, since a misbehaving bot can trip it over and over.
+pub async fn spam_detected(phone: &str, reason: &str) {
+    eprintln!("ALERT: Muting replies to {}: {}", mask_phone(phone), reason);
+
+    if !matches!(
+        std::env::var("SPAM_NOTIFY_ADMINS").as_deref(),
+        Ok("1") | Ok("true")
+    ) {
+        return;
+    }
+
+    let text = format!(
+        "🔇 *Replies muted*\n\
+        📱 User: {}\n\
+        ❓ Reason: {}",
+        mask_phone(phone),
+        reason
+    );
+    for admin in alert_phones() {
+        send_twilio_message(&admin, &text).await;
+    }
+}
@@ -0,0 +1,202 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, OnceLock};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+use crate::privacy::phone_for_storage;
+use crate::supervisor;
+
+static AUDIT_SENDER: OnceLock<mpsc::UnboundedSender<AuditEntry>> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub phone: String,
+    pub correlation_id: Option<String>,
+    pub reference: Option<String>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    QuoteShown {
+        amount: f64,
+        token: String,
+        rate: f64,
+        naira_amount: f64,
+    },
+    QuoteConfirmed,
+    WithdrawalCancelled {
+        step: String,
+    },
+    BankSelected {
+        bank_name: String,
+        account_number: String,
+        newly_added: bool,
+    },
+    OfframpInitiated {
+        amount: f64,
+        token: String,
+    },
+    /// The backend's payout differed from the quote by more than the tolerance.
+    PayoutAmountChanged {
+        quoted_naira_amount: f64,
+        payout_amount: f64,
+    },
+    PaymentTriggered {
+        success: bool,
+        error: Option<String>,
+    },
+    /// Compensating cancel after the payment trigger kept failing.
+    OfframpCancelled {
+        success: bool,
+        error: Option<String>,
+    },
+    TerminalStatus {
+        status: String,
+    },
+    /// Polling gave up before the backend reported a final status.
+    PollTimedOut {
+        waited_minutes: u64,
+    },
+    MessageSendFailed {
+        error: String,
+    },
+    /// A rating request went out after a completed withdrawal.
+    FeedbackRequested,
+    /// The user's 1–5 answer to it.
+    FeedbackRated {
+        rating: u8,
+    },
+    /// A verified bank account's name didn't resemble the user's registered name.
+    AccountNameMismatch {
+        account_name: String,
+        similarity: f64,
+        blocked: bool,
+    },
+    /// Someone outside `ALLOWED_COUNTRY_CODES` tried to sign up or withdraw.
+    RegionRestricted {
+        calling_code: Option<String>,
+        action: String,
+    },
+    /// A webhook payload we didn't recognise, acknowledged and otherwise ignored.
+    UnrecognizedCallback {
+        fields: Vec<String>,
+        message_type: Option<String>,
+    },
+    /// A flow left mid-way past `FLOW_TIMEOUT_SECS`, reset by reconciliation.
+    StaleFlowReset {
+        state: String,
+    },
+}
+
+pub fn audit_log_path() -> String {
+    std::env::var("AUDIT_LOG_PATH").unwrap_or_else(|_| "audit.log".to_string())
+}
+
+/// Queues an entry for the writer task. Never blocks the message path; entries recorded
+/// before `start_writer` runs are dropped.
+pub fn record(
+    phone: &str,
+    correlation_id: Option<&str>,
+    reference: Option<&str>,
+    event: AuditEvent,
+) {
+    let entry = AuditEntry {
+        timestamp: Utc::now(),
+        phone: phone_for_storage(phone),
+        correlation_id: correlation_id.map(str::to_string),
+        reference: reference.map(str::to_string),
+        event,
+    };
+
+    if let Some(sender) = AUDIT_SENDER.get()
+        && sender.send(entry).is_err()
+    {
+        eprintln!("Audit writer has stopped; entry dropped");
+    }
+}
+
+/// Spawns the task that appends audit entries to `AUDIT_LOG_PATH` as JSON lines.
+pub fn start_writer() {
+    let (sender, receiver) = mpsc::unbounded_channel::<AuditEntry>();
+    if AUDIT_SENDER.set(sender).is_err() {
+        return;
+    }
+    // Shared so a restarted writer picks up where the last one stopped
+    let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+
+    let path = audit_log_path();
+    supervisor::supervise("audit_writer", move || {
+        let path = path.clone();
+        let receiver = receiver.clone();
+        async move {
+            let mut file = match tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+            {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("Failed to open audit log {}: {}", path, e);
+                    return;
+                }
+            };
+
+            let mut receiver = receiver.lock().await;
+            while let Some(entry) = receiver.recv().await {
+                let mut line = match serde_json::to_string(&entry) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        eprintln!("Failed to encode audit entry: {}", e);
+                        continue;
+                    }
+                };
+                line.push('\n');
+
+                if let Err(e) = file.write_all(line.as_bytes()).await {
+                    eprintln!("Failed to write audit entry: {}", e);
+                }
+            }
+        }
+    });
+}
+
+pub async fn read_entries() -> Result<Vec<AuditEntry>, String> {
+    let path = audit_log_path();
+    let contents = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format!("Failed to read audit log {}: {}", path, e)),
+    };
+
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Every entry for a reference, plus the entries from the same flow recorded before
+/// the reference existed (quote, confirmation, bank selection).
+pub async fn trail_for_reference(reference: &str) -> Result<Vec<AuditEntry>, String> {
+    let entries = read_entries().await?;
+
+    let correlation_ids: Vec<String> = entries
+        .iter()
+        .filter(|e| e.reference.as_deref() == Some(reference))
+        .filter_map(|e| e.correlation_id.clone())
+        .collect();
+
+    Ok(entries
+        .into_iter()
+        .filter(|e| {
+            e.reference.as_deref() == Some(reference)
+                || e.correlation_id
+                    .as_ref()
+                    .is_some_and(|id| correlation_ids.contains(id))
+        })
+        .collect())
+}
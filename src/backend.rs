@@ -0,0 +1,1315 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+use crate::cache;
+use crate::config;
+use crate::metrics;
+use crate::model::{
+    BankDetails, BankListResponse, BankVerificationEnvelope, BankVerificationResponse,
+    RateResponse, ReceivePaymentRequest, TransactionHistoryResponse, TransactionRecord,
+    TransactionStatus, UserSessions, WebhookStatusResponse,
+};
+use crate::privacy::{mask_digit_runs, mask_phone};
+use crate::recording;
+use crate::reporting::{self, Severity};
+use crate::transactions;
+
+/// Active signing keys. Outbound requests sign with `primary`; inbound signatures are
+/// accepted under either key so `HMAC_KEY` can be rotated without downtime.
+#[derive(Debug)]
+pub struct HmacKeys {
+    pub primary: String,
+    pub secondary: Option<String>,
+}
+
+impl HmacKeys {
+    fn from_env() -> Result<Self, String> {
+        Self::from_vars("HMAC_KEY", "HMAC_KEY_SECONDARY")
+    }
+
+    /// `primary` (or `primary_FILE`) is required; `secondary` is only set during a rotation.
+    pub fn from_vars(primary: &str, secondary: &str) -> Result<Self, String> {
+        let primary_key = config::secret(primary)?
+            .ok_or_else(|| format!("{} (or {}_FILE) is not set", primary, primary))?;
+        let secondary = config::secret(secondary)?;
+        Ok(HmacKeys {
+            primary: primary_key,
+            secondary,
+        })
+    }
+
+    /// Primary first, then the secondary during a rotation window.
+    pub fn candidates(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.primary.as_str()).chain(self.secondary.as_deref())
+    }
+}
+
+static HMAC_KEYS: LazyLock<RwLock<Arc<HmacKeys>>> = LazyLock::new(|| {
+    let keys = HmacKeys::from_env().unwrap_or_else(|e| {
+        eprintln!("{}; backend requests will fail authentication", e);
+        HmacKeys {
+            primary: String::new(),
+            secondary: None,
+        }
+    });
+    RwLock::new(Arc::new(keys))
+});
+
+/// Snapshot of the current keys; callers keep using it even if a reload happens mid-request.
+pub fn hmac_keys() -> Arc<HmacKeys> {
+    HMAC_KEYS.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Re-reads the keys and swaps them in atomically. On error the old keys stay active.
+pub fn reload_hmac_keys() -> Result<Arc<HmacKeys>, String> {
+    let keys = Arc::new(HmacKeys::from_env()?);
+    *HMAC_KEYS.write().unwrap_or_else(|e| e.into_inner()) = keys.clone();
+    Ok(keys)
+}
+
+/// HMAC-SHA256 over `METHOD\npath\ntimestamp\nnonce\nbody`, hex encoded. The backend
+/// rebuilds the same string, so any change here must be coordinated with it.
+pub fn sign(
+    key: &str,
+    method: &str,
+    path: &str,
+    timestamp: &str,
+    nonce: &str,
+    body: &[u8],
+) -> String {
+    sign_with_headers(key, method, path, timestamp, nonce, &[], body)
+}
+
+/// `sign`, also covering `headers`: a `name:value\n` line each, in the order given (and
+/// listed in `x-signed-headers`), between the nonce and the body. Without headers it's
+/// exactly `sign`.
+pub fn sign_with_headers(
+    key: &str,
+    method: &str,
+    path: &str,
+    timestamp: &str,
+    nonce: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> String {
+    hex::encode(
+        request_mac(key, method, path, timestamp, nonce, headers, body)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+/// Checks a hex `signature` made by `sign`, in constant time.
+pub fn verify(
+    key: &str,
+    method: &str,
+    path: &str,
+    timestamp: &str,
+    nonce: &str,
+    body: &[u8],
+    signature: &str,
+) -> bool {
+    hex::decode(signature).is_ok_and(|expected| {
+        request_mac(key, method, path, timestamp, nonce, &[], body)
+            .verify_slice(&expected)
+            .is_ok()
+    })
+}
+
+fn request_mac(
+    key: &str,
+    method: &str,
+    path: &str,
+    timestamp: &str,
+    nonce: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(method.as_bytes());
+    mac.update(b"\n");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(timestamp.as_bytes());
+    mac.update(b"\n");
+    mac.update(nonce.as_bytes());
+    mac.update(b"\n");
+    for (name, value) in headers {
+        mac.update(name.to_ascii_lowercase().as_bytes());
+        mac.update(b":");
+        mac.update(value.as_bytes());
+        mac.update(b"\n");
+    }
+    mac.update(body);
+    mac
+}
+
+/// Path plus query string, which is what the backend sees for the signed request line.
+fn signing_path(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) => match parsed.query() {
+            Some(query) => format!("{}?{}", parsed.path(), query),
+            None => parsed.path().to_string(),
+        },
+        Err(_) => String::new(),
+    }
+}
+
+/// While the backend migrates, `BACKEND_LEGACY_API_KEY=true` also sends the raw key
+/// as `x-api-key` alongside the signature.
+fn legacy_api_key_enabled() -> bool {
+    matches!(
+        std::env::var("BACKEND_LEGACY_API_KEY").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+/// Builds a request carrying `x-timestamp`, `x-nonce` and `x-signature` headers so a
+/// captured request can't be replayed or altered. `query` goes onto the URL before
+/// signing, since the backend checks the path with its query string. `headers` are sent
+/// and signed too, named in `x-signed-headers`, for anything the backend acts on.
+pub fn signed_request(
+    client: &Client,
+    method: Method,
+    url: &str,
+    query: &[(&str, &str)],
+    headers: &[(&str, &str)],
+    body: Vec<u8>,
+) -> RequestBuilder {
+    let url = match reqwest::Url::parse(url) {
+        Ok(mut parsed) if !query.is_empty() => {
+            parsed.query_pairs_mut().extend_pairs(query);
+            parsed.to_string()
+        }
+        _ => url.to_string(),
+    };
+    let key = hmac_keys().primary.clone();
+    let timestamp = Utc::now().timestamp().to_string();
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    let signature = sign_with_headers(
+        &key,
+        method.as_str(),
+        &signing_path(&url),
+        &timestamp,
+        &nonce,
+        headers,
+        &body,
+    );
+
+    let mut request = client
+        .request(method, &url)
+        .header("x-service", "whatsapp-bot")
+        .header("x-timestamp", timestamp)
+        .header("x-nonce", nonce)
+        .header("x-signature", signature);
+    if !headers.is_empty() {
+        let names: Vec<String> = headers
+            .iter()
+            .map(|(name, _)| name.to_ascii_lowercase())
+            .collect();
+        request = request.header("x-signed-headers", names.join(","));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+    }
+    if !body.is_empty() {
+        request = request.header(CONTENT_TYPE, "application/json").body(body);
+    }
+
+    if legacy_api_key_enabled() {
+        request = request.header("x-api-key", key);
+    }
+
+    request
+}
+
+/// A signed GET of `url` with `query`.
+pub fn signed_get(client: &Client, url: &str, query: &[(&str, &str)]) -> RequestBuilder {
+    signed_request(client, Method::GET, url, query, &[], Vec::new())
+}
+
+/// A signed JSON POST of `payload`.
+pub fn signed_post<T: Serialize + ?Sized>(
+    client: &Client,
+    url: &str,
+    payload: &T,
+) -> RequestBuilder {
+    let body = serde_json::to_vec(payload).unwrap_or_default();
+    signed_request(client, Method::POST, url, &[], &[], body)
+}
+
+/// How much of a body that didn't parse goes in the log.
+const BODY_SNIPPET_CHARS: usize = 200;
+
+/// Reads a successful response as `T`. When it doesn't fit, the log says why and shows
+/// the start of the body, digits masked, so a change on the backend shows up in the logs
+/// rather than as a generic failure. The error is for the log, not the user.
+pub async fn parse_body<T: DeserializeOwned>(response: Response, what: &str) -> Result<T, String> {
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read {} response: {}", what, e))?;
+    serde_json::from_str(&body).map_err(|e| {
+        let snippet: String = body.chars().take(BODY_SNIPPET_CHARS).collect();
+        let error = format!(
+            "Unexpected {} response ({}): {}",
+            what,
+            e,
+            mask_digit_runs(&snippet)
+        );
+        eprintln!("{}", error);
+        reporting::capture(
+            Severity::Warning,
+            &format!("Unexpected {} response: {}", what, e),
+            Some(what),
+        );
+        error
+    })
+}
+
+/// Body the backend sends with a non-2xx response.
+#[derive(Debug, Default, Deserialize)]
+pub struct ErrorEnvelope {
+    #[serde(default)]
+    pub error_code: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl ErrorEnvelope {
+    /// The envelope in `body`, or an empty one when the body isn't one.
+    pub fn parse(body: &str) -> Self {
+        serde_json::from_str(body).unwrap_or_default()
+    }
+}
+
+/// What we tell the user for each `error_code` we understand.
+const ERROR_REPLIES: &[(&str, &str)] = &[
+    (
+        "INSUFFICIENT_BALANCE",
+        "💸 Your balance is too low for this withdrawal.\n\n\
+        Type `balance` to check it, then try a smaller amount.",
+    ),
+    (
+        "INVALID_BANK_ACCOUNT",
+        "🏦 The bank couldn't find that account.\n\n\
+        Check the account number and bank name, then send them again.",
+    ),
+    (
+        "LIMIT_EXCEEDED",
+        "🚦 This is over your withdrawal limit.\n\n\
+        Try a smaller amount, or wait for your limit to reset.",
+    ),
+    (
+        "ACCOUNT_FROZEN",
+        "🔒 Your account is on hold, so we can't process this.\n\n\
+        Type `support` to get it released.",
+    ),
+    (
+        "DUPLICATE_REQUEST",
+        "⏳ We're already working on this request.\n\n\
+        Type `status` to follow it rather than sending it again.",
+    ),
+];
+
+/// Turns a failed response into the reply for the user. Known `error_code`s get their
+/// own message; anything else, including a body that isn't the envelope, gets `generic`
+/// with `correlation_id` so support can find the request.
+pub async fn error_reply(
+    response: Response,
+    what: &str,
+    generic: &str,
+    correlation_id: Option<&str>,
+) -> String {
+    let (_, envelope) = read_error(response, what).await;
+    reply_for(&envelope, generic, correlation_id)
+}
+
+/// Logs and reports a failed response, returning its status and envelope for callers
+/// that tell some error codes apart themselves. The envelope is empty when the body
+/// isn't one.
+pub async fn read_error(response: Response, what: &str) -> (StatusCode, ErrorEnvelope) {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    let envelope = ErrorEnvelope::parse(&body);
+    eprintln!(
+        "{} failed with status {}: {} {}",
+        what,
+        status,
+        envelope.error_code.as_deref().unwrap_or("(no error code)"),
+        envelope.message.as_deref().unwrap_or("")
+    );
+    let severity = if status.is_server_error() {
+        Severity::Error
+    } else {
+        Severity::Warning
+    };
+    reporting::capture(
+        severity,
+        &format!(
+            "{} failed with status {}: {}",
+            what,
+            status,
+            envelope.error_code.as_deref().unwrap_or("(no error code)")
+        ),
+        Some(what),
+    );
+    (status, envelope)
+}
+
+/// `error_reply` for an envelope already read.
+pub fn reply_for(envelope: &ErrorEnvelope, generic: &str, correlation_id: Option<&str>) -> String {
+    let known = envelope.error_code.as_deref().and_then(|code| {
+        ERROR_REPLIES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(code))
+            .map(|(_, reply)| *reply)
+    });
+    match (known, correlation_id) {
+        (Some(reply), _) => reply.to_string(),
+        (None, Some(id)) => format!("{}\n\nSupport code: `{}`", generic, id),
+        (None, None) => generic.to_string(),
+    }
+}
+
+/// Backend endpoints whose health is tracked separately, so one slow service doesn't
+/// shed traffic for the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    CreateAccount,
+    Address,
+    Balance,
+    History,
+    Rate,
+    BankVerify,
+    BankList,
+    BankSave,
+    Offramp,
+    OfframpCancel,
+    Payment,
+    Status,
+}
+
+pub const DEGRADED_MESSAGE: &str = "⏳ We're experiencing delays with our payment partner.\n\n\
+    Please try again in a few minutes. Nothing has been charged.";
+
+/// (finished at, latency, succeeded) for recent calls, per endpoint.
+type HealthMap = HashMap<Endpoint, VecDeque<(Instant, Duration, bool)>>;
+
+static HEALTH: LazyLock<Mutex<HealthMap>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+const MAX_SAMPLES: usize = 50;
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Degraded endpoints, and when a request last went through to one as a probe.
+static PROBES: LazyLock<Mutex<HashMap<Endpoint, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// `BACKEND_HEALTH_WINDOW_SECS`, default 2 min. Samples older than this are forgotten,
+/// so a shed endpoint also recovers once its bad samples age out.
+fn health_window() -> Duration {
+    Duration::from_secs(env_u64("BACKEND_HEALTH_WINDOW_SECS", 120))
+}
+
+/// `BACKEND_PROBE_SECS`, default 15s: how often a degraded endpoint lets one request
+/// through to see whether it has recovered.
+fn probe_interval() -> Duration {
+    Duration::from_secs(env_u64("BACKEND_PROBE_SECS", 15))
+}
+
+/// `BACKEND_SLOW_PERCENT` (default 50) of the endpoint's deadline: a rate lookup is slow
+/// long before an account creation is.
+fn slow_threshold(endpoint: Endpoint) -> Duration {
+    TIMEOUTS
+        .for_endpoint(endpoint)
+        .mul_f64(env_u64("BACKEND_SLOW_PERCENT", 50) as f64 / 100.0)
+}
+
+fn record(endpoint: Endpoint, latency: Duration, succeeded: bool) {
+    let now = Instant::now();
+    let window = health_window();
+    let mut health = HEALTH.lock().unwrap_or_else(|e| e.into_inner());
+    let samples = health.entry(endpoint).or_default();
+
+    // A healthy answer while the endpoint was being shed means it is back: start over
+    // rather than wait for the bad samples to age out
+    let mut probes = PROBES.lock().unwrap_or_else(|e| e.into_inner());
+    if succeeded && latency < slow_threshold(endpoint) && probes.remove(&endpoint).is_some() {
+        println!("Backend endpoint {:?} has recovered", endpoint);
+        samples.clear();
+    }
+
+    samples.push_back((now, latency, succeeded));
+    while samples.len() > MAX_SAMPLES
+        || samples.front().is_some_and(|(at, _, _)| now - *at > window)
+    {
+        samples.pop_front();
+    }
+}
+
+/// Degraded once the window holds at least `BACKEND_DEGRADED_MIN_SAMPLES` calls (default
+/// 5) and either half of them failed or their median latency reached `slow_threshold`.
+pub fn is_degraded(endpoint: Endpoint) -> bool {
+    let now = Instant::now();
+    let window = health_window();
+    let health = HEALTH.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(samples) = health.get(&endpoint) else {
+        return false;
+    };
+
+    let mut latencies: Vec<Duration> = Vec::new();
+    let mut failures = 0;
+    for (at, latency, succeeded) in samples {
+        if now - *at <= window {
+            latencies.push(*latency);
+            failures += usize::from(!succeeded);
+        }
+    }
+    if latencies.len() < env_u64("BACKEND_DEGRADED_MIN_SAMPLES", 5) as usize {
+        return false;
+    }
+
+    latencies.sort();
+    let median = latencies[latencies.len() / 2];
+    failures * 2 >= latencies.len() || median >= slow_threshold(endpoint)
+}
+
+/// Read once, so every request to an endpoint gets the same deadline.
+static TIMEOUTS: LazyLock<config::TimeoutConfig> = LazyLock::new(config::TimeoutConfig::from_env);
+
+/// Shared by every backend call so connections are pooled. It has no timeout of its own:
+/// `send_tracked` gives each request its endpoint's.
+static CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
+
+pub fn client() -> Client {
+    CLIENT.clone()
+}
+
+/// `send()` with the endpoint's deadline from `TimeoutConfig`, which replaces the
+/// client's own, and that feeds the endpoint's health window. Timeouts, connection
+/// errors and 5xx responses count as failures; 4xx replies are the backend answering
+/// normally.
+pub trait TrackedSend {
+    fn send_tracked(self, endpoint: Endpoint) -> impl Future<Output = reqwest::Result<Response>>;
+}
+
+impl TrackedSend for RequestBuilder {
+    async fn send_tracked(self, endpoint: Endpoint) -> reqwest::Result<Response> {
+        let deadline = TIMEOUTS.for_endpoint(endpoint);
+        let request = self.timeout(deadline);
+        let started = Instant::now();
+        let result = if recording::enabled() {
+            recording::send(request, endpoint).await
+        } else {
+            request.send().await
+        };
+        let name = format!("{:?}", endpoint);
+        let timed_out = result.as_ref().is_err_and(|e| e.is_timeout());
+        if timed_out {
+            metrics::BACKEND_DEADLINES.inc(&[&name]);
+            eprintln!(
+                "WARN: Backend {:?} deadline exceeded after {}s",
+                endpoint,
+                deadline.as_secs()
+            );
+        }
+        let succeeded = result
+            .as_ref()
+            .is_ok_and(|res| !res.status().is_server_error());
+        let outcome = match (succeeded, timed_out) {
+            (true, _) => "ok",
+            (false, true) => "timeout",
+            (false, false) => "error",
+        };
+        metrics::BACKEND_REQUESTS.observe(&[&name, outcome], started.elapsed());
+        record(endpoint, started.elapsed(), succeeded);
+        if !succeeded && is_degraded(endpoint) {
+            eprintln!("ALERT: Backend endpoint {:?} is degraded", endpoint);
+        }
+        result
+    }
+}
+
+/// Fails fast while `endpoint` is degraded, instead of parking a worker until the
+/// endpoint's deadline. Commands that don't need it, and cached replies, keep working.
+/// Every `probe_interval` one request is let through, and its outcome decides whether
+/// the endpoint has recovered.
+pub fn shed_if_degraded(endpoint: Endpoint) -> Option<String> {
+    if !is_degraded(endpoint) {
+        return None;
+    }
+    let now = Instant::now();
+    let mut probes = PROBES.lock().unwrap_or_else(|e| e.into_inner());
+    match probes.get(&endpoint) {
+        Some(probed) if now - *probed < probe_interval() => Some(DEGRADED_MESSAGE.to_string()),
+        _ => {
+            probes.insert(endpoint, now);
+            None
+        }
+    }
+}
+
+pub async fn get_transaction_history(
+    session: &UserSessions,
+) -> Result<Vec<TransactionRecord>, String> {
+    let history_endpoint = std::env::var("SERVER_TRANSACTION_HISTORY_ENDPOINT").unwrap_or_default();
+
+    let client = client();
+
+    let formatted_phone = session.phone.trim_start_matches("+");
+    let response = signed_get(&client, &history_endpoint, &[("phone", formatted_phone)])
+        .send_tracked(Endpoint::History)
+        .await;
+
+    match response {
+        Ok(res) if res.status().is_success() => {
+            match res.json::<TransactionHistoryResponse>().await {
+                Ok(history) if history.success => Ok(history.data),
+                Ok(_) => Err("Failed to retrieve your transactions. Please try again.".to_string()),
+                Err(e) => {
+                    eprintln!("Failed to parse transaction history response: {}", e);
+                    Err("Failed to retrieve your transactions. Please try again.".to_string())
+                }
+            }
+        }
+        Ok(res) if res.status().as_u16() == 404 => Ok(vec![]),
+        Ok(res) => {
+            eprintln!(
+                "Transaction history request failed with status: {}",
+                res.status()
+            );
+            Err("Failed to retrieve your transactions. Please try again.".to_string())
+        }
+        Err(e) => {
+            eprintln!("Transaction history request error: {}", e);
+            Err("Failed to connect to server. Please try again.".to_string())
+        }
+    }
+}
+
+/// Current USD/NGN rate. Withdrawals always fetch a fresh one; `quote` may use a rate
+/// fetched within `RATE_CACHE_TTL_SECS`. The error is the reply to send.
+pub async fn fetch_usd_ngn_rate(allow_cached: bool) -> Result<f64, String> {
+    if allow_cached && let Some(rate) = cache::cached_rate() {
+        return Ok(rate);
+    }
+    if let Some(shed) = shed_if_degraded(Endpoint::Rate) {
+        return Err(shed);
+    }
+
+    let rate_endpoint = std::env::var("SERVER_RATE_ENDPOINT").unwrap_or_default();
+
+    let client = client();
+
+    let response = signed_get(&client, &rate_endpoint, &[])
+        .send_tracked(Endpoint::Rate)
+        .await;
+
+    let rate = match response {
+        Ok(res) if res.status().is_success() => parse_body::<RateResponse>(res, "Rate")
+            .await
+            .ok()
+            .map(|body| body.data.usd_ngn_rate),
+        Ok(_) => None,
+        Err(_) => return Err("❌ Failed to connect to server. Please try again.".to_string()),
+    };
+
+    let rate =
+        rate.ok_or_else(|| "❌ Failed to get exchange rate. Please try again.".to_string())?;
+    cache::store_rate(rate);
+    Ok(rate)
+}
+
+/// What `verify_bank_details` returns when the bank says the account doesn't exist.
+pub const ACCOUNT_NOT_FOUND: &str = "Account not found. Please check your details and try again.";
+
+pub async fn verify_bank_details(
+    bank_name: &str,
+    account_number: &str,
+    session: &mut UserSessions,
+) -> Result<BankVerificationResponse, String> {
+    let bank_verification_endpoint =
+        std::env::var("SERVER_BANK_ACCOUNT_VERIFY_ENDPOINT").unwrap_or_default();
+
+    let client = client();
+
+    let formatted_phone = session.phone.trim_start_matches("+");
+    let response = signed_request(
+        &client,
+        Method::POST,
+        &bank_verification_endpoint,
+        &[
+            ("phone", formatted_phone),
+            ("bank_name", bank_name),
+            ("account_number", account_number),
+        ],
+        &[],
+        Vec::new(),
+    )
+    .send_tracked(Endpoint::BankVerify)
+    .await;
+
+    match response {
+        Ok(res) if res.status().is_success() => {
+            match parse_body::<BankVerificationEnvelope>(res, "Bank verification").await {
+                Ok(body) => Ok(body.data),
+                // Logged by `parse_body`
+                Err(_) => Err("Invalid bank details received. Please try again.".to_string()),
+            }
+        }
+        Ok(res) if res.status().as_u16() == 404 => Err(ACCOUNT_NOT_FOUND.to_string()),
+        Ok(res) => Err(error_reply(
+            res,
+            "Bank verification",
+            "Failed to verify bank details. Please try again.",
+            session.correlation_id.as_deref(),
+        )
+        .await),
+        Err(e) => {
+            eprintln!("Verification request error: {}", e);
+            Err("Failed to connect to server. Please try again.".to_string())
+        }
+    }
+}
+
+pub async fn get_user_bank_details(session: &UserSessions) -> Result<Vec<BankDetails>, String> {
+    let bank_details_endpoint =
+        std::env::var("SERVER_BANK_ACCOUNT_GETTER_ENDPOINT").unwrap_or_default();
+
+    let client = client();
+
+    let formatted_phone = session.phone.trim_start_matches("+");
+    let response = signed_get(
+        &client,
+        &bank_details_endpoint,
+        &[("phone", formatted_phone)],
+    )
+    .send_tracked(Endpoint::BankList)
+    .await;
+
+    match response {
+        Ok(res) if res.status().is_success() => match res.json::<BankListResponse>().await {
+            Ok(parsed_response) => Ok(parsed_response.data.banks),
+            Err(e) => {
+                eprintln!("Failed to parse bank details response: {}", e);
+                Err("Failed to parse bank details. Please try again.".to_string())
+            }
+        },
+        Ok(res) if res.status().as_u16() == 404 => Ok(vec![]),
+        Ok(res) => {
+            eprintln!(
+                "Failed to retrieve bank details with status: {}",
+                res.status()
+            );
+            Err("Failed to retrieve bank details. Please try again.".to_string())
+        }
+        Err(e) => {
+            eprintln!("Bank details request error: {}", e);
+            Err("Failed to connect to server. Please try again.".to_string())
+        }
+    }
+}
+
+/// The saved entry for `verification`: same account number and bank code, or bank name
+/// when the list doesn't carry codes. Never just the first bank, which may be another account.
+pub fn find_saved_bank(
+    banks: Vec<BankDetails>,
+    verification: &BankVerificationResponse,
+) -> Option<BankDetails> {
+    banks.into_iter().find(|bank| {
+        bank.account_number == verification.account_number
+            && match bank.bank_code.as_deref() {
+                Some(code) => code == verification.bank_code,
+                None => bank.bank_name.eq_ignore_ascii_case(&verification.bank_name),
+            }
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BankSave {
+    /// With the new `bank_details_id` when the backend returns it.
+    Saved(Option<String>),
+    /// Timed out or 5xx on both attempts: it may or may not have been stored.
+    Unknown,
+}
+
+/// Same verified bank for the same user always gets the same key, so the backend can drop
+/// repeats whether they come from our retry or from the user confirming again. The account
+/// name is part of it, so updating a renamed account isn't dropped as a repeat.
+fn bank_save_id(phone: &str, verification: &BankVerificationResponse) -> String {
+    let digest = Sha256::digest(
+        format!(
+            "{}|{}|{}|{}",
+            phone, verification.bank_code, verification.account_number, verification.account_name
+        )
+        .as_bytes(),
+    );
+    hex::encode(&digest[..16])
+}
+
+/// Saves the verified bank, retrying once on a timeout or 5xx. `replaces` is the saved
+/// record being updated, when this corrects an existing bank rather than adding one.
+pub async fn save_bank_details_to_db(
+    session: &UserSessions,
+    verification: &BankVerificationResponse,
+    replaces: Option<&str>,
+) -> Result<BankSave, String> {
+    let bank_details_save_endpoint =
+        std::env::var("SERVER_BANK_DETAILS_CONFIRM_ENDPOINT").unwrap_or_default();
+
+    let client = client();
+
+    let formatted_phone = session.phone.trim_start_matches("+");
+    let mut payload = serde_json::json!({
+        "phone": formatted_phone,
+        "account_name": verification.account_name,
+        "account_number": verification.account_number,
+        "bank_code": verification.bank_code,
+        "bank_name": verification.bank_name,
+        "bank_save_id": bank_save_id(formatted_phone, verification),
+    });
+    if let Some(bank_details_id) = replaces {
+        payload["bank_details_id"] = bank_details_id.into();
+    }
+
+    const ATTEMPTS: u32 = 2;
+    for attempt in 1..=ATTEMPTS {
+        let response = signed_post(&client, &bank_details_save_endpoint, &payload)
+            .send_tracked(Endpoint::BankSave)
+            .await;
+
+        let failure = match response {
+            Ok(res) if res.status().is_success() => {
+                println!("Bank details saved successfully!");
+                let body: Value = res.json().await.unwrap_or_default();
+                let bank_details_id = body
+                    .pointer("/data/bank_details_id")
+                    .or_else(|| body.get("bank_details_id"))
+                    .and_then(|id| id.as_str())
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string);
+                return Ok(BankSave::Saved(bank_details_id));
+            }
+            Ok(res) if res.status().is_server_error() => format!("status {}", res.status()),
+            Ok(res) => {
+                return Err(error_reply(
+                    res,
+                    "Bank details save",
+                    "Failed to save bank details. Please try again.",
+                    session.correlation_id.as_deref(),
+                )
+                .await);
+            }
+            Err(e) if e.is_timeout() => "timed out".to_string(),
+            Err(_) => return Err("Failed to connect to server. Please try again.".to_string()),
+        };
+        eprintln!(
+            "Bank details save attempt {}/{} for {}: {}",
+            attempt,
+            ATTEMPTS,
+            mask_phone(&session.phone),
+            failure
+        );
+    }
+    Ok(BankSave::Unknown)
+}
+
+/// Retries the payment trigger up to `PAYMENT_TRIGGER_ATTEMPTS` times (default 3),
+/// backing off 2s, 4s, ... between attempts.
+pub async fn trigger_payment_with_retry(
+    payment_request: &ReceivePaymentRequest,
+) -> Result<(), String> {
+    let attempts: u32 = std::env::var("PAYMENT_TRIGGER_ATTEMPTS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(3);
+
+    let mut attempt = 1;
+    loop {
+        match trigger_payment(payment_request).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(e) => {
+                let backoff = Duration::from_secs(2u64.pow(attempt));
+                eprintln!(
+                    "Payment trigger attempt {}/{} for {} failed: {}; retrying in {:?}",
+                    attempt, attempts, payment_request.reference, e, backoff
+                );
+                sleep(backoff).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Asks the backend to drop an offramp that hasn't paid out yet.
+pub async fn cancel_offramp(
+    client: &reqwest::Client,
+    reference: &str,
+    reason: &str,
+) -> Result<(), String> {
+    let cancel_endpoint = std::env::var("SERVER_OFFRAMP_CANCEL_ENDPOINT")
+        .map_err(|_| "SERVER_OFFRAMP_CANCEL_ENDPOINT is not set".to_string())?;
+
+    let response = signed_post(
+        client,
+        &cancel_endpoint,
+        &serde_json::json!({
+            "reference": reference,
+            "reason": reason,
+        }),
+    )
+    .send_tracked(Endpoint::OfframpCancel)
+    .await
+    .map_err(|e| format!("Cancel request failed: {}", e))?;
+
+    if response.status().is_success() {
+        println!("Offramp {} cancelled ({})", reference, reason);
+        transactions::set_status(reference, "cancelled").await;
+        Ok(())
+    } else {
+        Err(format!(
+            "Cancel request rejected with status {}",
+            response.status()
+        ))
+    }
+}
+
+async fn trigger_payment(payment_request: &ReceivePaymentRequest) -> Result<(), String> {
+    let payment_endpoint = std::env::var("SERVER_PAYMENT_ENDPOINT").unwrap_or_default();
+
+    let client = client();
+
+    // The request body is the ReceivePaymentRequest struct itself
+    let response = signed_post(&client, &payment_endpoint, payment_request)
+        .send_tracked(Endpoint::Payment)
+        .await;
+
+    match response {
+        Ok(res) if res.status().is_success() => {
+            println!(
+                "Payment successfully triggered for reference: {}",
+                payment_request.reference
+            );
+            Ok(())
+        }
+        Ok(res) => {
+            eprintln!(
+                "Payment trigger failed with status: {} | Body: {:?}",
+                res.status(),
+                res.text().await
+            );
+            Err("Payment confirmation failed. Type `support` for help.".to_string())
+        }
+        Err(err) => {
+            eprintln!("Payment trigger request error: {}", err);
+            Err("Failed to connect to payment server. Please try again.".to_string())
+        }
+    }
+}
+
+/// `{base}/transactions/{reference}/status`, with the reference percent-encoded as a
+/// single path segment so user input can't change the path.
+fn transaction_status_url(base: &str, reference: &str) -> Result<reqwest::Url, String> {
+    let mut url = reqwest::Url::parse(base)
+        .map_err(|e| format!("Invalid TRANSACTION_STATUS_ENDPOINT: {}", e))?;
+    url.path_segments_mut()
+        .map_err(|_| "TRANSACTION_STATUS_ENDPOINT cannot take a path".to_string())?
+        .pop_if_empty()
+        .extend(["transactions", reference, "status"]);
+    Ok(url)
+}
+
+/// Header carrying the user's phone on status lookups, agreed with the backend and
+/// covered by the signature; it used to be a query parameter and ended up in access logs. `STATUS_PHONE_HEADER` (default
+/// `x-user-phone`) renames it, and `none` stops sending the phone once lookups are keyed
+/// by reference alone.
+fn status_phone_header() -> Option<String> {
+    match std::env::var("STATUS_PHONE_HEADER") {
+        Ok(name) if name.eq_ignore_ascii_case("none") || name.trim().is_empty() => None,
+        Ok(name) => Some(name.trim().to_string()),
+        Err(_) => Some("x-user-phone".to_string()),
+    }
+}
+
+pub async fn fetch_transaction_status(
+    client: &reqwest::Client,
+    reference: &str,
+    user_phone: &str,
+) -> Result<Option<TransactionStatus>, String> {
+    let base = std::env::var("TRANSACTION_STATUS_ENDPOINT")
+        .map_err(|_| "Transaction status endpoint is not configured".to_string())?;
+    let status_url = transaction_status_url(&base, reference)?;
+
+    // Signed, since the backend scopes the lookup to whoever this says is asking
+    let phone_header = status_phone_header();
+    let headers: Vec<(&str, &str)> = phone_header
+        .as_deref()
+        .map(|header| (header, user_phone))
+        .into_iter()
+        .collect();
+    let response = signed_request(
+        client,
+        Method::GET,
+        status_url.as_str(),
+        &[],
+        &headers,
+        Vec::new(),
+    )
+    .send_tracked(Endpoint::Status)
+    .await;
+
+    match response {
+        Ok(res) if res.status().is_success() => match res.json::<WebhookStatusResponse>().await {
+            Ok(response) if response.success => Ok(response.data),
+            Ok(_) => Ok(None),
+            Err(e) => Err(format!("Failed to parse transaction status: {}", e)),
+        },
+        Ok(res) if res.status().as_u16() == 404 => Ok(None),
+        Ok(res) => Err(format!(
+            "Transaction status request failed with status: {}",
+            res.status()
+        )),
+        Err(e) => Err(format!("Transaction status request error: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listed(id: &str, bank_name: &str, account_number: &str, code: Option<&str>) -> BankDetails {
+        BankDetails {
+            bank_details_id: id.to_string(),
+            bank_name: bank_name.to_string(),
+            account_number: account_number.to_string(),
+            account_name: "ADA OBI".to_string(),
+            bank_code: code.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn the_saved_bank_is_matched_on_account_and_bank_not_position() {
+        let verification = BankVerificationResponse {
+            bank_name: "Opay".to_string(),
+            account_number: "0123456789".to_string(),
+            account_name: "ADA OBI".to_string(),
+            bank_code: "999".to_string(),
+        };
+        let found = |banks| find_saved_bank(banks, &verification).map(|bank| bank.bank_details_id);
+
+        let banks = vec![
+            listed("other-account", "Opay", "9876543210", Some("999")),
+            listed("other-bank", "Kuda", "0123456789", Some("090")),
+            listed("new", "Opay", "0123456789", Some("999")),
+        ];
+        assert_eq!(found(banks).as_deref(), Some("new"));
+        // Without codes, the bank name has to match
+        let banks = vec![
+            listed("other-bank", "Kuda", "0123456789", None),
+            listed("new", "OPAY", "0123456789", None),
+        ];
+        assert_eq!(found(banks).as_deref(), Some("new"));
+        let banks = vec![listed("other-account", "Opay", "9876543210", Some("999"))];
+        assert_eq!(found(banks), None);
+    }
+
+    const GENERIC: &str = "Failed to initiate withdrawal.";
+
+    #[test]
+    fn each_known_error_code_gets_its_own_reply() {
+        for (code, expected) in [
+            ("INSUFFICIENT_BALANCE", "`balance`"),
+            ("INVALID_BANK_ACCOUNT", "Check the account number"),
+            ("LIMIT_EXCEEDED", "withdrawal limit"),
+            ("ACCOUNT_FROZEN", "`support`"),
+            ("DUPLICATE_REQUEST", "`status`"),
+            ("insufficient_balance", "`balance`"),
+        ] {
+            let envelope = ErrorEnvelope::parse(&format!(
+                r#"{{"error_code": "{}", "message": "from the backend"}}"#,
+                code
+            ));
+            let reply = reply_for(&envelope, GENERIC, Some("corr-1"));
+            assert!(reply.contains(expected), "{}: {}", code, reply);
+            assert!(!reply.contains(GENERIC), "{}: {}", code, reply);
+            assert!(!reply.contains("corr-1"), "{}: {}", code, reply);
+        }
+    }
+
+    #[test]
+    fn unknown_codes_and_malformed_bodies_get_the_generic_reply_and_support_code() {
+        for body in [
+            r#"{"error_code": "SOMETHING_NEW", "message": "?"}"#,
+            r#"{"message": "no code"}"#,
+            "<html>502 Bad Gateway</html>",
+            "",
+        ] {
+            let envelope = ErrorEnvelope::parse(body);
+            assert_eq!(
+                reply_for(&envelope, GENERIC, Some("corr-2")),
+                format!("{}\n\nSupport code: `corr-2`", GENERIC),
+                "{}",
+                body
+            );
+            assert_eq!(reply_for(&envelope, GENERIC, None), GENERIC);
+        }
+    }
+
+    const NONCE: &str = "0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn signature_matches_the_backends_vectors() {
+        assert_eq!(
+            sign(
+                "test-key",
+                "GET",
+                "/v1/rate?phone=2348012345678",
+                "1700000000",
+                NONCE,
+                b""
+            ),
+            "931a08533afa2944bfbbfd9717168f617e799748cc4b2b250eff167da380c304"
+        );
+        assert_eq!(
+            sign(
+                "test-key",
+                "POST",
+                "/v1/offramp",
+                "1700000000",
+                "nonce",
+                br#"{"amount":10}"#
+            ),
+            "14fa261a165c2ad1b72d12db355bf92537f591afbfb54478d927c822f5a3df84"
+        );
+    }
+
+    #[test]
+    fn verify_rejects_any_change() {
+        let signature = sign("k", "GET", "/p?a=1", "1", NONCE, b"");
+        assert!(verify("k", "GET", "/p?a=1", "1", NONCE, b"", &signature));
+        assert!(!verify("k", "POST", "/p?a=1", "1", NONCE, b"", &signature));
+        assert!(!verify("k", "GET", "/p?a=2", "1", NONCE, b"", &signature));
+        assert!(!verify("k", "GET", "/p?a=1", "2", NONCE, b"", &signature));
+        assert!(!verify(
+            "other", "GET", "/p?a=1", "1", NONCE, b"", &signature
+        ));
+        assert!(!verify("k", "GET", "/p?a=1", "1", NONCE, b"", "not hex"));
+    }
+
+    #[test]
+    fn the_secondary_key_is_only_a_candidate_during_a_rotation() {
+        // SAFETY: these names are only read by this test
+        unsafe { std::env::set_var("TEST_ROTATION_PRIMARY", "new") };
+        let keys = HmacKeys::from_vars("TEST_ROTATION_PRIMARY", "TEST_ROTATION_SECONDARY");
+        assert_eq!(keys.unwrap().candidates().collect::<Vec<_>>(), ["new"]);
+
+        // SAFETY: as above
+        unsafe { std::env::set_var("TEST_ROTATION_SECONDARY", "old") };
+        let keys = HmacKeys::from_vars("TEST_ROTATION_PRIMARY", "TEST_ROTATION_SECONDARY").unwrap();
+        assert_eq!(keys.candidates().collect::<Vec<_>>(), ["new", "old"]);
+
+        let signature = sign("old", "POST", "/p", "1", NONCE, b"{}");
+        let signed_with = |key: &str| verify(key, "POST", "/p", "1", NONCE, b"{}", &signature);
+        assert!(keys.candidates().any(signed_with));
+
+        assert!(HmacKeys::from_vars("TEST_ROTATION_MISSING", "TEST_ROTATION_SECONDARY").is_err());
+    }
+
+    #[test]
+    fn signing_path_keeps_the_query() {
+        assert_eq!(
+            signing_path("https://api.example.com/v1/balance?phone=234&token=0x1"),
+            "/v1/balance?phone=234&token=0x1"
+        );
+        assert_eq!(signing_path("https://api.example.com/v1/rate"), "/v1/rate");
+        assert_eq!(signing_path("not a url"), "");
+    }
+
+    fn header<'a>(request: &'a reqwest::Request, name: &str) -> &'a str {
+        request
+            .headers()
+            .get(name)
+            .map(|v| v.to_str().unwrap())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn signed_get_signs_its_query_and_sends_no_raw_key() {
+        let request = signed_get(
+            &Client::new(),
+            "https://api.example.com/v1/balance",
+            &[("phone", "2348012345678"), ("token", "0x1")],
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            request.url().as_str(),
+            "https://api.example.com/v1/balance?phone=2348012345678&token=0x1"
+        );
+        assert_eq!(header(&request, "x-service"), "whatsapp-bot");
+        assert!(request.headers().get("x-api-key").is_none());
+        assert!(request.body().is_none());
+        assert!(verify(
+            &hmac_keys().primary,
+            "GET",
+            "/v1/balance?phone=2348012345678&token=0x1",
+            header(&request, "x-timestamp"),
+            header(&request, "x-nonce"),
+            b"",
+            header(&request, "x-signature"),
+        ));
+    }
+
+    #[test]
+    fn signed_headers_are_covered_by_the_signature() {
+        let headers = [("x-user-phone", "2348012345678")];
+        let signature = sign_with_headers(
+            "test-key",
+            "GET",
+            "/transactions/KP-1/status",
+            "1700000000",
+            "nonce",
+            &headers,
+            b"",
+        );
+        assert_eq!(
+            signature,
+            "9f7b362f5fd0bdb24e2ee33649ced1facefad8f8196ef8beabf4380b1b3cdf84"
+        );
+
+        let verifies = |headers: &[(&str, &str)]| {
+            sign_with_headers(
+                "test-key",
+                "GET",
+                "/transactions/KP-1/status",
+                "1700000000",
+                "nonce",
+                headers,
+                b"",
+            ) == signature
+        };
+        assert!(verifies(&headers));
+        assert!(verifies(&[("X-User-Phone", "2348012345678")]));
+        assert!(!verifies(&[("x-user-phone", "2348099999999")]));
+        assert!(!verifies(&[]));
+    }
+
+    #[test]
+    fn signed_request_sends_and_lists_its_signed_headers() {
+        let request = signed_request(
+            &Client::new(),
+            Method::GET,
+            "https://api.example.com/transactions/KP-1/status",
+            &[],
+            &[("x-user-phone", "2348012345678")],
+            Vec::new(),
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(header(&request, "x-user-phone"), "2348012345678");
+        assert_eq!(header(&request, "x-signed-headers"), "x-user-phone");
+        let expected = sign_with_headers(
+            &hmac_keys().primary,
+            "GET",
+            "/transactions/KP-1/status",
+            header(&request, "x-timestamp"),
+            header(&request, "x-nonce"),
+            &[("x-user-phone", "2348012345678")],
+            b"",
+        );
+        assert_eq!(header(&request, "x-signature"), expected);
+        // Someone swapping in another number can't keep the signature
+        assert!(!verify(
+            &hmac_keys().primary,
+            "GET",
+            "/transactions/KP-1/status",
+            header(&request, "x-timestamp"),
+            header(&request, "x-nonce"),
+            b"",
+            header(&request, "x-signature"),
+        ));
+    }
+
+    #[test]
+    fn signed_post_signs_its_body() {
+        let request = signed_post(
+            &Client::new(),
+            "https://api.example.com/v1/offramp",
+            &serde_json::json!({ "amount": 10 }),
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(header(&request, "content-type"), "application/json");
+        assert!(request.headers().get("x-api-key").is_none());
+        let body = request.body().and_then(|body| body.as_bytes()).unwrap();
+        assert_eq!(body, br#"{"amount":10}"#);
+        assert!(verify(
+            &hmac_keys().primary,
+            "POST",
+            "/v1/offramp",
+            header(&request, "x-timestamp"),
+            header(&request, "x-nonce"),
+            body,
+            header(&request, "x-signature"),
+        ));
+    }
+
+    #[test]
+    fn slowness_is_judged_against_each_endpoints_own_deadline() {
+        // 40s is most of an offramp's minute but well inside account creation's 3 minutes
+        for _ in 0..5 {
+            record(Endpoint::Offramp, Duration::from_secs(40), true);
+            record(Endpoint::CreateAccount, Duration::from_secs(40), true);
+        }
+        assert!(is_degraded(Endpoint::Offramp));
+        assert!(!is_degraded(Endpoint::CreateAccount));
+    }
+
+    #[test]
+    fn a_degraded_endpoint_lets_a_probe_through_and_recovers_on_its_success() {
+        for _ in 0..5 {
+            record(Endpoint::Payment, Duration::from_millis(50), false);
+        }
+        assert!(is_degraded(Endpoint::Payment));
+
+        // The first request probes; the rest are shed until it answers
+        assert_eq!(shed_if_degraded(Endpoint::Payment), None);
+        assert!(shed_if_degraded(Endpoint::Payment).is_some());
+
+        record(Endpoint::Payment, Duration::from_millis(50), true);
+        assert!(!is_degraded(Endpoint::Payment));
+        assert_eq!(shed_if_degraded(Endpoint::Payment), None);
+    }
+
+    #[test]
+    fn a_failed_probe_keeps_the_endpoint_shed() {
+        for _ in 0..5 {
+            record(Endpoint::History, Duration::from_millis(50), false);
+        }
+        assert_eq!(shed_if_degraded(Endpoint::History), None);
+        record(Endpoint::History, Duration::from_millis(50), false);
+        assert!(is_degraded(Endpoint::History));
+        assert!(shed_if_degraded(Endpoint::History).is_some());
+    }
+}
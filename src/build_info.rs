@@ -0,0 +1,62 @@
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use actix_web::HttpResponse;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{mock_backend, outbound};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Set by `build.rs`; "unknown" when built outside a git checkout.
+pub const GIT_SHA: &str = env!("GIT_SHA");
+
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Starts the uptime clock. Called first thing in `main`.
+pub fn mark_started() {
+    LazyLock::force(&STARTED);
+}
+
+pub fn uptime_secs() -> u64 {
+    STARTED.elapsed().as_secs()
+}
+
+pub fn started_at() -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::from_std(STARTED.elapsed()).unwrap_or_default()
+}
+
+/// Switches that change how the instance behaves, so a misbehaving one can be told apart
+/// from its neighbours at a glance.
+#[derive(Debug, Clone, Serialize)]
+pub struct Flags {
+    pub mock_backend: bool,
+    pub twilio_dry_run: bool,
+    pub tls: bool,
+    pub session_store: String,
+    pub error_reporting: &'static str,
+}
+
+pub fn flags() -> Flags {
+    let set = |name: &str| std::env::var(name).is_ok_and(|v| !v.trim().is_empty());
+    Flags {
+        mock_backend: mock_backend::enabled(),
+        twilio_dry_run: outbound::dry_run(),
+        tls: set("TLS_CERT_PATH"),
+        session_store: std::env::var("SESSION_STORE").unwrap_or_else(|_| "memory".to_string()),
+        error_reporting: if set("SENTRY_DSN") { "sentry" } else { "log" },
+    }
+}
+
+/// `v0.1.0 (abc123def456)`, for log lines.
+pub fn describe() -> String {
+    format!("v{} ({})", VERSION, GIT_SHA)
+}
+
+/// `GET /version`: the build only, for deployment tooling.
+pub async fn version() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "version": VERSION,
+        "git_sha": GIT_SHA,
+    }))
+}
@@ -0,0 +1,210 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::OnceCell;
+
+/// (phone, token) -> (rendered reply, fetched at).
+type BalanceMap = HashMap<(String, String), (String, DateTime<Utc>)>;
+
+/// Last rendered balance reply per user and token, so repeated `balance` messages while
+/// waiting for a deposit don't each cost a backend round trip.
+static BALANCES: LazyLock<Mutex<BalanceMap>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// `BALANCE_CACHE_TTL_SECS`, default 20s. Zero disables caching.
+fn balance_ttl() -> Duration {
+    Duration::seconds(
+        std::env::var("BALANCE_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(20),
+    )
+}
+
+fn phone_key(phone: &str) -> String {
+    phone.trim_start_matches('+').to_string()
+}
+
+/// Balance fetches currently running, per (phone, token).
+type InFlightMap = HashMap<(String, String), Arc<OnceCell<String>>>;
+
+static IN_FLIGHT: LazyLock<Mutex<InFlightMap>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Runs `fetch` once for every caller that asks about the same phone and token while it
+/// is in flight; the others wait for and share its reply. Callers arriving afterwards
+/// are served by the cache, if `fetch` stored its reply there.
+pub async fn coalesce_balance<F, Fut>(phone: &str, token: &str, fetch: F) -> String
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = String>,
+{
+    let key = (phone_key(phone), token.to_string());
+    let cell = IN_FLIGHT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(key.clone())
+        .or_default()
+        .clone();
+
+    let reply = cell.get_or_init(fetch).await.clone();
+
+    let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+    if in_flight
+        .get(&key)
+        .is_some_and(|current| Arc::ptr_eq(current, &cell))
+    {
+        in_flight.remove(&key);
+    }
+    reply
+}
+
+/// A cached reply still within the TTL, with how old it is.
+pub fn cached_balance(phone: &str, token: &str) -> Option<(String, Duration)> {
+    let balances = BALANCES.lock().unwrap_or_else(|e| e.into_inner());
+    let (reply, fetched_at) = balances.get(&(phone_key(phone), token.to_string()))?;
+    let age = Utc::now() - *fetched_at;
+    (age < balance_ttl()).then(|| (reply.clone(), age))
+}
+
+pub fn store_balance(phone: &str, token: &str, reply: &str) {
+    let now = Utc::now();
+    let ttl = balance_ttl();
+    let mut balances = BALANCES.lock().unwrap_or_else(|e| e.into_inner());
+    balances.retain(|_, (_, fetched_at)| now - *fetched_at < ttl);
+    if ttl > Duration::zero() {
+        balances.insert(
+            (phone_key(phone), token.to_string()),
+            (reply.to_string(), now),
+        );
+    }
+}
+
+/// Drops every cached balance for `phone`; called when funds move.
+pub fn invalidate_balance(phone: &str) {
+    let key = phone_key(phone);
+    BALANCES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|(cached_phone, _), _| *cached_phone != key);
+}
+
+/// Last USD/NGN rate and when it was fetched.
+type CachedRate = Option<(f64, DateTime<Utc>)>;
+
+static RATE: LazyLock<Mutex<CachedRate>> = LazyLock::new(|| Mutex::new(None));
+
+/// `RATE_CACHE_TTL_SECS`, default 60s. Only indicative quotes read it.
+pub fn cached_rate() -> Option<f64> {
+    let ttl = Duration::seconds(
+        std::env::var("RATE_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+    );
+    let rate = RATE.lock().unwrap_or_else(|e| e.into_inner());
+    rate.filter(|(_, fetched_at)| Utc::now() - *fetched_at < ttl)
+        .map(|(rate, _)| rate)
+}
+
+pub fn store_rate(rate: f64) {
+    *RATE.lock().unwrap_or_else(|e| e.into_inner()) = Some((rate, Utc::now()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn a_stored_balance_is_served_with_its_age_until_it_expires() {
+        store_balance("+2348090001101", "USDT", "💰 10 USDT");
+        let (reply, age) = cached_balance("2348090001101", "USDT").unwrap();
+        assert_eq!(reply, "💰 10 USDT");
+        assert!(age < Duration::seconds(2));
+        assert!(cached_balance("+2348090001101", "USDC").is_none());
+
+        let fetched_at = Utc::now() - balance_ttl();
+        BALANCES.lock().unwrap().insert(
+            ("2348090001101".to_string(), "USDT".to_string()),
+            ("💰 9 USDT".to_string(), fetched_at),
+        );
+        assert!(cached_balance("+2348090001101", "USDT").is_none());
+    }
+
+    #[test]
+    fn invalidating_drops_every_token_for_that_phone_only() {
+        store_balance("+2348090001102", "USDT", "a");
+        store_balance("+2348090001102", "USDC", "b");
+        store_balance("+2348090001103", "USDT", "c");
+
+        invalidate_balance("2348090001102");
+        assert!(cached_balance("+2348090001102", "USDT").is_none());
+        assert!(cached_balance("+2348090001102", "USDC").is_none());
+        assert!(cached_balance("+2348090001103", "USDT").is_some());
+    }
+
+    /// Asks `callers` times at once, each fetch taking a while; returns the replies and
+    /// how many fetches ran.
+    async fn ask_at_once(
+        phone: &'static str,
+        token: &'static str,
+        callers: usize,
+    ) -> (Vec<String>, usize) {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let asks: Vec<_> = (0..callers)
+            .map(|_| tokio::spawn(ask(phone, token, fetches.clone())))
+            .collect();
+        let mut replies = Vec::new();
+        for ask in asks {
+            replies.push(ask.await.unwrap());
+        }
+        (replies, fetches.load(Ordering::SeqCst))
+    }
+
+    async fn ask(phone: &'static str, token: &'static str, fetches: Arc<AtomicUsize>) -> String {
+        coalesce_balance(phone, token, || async move {
+            let n = fetches.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            format!("💰 {} {} fetch {}", phone, token, n)
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn concurrent_asks_share_one_fetch() {
+        let (replies, fetches) = ask_at_once("+2348090001104", "USDT", 5).await;
+        assert_eq!(fetches, 1);
+        assert_eq!(replies, vec!["💰 +2348090001104 USDT fetch 1"; 5]);
+    }
+
+    #[tokio::test]
+    async fn asks_after_a_fetch_finishes_fetch_again() {
+        let (first, _) = ask_at_once("+2348090001105", "USDT", 3).await;
+        let (second, fetches) = ask_at_once("+2348090001105", "USDT", 3).await;
+        assert_eq!(fetches, 1);
+        assert_eq!(first, second);
+        assert!(
+            IN_FLIGHT
+                .lock()
+                .unwrap()
+                .keys()
+                .all(|(phone, _)| phone != "2348090001105")
+        );
+    }
+
+    #[tokio::test]
+    async fn other_tokens_and_phones_are_fetched_separately() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let (a, b, c, d) = tokio::join!(
+            ask("+2348090001106", "USDT", fetches.clone()),
+            ask("2348090001106", "USDT", fetches.clone()),
+            ask("+2348090001106", "USDC", fetches.clone()),
+            ask("+2348090001107", "USDT", fetches.clone()),
+        );
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+        // The same number however it's written
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, d);
+    }
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+
+use actix_web::error::InternalError;
+use actix_web::{Error, FromRequest, HttpRequest, HttpResponse, dev::Payload, web};
+use chrono::Utc;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use crate::access::normalize_phone;
+use crate::backend::{self, HmacKeys};
+use crate::money::{TokenAmount, token_decimals};
+use crate::privacy::mask_phone;
+use crate::twilio::send_notice;
+use crate::window::Notice;
+use crate::{cache, polling, request_body, transactions};
+
+/// `CALLBACK_HMAC_KEY`, with `CALLBACK_HMAC_KEY_SECONDARY` during a rotation. Separate
+/// from `HMAC_KEY` so the key the backend signs callbacks with can't also sign requests
+/// to it. Unset means every callback is refused.
+static CALLBACK_KEYS: LazyLock<RwLock<Option<Arc<HmacKeys>>>> = LazyLock::new(|| {
+    let keys = HmacKeys::from_vars("CALLBACK_HMAC_KEY", "CALLBACK_HMAC_KEY_SECONDARY")
+        .map_err(|e| eprintln!("ALERT: {}; every callback will be rejected", e))
+        .ok();
+    RwLock::new(keys.map(Arc::new))
+});
+
+fn keys() -> Option<Arc<HmacKeys>> {
+    CALLBACK_KEYS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Re-reads the callback keys; on error the old ones stay active.
+pub fn reload_keys() -> Result<Arc<HmacKeys>, String> {
+    let keys = Arc::new(HmacKeys::from_vars(
+        "CALLBACK_HMAC_KEY",
+        "CALLBACK_HMAC_KEY_SECONDARY",
+    )?);
+    *CALLBACK_KEYS.write().unwrap_or_else(|e| e.into_inner()) = Some(keys.clone());
+    Ok(keys)
+}
+
+/// `CALLBACK_MAX_SKEW_SECS`, default 5 min: how far `x-timestamp` may be from our clock.
+fn max_skew_secs() -> i64 {
+    std::env::var("CALLBACK_MAX_SKEW_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(300)
+}
+
+/// Nonces seen inside the skew window, so a captured callback can't be replayed before
+/// its timestamp goes stale.
+static SEEN_NONCES: LazyLock<Mutex<HashMap<String, i64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn first_use(nonce: &str, now: i64, skew: i64) -> bool {
+    let mut seen = SEEN_NONCES.lock().unwrap_or_else(|e| e.into_inner());
+    seen.retain(|_, at| now - *at <= skew * 2);
+    seen.insert(nonce.to_string(), now).is_none()
+}
+
+/// A backend→bot request whose `x-signature` checked out. It is signed the same way as
+/// our requests to the backend (see `backend::sign`), under `CALLBACK_HMAC_KEY` or,
+/// during a rotation, `CALLBACK_HMAC_KEY_SECONDARY`.
+///
+/// Taking it as a handler argument rejects the request with 401 before the handler runs.
+pub struct SignedCallback {
+    pub body: web::Bytes,
+}
+
+impl SignedCallback {
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn verify(req: &HttpRequest, body: &[u8]) -> Result<(), &'static str> {
+    let Some(keys) = keys() else {
+        return Err("no callback key configured");
+    };
+    verify_with(req, body, &keys, Utc::now().timestamp(), max_skew_secs())
+}
+
+/// `verify` against given keys and clock.
+fn verify_with(
+    req: &HttpRequest,
+    body: &[u8],
+    keys: &HmacKeys,
+    now: i64,
+    skew: i64,
+) -> Result<(), &'static str> {
+    let (Some(timestamp), Some(nonce), Some(signature)) = (
+        header(req, "x-timestamp"),
+        header(req, "x-nonce"),
+        header(req, "x-signature"),
+    ) else {
+        return Err("missing signature headers");
+    };
+
+    let sent_at: i64 = timestamp.parse().map_err(|_| "invalid timestamp")?;
+    if (now - sent_at).abs() > skew {
+        return Err("stale timestamp");
+    }
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or_else(|| req.path());
+    let valid = keys.candidates().filter(|key| !key.is_empty()).any(|key| {
+        backend::verify(
+            key,
+            req.method().as_str(),
+            path,
+            timestamp,
+            nonce,
+            body,
+            signature,
+        )
+    });
+    if !valid {
+        return Err("bad signature");
+    }
+
+    // Only a verified nonce is remembered, so forged requests can't fill the map
+    if !first_use(nonce, now, skew) {
+        return Err("replayed nonce");
+    }
+    Ok(())
+}
+
+impl FromRequest for SignedCallback {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if let Err(e) = request_body::check_headers(req, request_body::JSON) {
+            return Box::pin(async move { Err(e) });
+        }
+        let req = req.clone();
+        let body = web::Bytes::from_request(&req, payload);
+        Box::pin(async move {
+            let body = body.await?;
+            match verify(&req, &body) {
+                Ok(()) => Ok(SignedCallback { body }),
+                Err(reason) => {
+                    eprintln!("Rejected callback to {}: {}", req.path(), reason);
+                    let response = HttpResponse::Unauthorized().json(serde_json::json!({
+                        "success": false,
+                        "message": "Invalid callback signature",
+                    }));
+                    Err(InternalError::from_response(reason, response).into())
+                }
+            }
+        })
+    }
+}
+
+/// Fallback for `/callbacks/*`: unknown paths still have to be signed, so probes learn
+/// nothing about which callbacks exist.
+pub async fn unknown_callback(_callback: SignedCallback) -> HttpResponse {
+    HttpResponse::NotFound().finish()
+}
+
+fn bad_request(message: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "success": false,
+        "message": message,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusCallback {
+    pub reference: String,
+}
+
+/// `POST /callbacks/transaction-status`: the backend saying a withdrawal moved. Only a
+/// hint; the poller fetches the status itself before telling the user anything, so the
+/// callback just saves it the wait.
+pub async fn transaction_status(callback: SignedCallback) -> HttpResponse {
+    let Ok(StatusCallback { reference }) = callback.json() else {
+        return bad_request("Expected {\"reference\": \"…\"}");
+    };
+    let Some(tx) = transactions::lookup(&reference).await else {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Unknown reference",
+        }));
+    };
+
+    let polling = if polling::wake(&reference) {
+        "woken"
+    } else if !matches!(
+        tx.status.as_str(),
+        "completed" | "successful" | "failed" | "cancelled"
+    ) {
+        polling::start_transaction_polling_task(reference);
+        "started"
+    } else {
+        "already_final"
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "polling": polling,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DepositCallback {
+    pub phone: String,
+    pub token: String,
+    /// As the backend sends balances; see `TokenAmount::from_backend`.
+    pub amount: String,
+}
+
+/// `POST /callbacks/deposit`: funds arrived at a user's wallet. Drops their cached
+/// balance so the next `balance` is fresh, and tells them.
+pub async fn deposit(callback: SignedCallback) -> HttpResponse {
+    let Ok(DepositCallback {
+        phone,
+        token,
+        amount,
+    }) = callback.json()
+    else {
+        return bad_request("Expected phone, token and amount");
+    };
+    let Some(phone) = normalize_phone(&phone) else {
+        return bad_request("Invalid phone");
+    };
+    let amount = match TokenAmount::from_backend(&amount, token_decimals(&token)) {
+        Ok(amount) if !amount.is_zero() => amount,
+        _ => return bad_request("Invalid amount"),
+    };
+
+    cache::invalidate_balance(&phone);
+    println!(
+        "Deposit of {} {} to {}",
+        amount.display(),
+        token,
+        mask_phone(&phone)
+    );
+    let message = format!(
+        "💰 *Deposit received*\n\n\
+        {} {} has arrived in your wallet.\n\n\
+        Type `balance` to see your balance or `withdraw` to cash out.",
+        amount.display(),
+        token.to_uppercase()
+    );
+    send_notice(&phone, &message, Notice::Completion).await;
+    HttpResponse::Ok().json(serde_json::json!({ "success": true }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    const NOW: i64 = 1_700_000_000;
+    const BODY: &[u8] = br#"{"reference":"KP-1"}"#;
+
+    fn keys(primary: &str, secondary: Option<&str>) -> HmacKeys {
+        HmacKeys {
+            primary: primary.to_string(),
+            secondary: secondary.map(str::to_string),
+        }
+    }
+
+    fn request(timestamp: i64, nonce: &str, signature: &str) -> HttpRequest {
+        TestRequest::post()
+            .uri("/callbacks/transaction-status")
+            .insert_header(("x-timestamp", timestamp.to_string()))
+            .insert_header(("x-nonce", nonce))
+            .insert_header(("x-signature", signature))
+            .to_http_request()
+    }
+
+    fn signed(key: &str, timestamp: i64, nonce: &str, body: &[u8]) -> HttpRequest {
+        let signature = backend::sign(
+            key,
+            "POST",
+            "/callbacks/transaction-status",
+            &timestamp.to_string(),
+            nonce,
+            body,
+        );
+        request(timestamp, nonce, &signature)
+    }
+
+    #[test]
+    fn accepts_a_known_good_signature() {
+        // HMAC-SHA256("callback-key", "POST\n/callbacks/transaction-status\n1700000000\nvector-nonce\n" + BODY)
+        let req = request(
+            NOW,
+            "vector-nonce",
+            "e01455c8ffeb1d076d35fbb44d0f4e0b214da22c4c1871e0cd51491701a040e9",
+        );
+        assert_eq!(
+            verify_with(&req, BODY, &keys("callback-key", None), NOW, 300),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn accepts_the_secondary_key_during_a_rotation() {
+        let keys = keys("new-key", Some("old-key"));
+        let req = signed("old-key", NOW, "rotation-nonce", BODY);
+        assert_eq!(verify_with(&req, BODY, &keys, NOW, 300), Ok(()));
+    }
+
+    #[test]
+    fn rejects_other_keys_and_tampered_bodies() {
+        let keys = keys("callback-key", None);
+        let req = signed("backend-key", NOW, "wrong-key-nonce", BODY);
+        assert_eq!(
+            verify_with(&req, BODY, &keys, NOW, 300),
+            Err("bad signature")
+        );
+
+        let req = signed("callback-key", NOW, "tampered-nonce", BODY);
+        let tampered = br#"{"reference":"KP-2"}"#;
+        assert_eq!(
+            verify_with(&req, tampered, &keys, NOW, 300),
+            Err("bad signature")
+        );
+
+        let req = request(NOW, "garbage-nonce", "not hex");
+        assert_eq!(
+            verify_with(&req, BODY, &keys, NOW, 300),
+            Err("bad signature")
+        );
+    }
+
+    #[test]
+    fn rejects_timestamps_outside_the_window() {
+        let keys = keys("callback-key", None);
+        let req = signed("callback-key", NOW - 301, "stale-nonce", BODY);
+        assert_eq!(
+            verify_with(&req, BODY, &keys, NOW, 300),
+            Err("stale timestamp")
+        );
+        let req = signed("callback-key", NOW + 301, "future-nonce", BODY);
+        assert_eq!(
+            verify_with(&req, BODY, &keys, NOW, 300),
+            Err("stale timestamp")
+        );
+        let req = signed("callback-key", NOW - 300, "edge-nonce", BODY);
+        assert_eq!(verify_with(&req, BODY, &keys, NOW, 300), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_replayed_nonce() {
+        let keys = keys("callback-key", None);
+        let req = signed("callback-key", NOW, "replayed-nonce", BODY);
+        assert_eq!(verify_with(&req, BODY, &keys, NOW, 300), Ok(()));
+        assert_eq!(
+            verify_with(&req, BODY, &keys, NOW + 1, 300),
+            Err("replayed nonce")
+        );
+    }
+
+    #[test]
+    fn rejects_missing_headers() {
+        let req = TestRequest::post()
+            .uri("/callbacks/deposit")
+            .to_http_request();
+        assert_eq!(
+            verify_with(&req, BODY, &keys("callback-key", None), NOW, 300),
+            Err("missing signature headers")
+        );
+    }
+}
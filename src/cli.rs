@@ -0,0 +1,258 @@
+use std::ffi::OsString;
+
+use clap::{Parser, Subcommand};
+
+use crate::privacy::mask_phone;
+use crate::{config, proxy, server, store, tls};
+
+/// WhatsApp front end for Kharon Pay withdrawals.
+#[derive(Debug, Parser)]
+#[command(name = "kharon-pay-whatsapp", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum Command {
+    /// Run the webhook server (the default)
+    Serve,
+    /// Validate the environment and print a redacted summary
+    CheckConfig,
+    /// Send one WhatsApp message, for smoke tests
+    Send {
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        message: String,
+    },
+    /// Print the stored session, with account numbers masked
+    Session {
+        #[arg(long)]
+        phone: String,
+    },
+}
+
+/// Parses the full command line, program name first. `--help` and `--version` come back
+/// as errors too; `clap::Error::exit` prints them and exits 0.
+pub fn parse<I, T>(args: I) -> Result<Command, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    Cli::try_parse_from(args).map(|cli| cli.command.unwrap_or(Command::Serve))
+}
+
+/// What `serve` refuses to start without. `check-config` reports the same list, so a clean
+/// check means the server will come up.
+pub fn problems() -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for name in ["T_ACCOUNT_SID", "T_AUTH_TOKEN"] {
+        match config::secret(name) {
+            Ok(Some(_)) => {}
+            Ok(None) => problems.push(format!("{} (or {}_FILE) must be set", name, name)),
+            Err(e) => problems.push(e),
+        }
+    }
+    for name in ["T_WHATSAPP_NUMBER", "T_API_URL"] {
+        if std::env::var(name).is_err() {
+            problems.push(format!("{} must be set", name));
+        }
+    }
+
+    if let Err(e) = proxy::require_valid() {
+        problems.push(e);
+    }
+    match tls::from_env() {
+        Ok(Some(paths)) => {
+            if let Err(e) = tls::check(&paths) {
+                problems.push(e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => problems.push(e),
+    }
+    if let Err(e) = store::from_env() {
+        problems.push(e);
+    }
+
+    problems
+}
+
+/// Settings whose values are safe to print.
+const PLAIN_SETTINGS: [&str; 7] = [
+    "T_WHATSAPP_NUMBER",
+    "T_API_URL",
+    "SESSION_STORE",
+    "SESSION_DB_PATH",
+    "TRUSTED_PROXIES",
+    "TLS_CERT_PATH",
+    "BACKEND_HEALTH_URL",
+];
+
+/// Secret-bearing settings; only whether they are set is printed.
+const SECRET_SETTINGS: [&str; 10] = [
+    "T_ACCOUNT_SID",
+    "T_AUTH_TOKEN",
+    "HMAC_KEY",
+    "HMAC_KEY_SECONDARY",
+    "CALLBACK_HMAC_KEY",
+    "CALLBACK_HMAC_KEY_SECONDARY",
+    "SESSION_ENC_KEY",
+    "ADMIN_API_KEY",
+    "EXPORT_SIGNING_KEY",
+    "EXPORT_SIGNING_KEY_SECONDARY",
+];
+
+/// `check-config`: prints a redacted summary and any problems; exit code 1 when there are some.
+pub fn check_config() -> i32 {
+    println!("Settings:");
+    for name in PLAIN_SETTINGS {
+        let value = std::env::var(name).unwrap_or_else(|_| "(unset)".to_string());
+        println!("  {:<28} {}", name, value);
+    }
+    for name in SECRET_SETTINGS {
+        let state = match config::secret(name) {
+            Ok(Some(_)) => "set (redacted)",
+            Ok(None) => "(unset)",
+            Err(_) => "(unreadable)",
+        };
+        println!("  {:<28} {}", name, state);
+    }
+
+    let problems = problems();
+    if problems.is_empty() {
+        println!("\n✅ Configuration OK");
+        return 0;
+    }
+    println!("\n❌ {} problem(s):", problems.len());
+    for problem in &problems {
+        println!("  - {}", problem);
+    }
+    1
+}
+
+/// `send`: one message through the same Twilio path the server uses.
+pub async fn send(to: &str, message: &str) -> i32 {
+    match server::try_send_twilio_content(to, message, None).await {
+        Ok(sid) => {
+            println!(
+                "Sent to {} ({})",
+                mask_phone(to),
+                sid.as_deref().unwrap_or("no SID")
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("Send to {} failed: {}", mask_phone(to), e);
+            1
+        }
+    }
+}
+
+/// `session`: the stored session for `phone`, redacted as in the admin API.
+pub fn session(phone: &str) -> i32 {
+    let kind = std::env::var("SESSION_STORE").unwrap_or_else(|_| "memory".to_string());
+    if kind.eq_ignore_ascii_case("memory") {
+        eprintln!("SESSION_STORE=memory keeps sessions inside the server process; nothing to read");
+        return 1;
+    }
+
+    let sessions = match store::from_env() {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    match sessions.load(&store::session_key(phone)) {
+        Ok(Some(session)) => match serde_json::to_string_pretty(&session.redacted()) {
+            Ok(json) => {
+                println!("{}", json);
+                0
+            }
+            Err(e) => {
+                eprintln!("Failed to encode session: {}", e);
+                1
+            }
+        },
+        Ok(None) => {
+            eprintln!("No session for {}", mask_phone(phone));
+            1
+        }
+        Err(e) => {
+            eprintln!("Failed to load session for {}: {}", mask_phone(phone), e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+
+    fn parse_line(line: &str) -> Result<Command, clap::Error> {
+        parse(std::iter::once("kharon-pay-whatsapp").chain(line.split_whitespace()))
+    }
+
+    #[test]
+    fn no_command_serves() {
+        assert_eq!(parse_line("").unwrap(), Command::Serve);
+        assert_eq!(parse_line("serve").unwrap(), Command::Serve);
+        assert_eq!(parse_line("check-config").unwrap(), Command::CheckConfig);
+    }
+
+    #[test]
+    fn flags_take_either_form() {
+        assert_eq!(
+            parse_line("send --to +2348012345678 --message=hello").unwrap(),
+            Command::Send {
+                to: "+2348012345678".to_string(),
+                message: "hello".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_line("session --phone=+2348012345678").unwrap(),
+            Command::Session {
+                phone: "+2348012345678".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn bad_command_lines_are_refused() {
+        let kind = |line| parse_line(line).unwrap_err().kind();
+        assert_eq!(
+            kind("send --to +2348012345678"),
+            ErrorKind::MissingRequiredArgument
+        );
+        assert_eq!(kind("session --phone"), ErrorKind::InvalidValue);
+        assert_eq!(kind("serve --port 80"), ErrorKind::UnknownArgument);
+        assert_eq!(kind("deploy"), ErrorKind::InvalidSubcommand);
+    }
+
+    #[test]
+    fn help_and_version_are_generated() {
+        let help = parse_line("--help").unwrap_err();
+        assert_eq!(help.kind(), ErrorKind::DisplayHelp);
+        let text = help.to_string();
+        for command in ["serve", "check-config", "send", "session"] {
+            assert!(
+                text.contains(command),
+                "{} missing from:\n{}",
+                command,
+                text
+            );
+        }
+        assert_eq!(
+            parse_line("--version").unwrap_err().kind(),
+            ErrorKind::DisplayVersion
+        );
+        assert_eq!(
+            parse_line("send --help").unwrap_err().kind(),
+            ErrorKind::DisplayHelp
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::backend::Endpoint;
+
+fn env_secs(name: &str, default: u64) -> Duration {
+    Duration::from_secs(
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(default),
+    )
+}
+
+/// How long and how often we poll a withdrawal's status.
+#[derive(Debug, Clone)]
+pub struct PollConfig {
+    /// `POLL_INTERVAL_SECS`, default 2s.
+    pub interval: Duration,
+    /// `POLL_MAX_WAIT_MINUTES`, default 30.
+    pub max_wait: Duration,
+    /// `SLOW_POLL_INTERVAL_SECS`, default 10 min: re-check cadence once `max_wait` is spent.
+    pub slow_interval: Duration,
+    /// `SLOW_POLL_HOURS`, default 3; 0 turns the slow lane off.
+    pub slow_window: Duration,
+    /// `PROGRESS_UPDATE_AFTER_SECS`, default 5 min; 0 turns the interim update off.
+    pub progress_after: Option<Duration>,
+    /// `PROGRESS_UPDATE_MESSAGE`; `{reference}` is replaced with the transaction reference.
+    pub progress_message: String,
+}
+
+const DEFAULT_PROGRESS_MESSAGE: &str = "⏳ Your withdrawal (ref {reference}) is still processing.\n\n\
+    Some banks take up to 30 minutes — we'll confirm as soon as it lands.";
+
+impl PollConfig {
+    pub fn from_env() -> Self {
+        let slow_hours: u64 = std::env::var("SLOW_POLL_HOURS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3);
+
+        PollConfig {
+            interval: env_secs("POLL_INTERVAL_SECS", 2),
+            // The variable is in minutes; `env_secs` only parses the number
+            max_wait: env_secs("POLL_MAX_WAIT_MINUTES", 30) * 60,
+            slow_interval: env_secs("SLOW_POLL_INTERVAL_SECS", 10 * 60),
+            slow_window: Duration::from_secs(slow_hours * 60 * 60),
+            progress_after: match std::env::var("PROGRESS_UPDATE_AFTER_SECS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+            {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => Some(Duration::from_secs(5 * 60)),
+            },
+            progress_message: std::env::var("PROGRESS_UPDATE_MESSAGE")
+                .ok()
+                .filter(|m| !m.trim().is_empty())
+                .map(|m| m.replace("\\n", "\n"))
+                .unwrap_or_else(|| DEFAULT_PROGRESS_MESSAGE.to_string()),
+        }
+    }
+}
+
+/// How long each backend call may take, applied per request by `send_tracked`. A rate
+/// lookup that takes more than a few seconds isn't coming back, while creating an
+/// account deploys a wallet and can take minutes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeoutConfig {
+    /// `RATE_TIMEOUT_SECS`, default 5s.
+    pub rate: Duration,
+    /// `BALANCE_TIMEOUT_SECS`, default 10s.
+    pub balance: Duration,
+    /// `ADDRESS_TIMEOUT_SECS`, default 10s.
+    pub address: Duration,
+    /// `HISTORY_TIMEOUT_SECS`, default 15s.
+    pub history: Duration,
+    /// `BANK_VERIFY_TIMEOUT_SECS`, default 20s: the bank's name enquiry is slow.
+    pub bank_verify: Duration,
+    /// `BANK_TIMEOUT_SECS`, default 15s, for listing and saving banks.
+    pub bank: Duration,
+    /// `OFFRAMP_TIMEOUT_SECS`, default 60s, for initiating and cancelling an offramp.
+    pub offramp: Duration,
+    /// `PAYMENT_TIMEOUT_SECS`, default 60s.
+    pub payment: Duration,
+    /// `STATUS_TIMEOUT_SECS`, default 10s.
+    pub status: Duration,
+    /// `ACCOUNT_CREATION_TIMEOUT_SECS`, default 3 min, for the account and its controller.
+    pub account_creation: Duration,
+}
+
+impl TimeoutConfig {
+    pub fn from_env() -> Self {
+        TimeoutConfig {
+            rate: env_secs("RATE_TIMEOUT_SECS", 5),
+            balance: env_secs("BALANCE_TIMEOUT_SECS", 10),
+            address: env_secs("ADDRESS_TIMEOUT_SECS", 10),
+            history: env_secs("HISTORY_TIMEOUT_SECS", 15),
+            bank_verify: env_secs("BANK_VERIFY_TIMEOUT_SECS", 20),
+            bank: env_secs("BANK_TIMEOUT_SECS", 15),
+            offramp: env_secs("OFFRAMP_TIMEOUT_SECS", 60),
+            payment: env_secs("PAYMENT_TIMEOUT_SECS", 60),
+            status: env_secs("STATUS_TIMEOUT_SECS", 10),
+            account_creation: env_secs("ACCOUNT_CREATION_TIMEOUT_SECS", 3 * 60),
+        }
+    }
+
+    pub fn for_endpoint(&self, endpoint: Endpoint) -> Duration {
+        match endpoint {
+            Endpoint::CreateAccount => self.account_creation,
+            Endpoint::Address => self.address,
+            Endpoint::Balance => self.balance,
+            Endpoint::History => self.history,
+            Endpoint::Rate => self.rate,
+            Endpoint::BankVerify => self.bank_verify,
+            Endpoint::BankList | Endpoint::BankSave => self.bank,
+            Endpoint::Offramp | Endpoint::OfframpCancel => self.offramp,
+            Endpoint::Payment => self.payment,
+            Endpoint::Status => self.status,
+        }
+    }
+}
+
+/// Secrets fetched once from `SECRETS_URL` at startup; empty when it isn't set.
+static REMOTE_SECRETS: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Looks up a secret-bearing setting. Highest precedence first:
+/// 1. `<NAME>` in the environment, so an explicit override always wins;
+/// 2. `<NAME>_FILE`, a path whose trimmed contents are used. It is re-read on every call,
+///    so a rotated mount is picked up by the next reload;
+/// 3. `<NAME>` in the `SECRETS_URL` document.
+///
+/// Empty values count as unset. A `_FILE` that can't be read is an error, not a fallthrough.
+pub fn secret(name: &str) -> Result<Option<String>, String> {
+    if let Ok(value) = std::env::var(name)
+        && !value.is_empty()
+    {
+        return Ok(Some(value));
+    }
+
+    if let Ok(path) = std::env::var(format!("{}_FILE", name)) {
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}_FILE ({}): {}", name, path, e))?;
+        let value = contents.trim();
+        if !value.is_empty() {
+            return Ok(Some(value.to_string()));
+        }
+    }
+
+    Ok(REMOTE_SECRETS
+        .get()
+        .and_then(|secrets| secrets.get(name))
+        .filter(|value| !value.is_empty())
+        .cloned())
+}
+
+/// Pulls the remote secrets document from `SECRETS_URL`, if set. It may be a flat JSON
+/// object of name to value, or a Vault KV response (`data.data` or `data`).
+/// `SECRETS_TOKEN` (environment or file only) is sent as a bearer token.
+pub async fn load_remote_secrets() -> Result<(), String> {
+    let Ok(url) = std::env::var("SECRETS_URL") else {
+        return Ok(());
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| format!("Failed to build secrets client: {}", e))?;
+    let mut request = client.get(&url);
+    if let Some(token) = secret("SECRETS_TOKEN")? {
+        request = request.bearer_auth(token);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Failed to fetch secrets from SECRETS_URL: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "SECRETS_URL returned {}; refusing to start without secrets",
+            response.status()
+        ));
+    }
+    let document: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| format!("SECRETS_URL did not return JSON: {}", e))?;
+
+    let secrets = parse_secrets(&document)?;
+
+    println!("🔐 Loaded {} secrets from SECRETS_URL", secrets.len());
+    let _ = REMOTE_SECRETS.set(secrets);
+    Ok(())
+}
+
+/// The name-to-value pairs in a secrets document: a flat object, or the Vault KV v2
+/// (`data.data`) or v1 (`data`) shapes. Anything but strings and numbers is skipped.
+fn parse_secrets(document: &serde_json::Value) -> Result<HashMap<String, String>, String> {
+    Ok(document
+        .pointer("/data/data")
+        .or_else(|| document.get("data"))
+        .unwrap_or(document)
+        .as_object()
+        .ok_or("SECRETS_URL response is not a JSON object")?
+        .iter()
+        .filter_map(|(name, value)| match value {
+            serde_json::Value::String(s) => Some((name.clone(), s.clone())),
+            serde_json::Value::Number(n) => Some((name.clone(), n.to_string())),
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn flat_and_vault_documents_give_the_same_secrets() {
+        let secrets = json!({ "T_AUTH_TOKEN": "token", "PORT": 8080 });
+        let expected = parse_secrets(&secrets).unwrap();
+        assert_eq!(expected["T_AUTH_TOKEN"], "token");
+        assert_eq!(expected["PORT"], "8080");
+
+        let v1 = json!({ "data": secrets.clone() });
+        let v2 = json!({ "data": { "data": secrets, "metadata": { "version": 3 } } });
+        assert_eq!(parse_secrets(&v1).unwrap(), expected);
+        assert_eq!(parse_secrets(&v2).unwrap(), expected);
+    }
+
+    #[test]
+    fn nested_values_are_skipped_and_non_objects_rejected() {
+        let secrets = parse_secrets(&json!({ "A": "a", "B": { "c": 1 }, "D": null })).unwrap();
+        assert_eq!(secrets.len(), 1);
+        assert!(parse_secrets(&json!(["A"])).is_err());
+    }
+}
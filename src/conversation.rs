@@ -0,0 +1,2493 @@
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::alerts::{self, PayoutAlert};
+use crate::audit::{self, AuditEvent};
+use crate::backend::{
+    self, ACCOUNT_NOT_FOUND, BankSave, Endpoint, ErrorEnvelope, TrackedSend, cancel_offramp,
+    fetch_transaction_status, fetch_usd_ngn_rate, find_saved_bank, get_transaction_history,
+    get_user_bank_details, save_bank_details_to_db, shed_if_degraded, trigger_payment_with_retry,
+    verify_bank_details,
+};
+use crate::cache;
+use crate::eta;
+use crate::export;
+use crate::features;
+use crate::feedback;
+use crate::i18n::{self, Language, Msg};
+use crate::inflight::{self, Operation};
+use crate::model::{
+    AddressResponse, BalanceResponse, BankDetails, CreateControllerAPIResponse,
+    InitDisbursementResponse, PendingPayout, ReceivePaymentRequest, TransactionStatus, UserSessions,
+    UserState,
+};
+use crate::money::{self, TokenAmount, format_money};
+use crate::namecheck;
+use crate::onboarding;
+use crate::outbound::{ContentTemplate, OutboundMessage};
+use crate::parser::{
+    self, Command, CommandError, SUPPORTED_TOKENS, invoked_spec, parse_amount, parse_command,
+    shorthand_help,
+};
+use crate::polling::{self, start_transaction_polling_task, tx_hash_lines};
+use crate::privacy::{self, mask_account_number, mask_phone};
+use crate::region;
+use crate::reminder;
+use crate::starknet;
+use crate::state::{StateEvent, StateMachine};
+use crate::store;
+use crate::support;
+use crate::transactions::{self, IndexedTransaction};
+use crate::twilio::send_twilio_message;
+
+/// The first message after a restart found the session mid-flow: say where the user was,
+/// then handle the message as usual. A quote that has gone stale in the meantime is
+/// shown again at today's rate instead, unless they're cancelling.
+pub async fn resume_restored(
+    message_text: &str,
+    session: &mut UserSessions,
+) -> Vec<OutboundMessage> {
+    if StateMachine::check_invariants(session).is_err() {
+        return dispatch_message(message_text, session).await;
+    }
+    let Some(recap) = reminder::restored_recap(session) else {
+        return dispatch_message(message_text, session).await;
+    };
+
+    if reminder::restored_quote_stale(session)
+        && !message_text.trim().eq_ignore_ascii_case("cancel")
+        && let (Some(amount), Some(token)) =
+            (session.pending_amount, session.pending_currency.clone())
+    {
+        println!(
+            "Re-quoting {} after restart; their quote went stale",
+            mask_phone(&session.phone)
+        );
+        StateMachine::reset(session);
+        session.pending_amount = Some(amount);
+        session.pending_currency = Some(token.clone());
+        return vec![
+            "🔄 Picking up where you left off. Your earlier quote has expired, so here's \
+             a fresh one."
+                .into(),
+            handle_withdraw_initiation(amount, &token, session).await,
+        ];
+    }
+
+    let mut messages = vec![recap.into()];
+    messages.extend(dispatch_message(message_text, session).await);
+    messages
+}
+
+pub async fn dispatch_message(
+    message_text: &str,
+    session: &mut UserSessions,
+) -> Vec<OutboundMessage> {
+    if let Err(reason) = StateMachine::check_invariants(session) {
+        return StateMachine::repair(session, &reason);
+    }
+
+    if let Some(thanks) = feedback::capture(message_text, session) {
+        return vec![thanks.into()];
+    }
+
+    match &session.state {
+        UserState::Initial => handle_commands(message_text, session).await,
+
+        UserState::AccountCreation => handle_account_creation(message_text, session).await,
+
+        UserState::OfframpConfirmation => {
+            vec![handle_offramp_confirmation(message_text, session).await]
+        }
+
+        UserState::SavedBankConfirmation => {
+            vec![
+                handle_saved_bank_confirmation(message_text, session)
+                    .await
+                    .into(),
+            ]
+        }
+
+        UserState::BankSelection => vec![handle_bank_selection(message_text, session).await],
+
+        UserState::BankDetailsEntry => {
+            vec![handle_new_bank_details_entry(message_text, session).await]
+        }
+
+        UserState::BankDetailsConfirmation => {
+            vec![
+                handle_new_bank_confirmation(message_text, session)
+                    .await
+                    .into(),
+            ]
+        }
+
+        UserState::AmountConfirmation => {
+            vec![
+                handle_amount_confirmation(message_text, session)
+                    .await
+                    .into(),
+            ]
+        }
+
+        UserState::PayoutApproval => {
+            vec![handle_payout_approval(message_text, session).await.into()]
+        }
+
+        UserState::BankNameUpdate => {
+            vec![handle_bank_name_update(message_text, session).await.into()]
+        }
+    }
+}
+
+const UNKNOWN_COMMAND: &str =
+    "❓ I didn't understand that. Type `help` for available commands or `hi` to start.";
+
+async fn handle_commands(message: &str, session: &mut UserSessions) -> Vec<OutboundMessage> {
+    if let Some(spec) = invoked_spec(message)
+        && !spec.available_to(&session.phone)
+    {
+        return vec![features::COMING_SOON_MESSAGE.into()];
+    }
+    let command = parse_command(message);
+
+    match command {
+        Command::Greet => {
+            let welcome = i18n::welcome(
+                session.language(),
+                session.controller_address.is_some(),
+                &session.phone,
+            );
+            match onboarding::checklist(session).await {
+                Some(checklist) => vec![format!("{}\n\n{}", welcome, checklist).into()],
+                None => vec![welcome.into()],
+            }
+        }
+        Command::Create => {
+            if let Some(unavailable) = region_unavailable(session, "create") {
+                return unavailable;
+            }
+            if let Some(shed) = shed_if_degraded(Endpoint::CreateAccount) {
+                return vec![shed.into()];
+            }
+
+            let (repair, _) = StateMachine::advance(session, StateEvent::AccountCreationStarted);
+            if !repair.is_empty() {
+                return repair;
+            }
+
+            vec![USERNAME_PROMPT.into()]
+        }
+        Command::Address { refresh } => handle_get_address(session, refresh).await,
+        Command::Fund => handle_get_address(session, false).await,
+        Command::Balance { refresh, .. } => {
+            vec![handle_get_balance(session, refresh).await.into()]
+        }
+        Command::Export => vec![handle_export(session).await.into()],
+        Command::Withdraw { amount, token, .. } => {
+            if let Some(unavailable) = region_unavailable(session, "withdraw") {
+                return unavailable;
+            }
+            if let Some(short) = insufficient_balance(session, amount, &token).await {
+                return vec![short.into()];
+            }
+
+            session.pending_amount = Some(amount);
+            session.pending_currency = Some(token.clone());
+
+            vec![handle_withdraw_initiation(amount, &token, session).await]
+        }
+        Command::WithdrawAll { token, .. } => {
+            if let Some(unavailable) = region_unavailable(session, "withdraw") {
+                return unavailable;
+            }
+            let amount = match whole_balance(session, &token).await {
+                Ok(amount) => amount,
+                Err(reply) => return vec![reply.into()],
+            };
+
+            session.pending_amount = Some(amount);
+            session.pending_currency = Some(token.clone());
+
+            vec![handle_withdraw_initiation(amount, &token, session).await]
+        }
+        Command::Status { reference } => vec![handle_status(&reference, session).await.into()],
+        Command::Recap => vec![handle_recap(session).await.into()],
+        Command::Quote { amount, token } => vec![handle_quote(amount, &token).await.into()],
+        Command::CancelWithdrawal { reference } => {
+            vec![handle_cancel_withdrawal(&reference, session).await.into()]
+        }
+        Command::VerifyBank { index } => vec![handle_verify_bank(session, index).await.into()],
+        Command::Support { description } => {
+            vec![
+                support::open_ticket(session, description.as_deref())
+                    .await
+                    .into(),
+            ]
+        }
+        Command::Help => {
+            let mut reply = format!(
+                "{}\n\n{}",
+                i18n::help(
+                    session.language(),
+                    session.controller_address.is_some(),
+                    &session.phone,
+                ),
+                shorthand_help()
+            );
+            if let Some(checklist) = onboarding::checklist(session).await {
+                reply = format!("{}\n\n{}", reply, checklist);
+            }
+            vec![reply.into()]
+        }
+        Command::Language(Some(language)) => {
+            session.prefs.language = Some(language);
+            session.prefs.language_detected = false;
+            vec![i18n::text(language, Msg::LanguageSet).into()]
+        }
+        Command::Language(None) => vec![language_options(session).into()],
+        Command::Settings { field, value } => {
+            vec![
+                handle_settings(session, field.as_deref(), value.as_deref())
+                    .await
+                    .into(),
+            ]
+        }
+        Command::Notifications(Some(enabled)) => {
+            session.prefs.notifications_off = !enabled;
+            let msg = if enabled {
+                Msg::NotificationsOn
+            } else {
+                Msg::NotificationsOff
+            };
+            vec![i18n::text(session.language(), msg).into()]
+        }
+        Command::Notifications(None) => {
+            let msg = if session.prefs.notifications_off {
+                Msg::NotificationsOff
+            } else {
+                Msg::NotificationsOn
+            };
+            vec![i18n::text(session.language(), msg).into()]
+        }
+        // A bare `status` or a nudge like `any update?` means the withdrawal in progress
+        Command::Invalid(CommandError::StatusMissingReference) => {
+            match transactions::active_withdrawal(&session.phone).await {
+                Some(tx) => vec![handle_status_nudge(&tx, session).await.into()],
+                None => vec![command_error_message(&CommandError::StatusMissingReference).into()],
+            }
+        }
+        Command::Invalid(error) => vec![command_error_message(&error).into()],
+        Command::Unknown { input } if parser::is_status_nudge(&input) => {
+            match transactions::active_withdrawal(&session.phone).await {
+                Some(tx) => vec![handle_status_nudge(&tx, session).await.into()],
+                None => vec![UNKNOWN_COMMAND.into()],
+            }
+        }
+        Command::Unknown { .. } => vec![UNKNOWN_COMMAND.into()],
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled { "on" } else { "off" }
+}
+
+fn parse_on_off(value: Option<&str>) -> Option<bool> {
+    match value?.to_lowercase().as_str() {
+        "on" | "yes" | "true" => Some(true),
+        "off" | "no" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// `UTC`, `GMT+1`, `+01:00`, `-5:30` or `WAT`, as minutes east of UTC (at most ±14h).
+fn parse_utc_offset(raw: &str) -> Option<i32> {
+    let raw = raw.trim().to_lowercase();
+    if raw == "wat" {
+        return Some(60);
+    }
+    let offset = raw
+        .strip_prefix("utc")
+        .or_else(|| raw.strip_prefix("gmt"))
+        .unwrap_or(&raw)
+        .trim();
+    if offset.is_empty() {
+        return Some(0);
+    }
+
+    let (sign, rest) = match offset.split_at(1) {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    let total = hours * 60 + minutes;
+    (minutes < 60 && total <= 14 * 60).then_some(sign * total)
+}
+
+/// `settings`: shows every preference, or changes one of them.
+async fn handle_settings(
+    session: &mut UserSessions,
+    field: Option<&str>,
+    value: Option<&str>,
+) -> String {
+    let language = session.language();
+    let prefs = &mut session.prefs;
+    match field {
+        None => format!(
+            "⚙️ *Your Settings*\n\n\
+            🌐 Language: {}\n\
+            🕒 Timezone: {}\n\
+            📝 Plain text: {}\n\
+            🔔 Notifications: {}\n\
+            🏦 Default bank: {}\n\n\
+            To change one:\n\
+            • `settings language [name]`\n\
+            • `settings timezone [UTC+01:00]`\n\
+            • `settings plain on|off`\n\
+            • `settings notifications on|off`\n\
+            • `settings bank [number|clear]`",
+            language.name(),
+            prefs.timezone_label(),
+            on_off(prefs.plain_text),
+            on_off(!prefs.notifications_off),
+            prefs.default_bank_label.as_deref().unwrap_or("none")
+        ),
+        Some("language") => match value.and_then(Language::from_name) {
+            Some(language) => {
+                prefs.language = Some(language);
+                prefs.language_detected = false;
+                i18n::text(language, Msg::LanguageSet).to_string()
+            }
+            None => language_options(session),
+        },
+        Some("timezone") | Some("tz") => match value.and_then(parse_utc_offset) {
+            Some(minutes) => {
+                prefs.utc_offset_minutes = Some(minutes);
+                format!("✅ Timezone set to {}.", prefs.timezone_label())
+            }
+            None => "❓ Send your offset from UTC, e.g. `settings timezone UTC+01:00` \
+                (Lagos is UTC+01:00)."
+                .to_string(),
+        },
+        Some("plain") => match parse_on_off(value) {
+            Some(enabled) => {
+                prefs.plain_text = enabled;
+                format!("✅ Plain text is {}.", on_off(enabled))
+            }
+            None => "❓ Use `settings plain on` or `settings plain off`.".to_string(),
+        },
+        Some("notifications") => match parse_on_off(value) {
+            Some(enabled) => {
+                prefs.notifications_off = !enabled;
+                let msg = if enabled {
+                    Msg::NotificationsOn
+                } else {
+                    Msg::NotificationsOff
+                };
+                i18n::text(language, msg).to_string()
+            }
+            None => {
+                "❓ Use `settings notifications on` or `settings notifications off`.".to_string()
+            }
+        },
+        Some("bank") => set_default_bank(session, value).await,
+        Some(other) => format!(
+            "❓ There's no `{}` setting. Type `settings` to see them all.",
+            other
+        ),
+    }
+}
+
+/// `Opay ****6789`: how saved banks are listed.
+fn bank_label(bank: &BankDetails) -> String {
+    format!(
+        "{} {}",
+        bank.bank_name,
+        mask_account_number(&bank.account_number)
+    )
+}
+
+/// `settings bank 2` picks the second saved bank; `settings bank clear` forgets it.
+async fn set_default_bank(session: &mut UserSessions, value: Option<&str>) -> String {
+    if value.is_some_and(|v| v.eq_ignore_ascii_case("clear")) {
+        session.prefs.default_bank_id = None;
+        session.prefs.default_bank_label = None;
+        return "✅ Default bank cleared.".to_string();
+    }
+
+    let banks = match get_user_bank_details(session).await {
+        Ok(banks) if !banks.is_empty() => banks,
+        Ok(_) => return "🏦 You don't have any saved banks yet.".to_string(),
+        Err(e) => return format!("❌ Failed to check bank details: {}", e),
+    };
+    let picked = value
+        .and_then(|v| v.parse::<usize>().ok())
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| banks.get(i));
+    match picked {
+        Some(bank) => {
+            session.prefs.default_bank_id = Some(bank.bank_details_id.clone());
+            session.prefs.default_bank_label = Some(bank_label(bank));
+            format!("✅ Default bank set to {}.", bank_label(bank))
+        }
+        None => {
+            let options: Vec<String> = banks
+                .iter()
+                .enumerate()
+                .map(|(i, bank)| format!("{}. {}", i + 1, bank_label(bank)))
+                .collect();
+            format!(
+                "🏦 *Choose a default bank*\n\n{}\n\n\
+                Reply `settings bank [number]`, or `settings bank clear`.",
+                options.join("\n")
+            )
+        }
+    }
+}
+
+fn language_options(session: &UserSessions) -> String {
+    let options: Vec<String> = Language::ALL
+        .iter()
+        .map(|language| format!("• `language {}`", language.name().to_lowercase()))
+        .collect();
+    format!(
+        "🌐 *Language:* {}\n\nTo change it:\n{}",
+        session.language().name(),
+        options.join("\n")
+    )
+}
+
+/// Out-of-region numbers can look around but not sign up or withdraw; each attempt is
+/// audited so the growth team can see where demand comes from.
+fn region_unavailable(session: &UserSessions, action: &str) -> Option<Vec<OutboundMessage>> {
+    if region::is_supported(&session.phone) {
+        return None;
+    }
+
+    audit::record(
+        &session.phone,
+        None,
+        None,
+        AuditEvent::RegionRestricted {
+            calling_code: region::calling_code(&session.phone),
+            action: action.to_string(),
+        },
+    );
+    Some(vec![region::UNAVAILABLE_MESSAGE.into()])
+}
+
+fn command_error_message(error: &CommandError) -> String {
+    let supported = SUPPORTED_TOKENS.join(" or ");
+    match error {
+        CommandError::WithdrawMissingAmount { token: None } => "💸 *Withdraw Format:*\n`send [amount] [crypto] to [bank name]`\n\n*Example:* `send 1 USDT to Opay`".to_string(),
+        CommandError::WithdrawMissingAmount { token: Some(token) } => format!(
+            "❓ How much {} would you like to withdraw?\n\n*Example:* `withdraw 50 {}`",
+            token, token
+        ),
+        CommandError::WithdrawInvalidAmount(raw) => format!(
+            "❌ `{}` isn't a valid amount. Use a positive number, e.g. `withdraw 50 USDT`.",
+            raw
+        ),
+        CommandError::WithdrawMissingToken { amount } => format!(
+            "❓ I got the amount {} but which token — {}?\n\n*Example:* `withdraw {} USDT`",
+            display_amount(*amount),
+            supported,
+            display_amount(*amount)
+        ),
+        CommandError::WithdrawUnsupportedToken(token) => format!(
+            "❌ `{}` isn't supported yet. You can withdraw {}.",
+            token, supported
+        ),
+        CommandError::WithdrawAllMissingToken => format!(
+            "❓ All of which token — {}?\n\n*Example:* `withdraw all USDT`",
+            supported
+        ),
+        CommandError::BalanceUnsupportedToken(token) => format!(
+            "❌ `{}` isn't supported yet. Try `balance` or `balance USDT`.",
+            token
+        ),
+        CommandError::QuoteUsage => format!(
+            "📊 *Quote Format:*\n`quote [amount] [crypto]`\n\n*Example:* `quote 75 USDT` ({})",
+            supported
+        ),
+        CommandError::UnsupportedLanguage(raw) => format!(
+            "❌ `{}` isn't a language we speak yet. Choose one of: {}.",
+            raw,
+            Language::ALL.map(Language::name).join(", ")
+        ),
+        CommandError::CancelMissingReference => {
+            "ℹ️ There's nothing in progress to cancel.\n\n\
+            To cancel a submitted withdrawal, send `cancel [reference]`."
+                .to_string()
+        }
+        CommandError::StatusMissingReference => {
+            "❓ Please include your reference, e.g. `status TX-ABC123`.".to_string()
+        }
+    }
+}
+
+/// Error codes from the account endpoint that we answer with something better than a
+/// generic failure.
+const USERNAME_TAKEN: &str = "USERNAME_TAKEN";
+const INVALID_USERNAME: &str = "INVALID_USERNAME";
+const VALIDATION_ERROR: &str = "VALIDATION_ERROR";
+/// The phone is already registered: a retry after the wallet step failed.
+const ACCOUNT_EXISTS: &str = "ACCOUNT_EXISTS";
+
+const ACCOUNT_CREATION_FAILED: &str = "❌ Account creation failed. Please try again.";
+const WALLET_SETUP_FAILED: &str = "⚠️ *Your account was created, but your wallet isn't ready yet.*\n\n\
+    Type `create` again in a few minutes to finish setting it up.";
+
+/// How the account endpoint turned a username down.
+enum CreationRejection {
+    /// Someone else has it; the backend's reason, if it gave one.
+    UsernameTaken(Option<String>),
+    /// It broke a rule; the backend's message names the problem.
+    InvalidUsername(Option<String>),
+    AccountExists,
+    Unknown,
+}
+
+fn creation_rejection(status: StatusCode, error: &ErrorEnvelope) -> CreationRejection {
+    let code = error
+        .error_code
+        .as_deref()
+        .unwrap_or_default()
+        .to_uppercase();
+    let reason = error
+        .message
+        .as_deref()
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty())
+        .map(str::to_string);
+    match code.as_str() {
+        USERNAME_TAKEN => CreationRejection::UsernameTaken(reason),
+        INVALID_USERNAME | VALIDATION_ERROR => CreationRejection::InvalidUsername(reason),
+        ACCOUNT_EXISTS => CreationRejection::AccountExists,
+        "" if status == StatusCode::CONFLICT => CreationRejection::UsernameTaken(reason),
+        "" if status == StatusCode::UNPROCESSABLE_ENTITY => {
+            CreationRejection::InvalidUsername(reason)
+        }
+        _ => CreationRejection::Unknown,
+    }
+}
+
+const USERNAME_PROMPT: &str = "👤 *Choose a username*\n\n\
+    Reply with the username for your Kharon Pay account. \
+    Use letters, numbers and underscores only.";
+
+/// Creates the account under the username in `message`, then its wallet. A username
+/// the user can fix keeps them in `AccountCreation` to send another; anything else ends
+/// the flow.
+async fn handle_account_creation(
+    message: &str,
+    session: &mut UserSessions,
+) -> Vec<OutboundMessage> {
+    // `AccountCreation` is the marker that creation has started: a second `create` is
+    // a double-send, not the username
+    if matches!(parse_command(message), Command::Create) {
+        return vec![
+            format!(
+                "⏳ Your account is already being created.\n\n{}",
+                USERNAME_PROMPT
+            )
+            .into(),
+        ];
+    }
+
+    // A double-sent username or a Twilio retry must not create the user twice
+    let Some(_in_flight) = inflight::begin(&session.phone, Operation::AccountCreation) else {
+        return vec!["⏳ Your account is already being created, give me a few seconds.".into()];
+    };
+
+    let create_endpoint = std::env::var("SERVER_CREATE_ENDPOINT").unwrap_or_default();
+    let controller_create_endpoint =
+        std::env::var("SERVER_CREATE_CONTROLLER_ENDPOINT").unwrap_or_default();
+
+    let client = backend::client();
+
+    let formatted_phone = session.phone.trim_start_matches("+").to_string();
+    let username = message.trim();
+
+    let account_create_message =
+        "🔄 *Creating Your Account!*\n\nPlease wait while we set up your wallet...";
+    send_twilio_message(&formatted_phone, account_create_message).await;
+
+    let response = backend::signed_post(
+        &client,
+        &create_endpoint,
+        &serde_json::json!({
+            "username": username,
+            "service_type": "whatsapp",
+            "phone": &formatted_phone,
+        }),
+    )
+    .send_tracked(Endpoint::CreateAccount)
+    .await;
+
+    match response {
+        Ok(res) if res.status().is_success() => {
+            println!("Account creation request successful!");
+        }
+        Ok(res) => {
+            let (status, error) = backend::read_error(res, "Account creation request").await;
+            match creation_rejection(status, &error) {
+                CreationRejection::UsernameTaken(reason) => {
+                    let reason =
+                        reason.unwrap_or_else(|| format!("*{}* is already in use.", username));
+                    return vec![
+                        format!(
+                            "❌ *That username is taken.*\n\n{}\n\nReply with a different username.",
+                            reason
+                        )
+                        .into(),
+                    ];
+                }
+                CreationRejection::InvalidUsername(reason) => {
+                    let reason =
+                        reason.unwrap_or_else(|| format!("*{}* wasn't accepted.", username));
+                    return vec![
+                        format!(
+                            "❌ *That username won't work.*\n\n{}\n\nReply with a different username.",
+                            reason
+                        )
+                        .into(),
+                    ];
+                }
+                CreationRejection::AccountExists => {
+                    println!(
+                        "{} already has an account; setting up its wallet",
+                        mask_phone(&session.phone)
+                    );
+                }
+                CreationRejection::Unknown => {
+                    let reply = backend::reply_for(
+                        &error,
+                        ACCOUNT_CREATION_FAILED,
+                        session.correlation_id.as_deref(),
+                    );
+                    StateMachine::reset(session);
+                    return vec![reply.into()];
+                }
+            }
+        }
+        Err(err) => {
+            eprintln!("Account creation request error: {}", err);
+            StateMachine::reset(session);
+            return vec![ACCOUNT_CREATION_FAILED.into()];
+        }
+    }
+
+    let controller_response = backend::signed_post(
+        &client,
+        &controller_create_endpoint,
+        &serde_json::json!({
+            "username": username,
+            "service_type": "whatsapp",
+            "phone": formatted_phone,
+            "user_permission": ["user"],
+        }),
+    )
+    .send_tracked(Endpoint::CreateAccount)
+    .await;
+
+    // The account exists from here on, so every failure points back at `create` rather
+    // than asking for a username the backend would now call taken
+    match controller_response {
+        Ok(controller_res) if controller_res.status().is_success() => {
+            println!("Controller creation successful!");
+
+            match controller_res.json::<CreateControllerAPIResponse>().await {
+                Ok(response) => {
+                    match starknet::normalize_address(&response.data.controller_address) {
+                        Ok(controller_address) => {
+                            session.controller_address = Some(controller_address.clone());
+                            let (repair, _) =
+                                StateMachine::advance(session, StateEvent::AccountCreated);
+                            if !repair.is_empty() {
+                                return repair;
+                            }
+                            onboarding::start(session);
+                            println!("Controller Address: {}", controller_address);
+
+                            let mut messages = wallet_address_messages(&controller_address);
+                            messages.push(
+                                format!(
+                                    "🎉 *Account created successfully!*\n\n\
+                                📱 *To withdraw crypto:*\n{}",
+                                    i18n::command_snippet(
+                                        session.language(),
+                                        &["address", "fund", "withdraw"]
+                                    )
+                                )
+                                .into(),
+                            );
+                            messages
+                        }
+                        Err(reason) => {
+                            eprintln!(
+                                "ALERT: Backend returned an invalid controller address for {}: {:?} ({})",
+                                mask_phone(&session.phone),
+                                response.data.controller_address,
+                                reason
+                            );
+                            StateMachine::reset(session);
+                            vec!["❌ We couldn't verify your wallet address. Type `support` before sending any funds.".into()]
+                        }
+                    }
+                }
+                Err(parse_err) => {
+                    eprintln!("Failed to parse controller response: {}", parse_err);
+                    StateMachine::reset(session);
+                    vec![WALLET_SETUP_FAILED.into()]
+                }
+            }
+        }
+        Ok(controller_res) => {
+            let (_, error) = backend::read_error(controller_res, "Controller creation").await;
+            let reply = backend::reply_for(
+                &error,
+                WALLET_SETUP_FAILED,
+                session.correlation_id.as_deref(),
+            );
+            StateMachine::reset(session);
+            vec![reply.into()]
+        }
+        Err(err) => {
+            eprintln!("Controller creation error: {}", err);
+            StateMachine::reset(session);
+            vec![WALLET_SETUP_FAILED.into()]
+        }
+    }
+}
+
+/// Serves the address cached on the session when there is one; the controller address
+/// never changes for an account, so only `refresh address` goes back to the backend.
+async fn handle_get_address(session: &mut UserSessions, refresh: bool) -> Vec<OutboundMessage> {
+    if !refresh && let Some(address) = session.controller_address.as_deref() {
+        return wallet_address_messages(address);
+    }
+    if let Some(shed) = shed_if_degraded(Endpoint::Address) {
+        return vec![shed.into()];
+    }
+
+    let address_endpoint = std::env::var("SERVER_GET_ADDRESS_ENDPOINT").unwrap_or_default();
+
+    let client = backend::client();
+
+    let formatted_phone = session.phone.trim_start_matches("+");
+
+    let response = backend::signed_get(&client, &address_endpoint, &[("phone", formatted_phone)])
+        .send_tracked(Endpoint::Address)
+        .await;
+
+    match response {
+        Ok(res) if res.status().is_success() => {
+            match backend::parse_body::<AddressResponse>(res, "Address").await {
+                Ok(body) => {
+                    if let Some(raw_address) = body
+                        .data
+                        .and_then(|data| data.controller_address)
+                        .filter(|address| !address.is_empty())
+                    {
+                        match starknet::normalize_address(&raw_address) {
+                            Ok(address) => {
+                                session.controller_address = Some(address.clone());
+                                wallet_address_messages(&address)
+                            }
+                            Err(reason) => {
+                                eprintln!(
+                                    "ALERT: Backend returned an invalid controller address for {}: {:?} ({})",
+                                    mask_phone(&session.phone),
+                                    raw_address,
+                                    reason
+                                );
+                                vec!["❌ We couldn't verify your wallet address. Type `support` before sending any funds.".into()]
+                            }
+                        }
+                    } else {
+                        vec!["❌ No wallet address found. Please create an account first with `create`."
+                        .into()]
+                    }
+                }
+                Err(_) => vec!["❌ Failed to retrieve address. Please try again.".into()],
+            }
+        }
+        Ok(res) if res.status().as_u16() == 404 => {
+            session.controller_address = None;
+            vec!["❌ No account found. Please create an account first with `create`.".into()]
+        }
+        Ok(_) => vec!["❌ Failed to retrieve address. Please try again.".into()],
+        Err(_) => vec!["❌ Failed to connect to server. Please try again.".into()],
+    }
+}
+
+/// Label first, then the bare address on its own so tap-to-copy grabs only the address,
+/// then the network warning.
+fn wallet_address_messages(address: &str) -> Vec<OutboundMessage> {
+    vec![
+        format!(
+            "💳 *Your Wallet Address* ({})\n\n\
+            Your full address is in the next message — tap and hold it to copy.",
+            starknet::short_address(address)
+        )
+        .into(),
+        // The address belongs with the line announcing it
+        OutboundMessage::from(address).after(Duration::from_millis(500)),
+        "⚠️ *Only send USDT/USDC on Starknet to this address.*\n\n\
+        Funds sent from any other network will be lost."
+            .into(),
+    ]
+}
+
+async fn handle_get_balance(session: &UserSessions, refresh: bool) -> String {
+    let balance_endpoint = std::env::var("SERVER_BALANCE_ENDPOINT").unwrap_or_default();
+
+    let client = backend::client();
+
+    let formatted_phone = session.phone.trim_start_matches("+");
+    let (query_token, user_address) =
+        match (std::env::var("TEST_TOKEN"), std::env::var("TEST_ADDRESS")) {
+            (Ok(token), Ok(address)) => (token, address),
+            _ => {
+                eprintln!("TEST_TOKEN or TEST_ADDRESS is not set; cannot fetch balance");
+                return "❌ Balance checks are temporarily unavailable. Please try again later."
+                    .to_string();
+            }
+        };
+
+    if !refresh && let Some((reply, age)) = cache::cached_balance(&session.phone, &query_token) {
+        return format!(
+            "{}\n\n🕒 As of {}s ago. Type `balance refresh` for the latest.",
+            reply,
+            age.num_seconds()
+        );
+    }
+    if let Some(shed) = shed_if_degraded(Endpoint::Balance) {
+        return shed;
+    }
+
+    // Impatient users send `balance` several times; concurrent asks share one backend call
+    cache::coalesce_balance(&session.phone, &query_token, || async {
+        let response = backend::signed_get(
+            &client,
+            &balance_endpoint,
+            &[
+                ("phone", formatted_phone),
+                ("token", &query_token),
+                ("user_address", &user_address),
+            ],
+        )
+        .send_tracked(Endpoint::Balance)
+        .await;
+
+        match response {
+            Ok(res) if res.status().is_success() => {
+                match backend::parse_body::<BalanceResponse>(res, "Balance").await {
+                    Ok(BalanceResponse { data }) => {
+                        let (balance, token) = data
+                            .map(|data| (data.balance, data.token))
+                            .unwrap_or_default();
+                        if let Some(balance_str) = balance {
+                            let token = token.as_deref().unwrap_or(&query_token);
+                            let info = money::token_info(token);
+                            match TokenAmount::from_backend(
+                                &balance_str,
+                                money::token_decimals(token),
+                            ) {
+                                Ok(balance) => {
+                                    money::warn_if_unscaled(
+                                        &format!("Balance for {}", mask_phone(&session.phone)),
+                                        balance.whole_tokens(),
+                                        token,
+                                        None,
+                                    );
+                                    let (emoji, symbol) =
+                                        info.map_or(("🪙", "TOKEN"), |t| (t.emoji, t.symbol));
+                                    let reply = format!(
+                                        "💰 *Your Balance*\n\n{} {}: {}\n\n💵 Total: {}",
+                                        emoji,
+                                        symbol,
+                                        balance.display(),
+                                        balance.display_money("USD")
+                                    );
+                                    cache::store_balance(&session.phone, &query_token, &reply);
+                                    reply
+                                }
+                                Err(e) => {
+                                    eprintln!("Balance for {}: {}", mask_phone(&session.phone), e);
+                                    "❌ Invalid balance format".to_string()
+                                }
+                            }
+                        } else {
+                            "💰 *Your Balance*\n\n🪙 USDT: 0.00\n🪙 USDC: 0.00\n\n💵 Total: $0.00"
+                                .to_string()
+                        }
+                    }
+                    Err(_) => "❌ Failed to retrieve balance. Please try again.".to_string(),
+                }
+            }
+            Ok(res) if res.status().as_u16() == 404 => {
+                "❌ No account found. Please create an account first with `create`.".to_string()
+            }
+            Ok(_) => "❌ Failed to retrieve balance. Please try again.".to_string(),
+            Err(_) => "❌ Failed to connect to server. Please try again.".to_string(),
+        }
+    })
+    .await
+}
+
+async fn handle_export(session: &UserSessions) -> String {
+    if let Some(shed) = shed_if_degraded(Endpoint::History) {
+        return shed;
+    }
+
+    let records = match get_transaction_history(session).await {
+        Ok(records) => records,
+        Err(err) => return format!("❌ {}", err),
+    };
+
+    if records.is_empty() {
+        return "📭 You don't have any transactions to export yet.".to_string();
+    }
+
+    let count = records.len();
+    match export::create_export(export::render_csv(&records)) {
+        Ok(link) => format!(
+            "📄 *Your Transaction History*\n\n\
+            {} transactions exported to CSV.\n\n\
+            ⬇️ Download: {}\n\n\
+            ⏳ This link expires in {} minutes.",
+            count,
+            link,
+            export::link_ttl_secs() / 60
+        ),
+        Err(err) => {
+            eprintln!("Failed to create export for {}: {}", session.phone, err);
+            "❌ Export is unavailable right now. Please try again later.".to_string()
+        }
+    }
+}
+
+const QUOTE_OUT_OF_RANGE: &str = "❌ That amount is too large to quote. \
+    Check it's in whole tokens (e.g. `withdraw 5 USDT`) and try again.";
+
+/// The wallet's `token` balance, exactly. `Ok(None)` when the backend sent no figure, or
+/// one for a different token.
+async fn precise_balance(
+    session: &UserSessions,
+    token: &str,
+) -> Result<Option<TokenAmount>, String> {
+    let balance_endpoint = std::env::var("SERVER_BALANCE_ENDPOINT").unwrap_or_default();
+    let (Ok(query_token), Ok(user_address)) =
+        (std::env::var("TEST_TOKEN"), std::env::var("TEST_ADDRESS"))
+    else {
+        return Err("TEST_TOKEN or TEST_ADDRESS is not set".to_string());
+    };
+
+    let response = backend::signed_get(
+        &backend::client(),
+        &balance_endpoint,
+        &[
+            ("phone", session.phone.trim_start_matches('+')),
+            ("token", &query_token),
+            ("user_address", &user_address),
+        ],
+    )
+    .send_tracked(Endpoint::Balance)
+    .await
+    .map_err(|e| format!("Balance request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Balance request returned {}", response.status()));
+    }
+
+    let BalanceResponse { data } = backend::parse_body(response, "Balance").await?;
+    let Some((Some(balance), reported)) = data.map(|data| (data.balance, data.token)) else {
+        return Ok(None);
+    };
+    let symbol = |token: &str| money::token_info(token).map(|info| info.symbol);
+    if symbol(reported.as_deref().unwrap_or(&query_token)) != symbol(token) {
+        return Ok(None);
+    }
+    TokenAmount::from_backend(&balance, money::token_decimals(token)).map(Some)
+}
+
+/// A reply when `amount` is more than the user holds. Checked against the exact balance;
+/// when it can't be had, the backend still refuses an overdraw, so we let it through.
+async fn insufficient_balance(session: &UserSessions, amount: f64, token: &str) -> Option<String> {
+    let balance = match precise_balance(session, token).await {
+        Ok(Some(balance)) => balance,
+        Ok(None) => return None,
+        Err(e) => {
+            eprintln!(
+                "Balance pre-check for {} skipped: {}",
+                mask_phone(&session.phone),
+                e
+            );
+            return None;
+        }
+    };
+    let wanted = TokenAmount::from_whole_tokens(amount, balance.decimals).ok()?;
+    (wanted > balance).then(|| {
+        format!(
+            "❌ *Insufficient balance*\n\n\
+            You asked to withdraw {} {} but your balance is {} {}.\n\n\
+            Send `withdraw all {}` to withdraw everything.",
+            wanted.display(),
+            token,
+            balance.display(),
+            token,
+            token
+        )
+    })
+}
+
+/// The amount for `withdraw all`: the exact balance, as an `f64` that never reads back as
+/// more than it. Errors are the reply to send.
+async fn whole_balance(session: &UserSessions, token: &str) -> Result<f64, String> {
+    match precise_balance(session, token).await {
+        Ok(Some(balance)) if !balance.is_zero() => Ok(balance.at_most_f64()),
+        Ok(_) => Err(format!(
+            "💰 You have no {} to withdraw.\n\nType `fund` to see your deposit address.",
+            token
+        )),
+        Err(e) => {
+            eprintln!(
+                "Balance for withdraw-all by {} failed: {}",
+                mask_phone(&session.phone),
+                e
+            );
+            Err(
+                "❌ Couldn't check your balance just now. Please try again in a moment."
+                    .to_string(),
+            )
+        }
+    }
+}
+
+async fn handle_withdraw_initiation(
+    amount: f64,
+    crypto: &str,
+    session: &mut UserSessions,
+) -> OutboundMessage {
+    let rate = match fetch_usd_ngn_rate(false).await {
+        Ok(rate) => rate,
+        Err(reply) => return reply.into(),
+    };
+    let Some(naira_amount) = money::naira_for(amount, rate) else {
+        eprintln!(
+            "ALERT: Quote for {} {} at {} for {} is out of range; refusing it",
+            amount,
+            crypto,
+            rate,
+            mask_phone(&session.phone)
+        );
+        return QUOTE_OUT_OF_RANGE.into();
+    };
+
+    let (repair, _) = StateMachine::advance(session, StateEvent::QuoteShown);
+    if let Some(apology) = repair.into_iter().next() {
+        return apology;
+    }
+
+    let correlation_id = uuid::Uuid::new_v4().to_string();
+    audit::record(
+        &session.phone,
+        Some(&correlation_id),
+        None,
+        AuditEvent::QuoteShown {
+            amount,
+            token: crypto.to_string(),
+            rate,
+            naira_amount,
+        },
+    );
+    session.correlation_id = Some(correlation_id);
+    session.quoted_rate = Some(rate);
+    session.quoted_at = Some(Utc::now());
+    session.quoted_naira_amount = Some(naira_amount);
+
+    let quote = quote_prompt(session.language(), amount, crypto, rate, naira_amount);
+    with_buttons(quote, "T_QUOTE_BUTTONS_CONTENT_SID", &session.phone)
+}
+
+/// The quote a withdrawal asks the user to confirm.
+pub fn quote_prompt(
+    language: Language,
+    amount: f64,
+    crypto: &str,
+    rate: f64,
+    naira_amount: f64,
+) -> String {
+    i18n::render(
+        language,
+        Msg::WithdrawRequest,
+        &[
+            ("amount", &format_money(amount, crypto)),
+            ("rate", &format_money(rate, "NGN")),
+            ("crypto", crypto),
+            ("naira", &format_money(naira_amount, "NGN")),
+            ("eta", &eta::arrival_line(language, None)),
+        ],
+    )
+}
+
+/// Sends `body` through a quick-reply template when the prompt's SID variable is set,
+/// so ops can change the buttons without a deploy. The template's variable 1 carries
+/// the whole text; its button payloads must be the words the prompt asks for
+/// (`confirm`/`cancel` or `yes`/`no`), which arrive as `ButtonPayload`. Users outside
+/// the `interactive_buttons` rollout get the plain text.
+fn with_buttons(body: String, sid_var: &str, phone: &str) -> OutboundMessage {
+    match std::env::var(sid_var) {
+        Ok(sid)
+            if !sid.is_empty() && features::is_enabled(features::INTERACTIVE_BUTTONS, phone) =>
+        {
+            let variables = BTreeMap::from([("1".to_string(), body.clone())]);
+            OutboundMessage::with_content(body, ContentTemplate { sid, variables })
+        }
+        _ => body.into(),
+    }
+}
+
+/// `quote <amount> <token>`: what a withdrawal would pay right now, without starting one.
+async fn handle_quote(amount: f64, crypto: &str) -> String {
+    let rate = match fetch_usd_ngn_rate(true).await {
+        Ok(rate) => rate,
+        Err(reply) => return reply,
+    };
+    let Some(naira_amount) = money::naira_for(amount, rate) else {
+        return QUOTE_OUT_OF_RANGE.to_string();
+    };
+
+    format!(
+        "📊 *Indicative Quote*\n\n\
+        Amount: {}\n\
+        Rate: {} per {}\n\
+        You'd receive about: {}\n\n\
+        ℹ️ This is an estimate, not a locked rate. \
+        Type `withdraw {} {}` to start a real withdrawal.",
+        format_money(amount, crypto),
+        format_money(rate, "NGN"),
+        crypto,
+        format_money(naira_amount, "NGN"),
+        display_amount(amount),
+        crypto
+    )
+}
+
+/*
+
+fn handle_deposit_flow(message: &str, session: &mut UserSessions) -> String {
+    let crypto = message.to_uppercase();
+    session.state = UserState::DepositFlow;
+
+    // fund account: should return user's controller address with the right token to deposit
+
+    match crypto.as_str() {
+        "USDT" => "💵 *USDT Deposit Address (STARKNET)*\n\n\
+            `0x04718f5a0fc34cc1af16a1cdee98ffb20c31f5cd61d6ab07201858f4287c938d`\n\n\
+            ⚠️ *Important:*\n\
+            • Only send USDT (STARKNET) to this address\n\
+            • Minimum deposit: 1 USDT\n\
+            💬 Reply `balance` after sending to check status."
+            .to_string(),
+        "USDC" => "💵 *USDC Deposit Address (STARKNET)*\n\n\
+            `0x04718f5a0fc34cc1af16a1cdee98ffb20c31f5cd61d6ab07201858f4287c938d`\n\n\
+            ⚠️ *Important:*\n\
+            • Only send USDC (STARKNET) to this address\n\
+            • Minimum deposit: 1 USDC\n\
+            💬 Reply `balance` after sending to check status."
+            .to_string(),
+        _ => "❌ Unsupported crypto. We support `USDT` and `USDC` for now.".to_string(),
+    }
+}       */
+
+/// Asks the user to confirm the verified account, with everything the final `yes`
+/// commits to.
+pub fn bank_confirmation_prompt(
+    session: &UserSessions,
+    bank_name: &str,
+    account_name: &str,
+    account_number: &str,
+) -> String {
+    let summary = withdrawal_summary(session, bank_name, account_name, account_number);
+    i18n::render(
+        session.language(),
+        Msg::AccountVerified,
+        &[("summary", &summary)],
+    )
+}
+
+/// Everything the final `yes` commits to, taken from the quote stored on the session.
+fn withdrawal_summary(
+    session: &UserSessions,
+    bank_name: &str,
+    account_name: &str,
+    account_number: &str,
+) -> String {
+    let language = session.language();
+    let crypto = session.pending_currency.as_deref().unwrap_or("");
+    let mut summary = i18n::render(
+        language,
+        Msg::SummaryTitle,
+        &[(
+            "amount",
+            &money::format_optional_money(session.pending_amount, Some(crypto)),
+        )],
+    );
+    if let Some(rate) = session.quoted_rate {
+        summary.push_str(&i18n::render(
+            language,
+            Msg::SummaryRate,
+            &[("rate", &format_money(rate, "NGN")), ("crypto", crypto)],
+        ));
+    }
+    if let Some(naira_amount) = session.quoted_naira_amount {
+        summary.push_str(&i18n::render(
+            language,
+            Msg::SummaryReceive,
+            &[("naira", &format_money(naira_amount, "NGN"))],
+        ));
+    }
+    summary.push_str(&i18n::render(
+        language,
+        Msg::SummaryBank,
+        &[
+            ("bank", bank_name),
+            ("name", account_name),
+            ("number", &mask_account_number(account_number)),
+        ],
+    ));
+    summary
+}
+
+/// `confirm` or `cancel` for the quote; a plain yes or no (in the user's words) works too.
+async fn handle_offramp_confirmation(message: &str, session: &mut UserSessions) -> OutboundMessage {
+    match i18n::normalize_reply(session.language(), message).as_str() {
+        "confirm" | "yes" => {
+            audit::record(
+                &session.phone,
+                session.correlation_id.as_deref(),
+                None,
+                AuditEvent::QuoteConfirmed,
+            );
+
+            match get_user_bank_details(session).await {
+                Ok(mut banks) => {
+                    let default =
+                        session.prefs.default_bank_id.as_ref().and_then(|id| {
+                            banks.iter().position(|bank| &bank.bank_details_id == id)
+                        });
+                    if let Some(index) = default {
+                        banks = vec![banks.swap_remove(index)];
+                    }
+                    if banks.len() > 1 {
+                        session.bank_choices = banks;
+                        let (repair, _) =
+                            StateMachine::advance(session, StateEvent::SavedBanksFound);
+                        if let Some(apology) = repair.into_iter().next() {
+                            return apology;
+                        }
+                        return bank_picker(session);
+                    }
+                    if let Some(bank_details) = banks.into_iter().next() {
+                        session.pending_bank_details = Some(bank_details.clone());
+                        let (repair, _) =
+                            StateMachine::advance(session, StateEvent::SavedBankFound);
+                        if let Some(apology) = repair.into_iter().next() {
+                            return apology;
+                        }
+
+                        saved_bank_prompt(session, &bank_details)
+                    } else {
+                        let (repair, _) =
+                            StateMachine::advance(session, StateEvent::SavedBankMissing);
+                        if let Some(apology) = repair.into_iter().next() {
+                            return apology;
+                        }
+                        i18n::text(session.language(), Msg::BankDetailsRequired).into()
+                    }
+                }
+                Err(e) => {
+                    // Error during the API call (e.g., network error)
+                    format!("❌ Failed to check bank details: {}", e).into()
+                }
+            }
+        }
+        "cancel" | "no" => {
+            audit::record(
+                &session.phone,
+                session.correlation_id.as_deref(),
+                None,
+                AuditEvent::WithdrawalCancelled {
+                    step: "quote".to_string(),
+                },
+            );
+            StateMachine::reset(session);
+            i18n::text(session.language(), Msg::QuoteCancelled).into()
+        }
+        _ => i18n::text(session.language(), Msg::ConfirmOrCancel).into(),
+    }
+}
+
+/// Numbered list of the user's saved banks. When `T_BANK_LIST_CONTENT_SID` is set (and
+/// the list fits WhatsApp's ten rows) it goes out as a native list picker instead: the
+/// template's variable 1 is the prompt and 2, 3, … the row titles, and its rows must use
+/// the ids `bank_1`, `bank_2`, … which come back as `ListId` and are mapped to the number.
+fn bank_picker(session: &UserSessions) -> OutboundMessage {
+    let language = session.language();
+    let amount = match session.quoted_naira_amount {
+        Some(naira_amount) => format_money(naira_amount, "NGN"),
+        None => money::format_optional_money(
+            session.pending_amount,
+            session.pending_currency.as_deref(),
+        ),
+    };
+    let title = i18n::render(language, Msg::ChooseBank, &[("amount", &amount)]);
+    let rows: Vec<String> = session
+        .bank_choices
+        .iter()
+        .map(|bank| {
+            format!(
+                "{} {}",
+                bank.bank_name,
+                mask_account_number(&bank.account_number)
+            )
+        })
+        .collect();
+
+    let options: Vec<String> = rows
+        .iter()
+        .zip(&session.bank_choices)
+        .enumerate()
+        .map(|(i, (row, bank))| {
+            let flag = if session.prefs.bank_flagged(&bank.bank_details_id) {
+                " ⚠️ failed its last check"
+            } else {
+                ""
+            };
+            format!("{}. {} ({}){}", i + 1, row, bank.account_name, flag)
+        })
+        .collect();
+    let body = format!(
+        "{}\n\n{}\n\n{}",
+        title,
+        options.join("\n"),
+        i18n::text(language, Msg::ChooseBankReply)
+    );
+
+    match std::env::var("T_BANK_LIST_CONTENT_SID") {
+        Ok(sid) if !sid.is_empty() && rows.len() <= 10 => {
+            let mut variables = BTreeMap::from([("1".to_string(), title)]);
+            for (i, row) in rows.into_iter().enumerate() {
+                variables.insert((i + 2).to_string(), row);
+            }
+            OutboundMessage::with_content(body, ContentTemplate { sid, variables })
+        }
+        _ => body.into(),
+    }
+}
+
+async fn handle_new_bank_details_entry(
+    message: &str,
+    session: &mut UserSessions,
+) -> OutboundMessage {
+    let parts: Vec<&str> = message.split(',').map(|s| s.trim()).collect();
+
+    if parts.len() != 2 {
+        return "❌ Invalid format. Please provide bank details in this format:\n\n`Bank Name, Account Number`\n\n*Example:* `Opay, 0123456789`".into();
+    }
+
+    let bank_name = parts[0];
+    let account_number = parts[1];
+
+    if account_number.len() < 10 || !account_number.chars().all(|c| c.is_numeric()) {
+        return "❌ Invalid account number. Must be at least 10 digits.".into();
+    }
+
+    match verify_bank_details(bank_name, account_number, session).await {
+        Ok(verification) => {
+            let mismatch = namecheck::check(session, &verification.account_name).await;
+            if mismatch == Some(namecheck::Action::Block) {
+                return namecheck::MISMATCH_BLOCKED.into();
+            }
+
+            session.pending_bank_verification = Some(verification.clone());
+            let (repair, _) = StateMachine::advance(session, StateEvent::BankDetailsVerified);
+            if let Some(apology) = repair.into_iter().next() {
+                return apology;
+            }
+
+            let mut prompt = bank_confirmation_prompt(
+                session,
+                &verification.bank_name,
+                &verification.account_name,
+                &verification.account_number,
+            );
+            if mismatch.is_some() {
+                prompt = format!("{}\n\n{}", namecheck::MISMATCH_WARNING, prompt);
+            }
+            with_buttons(
+                prompt,
+                "T_VERIFIED_BANK_BUTTONS_CONTENT_SID",
+                &session.phone,
+            )
+        }
+        Err(err) => format!(
+            "❌ *Verification Failed*\n\n{}\n\n\
+            Please check your bank details and try again.",
+            err
+        )
+        .into(),
+    }
+}
+
+/// The summary for a saved bank, to be answered `yes` or `no` in `SavedBankConfirmation`.
+pub fn saved_bank_prompt(session: &UserSessions, bank_details: &BankDetails) -> OutboundMessage {
+    let summary = withdrawal_summary(
+        session,
+        &bank_details.bank_name,
+        &bank_details.account_name,
+        &bank_details.account_number,
+    );
+    let mut prompt = i18n::render(
+        session.language(),
+        Msg::SavedBankPrompt,
+        &[("summary", &summary)],
+    );
+    if session.prefs.bank_flagged(&bank_details.bank_details_id) {
+        prompt = format!("{}\n\n{}", FLAGGED_BANK_WARNING, prompt);
+    }
+    with_buttons(prompt, "T_SAVED_BANK_BUTTONS_CONTENT_SID", &session.phone)
+}
+
+/// The number of the saved bank to use, which is then confirmed like a single saved bank;
+/// `no` cancels, anything else shows the list again.
+async fn handle_bank_selection(message: &str, session: &mut UserSessions) -> OutboundMessage {
+    let reply = i18n::normalize_reply(session.language(), message);
+    let picked = reply
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| session.bank_choices.get(i).cloned());
+
+    match picked {
+        Some(bank_details) => {
+            session.pending_bank_details = Some(bank_details.clone());
+            audit::record(
+                &session.phone,
+                session.correlation_id.as_deref(),
+                None,
+                AuditEvent::BankSelected {
+                    bank_name: bank_details.bank_name.clone(),
+                    account_number: mask_account_number(&bank_details.account_number),
+                    newly_added: false,
+                },
+            );
+            let (repair, _) = StateMachine::advance(session, StateEvent::SavedBankFound);
+            if let Some(apology) = repair.into_iter().next() {
+                return apology;
+            }
+            saved_bank_prompt(session, &bank_details)
+        }
+        None if reply == "no" => {
+            audit::record(
+                &session.phone,
+                session.correlation_id.as_deref(),
+                None,
+                AuditEvent::WithdrawalCancelled {
+                    step: "bank_selection".to_string(),
+                },
+            );
+            StateMachine::reset(session);
+            i18n::text(session.language(), Msg::WithdrawalCancelled).into()
+        }
+        None => bank_picker(session),
+    }
+}
+
+/// `yes`/`no` for the one saved bank (or the default one).
+async fn handle_saved_bank_confirmation(message: &str, session: &mut UserSessions) -> String {
+    match i18n::normalize_reply(session.language(), message).as_str() {
+        "yes" => {
+            let bank_details = match session.pending_bank_details.clone() {
+                Some(details) => {
+                    // println!("session details {:#?}", details.clone());
+                    details
+                }
+                None => {
+                    return "❌ Bank details not found. Please start again.".to_string();
+                }
+            };
+
+            audit::record(
+                &session.phone,
+                session.correlation_id.as_deref(),
+                None,
+                AuditEvent::BankSelected {
+                    bank_name: bank_details.bank_name.clone(),
+                    account_number: mask_account_number(&bank_details.account_number),
+                    newly_added: false,
+                },
+            );
+
+            confirm_large_amount_or_execute(session, bank_details).await
+        }
+        "no" => {
+            audit::record(
+                &session.phone,
+                session.correlation_id.as_deref(),
+                None,
+                AuditEvent::WithdrawalCancelled {
+                    step: "saved_bank".to_string(),
+                },
+            );
+            StateMachine::reset(session);
+            i18n::text(session.language(), Msg::WithdrawalCancelled).to_string()
+        }
+        _ => i18n::text(session.language(), Msg::YesOrNo).to_string(),
+    }
+}
+
+/// `LARGE_WITHDRAWAL_THRESHOLD` (default 500, in token units, i.e. roughly USD for
+/// stablecoins). Withdrawals above it must have their amount typed back before executing.
+fn large_withdrawal_threshold() -> f64 {
+    std::env::var("LARGE_WITHDRAWAL_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(500.0)
+}
+
+/// `ABSURD_WITHDRAWAL_THRESHOLD` (default 1,000,000 tokens): an amount above it is more
+/// likely raw on-chain units than a real withdrawal, so the confirmation spells it out.
+/// Applies whatever `LARGE_WITHDRAWAL_THRESHOLD` is.
+fn absurd_withdrawal_threshold() -> f64 {
+    std::env::var("ABSURD_WITHDRAWAL_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1_000_000.0)
+}
+
+/// `750.00` -> `750`, `12.50` -> `12.5`: what we ask the user to type back.
+fn display_amount(amount: f64) -> String {
+    format!("{:.2}", amount)
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+fn amount_confirmation_prompt(language: Language, amount: f64, crypto: &str) -> String {
+    if amount > absurd_withdrawal_threshold() {
+        return i18n::render(
+            language,
+            Msg::AbsurdWithdrawal,
+            &[
+                ("amount", &display_amount(amount)),
+                ("words", &money::in_words(amount)),
+                ("crypto", crypto),
+            ],
+        );
+    }
+    i18n::render(
+        language,
+        Msg::LargeWithdrawal,
+        &[("amount", &display_amount(amount)), ("crypto", crypto)],
+    )
+}
+
+async fn confirm_large_amount_or_execute(
+    session: &mut UserSessions,
+    bank_details: BankDetails,
+) -> String {
+    let amount = session.pending_amount.unwrap_or(0.0);
+    if amount <= large_withdrawal_threshold() && amount <= absurd_withdrawal_threshold() {
+        return execute_offramp(session, &bank_details).await;
+    }
+
+    session.pending_bank_details = Some(bank_details);
+    session.amount_confirmation_attempts = 0;
+    let (repair, _) = StateMachine::advance(session, StateEvent::LargeAmountFlagged);
+    if let Some(apology) = repair.into_iter().next() {
+        return apology.body;
+    }
+
+    amount_confirmation_prompt(
+        session.language(),
+        amount,
+        session.pending_currency.as_deref().unwrap_or(""),
+    )
+}
+
+/// Up to three tries at typing the amount back; formatting like `1,000.00` is accepted.
+async fn handle_amount_confirmation(message: &str, session: &mut UserSessions) -> String {
+    const MAX_ATTEMPTS: u32 = 3;
+
+    let amount = session.pending_amount.unwrap_or(0.0);
+    let crypto = session.pending_currency.clone().unwrap_or_default();
+
+    if message.trim().eq_ignore_ascii_case("cancel") {
+        audit::record(
+            &session.phone,
+            session.correlation_id.as_deref(),
+            None,
+            AuditEvent::WithdrawalCancelled {
+                step: "amount_confirmation".to_string(),
+            },
+        );
+        StateMachine::reset(session);
+        return i18n::text(session.language(), Msg::WithdrawalCancelled).to_string();
+    }
+
+    let typed = parse_amount(message.trim());
+    if typed.is_some_and(|typed| (typed - amount).abs() < 0.005) {
+        let Some(bank_details) = session.pending_bank_details.clone() else {
+            return StateMachine::repair(session, "amount confirmation without bank details")
+                .into_iter()
+                .map(|m| m.body)
+                .collect();
+        };
+        return execute_offramp(session, &bank_details).await;
+    }
+
+    session.amount_confirmation_attempts += 1;
+    if session.amount_confirmation_attempts >= MAX_ATTEMPTS {
+        audit::record(
+            &session.phone,
+            session.correlation_id.as_deref(),
+            None,
+            AuditEvent::WithdrawalCancelled {
+                step: "amount_confirmation".to_string(),
+            },
+        );
+        StateMachine::reset(session);
+        return i18n::text(session.language(), Msg::AmountMismatchCancelled).to_string();
+    }
+
+    i18n::render(
+        session.language(),
+        Msg::AmountMismatch,
+        &[(
+            "prompt",
+            &amount_confirmation_prompt(session.language(), amount, &crypto),
+        )],
+    )
+}
+
+async fn handle_new_bank_confirmation(message: &str, session: &mut UserSessions) -> String {
+    match i18n::normalize_reply(session.language(), message).as_str() {
+        "yes" => {
+            let verification = match session.pending_bank_verification.clone() {
+                Some(v) => v,
+                None => {
+                    return "❌ Verification data not found. Please re-enter your bank details."
+                        .to_string();
+                }
+            };
+
+            let saved = match save_bank_details_to_db(session, &verification, None).await {
+                Ok(saved) => saved,
+                Err(err) => return format!("❌ Failed to save bank details: {}", err),
+            };
+
+            let found = match &saved {
+                // The save told us the id, so there's nothing to look up
+                BankSave::Saved(Some(bank_details_id)) => Ok(Some(BankDetails {
+                    bank_details_id: bank_details_id.clone(),
+                    bank_name: verification.bank_name.clone(),
+                    account_number: verification.account_number.clone(),
+                    account_name: verification.account_name.clone(),
+                    bank_code: Some(verification.bank_code.clone()),
+                })),
+                _ => get_user_bank_details(session)
+                    .await
+                    .map(|banks| find_saved_bank(banks, &verification)),
+            };
+
+            match found {
+                Ok(Some(bank_details)) => {
+                    session.pending_bank_verification = None;
+                    onboarding::bank_added(session);
+
+                    audit::record(
+                        &session.phone,
+                        session.correlation_id.as_deref(),
+                        None,
+                        AuditEvent::BankSelected {
+                            bank_name: bank_details.bank_name.clone(),
+                            account_number: mask_account_number(&bank_details.account_number),
+                            newly_added: true,
+                        },
+                    );
+
+                    confirm_large_amount_or_execute(session, bank_details).await
+                }
+                // The save may still have landed; `yes` again retries it under the same key
+                Ok(None) if saved == BankSave::Unknown => {
+                    "⏳ We couldn't confirm your bank was saved.\n\n\
+                    Type `yes` to try again."
+                        .to_string()
+                }
+                // Never fall back to another saved bank: that could pay the wrong account
+                Ok(None) => {
+                    eprintln!(
+                        "ALERT: Saved bank for {} is missing from their bank list",
+                        mask_phone(&session.phone)
+                    );
+                    "❌ Failed to find the bank you just saved. Nothing was sent. Type `support` for help."
+                        .to_string()
+                }
+                Err(err) => {
+                    format!("❌ Error retrieving bank details: {}", err)
+                }
+            }
+        }
+        "no" => {
+            session.pending_bank_verification = None;
+            let (repair, _) = StateMachine::advance(session, StateEvent::BankDetailsRejected);
+            if let Some(apology) = repair.into_iter().next() {
+                return apology.body;
+            }
+            i18n::text(session.language(), Msg::ReenterBankDetails).to_string()
+        }
+        _ => i18n::text(session.language(), Msg::YesOrReenter).to_string(),
+    }
+}
+
+const FLAGGED_BANK_WARNING: &str = "⚠️ This account failed its last check with the bank. \
+    Make sure it's still yours before you confirm, or reply `no` and type `verify` to check it again.";
+
+/// Banks format the same holder differently (`JOHN DOE`, `Doe John`), so only a different
+/// set of words counts as a new name.
+fn same_account_name(a: &str, b: &str) -> bool {
+    let words = |name: &str| {
+        let mut words: Vec<String> = name.split_whitespace().map(str::to_uppercase).collect();
+        words.sort();
+        words
+    };
+    words(a) == words(b)
+}
+
+/// `verify <n>`: asks the bank about a saved account again, since banks recycle account
+/// numbers and holders change their registered names. An account the bank no longer
+/// knows is flagged so withdrawals warn before using it; a new name is offered as an
+/// update to the saved record.
+async fn handle_verify_bank(session: &mut UserSessions, index: Option<usize>) -> String {
+    if let Some(shed) = shed_if_degraded(Endpoint::BankVerify) {
+        return shed;
+    }
+
+    let banks = match get_user_bank_details(session).await {
+        Ok(banks) if !banks.is_empty() => banks,
+        Ok(_) => return "🏦 You don't have any saved banks yet.".to_string(),
+        Err(e) => return format!("❌ Failed to check bank details: {}", e),
+    };
+    let picked = index
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| banks.get(i))
+        .cloned();
+    let Some(bank) = picked else {
+        let options: Vec<String> = banks
+            .iter()
+            .enumerate()
+            .map(|(i, bank)| format!("{}. {}", i + 1, bank_label(bank)))
+            .collect();
+        return format!(
+            "🏦 *Which bank should we check?*\n\n{}\n\nReply `verify [number]`.",
+            options.join("\n")
+        );
+    };
+
+    let verification =
+        match verify_bank_details(&bank.bank_name, &bank.account_number, session).await {
+            Ok(verification) => verification,
+            Err(e) if e == ACCOUNT_NOT_FOUND => {
+                session.prefs.set_bank_flag(&bank.bank_details_id, true);
+                return format!(
+                    "⚠️ {} no longer checks out: the bank can't find this account.\n\n\
+                    We'll warn you before it's used for a withdrawal.",
+                    bank_label(&bank)
+                );
+            }
+            Err(e) => return format!("❌ Couldn't check {} right now: {}", bank_label(&bank), e),
+        };
+
+    if same_account_name(&verification.account_name, &bank.account_name) {
+        session.prefs.set_bank_flag(&bank.bank_details_id, false);
+        return format!(
+            "✅ {} checks out. It's still registered to *{}*.",
+            bank_label(&bank),
+            bank.account_name
+        );
+    }
+
+    let reply = format!(
+        "⚠️ The bank now shows {} as *{}*, but it's saved as *{}*.\n\n\
+        Reply `yes` to update your saved details, or `no` to keep them as they are.",
+        bank_label(&bank),
+        verification.account_name,
+        bank.account_name
+    );
+    session.pending_bank_details = Some(bank);
+    session.pending_bank_verification = Some(verification);
+    let (repair, _) = StateMachine::advance(session, StateEvent::BankNameChanged);
+    if let Some(apology) = repair.into_iter().next() {
+        return apology.body;
+    }
+    reply
+}
+
+/// `yes` saves the bank's current name over the stored one; `no` keeps the stored record
+/// but flags it, since payouts against it may bounce.
+async fn handle_bank_name_update(message: &str, session: &mut UserSessions) -> String {
+    let (Some(bank), Some(verification)) = (
+        session.pending_bank_details.clone(),
+        session.pending_bank_verification.clone(),
+    ) else {
+        return StateMachine::repair(session, "bank name update without pending details")
+            .into_iter()
+            .map(|m| m.body)
+            .collect();
+    };
+
+    match i18n::normalize_reply(session.language(), message).as_str() {
+        "yes" => {
+            match save_bank_details_to_db(session, &verification, Some(&bank.bank_details_id)).await
+            {
+                Ok(BankSave::Saved(_)) => {
+                    session.prefs.set_bank_flag(&bank.bank_details_id, false);
+                    StateMachine::reset(session);
+                    format!(
+                        "✅ Updated. {} is now saved as *{}*.",
+                        bank_label(&bank),
+                        verification.account_name
+                    )
+                }
+                Ok(BankSave::Unknown) => "⏳ We couldn't confirm the update was saved.\n\n\
+                    Type `yes` to try again."
+                    .to_string(),
+                Err(e) => format!("❌ Failed to update bank details: {}", e),
+            }
+        }
+        "no" => {
+            session.prefs.set_bank_flag(&bank.bank_details_id, true);
+            StateMachine::reset(session);
+            "👍 Kept your saved details as they are. We'll warn you before this account is \
+            used for a withdrawal."
+                .to_string()
+        }
+        _ => i18n::text(session.language(), Msg::YesOrNo).to_string(),
+    }
+}
+
+async fn execute_offramp(session: &mut UserSessions, bank_details: &BankDetails) -> String {
+    if session.pending_amount.is_none() {
+        eprintln!(
+            "Offramp requested for {} without a pending amount",
+            session.phone
+        );
+        StateMachine::reset(session);
+        return "❌ Withdrawal details not found. Please start again with `withdraw [amount] [crypto]`.".to_string();
+    }
+
+    // The flow stays where it is, so the user can confirm again once the backend recovers
+    if let Some(shed) = shed_if_degraded(Endpoint::Offramp) {
+        return shed;
+    }
+
+    let Some(_in_flight) = inflight::begin(&session.phone, Operation::Offramp) else {
+        return "⏳ Your withdrawal is already being processed, give me a few seconds.".to_string();
+    };
+
+    match initiate_offramp_process(session, bank_details).await {
+        Ok(OfframpOutcome::Submitted(initiated_msg)) => {
+            // Reset session state
+            StateMachine::reset(session);
+            initiated_msg
+        }
+        Ok(OfframpOutcome::AwaitingApproval(prompt)) => prompt,
+        Err(err) => {
+            format!(
+                "❌ *Withdrawal Failed*\n\n{}\n\nPlease try again, or type `support` for help.",
+                err
+            )
+        }
+    }
+}
+
+/// What happened after the backend accepted an offramp.
+pub enum OfframpOutcome {
+    /// Payment triggered; the message confirms it.
+    Submitted(String),
+    /// The payout drifted from the quote, so it is held until the user approves it.
+    AwaitingApproval(String),
+}
+
+/// `QUOTE_TOLERANCE_PERCENT` (default 1%): how far the payout may move from the quoted
+/// NGN amount before we stop and ask.
+fn quote_drift_exceeded(quoted: f64, payout: f64) -> bool {
+    let tolerance: f64 = std::env::var("QUOTE_TOLERANCE_PERCENT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1.0);
+
+    quoted > 0.0 && (payout - quoted).abs() / quoted * 100.0 > tolerance
+}
+
+pub async fn initiate_offramp_process(
+    session: &mut UserSessions,
+    bank_details: &BankDetails,
+) -> Result<OfframpOutcome, String> {
+    let amount = session
+        .pending_amount
+        .ok_or_else(|| "Missing pending amount in session".to_string())?;
+    let crypto = session
+        .pending_currency
+        .clone()
+        .ok_or_else(|| "Missing pending currency in session".to_string())?;
+
+    let offramp_endpoint = std::env::var("SERVER_OFFRAMP_INIT_ENDPOINT").unwrap_or_default();
+
+    let client = backend::client();
+
+    let formatted_phone = session.phone.trim_start_matches("+");
+
+    // 1. Send initiation request
+    let response = backend::signed_post(
+        &client,
+        &offramp_endpoint,
+        &serde_json::json!({
+            "phone": formatted_phone,
+            "amount": amount,
+            "token_symbol": crypto,
+            "bank_account_id": bank_details.bank_details_id,
+            "currency": "NGN",
+            "order_type": "withdraw",
+            "payment_method": "bank_transfer",
+            "quoted_rate": session.quoted_rate,
+            "quoted_at": session.quoted_at,
+        }),
+    )
+    .send_tracked(Endpoint::Offramp)
+    .await;
+
+    match response {
+        Ok(res) if res.status().is_success() => {
+            let init_response = match res.json::<InitDisbursementResponse>().await {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to parse offramp init response: {}", e);
+                    return Err("Invalid response from server. Try again.".to_string());
+                }
+            };
+
+            if !init_response.success {
+                let error_msg = init_response.error.unwrap_or_else(|| {
+                    "Offramp initialization failed due to unknown error.".to_string()
+                });
+                eprintln!("Offramp init failed: {}", error_msg);
+                return Err(error_msg);
+            }
+
+            let disbursement_details = init_response.data.ok_or_else(|| {
+                "Missing disbursement details in successful response.".to_string()
+            })?;
+
+            audit::record(
+                &session.phone,
+                session.correlation_id.as_deref(),
+                Some(&init_response.reference),
+                AuditEvent::OfframpInitiated {
+                    amount,
+                    token: crypto.clone(),
+                },
+            );
+
+            println!(
+                "Offramp request initiated successfully! Reference: {}",
+                init_response.reference
+            );
+            let now = Utc::now();
+            transactions::record(&IndexedTransaction {
+                reference: init_response.reference.clone(),
+                phone: session.phone.clone(),
+                amount,
+                token: crypto.clone(),
+                bank_name: disbursement_details.bank_name.clone(),
+                account_name: disbursement_details.account_name.clone(),
+                masked_account: privacy::mask_account_number(&bank_details.account_number),
+                initiated_at: now,
+                status: "initiated".to_string(),
+                status_updated_at: now,
+                correlation_id: session.correlation_id.clone(),
+                tx_hash: Some(disbursement_details.crypto_tx_hash.clone())
+                    .filter(|hash| !hash.trim().is_empty()),
+                language: session.language(),
+            })
+            .await;
+
+            let payout = PendingPayout {
+                reference: init_response.reference,
+                amount: disbursement_details.amount,
+                currency: disbursement_details.currency,
+                bank_name: disbursement_details.bank_name,
+                account_name: disbursement_details.account_name,
+                crypto_tx_hash: disbursement_details.crypto_tx_hash,
+            };
+
+            money::warn_if_unscaled(
+                &format!("Payout {}", payout.reference),
+                payout.amount,
+                &payout.currency,
+                session
+                    .quoted_naira_amount
+                    .filter(|_| payout.currency.eq_ignore_ascii_case("NGN")),
+            );
+            if let Some(quoted) = session.quoted_naira_amount
+                && payout.currency.eq_ignore_ascii_case("NGN")
+                && quote_drift_exceeded(quoted, payout.amount)
+            {
+                println!(
+                    "Payout for {} drifted from quote: {} quoted, {} offered",
+                    payout.reference,
+                    format_money(quoted, "NGN"),
+                    format_money(payout.amount, "NGN")
+                );
+                audit::record(
+                    &session.phone,
+                    session.correlation_id.as_deref(),
+                    Some(&payout.reference),
+                    AuditEvent::PayoutAmountChanged {
+                        quoted_naira_amount: quoted,
+                        payout_amount: payout.amount,
+                    },
+                );
+
+                let message = format!(
+                    "⚠️ *The rate changed*\n\n\
+                    You were quoted {} for {}, but the payout is now {}.\n\n\
+                    Type `yes` to accept {} or `no` to cancel.",
+                    format_money(quoted, "NGN"),
+                    format_money(amount, &crypto),
+                    format_money(payout.amount, "NGN"),
+                    format_money(payout.amount, "NGN")
+                );
+
+                session.pending_payout = Some(payout);
+                let (repair, _) = StateMachine::advance(session, StateEvent::PayoutAmountChanged);
+                if let Some(apology) = repair.into_iter().next() {
+                    return Err(apology.body);
+                }
+                return Ok(OfframpOutcome::AwaitingApproval(message));
+            }
+
+            complete_payout(&client, session, &payout)
+                .await
+                .map(OfframpOutcome::Submitted)
+        }
+        Ok(res) => Err(backend::error_reply(
+            res,
+            "Offramp request",
+            "Failed to initiate withdrawal. Please try again, or type `support` if it keeps failing.",
+            session.correlation_id.as_deref(),
+        )
+        .await),
+        Err(err) => {
+            eprintln!("Offramp request error: {}", err);
+            Err("Failed to connect to server. Please try again.".to_string())
+        }
+    }
+}
+
+/// Triggers the payment for an initiated offramp and starts tracking it. If the trigger
+/// keeps failing, the offramp is cancelled so it doesn't sit in limbo on the backend.
+async fn complete_payout(
+    client: &reqwest::Client,
+    session: &UserSessions,
+    payout: &PendingPayout,
+) -> Result<String, String> {
+    let amount = session
+        .pending_amount
+        .ok_or_else(|| "Missing pending amount in session".to_string())?;
+    let crypto = session.pending_currency.clone().unwrap_or_default();
+    let formatted_phone = session.phone.trim_start_matches("+");
+
+    let token = match std::env::var("TEST_TOKEN") {
+        Ok(token) => token,
+        Err(_) => {
+            eprintln!(
+                "TEST_TOKEN is not set; cannot trigger payment for reference {}",
+                payout.reference
+            );
+            return Err(
+                "Withdrawal is temporarily unavailable. Type `support` for help.".to_string(),
+            );
+        }
+    };
+    let payment_request = ReceivePaymentRequest {
+        token,
+        amount: amount.to_string(),
+        reference: payout.reference.clone(),
+        phone: formatted_phone.to_string(),
+    };
+
+    let payment_result = trigger_payment_with_retry(&payment_request).await;
+    audit::record(
+        &session.phone,
+        session.correlation_id.as_deref(),
+        Some(&payout.reference),
+        AuditEvent::PaymentTriggered {
+            success: payment_result.is_ok(),
+            error: payment_result.as_ref().err().cloned(),
+        },
+    );
+
+    match payment_result {
+        Ok(_) => {
+            let success_msg = i18n::render(
+                session.language(),
+                Msg::WithdrawalInitiated,
+                &[
+                    ("amount", &format_money(payout.amount, &payout.currency)),
+                    ("bank", &payout.bank_name),
+                    ("name", &payout.account_name),
+                    ("reference", &payout.reference),
+                    ("tx", &tx_hash_lines(&payout.crypto_tx_hash)),
+                    (
+                        "eta",
+                        &eta::arrival_line(session.language(), Some(&payout.bank_name)),
+                    ),
+                ],
+            );
+
+            cache::invalidate_balance(&session.phone);
+
+            transactions::set_status(&payout.reference, "processing").await;
+            start_transaction_polling_task(payout.reference.clone());
+
+            Ok(success_msg)
+        }
+        Err(e) => {
+            let cancel_result =
+                cancel_offramp(client, &payout.reference, "payment_trigger_failed").await;
+            audit::record(
+                &session.phone,
+                session.correlation_id.as_deref(),
+                Some(&payout.reference),
+                AuditEvent::OfframpCancelled {
+                    success: cancel_result.is_ok(),
+                    error: cancel_result.as_ref().err().cloned(),
+                },
+            );
+
+            match cancel_result {
+                Ok(()) => {
+                    eprintln!(
+                        "ALERT: Payment trigger failed for {} ({}); offramp cancelled",
+                        payout.reference, e
+                    );
+                    Err(format!(
+                        "We couldn't complete this withdrawal, so it has been cancelled. \
+                        Your funds were not moved and you can try again.\n\n\
+                        🔢 *Reference:* {}",
+                        payout.reference
+                    ))
+                }
+                Err(cancel_err) => {
+                    let mut alert = PayoutAlert::new(
+                        &payout.reference,
+                        &session.phone,
+                        &format!(
+                            "payment trigger failed ({}); cancellation failed ({})",
+                            e, cancel_err
+                        ),
+                    );
+                    alert.amount = Some(amount);
+                    alert.currency = Some(crypto);
+                    alert.bank_name = Some(payout.bank_name.clone());
+                    alerts::payout_failed(alert).await;
+
+                    Err(format!("{}\n\n🔢 *Reference:* {}", e, payout.reference))
+                }
+            }
+        }
+    }
+}
+
+/// Reply to the "rate changed" prompt: pay out at the new amount, or cancel the offramp.
+async fn handle_payout_approval(message: &str, session: &mut UserSessions) -> String {
+    let Some(payout) = session.pending_payout.clone() else {
+        return StateMachine::repair(session, "payout approval without a pending payout")
+            .into_iter()
+            .map(|m| m.body)
+            .collect();
+    };
+
+    let client = backend::client();
+
+    match i18n::normalize_reply(session.language(), message).as_str() {
+        "yes" => {
+            let result = complete_payout(&client, session, &payout).await;
+            StateMachine::reset(session);
+            match result {
+                Ok(success_msg) => success_msg,
+                Err(err) => format!(
+                    "❌ *Withdrawal Failed*\n\n{}\n\nPlease try again, or type `support` for help.",
+                    err
+                ),
+            }
+        }
+        "no" => {
+            audit::record(
+                &session.phone,
+                session.correlation_id.as_deref(),
+                Some(&payout.reference),
+                AuditEvent::WithdrawalCancelled {
+                    step: "payout_approval".to_string(),
+                },
+            );
+
+            let cancel_result =
+                cancel_offramp(&client, &payout.reference, "payout_amount_declined").await;
+            audit::record(
+                &session.phone,
+                session.correlation_id.as_deref(),
+                Some(&payout.reference),
+                AuditEvent::OfframpCancelled {
+                    success: cancel_result.is_ok(),
+                    error: cancel_result.as_ref().err().cloned(),
+                },
+            );
+            StateMachine::reset(session);
+
+            match cancel_result {
+                Ok(()) => "❌ *Withdrawal Cancelled*\n\n\
+                    Your funds were not moved. Type `withdraw [amount] [crypto]` to get a new quote."
+                    .to_string(),
+                Err(err) => {
+                    let mut alert = PayoutAlert::new(
+                        &payout.reference,
+                        &session.phone,
+                        &format!("declined payout could not be cancelled ({})", err),
+                    );
+                    alert.amount = Some(payout.amount);
+                    alert.currency = Some(payout.currency.clone());
+                    alert.bank_name = Some(payout.bank_name.clone());
+                    alerts::payout_failed(alert).await;
+
+                    format!(
+                        "⚠️ We couldn't cancel this withdrawal automatically. Our team has been \
+                        notified and will make sure no funds are sent.\n\n🔢 *Reference:* {}",
+                        payout.reference
+                    )
+                }
+            }
+        }
+        _ => "❓ Please type `yes` to accept the new amount or `no` to cancel.".to_string(),
+    }
+}
+
+/// `3 min 20 sec`, `2 h 5 min`: how long a withdrawal has been going.
+fn elapsed_since(at: DateTime<Utc>) -> String {
+    let elapsed = Utc::now().signed_duration_since(at);
+    let (hours, minutes, seconds) = (
+        elapsed.num_hours(),
+        elapsed.num_minutes() % 60,
+        elapsed.num_seconds().max(0) % 60,
+    );
+    if hours > 0 {
+        format!("{} h {} min", hours, minutes)
+    } else if minutes > 0 {
+        format!("{} min {} sec", minutes, seconds)
+    } else {
+        format!("{} sec", seconds)
+    }
+}
+
+/// Answers a nudge about the user's withdrawal in progress with where it stands, from
+/// the backend if it answers and from our index otherwise.
+async fn handle_status_nudge(tx: &IndexedTransaction, session: &UserSessions) -> String {
+    let status = match shed_if_degraded(Endpoint::Status) {
+        Some(_) => None,
+        None => {
+            let client = backend::client();
+            let formatted_phone = session.phone.trim_start_matches("+");
+            fetch_transaction_status(&client, &tx.reference, formatted_phone)
+                .await
+                .unwrap_or_else(|err| {
+                    eprintln!("Status lookup for {} failed: {}", tx.reference, err);
+                    None
+                })
+        }
+    };
+    let current = status.map_or_else(|| tx.status.clone(), |status| status.status);
+
+    format!(
+        "⏳ *Your withdrawal is {}*\n\n\
+        💸 *Amount:* {}\n\
+        🏦 *To:* {} ({})\n\
+        🔢 *Reference:* {}\n\
+        🕒 *Started:* {} ago\n\n\
+        We'll message you as soon as it's done.",
+        current.to_lowercase(),
+        format_money(tx.amount, &tx.token),
+        tx.bank_name,
+        tx.masked_account,
+        tx.reference,
+        elapsed_since(tx.initiated_at)
+    )
+}
+
+async fn handle_status(reference: &str, session: &UserSessions) -> String {
+    if let Some(shed) = shed_if_degraded(Endpoint::Status) {
+        return shed;
+    }
+
+    let client = backend::client();
+
+    // Someone else's reference gets the same answer as one that doesn't exist
+    if let Some(tx) = transactions::lookup(reference).await
+        && store::session_key(&tx.phone) != store::session_key(&session.phone)
+    {
+        return format!("❌ No transaction found with reference `{}`.", reference);
+    }
+
+    let formatted_phone = session.phone.trim_start_matches("+");
+    match fetch_transaction_status(&client, reference, formatted_phone).await {
+        Ok(Some(status)) => status_reply(&status),
+        Ok(None) => format!("❌ No transaction found with reference `{}`.", reference),
+        Err(err) => {
+            eprintln!("Status lookup for {} failed: {}", reference, err);
+            "❌ Failed to check transaction status. Please try again.".to_string()
+        }
+    }
+}
+
+/// The answer to `status <reference>`.
+pub fn status_reply(status: &TransactionStatus) -> String {
+    format!(
+        "🔎 *Transaction Status*\n\n\
+        🔢 *Reference:* {}\n\
+        📅 *Status:* {}\n\
+        💰 *Amount:* {}\n\
+        🕒 *Last updated:* {}",
+        status.reference,
+        status.status,
+        money::format_optional_money(status.amount, status.currency.as_deref()),
+        status.last_updated.format("%Y-%m-%d %H:%M:%S")
+    )
+}
+
+/// `recap`: the user's last few messages with what we replied, plus where their recent
+/// withdrawals stand, from the transaction index.
+async fn handle_recap(session: &UserSessions) -> String {
+    // The newest entry is this `recap` itself
+    let history = &session.inbound_history[..session.inbound_history.len().saturating_sub(1)];
+    if history.is_empty() {
+        return "🧾 Nothing to recap yet. Type `help` to see what you can do.".to_string();
+    }
+
+    let mut recap = "🧾 *Your recent activity*\n".to_string();
+    for message in history.iter().rev().take(5).rev() {
+        recap.push_str(&format!(
+            "\n• {} — `{}`",
+            session
+                .prefs
+                .format_time(message.received_at, "%d %b %H:%M"),
+            message.body
+        ));
+        if let Some(outcome) = &message.outcome {
+            recap.push_str(&format!("\n   ↳ {}", outcome));
+        }
+    }
+
+    let withdrawals: Vec<String> = transactions::for_phone(&session.phone)
+        .await
+        .iter()
+        .take(3)
+        .map(|tx| format!("• `{}` — {}", tx.reference, tx.status))
+        .collect();
+    if !withdrawals.is_empty() {
+        recap.push_str(&format!(
+            "\n\n💸 *Recent withdrawals*\n{}",
+            withdrawals.join("\n")
+        ));
+    }
+
+    recap
+}
+
+/// `cancel <reference>`: only for the user's own withdrawals that haven't settled yet.
+async fn handle_cancel_withdrawal(reference: &str, session: &UserSessions) -> String {
+    // Like `handle_status`, but stricter: without our own record of them starting it,
+    // nobody cancels a withdrawal, whatever the backend would let through
+    let owned = transactions::lookup(reference)
+        .await
+        .is_some_and(|tx| store::session_key(&tx.phone) == store::session_key(&session.phone));
+    if !owned {
+        return format!(
+            "❌ No withdrawal found with reference `{}` on your account.",
+            reference
+        );
+    }
+
+    let client = backend::client();
+    let formatted_phone = session.phone.trim_start_matches("+");
+    let status = match fetch_transaction_status(&client, reference, formatted_phone).await {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            return format!(
+                "❌ No withdrawal found with reference `{}` on your account.",
+                reference
+            );
+        }
+        Err(err) => {
+            eprintln!("Status lookup for cancel of {} failed: {}", reference, err);
+            return "❌ Failed to check transaction status. Please try again.".to_string();
+        }
+    };
+
+    if matches!(
+        status.status.to_lowercase().as_str(),
+        "completed" | "successful" | "failed" | "cancelled"
+    ) {
+        return format!(
+            "ℹ️ Withdrawal `{}` can't be cancelled — it is already *{}*.",
+            status.reference, status.status
+        );
+    }
+
+    polling::expect_user_cancel(&status.reference);
+
+    let result = cancel_offramp(&client, &status.reference, "user_requested").await;
+    audit::record(
+        &session.phone,
+        None,
+        Some(&status.reference),
+        AuditEvent::OfframpCancelled {
+            success: result.is_ok(),
+            error: result.as_ref().err().cloned(),
+        },
+    );
+
+    match result {
+        Ok(()) => format!(
+            "✅ *Withdrawal Cancelled*\n\n\
+            🔢 *Reference:* {}\n\n\
+            Your funds were not sent. Type `withdraw [amount] [crypto]` to start a new one.",
+            status.reference
+        ),
+        Err(err) => {
+            polling::forget_user_cancel(&status.reference);
+            eprintln!("Cancel of {} failed: {}", status.reference, err);
+            format!(
+                "❌ We couldn't cancel `{}` right now. It may already be processing — \
+                check `status {}` or type `support`.",
+                status.reference, status.reference
+            )
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use actix_web::{
+    Error, HttpResponse,
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    middleware::Next,
+};
+
+use crate::server::empty_twiml;
+
+fn env_secs(name: &str, default: u64) -> Duration {
+    Duration::from_secs(
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(default),
+    )
+}
+
+/// Server-side deadline for every request, so no handler path can hold a worker forever.
+///
+/// `/webhook` gets `WEBHOOK_TIMEOUT_SECS` (default 10, under Twilio's 15s) and then the
+/// usual empty TwiML, so Twilio doesn't retry a message we're still working on. Other
+/// routes get `REQUEST_TIMEOUT_SECS` (default 60) and then 504.
+///
+/// Hitting the deadline drops the request future only: anything it spawned, like the
+/// webhook's message task, runs to completion.
+pub async fn limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let is_webhook = req.path() == "/webhook";
+    let timeout = if is_webhook {
+        env_secs("WEBHOOK_TIMEOUT_SECS", 10)
+    } else {
+        env_secs("REQUEST_TIMEOUT_SECS", 60)
+    };
+    let path = req.path().to_string();
+
+    match tokio::time::timeout(timeout, next.call(req)).await {
+        Ok(response) => response.map(ServiceResponse::map_into_boxed_body),
+        Err(_) => {
+            eprintln!("Request to {} exceeded its {:?} deadline", path, timeout);
+            let response = if is_webhook {
+                empty_twiml()
+            } else {
+                HttpResponse::GatewayTimeout().json(serde_json::json!({
+                    "success": false,
+                    "message": "Request timed out",
+                }))
+            };
+            // The request was moved into the timed-out future (and cloning it up front would
+            // break routing), so the fallback goes out as an error carrying its own response
+            Err(InternalError::from_response("request deadline exceeded", response).into())
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::Utc;
+
+use crate::i18n::{self, Language, Msg};
+use crate::transactions;
+
+/// How long recent withdrawals took to pay out, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub p50: i64,
+    pub p90: i64,
+}
+
+fn env_usize(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(default)
+}
+
+/// `ETA_MIN_SAMPLES`, default 5: fewer than this and we fall back to the static copy.
+fn min_samples() -> usize {
+    env_usize("ETA_MIN_SAMPLES", 5)
+}
+
+/// `ETA_WINDOW`, default 50: how many of the latest completions count.
+fn window() -> usize {
+    env_usize("ETA_WINDOW", 50)
+}
+
+/// `ETA_MAX_AGE_HOURS`, default 24: older completions say little about right now.
+fn max_age() -> chrono::Duration {
+    chrono::Duration::hours(env_usize("ETA_MAX_AGE_HOURS", 24) as i64)
+}
+
+/// Banks are typed all sorts of ways; `Opay` and ` opay ` are the same one.
+pub fn bank_key(bank: &str) -> String {
+    bank.trim().to_lowercase()
+}
+
+/// Nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[i64], pct: usize) -> i64 {
+    let rank = (pct * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+/// p50 and p90 of `samples`, or `None` with fewer than `min_samples` of them.
+pub fn estimate(mut samples: Vec<i64>, min_samples: usize) -> Option<Estimate> {
+    samples.retain(|seconds| *seconds > 0);
+    if samples.is_empty() || samples.len() < min_samples {
+        return None;
+    }
+    samples.sort_unstable();
+    Some(Estimate {
+        p50: percentile(&samples, 50),
+        p90: percentile(&samples, 90),
+    })
+}
+
+/// Notes a completed withdrawal; called by the poller once it sees the final status.
+pub fn record(reference: &str, bank: &str, seconds: i64, completed_at: chrono::DateTime<Utc>) {
+    if seconds > 0 {
+        transactions::record_completion(reference, &bank_key(bank), seconds, completed_at);
+    }
+}
+
+/// The estimate for `bank` if it has enough recent completions, otherwise across all
+/// banks. The bank is returned only when the estimate is its own.
+pub fn current(bank: Option<&str>) -> Option<(Estimate, Option<&str>)> {
+    let since = Utc::now() - max_age();
+    let (window, min_samples) = (window(), min_samples());
+    if let Some(bank) = bank
+        && let Some(estimate) = estimate(
+            transactions::completion_seconds(Some(&bank_key(bank)), since, window),
+            min_samples,
+        )
+    {
+        return Some((estimate, Some(bank)));
+    }
+    estimate(
+        transactions::completion_seconds(None, since, window),
+        min_samples,
+    )
+    .map(|estimate| (estimate, None))
+}
+
+/// `~40 sec`, `~2 min`, `~3 hours`, rounded the way people say it.
+pub fn approximate(seconds: i64) -> String {
+    match seconds {
+        ..90 => format!("~{} sec", (seconds.max(5) + 2) / 5 * 5),
+        90..5400 => format!("~{} min", (seconds + 30) / 60),
+        _ => format!("~{} hours", (seconds + 1800) / 3600),
+    }
+}
+
+/// The arrival line for a quote or initiation, falling back to the static copy when
+/// there isn't enough recent data.
+pub fn arrival_line(language: Language, bank: Option<&str>) -> String {
+    match current(bank) {
+        Some((estimate, Some(bank))) => i18n::render(
+            language,
+            Msg::ArrivalEstimateToBank,
+            &[
+                ("typical", &approximate(estimate.p50)),
+                ("most", &approximate(estimate.p90)),
+                ("bank", bank),
+            ],
+        ),
+        Some((estimate, None)) => i18n::render(
+            language,
+            Msg::ArrivalEstimate,
+            &[
+                ("typical", &approximate(estimate.p50)),
+                ("most", &approximate(estimate.p90)),
+            ],
+        ),
+        None => i18n::text(language, Msg::ArrivalUsual).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_estimate_is_the_nearest_rank_p50_and_p90() {
+        let samples: Vec<i64> = (1..=10).rev().map(|n| n * 60).collect();
+        assert_eq!(
+            estimate(samples, 5),
+            Some(Estimate {
+                p50: 5 * 60,
+                p90: 9 * 60
+            })
+        );
+        // One slow payout moves the p90, not the p50
+        assert_eq!(
+            estimate(vec![60, 60, 70, 80, 90, 3600], 5),
+            Some(Estimate { p50: 70, p90: 3600 })
+        );
+        assert_eq!(
+            estimate(vec![120], 1),
+            Some(Estimate { p50: 120, p90: 120 })
+        );
+    }
+
+    #[test]
+    fn too_few_samples_is_no_estimate() {
+        assert_eq!(estimate(vec![], 1), None);
+        assert_eq!(estimate(vec![60, 60, 60, 60], 5), None);
+        // Durations that can't be real don't count towards it
+        assert_eq!(estimate(vec![60, 60, 60, 60, 0, -5], 5), None);
+        assert!(estimate(vec![60, 60, 60, 60, 60], 5).is_some());
+    }
+
+    #[test]
+    fn durations_are_rounded_the_way_people_say_them() {
+        assert_eq!(approximate(0), "~5 sec");
+        assert_eq!(approximate(38), "~40 sec");
+        assert_eq!(approximate(89), "~90 sec");
+        assert_eq!(approximate(90), "~2 min");
+        assert_eq!(approximate(125), "~2 min");
+        assert_eq!(approximate(155), "~3 min");
+        assert_eq!(approximate(5399), "~90 min");
+        assert_eq!(approximate(5400), "~2 hours");
+        assert_eq!(approximate(3 * 3600), "~3 hours");
+    }
+
+    #[test]
+    fn banks_are_keyed_however_they_are_typed() {
+        assert_eq!(bank_key(" Opay "), bank_key("OPAY"));
+    }
+}
@@ -0,0 +1,249 @@
+use actix_web::{HttpResponse, Result, web};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use crate::backend::HmacKeys;
+use crate::model::TransactionRecord;
+use crate::money::{TokenAmount, token_decimals};
+
+struct StoredExport {
+    csv: String,
+    expires_at: DateTime<Utc>,
+}
+
+static EXPORTS: LazyLock<Mutex<HashMap<String, StoredExport>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    pub expires: i64,
+    pub signature: String,
+}
+
+pub fn render_csv(records: &[TransactionRecord]) -> String {
+    let mut csv = String::from("date,reference,token,crypto_amount,ngn_amount,bank,status\n");
+
+    for record in records {
+        let row = [
+            record.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            record.reference.clone(),
+            record.token_symbol.clone(),
+            crypto_amount(record),
+            record
+                .fiat_amount
+                .map(|amount| format!("{:.2}", amount))
+                .unwrap_or_default(),
+            record.bank_name.clone().unwrap_or_default(),
+            record.status.clone(),
+        ];
+
+        let escaped: Vec<String> = row.iter().map(|field| escape_csv_field(field)).collect();
+        csv.push_str(&escaped.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Every decimal the token has, not rounded to cents: a CSV is for reconciling.
+fn crypto_amount(record: &TransactionRecord) -> String {
+    TokenAmount::from_whole_tokens(record.crypto_amount, token_decimals(&record.token_symbol))
+        .map(|amount| amount.plain())
+        .unwrap_or_else(|_| record.crypto_amount.to_string())
+}
+
+fn escape_csv_field(field: &str) -> String {
+    // Leading formula characters are neutralised so spreadsheets don't evaluate them
+    let field = if field.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// `EXPORT_SIGNING_KEY`, plus `EXPORT_SIGNING_KEY_SECONDARY` during a rotation so links
+/// signed before it keep working. Never the backend keys: without its own key, export
+/// links are off.
+fn signing_keys() -> Result<HmacKeys, String> {
+    HmacKeys::from_vars("EXPORT_SIGNING_KEY", "EXPORT_SIGNING_KEY_SECONDARY")
+}
+
+fn signature_mac(key: &str, export_id: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}", export_id, expires).as_bytes());
+    mac
+}
+
+pub fn link_ttl_secs() -> i64 {
+    std::env::var("EXPORT_LINK_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(15 * 60)
+}
+
+/// Stores the CSV and returns a signed download link that expires after
+/// `EXPORT_LINK_TTL_SECS` (default 15 minutes).
+pub fn create_export(csv: String) -> Result<String, String> {
+    let base_url = std::env::var("PUBLIC_BASE_URL")
+        .map_err(|_| "PUBLIC_BASE_URL is not set; cannot build export links".to_string())?;
+    let key = signing_keys()?.primary;
+    let ttl_secs = link_ttl_secs();
+
+    let export_id = uuid::Uuid::new_v4().to_string();
+    let expires_at = Utc::now() + chrono::Duration::seconds(ttl_secs);
+    let expires = expires_at.timestamp();
+    let signature = hex::encode(
+        signature_mac(&key, &export_id, expires)
+            .finalize()
+            .into_bytes(),
+    );
+
+    let mut exports = EXPORTS.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Utc::now();
+    exports.retain(|_, export| export.expires_at > now);
+    exports.insert(export_id.clone(), StoredExport { csv, expires_at });
+
+    Ok(format!(
+        "{}/exports/{}?expires={}&signature={}",
+        base_url.trim_end_matches('/'),
+        export_id,
+        expires,
+        signature
+    ))
+}
+
+pub async fn download_export(
+    export_id: web::Path<String>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse> {
+    let keys = match signing_keys() {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("ALERT: {}; refusing export downloads", e);
+            return Ok(HttpResponse::NotFound().finish());
+        }
+    };
+
+    let signature_valid = hex::decode(&query.signature).is_ok_and(|provided| {
+        keys.candidates().any(|key| {
+            signature_mac(key, &export_id, query.expires)
+                .verify_slice(&provided)
+                .is_ok()
+        })
+    });
+    if !signature_valid {
+        return Ok(HttpResponse::Forbidden().body("Invalid export link"));
+    }
+
+    if Utc::now().timestamp() > query.expires {
+        return Ok(HttpResponse::Gone().body("This export link has expired"));
+    }
+
+    let exports = EXPORTS.lock().unwrap_or_else(|e| e.into_inner());
+    match exports.get(export_id.as_str()) {
+        Some(export) if export.expires_at > Utc::now() => Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"kharon-pay-transactions.csv\"",
+            ))
+            .body(export.csv.clone())),
+        _ => Ok(HttpResponse::Gone().body("This export link has expired")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::App;
+    use actix_web::test::{TestRequest, call_service, init_service};
+
+    fn record(token: &str, crypto_amount: f64) -> TransactionRecord {
+        TransactionRecord {
+            created_at: "2026-01-02T03:04:05Z".parse().unwrap(),
+            reference: "KP-1".to_string(),
+            token_symbol: token.to_string(),
+            crypto_amount,
+            fiat_amount: Some(1500.0),
+            bank_name: Some("Opay".to_string()),
+            status: "completed".to_string(),
+        }
+    }
+
+    #[test]
+    fn crypto_amounts_keep_every_decimal() {
+        let csv = render_csv(&[
+            record("USDT", 1234567.123456),
+            record("USDT", 0.000001),
+            record("USDT", 10.0),
+        ]);
+        let amounts: Vec<&str> = csv
+            .lines()
+            .skip(1)
+            .map(|row| row.split(',').nth(3).unwrap())
+            .collect();
+        assert_eq!(amounts, ["1234567.123456", "0.000001", "10.00"]);
+    }
+
+    #[test]
+    fn formulas_and_separators_are_escaped() {
+        assert_eq!(escape_csv_field("=SUM(A1)"), "'=SUM(A1)");
+        assert_eq!(escape_csv_field("Opay, Lagos"), "\"Opay, Lagos\"");
+        assert_eq!(escape_csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    async fn download(link: &str) -> u16 {
+        let app =
+            init_service(App::new().route("/exports/{id}", web::get().to(download_export))).await;
+        let path = link.trim_start_matches("https://pay.example");
+        let request = TestRequest::get().uri(path).to_request();
+        call_service(&app, request).await.status().as_u16()
+    }
+
+    #[actix_web::test]
+    async fn links_need_the_export_key_and_nothing_else() {
+        // SAFETY: only this test reads these variables
+        unsafe {
+            std::env::set_var("PUBLIC_BASE_URL", "https://pay.example");
+            std::env::remove_var("EXPORT_SIGNING_KEY");
+        }
+        // No export key: no links, and no downloads, whatever other key signed them
+        assert!(create_export("a,b\n".to_string()).is_err());
+        let export_id = "forged";
+        let forged = hex::encode(
+            signature_mac("backend-key", export_id, i64::MAX)
+                .finalize()
+                .into_bytes(),
+        );
+        let forged = format!(
+            "/exports/{}?expires={}&signature={}",
+            export_id,
+            i64::MAX,
+            forged
+        );
+        assert_eq!(download(&forged).await, 404);
+
+        // SAFETY: as above
+        unsafe { std::env::set_var("EXPORT_SIGNING_KEY", "export-key") };
+        let link = create_export("a,b\n".to_string()).expect("signed link");
+        assert_eq!(download(&link).await, 200);
+        assert_eq!(download(&forged).await, 403);
+        assert_eq!(
+            download(&link.replace("signature=", "signature=00")).await,
+            403
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{LazyLock, Mutex};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::access::normalize_phone;
+
+/// Quick-reply templates on the quote and bank confirmation prompts.
+pub const INTERACTIVE_BUTTONS: &str = "interactive_buttons";
+
+pub const COMING_SOON_MESSAGE: &str = "🚧 That's coming soon to KharonPay!\n\n\
+    We're rolling it out gradually and it isn't on for your number yet. \
+    Type `help` to see what you can do today.";
+
+/// Who gets a flagged feature. A flag that isn't configured at all is on for everyone,
+/// so defining one is how a feature gets held back.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeatureFlag {
+    /// On for everyone; `false` leaves only the cohort below.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Share of users, 0-100, picked by a hash of the flag and their number, so the
+    /// same people stay in as it grows.
+    #[serde(default)]
+    pub percent: u8,
+    /// Numbers that always get it, as `+234…`.
+    #[serde(default)]
+    pub allowlist: BTreeSet<String>,
+}
+
+impl FeatureFlag {
+    fn normalized(mut self) -> Self {
+        self.percent = self.percent.min(100);
+        self.allowlist = self
+            .allowlist
+            .iter()
+            .filter_map(|phone| normalize_phone(phone))
+            .collect();
+        self
+    }
+
+    fn applies_to(&self, flag: &str, phone: &str) -> bool {
+        if self.enabled {
+            return true;
+        }
+        let Some(phone) = normalize_phone(phone) else {
+            return false;
+        };
+        self.allowlist.contains(&phone) || bucket(flag, &phone) < self.percent
+    }
+}
+
+/// 0-99 for a number under a flag. Salted with the flag's name so each rollout picks
+/// its own cohort, and stable across restarts and instances.
+fn bucket(flag: &str, phone: &str) -> u8 {
+    let digest = Sha256::digest(format!("{}:{}", flag, phone).as_bytes());
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(prefix) % 100) as u8
+}
+
+/// `FEATURE_FLAGS`, a JSON object of flag name to `FeatureFlag`, e.g.
+/// `{"interactive_buttons": {"percent": 10, "allowlist": ["+2348012345678"]}}`.
+/// Command keywords are flags too, under the name `help` lists (`hi`, never an alias like
+/// `hello`): `{"cancel": {}}` hides `cancel` from everyone.
+fn from_env() -> BTreeMap<String, FeatureFlag> {
+    let Ok(raw) = std::env::var("FEATURE_FLAGS") else {
+        return BTreeMap::new();
+    };
+    match serde_json::from_str::<BTreeMap<String, FeatureFlag>>(&raw) {
+        Ok(flags) => flags
+            .into_iter()
+            .map(|(name, flag)| (name, flag.normalized()))
+            .collect(),
+        Err(e) => {
+            eprintln!(
+                "ALERT: FEATURE_FLAGS is unreadable ({}); every feature is on",
+                e
+            );
+            BTreeMap::new()
+        }
+    }
+}
+
+static FLAGS: LazyLock<Mutex<BTreeMap<String, FeatureFlag>>> =
+    LazyLock::new(|| Mutex::new(from_env()));
+
+/// Whether `phone` gets the feature.
+pub fn is_enabled(flag: &str, phone: &str) -> bool {
+    FLAGS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(flag)
+        .is_none_or(|rule| rule.applies_to(flag, phone))
+}
+
+/// Every configured flag as it stands now, for `/health` and the admin API.
+pub fn snapshot() -> BTreeMap<String, FeatureFlag> {
+    FLAGS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// `snapshot` without the allowlisted numbers, for `/health`, which anyone can read.
+pub fn summary() -> BTreeMap<String, serde_json::Value> {
+    FLAGS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(name, rule)| {
+            let summary = serde_json::json!({
+                "enabled": rule.enabled,
+                "percent": rule.percent,
+                "allowlisted": rule.allowlist.len(),
+            });
+            (name.clone(), summary)
+        })
+        .collect()
+}
+
+/// Which configured flags are on for one number, for the admin session view.
+pub fn for_phone(phone: &str) -> BTreeMap<String, bool> {
+    FLAGS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(name, rule)| (name.clone(), rule.applies_to(name, phone)))
+        .collect()
+}
+
+/// Replaces a flag until the next restart, which goes back to `FEATURE_FLAGS`.
+pub fn set(flag: &str, rule: FeatureFlag) {
+    FLAGS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(flag.to_string(), rule.normalized());
+}
+
+/// Drops a flag, turning its feature on for everyone; false if it wasn't configured.
+pub fn remove(flag: &str) -> bool {
+    FLAGS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(flag)
+        .is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phone(i: usize) -> String {
+        format!("+23480{:08}", i)
+    }
+
+    fn rollout(percent: u8) -> FeatureFlag {
+        FeatureFlag {
+            percent,
+            ..FeatureFlag::default()
+        }
+        .normalized()
+    }
+
+    #[test]
+    fn buckets_are_a_stable_hash_of_flag_and_number() {
+        // sha256("<flag>:<phone>"), first 8 bytes big-endian, mod 100
+        assert_eq!(bucket("interactive_buttons", "+2348012345678"), 50);
+        assert_eq!(bucket("withdraw", "+2348012345678"), 20);
+        assert!((0..1000).all(|i| bucket("withdraw", &phone(i)) < 100));
+    }
+
+    #[test]
+    fn a_rollout_reaches_about_its_share_and_only_grows() {
+        let phones: Vec<String> = (0..10_000).map(phone).collect();
+        let reached = |percent| {
+            let flag = rollout(percent);
+            phones
+                .iter()
+                .filter(|phone| flag.applies_to("withdraw", phone))
+                .cloned()
+                .collect::<BTreeSet<_>>()
+        };
+
+        let (ten, thirty) = (reached(10), reached(30));
+        assert!((900..1100).contains(&ten.len()), "{}", ten.len());
+        assert!((2800..3200).contains(&thirty.len()), "{}", thirty.len());
+        assert!(ten.is_subset(&thirty));
+        assert!(reached(0).is_empty());
+        assert_eq!(reached(100).len(), phones.len());
+    }
+
+    #[test]
+    fn each_flag_picks_its_own_cohort() {
+        let flag = rollout(50);
+        let differs = (0..100)
+            .map(phone)
+            .filter(|phone| flag.applies_to("quote", phone) != flag.applies_to("cancel", phone))
+            .count();
+        assert!(differs > 20, "{}", differs);
+    }
+
+    #[test]
+    fn allowlists_and_enabled_flags_override_the_percentage() {
+        let flag = FeatureFlag {
+            enabled: false,
+            percent: 250,
+            allowlist: ["whatsapp:+234 801 234 5678".to_string()].into(),
+        }
+        .normalized();
+        assert_eq!(flag.percent, 100);
+        assert!(flag.allowlist.contains("+2348012345678"));
+
+        let only_listed = FeatureFlag {
+            percent: 0,
+            ..flag.clone()
+        };
+        assert!(only_listed.applies_to("withdraw", "+2348012345678"));
+        assert!(!only_listed.applies_to("withdraw", "+2348012345679"));
+        assert!(!only_listed.applies_to("withdraw", "not a number"));
+
+        let everyone = FeatureFlag {
+            enabled: true,
+            ..FeatureFlag::default()
+        };
+        assert!(everyone.applies_to("withdraw", "not a number"));
+    }
+}
@@ -0,0 +1,213 @@
+use chrono::{Duration, Utc};
+
+use crate::audit::{self, AuditEvent};
+use crate::backend;
+use crate::i18n::{self, Language, Msg};
+use crate::metrics;
+use crate::model::{FeedbackPending, UserSessions, UserState};
+use crate::privacy::mask_phone;
+use crate::server::send_notice;
+use crate::window::{self, Notice};
+use crate::{prefs, store};
+
+/// A bare digit only counts as a rating within this long of the ask.
+const ANSWER_WINDOW: Duration = Duration::hours(1);
+/// Users who let this many asks in a row go unanswered aren't asked again.
+const MAX_UNANSWERED: u32 = 2;
+
+/// Asks for a 1–5 rating after a completed withdrawal, once per reference. Skipped for
+/// users with notifications off, outside the 24-hour window (there's no template for it),
+/// or after `MAX_UNANSWERED` ignored asks. The marker is written to the session before
+/// the ask goes out, so even an instant reply finds it.
+pub async fn request_rating(phone: &str, reference: &str, language: Language) {
+    let prefs = prefs::load(phone).await;
+    let skip = if prefs.notifications_off {
+        Some("notifications are off")
+    } else if prefs.feedback_unanswered >= MAX_UNANSWERED {
+        Some("recent asks went unanswered")
+    } else if !window::within_session_window(phone) {
+        Some("outside the 24h window")
+    } else {
+        None
+    };
+    if let Some(reason) = skip {
+        metrics::FEEDBACK_ASKS.inc(&["skipped"]);
+        println!(
+            "Not asking {} to rate {}: {}",
+            mask_phone(phone),
+            reference,
+            reason
+        );
+        return;
+    }
+
+    let Some(sessions) = store::shared() else {
+        return;
+    };
+    let (store, key) = (sessions.clone(), store::session_key(phone));
+    let mut session = match store::blocking(move || store.load(&key)).await {
+        Ok(Some(session)) => session,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!(
+                "Not asking {} to rate {}: {}",
+                mask_phone(phone),
+                reference,
+                e
+            );
+            return;
+        }
+    };
+    if session
+        .feedback_pending
+        .as_ref()
+        .is_some_and(|pending| pending.reference == reference)
+    {
+        return;
+    }
+
+    session.feedback_pending = Some(FeedbackPending {
+        reference: reference.to_string(),
+        asked_at: Utc::now(),
+    });
+    let (store, saved) = (sessions.clone(), session.clone());
+    if let Err(e) = store::blocking(move || store.save(&saved)).await {
+        eprintln!(
+            "Not asking {} to rate {}: {}",
+            mask_phone(phone),
+            reference,
+            e
+        );
+        return;
+    }
+    if let Err(e) = prefs::update(phone, |prefs| prefs.feedback_unanswered += 1).await {
+        eprintln!(
+            "Failed to save preferences for {}: {}",
+            mask_phone(phone),
+            e
+        );
+    }
+
+    metrics::FEEDBACK_ASKS.inc(&["asked"]);
+    audit::record(phone, None, Some(reference), AuditEvent::FeedbackRequested);
+    send_notice(
+        phone,
+        i18n::text(language, Msg::RatingRequest),
+        Notice::Feedback,
+    )
+    .await;
+}
+
+/// Consumes the pending rating request, if any. A bare 1–5 within the hour, from a user
+/// not in the middle of another flow, is the rating: it's recorded and thanked. Anything
+/// else quietly drops the request and is handled as a normal message.
+pub fn capture(message: &str, session: &mut UserSessions) -> Option<String> {
+    let pending = session.feedback_pending.take()?;
+    if session.state != UserState::Initial || Utc::now() - pending.asked_at > ANSWER_WINDOW {
+        return None;
+    }
+    let rating = match message.trim().as_bytes() {
+        [digit @ b'1'..=b'5'] => digit - b'0',
+        _ => return None,
+    };
+
+    session.prefs.feedback_unanswered = 0;
+    metrics::FEEDBACK_RATINGS.inc(&[&rating.to_string()]);
+    audit::record(
+        &session.phone,
+        None,
+        Some(&pending.reference),
+        AuditEvent::FeedbackRated { rating },
+    );
+    let phone = session.phone.clone();
+    tokio::spawn(async move { forward(&phone, &pending.reference, rating).await });
+
+    Some(i18n::text(session.language(), Msg::RatingThanks).to_string())
+}
+
+/// Sends the rating to `SERVER_FEEDBACK_ENDPOINT` for analytics. Best effort: it's in the
+/// audit log (and so the daily summary) either way.
+async fn forward(phone: &str, reference: &str, rating: u8) {
+    let Ok(url) = std::env::var("SERVER_FEEDBACK_ENDPOINT") else {
+        return;
+    };
+    let payload = serde_json::json!({
+        "phone": phone.trim_start_matches('+'),
+        "reference": reference,
+        "rating": rating,
+    });
+    let result = backend::signed_post(&backend::client(), &url, &payload)
+        .timeout(std::time::Duration::from_secs(10))
+        .send()
+        .await;
+    match result {
+        Ok(res) if res.status().is_success() => {}
+        Ok(res) => eprintln!("Rating for {} rejected: {}", reference, res.status()),
+        Err(e) => eprintln!("Rating for {} failed: {}", reference, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asked(phone: &str, ago: Duration) -> UserSessions {
+        let mut session = UserSessions::new(phone);
+        session.feedback_pending = Some(FeedbackPending {
+            reference: "KP-RATE-1".to_string(),
+            asked_at: Utc::now() - ago,
+        });
+        session.prefs.feedback_unanswered = 1;
+        session
+    }
+
+    #[tokio::test]
+    async fn a_bare_digit_within_the_hour_is_the_rating() {
+        let before = metrics::FEEDBACK_RATINGS.get(&["4"]);
+        let mut session = asked("+2348090000301", Duration::minutes(5));
+
+        let reply = capture(" 4 ", &mut session).expect("rating thanked");
+        assert_eq!(reply, i18n::text(Language::En, Msg::RatingThanks));
+        assert!(session.feedback_pending.is_none());
+        assert_eq!(session.prefs.feedback_unanswered, 0);
+        assert_eq!(metrics::FEEDBACK_RATINGS.get(&["4"]), before + 1);
+    }
+
+    #[test]
+    fn anything_else_cancels_the_capture_without_complaint() {
+        for message in ["thanks!", "6", "0", "45", "balance"] {
+            let mut session = asked("+2348090000302", Duration::minutes(5));
+            assert_eq!(capture(message, &mut session), None, "{}", message);
+            assert!(session.feedback_pending.is_none());
+            assert_eq!(session.prefs.feedback_unanswered, 1);
+        }
+        // Nothing pending, nothing captured
+        let mut session = UserSessions::new("+2348090000302");
+        assert_eq!(capture("5", &mut session), None);
+    }
+
+    #[test]
+    fn late_or_mid_flow_digits_are_not_ratings() {
+        let mut late = asked("+2348090000303", Duration::hours(2));
+        assert_eq!(capture("5", &mut late), None);
+
+        let mut busy = asked("+2348090000304", Duration::minutes(5));
+        busy.state = UserState::BankSelection;
+        assert_eq!(capture("2", &mut busy), None);
+        assert!(busy.feedback_pending.is_none());
+    }
+
+    #[tokio::test]
+    async fn users_who_ignored_the_last_two_asks_are_skipped() {
+        let phone = "+2348090000305";
+        prefs::update(phone, |prefs| prefs.feedback_unanswered = MAX_UNANSWERED)
+            .await
+            .unwrap();
+        let skipped = metrics::FEEDBACK_ASKS.get(&["skipped"]);
+
+        request_rating(phone, "KP-RATE-2", Language::En).await;
+
+        assert_eq!(metrics::FEEDBACK_ASKS.get(&["skipped"]), skipped + 1);
+        assert_eq!(prefs::load(phone).await.feedback_unanswered, MAX_UNANSWERED);
+    }
+}
@@ -0,0 +1,743 @@
+use serde::{Deserialize, Serialize};
+
+use crate::parser::{COMMANDS, Command, CommandSpec, command_spec, parse_command};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    Fr,
+    Sw,
+    /// Nigerian Pidgin.
+    Pcm,
+}
+
+impl Language {
+    pub const ALL: [Language; 4] = [Language::En, Language::Fr, Language::Sw, Language::Pcm];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::En => "English",
+            Language::Fr => "Français",
+            Language::Sw => "Kiswahili",
+            Language::Pcm => "Pidgin",
+        }
+    }
+
+    /// What `language <name>` accepts: the code, or the name in English or in the language itself.
+    pub fn from_name(raw: &str) -> Option<Language> {
+        match raw.trim().to_lowercase().as_str() {
+            "en" | "english" => Some(Language::En),
+            "fr" | "french" | "francais" | "français" => Some(Language::Fr),
+            "sw" | "swahili" | "kiswahili" => Some(Language::Sw),
+            "pcm" | "pidgin" | "naija" => Some(Language::Pcm),
+            _ => None,
+        }
+    }
+}
+
+/// Words common in first messages that only one supported language uses. English has
+/// no list: it's what we fall back to. Pidgin shares too much with English to guess
+/// reliably, so it is only ever chosen with the `language` command.
+const FRENCH_WORDS: &[&str] = &[
+    "bonjour", "bonsoir", "salut", "merci", "je", "veux", "voudrais", "retirer", "mon", "ma",
+    "solde", "comment", "ça", "ca", "va", "oui", "non", "plaît", "plait", "combien", "argent",
+    "aide", "compte", "est", "quoi",
+];
+const SWAHILI_WORDS: &[&str] = &[
+    "habari",
+    "jambo",
+    "hujambo",
+    "mambo",
+    "sasa",
+    "asante",
+    "nataka",
+    "kutoa",
+    "pesa",
+    "salio",
+    "ndiyo",
+    "hapana",
+    "tafadhali",
+    "msaada",
+    "karibu",
+    "gani",
+    "yako",
+    "yangu",
+    "nini",
+    "akaunti",
+];
+
+/// Guesses the language of a free-text message. Only greetings and text that isn't a
+/// command are considered, so `balance` or `withdraw 5 usdt` never switch anyone's
+/// language. Ties and unrecognised text give `None`, which means English.
+pub fn detect(text: &str) -> Option<Language> {
+    if !matches!(
+        parse_command(text),
+        Command::Greet | Command::Unknown { .. }
+    ) {
+        return None;
+    }
+
+    let words: Vec<String> = text
+        .split(|c: char| c.is_whitespace() || c == '\'' || c == '’')
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect();
+    let score = |list: &[&str]| {
+        words
+            .iter()
+            .filter(|word| list.contains(&word.as_str()))
+            .count()
+    };
+
+    let french = score(FRENCH_WORDS);
+    let swahili = score(SWAHILI_WORDS);
+    match french.cmp(&swahili) {
+        std::cmp::Ordering::Greater => Some(Language::Fr),
+        std::cmp::Ordering::Less => Some(Language::Sw),
+        std::cmp::Ordering::Equal => None,
+    }
+}
+
+/// Every piece of user-facing copy that has translations. Placeholders in `{braces}`
+/// are filled by `render`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Msg {
+    /// `{commands}`; see `welcome`
+    Welcome,
+    /// `{commands}`, `{examples}`; see `help`
+    Help,
+    LanguageSet,
+    /// `{amount}`, `{rate}`, `{crypto}`, `{naira}`, `{eta}`
+    WithdrawRequest,
+    /// `{amount}`
+    SummaryTitle,
+    /// `{rate}`, `{crypto}`
+    SummaryRate,
+    /// `{naira}`
+    SummaryReceive,
+    /// `{bank}`, `{name}`, `{number}`
+    SummaryBank,
+    /// `{summary}`
+    SavedBankPrompt,
+    BankDetailsRequired,
+    QuoteCancelled,
+    ConfirmOrCancel,
+    /// `{summary}`
+    AccountVerified,
+    WithdrawalCancelled,
+    YesOrNo,
+    ReenterBankDetails,
+    YesOrReenter,
+    /// `{amount}`, `{crypto}`
+    LargeWithdrawal,
+    /// `{amount}`, `{words}`, `{crypto}`
+    AbsurdWithdrawal,
+    /// `{prompt}`
+    AmountMismatch,
+    AmountMismatchCancelled,
+    /// `{amount}`, `{bank}`, `{name}`, `{reference}`, `{tx}`, `{eta}`
+    WithdrawalInitiated,
+    /// `{amount}`, `{bank}`, `{name}`, `{reference}`, `{tx}`, `{duration}`, `{completed_at}`
+    WithdrawalCompleted,
+    /// `{reference}`, `{status}`
+    WithdrawalFailed,
+    /// `{amount}`
+    ChooseBank,
+    ChooseBankReply,
+    NotificationsOn,
+    NotificationsOff,
+    RatingRequest,
+    RatingThanks,
+    SandboxJoined,
+    OptedOut,
+    OptedIn,
+    /// `{reference}`, `{eta}`; pushed by the backend through `/notify`
+    SettlementDelayed,
+    /// `{until}`; pushed by the backend through `/notify`
+    AccountReview,
+    /// `{typical}`, `{most}`; see `eta`
+    ArrivalEstimate,
+    /// `{typical}`, `{most}`, `{bank}`
+    ArrivalEstimateToBank,
+    /// When there are too few recent completions to estimate from
+    ArrivalUsual,
+}
+
+/// Copy for `msg` in `language`, falling back to English where there's no translation.
+pub fn text(language: Language, msg: Msg) -> &'static str {
+    match language {
+        Language::En => Some(english(msg)),
+        Language::Fr => french(msg),
+        Language::Sw => swahili(msg),
+        Language::Pcm => pidgin(msg),
+    }
+    .unwrap_or_else(|| english(msg))
+}
+
+/// `text` with each `{name}` replaced by its value. Substitution is a single pass, so a
+/// value that itself contains braces (a bank name, say) is left alone.
+pub fn render(language: Language, msg: Msg, args: &[(&str, &str)]) -> String {
+    let template = text(language, msg);
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let value = after.find('}').and_then(|close| {
+            let name = &after[..close];
+            args.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (*value, close))
+        });
+        match value {
+            Some((value, close)) => {
+                rendered.push_str(value);
+                rest = &after[close + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// The `{name}` placeholders in `msg`'s English copy, which every translation shares.
+pub fn placeholders(msg: Msg) -> Vec<&'static str> {
+    let mut names = Vec::new();
+    let mut rest = english(msg);
+    while let Some(open) = rest.find('{') {
+        let after = &rest[open + 1..];
+        let Some(close) = after.find('}') else {
+            break;
+        };
+        if !names.contains(&&after[..close]) {
+            names.push(&after[..close]);
+        }
+        rest = &after[close + 1..];
+    }
+    names
+}
+
+/// `• \`keyword\` - what it does`, or with the full usage (`send [amount]...`) for `help`.
+fn command_line(language: Language, spec: &CommandSpec, with_usage: bool) -> String {
+    let keyword = if with_usage {
+        command_usage(language, spec.name).unwrap_or(spec.usage)
+    } else {
+        spec.name
+    };
+    let description = command_description(language, spec.name).unwrap_or(spec.description);
+    format!("• `{}` - {}", keyword, description)
+}
+
+/// The welcome message, its menu generated from the command registry.
+pub fn welcome(language: Language, has_account: bool, phone: &str) -> String {
+    let commands: Vec<String> = COMMANDS
+        .iter()
+        .filter(|spec| spec.in_menu && spec.visible_to(has_account) && spec.available_to(phone))
+        .map(|spec| command_line(language, spec, false))
+        .collect();
+    render(
+        language,
+        Msg::Welcome,
+        &[("commands", &commands.join("\n"))],
+    )
+}
+
+/// The help message: every listed command with its usage, then the examples.
+pub fn help(language: Language, has_account: bool, phone: &str) -> String {
+    let visible = || {
+        COMMANDS
+            .iter()
+            .filter(|spec| spec.visible_to(has_account) && spec.available_to(phone))
+    };
+    let commands: Vec<String> = visible()
+        .map(|spec| command_line(language, spec, true))
+        .collect();
+    let examples: Vec<String> = visible()
+        .filter_map(|spec| spec.example)
+        .map(|example| format!("• `{}`", example))
+        .collect();
+    render(
+        language,
+        Msg::Help,
+        &[
+            ("commands", &commands.join("\n")),
+            ("examples", &examples.join("\n")),
+        ],
+    )
+}
+
+/// Menu lines for just the named commands, for replies that point at what to do next.
+pub fn command_snippet(language: Language, names: &[&str]) -> String {
+    names
+        .iter()
+        .filter_map(|name| command_spec(name))
+        .map(|spec| command_line(language, spec, false))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Usage with translated argument names, for the commands that take arguments.
+fn command_usage(language: Language, name: &str) -> Option<&'static str> {
+    Some(match (language, name) {
+        (Language::Fr, "withdraw") => "send [montant] [crypto] to [banque]",
+        (Language::Fr, "quote") => "quote [montant] [crypto]",
+        (Language::Fr, "status") => "status [référence]",
+        (Language::Fr, "cancel") => "cancel [référence]",
+        (Language::Fr, "language") => "language [nom]",
+        (Language::Fr, "verify") => "verify [numéro]",
+        (Language::Fr, "support") => "support [problème]",
+        (Language::Sw, "withdraw") => "send [kiasi] [crypto] to [benki]",
+        (Language::Sw, "quote") => "quote [kiasi] [crypto]",
+        (Language::Sw, "status") => "status [kumbukumbu]",
+        (Language::Sw, "cancel") => "cancel [kumbukumbu]",
+        (Language::Sw, "language") => "language [jina]",
+        (Language::Sw, "verify") => "verify [namba]",
+        (Language::Sw, "support") => "support [tatizo]",
+        (Language::Pcm, "support") => "support [wetin happen]",
+        _ => return None,
+    })
+}
+
+/// Command descriptions; English is in the registry itself.
+fn command_description(language: Language, name: &str) -> Option<&'static str> {
+    Some(match (language, name) {
+        (Language::Fr, "create") => "Créer un compte",
+        (Language::Fr, "address") => "Obtenir l'adresse de votre portefeuille",
+        (Language::Fr, "fund") => "Déposer des cryptos sur votre adresse",
+        (Language::Fr, "balance") => "Voir votre solde",
+        (Language::Fr, "withdraw") => "Envoyer des cryptos vers votre banque",
+        (Language::Fr, "quote") => "Voir ce que vous recevriez",
+        (Language::Fr, "status") => "Suivre un retrait",
+        (Language::Fr, "cancel") => "Annuler un retrait pas encore payé",
+        (Language::Fr, "export") => "Télécharger votre historique (CSV)",
+        (Language::Fr, "recap") => "Voir vos dernières actions",
+        (Language::Fr, "language") => "Changer de langue",
+        (Language::Fr, "notifications") => "Activer ou couper les rappels",
+        (Language::Fr, "settings") => "Fuseau horaire, texte simple, banque par défaut",
+        (Language::Fr, "verify") => "Revérifier un compte bancaire enregistré",
+        (Language::Fr, "support") => "Ouvrir un ticket auprès de notre équipe",
+        (Language::Fr, "help") => "Voir toutes les commandes",
+        (Language::Sw, "create") => "Fungua akaunti mpya",
+        (Language::Sw, "address") => "Pata anwani ya pochi yako",
+        (Language::Sw, "fund") => "Weka crypto kwenye anwani ya pochi yako",
+        (Language::Sw, "balance") => "Angalia salio",
+        (Language::Sw, "withdraw") => "Tuma crypto kwenye akaunti yako ya benki",
+        (Language::Sw, "quote") => "Ona utakachopokea",
+        (Language::Sw, "status") => "Fuatilia utoaji",
+        (Language::Sw, "cancel") => "Ghairi utoaji ambao haujalipwa",
+        (Language::Sw, "export") => "Pakua historia yako (CSV)",
+        (Language::Sw, "recap") => "Ona ulichofanya hivi karibuni",
+        (Language::Sw, "language") => "Badili lugha",
+        (Language::Sw, "notifications") => "Washa au zima vikumbusho",
+        (Language::Sw, "settings") => "Saa za eneo, maandishi wazi, benki chaguo-msingi",
+        (Language::Sw, "verify") => "Hakiki tena akaunti ya benki iliyohifadhiwa",
+        (Language::Sw, "support") => "Fungua tiketi kwa timu yetu",
+        (Language::Sw, "help") => "Onyesha amri zote",
+        (Language::Pcm, "create") => "Open new account",
+        (Language::Pcm, "address") => "Collect your wallet address",
+        (Language::Pcm, "fund") => "Put crypto for your wallet address",
+        (Language::Pcm, "balance") => "Check your crypto balance",
+        (Language::Pcm, "withdraw") => "Send crypto enter your bank account",
+        (Language::Pcm, "quote") => "See how much you go collect",
+        (Language::Pcm, "status") => "Check how your withdrawal dey go",
+        (Language::Pcm, "cancel") => "Cancel withdrawal wey never pay",
+        (Language::Pcm, "recap") => "See wetin you don do recently",
+        (Language::Pcm, "notifications") => "Switch reminders on or off",
+        (Language::Pcm, "settings") => "Your timezone, plain text, default bank",
+        (Language::Pcm, "verify") => "Check your saved bank account again",
+        (Language::Pcm, "support") => "Open ticket make our team help you",
+        (Language::Pcm, "help") => "See all the commands",
+        _ => return None,
+    })
+}
+
+/// Maps the many ways people say yes or no onto `yes`/`no`; anything else comes back
+/// trimmed and lowercased. Pidgin speakers get `abeg yes`, `na so`, `no o` and friends.
+pub fn normalize_reply(language: Language, message: &str) -> String {
+    let reply = message.trim().to_lowercase();
+    if language != Language::Pcm {
+        return reply;
+    }
+
+    let words: Vec<&str> = reply
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '!' || c == '.')
+        .filter(|word| !matches!(*word, "" | "abeg" | "o" | "oo" | "ooo" | "sir" | "ma"))
+        .collect();
+    match words.join(" ").as_str() {
+        "yes" | "yes na" | "na so" | "ehen" | "oya" | "i gree" | "make e go" => "yes".to_string(),
+        "no" | "no be so" | "mba" | "i no gree" | "leave am" => "no".to_string(),
+        _ => reply,
+    }
+}
+
+fn english(msg: Msg) -> &'static str {
+    match msg {
+        Msg::Welcome => {
+            "🟢 Welcome to *Kharon Pay*! 💰\n\nSend crypto to your bank in seconds.\n\n📱 *Commands:*\n{commands}\n\n🔕 Prefer fewer messages? Type `notifications off` to stop reminders.\n\nWhat would you like to do?"
+        }
+        Msg::Help => "🔰 *Kharon Pay Help*\n\n*Commands:*\n{commands}\n\n*Examples:*\n{examples}",
+        Msg::LanguageSet => "✅ Language set to English.",
+        Msg::WithdrawRequest => {
+            "💸 *Withdraw Request*\n\n\
+            Amount: {amount}\n\
+            Rate: {rate} per {crypto}\n\
+            You'll receive: {naira}\n\n\
+            {eta}\n\n\
+            Type `confirm` to proceed or `cancel` to abort."
+        }
+        Msg::SummaryTitle => "📋 *Withdrawal Summary*\n\n💸 Amount: {amount}\n",
+        Msg::SummaryRate => "📈 Rate: {rate} per {crypto}\n",
+        Msg::SummaryReceive => "💰 You'll receive: {naira}\n",
+        Msg::SummaryBank => {
+            "\n🏦 Bank: {bank}\n\
+            👤 Account Name: {name}\n\
+            🔢 Account Number: {number}"
+        }
+        Msg::SavedBankPrompt => {
+            "{summary}\n\n\
+            Proceed with your saved account?\n\
+            Type `yes` to confirm or `no` to cancel."
+        }
+        Msg::BankDetailsRequired => {
+            "🏦 *Bank Details Required*\n\nPlease provide your bank details in this format:\n\n`Bank Name, Account Number`\n\n*Example:* `Opay, 0123456789`"
+        }
+        Msg::QuoteCancelled => {
+            "❌ *Withdrawal Cancelled*\n\nYour withdrawal request has been cancelled. Type `send [amount] [crypto] to [bank name]` to start again."
+        }
+        Msg::ConfirmOrCancel => "❓ Please type `confirm` to proceed or `cancel` to abort.",
+        Msg::AccountVerified => {
+            "✅ *Account Verified!*\n\n\
+            {summary}\n\n\
+            Is this correct?\n\
+            Type `yes` to confirm or `no` to re-enter."
+        }
+        Msg::WithdrawalCancelled => {
+            "❌ *Withdrawal Cancelled*\n\n\
+            Type `withdraw [amount] [crypto]` to start again."
+        }
+        Msg::YesOrNo => "❓ Please type `yes` to confirm or `no` to cancel.",
+        Msg::ReenterBankDetails => {
+            "🔄 *Please re-enter Bank Details*\n\nPlease provide your bank details in this format:\n\n`Bank Name, Account Number`\n\n*Example:* `Opay, 0123456789`"
+        }
+        Msg::YesOrReenter => "❓ Please type `yes` to confirm or `no` to re-enter.",
+        Msg::LargeWithdrawal => {
+            "🔐 *Large withdrawal*\n\n\
+            You're about to withdraw {amount} {crypto}.\n\n\
+            To confirm, please type *{amount}*\n\
+            or type `cancel` to stop."
+        }
+        Msg::AbsurdWithdrawal => {
+            "🚨 *Unusually large withdrawal*\n\n\
+            You're about to withdraw {amount} {crypto}: *{words}* {crypto}.\n\n\
+            If you meant a smaller amount, type `cancel` and start again.\n\
+            Otherwise, to confirm, type *{amount}*"
+        }
+        Msg::AmountMismatch => "❌ That doesn't match.\n\n{prompt}",
+        Msg::AmountMismatchCancelled => {
+            "❌ *Withdrawal Cancelled*\n\n\
+            The amount didn't match, so nothing was sent.\n\
+            Type `withdraw [amount] [crypto]` to start again."
+        }
+        Msg::WithdrawalInitiated => {
+            "✅ *Withdrawal Successfully Initiated!*\n\n\
+            We have successfully sent *{amount}* to your account:\n\n\
+            🏦 **Bank:** {bank}\n\
+            👤 **Name:** {name}\n\
+            🔢 **Ref:** {reference}\n\n\
+            {tx}\
+            {eta}\n\
+            You will get a confirmation message once transaction is completed."
+        }
+        Msg::WithdrawalCompleted => {
+            "✅ *Withdrawal Completed Successfully! 🎉*\n\n\
+            Funds deposited to your bank account:\n\n\
+            💰 *Amount:* {amount}\n\
+            🏦 *Bank:* {bank}\n\
+            👤 *Account Name:* {name}\n\n\
+            🔢 *Reference:* {reference}\n\n\
+            {tx}\
+            ⏱️ *Withdrawal processed in:* {duration}\n\n\
+            📅 *Completed at:* {completed_at}\n\n\
+            Thank you for using KharonPay!"
+        }
+        Msg::WithdrawalFailed => {
+            "❌ *Withdrawal Failed*\n\n\
+            Unfortunately, your withdrawal could not be completed.\n\n\
+            🔢 **Reference:** {reference}\n\
+            📅 **Status:** {status}\n\n\
+            Type `support` and our team will look into it."
+        }
+        Msg::ChooseBank => "🏦 *Choose an account*\n\nWhich saved account should receive {amount}?",
+        Msg::ChooseBankReply => "Reply with the number of the account, or `no` to cancel.",
+        Msg::NotificationsOn => {
+            "🔔 *Notifications are on.*\n\nWe'll remind you about unfinished withdrawals. Type `notifications off` to stop."
+        }
+        Msg::NotificationsOff => {
+            "🔕 *Notifications are off.*\n\nYou'll still get withdrawal updates, but no reminders. Type `notifications on` to turn them back on."
+        }
+        Msg::RatingRequest => {
+            "⭐ How was this withdrawal?\n\nReply with a number from 1 (poor) to 5 (great)."
+        }
+        Msg::RatingThanks => "🙏 Thanks for the feedback!",
+        Msg::SandboxJoined => {
+            "✅ You're connected to *Kharon Pay*.\n\nType `create` to set up your wallet, or `help` to see everything you can do."
+        }
+        Msg::OptedOut => {
+            "🔕 You've unsubscribed and won't get any more messages from us.\n\nReply START any time to come back."
+        }
+        Msg::OptedIn => "🔔 You're subscribed again.",
+        Msg::SettlementDelayed => {
+            "⏳ *Settlement delayed*\n\n\
+            Your withdrawal {reference} is taking longer than usual to reach your bank. \
+            We expect it by {eta}.\n\n\
+            There's nothing you need to do; we'll message you once it arrives."
+        }
+        Msg::AccountReview => {
+            "🔎 *Account review*\n\n\
+            Your account is under a routine compliance review, so withdrawals are paused \
+            until {until}.\n\n\
+            Type `support` if you have any questions."
+        }
+        Msg::ArrivalEstimate => {
+            "⏱️ Withdrawals are typically arriving in {typical} right now (most within {most})."
+        }
+        Msg::ArrivalEstimateToBank => {
+            "⏱️ Typically {typical} to {bank} right now (most within {most})."
+        }
+        Msg::ArrivalUsual => "⏱️ The funds should reflect in your account shortly.",
+    }
+}
+
+/// Only the entry points are translated so far; the withdrawal flow stays in English.
+fn french(msg: Msg) -> Option<&'static str> {
+    match msg {
+        Msg::Welcome => Some(
+            "🟢 Bienvenue sur *Kharon Pay* ! 💰\n\nEnvoyez vos cryptos vers votre banque en quelques secondes.\n\n📱 *Commandes :*\n{commands}\n\n🔕 Trop de messages ? Tapez `notifications off` pour arrêter les rappels.\n\nLes commandes restent en anglais. Tapez `language english` pour passer à l'anglais.\n\nQue souhaitez-vous faire ?",
+        ),
+        Msg::Help => {
+            Some("🔰 *Aide Kharon Pay*\n\n*Commandes :*\n{commands}\n\n*Exemples :*\n{examples}")
+        }
+        Msg::LanguageSet => Some("✅ Langue définie : français."),
+        _ => None,
+    }
+}
+
+/// Only the entry points are translated so far; the withdrawal flow stays in English.
+fn swahili(msg: Msg) -> Option<&'static str> {
+    match msg {
+        Msg::Welcome => Some(
+            "🟢 Karibu *Kharon Pay*! 💰\n\nTuma crypto kwenye benki yako kwa sekunde chache.\n\n📱 *Amri:*\n{commands}\n\n🔕 Andika `notifications off` kusimamisha vikumbusho.\n\nAmri zinabaki kwa Kiingereza. Andika `language english` kubadili kwenda Kiingereza.\n\nUngependa kufanya nini?",
+        ),
+        Msg::Help => {
+            Some("🔰 *Msaada wa Kharon Pay*\n\n*Amri:*\n{commands}\n\n*Mifano:*\n{examples}")
+        }
+        Msg::LanguageSet => Some("✅ Lugha imewekwa: Kiswahili."),
+        _ => None,
+    }
+}
+
+fn pidgin(msg: Msg) -> Option<&'static str> {
+    Some(match msg {
+        Msg::Welcome => {
+            "🟢 Welcome to *Kharon Pay*! 💰\n\nSend your crypto enter your bank sharp sharp.\n\n📱 *Wetin you fit do:*\n{commands}\n\n🔕 Message too much? Type `notifications off` make reminders stop.\n\nWetin you wan do?"
+        }
+        Msg::Help => "🔰 *Kharon Pay Help*\n\n*Commands:*\n{commands}\n\n*Examples:*\n{examples}",
+        Msg::LanguageSet => "✅ Oya, from now we go dey yarn you for Pidgin.",
+        Msg::WithdrawRequest => {
+            "💸 *Withdraw Request*\n\n\
+            Amount: {amount}\n\
+            Rate: {rate} per {crypto}\n\
+            You go collect: {naira}\n\n\
+            {eta}\n\n\
+            Type `confirm` make we continue or `cancel` to stop am."
+        }
+        Msg::SummaryTitle => "📋 *Withdrawal Summary*\n\n💸 Amount: {amount}\n",
+        Msg::SummaryRate => "📈 Rate: {rate} per {crypto}\n",
+        Msg::SummaryReceive => "💰 You go collect: {naira}\n",
+        Msg::SummaryBank => {
+            "\n🏦 Bank: {bank}\n\
+            👤 Account Name: {name}\n\
+            🔢 Account Number: {number}"
+        }
+        Msg::SavedBankPrompt => {
+            "{summary}\n\n\
+            Make we use this your saved account?\n\
+            Type `yes` if na so or `no` to cancel."
+        }
+        Msg::BankDetailsRequired => {
+            "🏦 *We Need Your Bank Details*\n\nAbeg send your bank details like this:\n\n`Bank Name, Account Number`\n\n*Example:* `Opay, 0123456789`"
+        }
+        Msg::QuoteCancelled => {
+            "❌ *Withdrawal Don Cancel*\n\nWe don cancel your withdrawal. Type `send [amount] [crypto] to [bank name]` if you wan start again."
+        }
+        Msg::ConfirmOrCancel => "❓ Abeg type `confirm` make we continue or `cancel` to stop am.",
+        Msg::AccountVerified => {
+            "✅ *We Don Check The Account!*\n\n\
+            {summary}\n\n\
+            E correct so?\n\
+            Type `yes` if na so or `no` to enter am again."
+        }
+        Msg::WithdrawalCancelled => {
+            "❌ *Withdrawal Don Cancel*\n\n\
+            Type `withdraw [amount] [crypto]` if you wan start again."
+        }
+        Msg::YesOrNo => "❓ Abeg type `yes` to confirm or `no` to cancel.",
+        Msg::ReenterBankDetails => {
+            "🔄 *Enter Your Bank Details Again*\n\nAbeg send your bank details like this:\n\n`Bank Name, Account Number`\n\n*Example:* `Opay, 0123456789`"
+        }
+        Msg::YesOrReenter => "❓ Abeg type `yes` to confirm or `no` to enter am again.",
+        Msg::LargeWithdrawal => {
+            "🔐 *Big withdrawal*\n\n\
+            You wan withdraw {amount} {crypto}.\n\n\
+            To confirm am, abeg type *{amount}*\n\
+            or type `cancel` to stop."
+        }
+        Msg::AbsurdWithdrawal => {
+            "🚨 *This withdrawal big well well*\n\n\
+            You wan withdraw {amount} {crypto}: *{words}* {crypto}.\n\n\
+            If na smaller amount you mean, type `cancel` make you start again.\n\
+            If na true, type *{amount}* to confirm am."
+        }
+        Msg::AmountMismatch => "❌ E no match.\n\n{prompt}",
+        Msg::AmountMismatchCancelled => {
+            "❌ *Withdrawal Don Cancel*\n\n\
+            The amount no match, so we no send anything.\n\
+            Type `withdraw [amount] [crypto]` if you wan start again."
+        }
+        Msg::WithdrawalInitiated => {
+            "✅ *Withdrawal Don Start!*\n\n\
+            We don send *{amount}* to your account:\n\n\
+            🏦 **Bank:** {bank}\n\
+            👤 **Name:** {name}\n\
+            🔢 **Ref:** {reference}\n\n\
+            {tx}\
+            {eta}\n\
+            We go message you once e land."
+        }
+        Msg::WithdrawalCompleted => {
+            "✅ *Your Money Don Land! 🎉*\n\n\
+            We don put the money for your bank account:\n\n\
+            💰 *Amount:* {amount}\n\
+            🏦 *Bank:* {bank}\n\
+            👤 *Account Name:* {name}\n\n\
+            🔢 *Reference:* {reference}\n\n\
+            {tx}\
+            ⏱️ *E take:* {duration}\n\n\
+            📅 *E land for:* {completed_at}\n\n\
+            Thank you say you use KharonPay!"
+        }
+        Msg::WithdrawalFailed => {
+            "❌ *Withdrawal No Go Through*\n\n\
+            Sorry, your withdrawal no fit complete.\n\n\
+            🔢 **Reference:** {reference}\n\
+            📅 **Status:** {status}\n\n\
+            Type `support` make our team help you."
+        }
+        Msg::ChooseBank => {
+            "🏦 *Choose Account*\n\nWhich of your saved account we go send {amount} enter?"
+        }
+        Msg::ChooseBankReply => {
+            "Reply with the number wey dey front of the account, or `no` to cancel."
+        }
+        Msg::NotificationsOn => {
+            "🔔 *Notifications don on.*\n\nWe go remind you if you no finish withdrawal. Type `notifications off` to stop am."
+        }
+        Msg::NotificationsOff => {
+            "🔕 *Notifications don off.*\n\nWe go still tell you how your withdrawal dey go, but no more reminders. Type `notifications on` to on am back."
+        }
+        Msg::RatingRequest => {
+            "⭐ How this withdrawal take be?\n\nReply with number from 1 (e no good) to 5 (e sweet well well)."
+        }
+        Msg::RatingThanks => "🙏 Thank you for the feedback!",
+        Msg::SandboxJoined => {
+            "✅ You don connect to *Kharon Pay*.\n\nType `create` to set up your wallet, or `help` to see everything wey you fit do."
+        }
+        Msg::OptedOut => {
+            "🔕 You don unsubscribe. We no go send you any message again.\n\nReply START anytime wey you wan come back."
+        }
+        Msg::OptedIn => "🔔 You don subscribe again.",
+        Msg::SettlementDelayed => {
+            "⏳ *Settlement Dey Delay*\n\n\
+            Your withdrawal {reference} dey take time pass normal to reach your bank. \
+            E suppose land by {eta}.\n\n\
+            You no need do anything; we go message you once e land."
+        }
+        Msg::AccountReview => {
+            "🔎 *Account Review*\n\n\
+            We dey do normal compliance check for your account, so withdrawal go pause \
+            until {until}.\n\n\
+            Type `support` if you get any question."
+        }
+        Msg::ArrivalEstimate => {
+            "⏱️ Right now, withdrawal dey usually land for {typical} (most of dem within {most})."
+        }
+        Msg::ArrivalEstimateToBank => {
+            "⏱️ Right now, e dey usually take {typical} to reach {bank} (most within {most})."
+        }
+        Msg::ArrivalUsual => "⏱️ The money go enter your account soon.",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn greetings_are_answered_in_their_own_language() {
+        for french in [
+            "Bonjour",
+            "salut !",
+            "Bonsoir, ça va ?",
+            "je veux retirer mon argent",
+        ] {
+            assert_eq!(detect(french), Some(Language::Fr), "{}", french);
+        }
+        for swahili in [
+            "Habari",
+            "jambo",
+            "Mambo vipi",
+            "Habari yako, nataka kutoa pesa",
+        ] {
+            assert_eq!(detect(swahili), Some(Language::Sw), "{}", swahili);
+        }
+    }
+
+    #[test]
+    fn english_pidgin_and_mixed_text_default_to_english() {
+        for unsure in [
+            "hi",
+            "Hello there",
+            "how far",
+            "bonjour habari",
+            "what is this?",
+            "",
+            "👋",
+        ] {
+            assert_eq!(detect(unsure), None, "{:?}", unsure);
+        }
+    }
+
+    #[test]
+    fn commands_never_switch_the_language() {
+        for command in [
+            "balance",
+            "withdraw 5 usdt",
+            "help",
+            "status KP-123",
+            "language fr",
+            "quote 10 USDT",
+        ] {
+            assert_eq!(detect(command), None, "{}", command);
+        }
+    }
+}
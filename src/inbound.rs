@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::audit::{self, AuditEvent};
+use crate::privacy::mask_phone;
+
+/// Delivery updates for messages we sent. Earlier ones (`queued`, `accepted`...) are
+/// acknowledged too but not recorded, since the message log keeps only the latest status.
+const DELIVERY_STATUSES: &[&str] = &["delivered", "read", "sent", "failed", "undelivered"];
+const EARLY_STATUSES: &[&str] = &["queued", "accepted", "sending", "scheduled", "canceled"];
+
+/// Fields whose values say what kind of payload this is without saying anything about
+/// the person; only these are logged alongside the field names.
+const DISTINGUISHING_FIELDS: &[&str] = &[
+    "MessageType",
+    "EventType",
+    "SmsStatus",
+    "MessageStatus",
+    "CallStatus",
+    "OptOutType",
+    "NumMedia",
+    "ApiVersion",
+];
+
+/// What a Twilio webhook is, worked out before any session is touched.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inbound {
+    /// Something the user typed.
+    Text { phone: String, body: String },
+    /// A tap on a list row or button, mapped back to what they'd have typed.
+    Interactive { phone: String, body: String },
+    /// An image, voice note or document with no caption.
+    Media { phone: String },
+    /// A delivery update for one of our messages. `record` is false for the early
+    /// statuses, which would overwrite a later one if they arrived out of order.
+    StatusCallback {
+        message_sid: Option<String>,
+        status: String,
+        record: bool,
+    },
+    /// Twilio's Advanced Opt-Out has already answered a STOP or START.
+    OptOut { phone: String, opted_out: bool },
+    /// Anything else: call events, reactions, locations, fields Twilio adds later.
+    Unknown(UnknownCallback),
+}
+
+impl Inbound {
+    /// The classification alone, for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Inbound::Text { .. } => "text",
+            Inbound::Interactive { .. } => "interactive",
+            Inbound::Media { .. } => "media",
+            Inbound::StatusCallback { .. } => "status_callback",
+            Inbound::OptOut { .. } => "opt_out_event",
+            Inbound::Unknown(_) => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownCallback {
+    pub phone: Option<String>,
+    /// Every field name in the payload, sorted.
+    pub fields: Vec<String>,
+    /// Values of the `DISTINGUISHING_FIELDS` that were present.
+    pub hints: Vec<(String, String)>,
+}
+
+impl UnknownCallback {
+    fn from_form(form_data: &HashMap<String, String>) -> Self {
+        let mut fields: Vec<String> = form_data.keys().cloned().collect();
+        fields.sort();
+        let hints = DISTINGUISHING_FIELDS
+            .iter()
+            .filter_map(|field| {
+                let value = form_data.get(*field).filter(|v| !v.is_empty())?;
+                Some((field.to_string(), value.clone()))
+            })
+            .collect();
+        UnknownCallback {
+            phone: sender(form_data),
+            fields,
+            hints,
+        }
+    }
+}
+
+fn sender(form_data: &HashMap<String, String>) -> Option<String> {
+    form_data
+        .get("From")
+        .filter(|from| !from.is_empty())
+        .map(|from| from.replace("whatsapp:", ""))
+}
+
+/// Taps on list pickers and buttons arrive as ids rather than typed text; this maps them
+/// back to what the user would have typed. Bank rows (`bank_2`) become their number and
+/// button payloads are used as typed.
+fn interactive_reply(form_data: &HashMap<String, String>) -> Option<String> {
+    if let Some(payload) = form_data.get("ButtonPayload").filter(|p| !p.is_empty()) {
+        return Some(payload.clone());
+    }
+    let list_id = form_data.get("ListId").filter(|id| !id.is_empty())?;
+    Some(list_id.strip_prefix("bank_").unwrap_or(list_id).to_string())
+}
+
+pub fn classify(form_data: &HashMap<String, String>) -> Inbound {
+    let field = |name: &str| form_data.get(name).map(String::as_str).unwrap_or("");
+
+    // Voice calls to the number share the webhook but have nothing for us
+    if form_data.contains_key("CallSid") || form_data.contains_key("CallStatus") {
+        return Inbound::Unknown(UnknownCallback::from_form(form_data));
+    }
+
+    // Inbound messages carry `SmsStatus=received` too, so only other statuses count
+    let status = form_data
+        .get("MessageStatus")
+        .or(form_data.get("SmsStatus"))
+        .map(|s| s.to_lowercase());
+    if let Some(status) = status.filter(|s| s != "received" && s != "receiving") {
+        if DELIVERY_STATUSES.contains(&status.as_str()) || EARLY_STATUSES.contains(&status.as_str())
+        {
+            return Inbound::StatusCallback {
+                message_sid: form_data.get("MessageSid").cloned(),
+                record: DELIVERY_STATUSES.contains(&status.as_str()),
+                status,
+            };
+        }
+        return Inbound::Unknown(UnknownCallback::from_form(form_data));
+    }
+
+    let Some(phone) = sender(form_data) else {
+        return Inbound::Unknown(UnknownCallback::from_form(form_data));
+    };
+
+    match field("OptOutType").to_uppercase().as_str() {
+        "STOP" => {
+            return Inbound::OptOut {
+                phone,
+                opted_out: true,
+            };
+        }
+        "START" => {
+            return Inbound::OptOut {
+                phone,
+                opted_out: false,
+            };
+        }
+        // HELP is left to our own help reply
+        _ => {}
+    }
+
+    if let Some(body) = interactive_reply(form_data) {
+        return Inbound::Interactive { phone, body };
+    }
+    let body = field("Body");
+    if !body.trim().is_empty() {
+        return Inbound::Text {
+            phone,
+            body: body.to_string(),
+        };
+    }
+    let has_media = field("NumMedia").parse::<u32>().is_ok_and(|n| n > 0);
+    if has_media {
+        return Inbound::Media { phone };
+    }
+    Inbound::Unknown(UnknownCallback::from_form(form_data))
+}
+
+/// When each inbound `MessageSid` was first handled, so a Twilio retry isn't handled twice.
+static SEEN_MESSAGES: LazyLock<Mutex<HashMap<String, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// `INBOUND_DEDUPE_SECS`, default 10 min: how long a `MessageSid` is remembered. Twilio
+/// retries a webhook that timed out within seconds, not minutes.
+fn dedupe_window() -> Duration {
+    Duration::from_secs(
+        std::env::var("INBOUND_DEDUPE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10 * 60),
+    )
+}
+
+/// False for a message we've already handled: Twilio posts it again when our answer was
+/// slow, and a second `confirm` must not confirm twice. Payloads without a `MessageSid`
+/// are always new.
+pub fn first_delivery(form_data: &HashMap<String, String>) -> bool {
+    let Some(message_sid) = form_data.get("MessageSid").filter(|sid| !sid.is_empty()) else {
+        return true;
+    };
+    let now = Instant::now();
+    let mut seen = SEEN_MESSAGES.lock().unwrap_or_else(|e| e.into_inner());
+    seen.retain(|_, at| now.duration_since(*at) < dedupe_window());
+    seen.insert(message_sid.clone(), now).is_none()
+}
+
+/// Logs a payload we don't recognise, with enough of its shape to tell what it was, and
+/// audits it so the daily digest shows when a new kind starts arriving.
+pub fn report_unknown(unknown: &UnknownCallback) {
+    let hints: serde_json::Map<String, serde_json::Value> = unknown
+        .hints
+        .iter()
+        .map(|(field, value)| (field.clone(), value.clone().into()))
+        .collect();
+    let json = serde_json::json!({
+        "phone": unknown.phone.as_deref().map(mask_phone),
+        "fields": unknown.fields,
+        "hints": hints,
+    });
+    eprintln!("WARN: Unrecognized Twilio callback: {}", json);
+    audit::record(
+        unknown.phone.as_deref().unwrap_or_default(),
+        None,
+        None,
+        AuditEvent::UnrecognizedCallback {
+            fields: unknown.fields.clone(),
+            message_type: unknown
+                .hints
+                .iter()
+                .find(|(field, _)| field == "MessageType" || field == "EventType")
+                .map(|(_, value)| value.clone()),
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn a_bank_row_tap_reads_as_its_number() {
+        for (list_id, typed) in [
+            ("bank_1", "1"),
+            ("bank_10", "10"),
+            ("other_row", "other_row"),
+        ] {
+            let tap = form(&[("ListId", list_id), ("Body", "Opay ******6789")]);
+            assert_eq!(
+                interactive_reply(&tap).as_deref(),
+                Some(typed),
+                "{}",
+                list_id
+            );
+        }
+    }
+
+    #[test]
+    fn a_button_tap_reads_as_its_payload() {
+        let tap = form(&[("ButtonPayload", "confirm"), ("Body", "✅ Confirm")]);
+        assert_eq!(interactive_reply(&tap).as_deref(), Some("confirm"));
+    }
+
+    #[test]
+    fn typed_text_is_not_a_tap() {
+        for fields in [
+            &[("Body", "2")][..],
+            &[("ListId", ""), ("ButtonPayload", ""), ("Body", "2")][..],
+        ] {
+            assert_eq!(interactive_reply(&form(fields)), None, "{:?}", fields);
+        }
+    }
+}
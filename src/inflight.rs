@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::OwnedMutexGuard;
+
+/// Backend operations that must not run twice at once for the same user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    AccountCreation,
+    Offramp,
+}
+
+static IN_FLIGHT: LazyLock<Mutex<HashMap<(String, Operation), Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// `IN_FLIGHT_TIMEOUT_SECS` (default 5 min): after this an entry is treated as abandoned,
+/// in case a handler hung without ever dropping its guard.
+fn timeout() -> Duration {
+    Duration::from_secs(
+        std::env::var("IN_FLIGHT_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5 * 60),
+    )
+}
+
+/// Held for as long as the operation runs; dropping it, on success, error or panic,
+/// lets the next attempt through.
+pub struct InFlightGuard {
+    key: (String, Operation),
+    started: Instant,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+        // A timed-out entry may already belong to a newer attempt
+        if in_flight.get(&self.key) == Some(&self.started) {
+            in_flight.remove(&self.key);
+        }
+    }
+}
+
+/// Claims `operation` for `phone`, or `None` while another attempt still holds it.
+pub fn begin(phone: &str, operation: Operation) -> Option<InFlightGuard> {
+    let key = (phone.to_string(), operation);
+    let now = Instant::now();
+    let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+
+    if let Some(started) = in_flight.get(&key)
+        && now.duration_since(*started) < timeout()
+    {
+        return None;
+    }
+
+    in_flight.insert(key.clone(), now);
+    Some(InFlightGuard { key, started: now })
+}
+
+pub const STILL_WORKING_MESSAGE: &str =
+    "⏳ One moment, still working on your last request. I'll reply as soon as it's done.";
+
+/// One turn per user (digits only) at handling inbound messages. Like `outbound::lane`,
+/// tokio's mutex is FIFO, so messages sent in quick succession are handled in order, each
+/// against the session the previous one saved.
+static TURNS: LazyLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// `MAX_QUEUED_MESSAGES` (default 2): how many messages may wait behind the one being
+/// handled before further ones are answered with `STILL_WORKING_MESSAGE` instead.
+fn max_queued() -> usize {
+    std::env::var("MAX_QUEUED_MESSAGES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2)
+}
+
+/// Waits for `phone`'s turn to handle a message. `None` when the queue is already full,
+/// or the turn didn't come within `timeout()`, so a hung handler can't hold up every
+/// message after it. Take this before the user's outbound lane, never while holding it.
+pub async fn conversation_turn(phone: &str) -> Option<OwnedMutexGuard<()>> {
+    let key: String = phone.chars().filter(char::is_ascii_digit).collect();
+    let turn = {
+        let mut turns = TURNS.lock().unwrap_or_else(|e| e.into_inner());
+        // Turns nobody is holding or waiting on are only the map's copy
+        turns.retain(|_, turn| Arc::strong_count(turn) > 1);
+        let turn = turns.entry(key).or_default();
+        // Everyone holding or waiting, less the map's copy and the one being handled
+        let queued = Arc::strong_count(turn).saturating_sub(2);
+        if queued >= max_queued() {
+            return None;
+        }
+        turn.clone()
+    };
+    tokio::time::timeout(timeout(), turn.lock_owned())
+        .await
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lets every spawned task run up to its next await on the current-thread runtime.
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[test]
+    fn an_operation_runs_once_at_a_time_per_user() {
+        let guard = begin("+2348090000001", Operation::Offramp).expect("first attempt");
+        assert!(begin("+2348090000001", Operation::Offramp).is_none());
+        assert!(begin("+2348090000001", Operation::AccountCreation).is_some());
+        assert!(begin("+2348090000002", Operation::Offramp).is_some());
+
+        drop(guard);
+        assert!(begin("+2348090000001", Operation::Offramp).is_some());
+    }
+
+    #[tokio::test]
+    async fn a_users_messages_take_their_turns_in_arrival_order() {
+        let first = conversation_turn("+2348090000003")
+            .await
+            .expect("free turn");
+        let handled = Arc::new(Mutex::new(Vec::new()));
+
+        let mut waiting = Vec::new();
+        // The same number however Twilio formats it
+        for (i, phone) in ["whatsapp:+2348090000003", "+234 809 000 0003"]
+            .into_iter()
+            .enumerate()
+        {
+            let handled = handled.clone();
+            waiting.push(tokio::spawn(async move {
+                let _turn = conversation_turn(phone).await.expect("queued");
+                handled.lock().unwrap().push(i);
+            }));
+            settle().await;
+        }
+
+        // Someone else's messages don't wait behind them
+        assert!(conversation_turn("+2348090000004").await.is_some());
+        assert!(handled.lock().unwrap().is_empty());
+
+        drop(first);
+        for task in waiting {
+            task.await.unwrap();
+        }
+        assert_eq!(*handled.lock().unwrap(), [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn messages_beyond_the_queue_are_turned_away() {
+        let first = conversation_turn("+2348090000005")
+            .await
+            .expect("free turn");
+        let waiting: Vec<_> = (0..max_queued())
+            .map(|_| tokio::spawn(conversation_turn("+2348090000005")))
+            .collect();
+        settle().await;
+
+        assert!(conversation_turn("+2348090000005").await.is_none());
+
+        drop(first);
+        for task in waiting {
+            assert!(task.await.unwrap().is_some());
+        }
+        assert!(conversation_turn("+2348090000005").await.is_some());
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use crate::i18n::{self, Msg};
+use crate::outbound::{self, OutboundMessage};
+use crate::prefs;
+use crate::privacy::mask_phone;
+use crate::store::{self, SessionStore};
+
+/// Messages that belong to the channel rather than to us. They're answered before the
+/// session is loaded, so they never reach a flow (a sandbox code is not a username).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelKeyword {
+    /// `join <word>-<word>`, sent to the Twilio sandbox number to connect to it.
+    SandboxJoin,
+    /// STOP, STOPALL, UNSUBSCRIBE. Twilio's CANCEL, END and QUIT are left out: `cancel`
+    /// already means something in our flows.
+    OptOut,
+    /// START, UNSTOP.
+    OptIn,
+}
+
+/// Only the whole message counts: "stop the withdrawal" is not an opt-out.
+pub fn detect(body: &str) -> Option<ChannelKeyword> {
+    let words: Vec<String> = body
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect();
+    match words.as_slice() {
+        [word] => match word.trim_matches(|c: char| !c.is_alphanumeric()) {
+            "stop" | "stopall" | "unsubscribe" => Some(ChannelKeyword::OptOut),
+            "start" | "unstop" => Some(ChannelKeyword::OptIn),
+            _ => None,
+        },
+        [join, code] if join == "join" && is_sandbox_code(code) => {
+            Some(ChannelKeyword::SandboxJoin)
+        }
+        _ => None,
+    }
+}
+
+/// Sandbox codes are two or more words joined by hyphens, like `happy-tiger`.
+fn is_sandbox_code(code: &str) -> bool {
+    let parts: Vec<&str> = code.split('-').collect();
+    parts.len() >= 2
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Whether `phone` sent STOP and hasn't sent START since. Nothing goes out to them.
+pub async fn opted_out(phone: &str) -> bool {
+    prefs::load(phone).await.opted_out
+}
+
+/// Records a STOP or START that Twilio's Advanced Opt-Out has already answered, so
+/// nothing is sent back: after a STOP it wouldn't be delivered, and after a START the
+/// user already has Twilio's confirmation.
+pub async fn record_opt_out_event(phone: &str, opted_out: bool) {
+    let changed = prefs::update(phone, move |prefs| {
+        std::mem::replace(&mut prefs.opted_out, opted_out) != opted_out
+    })
+    .await;
+    match changed {
+        Ok(false) => {}
+        Ok(true) if opted_out => println!("{} opted out of messages via Twilio", mask_phone(phone)),
+        Ok(true) => println!("{} opted back in to messages via Twilio", mask_phone(phone)),
+        Err(e) => eprintln!(
+            "ALERT: Failed to record opt-out change for {}: {}",
+            mask_phone(phone),
+            e
+        ),
+    }
+}
+
+/// Whether `phone` has an account, so START welcomes them with the right menu. A session
+/// that can't be read counts as none; the newcomer menu still works for them.
+async fn has_account(phone: &str, sessions: Arc<dyn SessionStore>) -> bool {
+    let key = phone.to_string();
+    match store::blocking(move || sessions.load(&key)).await {
+        Ok(session) => session.is_some_and(|session| session.controller_address.is_some()),
+        Err(e) => {
+            eprintln!("Failed to load session for {}: {}", mask_phone(phone), e);
+            false
+        }
+    }
+}
+
+/// Answers a channel keyword. The opt-out confirmation is sent before the flag is set,
+/// since nothing can go out after it; the opt-in clears the flag before replying.
+pub async fn handle(phone: &str, keyword: ChannelKeyword, sessions: Arc<dyn SessionStore>) {
+    let prefs = prefs::load(phone).await;
+    let language = prefs.language.unwrap_or_default();
+    let has_account = keyword == ChannelKeyword::OptIn && has_account(phone, sessions).await;
+
+    let messages: Vec<OutboundMessage> = match keyword {
+        ChannelKeyword::SandboxJoin => vec![i18n::text(language, Msg::SandboxJoined).into()],
+        ChannelKeyword::OptOut if prefs.opted_out => return,
+        ChannelKeyword::OptOut => vec![i18n::text(language, Msg::OptedOut).into()],
+        ChannelKeyword::OptIn if prefs.opted_out => vec![
+            i18n::text(language, Msg::OptedIn).into(),
+            i18n::welcome(language, has_account, phone).into(),
+        ],
+        ChannelKeyword::OptIn => vec![i18n::welcome(language, has_account, phone).into()],
+    };
+
+    if keyword == ChannelKeyword::OptIn && prefs.opted_out {
+        if let Err(e) = prefs::update(phone, |prefs| prefs.opted_out = false).await {
+            eprintln!("Failed to record opt-in for {}: {}", mask_phone(phone), e);
+            return;
+        }
+        println!("{} opted back in to messages", mask_phone(phone));
+    }
+
+    let messages = if prefs.plain_text {
+        messages.into_iter().map(OutboundMessage::plain).collect()
+    } else {
+        messages
+    };
+    outbound::send_sequence(phone, &messages).await;
+
+    if keyword == ChannelKeyword::OptOut {
+        if let Err(e) = prefs::update(phone, |prefs| prefs.opted_out = true).await {
+            eprintln!(
+                "ALERT: Failed to record opt-out for {}: {}",
+                mask_phone(phone),
+                e
+            );
+            return;
+        }
+        println!("{} opted out of messages", mask_phone(phone));
+    }
+}
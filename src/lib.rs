@@ -0,0 +1,62 @@
+//! The Kharon Pay WhatsApp bot. `main` wires it into a server; tests, benches and the
+//! fuzz targets reach the same modules through here.
+
+pub mod access;
+pub mod access_log;
+pub mod admin;
+pub mod alerts;
+pub mod audit;
+pub mod backend;
+pub mod build_info;
+pub mod cache;
+pub mod callbacks;
+pub mod cli;
+pub mod config;
+pub mod conversation;
+pub mod deadline;
+pub mod eta;
+pub mod export;
+pub mod features;
+pub mod feedback;
+pub mod i18n;
+pub mod inbound;
+pub mod inflight;
+pub mod keywords;
+pub mod message_log;
+pub mod metrics;
+pub mod mock_backend;
+pub mod model;
+pub mod money;
+pub mod namecheck;
+pub mod notify;
+pub mod onboarding;
+pub mod outbound;
+pub mod parser;
+pub mod polling;
+pub mod prefs;
+pub mod privacy;
+pub mod proxy;
+pub mod readiness;
+pub mod reconcile;
+pub mod recording;
+pub mod region;
+pub mod reminder;
+pub mod reporting;
+pub mod request_body;
+pub mod seal;
+pub mod selftest;
+pub mod server;
+pub mod simulator;
+pub mod starknet;
+pub mod state;
+pub mod status_batch;
+pub mod store;
+pub mod summary;
+pub mod supervisor;
+pub mod support;
+pub mod throttle;
+pub mod tls;
+pub mod transactions;
+pub mod twilio;
+pub mod webhook;
+pub mod window;
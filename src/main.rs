@@ -1,47 +1,86 @@
-use actix_web::{App, HttpResponse, HttpServer, middleware::Logger, web};
-use std::collections::HashMap;
+use actix_web::{App, HttpServer, middleware::from_fn, web};
 
-use crate::server::{SessionMap, handle_twilio_webhook, health_check};
-
-mod model;
-mod server;
+use kharon_pay_whatsapp::cli::{self, Command};
+use kharon_pay_whatsapp::{
+    access_log, build_info, config, deadline, mock_backend, proxy, readiness, reporting,
+    request_body, selftest, server, simulator, store, tls,
+};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    build_info::mark_started();
     dotenv::dotenv().ok();
+    if mock_backend::enabled() {
+        mock_backend::configure_env();
+    }
+    if simulator::enabled() {
+        println!("🧪 DEBUG_SIMULATOR is on: /debug/simulate runs messages without WhatsApp");
+    }
     env_logger::init();
+    reporting::init().map_err(std::io::Error::other)?;
+
+    let command = cli::parse(std::env::args()).unwrap_or_else(|e| e.exit());
 
-    let _account_sid =
-        std::env::var("T_ACCOUNT_SID").expect("T_ACCOUNT_SID must be set in .env file");
-    let _auth_token =
-        std::env::var("T_AUTH_TOKEN").expect("T_AUTH_TOKEN must be set in .env file");
-    let _whatsapp_number = std::env::var("T_WHATSAPP_NUMBER")
-        .expect("T_WHATSAPP_NUMBER must be set in .env file");
-    let _api_url = std::env::var("T_API_URL").expect("T_API_URL must be set in .env file");
+    config::load_remote_secrets()
+        .await
+        .map_err(std::io::Error::other)?;
 
-    let sessions: web::Data<std::sync::Mutex<SessionMap>> =
-        web::Data::new(std::sync::Mutex::new(HashMap::new()));
+    match command {
+        Command::CheckConfig => std::process::exit(cli::check_config()),
+        Command::Send { to, message } => std::process::exit(cli::send(&to, &message).await),
+        Command::Session { phone } => std::process::exit(cli::session(&phone)),
+        Command::Serve => {}
+    }
 
-    println!("🚀 Kharon Pay WhatsApp Server starting on port 6500");
-    
-    HttpServer::new(move || {
+    let problems = cli::problems();
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("Config error: {}", problem);
+        }
+        return Err(std::io::Error::other(
+            "invalid configuration; run `check-config` for a summary",
+        ));
+    }
+    let tls_paths = tls::from_env().map_err(std::io::Error::other)?;
+    if let Some(paths) = &tls_paths {
+        tls::init(paths).map_err(std::io::Error::other)?;
+    }
+    let session_store = store::from_env().map_err(std::io::Error::other)?;
+    selftest::run(session_store.as_ref())
+        .await
+        .map_err(std::io::Error::other)?;
+    server::start_background_tasks(session_store.clone());
+    let sessions: web::Data<dyn store::SessionStore> = web::Data::from(session_store);
+
+    let make_app = move || {
         App::new()
             .app_data(sessions.clone())
-            .wrap(Logger::default())
-            .route("/webhook", web::post().to(handle_twilio_webhook))
-            .route("/health", web::get().to(health_check))
-            .route(
-                "/",
-                web::get().to(|| async {
-                    HttpResponse::Ok().json(serde_json::json!({
-                        "message": "Kharon Pay WhatsApp Bot API",
-                        "status": "running",
-                        "webhook": "/webhook",
-                    }))
-                }),
-            )
-    })
-    .bind("0.0.0.0:6500")?
-    .run()
-    .await
+            .app_data(request_body::payload_config())
+            .wrap(from_fn(deadline::limit))
+            .wrap(from_fn(proxy::attach_client))
+            .wrap(from_fn(access_log::log))
+            .configure(server::routes)
+    };
+
+    let server = if let Some(paths) = tls_paths {
+        tls::start_reload_task(paths);
+        println!(
+            "🚀 Kharon Pay WhatsApp Server {} starting on port 6500 (HTTPS); flags: {}",
+            build_info::describe(),
+            serde_json::json!(build_info::flags())
+        );
+        tls::serve(make_app, std::net::TcpListener::bind("0.0.0.0:6500")?)?
+    } else {
+        println!(
+            "🚀 Kharon Pay WhatsApp Server {} starting on port 6500; flags: {}",
+            build_info::describe(),
+            serde_json::json!(build_info::flags())
+        );
+        HttpServer::new(make_app)
+            .bind("0.0.0.0:6500")?
+            .disable_signals()
+            .run()
+    };
+    readiness::drain_on_shutdown(server.handle());
+    server.await
 }
@@ -0,0 +1,339 @@
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+
+use crate::privacy::{hash_phone, phone_for_storage};
+use crate::supervisor;
+
+/// One message we sent, as support sees it. The body itself isn't kept; its hash is
+/// enough to prove which text went out.
+#[derive(Debug, Clone, Serialize)]
+pub struct LoggedMessage {
+    pub phone: String,
+    pub body_hash: String,
+    pub sent_at: DateTime<Utc>,
+    pub message_sid: Option<String>,
+    pub status: String,
+    pub status_updated_at: DateTime<Utc>,
+}
+
+enum LogOp {
+    Sent {
+        phone: String,
+        phone_hash: String,
+        body_hash: String,
+        sent_at: DateTime<Utc>,
+        message_sid: Option<String>,
+        status: String,
+    },
+    Status {
+        message_sid: String,
+        status: String,
+        at: DateTime<Utc>,
+    },
+}
+
+static LOG_SENDER: OnceLock<mpsc::UnboundedSender<LogOp>> = OnceLock::new();
+
+pub fn message_log_path() -> String {
+    std::env::var("MESSAGE_LOG_DB_PATH").unwrap_or_else(|_| "messages.db".to_string())
+}
+
+/// `MESSAGE_LOG_RETENTION_DAYS`, default 30.
+fn retention() -> chrono::Duration {
+    chrono::Duration::days(
+        std::env::var("MESSAGE_LOG_RETENTION_DAYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30),
+    )
+}
+
+fn open(path: &str) -> Result<Connection, String> {
+    let conn = Connection::open(path)
+        .map_err(|e| format!("Failed to open message log {}: {}", path, e))?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS outbound_messages (
+             id INTEGER PRIMARY KEY AUTOINCREMENT,
+             phone TEXT NOT NULL,
+             phone_hash TEXT NOT NULL,
+             body_hash TEXT NOT NULL,
+             sent_at TEXT NOT NULL,
+             message_sid TEXT,
+             status TEXT NOT NULL,
+             status_updated_at TEXT NOT NULL
+         );
+         CREATE INDEX IF NOT EXISTS outbound_messages_phone ON outbound_messages (phone_hash, sent_at);
+         CREATE INDEX IF NOT EXISTS outbound_messages_sid ON outbound_messages (message_sid);",
+    )
+    .map_err(|e| format!("Failed to initialise message log: {}", e))?;
+    Ok(conn)
+}
+
+/// Queues a sent (or failed) message for the log; never blocks the send path.
+pub fn record_sent(to: &str, body: &str, message_sid: Option<String>, status: &str) {
+    let Some(sender) = LOG_SENDER.get() else {
+        return;
+    };
+    let _ = sender.send(LogOp::Sent {
+        phone: phone_for_storage(to),
+        phone_hash: hash_phone(to),
+        body_hash: hex::encode(Sha256::digest(body.as_bytes())),
+        sent_at: Utc::now(),
+        message_sid,
+        status: status.to_string(),
+    });
+}
+
+/// Final delivery status from a Twilio status callback.
+pub fn record_status(message_sid: &str, status: &str) {
+    let Some(sender) = LOG_SENDER.get() else {
+        return;
+    };
+    let _ = sender.send(LogOp::Status {
+        message_sid: message_sid.to_string(),
+        status: status.to_string(),
+        at: Utc::now(),
+    });
+}
+
+fn apply(conn: &Connection, op: LogOp) -> rusqlite::Result<usize> {
+    match op {
+        LogOp::Sent {
+            phone,
+            phone_hash,
+            body_hash,
+            sent_at,
+            message_sid,
+            status,
+        } => conn.execute(
+            "INSERT INTO outbound_messages
+                 (phone, phone_hash, body_hash, sent_at, message_sid, status, status_updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?4)",
+            params![
+                phone,
+                phone_hash,
+                body_hash,
+                sent_at.to_rfc3339(),
+                message_sid,
+                status
+            ],
+        ),
+        LogOp::Status {
+            message_sid,
+            status,
+            at,
+        } => conn.execute(
+            "UPDATE outbound_messages SET status = ?2, status_updated_at = ?3 WHERE message_sid = ?1",
+            params![message_sid, status, at.to_rfc3339()],
+        ),
+    }
+}
+
+fn prune(conn: &Connection) {
+    let cutoff = (Utc::now() - retention()).to_rfc3339();
+    match conn.execute(
+        "DELETE FROM outbound_messages WHERE sent_at < ?1",
+        params![cutoff],
+    ) {
+        Ok(0) => {}
+        Ok(removed) => println!("Pruned {} outbound messages past retention", removed),
+        Err(e) => eprintln!("Failed to prune message log: {}", e),
+    }
+}
+
+/// Spawns the writer that stores outbound messages in `MESSAGE_LOG_DB_PATH`. Writes run
+/// on the blocking pool in batches, and entries older than the retention are pruned hourly.
+pub fn start_writer() {
+    let path = message_log_path();
+    let conn = match open(&path) {
+        Ok(conn) => Arc::new(Mutex::new(conn)),
+        Err(e) => {
+            eprintln!("{}; outbound messages won't be logged", e);
+            return;
+        }
+    };
+
+    let (sender, receiver) = mpsc::unbounded_channel::<LogOp>();
+    if LOG_SENDER.set(sender).is_err() {
+        return;
+    }
+    let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+
+    supervisor::supervise("message_log", move || {
+        let conn = conn.clone();
+        let receiver = receiver.clone();
+        async move {
+            let mut receiver = receiver.lock().await;
+            let mut prune_timer = tokio::time::interval(Duration::from_secs(60 * 60));
+
+            loop {
+                let mut batch = Vec::new();
+                tokio::select! {
+                    received = receiver.recv_many(&mut batch, 100) => {
+                        if received == 0 {
+                            return;
+                        }
+                    }
+                    _ = prune_timer.tick() => {}
+                }
+
+                let conn = conn.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    let conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+                    if batch.is_empty() {
+                        prune(&conn);
+                    }
+                    for op in batch {
+                        if let Err(e) = apply(&conn, op) {
+                            eprintln!("Failed to write message log entry: {}", e);
+                        }
+                    }
+                })
+                .await;
+                if let Err(e) = result {
+                    eprintln!("Message log write task failed: {}", e);
+                }
+            }
+        }
+    });
+}
+
+/// Messages sent to `phone` since `since`, newest first.
+pub async fn messages_for(
+    phone: &str,
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<LoggedMessage>, String> {
+    let path = message_log_path();
+    let phone_hash = hash_phone(phone);
+    let since = since.unwrap_or_else(|| Utc::now() - retention());
+
+    tokio::task::spawn_blocking(move || {
+        let conn = open(&path)?;
+        let mut statement = conn
+            .prepare(
+                "SELECT phone, body_hash, sent_at, message_sid, status, status_updated_at
+                 FROM outbound_messages
+                 WHERE phone_hash = ?1 AND sent_at >= ?2
+                 ORDER BY sent_at DESC",
+            )
+            .map_err(|e| format!("Failed to query message log: {}", e))?;
+
+        let rows = statement
+            .query_map(params![phone_hash, since.to_rfc3339()], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            })
+            .map_err(|e| format!("Failed to query message log: {}", e))?;
+
+        let mut messages = Vec::new();
+        for row in rows {
+            let (phone, body_hash, sent_at, message_sid, status, status_updated_at) =
+                row.map_err(|e| format!("Failed to read message log row: {}", e))?;
+            messages.push(LoggedMessage {
+                phone,
+                body_hash,
+                sent_at: parse_timestamp(&sent_at),
+                message_sid,
+                status,
+                status_updated_at: parse_timestamp(&status_updated_at),
+            });
+        }
+        Ok(messages)
+    })
+    .await
+    .map_err(|e| format!("Message log query failed: {}", e))?
+}
+
+fn parse_timestamp(raw: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(raw)
+        .map(|at| at.with_timezone(&Utc))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(phone: &str, sent_at: DateTime<Utc>, message_sid: Option<&str>) -> LogOp {
+        LogOp::Sent {
+            phone: phone_for_storage(phone),
+            phone_hash: hash_phone(phone),
+            body_hash: "hash".to_string(),
+            sent_at,
+            message_sid: message_sid.map(str::to_string),
+            status: "queued".to_string(),
+        }
+    }
+
+    fn statuses(conn: &Connection) -> Vec<(Option<String>, String)> {
+        let mut statement = conn
+            .prepare("SELECT message_sid, status FROM outbound_messages ORDER BY id")
+            .unwrap();
+        statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    fn scratch_log(name: &str) -> Connection {
+        let path = std::env::temp_dir().join(format!(
+            "kharon-message-log-{}-{}.db",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        open(&path.to_string_lossy()).unwrap()
+    }
+
+    #[test]
+    fn a_callback_updates_only_the_message_it_names() {
+        let conn = scratch_log("status");
+        apply(&conn, sent("+2348090002101", Utc::now(), Some("SM1"))).unwrap();
+        apply(&conn, sent("+2348090002101", Utc::now(), Some("SM2"))).unwrap();
+        let updated = apply(
+            &conn,
+            LogOp::Status {
+                message_sid: "SM2".to_string(),
+                status: "delivered".to_string(),
+                at: Utc::now(),
+            },
+        )
+        .unwrap();
+        assert_eq!(updated, 1);
+        assert_eq!(
+            statuses(&conn),
+            [
+                (Some("SM1".to_string()), "queued".to_string()),
+                (Some("SM2".to_string()), "delivered".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn pruning_drops_only_messages_past_retention() {
+        let conn = scratch_log("prune");
+        let expired = Utc::now() - retention() - chrono::Duration::hours(1);
+        let kept = Utc::now() - retention() + chrono::Duration::hours(1);
+        apply(&conn, sent("+2348090002102", expired, Some("SM-OLD"))).unwrap();
+        apply(&conn, sent("+2348090002102", kept, Some("SM-KEPT"))).unwrap();
+
+        prune(&conn);
+        assert_eq!(
+            statuses(&conn),
+            [(Some("SM-KEPT".to_string()), "queued".to_string())]
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use actix_web::HttpResponse;
+
+use crate::supervisor::{self, TaskStatus};
+
+/// A monotonically increasing count, split by the values of `labels`.
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+}
+
+/// Durations in seconds, counted into cumulative `buckets` (upper bounds, ascending).
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    buckets: &'static [f64],
+}
+
+const REQUEST_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0];
+/// Account creation legitimately takes minutes; a rate lookup shouldn't take five seconds.
+const BACKEND_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+pub static HTTP_REQUESTS: Histogram = Histogram {
+    name: "http_request_duration_seconds",
+    help: "Time to answer an HTTP request, by route pattern and status.",
+    labels: &["method", "route", "status"],
+    buckets: REQUEST_BUCKETS,
+};
+
+pub static WEBHOOK_REQUESTS: Histogram = Histogram {
+    name: "webhook_duration_seconds",
+    help: "Time to answer a Twilio webhook, by what it was and how it was handled.",
+    labels: &["kind", "handling"],
+    buckets: REQUEST_BUCKETS,
+};
+
+pub static BACKEND_REQUESTS: Histogram = Histogram {
+    name: "backend_request_duration_seconds",
+    help: "Time for a Kharon Pay backend call, by endpoint and outcome.",
+    labels: &["endpoint", "outcome"],
+    buckets: BACKEND_BUCKETS,
+};
+
+pub static POLL_TIMEOUTS: Counter = Counter {
+    name: "status_poll_timeouts_total",
+    help: "Withdrawals unsettled when polling ran out; `fast` tells the user, `slow` gives up.",
+    labels: &["phase"],
+};
+
+pub static FEEDBACK_ASKS: Counter = Counter {
+    name: "feedback_asks_total",
+    help: "Post-withdrawal rating asks, sent or skipped.",
+    labels: &["outcome"],
+};
+
+pub static FEEDBACK_RATINGS: Counter = Counter {
+    name: "feedback_ratings_total",
+    help: "Ratings received, by score.",
+    labels: &["rating"],
+};
+
+pub static INBOUND: Counter = Counter {
+    name: "webhook_inbound_total",
+    help: "Twilio webhooks by classification; watch `unknown` for new payload shapes.",
+    labels: &["kind"],
+};
+
+pub static STALE_SESSIONS_RESET: Counter = Counter {
+    name: "stale_sessions_reset_total",
+    help: "Sessions reset by reconciliation after sitting mid-flow past the flow timeout.",
+    labels: &[],
+};
+
+pub static STATE_TRANSITIONS: Counter = Counter {
+    name: "state_transitions_total",
+    help: "Conversation state changes, by edge.",
+    labels: &["from", "to"],
+};
+
+pub static BACKEND_DEADLINES: Counter = Counter {
+    name: "backend_deadline_exceeded_total",
+    help: "Backend calls cut off by their endpoint's deadline.",
+    labels: &["endpoint"],
+};
+
+static HISTOGRAMS: [&Histogram; 3] = [&HTTP_REQUESTS, &WEBHOOK_REQUESTS, &BACKEND_REQUESTS];
+
+#[derive(Debug, Clone, Default)]
+struct Observed {
+    /// Per bucket, not cumulative; one more than the bounds, for `+Inf`.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Label values, in the order the metric declares its labels.
+type Series = Vec<String>;
+
+/// Counters by name, each registered by its first increment.
+type Counted = BTreeMap<&'static str, (&'static Counter, BTreeMap<Series, u64>)>;
+
+static COUNTS: LazyLock<Mutex<Counted>> = LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+static OBSERVATIONS: LazyLock<Mutex<BTreeMap<(&'static str, Series), Observed>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+fn series(labels: &[&str], values: &[&str]) -> Series {
+    debug_assert_eq!(labels.len(), values.len(), "label values don't match");
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+impl Counter {
+    pub fn inc(&'static self, values: &[&str]) {
+        let mut counts = COUNTS.lock().unwrap_or_else(|e| e.into_inner());
+        let (_, series_counts) = counts
+            .entry(self.name)
+            .or_insert_with(|| (self, BTreeMap::new()));
+        *series_counts
+            .entry(series(self.labels, values))
+            .or_default() += 1;
+    }
+
+    pub fn get(&self, values: &[&str]) -> u64 {
+        self.values()
+            .get(&series(self.labels, values))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Every series counted so far, by its label values.
+    pub fn values(&self) -> BTreeMap<Series, u64> {
+        COUNTS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(self.name)
+            .map(|(_, series_counts)| series_counts.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl Histogram {
+    pub fn observe(&self, values: &[&str], elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = self
+            .buckets
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(self.buckets.len());
+
+        let mut observations = OBSERVATIONS.lock().unwrap_or_else(|e| e.into_inner());
+        let observed = observations
+            .entry((self.name, series(self.labels, values)))
+            .or_insert_with(|| Observed {
+                counts: vec![0; self.buckets.len() + 1],
+                ..Observed::default()
+            });
+        observed.counts[bucket] += 1;
+        observed.sum += seconds;
+        observed.count += 1;
+    }
+
+    /// How many observations `values` has had.
+    pub fn count(&self, values: &[&str]) -> u64 {
+        OBSERVATIONS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(self.name, series(self.labels, values)))
+            .map_or(0, |observed| observed.count)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// `{a="1",b="2"}`, or nothing when there are no labels.
+fn label_set(labels: &[&str], values: &[String], extra: Option<(&str, &str)>) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .zip(values)
+        .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+        .chain(extra.map(|(label, value)| format!("{}=\"{}\"", label, value)))
+        .collect();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Everything in the Prometheus text format. Background task health is read from the
+/// supervisor as it stands.
+pub fn render() -> String {
+    let mut out = String::new();
+
+    let counts = COUNTS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    for (counter, series_counts) in counts.values() {
+        header(&mut out, counter.name, counter.help, "counter");
+        for (series, count) in series_counts {
+            let labels = label_set(counter.labels, series, None);
+            let _ = writeln!(out, "{}{} {}", counter.name, labels, count);
+        }
+    }
+
+    let observations = OBSERVATIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    for histogram in HISTOGRAMS {
+        header(&mut out, histogram.name, histogram.help, "histogram");
+        let observed = observations
+            .iter()
+            .filter(|((name, _), _)| *name == histogram.name);
+        for ((_, series), observed) in observed {
+            let mut cumulative = 0;
+            for (i, count) in observed.counts.iter().enumerate() {
+                cumulative += count;
+                let bound = histogram
+                    .buckets
+                    .get(i)
+                    .map_or("+Inf".to_string(), |bound| bound.to_string());
+                let labels = label_set(histogram.labels, series, Some(("le", &bound)));
+                let _ = writeln!(out, "{}_bucket{} {}", histogram.name, labels, cumulative);
+            }
+            let labels = label_set(histogram.labels, series, None);
+            let _ = writeln!(out, "{}_sum{} {}", histogram.name, labels, observed.sum);
+            let _ = writeln!(out, "{}_count{} {}", histogram.name, labels, observed.count);
+        }
+    }
+
+    let tasks = supervisor::snapshot();
+    header(
+        &mut out,
+        "background_task_up",
+        "1 while a supervised background task is running.",
+        "gauge",
+    );
+    for (name, task) in &tasks {
+        let up = u8::from(task.status == TaskStatus::Running);
+        let _ = writeln!(out, "background_task_up{{task=\"{}\"}} {}", name, up);
+    }
+    header(
+        &mut out,
+        "background_task_restarts_total",
+        "Times a supervised background task panicked or exited and was restarted.",
+        "counter",
+    );
+    for (name, task) in &tasks {
+        let _ = writeln!(
+            out,
+            "background_task_restarts_total{{task=\"{}\"}} {}",
+            name, task.restarts
+        );
+    }
+
+    out
+}
+
+/// `GET /metrics`, for Prometheus to scrape. No label value is ever a phone number or a
+/// reference.
+pub async fn handle() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TEST_COUNTER: Counter = Counter {
+        name: "test_events_total",
+        help: "Test.",
+        labels: &["kind"],
+    };
+
+    static TEST_QUOTED: Counter = Counter {
+        name: "test_quoted_total",
+        help: "Test.",
+        labels: &["kind"],
+    };
+
+    static TEST_HISTOGRAM: Histogram = Histogram {
+        name: "test_duration_seconds",
+        help: "Test.",
+        labels: &["route"],
+        buckets: &[0.1, 1.0],
+    };
+
+    #[test]
+    fn counters_count_per_label_value() {
+        TEST_COUNTER.inc(&["a"]);
+        TEST_COUNTER.inc(&["a"]);
+        TEST_COUNTER.inc(&["b"]);
+        assert_eq!(TEST_COUNTER.get(&["a"]), 2);
+        assert_eq!(TEST_COUNTER.get(&["b"]), 1);
+        assert_eq!(TEST_COUNTER.get(&["c"]), 0);
+        assert_eq!(TEST_COUNTER.values().len(), 2);
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let observations = [50, 500, 700, 5000];
+        for ms in observations {
+            TEST_HISTOGRAM.observe(&["/x"], Duration::from_millis(ms));
+        }
+        assert_eq!(TEST_HISTOGRAM.count(&["/x"]), 4);
+
+        let observed = OBSERVATIONS
+            .lock()
+            .unwrap()
+            .get(&("test_duration_seconds", vec!["/x".to_string()]))
+            .cloned()
+            .unwrap();
+        assert_eq!(observed.counts, vec![1, 2, 1]);
+        assert!((observed.sum - 6.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn exposition_has_help_type_and_escaped_labels() {
+        TEST_QUOTED.inc(&["say \"hi\""]);
+        BACKEND_REQUESTS.observe(&["Selftest", "ok"], Duration::from_millis(300));
+
+        let text = render();
+        assert!(text.contains("# TYPE test_quoted_total counter"));
+        assert!(text.contains("test_quoted_total{kind=\"say \\\"hi\\\"\"} 1"));
+        assert!(text.contains("# TYPE backend_request_duration_seconds histogram"));
+        let selftest = r#"endpoint="Selftest",outcome="ok""#;
+        for line in [
+            format!("_bucket{{{},le=\"0.25\"}} 0", selftest),
+            format!("_bucket{{{},le=\"0.5\"}} 1", selftest),
+            format!("_bucket{{{},le=\"+Inf\"}} 1", selftest),
+            format!("_count{{{}}} 1", selftest),
+        ] {
+            let line = format!("backend_request_duration_seconds{}", line);
+            assert!(text.contains(&line), "{} missing", line);
+        }
+    }
+}
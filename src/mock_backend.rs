@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+use actix_web::{HttpResponse, web};
+use chrono::{DateTime, Utc};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+/// `MOCK_BACKEND=1`: the bot talks to a fake backend served from its own `/mock-backend`
+/// routes, and prints replies instead of sending them, so flows run with nothing else.
+pub fn enabled() -> bool {
+    matches!(
+        std::env::var("MOCK_BACKEND").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+/// `MOCK_BACKEND_URL`, default this server's own `/mock-backend` on port 6500; benches
+/// and load tests serving the mock on another port point it there.
+fn base() -> String {
+    std::env::var("MOCK_BACKEND_URL")
+        .unwrap_or_else(|_| "http://127.0.0.1:6500/mock-backend".to_string())
+}
+
+const RATE: f64 = 1500.0;
+const USDT: &str = "0x07d54bad6d6fcff799133a8c0b1fb8120876bb080d75cd601a5c68164d6f6d75";
+
+/// Endpoint variables and the mock path each one points at.
+const ENDPOINTS: &[(&str, &str)] = &[
+    ("SERVER_CREATE_ENDPOINT", "/accounts"),
+    ("SERVER_CREATE_CONTROLLER_ENDPOINT", "/controllers"),
+    ("SERVER_GET_ADDRESS_ENDPOINT", "/address"),
+    ("SERVER_BALANCE_ENDPOINT", "/balance"),
+    ("SERVER_TRANSACTION_HISTORY_ENDPOINT", "/transactions"),
+    ("SERVER_RATE_ENDPOINT", "/rate"),
+    ("SERVER_BANK_ACCOUNT_VERIFY_ENDPOINT", "/banks/verify"),
+    ("SERVER_BANK_ACCOUNT_GETTER_ENDPOINT", "/banks"),
+    ("SERVER_BANK_DETAILS_CONFIRM_ENDPOINT", "/banks"),
+    ("SERVER_OFFRAMP_INIT_ENDPOINT", "/offramp"),
+    ("SERVER_OFFRAMP_CANCEL_ENDPOINT", "/offramp/cancel"),
+    ("SERVER_PAYMENT_ENDPOINT", "/payments"),
+    ("SERVER_FEEDBACK_ENDPOINT", "/feedback"),
+    ("SERVER_USER_PROFILE_ENDPOINT", "/profile"),
+    ("SUPPORT_WEBHOOK_URL", "/support"),
+    // The poller appends `/transactions/{reference}/status`
+    ("TRANSACTION_STATUS_ENDPOINT", ""),
+];
+
+/// Settings the bot refuses to start without, filled in only when missing.
+const DEFAULTS: &[(&str, &str)] = &[
+    ("T_ACCOUNT_SID", "ACmock"),
+    ("T_AUTH_TOKEN", "mock"),
+    ("T_WHATSAPP_NUMBER", "whatsapp:+14155238886"),
+    ("T_API_URL", "http://127.0.0.1:6500/mock-backend/twilio"),
+    ("TWILIO_DRY_RUN", "1"),
+    ("HMAC_KEY", "mock-backend"),
+    ("CALLBACK_HMAC_KEY", "mock-callbacks"),
+    ("EXPORT_SIGNING_KEY", "mock-exports"),
+    ("TEST_TOKEN", USDT),
+    ("TEST_ADDRESS", "0x0mock"),
+];
+
+/// Points every backend endpoint at the mock. Must run before anything else reads the
+/// environment.
+pub fn configure_env() {
+    let base = base();
+    // SAFETY: called first thing in `main`, before any other thread exists
+    unsafe {
+        for (var, path) in ENDPOINTS {
+            std::env::set_var(var, format!("{}{}", base, path));
+        }
+        std::env::remove_var("TRANSACTION_STATUS_BATCH_ENDPOINT");
+        for (var, value) in DEFAULTS {
+            if std::env::var(var).is_err() {
+                std::env::set_var(var, value);
+            }
+        }
+        // The mock routes aren't listening yet when the startup self-test runs
+        if std::env::var("SELF_TEST").is_err() {
+            std::env::set_var("SELF_TEST", "off");
+        }
+    }
+    println!("🧪 MOCK_BACKEND is on: backend calls go to {}", base);
+}
+
+struct MockTransaction {
+    phone: String,
+    token: String,
+    crypto_amount: f64,
+    naira_amount: f64,
+    bank_name: String,
+    created_at: DateTime<Utc>,
+    paid_at: Option<DateTime<Utc>>,
+    cancelled: bool,
+    /// The quote the bot sent with the init, reported back as the status's metadata.
+    quote: Value,
+}
+
+impl MockTransaction {
+    /// `MOCK_COMPLETE_AFTER_SECS` (default 8) after the payment trigger, pending turns
+    /// completed, so the poller and completion message can be seen end to end.
+    fn status(&self) -> &'static str {
+        let complete_after = std::env::var("MOCK_COMPLETE_AFTER_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(8);
+        match self.paid_at {
+            _ if self.cancelled => "cancelled",
+            None => "pending",
+            Some(at) if (Utc::now() - at).num_seconds() >= complete_after => "completed",
+            Some(_) => "processing",
+        }
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    /// Saved banks per phone (digits only), as the bank list returns them.
+    banks: HashMap<String, Vec<Value>>,
+    transactions: HashMap<String, MockTransaction>,
+    tickets: u32,
+    /// Failures to script per phone (digits only), set through `/faults`.
+    faults: HashMap<String, Faults>,
+}
+
+/// What should go wrong for one phone's withdrawals.
+#[derive(Default)]
+struct Faults {
+    /// Payment triggers to refuse before accepting one.
+    payment_failures: u32,
+    /// Refuse every cancellation.
+    cancel_fails: bool,
+    /// Refuse every offramp with this `error_code`, or with a body that isn't the error
+    /// envelope when it's `garbled`.
+    offramp_error: Option<String>,
+    /// Bank saves to answer only after `MOCK_STALL_SECS` (default 2), past the deadline,
+    /// before answering one in time.
+    bank_save_stalls: u32,
+    /// Stalled bank saves are dropped rather than stored.
+    stalled_saves_lost: bool,
+}
+
+static STATE: LazyLock<Mutex<MockState>> = LazyLock::new(|| Mutex::new(MockState::default()));
+
+fn state() -> std::sync::MutexGuard<'static, MockState> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn digest(input: &str) -> String {
+    hex::encode(Sha256::digest(input.as_bytes()))
+}
+
+type Query = web::Query<HashMap<String, String>>;
+
+fn param<'a>(query: &'a Query, name: &str) -> &'a str {
+    query.get(name).map(String::as_str).unwrap_or_default()
+}
+
+fn field<'a>(body: &'a Value, name: &str) -> &'a str {
+    body.get(name).and_then(Value::as_str).unwrap_or_default()
+}
+
+/// Same phone, same address, so `address` and account creation agree.
+fn controller_address(phone: &str) -> String {
+    format!("0x0{}", &digest(&format!("controller:{}", phone))[..62])
+}
+
+/// Usernames starting `taken` are in use and ones with anything but letters, digits
+/// and `_` are invalid, so each rejection can be tried.
+async fn create_account(body: web::Json<Value>) -> HttpResponse {
+    let username = field(&body, "username").trim();
+    if username.starts_with("taken") {
+        return HttpResponse::Conflict().json(json!({
+            "error_code": "USERNAME_TAKEN",
+            "message": format!("The username {} is already in use.", username),
+        }));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return HttpResponse::UnprocessableEntity().json(json!({
+            "error_code": "VALIDATION_ERROR",
+            "message": "Usernames may only contain letters, numbers and underscores.",
+        }));
+    }
+    HttpResponse::Ok().json(json!({ "success": true, "message": "Account created" }))
+}
+
+/// Usernames starting `nowallet` fail here, after their account was created.
+async fn create_controller(body: web::Json<Value>) -> HttpResponse {
+    if field(&body, "username").trim().starts_with("nowallet") {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error_code": "PROVISIONING_FAILED",
+            "message": "Controller provisioning timed out",
+        }));
+    }
+    let phone = field(&body, "phone");
+    HttpResponse::Ok().json(json!({
+        "success": "true",
+        "message": "Controller created",
+        "data": {
+            "controller_address": controller_address(phone),
+            "username": field(&body, "username"),
+            "session_id": format!("mock-session-{}", &digest(phone)[..8]),
+            "session_options": {},
+        },
+    }))
+}
+
+async fn get_address(query: Query) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "data": { "controller_address": controller_address(param(&query, "phone")) },
+    }))
+}
+
+/// `MOCK_BALANCE` (default `250.00`) is every wallet's balance, in whole tokens.
+async fn get_balance(query: Query) -> HttpResponse {
+    let balance = std::env::var("MOCK_BALANCE").unwrap_or_else(|_| "250.00".into());
+    HttpResponse::Ok().json(json!({
+        "data": { "balance": balance, "token": param(&query, "token") },
+    }))
+}
+
+async fn get_rate() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "data": { "usd_ngn_rate": RATE } }))
+}
+
+/// Any ten-digit number verifies, except ones starting `000`, which the bank can't find;
+/// the bank code is derived from the name so saving the same bank twice matches.
+async fn verify_bank(query: Query) -> HttpResponse {
+    if param(&query, "account_number").starts_with("000") {
+        return HttpResponse::NotFound().json(json!({
+            "error_code": "INVALID_BANK_ACCOUNT",
+            "message": "Account not found",
+        }));
+    }
+    let bank_name = param(&query, "bank_name");
+    HttpResponse::Ok().json(json!({
+        "data": {
+            "account_name": "MOCK ACCOUNT HOLDER",
+            "account_number": param(&query, "account_number"),
+            "bank_name": bank_name,
+            "bank_code": &digest(&bank_name.to_lowercase())[..3],
+        },
+    }))
+}
+
+async fn list_banks(query: Query) -> HttpResponse {
+    let banks = state()
+        .banks
+        .get(param(&query, "phone"))
+        .cloned()
+        .unwrap_or_default();
+    HttpResponse::Ok().json(json!({ "status": "success", "data": { "banks": banks } }))
+}
+
+/// Saves are idempotent on `bank_save_id`, like the real endpoint.
+async fn save_bank(body: web::Json<Value>) -> HttpResponse {
+    let stalled = state()
+        .faults
+        .get_mut(field(&body, "phone"))
+        .filter(|faults| faults.bank_save_stalls > 0)
+        .map(|faults| {
+            faults.bank_save_stalls -= 1;
+            faults.stalled_saves_lost
+        });
+    if let Some(lost) = stalled {
+        if !lost {
+            store_bank(&body);
+        }
+        let stall = std::env::var("MOCK_STALL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2);
+        tokio::time::sleep(std::time::Duration::from_secs(stall)).await;
+        return unavailable();
+    }
+    let bank_details_id = store_bank(&body);
+    HttpResponse::Ok()
+        .json(json!({ "success": true, "data": { "bank_details_id": bank_details_id } }))
+}
+
+/// Adds the bank in `body` to its phone's list, unless it's already there, or replaces the
+/// saved one it names as `bank_details_id`. Returns its id.
+fn store_bank(body: &Value) -> String {
+    let bank_details_id = format!("mock-bank-{}", &digest(field(body, "bank_save_id"))[..8]);
+    let mut state = state();
+    let banks = state
+        .banks
+        .entry(field(body, "phone").to_string())
+        .or_default();
+    let replaces = field(body, "bank_details_id");
+    if let Some(bank) = banks
+        .iter_mut()
+        .find(|bank| !replaces.is_empty() && bank["bank_details_id"] == replaces)
+    {
+        bank["bank_name"] = json!(field(body, "bank_name"));
+        bank["bank_account_number"] = json!(field(body, "account_number"));
+        bank["account_name"] = json!(field(body, "account_name"));
+        bank["bank_code"] = json!(field(body, "bank_code"));
+        return replaces.to_string();
+    }
+    if !banks
+        .iter()
+        .any(|bank| bank["bank_details_id"] == bank_details_id)
+    {
+        banks.push(json!({
+            "bank_details_id": bank_details_id,
+            "bank_name": field(body, "bank_name"),
+            "bank_account_number": field(body, "account_number"),
+            "account_name": field(body, "account_name"),
+            "bank_code": field(body, "bank_code"),
+        }));
+    }
+    bank_details_id
+}
+
+async fn init_offramp(body: web::Json<Value>) -> HttpResponse {
+    let phone = field(&body, "phone").to_string();
+    let amount = body
+        .get("amount")
+        .and_then(Value::as_f64)
+        .unwrap_or_default();
+    let mut state = state();
+    if let Some(code) = state
+        .faults
+        .get(&phone)
+        .and_then(|faults| faults.offramp_error.as_deref())
+    {
+        return match code {
+            "garbled" => HttpResponse::BadGateway().body("<html>502 Bad Gateway</html>"),
+            code => HttpResponse::UnprocessableEntity().json(json!({
+                "error_code": code,
+                "message": "Scripted offramp failure",
+            })),
+        };
+    }
+    let Some(bank) = state.banks.get(&phone).and_then(|banks| {
+        banks
+            .iter()
+            .find(|bank| bank["bank_details_id"] == body["bank_account_id"])
+            .cloned()
+    }) else {
+        return HttpResponse::UnprocessableEntity().json(json!({
+            "error_code": "INVALID_BANK_ACCOUNT",
+            "message": "Unknown bank_account_id",
+        }));
+    };
+
+    let reference = format!(
+        "MOCK-{}",
+        uuid::Uuid::new_v4().simple().to_string()[..10].to_uppercase()
+    );
+    let naira_amount = (amount * RATE * 100.0).round() / 100.0;
+    state.transactions.insert(
+        reference.clone(),
+        MockTransaction {
+            phone,
+            token: field(&body, "token_symbol").to_string(),
+            crypto_amount: amount,
+            naira_amount,
+            bank_name: bank["bank_name"].as_str().unwrap_or_default().to_string(),
+            created_at: Utc::now(),
+            paid_at: None,
+            cancelled: false,
+            quote: json!({
+                "quoted_rate": body["quoted_rate"],
+                "quoted_at": body["quoted_at"],
+            }),
+        },
+    );
+
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "message": "Offramp initiated",
+        "reference": reference,
+        "data": {
+            "account_name": bank["account_name"],
+            "account_number": bank["bank_account_number"],
+            "bank_name": bank["bank_name"],
+            "bank_code": bank["bank_code"],
+            "amount": naira_amount,
+            "currency": "NGN",
+            "crypto_tx_hash": format!("0x{}", digest(&reference)),
+        },
+        "error": null,
+    }))
+}
+
+fn update_transaction(body: &Value, update: impl FnOnce(&mut MockTransaction)) -> HttpResponse {
+    match state().transactions.get_mut(field(body, "reference")) {
+        Some(transaction) => {
+            update(transaction);
+            HttpResponse::Ok().json(json!({ "success": true }))
+        }
+        None => HttpResponse::NotFound().json(json!({ "success": false })),
+    }
+}
+
+/// The phone whose withdrawal `body` names, if it's one we have.
+fn transaction_phone(body: &Value) -> Option<String> {
+    state()
+        .transactions
+        .get(field(body, "reference"))
+        .map(|transaction| transaction.phone.clone())
+}
+
+fn unavailable() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(json!({ "success": false }))
+}
+
+async fn trigger_payment(body: web::Json<Value>) -> HttpResponse {
+    if let Some(phone) = transaction_phone(&body)
+        && let Some(faults) = state().faults.get_mut(&phone)
+        && faults.payment_failures > 0
+    {
+        faults.payment_failures -= 1;
+        return unavailable();
+    }
+    update_transaction(&body, |transaction| transaction.paid_at = Some(Utc::now()))
+}
+
+async fn cancel_offramp(body: web::Json<Value>) -> HttpResponse {
+    if let Some(phone) = transaction_phone(&body)
+        && state()
+            .faults
+            .get(&phone)
+            .is_some_and(|faults| faults.cancel_fails)
+    {
+        return unavailable();
+    }
+    update_transaction(&body, |transaction| transaction.cancelled = true)
+}
+
+/// Scripts failures for a phone's withdrawals, for trying the compensation path and the
+/// error replies: `{"phone", "payment_failures", "cancel_fails", "offramp_error",
+/// "bank_save_stalls", "stalled_saves_lost"}`.
+async fn set_faults(body: web::Json<Value>) -> HttpResponse {
+    let faults = Faults {
+        payment_failures: body["payment_failures"].as_u64().unwrap_or(0) as u32,
+        cancel_fails: body["cancel_fails"].as_bool().unwrap_or(false),
+        offramp_error: body["offramp_error"].as_str().map(str::to_string),
+        bank_save_stalls: body["bank_save_stalls"].as_u64().unwrap_or(0) as u32,
+        stalled_saves_lost: body["stalled_saves_lost"].as_bool().unwrap_or(false),
+    };
+    state()
+        .faults
+        .insert(field(&body, "phone").to_string(), faults);
+    HttpResponse::Ok().json(json!({ "success": true }))
+}
+
+async fn transaction_status(path: web::Path<String>) -> HttpResponse {
+    let reference = path.into_inner();
+    let state = state();
+    let Some(transaction) = state.transactions.get(&reference) else {
+        return HttpResponse::NotFound().json(json!({ "success": false, "message": "Not found" }));
+    };
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "message": "ok",
+        "data": {
+            "transaction_id": format!("tx-{}", reference),
+            "reference": reference,
+            "status": transaction.status(),
+            "amount": transaction.naira_amount,
+            "currency": "NGN",
+            "last_updated": Utc::now(),
+            "metadata": transaction.quote,
+        },
+    }))
+}
+
+/// `MOCK_PROFILE_NAME` (default the name every bank verifies to) is everyone's registered
+/// name; set it to something else to see the name mismatch warning.
+async fn get_profile(query: Query) -> HttpResponse {
+    let name = std::env::var("MOCK_PROFILE_NAME").unwrap_or_else(|_| "Mock Account Holder".into());
+    HttpResponse::Ok().json(json!({
+        "data": { "phone": param(&query, "phone"), "full_name": name },
+    }))
+}
+
+async fn record_rating(body: web::Json<Value>) -> HttpResponse {
+    println!("🧪 Rating: {}", body.0);
+    HttpResponse::Ok().json(json!({ "success": true }))
+}
+
+async fn open_ticket(body: web::Json<Value>) -> HttpResponse {
+    let mut state = state();
+    state.tickets += 1;
+    println!("🧪 Support ticket: {}", body.0);
+    HttpResponse::Ok().json(json!({ "ticket_id": format!("MOCK-{}", state.tickets) }))
+}
+
+async fn transaction_history(query: Query) -> HttpResponse {
+    let phone = param(&query, "phone");
+    let state = state();
+    let mut records: Vec<Value> = state
+        .transactions
+        .iter()
+        .filter(|(_, transaction)| transaction.phone == phone)
+        .map(|(reference, transaction)| {
+            json!({
+                "created_at": transaction.created_at,
+                "reference": reference,
+                "token_symbol": transaction.token,
+                "crypto_amount": transaction.crypto_amount,
+                "fiat_amount": transaction.naira_amount,
+                "bank_name": transaction.bank_name,
+                "status": transaction.status(),
+            })
+        })
+        .collect();
+    records.sort_by(|a, b| b["created_at"].as_str().cmp(&a["created_at"].as_str()));
+    HttpResponse::Ok().json(json!({ "success": true, "data": records }))
+}
+
+/// Mounted on every app; registers nothing unless `MOCK_BACKEND` is on.
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    if !enabled() {
+        return;
+    }
+    cfg.service(
+        web::scope("/mock-backend")
+            .route("/accounts", web::post().to(create_account))
+            .route("/controllers", web::post().to(create_controller))
+            .route("/address", web::get().to(get_address))
+            .route("/balance", web::get().to(get_balance))
+            .route("/rate", web::get().to(get_rate))
+            .route("/banks/verify", web::post().to(verify_bank))
+            .route("/banks", web::get().to(list_banks))
+            .route("/banks", web::post().to(save_bank))
+            .route("/offramp", web::post().to(init_offramp))
+            .route("/offramp/cancel", web::post().to(cancel_offramp))
+            .route("/payments", web::post().to(trigger_payment))
+            .route("/faults", web::post().to(set_faults))
+            .route("/transactions", web::get().to(transaction_history))
+            .route("/profile", web::get().to(get_profile))
+            .route("/feedback", web::post().to(record_rating))
+            .route("/support", web::post().to(open_ticket))
+            .route(
+                "/transactions/{reference}/status",
+                web::get().to(transaction_status),
+            ),
+    );
+}
@@ -1,28 +1,521 @@
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
 
-#[derive(Debug, Clone)]
+use crate::i18n::Language;
+use crate::privacy::{mask_account_number, mask_digit_runs};
+
+/// Bumped whenever `UserSessions` changes shape. New fields must be `#[serde(default)]`
+/// so sessions persisted by an older build still load.
+pub const SESSION_SCHEMA_VERSION: u32 = 14;
+
+/// Transitions kept in `UserSessions::transitions`.
+pub const TRANSITION_HISTORY_SIZE: usize = 20;
+
+/// Sessions stored before versioning was introduced carry no version at all.
+fn legacy_schema_version() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserSessions {
+    #[serde(default = "legacy_schema_version")]
+    pub schema_version: u32,
     pub phone: String,
+    #[serde(default)]
     pub state: UserState,
+    #[allow(dead_code)]
+    #[serde(default)]
     pub account_id: Option<String>,
+    #[serde(default)]
     pub pending_amount: Option<f64>,
+    #[serde(default)]
     pub pending_currency: Option<String>,
+    #[serde(default)]
     pub controller_address: Option<String>,
+    #[serde(default)]
     pub pending_bank_details: Option<BankDetails>,
+    #[serde(default)]
     pub pending_bank_verification: Option<BankVerificationResponse>,
+    /// Ties together the audit entries of one withdrawal flow.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    /// Set once an abandoned-flow nudge has gone out, so a stalled flow is nudged only once.
+    #[serde(default)]
+    pub reminded: bool,
+    /// Rate and NGN amount shown in the quote, kept so the payout can be checked against them.
+    #[serde(default)]
+    pub quoted_rate: Option<f64>,
+    #[serde(default)]
+    pub quoted_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub quoted_naira_amount: Option<f64>,
+    /// Initiated offramp held back because its payout drifted from the quote.
+    #[serde(default)]
+    pub pending_payout: Option<PendingPayout>,
+    /// Wrong amounts typed so far while confirming a large withdrawal.
+    #[serde(default)]
+    pub amount_confirmation_attempts: u32,
+    /// The user's recent messages, oldest first, as stored by `record_inbound`.
+    #[serde(default)]
+    pub inbound_history: Vec<InboundMessage>,
+    /// Saved banks offered as a numbered list when there's more than one; empty otherwise.
+    #[serde(default)]
+    pub bank_choices: Vec<BankDetails>,
+    /// When the user last messaged us; WhatsApp's 24-hour window runs from here.
+    #[serde(default)]
+    pub last_inbound_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Set when we've just asked for a rating, so a bare digit reply is taken as one.
+    #[serde(default)]
+    pub feedback_pending: Option<FeedbackPending>,
+    /// The last `TRANSITION_HISTORY_SIZE` state changes, oldest first, for operators
+    /// working out how a user got where they are.
+    #[serde(default)]
+    pub transitions: VecDeque<StateTransition>,
+    /// The user's preferences, loaded alongside the session but stored on their own.
+    #[serde(skip)]
+    pub prefs: UserPreferences,
+    /// Set by the store when the session was last saved before this process started, so
+    /// the first reply after a deploy can remind the user where they were.
+    #[serde(skip)]
+    pub restored: bool,
+    /// Preferences kept on the session by v7–v10; moved into `prefs` on load.
+    #[serde(default, rename = "language", skip_serializing)]
+    legacy_language: Option<Language>,
+    #[serde(default, rename = "language_detected", skip_serializing)]
+    legacy_language_detected: bool,
+    #[serde(default, rename = "notifications_off", skip_serializing)]
+    legacy_notifications_off: bool,
+}
+
+/// Settings that outlive any one conversation. They're stored apart from sessions, so
+/// resetting or evicting a session never loses them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserPreferences {
+    pub phone: String,
+    /// `None` until the user picks one or we detect it from their first messages.
+    #[serde(default)]
+    pub language: Option<Language>,
+    /// Set when `language` was guessed rather than chosen.
+    #[serde(default)]
+    pub language_detected: bool,
+    /// Minutes east of UTC, for the times we show.
+    #[serde(default)]
+    pub utc_offset_minutes: Option<i32>,
+    /// No `*bold*` and no interactive templates, for clients that render them badly.
+    #[serde(default)]
+    pub plain_text: bool,
+    /// Only essential messages (withdrawal updates) go out.
+    #[serde(default)]
+    pub notifications_off: bool,
+    /// `bank_details_id` offered first, on its own, when several banks are saved.
+    #[serde(default)]
+    pub default_bank_id: Option<String>,
+    /// How `settings` shows the default bank without asking the backend, e.g. `Opay ****6789`.
+    #[serde(default)]
+    pub default_bank_label: Option<String>,
+    /// `bank_details_id`s that failed a `verify` re-check, so withdrawals warn before using
+    /// them. Kept here rather than on the session so the flag outlives idle purges.
+    #[serde(default)]
+    pub flagged_bank_ids: Vec<String>,
+    /// Rating requests in a row that got no rating back; at 2 we stop asking.
+    #[serde(default)]
+    pub feedback_unanswered: u32,
+    /// Sent STOP and not START since; `try_send_twilio_content` sends them nothing.
+    #[serde(default)]
+    pub opted_out: bool,
+    /// Twilio refused the number as invalid (21211); nothing is sent until they write in.
+    #[serde(default)]
+    pub invalid_number: bool,
+    /// Set when the account is created here and cleared once every first step is done;
+    /// `None` means there's no checklist to show.
+    #[serde(default)]
+    pub onboarding: Option<OnboardingProgress>,
+}
+
+/// First steps after account creation. Each only ever goes from false to true.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OnboardingProgress {
+    #[serde(default)]
+    pub funded: bool,
+    #[serde(default)]
+    pub bank_added: bool,
+    #[serde(default)]
+    pub withdrawn: bool,
+    /// When the backend was last asked about the steps still open.
+    #[serde(default)]
+    pub checked_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl OnboardingProgress {
+    pub fn complete(&self) -> bool {
+        self.funded && self.bank_added && self.withdrawn
+    }
 }
 
-#[derive(Debug, Clone)]
+impl UserPreferences {
+    pub fn new(phone: &str) -> Self {
+        UserPreferences {
+            phone: phone.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn bank_flagged(&self, bank_details_id: &str) -> bool {
+        self.flagged_bank_ids.iter().any(|id| id == bank_details_id)
+    }
+
+    pub fn set_bank_flag(&mut self, bank_details_id: &str, flagged: bool) {
+        self.flagged_bank_ids.retain(|id| id != bank_details_id);
+        if flagged {
+            self.flagged_bank_ids.push(bank_details_id.to_string());
+        }
+    }
+
+    /// `UTC`, `UTC+01:00`, `UTC-05:30`.
+    pub fn timezone_label(&self) -> String {
+        match self.utc_offset_minutes {
+            None | Some(0) => "UTC".to_string(),
+            Some(minutes) => format!(
+                "UTC{}{:02}:{:02}",
+                if minutes < 0 { '-' } else { '+' },
+                minutes.abs() / 60,
+                minutes.abs() % 60
+            ),
+        }
+    }
+
+    /// `at` in the user's timezone, labelled.
+    pub fn format_time(&self, at: chrono::DateTime<chrono::Utc>, format: &str) -> String {
+        use chrono::Offset;
+
+        let offset = chrono::FixedOffset::east_opt(self.utc_offset_minutes.unwrap_or(0) * 60)
+            .unwrap_or_else(|| chrono::Utc.fix());
+        format!(
+            "{} {}",
+            at.with_timezone(&offset).format(format),
+            self.timezone_label()
+        )
+    }
+}
+
+/// One message the user sent, truncated and masked for storage, with the gist of our reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InboundMessage {
+    pub received_at: chrono::DateTime<chrono::Utc>,
+    pub body: String,
+    #[serde(default)]
+    pub outcome: Option<String>,
+}
+
+/// One entry in `UserSessions::transitions`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateTransition {
+    pub at: chrono::DateTime<chrono::Utc>,
+    pub from: UserState,
+    pub to: UserState,
+    /// The `StateEvent` applied, or `Repair` when a broken session was reset.
+    pub event: String,
+    /// How the message behind it was classified (`command:withdraw`,
+    /// `reply:BankSelection`); `None` for background work.
+    #[serde(default)]
+    pub trigger: Option<String>,
+}
+
+/// A rating request awaiting its answer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackPending {
+    pub reference: String,
+    pub asked_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// The parts of an initiated disbursement needed to trigger and report on the payment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingPayout {
+    pub reference: String,
+    pub amount: f64,
+    pub currency: String,
+    pub bank_name: String,
+    pub account_name: String,
+    pub crypto_tx_hash: String,
+}
+
+/// How much inbound history `record_inbound` keeps, and how it's stored.
+struct HistoryRules {
+    size: usize,
+    retention: chrono::Duration,
+    max_chars: usize,
+    mask: bool,
+}
+
+impl HistoryRules {
+    fn from_env() -> Self {
+        let env = |name: &str, default: usize| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        HistoryRules {
+            size: env("INBOUND_HISTORY_SIZE", 20),
+            retention: chrono::Duration::hours(env("INBOUND_HISTORY_RETENTION_HOURS", 72) as i64),
+            max_chars: env("INBOUND_HISTORY_MAX_CHARS", 160),
+            mask: !matches!(
+                std::env::var("INBOUND_HISTORY_MASK").as_deref(),
+                Ok("0") | Ok("false")
+            ),
+        }
+    }
+}
+
+impl UserSessions {
+    pub fn new(phone: &str) -> Self {
+        UserSessions {
+            schema_version: SESSION_SCHEMA_VERSION,
+            phone: phone.to_string(),
+            state: UserState::Initial,
+            account_id: None,
+            pending_amount: None,
+            pending_currency: None,
+            controller_address: None,
+            pending_bank_details: None,
+            pending_bank_verification: None,
+            correlation_id: None,
+            reminded: false,
+            quoted_rate: None,
+            quoted_at: None,
+            quoted_naira_amount: None,
+            pending_payout: None,
+            amount_confirmation_attempts: 0,
+            inbound_history: Vec::new(),
+            bank_choices: Vec::new(),
+            last_inbound_at: None,
+            feedback_pending: None,
+            transitions: VecDeque::new(),
+            prefs: UserPreferences::new(phone),
+            restored: false,
+            legacy_language: None,
+            legacy_language_detected: false,
+            legacy_notifications_off: false,
+        }
+    }
+
+    /// Brings a session loaded from storage up to the current schema. v2 added
+    /// `correlation_id`, v3 `reminded`, v4 the quote fields and `pending_payout`, v5
+    /// `amount_confirmation_attempts`, v6 `inbound_history`, v7 the language fields, v8
+    /// `bank_choices`, v9 `last_inbound_at` and v10 `notifications_off`, all of which
+    /// serde already defaults. v11 moved the language and notification fields into
+    /// `UserPreferences`; see `take_legacy_preferences`. v12 added `feedback_pending`,
+    /// also defaulted. v13 split picking between several saved banks out of
+    /// `SavedBankConfirmation` into `BankSelection`. v14 added `transitions`, defaulted.
+    pub fn upgrade(mut self) -> Self {
+        if self.schema_version < 13
+            && self.state == UserState::SavedBankConfirmation
+            && !self.bank_choices.is_empty()
+        {
+            // Mid-pick under the old layout: the first bank stood in as the pending one
+            self.state = UserState::BankSelection;
+            self.pending_bank_details = None;
+        }
+        self.schema_version = SESSION_SCHEMA_VERSION;
+        self
+    }
+
+    /// Appends a message to the history, keeping the last `INBOUND_HISTORY_SIZE` (default
+    /// 20, 0 turns history off) from within `INBOUND_HISTORY_RETENTION_HOURS` (default 72).
+    /// Bodies are cut to `INBOUND_HISTORY_MAX_CHARS` (default 160) and long digit runs
+    /// are masked unless `INBOUND_HISTORY_MASK=false`.
+    pub fn record_inbound(&mut self, body: &str) {
+        self.push_inbound(body, &HistoryRules::from_env(), chrono::Utc::now());
+    }
+
+    fn push_inbound(
+        &mut self,
+        body: &str,
+        rules: &HistoryRules,
+        now: chrono::DateTime<chrono::Utc>,
+    ) {
+        self.inbound_history
+            .retain(|message| now - message.received_at < rules.retention);
+        if rules.size == 0 {
+            self.inbound_history.clear();
+            return;
+        }
+
+        let mut body: String = body.trim().chars().take(rules.max_chars).collect();
+        if rules.mask {
+            body = mask_digit_runs(&body);
+        }
+        self.inbound_history.push(InboundMessage {
+            received_at: now,
+            body,
+            outcome: None,
+        });
+        let excess = self.inbound_history.len().saturating_sub(rules.size);
+        self.inbound_history.drain(..excess);
+    }
+
+    pub fn language(&self) -> Language {
+        self.prefs.language.unwrap_or_default()
+    }
+
+    /// Moves preferences stored on a pre-v11 session into `prefs`. Returns whether there
+    /// were any, so the caller knows to persist them.
+    pub fn take_legacy_preferences(&mut self) -> bool {
+        let found = self.legacy_language.is_some() || self.legacy_notifications_off;
+        if self.legacy_language.is_some() {
+            self.prefs.language = self.legacy_language.take();
+            self.prefs.language_detected = self.legacy_language_detected;
+        }
+        if self.legacy_notifications_off {
+            self.prefs.notifications_off = true;
+            self.legacy_notifications_off = false;
+        }
+        found
+    }
+
+    /// Appends to `transitions`, dropping the oldest once it holds
+    /// `TRANSITION_HISTORY_SIZE`.
+    pub fn record_transition(&mut self, transition: StateTransition) {
+        if self.transitions.len() == TRANSITION_HISTORY_SIZE {
+            self.transitions.pop_front();
+        }
+        self.transitions.push_back(transition);
+    }
+
+    /// Notes the first line of our reply against the message just recorded.
+    pub fn record_outcome(&mut self, reply: &str) {
+        let Some(last) = self.inbound_history.last_mut() else {
+            return;
+        };
+        let line = reply
+            .lines()
+            .map(|line| line.replace('*', "").trim().to_string())
+            .find(|line| !line.is_empty())
+            .unwrap_or_default();
+        if !line.is_empty() {
+            last.outcome = Some(line.chars().take(80).collect());
+        }
+    }
+
+    /// Copy safe to show to operators: account numbers reduced to their last four digits.
+    pub fn redacted(&self) -> Self {
+        let mut session = self.clone();
+        for details in session.bank_choices.iter_mut() {
+            details.account_number = mask_account_number(&details.account_number);
+        }
+        if let Some(details) = session.pending_bank_details.as_mut() {
+            details.account_number = mask_account_number(&details.account_number);
+        }
+        if let Some(verification) = session.pending_bank_verification.as_mut() {
+            verification.account_number = mask_account_number(&verification.account_number);
+        }
+        session
+    }
+}
+
+/// Stored by name. A name this build doesn't know (written by a newer one, or a state
+/// since removed) loads as `Initial` rather than failing the whole session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
 pub enum UserState {
+    #[default]
     Initial,
     AccountCreation,
     BankDetailsEntry,
     OfframpConfirmation,
     BankDetailsConfirmation,
     SavedBankConfirmation,
+    /// Several saved banks; waiting for the user to pick one by number from
+    /// `bank_choices`.
+    BankSelection,
+    /// Large withdrawal waiting for the user to type the amount back.
+    AmountConfirmation,
+    /// Offramp initiated but the payout moved away from the quote; waiting on the user.
+    PayoutApproval,
+    /// `verify` found a saved bank under a new account name; waiting for the user to
+    /// approve updating it.
+    BankNameUpdate,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+impl UserState {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "Initial" => UserState::Initial,
+            "AccountCreation" => UserState::AccountCreation,
+            "BankDetailsEntry" => UserState::BankDetailsEntry,
+            "OfframpConfirmation" => UserState::OfframpConfirmation,
+            "BankDetailsConfirmation" => UserState::BankDetailsConfirmation,
+            "SavedBankConfirmation" => UserState::SavedBankConfirmation,
+            "BankSelection" => UserState::BankSelection,
+            "AmountConfirmation" => UserState::AmountConfirmation,
+            "PayoutApproval" => UserState::PayoutApproval,
+            "BankNameUpdate" => UserState::BankNameUpdate,
+            _ => return None,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for UserState {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(UserState::from_name(&name).unwrap_or_else(|| {
+            eprintln!(
+                "Stored session has unknown state {:?}; starting it over from Initial",
+                name
+            );
+            UserState::Initial
+        }))
+    }
+}
+
+/// Reply from `SERVER_RATE_ENDPOINT`.
+#[derive(Debug, Deserialize)]
+pub struct RateResponse {
+    pub data: RateData,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RateData {
+    pub usd_ngn_rate: f64,
+}
+
+/// Reply from `SERVER_BALANCE_ENDPOINT`. A wallet that has never held anything comes
+/// back without a balance.
+#[derive(Debug, Deserialize)]
+pub struct BalanceResponse {
+    #[serde(default)]
+    pub data: Option<BalanceData>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BalanceData {
+    /// A decimal string, e.g. `"250.00"`.
+    #[serde(default)]
+    pub balance: Option<String>,
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Reply from `SERVER_GET_ADDRESS_ENDPOINT`; no address means no account yet.
+#[derive(Debug, Deserialize)]
+pub struct AddressResponse {
+    #[serde(default)]
+    pub data: Option<AddressData>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddressData {
+    #[serde(default)]
+    pub controller_address: Option<String>,
+}
+
+/// Reply from `SERVER_BANK_ACCOUNT_VERIFY_ENDPOINT`.
+#[derive(Debug, Deserialize)]
+pub struct BankVerificationEnvelope {
+    pub data: BankVerificationResponse,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BankVerificationResponse {
     pub bank_name: String,
     pub account_number: String,
@@ -30,6 +523,18 @@ pub struct BankVerificationResponse {
     pub bank_code: String,
 }
 
+impl fmt::Debug for BankVerificationResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BankVerificationResponse")
+            .field("bank_name", &self.bank_name)
+            .field("account_number", &mask_account_number(&self.account_number))
+            .field("account_name", &self.account_name)
+            .field("bank_code", &self.bank_code)
+            .finish()
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct CreateControllerData {
     pub controller_address: String,
@@ -38,6 +543,7 @@ pub struct CreateControllerData {
     pub session_options: serde_json::Value,
 }
 
+#[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct CreateControllerAPIResponse {
     pub success: String,
@@ -45,15 +551,29 @@ pub struct CreateControllerAPIResponse {
     pub data: CreateControllerData,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BankDetails {
     pub bank_details_id: String,
     pub bank_name: String,
     #[serde(rename = "bank_account_number")]
     pub account_number: String,
     pub account_name: String,
+    #[serde(default)]
+    pub bank_code: Option<String>,
+}
+
+impl fmt::Debug for BankDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BankDetails")
+            .field("bank_details_id", &self.bank_details_id)
+            .field("bank_name", &self.bank_name)
+            .field("account_number", &mask_account_number(&self.account_number))
+            .field("account_name", &self.account_name)
+            .finish()
+    }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, serde::Deserialize)]
 pub struct BankListResponse {
     pub status: String,
@@ -73,17 +593,20 @@ pub struct ReceivePaymentRequest {
     pub phone: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct DisbursementDetails {
     pub account_name: String,
     pub account_number: String,
     pub bank_name: String,
     pub bank_code: String,
-    pub amount: f64, 
+    pub amount: f64,
     pub currency: String,
+    #[serde(default)]
     pub crypto_tx_hash: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct InitDisbursementResponse {
     pub success: bool,
@@ -93,7 +616,7 @@ pub struct InitDisbursementResponse {
     pub error: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TransactionStatus {
     pub transaction_id: String,
     pub reference: String,
@@ -109,4 +632,207 @@ pub struct WebhookStatusResponse {
     pub success: bool,
     pub data: Option<TransactionStatus>,
     pub message: String,
-}
\ No newline at end of file
+}
+
+/// Reply from `TRANSACTION_STATUS_BATCH_ENDPOINT`; references the backend doesn't know
+/// are simply left out of `data`.
+#[derive(Debug, Deserialize)]
+pub struct BatchStatusResponse {
+    pub success: bool,
+    #[serde(default)]
+    pub data: Vec<TransactionStatus>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionHistoryResponse {
+    pub success: bool,
+    #[serde(default)]
+    pub data: Vec<TransactionRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionRecord {
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub reference: String,
+    pub token_symbol: String,
+    pub crypto_amount: f64,
+    #[serde(default)]
+    pub fiat_amount: Option<f64>,
+    #[serde(default)]
+    pub bank_name: Option<String>,
+    pub status: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1: &str = include_str!("../tests/fixtures/sessions/v1.json");
+    const V10: &str = include_str!("../tests/fixtures/sessions/v10.json");
+
+    fn bank() -> BankDetails {
+        BankDetails {
+            bank_details_id: "bank-1".to_string(),
+            bank_name: "Opay".to_string(),
+            account_number: "0123456789".to_string(),
+            account_name: "ADA OBI".to_string(),
+            bank_code: Some("999".to_string()),
+        }
+    }
+
+    #[test]
+    fn a_session_round_trips_through_json() {
+        let mut session = UserSessions::new("+2348012345678");
+        session.state = UserState::BankSelection;
+        session.pending_amount = Some(25.0);
+        session.pending_currency = Some("USDT".to_string());
+        session.bank_choices = vec![bank(), bank()];
+        session.quoted_rate = Some(1523.45);
+        session.amount_confirmation_attempts = 2;
+        session.record_inbound("withdraw 25 usdt");
+
+        let stored = serde_json::to_value(&session).unwrap();
+        let loaded: UserSessions = serde_json::from_value(stored.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), stored);
+        assert_eq!(loaded.schema_version, SESSION_SCHEMA_VERSION);
+        assert_eq!(loaded.state, UserState::BankSelection);
+        // Stored whole; only views for people are masked
+        assert_eq!(loaded.bank_choices[0].account_number, "0123456789");
+    }
+
+    #[test]
+    fn a_v1_session_loads_and_upgrades() {
+        let session: UserSessions = serde_json::from_str(V1).unwrap();
+        assert_eq!(session.schema_version, 1);
+        assert_eq!(session.state, UserState::SavedBankConfirmation);
+        assert_eq!(session.pending_amount, Some(25.0));
+        assert_eq!(
+            session.pending_bank_details.as_ref().unwrap().bank_code,
+            None
+        );
+        assert!(session.correlation_id.is_none());
+        assert!(session.bank_choices.is_empty());
+        assert!(session.transitions.is_empty());
+
+        let session = session.upgrade();
+        assert_eq!(session.schema_version, SESSION_SCHEMA_VERSION);
+        // A single saved bank was never a pick, so the state stands
+        assert_eq!(session.state, UserState::SavedBankConfirmation);
+    }
+
+    #[test]
+    fn a_v10_session_hands_its_preferences_over() {
+        let mut session: UserSessions = serde_json::from_str(V10).unwrap();
+        assert!(session.take_legacy_preferences());
+        assert_eq!(session.prefs.language, Some(Language::Fr));
+        assert!(session.prefs.language_detected);
+        assert!(session.prefs.notifications_off);
+        assert!(!session.take_legacy_preferences());
+
+        let saved = serde_json::to_value(&session).unwrap();
+        assert!(saved.get("language").is_none(), "{}", saved);
+    }
+
+    #[test]
+    fn an_unknown_state_loads_as_initial() {
+        let stored = V1.replace("SavedBankConfirmation", "SomeFutureState");
+        let session: UserSessions = serde_json::from_str(&stored).unwrap();
+        assert_eq!(session.state, UserState::Initial);
+    }
+
+    #[test]
+    fn debug_and_operator_views_mask_account_numbers() {
+        let mut session: UserSessions = serde_json::from_str(V1).unwrap();
+        session.bank_choices = vec![bank()];
+        session.pending_bank_verification = Some(BankVerificationResponse {
+            bank_name: "Opay".to_string(),
+            account_number: "0123456789".to_string(),
+            account_name: "ADA OBI".to_string(),
+            bank_code: "999".to_string(),
+        });
+
+        assert!(!format!("{:?}", session).contains("0123456789"));
+        let shown = serde_json::to_string(&session.redacted()).unwrap();
+        assert!(!shown.contains("0123456789"), "{}", shown);
+        assert!(shown.contains("6789"), "{}", shown);
+    }
+
+    fn rules(size: usize) -> HistoryRules {
+        HistoryRules {
+            size,
+            retention: chrono::Duration::hours(72),
+            max_chars: 30,
+            mask: true,
+        }
+    }
+
+    fn bodies(session: &UserSessions) -> Vec<&str> {
+        session
+            .inbound_history
+            .iter()
+            .map(|message| message.body.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn the_history_keeps_only_the_newest_messages() {
+        let mut session = UserSessions::new("+2348012345678");
+        let now = chrono::Utc::now();
+        for body in ["one", "two", "three", "four", "five"] {
+            session.push_inbound(body, &rules(3), now);
+        }
+        assert_eq!(bodies(&session), ["three", "four", "five"]);
+
+        session.push_inbound("six", &rules(0), now);
+        assert!(session.inbound_history.is_empty());
+    }
+
+    #[test]
+    fn messages_past_retention_are_dropped_on_the_next_one() {
+        let mut session = UserSessions::new("+2348012345678");
+        let now = chrono::Utc::now();
+        session.push_inbound("old", &rules(20), now - chrono::Duration::hours(73));
+        session.push_inbound("recent", &rules(20), now - chrono::Duration::hours(71));
+        session.push_inbound("new", &rules(20), now);
+        assert_eq!(bodies(&session), ["recent", "new"]);
+    }
+
+    #[test]
+    fn stored_bodies_are_trimmed_cut_and_masked() {
+        let mut session = UserSessions::new("+2348012345678");
+        let now = chrono::Utc::now();
+        session.push_inbound("  send to 0123456789  ", &rules(20), now);
+        session.push_inbound(&"x".repeat(50), &rules(20), now);
+        let unmasked = HistoryRules {
+            mask: false,
+            ..rules(20)
+        };
+        session.push_inbound("send to 0123456789", &unmasked, now);
+
+        let stored = bodies(&session);
+        assert!(!stored[0].contains("0123456789"), "{}", stored[0]);
+        assert!(stored[0].starts_with("send to ") && stored[0].ends_with("6789"));
+        assert_eq!(stored[1], "x".repeat(30));
+        assert_eq!(stored[2], "send to 0123456789");
+    }
+
+    #[test]
+    fn an_outcome_is_the_first_line_of_the_reply() {
+        let mut session = UserSessions::new("+2348012345678");
+        // Nothing to attach it to yet
+        session.record_outcome("✅ Done");
+
+        session.push_inbound("balance", &rules(20), chrono::Utc::now());
+        session.record_outcome("\n💰 *Your Balance*\n\n250 USDT");
+        assert_eq!(
+            session.inbound_history[0].outcome.as_deref(),
+            Some("💰 Your Balance")
+        );
+        session.record_outcome(&"y".repeat(100));
+        assert_eq!(
+            session.inbound_history[0].outcome.as_deref().map(str::len),
+            Some(80)
+        );
+    }
+}
@@ -0,0 +1,512 @@
+/// Shown instead of an amount the backend didn't give us, rather than a misleading `0.00`.
+pub const MISSING_AMOUNT: &str = "—";
+
+/// `₦1,523,450.75`, `$12.00` or `1,000.00 USDT`: two decimals and thousands separators,
+/// with a symbol for fiat and the code after the number for tokens.
+pub fn format_money(amount: f64, currency: &str) -> String {
+    with_currency(group_thousands(amount), currency)
+}
+
+fn with_currency(number: String, currency: &str) -> String {
+    match currency.trim().to_uppercase().as_str() {
+        "NGN" => format!("₦{}", number),
+        "USD" => format!("${}", number),
+        "" => number,
+        code => format!("{} {}", number, code),
+    }
+}
+
+/// For amounts that may be missing from a backend response; `None` and zero both mean
+/// "we don't know", since no withdrawal is ever for nothing.
+pub fn format_optional_money(amount: Option<f64>, currency: Option<&str>) -> String {
+    match amount {
+        Some(amount) if amount.is_finite() && amount != 0.0 => {
+            format_money(amount, currency.unwrap_or(""))
+        }
+        _ => MISSING_AMOUNT.to_string(),
+    }
+}
+
+fn group_thousands(amount: f64) -> String {
+    let fixed = format!("{:.2}", amount.abs());
+    let (whole, fraction) = fixed.split_once('.').unwrap_or((&fixed, "00"));
+    let grouped = group_digits(whole);
+
+    let sign = if amount < 0.0 && fixed != "0.00" {
+        "-"
+    } else {
+        ""
+    };
+    format!("{}{}.{}", sign, grouped, fraction)
+}
+
+fn group_digits(whole: &str) -> String {
+    let mut grouped = String::with_capacity(whole.len() + whole.len() / 3);
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// `raw` without its thousands separators, or `None` when its commas aren't thousands
+/// separators: `2,5` is not 25, and `1,00` or `1000,000` are typos, not amounts.
+pub fn ungroup(raw: &str) -> Option<String> {
+    let (whole, fraction) = raw.split_once('.').unwrap_or((raw, ""));
+    if fraction.contains(',') {
+        return None;
+    }
+    if !whole.contains(',') {
+        return Some(raw.to_string());
+    }
+    let digits = |group: &str| group.bytes().all(|b| b.is_ascii_digit());
+    let mut groups = whole.split(',');
+    let first = groups.next().unwrap_or_default();
+    let grouped = (1..=3).contains(&first.len())
+        && digits(first)
+        && groups.all(|group| group.len() == 3 && digits(group));
+    grouped.then(|| raw.replace(',', ""))
+}
+
+/// Above this a naira amount can't be held to the kobo in an `f64` (2^53 kobo).
+const MAX_EXACT_NAIRA: f64 = 9_007_199_254_740_992.0 / 100.0;
+
+/// `amount` tokens at `rate`, or `None` when the product is non-finite, not positive or
+/// too large to represent to the kobo, so no quote is built on it.
+pub fn naira_for(amount: f64, rate: f64) -> Option<f64> {
+    let naira = amount * rate;
+    (naira.is_finite() && naira > 0.0 && naira <= MAX_EXACT_NAIRA).then_some(naira)
+}
+
+/// Decimals of the smallest unit: kobo and cents for fiat, the token's own otherwise.
+fn currency_decimals(currency: &str) -> u32 {
+    match currency.trim().to_uppercase().as_str() {
+        "NGN" | "USD" => 2,
+        token => token_decimals(token),
+    }
+}
+
+/// Whether `amount`, meant in whole units, looks like it was sent in the smallest unit
+/// instead. Against an `expected` value that's being off by exactly the scale; without
+/// one, a whole number at least as large as the scale, which no real balance is.
+pub fn looks_unscaled(amount: f64, decimals: u32, expected: Option<f64>) -> bool {
+    if !amount.is_finite() || amount <= 0.0 || decimals == 0 {
+        return false;
+    }
+    let scale = 10f64.powi(decimals as i32);
+    match expected {
+        Some(expected) if expected > 0.0 => (amount / expected / scale - 1.0).abs() < 0.05,
+        _ => amount.fract() == 0.0 && amount >= scale,
+    }
+}
+
+/// Logs `what` when its amount looks like un-scaled base units; see `looks_unscaled`.
+/// Only flagged: the amount is still shown as given.
+pub fn warn_if_unscaled(what: &str, amount: f64, currency: &str, expected: Option<f64>) {
+    let decimals = currency_decimals(currency);
+    if looks_unscaled(amount, decimals, expected) {
+        eprintln!(
+            "WARN: {} of {} {} looks like un-scaled base units ({} decimals)",
+            what, amount, currency, decimals
+        );
+    }
+}
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+const SCALES: [&str; 7] = [
+    "",
+    "thousand",
+    "million",
+    "billion",
+    "trillion",
+    "quadrillion",
+    "quintillion",
+];
+
+fn below_thousand(n: u64) -> String {
+    let mut words = Vec::new();
+    if n >= 100 {
+        words.push(format!("{} hundred", ONES[(n / 100) as usize]));
+    }
+    match n % 100 {
+        0 => {}
+        rest @ 1..20 => words.push(ONES[rest as usize].to_string()),
+        rest if rest % 10 == 0 => words.push(TENS[(rest / 10) as usize].to_string()),
+        rest => words.push(format!(
+            "{}-{}",
+            TENS[(rest / 10) as usize],
+            ONES[(rest % 10) as usize]
+        )),
+    }
+    words.join(" ")
+}
+
+/// `5000000` -> `five million`, `12.5` -> `twelve point five`: an amount spelled out, to
+/// two decimals, so a stray run of zeros can't hide in the digits.
+pub fn in_words(amount: f64) -> String {
+    let fixed = format!("{:.2}", amount.abs());
+    let (whole, fraction) = fixed.split_once('.').unwrap_or((&fixed, "00"));
+    let mut whole: u64 = whole.parse().unwrap_or(u64::MAX);
+
+    let mut groups = Vec::new();
+    for scale in SCALES {
+        let group = whole % 1000;
+        if group > 0 {
+            let words = below_thousand(group);
+            groups.push(if scale.is_empty() {
+                words
+            } else {
+                format!("{} {}", words, scale)
+            });
+        }
+        whole /= 1000;
+        if whole == 0 {
+            break;
+        }
+    }
+    groups.reverse();
+    let mut words = if groups.is_empty() {
+        ONES[0].to_string()
+    } else {
+        groups.join(" ")
+    };
+
+    let fraction = fraction.trim_end_matches('0');
+    if !fraction.is_empty() {
+        words.push_str(" point");
+        for digit in fraction.bytes() {
+            words.push(' ');
+            words.push_str(ONES[(digit - b'0') as usize]);
+        }
+    }
+    words
+}
+
+/// A token we pay out. USDT and USDC both use 6 decimals on Starknet.
+pub struct TokenInfo {
+    pub symbol: &'static str,
+    /// The contract the backend reports balances by, where we know it.
+    pub address: Option<&'static str>,
+    pub decimals: u32,
+    pub emoji: &'static str,
+}
+
+pub const TOKENS: &[TokenInfo] = &[
+    TokenInfo {
+        symbol: "USDT",
+        address: Some("0x07d54bad6d6fcff799133a8c0b1fb8120876bb080d75cd601a5c68164d6f6d75"),
+        decimals: 6,
+        emoji: "💵",
+    },
+    TokenInfo {
+        symbol: "USDC",
+        address: None,
+        decimals: 6,
+        emoji: "💵",
+    },
+];
+
+/// Decimals for a token we don't know; the Starknet ERC-20 default.
+const DEFAULT_DECIMALS: u32 = 18;
+
+/// Looks a token up by contract address or symbol.
+pub fn token_info(token: &str) -> Option<&'static TokenInfo> {
+    let token = token.trim();
+    TOKENS.iter().find(|info| {
+        info.symbol.eq_ignore_ascii_case(token)
+            || info
+                .address
+                .is_some_and(|address| address.eq_ignore_ascii_case(token))
+    })
+}
+
+pub fn token_decimals(token: &str) -> u32 {
+    token_info(token).map_or(DEFAULT_DECIMALS, |info| info.decimals)
+}
+
+/// An exact token amount, in the token's smallest unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TokenAmount {
+    pub units: u128,
+    pub decimals: u32,
+}
+
+/// `whole[.fraction]` in whole tokens, e.g. `123.456` or `5`. Fraction digits beyond
+/// `decimals` are dropped when `truncate`, refused otherwise.
+fn parse_whole_tokens(raw: &str, decimals: u32, truncate: bool) -> Result<TokenAmount, String> {
+    let invalid = || format!("invalid token amount {:?}", raw);
+    let scale = 10u128
+        .checked_pow(decimals)
+        .ok_or_else(|| format!("unsupported token decimals {}", decimals))?;
+
+    let (whole, fraction) = raw.split_once('.').unwrap_or((raw, ""));
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !digits(whole) || !digits(fraction) {
+        return Err(invalid());
+    }
+    if !truncate && fraction.len() > decimals as usize {
+        return Err(format!(
+            "{:?} has more than {} decimal places",
+            raw, decimals
+        ));
+    }
+    let whole: u128 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().map_err(|_| invalid())?
+    };
+    let kept = &fraction[..fraction.len().min(decimals as usize)];
+    let fraction: u128 = format!("{:0<width$}", kept, width = decimals as usize)
+        .parse()
+        .unwrap_or(0);
+    let units = whole
+        .checked_mul(scale)
+        .and_then(|units| units.checked_add(fraction))
+        .ok_or_else(invalid)?;
+    Ok(TokenAmount { units, decimals })
+}
+
+impl TokenAmount {
+    /// Reads an amount the way the backend sends it: `"123.456"` is in whole tokens, while
+    /// a bare integer (`"5000000"`) or hex (`"0x4c4b40"`) is the raw on-chain amount in
+    /// the smallest unit. Fraction digits beyond `decimals` are dropped, never rounded up,
+    /// so we can't show more than the user holds. Never for what users type; see `parse`.
+    pub fn from_backend(raw: &str, decimals: u32) -> Result<Self, String> {
+        let raw = raw.trim();
+        let invalid = || format!("invalid token amount {:?}", raw);
+        if 10u128.checked_pow(decimals).is_none() {
+            return Err(format!("unsupported token decimals {}", decimals));
+        }
+
+        let units = if let Some(hex) = raw.strip_prefix("0x").or(raw.strip_prefix("0X")) {
+            if hex.is_empty() {
+                return Err(invalid());
+            }
+            u128::from_str_radix(hex, 16).map_err(|_| invalid())?
+        } else if raw.contains('.') {
+            return parse_whole_tokens(raw, decimals, true);
+        } else if !raw.is_empty() && raw.bytes().all(|b| b.is_ascii_digit()) {
+            raw.parse().map_err(|_| invalid())?
+        } else {
+            return Err(invalid());
+        };
+        Ok(TokenAmount { units, decimals })
+    }
+
+    /// Reads an amount a user typed, always in whole tokens: `5` is five tokens, never
+    /// five of the smallest unit. Thousands separators are fine; more decimal places than
+    /// the token has is refused rather than quietly cut.
+    pub fn parse(raw: &str, decimals: u32) -> Result<Self, String> {
+        let ungrouped =
+            ungroup(raw.trim()).ok_or_else(|| format!("invalid token amount {:?}", raw))?;
+        parse_whole_tokens(&ungrouped, decimals, false)
+    }
+
+    /// An amount we hold as `f64` (amounts in sessions and payloads), to the nearest unit.
+    pub fn from_whole_tokens(amount: f64, decimals: u32) -> Result<Self, String> {
+        let invalid = || format!("invalid token amount {}", amount);
+        let scale = 10f64.powi(decimals as i32);
+        let units = (amount * scale).round();
+        if !units.is_finite() || units < 0.0 || units >= u128::MAX as f64 {
+            return Err(invalid());
+        }
+        Ok(TokenAmount {
+            units: units as u128,
+            decimals,
+        })
+    }
+
+    /// The largest `f64` that doesn't read back as more than this amount, for payloads
+    /// that carry amounts as numbers: "withdraw all" must never ask for more than there is.
+    pub fn at_most_f64(&self) -> f64 {
+        let mut value = self.whole_tokens();
+        while value > 0.0
+            && Self::from_whole_tokens(value, self.decimals).is_ok_and(|back| back > *self)
+        {
+            value = value.next_down();
+        }
+        value
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.units == 0
+    }
+
+    /// In whole tokens; close enough for checks, never for display.
+    pub fn whole_tokens(&self) -> f64 {
+        self.units as f64 / 10f64.powi(self.decimals as i32)
+    }
+
+    fn split(&self) -> (u128, u128) {
+        let scale = 10u128.pow(self.decimals);
+        (self.units / scale, self.units % scale)
+    }
+
+    /// `123,456,789.123456`: every significant decimal, but at least two.
+    pub fn display(&self) -> String {
+        let (whole, fraction) = self.split();
+        let mut fraction = format!("{:0width$}", fraction, width = self.decimals as usize);
+        while fraction.len() > 2 && fraction.ends_with('0') {
+            fraction.pop();
+        }
+        let fraction = format!("{:0<2}", fraction);
+        format!("{}.{}", group_digits(&whole.to_string()), fraction)
+    }
+
+    /// `123456789.123456`: `display` without the thousands separators, for files.
+    pub fn plain(&self) -> String {
+        self.display().replace(',', "")
+    }
+
+    /// Like `format_money`, but cut to two decimals rather than rounded.
+    pub fn display_money(&self, currency: &str) -> String {
+        let (whole, fraction) = self.split();
+        let cents = if self.decimals >= 2 {
+            fraction / 10u128.pow(self.decimals - 2)
+        } else {
+            fraction * 10u128.pow(2 - self.decimals)
+        };
+        with_currency(
+            format!("{}.{:02}", group_digits(&whole.to_string()), cents),
+            currency,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usdt(units: u128) -> TokenAmount {
+        TokenAmount { units, decimals: 6 }
+    }
+
+    #[test]
+    fn typed_amounts_are_whole_tokens() {
+        assert_eq!(TokenAmount::parse("5", 6), Ok(usdt(5_000_000)));
+        assert_eq!(TokenAmount::parse(" 1,000.5 ", 6), Ok(usdt(1_000_500_000)));
+        assert_eq!(TokenAmount::parse(".25", 6), Ok(usdt(250_000)));
+        assert_eq!(TokenAmount::parse("0.000001", 6), Ok(usdt(1)));
+
+        let err = TokenAmount::parse("0.0000001", 6).unwrap_err();
+        assert!(err.contains("more than 6 decimal places"), "{}", err);
+        for bad in [
+            "", ".", "0x10", "-5", "5e3", "five", "1.2.3", "2,5", "1000,000",
+        ] {
+            assert!(TokenAmount::parse(bad, 6).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn backend_integers_are_base_units() {
+        assert_eq!(TokenAmount::from_backend("5000000", 6), Ok(usdt(5_000_000)));
+        assert_eq!(
+            TokenAmount::from_backend("0x4c4b40", 6),
+            Ok(usdt(5_000_000))
+        );
+        assert_eq!(
+            TokenAmount::from_backend("123456789.1234569", 6),
+            Ok(usdt(123_456_789_123_456))
+        );
+        for bad in ["", "0x", "1,000", "-1", "12a"] {
+            assert!(TokenAmount::from_backend(bad, 6).is_err(), "{:?}", bad);
+        }
+        assert!(TokenAmount::from_backend("1", 40).is_err());
+    }
+
+    #[test]
+    fn f64_amounts_convert_to_the_nearest_unit_and_back_never_over() {
+        assert_eq!(TokenAmount::from_whole_tokens(0.1, 6), Ok(usdt(100_000)));
+        assert_eq!(
+            TokenAmount::from_whole_tokens(250.0, 6),
+            Ok(usdt(250_000_000))
+        );
+        assert!(TokenAmount::from_whole_tokens(-1.0, 6).is_err());
+        assert!(TokenAmount::from_whole_tokens(f64::NAN, 6).is_err());
+
+        for balance in [usdt(1), usdt(123_456_789_123_456), usdt(u64::MAX as u128)] {
+            let amount = balance.at_most_f64();
+            assert!(TokenAmount::from_whole_tokens(amount, 6).unwrap() <= balance);
+        }
+        assert_eq!(usdt(0).at_most_f64(), 0.0);
+    }
+
+    #[test]
+    fn base_units_read_as_whole_tokens_are_flagged() {
+        // A balance or payout with no figure to compare it to
+        assert!(looks_unscaled(5_000_000.0, 6, None));
+        assert!(looks_unscaled(250_000_000.0, 6, None));
+        assert!(looks_unscaled(1_500_000.0, 2, None));
+        for plausible in [5.0, 250.0, 999_999.0, 1_000_000.5, 0.000001] {
+            assert!(!looks_unscaled(plausible, 6, None), "{}", plausible);
+        }
+        for nonsense in [0.0, -5_000_000.0, f64::NAN, f64::INFINITY] {
+            assert!(!looks_unscaled(nonsense, 6, None), "{}", nonsense);
+        }
+        assert!(!looks_unscaled(5_000_000.0, 0, None));
+
+        // Against the quoted amount, off by exactly the scale
+        assert!(looks_unscaled(1_500_000.0, 2, Some(15_000.0)));
+        assert!(looks_unscaled(1_512_000.0, 2, Some(15_000.0)));
+        assert!(!looks_unscaled(15_000.0, 2, Some(15_000.0)));
+        assert!(!looks_unscaled(15_300.0, 2, Some(15_000.0)));
+        assert!(!looks_unscaled(150_000.0, 2, Some(15_000.0)));
+        // A zero quote compares to nothing
+        assert!(looks_unscaled(1_500_000.0, 2, Some(0.0)));
+    }
+
+    #[test]
+    fn a_quote_that_cannot_be_held_to_the_kobo_is_refused() {
+        assert_eq!(naira_for(10.0, 1500.0), Some(15_000.0));
+        assert_eq!(naira_for(0.5, 1523.45), Some(761.725));
+        assert!(naira_for(1_000_000_000.0, 1500.0).is_some());
+
+        assert_eq!(naira_for(1_000_000_000_000.0, 1500.0), None);
+        assert_eq!(naira_for(f64::MAX, 2.0), None);
+        assert_eq!(naira_for(10.0, f64::NAN), None);
+        assert_eq!(naira_for(10.0, 0.0), None);
+        assert_eq!(naira_for(-10.0, 1500.0), None);
+    }
+
+    #[test]
+    fn amounts_are_spelled_out_to_two_decimals() {
+        assert_eq!(in_words(5_000_000.0), "five million");
+        assert_eq!(in_words(12.5), "twelve point five");
+        assert_eq!(in_words(0.0), "zero");
+        assert_eq!(in_words(100.0), "one hundred");
+        assert_eq!(in_words(1_000_001.0), "one million one");
+        assert_eq!(
+            in_words(1_234_567.08),
+            "one million two hundred thirty-four thousand five hundred sixty-seven point zero eight"
+        );
+        assert_eq!(in_words(40.999), "forty-one");
+    }
+}
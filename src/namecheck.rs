@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::audit::{self, AuditEvent};
+use crate::backend;
+use crate::model::UserSessions;
+use crate::privacy::mask_phone;
+
+/// Shown above the confirmation when the verified account looks like someone else's.
+pub const MISMATCH_WARNING: &str = "⚠️ This account belongs to a different name than your \
+    profile. Only confirm if you're sure you want to send to it.";
+
+/// Shown instead of the confirmation when `NAME_MISMATCH_ACTION=block`.
+pub const MISMATCH_BLOCKED: &str = "⚠️ *Account not in your name*\n\n\
+    Withdrawals can only go to an account registered to the name on your profile. \
+    Please enter one of your own accounts, or type `support` if you think this is wrong.";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Off,
+    Warn,
+    Block,
+}
+
+/// `NAME_MISMATCH_ACTION`: `warn` (the default) asks for confirmation as usual under a
+/// warning, `block` refuses the account, `off` skips the check.
+fn action() -> Action {
+    match std::env::var("NAME_MISMATCH_ACTION")
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "off" => Action::Off,
+        "block" => Action::Block,
+        _ => Action::Warn,
+    }
+}
+
+/// `NAME_MATCH_THRESHOLD`, between 0 and 1 (default 0.5).
+fn threshold() -> f64 {
+    std::env::var("NAME_MATCH_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|t| (0.0..=1.0).contains(t))
+        .unwrap_or(0.5)
+}
+
+fn tokens(name: &str) -> Vec<String> {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_uppercase)
+        .collect()
+}
+
+/// Share of the shorter name's words found in the longer one, so a middle name or a
+/// swapped order (`JOHN A DOE` for `Doe John`) still scores 1. An initial matches any
+/// word it starts.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (tokens(a), tokens(b));
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if short.is_empty() {
+        return 0.0;
+    }
+    let mut unused = long;
+    let mut matched = 0;
+    for word in &short {
+        let found = unused.iter().position(|other| {
+            other == word
+                || (word.len() == 1 && other.starts_with(word.as_str()))
+                || (other.len() == 1 && word.starts_with(other.as_str()))
+        });
+        if let Some(i) = found {
+            unused.swap_remove(i);
+            matched += 1;
+        }
+    }
+    matched as f64 / short.len() as f64
+}
+
+/// The name the user registered with, from `SERVER_USER_PROFILE_ENDPOINT`. `None` when
+/// the endpoint isn't configured or has no name for them, which skips the check.
+async fn registered_name(phone: &str) -> Option<String> {
+    let url = std::env::var("SERVER_USER_PROFILE_ENDPOINT")
+        .ok()
+        .filter(|url| !url.is_empty())?;
+    let response = backend::signed_get(
+        &backend::client(),
+        &url,
+        &[("phone", phone.trim_start_matches('+'))],
+    )
+    .timeout(Duration::from_secs(10))
+    .send()
+    .await;
+    let body: Value = match response {
+        Ok(res) if res.status().is_success() => res.json().await.ok()?,
+        Ok(res) => {
+            eprintln!(
+                "Profile lookup for {} returned {}",
+                mask_phone(phone),
+                res.status()
+            );
+            return None;
+        }
+        Err(e) => {
+            eprintln!("Profile lookup for {} failed: {}", mask_phone(phone), e);
+            return None;
+        }
+    };
+    ["/data/full_name", "/data/name", "/full_name", "/name"]
+        .iter()
+        .filter_map(|pointer| body.pointer(pointer)?.as_str())
+        .map(str::trim)
+        .find(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+/// Compares a verified account name against the user's registered one. Returns the
+/// action to take when they differ; a mismatch is audited for compliance review either
+/// way.
+pub async fn check(session: &UserSessions, account_name: &str) -> Option<Action> {
+    let action = action();
+    if action == Action::Off {
+        return None;
+    }
+    let registered = registered_name(&session.phone).await?;
+    let similarity = similarity(&registered, account_name);
+    if similarity >= threshold() {
+        return None;
+    }
+
+    println!(
+        "Account name for {} doesn't match their profile (similarity {:.2})",
+        mask_phone(&session.phone),
+        similarity
+    );
+    audit::record(
+        &session.phone,
+        session.correlation_id.as_deref(),
+        None,
+        AuditEvent::AccountNameMismatch {
+            account_name: account_name.to_string(),
+            similarity,
+            blocked: action == Action::Block,
+        },
+    );
+    Some(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_name_however_written_matches_fully() {
+        for (registered, account) in [
+            ("Ada Obi", "ADA OBI"),
+            ("Doe John", "JOHN A DOE"),
+            ("Chinedu-Okafor", "OKAFOR CHINEDU"),
+            ("J. Doe", "JOHN DOE"),
+        ] {
+            assert_eq!(similarity(registered, account), 1.0, "{}", account);
+        }
+    }
+
+    #[test]
+    fn a_partly_shared_name_scores_the_share_of_its_words() {
+        // One surname shared, the first names not
+        assert_eq!(similarity("Ada Obi", "CHIOMA OBI"), 0.5);
+        assert_eq!(similarity("Ada Ngozi Obi", "ADA EMEKA OKAFOR"), 1.0 / 3.0);
+        // A repeated word is only matched once
+        assert_eq!(similarity("Obi Obi", "ADA OBI"), 0.5);
+    }
+
+    #[test]
+    fn a_clearly_different_name_scores_nothing() {
+        assert_eq!(similarity("Ada Obi", "MUSA IBRAHIM"), 0.0);
+        assert_eq!(similarity("Ada Obi", ""), 0.0);
+        assert_eq!(similarity("", "ADA OBI"), 0.0);
+        // An initial only stands for a word it starts
+        assert_eq!(similarity("A. Obi", "MUSA IBRAHIM"), 0.0);
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+
+use actix_web::HttpResponse;
+use serde::Deserialize;
+
+use crate::access::normalize_phone;
+use crate::callbacks::SignedCallback;
+use crate::i18n::{self, Msg};
+use crate::prefs;
+use crate::privacy::mask_phone;
+use crate::server::send_notice;
+use crate::window::Notice;
+
+/// Catalog messages the backend may push, by the id it names them with.
+const TEMPLATES: &[(&str, Msg)] = &[
+    ("settlement_delayed", Msg::SettlementDelayed),
+    ("account_review", Msg::AccountReview),
+    ("withdrawal_failed", Msg::WithdrawalFailed),
+];
+
+#[derive(Debug, Deserialize)]
+pub struct NotifyRequest {
+    pub phone: String,
+    /// An id from `TEMPLATES`; its placeholders are filled from `variables`.
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    /// Sent as-is in place of a template, only with `NOTIFY_ALLOW_FREEFORM=true`.
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Goes out even to users who turned notifications off.
+    #[serde(default)]
+    pub critical: bool,
+}
+
+/// `NOTIFY_ALLOW_FREEFORM=true` accepts `body` in place of a template; off by default so
+/// everything users read is copy we wrote.
+fn freeform_allowed() -> bool {
+    matches!(
+        std::env::var("NOTIFY_ALLOW_FREEFORM").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+fn template(id: &str) -> Option<Msg> {
+    TEMPLATES
+        .iter()
+        .find(|(name, _)| *name == id)
+        .map(|(_, msg)| *msg)
+}
+
+/// Every placeholder filled, and nothing the template doesn't use.
+fn check_variables(msg: Msg, variables: &BTreeMap<String, String>) -> Result<(), String> {
+    let placeholders = i18n::placeholders(msg);
+    let missing: Vec<&str> = placeholders
+        .iter()
+        .filter(|name| variables.get(**name).is_none_or(|v| v.trim().is_empty()))
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("Missing variables: {}", missing.join(", ")));
+    }
+    let unknown: Vec<&str> = variables
+        .keys()
+        .map(String::as_str)
+        .filter(|name| !placeholders.contains(name))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(format!("Unknown variables: {}", unknown.join(", ")));
+    }
+    Ok(())
+}
+
+/// The message in the user's language, or why the request can't be sent.
+async fn compose(request: &NotifyRequest, phone: &str) -> Result<String, String> {
+    match (&request.template, &request.body) {
+        (Some(_), Some(_)) => Err("Send either template or body, not both".to_string()),
+        (Some(id), None) => {
+            let msg = template(id).ok_or_else(|| format!("Unknown template {}", id))?;
+            check_variables(msg, &request.variables)?;
+            let args: Vec<(&str, &str)> = request
+                .variables
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect();
+            let language = prefs::load(phone).await.language.unwrap_or_default();
+            Ok(i18n::render(language, msg, &args))
+        }
+        (None, Some(_)) if !freeform_allowed() => {
+            Err("Free-form bodies are not accepted; use a template".to_string())
+        }
+        (None, Some(body)) if body.trim().is_empty() => Err("body is empty".to_string()),
+        (None, Some(body)) => Ok(body.trim().to_string()),
+        (None, None) => Err("template is required".to_string()),
+    }
+}
+
+fn rejected(message: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "success": false,
+        "message": message,
+    }))
+}
+
+/// `POST /notify`: a signed request from the backend to message a user. The message
+/// joins the user's outbound lane and goes out as a notice, so the 24-hour window and
+/// template fallback apply; the response carries the id it's logged under.
+pub async fn handle_notify(callback: SignedCallback) -> HttpResponse {
+    let request: NotifyRequest = match callback.json() {
+        Ok(request) => request,
+        Err(e) => return rejected(format!("Invalid notification: {}", e)),
+    };
+    let Some(phone) = normalize_phone(&request.phone) else {
+        return rejected("phone is required".to_string());
+    };
+    let message = match compose(&request, &phone).await {
+        Ok(message) => message,
+        Err(reason) => {
+            eprintln!(
+                "Rejected notification for {}: {}",
+                mask_phone(&phone),
+                reason
+            );
+            return rejected(reason);
+        }
+    };
+
+    let message_id = uuid::Uuid::new_v4().to_string();
+    let notice = Notice::Announcement {
+        critical: request.critical,
+    };
+    println!(
+        "Queued notification {} ({}) for {}",
+        message_id,
+        request.template.as_deref().unwrap_or("free-form"),
+        mask_phone(&phone)
+    );
+    tokio::spawn(async move {
+        send_notice(&phone, &message, notice).await;
+    });
+
+    HttpResponse::Accepted().json(serde_json::json!({
+        "success": true,
+        "message_id": message_id,
+    }))
+}
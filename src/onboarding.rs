@@ -0,0 +1,252 @@
+use std::time::Duration;
+
+use crate::backend;
+use crate::model::{BalanceResponse, OnboardingProgress, UserSessions};
+use crate::money::{self, TokenAmount};
+use crate::privacy::mask_phone;
+use crate::server::{get_transaction_history, get_user_bank_details};
+use chrono::Utc;
+
+/// `ONBOARDING_REFRESH_SECS`, default 10 min: how stale the open steps may be before `hi`
+/// or `help` asks the backend about them again.
+fn refresh_after() -> chrono::Duration {
+    chrono::Duration::seconds(
+        std::env::var("ONBOARDING_REFRESH_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(600),
+    )
+}
+
+/// Starts the checklist for an account created just now.
+pub fn start(session: &mut UserSessions) {
+    session.prefs.onboarding = Some(OnboardingProgress::default());
+}
+
+/// Ticks the bank step as soon as one is saved, without waiting for a refresh.
+pub fn bank_added(session: &mut UserSessions) {
+    if let Some(progress) = session.prefs.onboarding.as_mut() {
+        progress.bank_added = true;
+    }
+}
+
+/// Whether any of the user's balance is above zero. Asked directly rather than through
+/// `balance`, which renders a reply rather than a number.
+async fn has_balance(phone: &str) -> Result<bool, String> {
+    let (Ok(endpoint), Ok(token), Ok(address)) = (
+        std::env::var("SERVER_BALANCE_ENDPOINT"),
+        std::env::var("TEST_TOKEN"),
+        std::env::var("TEST_ADDRESS"),
+    ) else {
+        return Err("balance endpoint not configured".to_string());
+    };
+    let response = backend::signed_get(
+        &backend::client(),
+        &endpoint,
+        &[
+            ("phone", phone.trim_start_matches('+')),
+            ("token", &token),
+            ("user_address", &address),
+        ],
+    )
+    .timeout(Duration::from_secs(10))
+    .send()
+    .await
+    .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("returned {}", response.status()));
+    }
+    let body: BalanceResponse = backend::parse_body(response, "Balance").await?;
+    Ok(body
+        .data
+        .and_then(|data| data.balance)
+        .and_then(|balance| TokenAmount::from_backend(&balance, money::token_decimals(&token)).ok())
+        .is_some_and(|balance| !balance.is_zero()))
+}
+
+/// Asks the backend about the steps still open. A step that can't be checked stays as
+/// it was; the next refresh tries again.
+async fn refresh(session: &UserSessions, progress: &mut OnboardingProgress) {
+    if !progress.withdrawn || !progress.funded {
+        match get_transaction_history(session).await {
+            Ok(history) => {
+                // Money can only have left the wallet if it was funded first
+                progress.funded |= !history.is_empty();
+                progress.withdrawn |= history.iter().any(|tx| {
+                    matches!(
+                        tx.status.to_lowercase().as_str(),
+                        "completed" | "successful"
+                    )
+                });
+            }
+            Err(e) => eprintln!(
+                "Onboarding check of history for {} failed: {}",
+                mask_phone(&session.phone),
+                e
+            ),
+        }
+    }
+    if !progress.bank_added {
+        match get_user_bank_details(session).await {
+            Ok(banks) => progress.bank_added = !banks.is_empty(),
+            Err(e) => eprintln!(
+                "Onboarding check of banks for {} failed: {}",
+                mask_phone(&session.phone),
+                e
+            ),
+        }
+    }
+    if !progress.funded {
+        match has_balance(&session.phone).await {
+            Ok(funded) => progress.funded = funded,
+            Err(e) => eprintln!(
+                "Onboarding check of balance for {} failed: {}",
+                mask_phone(&session.phone),
+                e
+            ),
+        }
+    }
+    progress.checked_at = Some(Utc::now());
+}
+
+pub fn render(progress: &OnboardingProgress) -> String {
+    let mark = |done: bool| if done { "✅" } else { "⬜" };
+    let next = if !progress.funded {
+        "Type `address` to get your wallet address and send USDT to it."
+    } else if !progress.bank_added {
+        "Type `withdraw [amount] USDT`; we'll ask for your bank details along the way."
+    } else {
+        "Type `withdraw [amount] USDT` to cash out to your saved bank."
+    };
+    format!(
+        "📋 *Getting started*\n\n\
+        ✅ Account created\n\
+        {} Fund your wallet\n\
+        {} Add a bank account\n\
+        {} Make your first withdrawal\n\n\
+        👉 {}",
+        mark(progress.funded),
+        mark(progress.bank_added),
+        mark(progress.withdrawn),
+        next
+    )
+}
+
+/// The checklist for `hi` and `help`, while any step is open. Open steps are re-checked
+/// at most every `ONBOARDING_REFRESH_SECS`; once all are done the checklist goes away
+/// for good.
+pub async fn checklist(session: &mut UserSessions) -> Option<String> {
+    let mut progress = session.prefs.onboarding.clone()?;
+    let stale = progress
+        .checked_at
+        .is_none_or(|at| Utc::now() - at >= refresh_after());
+    if stale {
+        refresh(session, &mut progress).await;
+    }
+
+    if progress.complete() {
+        println!("{} finished onboarding", mask_phone(&session.phone));
+        session.prefs.onboarding = None;
+        return None;
+    }
+    let reply = render(&progress);
+    session.prefs.onboarding = Some(progress);
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(funded: bool, bank_added: bool, withdrawn: bool) -> OnboardingProgress {
+        OnboardingProgress {
+            funded,
+            bank_added,
+            withdrawn,
+            checked_at: Some(Utc::now()),
+        }
+    }
+
+    fn marks(checklist: &str) -> Vec<&str> {
+        checklist
+            .lines()
+            .filter(|line| line.starts_with('✅') || line.starts_with('⬜'))
+            .collect()
+    }
+
+    #[test]
+    fn each_partial_progress_ticks_its_steps_and_points_at_the_next() {
+        let cases = [
+            (
+                progress(false, false, false),
+                ["⬜ Fund your wallet", "⬜ Add a bank account"],
+                "👉 Type `address`",
+            ),
+            (
+                progress(true, false, false),
+                ["✅ Fund your wallet", "⬜ Add a bank account"],
+                "👉 Type `withdraw [amount] USDT`; we'll ask for your bank details",
+            ),
+            // A bank can be saved before any money arrives
+            (
+                progress(false, true, false),
+                ["⬜ Fund your wallet", "✅ Add a bank account"],
+                "👉 Type `address`",
+            ),
+            (
+                progress(true, true, false),
+                ["✅ Fund your wallet", "✅ Add a bank account"],
+                "👉 Type `withdraw [amount] USDT` to cash out to your saved bank.",
+            ),
+        ];
+        for (progress, [funded, bank], next) in cases {
+            let checklist = render(&progress);
+            assert!(
+                checklist.starts_with("📋 *Getting started*"),
+                "{}",
+                checklist
+            );
+            assert_eq!(
+                marks(&checklist),
+                [
+                    "✅ Account created",
+                    funded,
+                    bank,
+                    "⬜ Make your first withdrawal"
+                ],
+                "{}",
+                checklist
+            );
+            assert!(
+                checklist.lines().last().unwrap().starts_with(next),
+                "{}",
+                checklist
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn a_recent_check_is_shown_without_asking_the_backend_again() {
+        let mut session = UserSessions::new("+2348090003101");
+        session.prefs.onboarding = Some(progress(true, false, false));
+
+        let checklist = checklist(&mut session).await.expect("a checklist");
+        assert_eq!(checklist, render(&progress(true, false, false)));
+        assert_eq!(
+            session.prefs.onboarding.as_ref().map(|p| p.bank_added),
+            Some(false)
+        );
+    }
+
+    #[tokio::test]
+    async fn the_checklist_goes_away_once_every_step_is_done() {
+        let mut session = UserSessions::new("+2348090003102");
+        session.prefs.onboarding = Some(progress(true, false, true));
+        bank_added(&mut session);
+
+        assert_eq!(checklist(&mut session).await, None);
+        assert_eq!(session.prefs.onboarding, None);
+        // And stays away, as it does for accounts made before the checklist existed
+        assert_eq!(checklist(&mut session).await, None);
+    }
+}
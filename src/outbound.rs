@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use tokio::sync::OwnedMutexGuard;
+
+use crate::server::try_send_twilio_content;
+
+/// An approved Twilio Content API template and the values for its `{{n}}` variables.
+#[derive(Debug, Clone)]
+pub struct ContentTemplate {
+    pub sid: String,
+    pub variables: BTreeMap<String, String>,
+}
+
+/// A single WhatsApp message queued for a user. Handlers return these in the order
+/// they should be delivered.
+#[derive(Debug, Clone)]
+pub struct OutboundMessage {
+    pub body: String,
+    /// Sent instead of `body` when set; `body` is still what gets logged and what
+    /// channels without templates fall back to.
+    pub content: Option<ContentTemplate>,
+    /// Pause before this message when it follows another; `None` uses `default_gap`.
+    pub delay: Option<Duration>,
+}
+
+impl OutboundMessage {
+    pub fn text(body: impl Into<String>) -> Self {
+        OutboundMessage {
+            body: body.into(),
+            content: None,
+            delay: None,
+        }
+    }
+
+    pub fn with_content(body: impl Into<String>, content: ContentTemplate) -> Self {
+        OutboundMessage {
+            body: body.into(),
+            content: Some(content),
+            delay: None,
+        }
+    }
+
+    /// Overrides the gap before this message: shorter for a follow-up that belongs with
+    /// the one before it, longer after a message that takes a while to read.
+    pub fn after(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// For users with plain text on: the text alone, without templates or bold.
+    pub fn plain(self) -> Self {
+        OutboundMessage {
+            delay: self.delay,
+            ..OutboundMessage::text(plain_text(&self.body))
+        }
+    }
+}
+
+/// `TWILIO_DRY_RUN=1` prints outbound messages instead of calling Twilio.
+pub fn dry_run() -> bool {
+    matches!(
+        std::env::var("TWILIO_DRY_RUN").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Held for the length of one Twilio send, so readiness can see sends piling up.
+pub struct SendGuard(());
+
+impl Drop for SendGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn track_send() -> SendGuard {
+    IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+    SendGuard(())
+}
+
+/// Twilio sends started and not yet finished.
+pub fn in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+/// Twilio refuses a WhatsApp body longer than this many characters.
+pub const MAX_BODY_CHARS: usize = 1600;
+
+/// WhatsApp refuses a template whose body, variables filled in, is longer than this.
+pub const MAX_TEMPLATE_CHARS: usize = 1024;
+
+/// Drops WhatsApp `*bold*` markers (and the `**` the older copy uses). Longer runs are
+/// masking, as in `******6789`, and stay.
+pub fn plain_text(body: &str) -> String {
+    let mut plain = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(start) = rest.find('*') {
+        plain.push_str(&rest[..start]);
+        let run = rest[start..].len() - rest[start..].trim_start_matches('*').len();
+        if run >= 3 {
+            plain.push_str(&rest[start..start + run]);
+        }
+        rest = &rest[start + run..];
+    }
+    plain.push_str(rest);
+    plain
+}
+
+impl From<String> for OutboundMessage {
+    fn from(body: String) -> Self {
+        OutboundMessage::text(body)
+    }
+}
+
+impl From<&str> for OutboundMessage {
+    fn from(body: &str) -> Self {
+        OutboundMessage::text(body)
+    }
+}
+
+/// `OUTBOUND_GAP_MS` (default 1000): the pause between consecutive messages to one user
+/// when the message doesn't set its own.
+pub fn default_gap() -> Duration {
+    Duration::from_millis(
+        std::env::var("OUTBOUND_GAP_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1000),
+    )
+}
+
+/// How long to wait before the message at `index`. The first goes out at once, so a
+/// lone reply is never held back.
+fn gap_before(index: usize, message: &OutboundMessage) -> Duration {
+    if index == 0 {
+        return Duration::ZERO;
+    }
+    message.delay.unwrap_or_else(default_gap)
+}
+
+/// One lane per user (digits only). tokio's mutex hands itself out in the order it was
+/// asked for, so each lane is a FIFO of sends and sequences.
+static LANES: LazyLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Waits for `to`'s turn to send. Held for one message or a whole sequence, so a reply
+/// from the webhook never lands in the middle of a batch from a background task.
+pub async fn lane(to: &str) -> OwnedMutexGuard<()> {
+    let key: String = to.chars().filter(char::is_ascii_digit).collect();
+    let lane = {
+        let mut lanes = LANES.lock().unwrap_or_else(|e| e.into_inner());
+        // Lanes nobody is holding or waiting on are only the map's copy
+        lanes.retain(|_, lane| Arc::strong_count(lane) > 1);
+        lanes.entry(key).or_default().clone()
+    };
+    lane.lock_owned().await
+}
+
+/// Delivers messages strictly in order, pausing between them so WhatsApp keeps the sequence.
+/// The whole batch goes out in one turn of the user's lane.
+pub async fn send_sequence(to: &str, messages: &[OutboundMessage]) {
+    let _lane = lane(to).await;
+    for (i, message) in messages.iter().enumerate() {
+        let gap = gap_before(i, message);
+        if !gap.is_zero() {
+            tokio::time::sleep(gap).await;
+        }
+        // Failures are already logged and audited by the time they come back
+        let _ = try_send_twilio_content(to, &message.body, message.content.as_ref()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_drops_bold_but_keeps_masks() {
+        assert_eq!(plain_text("*Account Verified!*"), "Account Verified!");
+        assert_eq!(plain_text("**Old** copy"), "Old copy");
+        assert_eq!(
+            plain_text("🔢 *Account Number:* ******6789"),
+            "🔢 Account Number: ******6789"
+        );
+        assert_eq!(plain_text("no markers"), "no markers");
+    }
+
+    #[test]
+    fn only_follow_ups_wait() {
+        let message = OutboundMessage::text("hi");
+        assert_eq!(gap_before(0, &message), Duration::ZERO);
+        assert_eq!(gap_before(1, &message), default_gap());
+
+        let quick = OutboundMessage::text("and").after(Duration::from_millis(200));
+        assert_eq!(gap_before(0, &quick), Duration::ZERO);
+        assert_eq!(gap_before(3, &quick), Duration::from_millis(200));
+    }
+
+    /// How long `send_sequence` took on the paused clock, and what it sent.
+    async fn paced(phone: &str, messages: &[OutboundMessage]) -> (Duration, Vec<String>) {
+        let started = tokio::time::Instant::now();
+        let (_, sent) =
+            crate::twilio::capture(send_sequence(phone, messages), Duration::ZERO).await;
+        let bodies = sent.into_iter().map(|message| message.body).collect();
+        (started.elapsed(), bodies)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_lone_message_goes_out_without_waiting() {
+        let (took, sent) = paced("+2348090000104", &["done".into()]).await;
+        assert_eq!(took, Duration::ZERO);
+        assert_eq!(sent, ["done"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn follow_ups_wait_the_default_gap_unless_they_set_their_own() {
+        let (took, sent) = paced(
+            "+2348090000105",
+            &["one".into(), "two".into(), "three".into()],
+        )
+        .await;
+        assert_eq!(took, default_gap() * 2);
+        assert_eq!(sent, ["one", "two", "three"]);
+
+        let (took, sent) = paced(
+            "+2348090000105",
+            &[
+                OutboundMessage::text("label"),
+                OutboundMessage::text("address").after(Duration::from_millis(200)),
+                OutboundMessage::text("warning"),
+            ],
+        )
+        .await;
+        assert_eq!(took, Duration::from_millis(200) + default_gap());
+        assert_eq!(sent, ["label", "address", "warning"]);
+
+        // Only follow-ups wait, so a delay on the first is ignored
+        let (took, _) = paced(
+            "+2348090000105",
+            &[OutboundMessage::text("first").after(Duration::from_secs(5))],
+        )
+        .await;
+        assert_eq!(took, Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn a_users_batches_go_out_whole_and_in_order() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let send = |batch: &'static str, phone: &'static str, parts: usize| {
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                let _lane = lane(phone).await;
+                for part in 0..parts {
+                    sent.lock().unwrap().push(format!("{}{}", batch, part));
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            })
+        };
+
+        // A background batch is mid-way when two more, one from the webhook, queue up
+        let batch = send("a", "+2348090000101", 3);
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        let reply = send("b", "whatsapp:+2348090000101", 1);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let another = send("c", "+234 809 000 0101", 2);
+        for task in [batch, reply, another] {
+            task.await.unwrap();
+        }
+        assert_eq!(*sent.lock().unwrap(), ["a0", "a1", "a2", "b0", "c0", "c1"]);
+    }
+
+    #[tokio::test]
+    async fn other_users_lanes_are_independent() {
+        let _held = lane("+2348090000102").await;
+        let other = tokio::time::timeout(Duration::from_secs(1), lane("+2348090000103")).await;
+        assert!(other.is_ok());
+        let same = tokio::time::timeout(Duration::from_millis(20), lane("2348090000102")).await;
+        assert!(same.is_err());
+    }
+}
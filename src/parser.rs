@@ -0,0 +1,988 @@
+use crate::features;
+use crate::i18n::Language;
+use crate::money;
+
+/// Tokens we can offramp today.
+pub const SUPPORTED_TOKENS: [&str; 2] = ["USDT", "USDC"];
+
+/// Multi-word phrases we advertise in messages, mapped to the keyword they stand for.
+/// Anything a template tells users to type should be listed here.
+pub const PHRASE_ALIASES: &[(&str, &str)] = &[
+    ("copy address", "address"),
+    ("fund account", "fund"),
+    ("check balance", "balance"),
+    ("how much is", "quote"),
+];
+
+/// One-word shorthands for people typing on a phone, mapped to the keyword they stand
+/// for; whatever follows is passed through as that command's arguments. A shorthand must
+/// never be a keyword itself or appear twice. There's no `history` command, so `hist`
+/// means `recap`.
+pub const SHORTHAND_ALIASES: &[(&str, &str)] = &[
+    ("bal", "balance"),
+    ("w", "withdraw"),
+    ("addr", "address"),
+    ("hist", "recap"),
+    ("tx", "status"),
+];
+
+/// Which menus list a command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Visibility {
+    /// Listed in `help` for everyone.
+    Listed,
+    /// Listed until we know the user has an account.
+    WithoutAccount,
+    /// Works, but never listed.
+    Hidden,
+}
+
+/// A command as users see it. Menus and help are generated from `COMMANDS`, so a
+/// command added here shows up everywhere it should; only the intro sentences around
+/// the lists are written by hand. Descriptions are English; `i18n` translates them by
+/// `name`.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandSpec {
+    /// The keyword itself.
+    pub name: &'static str,
+    /// Other words that mean the same thing.
+    pub aliases: &'static [&'static str],
+    /// How it's typed, with arguments in brackets.
+    pub usage: &'static str,
+    pub description: &'static str,
+    /// Listed under *Examples* in `help`.
+    pub example: Option<&'static str>,
+    pub visibility: Visibility,
+    /// Also listed in the welcome menu.
+    pub in_menu: bool,
+}
+
+impl CommandSpec {
+    /// Whether a user gets to see this command; `has_account` is only true once we know
+    /// they have one.
+    pub fn visible_to(&self, has_account: bool) -> bool {
+        match self.visibility {
+            Visibility::Listed => true,
+            Visibility::WithoutAccount => !has_account,
+            Visibility::Hidden => false,
+        }
+    }
+
+    /// Whether the command's rollout has reached this user; see `features`.
+    pub fn available_to(&self, phone: &str) -> bool {
+        features::is_enabled(self.name, phone)
+    }
+
+    const fn aliases(mut self, aliases: &'static [&'static str]) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    const fn example(mut self, example: &'static str) -> Self {
+        self.example = Some(example);
+        self
+    }
+
+    const fn visibility(mut self, visibility: Visibility) -> Self {
+        self.visibility = visibility;
+        self
+    }
+
+    const fn in_menu(mut self) -> Self {
+        self.in_menu = true;
+        self
+    }
+}
+
+const fn spec(name: &'static str, usage: &'static str, description: &'static str) -> CommandSpec {
+    CommandSpec {
+        name,
+        aliases: &[],
+        usage,
+        description,
+        example: None,
+        visibility: Visibility::Listed,
+        in_menu: false,
+    }
+}
+
+/// Every keyword we understand, in the order `help` lists them.
+pub const COMMANDS: &[CommandSpec] = &[
+    spec("hi", "hi", "Show the main menu")
+        .aliases(&["hello", "hey", "bonjour", "salut", "jambo", "habari"])
+        .visibility(Visibility::Hidden),
+    spec("create", "create", "Create new account")
+        .visibility(Visibility::WithoutAccount)
+        .in_menu(),
+    spec("address", "address", "Get your wallet address")
+        .example("address")
+        .in_menu(),
+    spec("fund", "fund", "Deposit crypto to your wallet address")
+        .aliases(&["deposit"])
+        .in_menu(),
+    spec("balance", "balance", "Check crypto balance")
+        .example("balance")
+        .in_menu(),
+    spec(
+        "withdraw",
+        "send [amount] [crypto] to [bank name]",
+        "Send crypto to your bank account",
+    )
+    .aliases(&["send"])
+    .example("send 100 USDT to Opay")
+    .in_menu(),
+    spec("quote", "quote [amount] [crypto]", "See what you'd receive"),
+    spec("status", "status [reference]", "Check a withdrawal"),
+    spec(
+        "cancel",
+        "cancel [reference]",
+        "Cancel a withdrawal that hasn't paid out",
+    ),
+    spec(
+        "export",
+        "export",
+        "Download your transaction history (CSV)",
+    ),
+    spec("recap", "recap", "See what you've done recently"),
+    spec("language", "language [name]", "Change language").aliases(&["lang"]),
+    spec(
+        "notifications",
+        "notifications on|off",
+        "Turn reminders on or off",
+    )
+    .aliases(&["notification"]),
+    spec("settings", "settings", "Timezone, plain text, default bank")
+        .aliases(&["setting", "preferences"]),
+    spec("verify", "verify [number]", "Re-check a saved bank account"),
+    spec(
+        "support",
+        "support [what happened]",
+        "Open a ticket with our team",
+    )
+    .aliases(&["helpdesk"]),
+    spec(
+        "refresh",
+        "refresh address|balance",
+        "Look up your address or balance again",
+    )
+    .visibility(Visibility::Hidden),
+    spec("help", "help", "Show all commands").in_menu(),
+];
+
+/// The registry entry for a keyword or any of its aliases.
+pub fn command_spec(word: &str) -> Option<&'static CommandSpec> {
+    COMMANDS
+        .iter()
+        .find(|spec| spec.name == word || spec.aliases.contains(&word))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Greet,
+    Create,
+    Address {
+        refresh: bool,
+    },
+    Fund,
+    Balance {
+        token: Option<String>,
+        refresh: bool,
+    },
+    Withdraw {
+        amount: f64,
+        token: String,
+        bank: Option<String>,
+    },
+    /// `withdraw all USDT`: the whole balance, looked up when the command runs.
+    WithdrawAll {
+        token: String,
+        bank: Option<String>,
+    },
+    Export,
+    Help,
+    Status {
+        reference: String,
+    },
+    Recap,
+    /// `language` alone shows the options.
+    Language(Option<Language>),
+    /// `notifications on|off`; `None` shows the current setting.
+    Notifications(Option<bool>),
+    /// `settings` alone lists them; `settings <field> <value>` changes one.
+    Settings {
+        field: Option<String>,
+        value: Option<String>,
+    },
+    /// Indicative conversion; nothing is locked in.
+    Quote {
+        amount: f64,
+        token: String,
+    },
+    /// `verify <n>` re-checks the n-th saved bank (as numbered by `settings bank`);
+    /// `verify` alone lists them.
+    VerifyBank {
+        index: Option<usize>,
+    },
+    /// `support` opens a ticket; anything after it is the user's description.
+    Support {
+        description: Option<String>,
+    },
+    /// `cancel <reference>` for a submitted withdrawal; a bare `cancel` belongs to the
+    /// flow the user is in and never reaches the parser mid-flow.
+    CancelWithdrawal {
+        reference: String,
+    },
+    /// Recognised command with bad or missing arguments.
+    Invalid(CommandError),
+    Unknown {
+        input: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    /// `token` is set when the user named one but no amount.
+    WithdrawMissingAmount {
+        token: Option<String>,
+    },
+    WithdrawInvalidAmount(String),
+    WithdrawMissingToken {
+        amount: f64,
+    },
+    WithdrawUnsupportedToken(String),
+    /// `withdraw all` without saying which token.
+    WithdrawAllMissingToken,
+    BalanceUnsupportedToken(String),
+    StatusMissingReference,
+    QuoteUsage,
+    UnsupportedLanguage(String),
+    CancelMissingReference,
+}
+
+impl Command {
+    /// The command's keyword, for logs: never any of the user's arguments.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Greet => "greet",
+            Command::Create => "create",
+            Command::Address { .. } => "address",
+            Command::Fund => "fund",
+            Command::Balance { .. } => "balance",
+            Command::Withdraw { .. } | Command::WithdrawAll { .. } => "withdraw",
+            Command::Export => "export",
+            Command::Help => "help",
+            Command::Status { .. } => "status",
+            Command::Recap => "recap",
+            Command::Language(_) => "language",
+            Command::Notifications(_) => "notifications",
+            Command::Settings { .. } => "settings",
+            Command::Quote { .. } => "quote",
+            Command::VerifyBank { .. } => "verify",
+            Command::Support { .. } => "support",
+            Command::CancelWithdrawal { .. } => "cancel",
+            Command::Invalid(_) => "invalid",
+            Command::Unknown { .. } => "unknown",
+        }
+    }
+}
+
+/// The keyword `words` start with, after phrase and shorthand aliases, and the rest.
+fn split_keyword<'a>(words: &'a [&'a str]) -> Option<(String, &'a [&'a str])> {
+    let first = words.first()?;
+    Some(match match_alias(words) {
+        Some((keyword, consumed)) => (keyword.to_string(), &words[consumed..]),
+        None => {
+            let word = first
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase();
+            (expand_shorthand(&word).unwrap_or(word), &words[1..])
+        }
+    })
+}
+
+/// The registry entry `input` invokes, whatever its arguments: the command a feature
+/// flag is checked against before those arguments are, so a held-back command reads
+/// as coming soon rather than as a usage error.
+pub fn invoked_spec(input: &str) -> Option<&'static CommandSpec> {
+    let words: Vec<&str> = input.split_whitespace().collect();
+    split_keyword(&words).and_then(|(keyword, _)| command_spec(&keyword))
+}
+
+pub fn parse_command(input: &str) -> Command {
+    let words: Vec<&str> = input.split_whitespace().collect();
+    let Some((keyword, args)) = split_keyword(&words) else {
+        return Command::Unknown {
+            input: String::new(),
+        };
+    };
+
+    let keyword = command_spec(&keyword).map_or(keyword.as_str(), |spec| spec.name);
+    match keyword {
+        "hi" => Command::Greet,
+        "create" => Command::Create,
+        "address" => Command::Address { refresh: false },
+        "refresh" => parse_refresh(args, input),
+        "fund" => Command::Fund,
+        "balance" => parse_balance(args),
+        "withdraw" => parse_withdraw(args),
+        "export" => Command::Export,
+        "help" => Command::Help,
+        "recap" => Command::Recap,
+        "verify" => Command::VerifyBank {
+            index: args
+                .first()
+                .and_then(|n| n.trim_start_matches('#').parse().ok()),
+        },
+        "quote" => parse_quote(args),
+        "support" => Command::Support {
+            description: Some(args.join(" ")).filter(|d| !d.is_empty()),
+        },
+        "settings" => Command::Settings {
+            field: args.first().map(|field| field.to_lowercase()),
+            value: Some(args.get(1..).unwrap_or_default().join(" ")).filter(|v| !v.is_empty()),
+        },
+        "notifications" => match args.first().map(|arg| arg.to_lowercase()).as_deref() {
+            Some("on") => Command::Notifications(Some(true)),
+            Some("off") => Command::Notifications(Some(false)),
+            _ => Command::Notifications(None),
+        },
+        "language" => match args.first() {
+            None => Command::Language(None),
+            Some(raw) => match Language::from_name(raw) {
+                Some(language) => Command::Language(Some(language)),
+                None => Command::Invalid(CommandError::UnsupportedLanguage(raw.to_string())),
+            },
+        },
+        "status" => match args.first() {
+            Some(reference) => Command::Status {
+                reference: reference.to_string(),
+            },
+            None => Command::Invalid(CommandError::StatusMissingReference),
+        },
+        "cancel" => match args.first() {
+            Some(reference) => Command::CancelWithdrawal {
+                reference: reference.to_string(),
+            },
+            None => Command::Invalid(CommandError::CancelMissingReference),
+        },
+        _ => Command::Unknown {
+            input: input.trim().to_string(),
+        },
+    }
+}
+
+/// Returns the keyword for a leading alias phrase and how many words it used up.
+fn match_alias(words: &[&str]) -> Option<(&'static str, usize)> {
+    PHRASE_ALIASES.iter().find_map(|(phrase, keyword)| {
+        let phrase_words: Vec<&str> = phrase.split(' ').collect();
+        let matches = words.len() >= phrase_words.len()
+            && phrase_words
+                .iter()
+                .zip(words)
+                .all(|(expected, word)| word.eq_ignore_ascii_case(expected));
+        matches.then_some((*keyword, phrase_words.len()))
+    })
+}
+
+fn expand_shorthand(word: &str) -> Option<String> {
+    SHORTHAND_ALIASES
+        .iter()
+        .find(|(shorthand, _)| *shorthand == word)
+        .map(|(_, keyword)| keyword.to_string())
+}
+
+/// The shorthands as a line for the help text, e.g. `bal` → balance.
+pub fn shorthand_help() -> String {
+    let list: Vec<String> = SHORTHAND_ALIASES
+        .iter()
+        .map(|(shorthand, keyword)| format!("`{}` → {}", shorthand, keyword))
+        .collect();
+    format!("⚡ *Shortcuts:* {}", list.join(", "))
+}
+
+/// `refresh address` / `refresh balance` bypass the cached copies.
+fn parse_refresh(args: &[&str], input: &str) -> Command {
+    match args.first().map(|arg| arg.to_lowercase()).as_deref() {
+        Some("address") => Command::Address { refresh: true },
+        Some("balance") => Command::Balance {
+            token: None,
+            refresh: true,
+        },
+        _ => Command::Unknown {
+            input: input.trim().to_string(),
+        },
+    }
+}
+
+/// `balance`, `balance USDT`, `balance refresh`.
+fn parse_balance(args: &[&str]) -> Command {
+    match args.first() {
+        None => Command::Balance {
+            token: None,
+            refresh: false,
+        },
+        Some(arg) if arg.eq_ignore_ascii_case("refresh") => Command::Balance {
+            token: None,
+            refresh: true,
+        },
+        Some(token) => match normalize_token(token) {
+            Some(token) => Command::Balance {
+                token: Some(token),
+                refresh: false,
+            },
+            None => Command::Invalid(CommandError::BalanceUnsupportedToken(token.to_string())),
+        },
+    }
+}
+
+/// `withdraw <amount> <token>` or `send <amount> <token> to <bank name>`; `all` in
+/// place of the amount sends the whole balance.
+fn parse_withdraw(args: &[&str]) -> Command {
+    let (details, bank) = match args.iter().position(|word| word.eq_ignore_ascii_case("to")) {
+        Some(to) => (
+            &args[..to],
+            Some(args[to + 1..].join(" ")).filter(|bank| !bank.is_empty()),
+        ),
+        None => (args, None),
+    };
+
+    if details.iter().any(|word| word.eq_ignore_ascii_case("all")) {
+        let mut others = details
+            .iter()
+            .filter(|word| !word.eq_ignore_ascii_case("all"));
+        return match others.clone().find_map(|word| normalize_token(word)) {
+            Some(token) => Command::WithdrawAll { token, bank },
+            None => match others.next() {
+                Some(word) => {
+                    Command::Invalid(CommandError::WithdrawUnsupportedToken(word.to_string()))
+                }
+                None => Command::Invalid(CommandError::WithdrawAllMissingToken),
+            },
+        };
+    }
+
+    match parse_amount_and_token(details) {
+        Ok((amount, token)) => Command::Withdraw {
+            amount,
+            token,
+            bank,
+        },
+        Err(error) => Command::Invalid(error),
+    }
+}
+
+/// `quote 75 USDT`, or `how much is 75 usdt in naira?` via the alias.
+fn parse_quote(args: &[&str]) -> Command {
+    match parse_amount_and_token(args) {
+        Ok((amount, token)) => Command::Quote { amount, token },
+        Err(error @ CommandError::WithdrawUnsupportedToken(_)) => Command::Invalid(error),
+        Err(_) => Command::Invalid(CommandError::QuoteUsage),
+    }
+}
+
+/// Finds the amount and token wherever they appear: `5 usdt`, `usdt 5`, `$5 usdt`,
+/// `5usdt`. Words that are neither are ignored unless no token turns up, in which
+/// case the first one is reported as the unsupported token.
+fn parse_amount_and_token(words: &[&str]) -> Result<(f64, String), CommandError> {
+    let mut amount = None;
+    let mut token = None;
+    let mut invalid_amount = None;
+    let mut unknown_word = None;
+
+    for raw in words {
+        let word = raw
+            .trim_start_matches(['$', '₦'])
+            .trim_end_matches(['?', '!', '.', ',', '$', '₦']);
+        if word.is_empty() {
+            continue;
+        }
+
+        if let Some(found) = normalize_token(word) {
+            token.get_or_insert(found);
+            continue;
+        }
+
+        // Glued forms like `5usdt`
+        let split = word
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ','))
+            .unwrap_or(word.len());
+        let (number, suffix) = word.split_at(split);
+        if !number.is_empty()
+            && !suffix.is_empty()
+            && let Some(found) = normalize_token(suffix)
+        {
+            token.get_or_insert(found);
+            match parse_amount(number) {
+                Some(value) => {
+                    amount.get_or_insert(value);
+                }
+                None => {
+                    invalid_amount.get_or_insert_with(|| raw.to_string());
+                }
+            }
+            continue;
+        }
+
+        if let Some(value) = parse_amount(word) {
+            amount.get_or_insert(value);
+        } else if word.chars().any(|c| c.is_ascii_digit()) {
+            invalid_amount.get_or_insert_with(|| raw.to_string());
+        } else {
+            unknown_word.get_or_insert_with(|| word.to_string());
+        }
+    }
+
+    match (amount, token) {
+        (Some(amount), Some(token)) => Ok((amount, token)),
+        (None, token) => match invalid_amount {
+            Some(raw) => Err(CommandError::WithdrawInvalidAmount(raw)),
+            None => Err(CommandError::WithdrawMissingAmount { token }),
+        },
+        (Some(amount), None) => match unknown_word {
+            Some(word) => Err(CommandError::WithdrawUnsupportedToken(word)),
+            None => Err(CommandError::WithdrawMissingToken { amount }),
+        },
+    }
+}
+
+/// Words that make a short message a nudge about a withdrawal in progress.
+const NUDGE_WORDS: &[&str] = &[
+    "update", "updates", "status", "where", "wheres", "waiting", "yet", "news",
+];
+
+/// Words that mean a message is about something else, even alongside a nudge word.
+const OTHER_TOPIC_WORDS: &[&str] = &[
+    "how", "bank", "address", "deposit", "rate", "name", "username", "language", "settings",
+    "withdraw",
+];
+
+/// `??`, `any update?`, `where is my money`: a short message asking after a withdrawal
+/// rather than a command. Kept narrow, since a miss only costs the usual "didn't
+/// understand" reply.
+pub fn is_status_nudge(input: &str) -> bool {
+    let input = input.trim();
+    if input.contains('?') && input.chars().all(|c| matches!(c, '?' | '!' | '.')) {
+        return true;
+    }
+
+    let words: Vec<String> = input
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.is_empty() || words.len() > 6 {
+        return false;
+    }
+    let any_of = |list: &[&str]| words.iter().any(|word| list.contains(&word.as_str()));
+    any_of(NUDGE_WORDS) && !any_of(OTHER_TOPIC_WORDS)
+}
+
+/// Positive, finite amounts only; tolerates thousands separators like `1,000`, but no
+/// other commas.
+pub fn parse_amount(raw: &str) -> Option<f64> {
+    money::ungroup(raw)?
+        .parse::<f64>()
+        .ok()
+        .filter(|amount| amount.is_finite() && *amount > 0.0)
+}
+
+pub fn normalize_token(raw: &str) -> Option<String> {
+    let token = raw.to_uppercase();
+    SUPPORTED_TOKENS.contains(&token.as_str()).then_some(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_accept_only_thousands_separators() {
+        let cases: &[(&str, Option<f64>)] = &[
+            ("5", Some(5.0)),
+            ("12.50", Some(12.5)),
+            (".5", Some(0.5)),
+            ("1,000", Some(1000.0)),
+            ("1,000.50", Some(1000.5)),
+            ("12,345,678", Some(12_345_678.0)),
+            ("999,999.99", Some(999_999.99)),
+            ("2,5", None),
+            ("1,00", None),
+            ("1000,000", None),
+            ("1,0000", None),
+            (",100", None),
+            ("100,", None),
+            ("1,,000", None),
+            ("1.000,5", None),
+            ("1,000.5,0", None),
+            ("0", None),
+            ("-5", None),
+            ("inf", None),
+            ("NaN", None),
+            ("", None),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(parse_amount(raw), *expected, "{:?}", raw);
+        }
+    }
+
+    fn withdraw(amount: f64, token: &str, bank: Option<&str>) -> Command {
+        Command::Withdraw {
+            amount,
+            token: token.to_string(),
+            bank: bank.map(str::to_string),
+        }
+    }
+
+    fn invalid(error: CommandError) -> Command {
+        Command::Invalid(error)
+    }
+
+    /// Messages as users have actually typed them, with the command each one means.
+    #[test]
+    fn real_messages_parse_to_what_they_mean() {
+        let usdt = || Some("USDT".to_string());
+        let cases: Vec<(&str, Command)> = vec![
+            ("hi", Command::Greet),
+            ("Hi", Command::Greet),
+            ("HELLO", Command::Greet),
+            ("hey!", Command::Greet),
+            ("Bonjour", Command::Greet),
+            ("  hi  ", Command::Greet),
+            ("create", Command::Create),
+            ("Create", Command::Create),
+            ("address", Command::Address { refresh: false }),
+            ("copy address", Command::Address { refresh: false }),
+            ("addr", Command::Address { refresh: false }),
+            ("refresh address", Command::Address { refresh: true }),
+            ("fund account", Command::Fund),
+            ("deposit", Command::Fund),
+            (
+                "balance",
+                Command::Balance {
+                    token: None,
+                    refresh: false,
+                },
+            ),
+            (
+                "Check balance",
+                Command::Balance {
+                    token: None,
+                    refresh: false,
+                },
+            ),
+            (
+                "bal usdt",
+                Command::Balance {
+                    token: usdt(),
+                    refresh: false,
+                },
+            ),
+            (
+                "balance refresh",
+                Command::Balance {
+                    token: None,
+                    refresh: true,
+                },
+            ),
+            (
+                "balance btc",
+                invalid(CommandError::BalanceUnsupportedToken("btc".to_string())),
+            ),
+            ("withdraw 50 usdt", withdraw(50.0, "USDT", None)),
+            ("Withdraw 50 USDT", withdraw(50.0, "USDT", None)),
+            ("withdraw   50    usdt", withdraw(50.0, "USDT", None)),
+            ("withdraw USDT 50", withdraw(50.0, "USDT", None)),
+            ("withdraw $20 usdc", withdraw(20.0, "USDC", None)),
+            ("withdraw 5usdt", withdraw(5.0, "USDT", None)),
+            ("w 12.5 usdc", withdraw(12.5, "USDC", None)),
+            ("withdraw 1,000 usdt", withdraw(1000.0, "USDT", None)),
+            (
+                "send 100 USDT to Opay",
+                withdraw(100.0, "USDT", Some("Opay")),
+            ),
+            (
+                "send 100 usdt to first bank",
+                withdraw(100.0, "USDT", Some("first bank")),
+            ),
+            (
+                "withdraw all usdt",
+                Command::WithdrawAll {
+                    token: "USDT".to_string(),
+                    bank: None,
+                },
+            ),
+            (
+                "withdraw all",
+                invalid(CommandError::WithdrawAllMissingToken),
+            ),
+            (
+                "withdraw",
+                invalid(CommandError::WithdrawMissingAmount { token: None }),
+            ),
+            (
+                "withdraw usdt",
+                invalid(CommandError::WithdrawMissingAmount { token: usdt() }),
+            ),
+            (
+                "withdraw 50",
+                invalid(CommandError::WithdrawMissingToken { amount: 50.0 }),
+            ),
+            (
+                "withdraw 50 naira",
+                invalid(CommandError::WithdrawUnsupportedToken("naira".to_string())),
+            ),
+            (
+                "withdraw -5 usdt",
+                invalid(CommandError::WithdrawInvalidAmount("-5".to_string())),
+            ),
+            (
+                "quote 75 usdt",
+                Command::Quote {
+                    amount: 75.0,
+                    token: "USDT".to_string(),
+                },
+            ),
+            (
+                "how much is 75 usdt in naira?",
+                Command::Quote {
+                    amount: 75.0,
+                    token: "USDT".to_string(),
+                },
+            ),
+            ("quote", invalid(CommandError::QuoteUsage)),
+            (
+                "status KP-123ABC",
+                Command::Status {
+                    reference: "KP-123ABC".to_string(),
+                },
+            ),
+            (
+                "tx KP-123ABC",
+                Command::Status {
+                    reference: "KP-123ABC".to_string(),
+                },
+            ),
+            ("status", invalid(CommandError::StatusMissingReference)),
+            (
+                "cancel KP-123ABC",
+                Command::CancelWithdrawal {
+                    reference: "KP-123ABC".to_string(),
+                },
+            ),
+            ("cancel", invalid(CommandError::CancelMissingReference)),
+            ("help", Command::Help),
+            ("HELP", Command::Help),
+            ("export", Command::Export),
+            ("hist", Command::Recap),
+            ("language", Command::Language(None)),
+            ("lang french", Command::Language(Some(Language::Fr))),
+            ("language pidgin", Command::Language(Some(Language::Pcm))),
+            (
+                "language klingon",
+                invalid(CommandError::UnsupportedLanguage("klingon".to_string())),
+            ),
+            ("notifications off", Command::Notifications(Some(false))),
+            ("notifications", Command::Notifications(None)),
+            ("verify 2", Command::VerifyBank { index: Some(2) }),
+            ("verify #1", Command::VerifyBank { index: Some(1) }),
+            (
+                "settings timezone Africa/Lagos",
+                Command::Settings {
+                    field: Some("timezone".to_string()),
+                    value: Some("Africa/Lagos".to_string()),
+                },
+            ),
+            (
+                "support my money never arrived",
+                Command::Support {
+                    description: Some("my money never arrived".to_string()),
+                },
+            ),
+            (
+                "where is my money",
+                Command::Unknown {
+                    input: "where is my money".to_string(),
+                },
+            ),
+            (
+                "thanks 🙏",
+                Command::Unknown {
+                    input: "thanks 🙏".to_string(),
+                },
+            ),
+            (
+                "",
+                Command::Unknown {
+                    input: String::new(),
+                },
+            ),
+        ];
+        for (message, expected) in cases {
+            assert_eq!(parse_command(message), expected, "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn advertised_phrases_are_their_keywords_however_typed() {
+        for (phrase, keyword) in PHRASE_ALIASES {
+            assert!(
+                command_spec(keyword).is_some(),
+                "{} isn't a command",
+                keyword
+            );
+            for rest in ["", " 10 usdt"] {
+                let expected = parse_command(&format!("{}{}", keyword, rest));
+                for typed in [
+                    format!("{}{}", phrase, rest),
+                    format!("{}{}", phrase.to_uppercase(), rest),
+                    format!("  {}  {}", phrase.replace(' ', "   "), rest),
+                ] {
+                    assert_eq!(parse_command(&typed), expected, "{:?}", typed);
+                }
+            }
+        }
+        assert_eq!(parse_command("Check Balance").name(), "balance");
+        // Only the whole phrase counts
+        assert_eq!(parse_command("copy").name(), "unknown");
+    }
+
+    #[test]
+    fn shorthands_never_shadow_a_keyword_or_each_other() {
+        for (i, (shorthand, keyword)) in SHORTHAND_ALIASES.iter().enumerate() {
+            assert!(
+                command_spec(shorthand).is_none(),
+                "{} is already a keyword or alias",
+                shorthand
+            );
+            assert!(
+                !PHRASE_ALIASES
+                    .iter()
+                    .any(|(phrase, _)| phrase.split(' ').next() == Some(shorthand)),
+                "{} starts a phrase",
+                shorthand
+            );
+            assert!(
+                SHORTHAND_ALIASES[i + 1..]
+                    .iter()
+                    .all(|(other, _)| other != shorthand),
+                "{} is listed twice",
+                shorthand
+            );
+            assert!(
+                command_spec(keyword).is_some(),
+                "{} isn't a command",
+                keyword
+            );
+        }
+    }
+
+    #[test]
+    fn each_shorthand_is_its_keyword_with_or_without_arguments() {
+        let cases: &[(&str, &str)] = &[
+            ("bal", " usdc"),
+            ("w", " 5 usdt"),
+            ("addr", " please"),
+            ("hist", " week"),
+            ("tx", " KP-123ABC"),
+        ];
+        for (shorthand, keyword) in SHORTHAND_ALIASES {
+            let (_, args) = cases
+                .iter()
+                .find(|(listed, _)| listed == shorthand)
+                .unwrap_or_else(|| panic!("no case for {}", shorthand));
+            for rest in ["", args] {
+                assert_eq!(
+                    parse_command(&format!("{}{}", shorthand, rest)),
+                    parse_command(&format!("{}{}", keyword, rest)),
+                    "{}{}",
+                    shorthand,
+                    rest
+                );
+            }
+        }
+        assert_eq!(
+            parse_command("w"),
+            invalid(CommandError::WithdrawMissingAmount { token: None })
+        );
+        assert_eq!(parse_command("W 5 USDT"), withdraw(5.0, "USDT", None));
+        assert_eq!(
+            parse_command("tx"),
+            invalid(CommandError::StatusMissingReference)
+        );
+        assert_eq!(
+            parse_command("bal usdc"),
+            Command::Balance {
+                token: Some("USDC".to_string()),
+                refresh: false,
+            }
+        );
+        // Only a whole word is a shorthand
+        assert_eq!(parse_command("balx").name(), "unknown");
+    }
+
+    #[test]
+    fn a_misplaced_comma_is_an_invalid_amount() {
+        assert_eq!(
+            parse_command("withdraw 2,5 USDT"),
+            Command::Invalid(CommandError::WithdrawInvalidAmount("2,5".to_string()))
+        );
+        assert!(matches!(
+            parse_command("withdraw 2,500 USDT"),
+            Command::Withdraw { amount, .. } if amount == 2500.0
+        ));
+    }
+
+    #[test]
+    fn short_questions_about_a_withdrawal_are_nudges() {
+        for nudge in [
+            "?",
+            "??",
+            "???!!",
+            "any update?",
+            "Any updates??",
+            "update pls",
+            "where is my money",
+            "where's my money?",
+            "WHERE IS IT",
+            "still waiting...",
+            "not yet?",
+            "any news",
+            "status?",
+        ] {
+            assert!(is_status_nudge(nudge), "{:?}", nudge);
+            // Nor a command of its own, bar `status` without its reference
+            assert!(
+                matches!(
+                    parse_command(nudge),
+                    Command::Unknown { .. }
+                        | Command::Invalid(CommandError::StatusMissingReference)
+                ),
+                "{:?}",
+                nudge
+            );
+        }
+    }
+
+    #[test]
+    fn anything_else_is_not_a_nudge() {
+        for other in [
+            "",
+            "...",
+            "ok",
+            "thank you",
+            "where can I deposit usdt",
+            "how do i update my bank",
+            "where do i find my address",
+            "what is the rate today",
+            "update my name",
+            "I have been waiting for my money since yesterday morning",
+        ] {
+            assert!(!is_status_nudge(other), "{:?}", other);
+        }
+    }
+}
@@ -0,0 +1,516 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
+};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::alerts::{self, PayoutAlert};
+use crate::audit::{self, AuditEvent};
+use crate::backend::{self, fetch_transaction_status};
+use crate::cache;
+use crate::config::PollConfig;
+use crate::eta;
+use crate::feedback;
+use crate::i18n::{self, Language, Msg};
+use crate::metrics;
+use crate::model::TransactionStatus;
+use crate::money;
+use crate::prefs;
+use crate::privacy::mask_phone;
+use crate::starknet;
+use crate::status_batch;
+use crate::transactions::{self, IndexedTransaction};
+use crate::twilio::{self, send_notice};
+use crate::window::Notice;
+
+/// Truncated on-chain hash plus explorer link, or nothing when the backend sent no hash.
+pub fn tx_hash_lines(tx_hash: &str) -> String {
+    match starknet::explorer_tx_url(tx_hash) {
+        Some(url) => format!(
+            "⛓️ **Tx:** `{}`\n{}\n\n",
+            starknet::short_address(tx_hash.trim()),
+            url
+        ),
+        None => String::new(),
+    }
+}
+
+/// The notice that a withdrawal has paid out. An amount the backend left out shows as
+/// missing rather than as `0.00`.
+pub fn completion_message(
+    language: Language,
+    status: &TransactionStatus,
+    bank_name: &str,
+    account_name: &str,
+    tx_hash: &str,
+    time_taken: &str,
+    completed_at: &str,
+) -> String {
+    i18n::render(
+        language,
+        Msg::WithdrawalCompleted,
+        &[
+            (
+                "amount",
+                &money::format_optional_money(status.amount, status.currency.as_deref()),
+            ),
+            ("bank", bank_name),
+            ("name", account_name),
+            ("reference", &status.reference),
+            ("tx", &tx_hash_lines(tx_hash)),
+            ("duration", time_taken),
+            ("completed_at", completed_at),
+        ],
+    )
+}
+
+/// How a round of polling ended. Failures are reported to the user before returning.
+#[derive(Debug, PartialEq)]
+enum PollOutcome {
+    Completed,
+    Failed,
+    TimedOut,
+}
+
+/// Runs `check` every `interval` until it returns an outcome or `max_wait` has elapsed,
+/// whichever comes first. The first check is immediate and the last happens no later
+/// than the deadline.
+async fn poll_until<F, Fut>(
+    reference: &str,
+    interval: Duration,
+    max_wait: Duration,
+    mut check: F,
+) -> PollOutcome
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<PollOutcome>>,
+{
+    let deadline = tokio::time::Instant::now() + max_wait;
+    loop {
+        if let Some(outcome) = check().await {
+            return outcome;
+        }
+        if tokio::time::Instant::now() + interval > deadline {
+            return PollOutcome::TimedOut;
+        }
+        nap(reference, interval).await;
+    }
+}
+
+/// Polls until the withdrawal reaches a final status or `max_wait` has elapsed; see
+/// `poll_until`. `progress` is a single interim message sent once that much time has
+/// passed without a final status.
+async fn poll_and_notify_on_completion(
+    tx: &IndexedTransaction,
+    poll_interval: Duration,
+    max_wait: Duration,
+    progress: Option<(Duration, &str)>,
+) -> Result<PollOutcome, String> {
+    let IndexedTransaction {
+        reference,
+        phone: user_phone,
+        bank_name,
+        account_name,
+        initiated_at,
+        correlation_id,
+        tx_hash,
+        language,
+        ..
+    } = tx;
+    let formatted_phone = user_phone.trim_start_matches('+');
+
+    if std::env::var("TRANSACTION_STATUS_ENDPOINT").is_err() {
+        eprintln!(
+            "TRANSACTION_STATUS_ENDPOINT is not set; cannot poll transaction {}",
+            reference
+        );
+        return Err("Transaction status endpoint is not configured".to_string());
+    }
+
+    let client = &backend::client();
+    let started = tokio::time::Instant::now();
+    let progress_sent = &AtomicBool::new(false);
+
+    let outcome = poll_until(reference, poll_interval, max_wait, move || async move {
+        if let Ok(Some(status_data)) =
+            poll_transaction_status(client, reference, formatted_phone).await
+        {
+            let status_lower = status_data.status.to_lowercase();
+
+            if matches!(
+                status_lower.as_str(),
+                "completed" | "successful" | "failed" | "cancelled"
+            ) {
+                audit::record(
+                    user_phone,
+                    correlation_id.as_deref(),
+                    Some(reference),
+                    AuditEvent::TerminalStatus {
+                        status: status_data.status.clone(),
+                    },
+                );
+                transactions::set_status(reference, &status_lower).await;
+            }
+
+            if status_lower == "completed" || status_lower == "successful" {
+                if let (Some(amount), Some(currency)) =
+                    (status_data.amount, status_data.currency.as_deref())
+                {
+                    money::warn_if_unscaled(
+                        &format!("Completed transaction {}", reference),
+                        amount,
+                        currency,
+                        None,
+                    );
+                }
+                let completed_at = status_data.last_updated;
+                let duration = completed_at.signed_duration_since(*initiated_at);
+                eta::record(reference, bank_name, duration.num_seconds(), completed_at);
+                let minutes = duration.num_minutes();
+                let seconds = duration.num_seconds() % 60;
+
+                let time_taken = if minutes > 0 {
+                    format!("{} min {} sec", minutes, seconds)
+                } else {
+                    format!("{} seconds", seconds)
+                };
+
+                let success_msg = completion_message(
+                    *language,
+                    &status_data,
+                    bank_name,
+                    account_name,
+                    tx_hash.as_deref().unwrap_or_default(),
+                    &time_taken,
+                    &prefs::load(user_phone)
+                        .await
+                        .format_time(completed_at, "%Y-%m-%d %H:%M:%S"),
+                );
+
+                cache::invalidate_balance(user_phone);
+                send_notice(user_phone, &success_msg, Notice::Completion).await;
+                feedback::request_rating(user_phone, reference, *language).await;
+
+                println!(
+                    "Transaction {} completed in {} and notification sent",
+                    reference, time_taken
+                );
+
+                return Some(PollOutcome::Completed);
+            }
+
+            if status_lower == "cancelled"
+                && USER_CANCELLED
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .remove(reference)
+            {
+                // The user asked for this and was already told; nothing to alert on
+                return Some(PollOutcome::Failed);
+            }
+
+            if status_lower == "failed" || status_lower == "cancelled" {
+                let failure_msg = i18n::render(
+                    *language,
+                    Msg::WithdrawalFailed,
+                    &[
+                        ("reference", &status_data.reference),
+                        ("status", &status_data.status),
+                    ],
+                );
+
+                send_notice(user_phone, &failure_msg, Notice::Completion).await;
+
+                let mut alert = PayoutAlert::new(reference, user_phone, &status_data.status);
+                alert.amount = status_data.amount;
+                alert.currency = status_data.currency.clone();
+                alert.bank_name = Some(bank_name.clone());
+                alerts::payout_failed(alert).await;
+
+                return Some(PollOutcome::Failed);
+            }
+        }
+
+        // Only reached when this check wasn't final, so a completion always wins
+        if let Some((after, message)) = progress
+            && started.elapsed() >= after
+            && !progress_sent.swap(true, Ordering::Relaxed)
+        {
+            send_notice(
+                user_phone,
+                &message.replace("{reference}", reference),
+                Notice::Completion,
+            )
+            .await;
+        }
+
+        None
+    })
+    .await;
+    Ok(outcome)
+}
+
+/// Wake-ups for running pollers, keyed by reference, so a status callback gets the user
+/// told now rather than at the next interval.
+static WAKERS: LazyLock<Mutex<HashMap<String, Arc<Notify>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Registers a poller for `reference` until dropped.
+struct Polling {
+    reference: String,
+    waker: Arc<Notify>,
+}
+
+impl Polling {
+    fn start(reference: &str) -> Self {
+        let waker = Arc::new(Notify::new());
+        WAKERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(reference.to_string(), waker.clone());
+        Polling {
+            reference: reference.to_string(),
+            waker,
+        }
+    }
+}
+
+impl Drop for Polling {
+    fn drop(&mut self) {
+        let mut wakers = WAKERS.lock().unwrap_or_else(|e| e.into_inner());
+        // A newer poller for the same reference keeps its own registration
+        if wakers
+            .get(&self.reference)
+            .is_some_and(|waker| Arc::ptr_eq(waker, &self.waker))
+        {
+            wakers.remove(&self.reference);
+        }
+    }
+}
+
+/// Sleeps for `duration`, or less if `wake` is called for `reference` meanwhile.
+async fn nap(reference: &str, duration: Duration) {
+    let waker = WAKERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(reference)
+        .cloned();
+    match waker {
+        Some(waker) => {
+            tokio::select! {
+                _ = sleep(duration) => {}
+                _ = waker.notified() => {}
+            }
+        }
+        None => sleep(duration).await,
+    }
+}
+
+/// Has the poller for `reference` check again right away; false when nothing is
+/// polling it. A wake sent while the poller is mid-check is kept for its next nap.
+pub fn wake(reference: &str) -> bool {
+    match WAKERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(reference)
+    {
+        Some(waker) => {
+            waker.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Looks up a transaction on the backend; `Ok(None)` when the backend has no record of it.
+/// Poller's view of a transaction: from the shared batch when `TRANSACTION_STATUS_BATCH_ENDPOINT`
+/// is set, otherwise (or when the batch didn't cover it) a lookup of its own.
+async fn poll_transaction_status(
+    client: &reqwest::Client,
+    reference: &str,
+    user_phone: &str,
+) -> Result<Option<TransactionStatus>, String> {
+    if let Some(status) = status_batch::lookup(reference).await {
+        return Ok(Some(status));
+    }
+    fetch_transaction_status(client, reference, user_phone).await
+}
+
+/// References the user cancelled themselves, so the poller doesn't report them as failures.
+static USER_CANCELLED: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Marks `reference` as cancelled by the user, before asking the backend to cancel it.
+pub fn expect_user_cancel(reference: &str) {
+    USER_CANCELLED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(reference.to_string());
+}
+
+/// Undoes `expect_user_cancel` when the backend refused the cancel.
+pub fn forget_user_cancel(reference: &str) {
+    USER_CANCELLED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(reference);
+}
+
+/// Tells the user we're still waiting and records the timeout, so a stuck payout
+/// doesn't just go quiet.
+async fn notify_poll_timeout(tx: &IndexedTransaction, waited: Duration) {
+    let waited_minutes = waited.as_secs() / 60;
+    metrics::POLL_TIMEOUTS.inc(&["fast"]);
+    eprintln!(
+        "ALERT: Transaction {} for {} not final after {} minutes of polling",
+        tx.reference,
+        mask_phone(&tx.phone),
+        waited_minutes
+    );
+    audit::record(
+        &tx.phone,
+        tx.correlation_id.as_deref(),
+        Some(&tx.reference),
+        AuditEvent::PollTimedOut { waited_minutes },
+    );
+
+    let delay_msg = format!(
+        "⏳ *Your withdrawal is taking longer than usual*\n\n\
+        🔢 *Reference:* {}\n\n\
+        We're still tracking it and will message you as soon as it completes.\n\
+        You can also check on it anytime with `status {}`.",
+        tx.reference, tx.reference
+    );
+    send_notice(&tx.phone, &delay_msg, Notice::Completion).await;
+}
+
+pub fn start_transaction_polling_task(reference: String) {
+    start_transaction_polling_task_with(reference, PollConfig::from_env());
+}
+
+/// Fast polling for `config.max_wait`, then, if still unsettled, a slow re-check every
+/// `config.slow_interval` for `config.slow_window`. What to report, and to whom, comes
+/// from the transaction index.
+pub fn start_transaction_polling_task_with(
+    reference: String,
+    config: PollConfig,
+) -> JoinHandle<()> {
+    // Registered before the task starts, so a callback right after this can wake it
+    let polling = Polling::start(&reference);
+    tokio::spawn(twilio::with_capture(async move {
+        let _polling = polling;
+        let Some(pending) = transactions::lookup(&reference).await else {
+            eprintln!(
+                "ALERT: Transaction {} isn't indexed; cannot poll it",
+                reference
+            );
+            return;
+        };
+        let started = tokio::time::Instant::now();
+        let progress = config
+            .progress_after
+            .map(|after| (after, config.progress_message.as_str()));
+        let outcome =
+            poll_and_notify_on_completion(&pending, config.interval, config.max_wait, progress)
+                .await;
+        println!(
+            "Polling {} ended with {:?} after {}s",
+            pending.reference,
+            outcome,
+            started.elapsed().as_secs()
+        );
+
+        match outcome {
+            Ok(PollOutcome::TimedOut) => notify_poll_timeout(&pending, started.elapsed()).await,
+            _ => return,
+        }
+
+        if config.slow_window.is_zero() {
+            return;
+        }
+        nap(&reference, config.slow_interval).await;
+        let slow_outcome =
+            poll_and_notify_on_completion(&pending, config.slow_interval, config.slow_window, None)
+                .await;
+        println!(
+            "Slow polling {} ended with {:?} after {}s in total",
+            pending.reference,
+            slow_outcome,
+            started.elapsed().as_secs()
+        );
+        if let Ok(PollOutcome::TimedOut) = slow_outcome {
+            metrics::POLL_TIMEOUTS.inc(&["slow"]);
+            eprintln!(
+                "ALERT: Transaction {} still not final after {} more minutes; giving up on polling",
+                pending.reference,
+                config.slow_window.as_secs() / 60
+            );
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    /// Polls a status that never settles under a paused clock, returning how many checks
+    /// ran and when, relative to the start.
+    async fn unsettled(interval: Duration, max_wait: Duration) -> (PollOutcome, Vec<Duration>) {
+        let started = Instant::now();
+        let mut checks = Vec::new();
+        let outcome = poll_until("KP-UNSETTLED", interval, max_wait, || {
+            checks.push(started.elapsed());
+            async { None }
+        })
+        .await;
+        (outcome, checks)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn thirty_minutes_of_polling_lasts_thirty_minutes() {
+        let max_wait = Duration::from_secs(30 * 60);
+        let (outcome, checks) = unsettled(Duration::from_secs(2), max_wait).await;
+        assert_eq!(outcome, PollOutcome::TimedOut);
+        assert_eq!(checks.len(), 901);
+        assert_eq!(checks[0], Duration::ZERO);
+        assert_eq!(*checks.last().unwrap(), max_wait);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_last_check_is_never_past_the_deadline() {
+        let max_wait = Duration::from_secs(10);
+        let (outcome, checks) = unsettled(Duration::from_secs(3), max_wait).await;
+        assert_eq!(outcome, PollOutcome::TimedOut);
+        let secs: Vec<u64> = checks.iter().map(Duration::as_secs).collect();
+        assert_eq!(secs, [0, 3, 6, 9]);
+
+        // A budget shorter than one interval still gets its one check
+        let (_, checks) = unsettled(Duration::from_secs(3), Duration::from_secs(1)).await;
+        assert_eq!(checks, [Duration::ZERO]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_final_status_ends_polling_at_that_check() {
+        let started = Instant::now();
+        let mut checks = 0;
+        let outcome = poll_until(
+            "KP-SETTLES",
+            Duration::from_secs(2),
+            Duration::from_secs(60),
+            || {
+                checks += 1;
+                let outcome = (checks == 4).then_some(PollOutcome::Completed);
+                async move { outcome }
+            },
+        )
+        .await;
+        assert_eq!(outcome, PollOutcome::Completed);
+        assert_eq!(checks, 4);
+        assert_eq!(started.elapsed(), Duration::from_secs(6));
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::model::UserPreferences;
+use crate::store;
+
+/// Preferences read recently, by session key, with when they were read. Every write goes
+/// through `update`, so an entry can't go stale within a process; the TTL only bounds
+/// how many users are held at once.
+static CACHE: LazyLock<Mutex<HashMap<String, (Instant, UserPreferences)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Held across each read-modify-write, so two writers for one user can't both start from
+/// the same copy and lose one change.
+static WRITES: Mutex<()> = Mutex::new(());
+
+/// `PREFS_CACHE_SECS`, default 10 min.
+fn cache_ttl() -> Duration {
+    Duration::from_secs(
+        std::env::var("PREFS_CACHE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10 * 60),
+    )
+}
+
+/// At most this many users are cached; past it, expired entries go, then the oldest.
+const CACHE_CAPACITY: usize = 10_000;
+
+fn cached(key: &str) -> Option<UserPreferences> {
+    let cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache
+        .get(key)
+        .filter(|(at, _)| at.elapsed() < cache_ttl())
+        .map(|(_, prefs)| prefs.clone())
+}
+
+fn cache(prefs: &UserPreferences) {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    insert_bounded(&mut cache, prefs, CACHE_CAPACITY, cache_ttl());
+}
+
+fn insert_bounded(
+    cache: &mut HashMap<String, (Instant, UserPreferences)>,
+    prefs: &UserPreferences,
+    capacity: usize,
+    ttl: Duration,
+) {
+    let now = Instant::now();
+    if cache.len() >= capacity && !cache.contains_key(&prefs.phone) {
+        cache.retain(|_, (at, _)| now.duration_since(*at) < ttl);
+        if cache.len() >= capacity
+            && let Some(oldest) = cache
+                .iter()
+                .min_by_key(|(_, (at, _))| *at)
+                .map(|(key, _)| key.clone())
+        {
+            cache.remove(&oldest);
+        }
+    }
+    cache.insert(prefs.phone.clone(), (now, prefs.clone()));
+}
+
+/// Reads from the store, bypassing the cache. Not cached on error, so the next message
+/// tries the store again.
+fn read(key: &str) -> Result<UserPreferences, String> {
+    let prefs = match store::shared() {
+        Some(store) => store.load_preferences(key)?,
+        None => None,
+    }
+    .unwrap_or_else(|| UserPreferences::new(key));
+    cache(&prefs);
+    Ok(prefs)
+}
+
+/// The user's preferences, or defaults if they've never set any.
+pub async fn load(phone: &str) -> UserPreferences {
+    let key = store::session_key(phone);
+    if let Some(prefs) = cached(&key) {
+        return prefs;
+    }
+    let lookup = key.clone();
+    match store::blocking(move || read(&lookup)).await {
+        Ok(prefs) => prefs,
+        Err(e) => {
+            eprintln!("Failed to load preferences: {}", e);
+            UserPreferences::new(&key)
+        }
+    }
+}
+
+/// Applies `change` to the user's current preferences and saves them, returning what
+/// `change` returned.
+pub async fn update<T, F>(phone: &str, change: F) -> Result<T, String>
+where
+    F: FnOnce(&mut UserPreferences) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let key = store::session_key(phone);
+    store::blocking(move || {
+        let _write = WRITES.lock().unwrap_or_else(|e| e.into_inner());
+        let mut prefs = match cached(&key) {
+            Some(prefs) => prefs,
+            None => read(&key)?,
+        };
+        let result = change(&mut prefs);
+        prefs.phone = key;
+        if let Some(store) = store::shared() {
+            store.save_preferences(&prefs)?;
+        }
+        cache(&prefs);
+        Ok(result)
+    })
+    .await
+}
+
+/// Saves only the fields `after` changed from `before`, the copy a handler loaded, so
+/// fields changed elsewhere meanwhile (an opt-out from Twilio's callback) survive.
+pub async fn save_changes(before: &UserPreferences, after: &UserPreferences) -> Result<(), String> {
+    let changed = changed_fields(before, after)?;
+    if changed.is_empty() {
+        return Ok(());
+    }
+    update(&after.phone, move |prefs| {
+        let mut current = serde_json::to_value(&*prefs).map_err(|e| e.to_string())?;
+        if let Value::Object(fields) = &mut current {
+            fields.extend(changed);
+        }
+        *prefs = serde_json::from_value(current).map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await?
+}
+
+fn changed_fields(
+    before: &UserPreferences,
+    after: &UserPreferences,
+) -> Result<serde_json::Map<String, Value>, String> {
+    let (Value::Object(before), Value::Object(after)) = (
+        serde_json::to_value(before).map_err(|e| e.to_string())?,
+        serde_json::to_value(after).map_err(|e| e.to_string())?,
+    ) else {
+        return Err("Preferences didn't serialize to an object".to_string());
+    };
+    Ok(after
+        .into_iter()
+        .filter(|(field, value)| field != "phone" && before.get(field) != Some(value))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn saving_a_handlers_copy_keeps_an_opt_out_recorded_meanwhile() {
+        let phone = "+2348090000101";
+        let before = load(phone).await;
+
+        update(phone, |prefs| prefs.opted_out = true).await.unwrap();
+
+        let mut after = before.clone();
+        after.plain_text = true;
+        save_changes(&before, &after).await.unwrap();
+
+        let saved = load(phone).await;
+        assert!(saved.opted_out);
+        assert!(saved.plain_text);
+    }
+
+    #[tokio::test]
+    async fn an_unchanged_copy_writes_nothing() {
+        let phone = "+2348090000102";
+        let before = load(phone).await;
+        update(phone, |prefs| prefs.invalid_number = true)
+            .await
+            .unwrap();
+
+        save_changes(&before, &before.clone()).await.unwrap();
+        assert!(load(phone).await.invalid_number);
+    }
+
+    #[test]
+    fn the_cache_drops_its_oldest_entry_when_full() {
+        let mut cache = HashMap::new();
+        for phone in ["+2348090000103", "+2348090000104", "+2348090000105"] {
+            insert_bounded(
+                &mut cache,
+                &UserPreferences::new(phone),
+                2,
+                Duration::from_secs(60),
+            );
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains_key("+2348090000103"));
+    }
+
+    #[test]
+    fn expired_entries_go_first() {
+        let mut cache = HashMap::new();
+        insert_bounded(
+            &mut cache,
+            &UserPreferences::new("+2348090000106"),
+            2,
+            Duration::ZERO,
+        );
+        insert_bounded(
+            &mut cache,
+            &UserPreferences::new("+2348090000107"),
+            2,
+            Duration::ZERO,
+        );
+        insert_bounded(
+            &mut cache,
+            &UserPreferences::new("+2348090000108"),
+            2,
+            Duration::ZERO,
+        );
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,72 @@
+use sha2::{Digest, Sha256};
+
+/// Keeps the country code and last four digits: `+2348012345678` -> `+234******5678`.
+pub fn mask_phone(phone: &str) -> String {
+    let digits: Vec<char> = phone.trim_start_matches('+').chars().collect();
+    if digits.len() <= 7 {
+        return "****".to_string();
+    }
+
+    let hidden = digits.len() - 7;
+    format!(
+        "+{}{}{}",
+        digits[..3].iter().collect::<String>(),
+        "*".repeat(hidden),
+        digits[digits.len() - 4..].iter().collect::<String>()
+    )
+}
+
+pub fn mask_account_number(account_number: &str) -> String {
+    let chars: Vec<char> = account_number.chars().collect();
+    if chars.len() <= 4 {
+        return "****".to_string();
+    }
+
+    format!(
+        "{}{}",
+        "*".repeat(chars.len() - 4),
+        chars[chars.len() - 4..].iter().collect::<String>()
+    )
+}
+
+/// Masks runs of eight or more digits, which in free text are account or phone numbers,
+/// down to their last four. Amounts are shorter and stay readable.
+pub fn mask_digit_runs(text: &str) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut run = String::new();
+    let flush = |run: &mut String, masked: &mut String| {
+        if run.len() >= 8 {
+            masked.push_str(&mask_account_number(run));
+        } else {
+            masked.push_str(run);
+        }
+        run.clear();
+    };
+
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            run.push(c);
+        } else {
+            flush(&mut run, &mut masked);
+            masked.push(c);
+        }
+    }
+    flush(&mut run, &mut masked);
+    masked
+}
+
+pub fn hash_phone(phone: &str) -> String {
+    let normalized = phone
+        .trim_start_matches("whatsapp:")
+        .trim_start_matches('+');
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+/// Renders a phone for persisted records, hashed when `PHONE_STORAGE_MODE=hash`
+/// and masked otherwise.
+pub fn phone_for_storage(phone: &str) -> String {
+    match std::env::var("PHONE_STORAGE_MODE").as_deref() {
+        Ok("hash") => hash_phone(phone),
+        _ => mask_phone(phone),
+    }
+}
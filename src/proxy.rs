@@ -0,0 +1,251 @@
+use std::net::IpAddr;
+use std::sync::LazyLock;
+
+use actix_web::{
+    Error, HttpMessage, HttpRequest,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::HeaderMap,
+    middleware::Next,
+};
+use ipnet::IpNet;
+
+use crate::tls;
+
+/// `TRUSTED_PROXIES`: comma-separated CIDRs (or bare IPs) of the nginx/Cloudflare hops in
+/// front of us. Empty means we're reached directly and forwarding headers are ignored.
+fn load_trusted() -> Result<Vec<IpNet>, String> {
+    parse_trusted(&std::env::var("TRUSTED_PROXIES").unwrap_or_default())
+}
+
+fn parse_trusted(raw: &str) -> Result<Vec<IpNet>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("TRUSTED_PROXIES: '{}' is not an IP or CIDR", entry))
+        })
+        .collect()
+}
+
+static TRUSTED: LazyLock<Result<Vec<IpNet>, String>> = LazyLock::new(load_trusted);
+
+/// Called at startup, so a typo in `TRUSTED_PROXIES` stops the server instead of silently
+/// trusting nobody (or the wrong network).
+pub fn require_valid() -> Result<(), String> {
+    TRUSTED.as_ref().map(|_| ()).map_err(Clone::clone)
+}
+
+fn is_trusted(trusted: &[IpNet], ip: IpAddr) -> bool {
+    trusted.iter().any(|net| net.contains(&ip))
+}
+
+/// Who actually sent a request and what URL they used, as seen before any proxy rewrote it.
+/// Twilio signs the public URL, and rate limits are per real client, so both read this
+/// instead of the socket peer or the raw `Host`.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    // For the Twilio signature check, which isn't wired up yet
+    #[allow(dead_code)]
+    pub url: String,
+}
+
+fn first_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+/// Walks `X-Forwarded-For` from the right, past our own proxies, to the first hop we don't
+/// control. Anything left of that was written by the client and can't be believed. A
+/// garbled hop ends the walk at the proxy that appended it.
+fn forwarded_client(trusted: &[IpNet], peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        let Ok(ip) = hop.parse::<IpAddr>() else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(trusted, ip) {
+            break;
+        }
+    }
+    client
+}
+
+fn resolve(
+    trusted: &[IpNet],
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+    scheme: &str,
+    host: &str,
+    path_and_query: &str,
+) -> ClientInfo {
+    let Some(peer) = peer.filter(|ip| is_trusted(trusted, *ip)) else {
+        // A direct caller can put anything in X-Forwarded-*, so none of it counts
+        return ClientInfo {
+            ip: peer,
+            url: format!("{}://{}{}", scheme, host, path_and_query),
+        };
+    };
+
+    let scheme = first_value(headers, "x-forwarded-proto").unwrap_or(scheme);
+    let host = first_value(headers, "x-forwarded-host").unwrap_or(host);
+    ClientInfo {
+        ip: Some(forwarded_client(trusted, peer, headers)),
+        url: format!("{}://{}{}", scheme, host, path_and_query),
+    }
+}
+
+fn resolve_request(req: &HttpRequest) -> ClientInfo {
+    let trusted = TRUSTED.as_deref().unwrap_or_default();
+    let uri = req.uri();
+    let host = req
+        .headers()
+        .get("host")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| uri.authority().map(|a| a.as_str()))
+        .unwrap_or("localhost");
+    let scheme = uri
+        .scheme_str()
+        .unwrap_or(if tls::enabled() { "https" } else { "http" });
+    let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+
+    resolve(
+        trusted,
+        req.peer_addr().map(|addr| addr.ip()),
+        req.headers(),
+        scheme,
+        host,
+        path_and_query,
+    )
+}
+
+/// Resolves the client once per request and stores it as a request extension.
+pub async fn attach_client(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let info = resolve_request(req.request());
+    req.extensions_mut().insert(info);
+    next.call(req).await
+}
+
+/// The `ClientInfo` set by `attach_client`, or resolved on the spot for routes outside it.
+pub fn client(req: &HttpRequest) -> ClientInfo {
+    req.extensions()
+        .get::<ClientInfo>()
+        .cloned()
+        .unwrap_or_else(|| resolve_request(req))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    fn resolved(peer: &str, pairs: &[(&'static str, &'static str)]) -> ClientInfo {
+        let trusted = parse_trusted("10.0.0.0/8, 173.245.48.0/20").unwrap();
+        resolve(
+            &trusted,
+            Some(peer.parse().unwrap()),
+            &headers(pairs),
+            "http",
+            "internal:6500",
+            "/webhook?x=1",
+        )
+    }
+
+    const SPOOFED: &[(&str, &str)] = &[
+        ("x-forwarded-for", "1.2.3.4"),
+        ("x-forwarded-proto", "https"),
+        ("x-forwarded-host", "evil.example"),
+    ];
+
+    #[test]
+    fn an_untrusted_peer_cannot_spoof_its_ip_scheme_or_host() {
+        let info = resolved("203.0.113.9", SPOOFED);
+        assert_eq!(info.ip, Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(info.url, "http://internal:6500/webhook?x=1");
+    }
+
+    #[test]
+    fn a_trusted_chain_resolves_to_the_rightmost_untrusted_hop() {
+        let info = resolved(
+            "10.0.0.2",
+            &[
+                // The first hop is whatever the client wrote, so it's skipped
+                ("x-forwarded-for", "6.6.6.6, 198.51.100.7, 173.245.48.5"),
+                ("x-forwarded-proto", "https"),
+                ("x-forwarded-host", "bot.kharon.example"),
+            ],
+        );
+        assert_eq!(info.ip, Some("198.51.100.7".parse().unwrap()));
+        assert_eq!(info.url, "https://bot.kharon.example/webhook?x=1");
+    }
+
+    #[test]
+    fn repeated_forwarded_headers_are_read_as_one_chain() {
+        let info = resolved(
+            "10.0.0.2",
+            &[
+                ("x-forwarded-for", "198.51.100.7"),
+                ("x-forwarded-for", "10.0.0.3"),
+            ],
+        );
+        assert_eq!(info.ip, Some("198.51.100.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn a_garbled_hop_stops_at_the_proxy_that_appended_it() {
+        let info = resolved(
+            "10.0.0.2",
+            &[("x-forwarded-for", "198.51.100.7, not-an-ip, 10.0.0.3")],
+        );
+        assert_eq!(info.ip, Some("10.0.0.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn a_trusted_peer_without_forwarding_headers_is_the_client() {
+        let info = resolved("10.0.0.2", &[]);
+        assert_eq!(info.ip, Some("10.0.0.2".parse().unwrap()));
+        assert_eq!(info.url, "http://internal:6500/webhook?x=1");
+    }
+
+    #[test]
+    fn trusted_proxies_take_cidrs_and_bare_ips_and_reject_anything_else() {
+        let trusted = parse_trusted(" 10.0.0.0/8 ,192.0.2.1,, ::1 ").unwrap();
+        assert_eq!(trusted.len(), 3);
+        assert!(is_trusted(&trusted, "192.0.2.1".parse().unwrap()));
+        assert!(!is_trusted(&trusted, "192.0.2.2".parse().unwrap()));
+        assert!(parse_trusted("").unwrap().is_empty());
+
+        let error = parse_trusted("10.0.0.0/8, nginx").unwrap_err();
+        assert!(error.contains("'nginx'"), "{}", error);
+    }
+}
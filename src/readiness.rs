@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use actix_web::{HttpResponse, dev::ServerHandle, web};
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::store::{self, SessionStore};
+use crate::supervisor::{self, TaskStatus};
+use crate::{outbound, selftest};
+
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// `/livez`: the process is up and answering. No dependencies, so a slow backend never
+/// gets us restarted.
+pub async fn livez() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "alive" }))
+}
+
+/// `BACKEND_HEALTH_URL`, when set, must answer below 500 within 2s. Unset means we don't
+/// know of a cheap backend endpoint to poll, and the check is skipped.
+async fn probe_backend() -> Result<&'static str, String> {
+    let Ok(url) = std::env::var("BACKEND_HEALTH_URL") else {
+        return Ok("not_configured");
+    };
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(2))
+        .build()
+        .map_err(|e| e.to_string())?;
+    match client.get(&url).send().await {
+        Ok(response) if response.status().is_server_error() => {
+            Err(format!("returned {}", response.status()))
+        }
+        Ok(_) => Ok("ok"),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn check(result: &Result<&'static str, String>) -> serde_json::Value {
+    match result {
+        Ok(status) => serde_json::json!({ "status": status }),
+        Err(e) => serde_json::json!({ "status": "down", "error": e }),
+    }
+}
+
+/// `/readyz`: 200 only when we can usefully take traffic. 503 while draining for
+/// shutdown, or when the session store or backend is unreachable, a background task has
+/// died, or more than `READY_MAX_OUTBOUND_IN_FLIGHT` sends (default 200) are stuck open.
+pub async fn readyz(sessions: web::Data<dyn SessionStore>) -> HttpResponse {
+    if DRAINING.load(Ordering::Relaxed) {
+        return HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "status": "draining",
+        }));
+    }
+
+    let sessions = sessions.into_inner();
+    let store = store::blocking(move || sessions.load("readyz"))
+        .await
+        .map(|_| "ok");
+    let backend = probe_backend().await;
+
+    let dead: Vec<&str> = supervisor::snapshot()
+        .into_iter()
+        .filter(|(_, task)| task.status == TaskStatus::Dead)
+        .map(|(name, _)| name)
+        .collect();
+    let tasks = if dead.is_empty() {
+        Ok("ok")
+    } else {
+        Err(format!("dead: {}", dead.join(", ")))
+    };
+
+    let in_flight = outbound::in_flight();
+    let max_in_flight = env_u64("READY_MAX_OUTBOUND_IN_FLIGHT", 200) as usize;
+    let outbound = if in_flight <= max_in_flight {
+        Ok("ok")
+    } else {
+        Err(format!("{} sends in flight", in_flight))
+    };
+
+    let ready = store.is_ok() && backend.is_ok() && tasks.is_ok() && outbound.is_ok();
+    let body = serde_json::json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": {
+            "session_store": check(&store),
+            "backend": check(&backend),
+            "background_tasks": check(&tasks),
+            "outbound": check(&outbound),
+        },
+        "outbound_in_flight": in_flight,
+        "startup_self_test": selftest::results(),
+    });
+
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// Replaces actix's own signal handling: on SIGTERM/SIGINT, `/readyz` turns 503 first and
+/// the listener stays open for `SHUTDOWN_DRAIN_SECS` (default 10) so the load balancer
+/// stops routing to us, then the server stops gracefully. A second signal skips the wait.
+pub fn drain_on_shutdown(server: ServerHandle) {
+    let drain = Duration::from_secs(env_u64("SHUTDOWN_DRAIN_SECS", 10));
+
+    tokio::spawn(async move {
+        let (Ok(mut term), Ok(mut int)) = (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::interrupt()),
+        ) else {
+            eprintln!(
+                "Cannot listen for shutdown signals; SIGTERM will stop the process without draining"
+            );
+            return;
+        };
+
+        tokio::select! {
+            _ = term.recv() => {}
+            _ = int.recv() => {}
+        }
+        DRAINING.store(true, Ordering::Relaxed);
+        println!(
+            "Shutdown requested; draining for {:?} before closing",
+            drain
+        );
+
+        tokio::select! {
+            _ = tokio::time::sleep(drain) => {}
+            _ = term.recv() => println!("Second signal; closing now"),
+            _ = int.recv() => println!("Second signal; closing now"),
+        }
+        server.stop(true).await;
+    });
+}
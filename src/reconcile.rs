@@ -0,0 +1,163 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::alerts::{self, PayoutAlert};
+use crate::audit::{self, AuditEvent};
+use crate::backend::{self, cancel_offramp};
+use crate::metrics;
+use crate::model::{UserSessions, UserState};
+use crate::outbound;
+use crate::privacy::mask_phone;
+use crate::server::send_notice;
+use crate::state::StateMachine;
+use crate::store::{self, SessionStore};
+use crate::summary;
+use crate::supervisor;
+use crate::window::Notice;
+
+const RESET_MESSAGE: &str = "🔄 Your unfinished request from earlier has been reset, \
+    so nothing from it will go through.\n\nType `hi` to start again.";
+
+/// `FLOW_TIMEOUT_SECS`, default 6 hours: a session left mid-flow for longer is past
+/// finishing. 0 turns reconciliation off.
+fn flow_timeout() -> Duration {
+    Duration::from_secs(
+        std::env::var("FLOW_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(6 * 60 * 60),
+    )
+}
+
+/// `RECONCILE_NOTIFY_USERS=true` tells each user whose flow was reset; off by default.
+fn notify_users() -> bool {
+    matches!(
+        std::env::var("RECONCILE_NOTIFY_USERS").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+/// An offramp waiting on the user's approval was already initiated, so it's cancelled
+/// with the backend before the session forgets it.
+async fn cancel_pending_payout(session: &UserSessions) {
+    let Some(payout) = &session.pending_payout else {
+        return;
+    };
+    let client = backend::client();
+    let result = cancel_offramp(&client, &payout.reference, "flow_timed_out").await;
+    audit::record(
+        &session.phone,
+        session.correlation_id.as_deref(),
+        Some(&payout.reference),
+        AuditEvent::OfframpCancelled {
+            success: result.is_ok(),
+            error: result.as_ref().err().cloned(),
+        },
+    );
+    if let Err(err) = result {
+        let mut alert = PayoutAlert::new(
+            &payout.reference,
+            &session.phone,
+            &format!("timed-out payout could not be cancelled ({})", err),
+        );
+        alert.amount = Some(payout.amount);
+        alert.currency = Some(payout.currency.clone());
+        alert.bank_name = Some(payout.bank_name.clone());
+        alerts::payout_failed(alert).await;
+    }
+}
+
+/// Resets every session that has sat outside `Initial` for longer than
+/// `FLOW_TIMEOUT_SECS`, returning how many were reset.
+pub async fn reconcile(store: &Arc<dyn SessionStore>) -> usize {
+    let (scan, timeout) = (store.clone(), flow_timeout());
+    let stale = match store::blocking(move || scan.mid_flow(timeout)).await {
+        Ok(stale) => stale,
+        Err(e) => {
+            eprintln!("Stale flow scan failed: {}", e);
+            return 0;
+        }
+    };
+    let notify = notify_users();
+
+    let mut reset = 0;
+    for session in stale {
+        // Re-read so a reply that landed since the scan isn't thrown away
+        let (current, phone) = (store.clone(), session.phone.clone());
+        let mut session = match store::blocking(move || current.load(&phone)).await {
+            Ok(Some(current))
+                if current.state == session.state
+                    && current.last_inbound_at == session.last_inbound_at =>
+            {
+                current
+            }
+            _ => continue,
+        };
+        let state = session.state;
+        if state == UserState::PayoutApproval {
+            cancel_pending_payout(&session).await;
+        }
+        audit::record(
+            &session.phone,
+            session.correlation_id.as_deref(),
+            session
+                .pending_payout
+                .as_ref()
+                .map(|p| p.reference.as_str()),
+            AuditEvent::StaleFlowReset {
+                state: format!("{:?}", state),
+            },
+        );
+        StateMachine::reset(&mut session);
+        let (saving, saved) = (store.clone(), session.clone());
+        if let Err(e) = store::blocking(move || saving.save(&saved)).await {
+            eprintln!(
+                "Failed to reset stale session for {}: {}",
+                mask_phone(&session.phone),
+                e
+            );
+            continue;
+        }
+        metrics::STALE_SESSIONS_RESET.inc(&[]);
+        println!(
+            "Reset stale {:?} session for {}",
+            state,
+            mask_phone(&session.phone)
+        );
+
+        if notify {
+            // Same default gap as the reminders, so a post-incident batch doesn't burst
+            if reset > 0 {
+                tokio::time::sleep(outbound::default_gap()).await;
+            }
+            send_notice(&session.phone, RESET_MESSAGE, Notice::Reminder).await;
+        }
+        reset += 1;
+    }
+    reset
+}
+
+/// Reconciles once at startup, then nightly at `RECONCILE_HOUR` (local to
+/// `SUMMARY_UTC_OFFSET`, default 03:00).
+pub fn start_reconcile_task(store: Arc<dyn SessionStore>) {
+    if flow_timeout().is_zero() {
+        return;
+    }
+    let hour: u32 = std::env::var("RECONCILE_HOUR")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|h| *h < 24)
+        .unwrap_or(3);
+
+    supervisor::supervise("stale_flow_reconcile", move || {
+        let store = store.clone();
+        async move {
+            loop {
+                match reconcile(&store).await {
+                    0 => {}
+                    reset => println!("Reconciled {} sessions stuck mid-flow", reset),
+                }
+                tokio::time::sleep(summary::until_local_hour(hour)).await;
+            }
+        }
+    });
+}
@@ -0,0 +1,83 @@
+use crate::access::normalize_phone;
+
+pub const UNAVAILABLE_MESSAGE: &str = "🌍 KharonPay isn't available in your country yet.\n\n\
+    We're only licensed in Nigeria for now, but we've noted your interest and will reach \
+    out when we launch in your region. You can still type `help` to see what we offer.";
+
+/// Two-digit ITU codes; everything else outside zones 1 and 7 uses three digits.
+const TWO_DIGIT_CODES: &[&str] = &[
+    "20", "27", "30", "31", "32", "33", "34", "36", "39", "40", "41", "43", "44", "45", "46", "47",
+    "48", "49", "51", "52", "53", "54", "55", "56", "57", "58", "60", "61", "62", "63", "64", "65",
+    "66", "81", "82", "84", "86", "90", "91", "92", "93", "94", "95", "98",
+];
+
+/// `ALLOWED_COUNTRY_CODES`, comma separated without `+`; default `234` (Nigeria).
+fn allowed_codes() -> Vec<String> {
+    parse_codes(&std::env::var("ALLOWED_COUNTRY_CODES").unwrap_or_else(|_| "234".to_string()))
+}
+
+fn parse_codes(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|code| code.trim().trim_start_matches('+').to_string())
+        .filter(|code| !code.is_empty())
+        .collect()
+}
+
+/// Calling code of an E.164-ish number, or `None` if it's too short to be a phone number.
+pub fn calling_code(phone: &str) -> Option<String> {
+    let normalized = normalize_phone(phone)?;
+    let digits = normalized.trim_start_matches('+');
+    if digits.len() < 8 {
+        return None;
+    }
+
+    let len = match &digits[..1] {
+        "1" | "7" => 1,
+        _ if TWO_DIGIT_CODES.contains(&&digits[..2]) => 2,
+        _ => 3,
+    };
+    Some(digits[..len].to_string())
+}
+
+/// Whether we may open accounts and pay out for this number.
+pub fn is_supported(phone: &str) -> bool {
+    calling_code(phone).is_some_and(|code| allowed_codes().contains(&code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nigerian_and_kenyan_numbers_have_their_own_codes() {
+        for nigerian in ["+2348090001501", "whatsapp:+2348090001501", "2348090001501"] {
+            assert_eq!(
+                calling_code(nigerian).as_deref(),
+                Some("234"),
+                "{}",
+                nigerian
+            );
+        }
+        for kenyan in ["+254712345678", "whatsapp:+254 712 345 678"] {
+            assert_eq!(calling_code(kenyan).as_deref(), Some("254"), "{}", kenyan);
+        }
+        // One- and two-digit zones aren't read as a longer code
+        assert_eq!(calling_code("+14155238886").as_deref(), Some("1"));
+        assert_eq!(calling_code("+447700900123").as_deref(), Some("44"));
+    }
+
+    #[test]
+    fn malformed_numbers_have_no_code() {
+        for malformed in ["", "whatsapp:", "not a phone", "+234", "+2348090"] {
+            assert_eq!(calling_code(malformed), None, "{:?}", malformed);
+            assert!(!is_supported(malformed), "{:?}", malformed);
+        }
+    }
+
+    #[test]
+    fn allowed_codes_tolerate_plus_signs_and_spaces() {
+        assert_eq!(parse_codes("234"), ["234"]);
+        assert_eq!(parse_codes(" +234, 254 ,,"), ["234", "254"]);
+        assert!(parse_codes("").is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+
+use crate::model::{UserSessions, UserState};
+use crate::money::format_money;
+use crate::outbound;
+use crate::privacy::mask_phone;
+use crate::server::send_notice;
+use crate::store::{self, SessionStore};
+use crate::supervisor;
+use crate::window::Notice;
+
+/// The nudge for a flow left hanging in `session.state`, or `None` for `Initial`.
+pub fn reminder_message(session: &UserSessions) -> Option<String> {
+    flow_prompt(session).map(|prompt| format!("👋 {}", prompt))
+}
+
+/// Prepended to the first reply after a restart finds the user mid-flow, since what we
+/// last said to them may be long gone from their screen.
+pub fn restored_recap(session: &UserSessions) -> Option<String> {
+    flow_prompt(session).map(|prompt| format!("🔄 Picking up where you left off. {}", prompt))
+}
+
+/// `RESTORED_QUOTE_MAX_AGE_SECS` (default 300): a quote older than this when a restart
+/// finds it waiting for `confirm` is shown again at the current rate.
+pub fn restored_quote_stale(session: &UserSessions) -> bool {
+    let max_age = std::env::var("RESTORED_QUOTE_MAX_AGE_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300);
+    session.state == UserState::OfframpConfirmation
+        && session
+            .quoted_at
+            .is_none_or(|at| Utc::now() - at > chrono::Duration::seconds(max_age))
+}
+
+/// Where the user is in `session.state` and what to reply, or `None` for `Initial`.
+fn flow_prompt(session: &UserSessions) -> Option<String> {
+    let bank = session
+        .pending_bank_details
+        .as_ref()
+        .map(|details| details.bank_name.as_str())
+        .or(session
+            .pending_bank_verification
+            .as_ref()
+            .map(|verification| verification.bank_name.as_str()));
+    let withdrawing = match (
+        session.pending_amount,
+        session.pending_currency.as_deref(),
+        bank,
+    ) {
+        (Some(amount), Some(token), Some(bank)) => format!(
+            "You were withdrawing {} to {}",
+            format_money(amount, token),
+            bank
+        ),
+        (Some(amount), Some(token), None) => {
+            format!("You were withdrawing {}", format_money(amount, token))
+        }
+        _ => "You were in the middle of a withdrawal".to_string(),
+    };
+
+    let message = match session.state {
+        UserState::Initial => return None,
+        UserState::AccountCreation => "Your Kharon Pay account isn't set up yet.\n\n\
+            Reply with a username to finish creating it."
+            .to_string(),
+        UserState::OfframpConfirmation => format!(
+            "{} — reply `confirm` to continue or `cancel` to stop.",
+            withdrawing
+        ),
+        UserState::BankSelection => format!(
+            "{} — reply with the number of the bank to use, or `no` to cancel.",
+            withdrawing
+        ),
+        UserState::SavedBankConfirmation => format!(
+            "{} — reply `yes` to use your saved bank or `no` to cancel.",
+            withdrawing
+        ),
+        UserState::BankDetailsEntry => format!(
+            "{} — send your bank details as `Bank Name, Account Number` to continue.",
+            withdrawing
+        ),
+        UserState::AmountConfirmation => format!(
+            "{} — type the amount to confirm, or `cancel` to stop.",
+            withdrawing
+        ),
+        UserState::PayoutApproval => format!(
+            "{} — the payout amount changed. Reply `yes` to accept it or `no` to cancel.",
+            withdrawing
+        ),
+        UserState::BankDetailsConfirmation => format!(
+            "{} — reply `yes` if the account details are correct or `no` to re-enter them.",
+            withdrawing
+        ),
+        UserState::BankNameUpdate => "Your bank now shows a different account name. \
+            Reply `yes` to update your saved details or `no` to keep them."
+            .to_string(),
+    };
+
+    Some(message)
+}
+
+/// Nudges users whose session has sat mid-flow for `REMINDER_IDLE_SECS` (default 10 min),
+/// scanning every `REMINDER_SCAN_INTERVAL_SECS` (default 60). Set `REMINDER_IDLE_SECS=0`
+/// to turn reminders off.
+pub fn start_reminder_task(store: Arc<dyn SessionStore>) {
+    let idle_secs: u64 = std::env::var("REMINDER_IDLE_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10 * 60);
+    if idle_secs == 0 {
+        return;
+    }
+    let idle = Duration::from_secs(idle_secs);
+    let interval = Duration::from_secs(
+        std::env::var("REMINDER_SCAN_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+    );
+
+    supervisor::supervise("reminders", move || {
+        let store = store.clone();
+        async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                if let Err(e) = remind_stalled(&store, idle).await {
+                    eprintln!("Reminder scan failed: {}", e);
+                }
+            }
+        }
+    });
+}
+
+/// Nudges, once, everyone whose session has sat mid-flow for `idle`. Returns how many
+/// were nudged.
+pub async fn remind_stalled(
+    store: &Arc<dyn SessionStore>,
+    idle: Duration,
+) -> Result<usize, String> {
+    let scan = store.clone();
+    let stalled = store::blocking(move || scan.stalled(idle)).await?;
+
+    let mut reminded = 0;
+    for (i, session) in stalled.into_iter().enumerate() {
+        let Some(message) = reminder_message(&session) else {
+            continue;
+        };
+
+        // Same default gap as `outbound::send_sequence` so a large batch doesn't burst
+        if i > 0 {
+            tokio::time::sleep(outbound::default_gap()).await;
+        }
+
+        // Re-read so a reply that landed since the scan isn't overwritten, and mark
+        // before sending: a missed nudge is better than a repeated one
+        let (current, phone) = (store.clone(), session.phone.clone());
+        let mut session = match store::blocking(move || current.load(&phone)).await {
+            Ok(Some(current)) if current.state == session.state && !current.reminded => current,
+            _ => continue,
+        };
+        session.reminded = true;
+        let (saving, saved) = (store.clone(), session.clone());
+        if let Err(e) = store::blocking(move || saving.save(&saved)).await {
+            eprintln!(
+                "Failed to mark session reminded for {}: {}",
+                mask_phone(&session.phone),
+                e
+            );
+            continue;
+        }
+
+        send_notice(&session.phone, &message, Notice::Reminder).await;
+        println!(
+            "Sent abandoned-flow reminder to {} ({:?})",
+            mask_phone(&session.phone),
+            session.state
+        );
+        reminded += 1;
+    }
+    Ok(reminded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemorySessionStore;
+    use crate::{prefs, twilio, window};
+
+    fn withdrawing(phone: &str) -> UserSessions {
+        let mut session = UserSessions::new(phone);
+        session.state = UserState::OfframpConfirmation;
+        session.pending_amount = Some(50.0);
+        session.pending_currency = Some("USDT".to_string());
+        // Inside the 24-hour window, so the nudge goes out as freeform
+        window::record_inbound(phone, Utc::now());
+        session
+    }
+
+    fn store_with(sessions: &[UserSessions]) -> Arc<dyn SessionStore> {
+        let store = MemorySessionStore::with_shards(1);
+        for session in sessions {
+            store.save(session).unwrap();
+        }
+        Arc::new(store)
+    }
+
+    async fn remind(store: &Arc<dyn SessionStore>, idle: Duration) -> Vec<twilio::CapturedMessage> {
+        let (reminded, sent) = twilio::capture(remind_stalled(store, idle), Duration::ZERO).await;
+        assert_eq!(reminded.unwrap(), sent.len());
+        sent
+    }
+
+    #[tokio::test]
+    async fn a_stalled_flow_is_nudged_once() {
+        let stalled = "+2348090000701";
+        let store = store_with(&[withdrawing(stalled), UserSessions::new("+2348090000702")]);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let sent = remind(&store, Duration::ZERO).await;
+        assert_eq!(sent.len(), 1, "{:?}", sent);
+        assert_eq!(sent[0].to, stalled);
+        assert_eq!(
+            sent[0].body,
+            "👋 You were withdrawing 50.00 USDT — reply `confirm` to continue or `cancel` to stop."
+        );
+        assert!(store.load(stalled).unwrap().unwrap().reminded);
+
+        // Still stalled, but already nudged
+        assert!(remind(&store, Duration::ZERO).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_active_flow_is_left_alone() {
+        let store = store_with(&[withdrawing("+2348090000703")]);
+        assert!(remind(&store, Duration::from_secs(600)).await.is_empty());
+        assert!(!store.load("+2348090000703").unwrap().unwrap().reminded);
+    }
+
+    #[tokio::test]
+    async fn users_who_turned_notifications_off_are_not_nudged() {
+        let phone = "+2348090000704";
+        prefs::update(phone, |prefs| prefs.notifications_off = true)
+            .await
+            .unwrap();
+        let store = store_with(&[withdrawing(phone)]);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let (_, sent) =
+            twilio::capture(remind_stalled(&store, Duration::ZERO), Duration::ZERO).await;
+        assert!(sent.is_empty(), "{:?}", sent);
+    }
+}
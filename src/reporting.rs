@@ -0,0 +1,430 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sentry::protocol::{Event, Level, User};
+use serde::Serialize;
+
+use crate::build_info;
+use crate::model::{StateTransition, UserSessions, UserState};
+use crate::privacy::{mask_digit_runs, mask_phone};
+
+/// Transitions kept per user as breadcrumbs, and for how long.
+const MAX_BREADCRUMBS: usize = 10;
+const BREADCRUMB_TTL: chrono::Duration = chrono::Duration::hours(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+    Fatal,
+}
+
+/// Who and what a report is about, set for the duration of a webhook or background
+/// task. The phone is kept raw here and masked when the report is built.
+#[derive(Debug, Clone, Default)]
+pub struct ReportContext {
+    phone: Option<String>,
+    correlation_id: Option<String>,
+    state: Option<UserState>,
+    task: Option<&'static str>,
+    /// How the message being handled was classified; see `StateTransition::trigger`.
+    trigger: Option<String>,
+    /// The session's stored transitions, for when the in-memory trail was lost to a
+    /// restart.
+    history: Vec<Breadcrumb>,
+}
+
+impl ReportContext {
+    pub fn for_session(session: &UserSessions) -> Self {
+        ReportContext {
+            phone: Some(session.phone.clone()),
+            correlation_id: session.correlation_id.clone(),
+            state: Some(session.state),
+            task: None,
+            trigger: None,
+            history: session.transitions.iter().map(breadcrumb).collect(),
+        }
+    }
+
+    pub fn with_trigger(mut self, trigger: &str) -> Self {
+        self.trigger = Some(trigger.to_string());
+        self
+    }
+
+    pub fn for_task(name: &'static str) -> Self {
+        ReportContext {
+            task: Some(name),
+            ..ReportContext::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Breadcrumb {
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+}
+
+/// Everything that leaves the process. Built only through `ErrorReport::new`, which
+/// masks the phone and any digit runs in the message.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub severity: Severity,
+    pub message: String,
+    pub phone: Option<String>,
+    pub correlation_id: Option<String>,
+    pub state: Option<UserState>,
+    pub endpoint: Option<String>,
+    pub task: Option<&'static str>,
+    pub breadcrumbs: Vec<Breadcrumb>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl ErrorReport {
+    fn new(severity: Severity, message: &str, endpoint: Option<&str>) -> Self {
+        let context = CONTEXT.try_with(Clone::clone).unwrap_or_default();
+        let mut breadcrumbs = context
+            .phone
+            .as_deref()
+            .map(breadcrumbs_for)
+            .unwrap_or_default();
+        if breadcrumbs.is_empty() {
+            breadcrumbs = context.history;
+        }
+        ErrorReport {
+            severity,
+            message: mask_digit_runs(message),
+            phone: context.phone.as_deref().map(mask_phone),
+            correlation_id: context.correlation_id,
+            state: context.state,
+            endpoint: endpoint.map(str::to_string),
+            task: context.task,
+            breadcrumbs,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+pub trait ErrorReporter: Send + Sync {
+    fn report(&self, report: &ErrorReport);
+}
+
+/// Used when `SENTRY_DSN` is unset: the report goes to stderr, where the rest of our
+/// errors already are.
+pub struct LogReporter;
+
+impl ErrorReporter for LogReporter {
+    fn report(&self, report: &ErrorReport) {
+        let json = serde_json::to_string(report).unwrap_or_else(|_| report.message.clone());
+        eprintln!("ALERT: Error report: {}", json);
+    }
+}
+
+/// Sends reports through the Sentry SDK to the project named by `SENTRY_DSN`, tagged with
+/// `SENTRY_ENVIRONMENT` (default `production`) and this build's release.
+pub struct SentryReporter {
+    client: sentry::Client,
+}
+
+impl SentryReporter {
+    pub fn from_dsn(dsn: &str) -> Result<Self, String> {
+        let dsn: sentry::types::Dsn = dsn
+            .parse()
+            .map_err(|e| format!("SENTRY_DSN is invalid: {}", e))?;
+        let environment =
+            std::env::var("SENTRY_ENVIRONMENT").unwrap_or_else(|_| "production".to_string());
+        let mut options = sentry::ClientOptions::default();
+        options.dsn = Some(dsn);
+        options.release =
+            Some(format!("{}@{}", env!("CARGO_PKG_NAME"), build_info::VERSION).into());
+        options.environment = Some(environment.into());
+        // Everything we send is masked by `ErrorReport::new`; don't let the SDK add more
+        options.send_default_pii = false;
+        let client = sentry::Client::from(options);
+        Ok(SentryReporter { client })
+    }
+
+    fn event(report: &ErrorReport) -> Event<'static> {
+        let level = match report.severity {
+            Severity::Warning => Level::Warning,
+            Severity::Error => Level::Error,
+            Severity::Fatal => Level::Fatal,
+        };
+        let tags = [
+            ("correlation_id", report.correlation_id.clone()),
+            ("state", report.state.map(|state| format!("{:?}", state))),
+            ("endpoint", report.endpoint.clone()),
+            ("task", report.task.map(str::to_string)),
+        ]
+        .into_iter()
+        .filter_map(|(tag, value)| Some((tag.to_string(), value?)))
+        .collect();
+        let breadcrumbs: Vec<sentry::Breadcrumb> = report
+            .breadcrumbs
+            .iter()
+            .map(|crumb| sentry::Breadcrumb {
+                timestamp: crumb.timestamp.into(),
+                category: Some("state".to_string()),
+                message: Some(crumb.message.clone()),
+                ..Default::default()
+            })
+            .collect();
+        Event {
+            level,
+            message: Some(report.message.clone()),
+            user: report.phone.as_ref().map(|phone| User {
+                id: Some(phone.clone()),
+                ..Default::default()
+            }),
+            tags,
+            breadcrumbs: breadcrumbs.into(),
+            timestamp: report.timestamp.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl ErrorReporter for SentryReporter {
+    fn report(&self, report: &ErrorReport) {
+        self.client.capture_event(Self::event(report), None);
+        // The SDK sends from its own thread; a panic may take the process down first
+        if report.severity == Severity::Fatal && !self.client.flush(Some(Duration::from_secs(2))) {
+            LogReporter.report(report);
+        }
+    }
+}
+
+tokio::task_local! {
+    static CONTEXT: ReportContext;
+}
+
+static REPORTER: OnceLock<Box<dyn ErrorReporter>> = OnceLock::new();
+
+static BREADCRUMBS: LazyLock<Mutex<HashMap<String, VecDeque<Breadcrumb>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// `ERROR_REPORT_LEVEL`: `warning`, `error` (the default) or `fatal`. Panics are fatal,
+/// backend 5xx errors are errors and other backend rejections warnings.
+fn threshold() -> Severity {
+    match std::env::var("ERROR_REPORT_LEVEL")
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "warning" => Severity::Warning,
+        "fatal" => Severity::Fatal,
+        _ => Severity::Error,
+    }
+}
+
+/// Picks the reporter (Sentry when `SENTRY_DSN` is set) and reports every panic from
+/// then on, with whatever context the panicking task was running under.
+pub fn init() -> Result<(), String> {
+    let reporter: Box<dyn ErrorReporter> = match std::env::var("SENTRY_DSN") {
+        Ok(dsn) if !dsn.trim().is_empty() => {
+            println!("Reporting errors to Sentry");
+            Box::new(SentryReporter::from_dsn(dsn.trim())?)
+        }
+        _ => Box::new(LogReporter),
+    };
+    let _ = REPORTER.set(reporter);
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        let message = match info.location() {
+            Some(at) => format!("panicked at {}:{}: {}", at.file(), at.line(), payload),
+            None => format!("panicked: {}", payload),
+        };
+        capture(Severity::Fatal, &message, None);
+        previous(info);
+    }));
+    Ok(())
+}
+
+/// Reports `message` when it's at or above `ERROR_REPORT_LEVEL`. `endpoint` names the
+/// backend call involved, if any.
+pub fn capture(severity: Severity, message: &str, endpoint: Option<&str>) {
+    if severity < threshold() {
+        return;
+    }
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    reporter.report(&ErrorReport::new(severity, message, endpoint));
+}
+
+/// Runs `future` with `context` attached to anything it reports.
+pub async fn scoped<F: Future>(context: ReportContext, future: F) -> F::Output {
+    CONTEXT.scope(context, future).await
+}
+
+/// The correlation id of the flow the current task is working on, if any.
+pub fn current_correlation_id() -> Option<String> {
+    CONTEXT
+        .try_with(|context| context.correlation_id.clone())
+        .ok()
+        .flatten()
+}
+
+/// How the message the current task is handling was classified, if it is handling one.
+pub fn current_trigger() -> Option<String> {
+    CONTEXT
+        .try_with(|context| context.trigger.clone())
+        .ok()
+        .flatten()
+}
+
+fn breadcrumb(transition: &StateTransition) -> Breadcrumb {
+    let cause = match &transition.trigger {
+        Some(trigger) => format!("{}, {}", transition.event, trigger),
+        None => transition.event.clone(),
+    };
+    Breadcrumb {
+        timestamp: transition.at,
+        message: format!("{:?} -> {:?} ({})", transition.from, transition.to, cause),
+    }
+}
+
+/// Notes a state transition, sent along as a breadcrumb with the user's next report.
+pub fn record_transition(phone: &str, transition: &StateTransition) {
+    let now = Utc::now();
+    let mut crumbs = BREADCRUMBS.lock().unwrap_or_else(|e| e.into_inner());
+    crumbs.retain(|_, trail| {
+        trail
+            .back()
+            .is_some_and(|c| now - c.timestamp < BREADCRUMB_TTL)
+    });
+    let trail = crumbs.entry(phone.to_string()).or_default();
+    if trail.len() == MAX_BREADCRUMBS {
+        trail.pop_front();
+    }
+    trail.push_back(breadcrumb(transition));
+}
+
+fn breadcrumbs_for(phone: &str) -> Vec<Breadcrumb> {
+    let now = Utc::now();
+    BREADCRUMBS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(phone)
+        .map(|trail| {
+            trail
+                .iter()
+                .filter(|crumb| now - crumb.timestamp < BREADCRUMB_TTL)
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transition(from: UserState, to: UserState) -> StateTransition {
+        StateTransition {
+            at: Utc::now(),
+            from,
+            to,
+            event: "QuoteShown".to_string(),
+            trigger: Some("command:withdraw".to_string()),
+        }
+    }
+
+    async fn report_for(phone: &str) -> ErrorReport {
+        let mut session = UserSessions::new(phone);
+        session.state = UserState::OfframpConfirmation;
+        session.correlation_id = Some("corr-1".to_string());
+        record_transition(
+            phone,
+            &transition(UserState::Initial, UserState::OfframpConfirmation),
+        );
+
+        scoped(ReportContext::for_session(&session), async {
+            ErrorReport::new(
+                Severity::Error,
+                "Offramp for 2348090000501 returned 502",
+                Some("Offramp"),
+            )
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn reports_carry_the_masked_context() {
+        let report = report_for("+2348090000501").await;
+        assert_eq!(
+            report.phone.as_deref(),
+            Some(mask_phone("+2348090000501").as_str())
+        );
+        assert!(
+            !report.message.contains("2348090000501"),
+            "{}",
+            report.message
+        );
+        assert_eq!(report.correlation_id.as_deref(), Some("corr-1"));
+        assert_eq!(report.state, Some(UserState::OfframpConfirmation));
+        assert_eq!(report.endpoint.as_deref(), Some("Offramp"));
+        assert_eq!(report.breadcrumbs.len(), 1);
+        assert_eq!(
+            report.breadcrumbs[0].message,
+            "Initial -> OfframpConfirmation (QuoteShown, command:withdraw)"
+        );
+    }
+
+    #[tokio::test]
+    async fn outside_any_context_a_report_is_bare() {
+        let report = ErrorReport::new(Severity::Warning, "rate feed stale", None);
+        assert!(report.phone.is_none() && report.state.is_none() && report.task.is_none());
+        assert!(report.breadcrumbs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sentry_events_carry_the_report_and_nothing_more() {
+        let report = report_for("+2348090000502").await;
+        let events = sentry::test::with_captured_events(|| {
+            sentry::capture_event(SentryReporter::event(&report));
+        });
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+
+        assert_eq!(event.level, Level::Error);
+        assert_eq!(event.message.as_deref(), Some(report.message.as_str()));
+        let user = event.user.as_ref().expect("user");
+        assert_eq!(
+            user.id.as_deref(),
+            Some(mask_phone("+2348090000502").as_str())
+        );
+        assert!(user.ip_address.is_none());
+        assert_eq!(event.tags["correlation_id"], "corr-1");
+        assert_eq!(event.tags["state"], "OfframpConfirmation");
+        assert_eq!(event.tags["endpoint"], "Offramp");
+        assert!(!event.tags.contains_key("task"));
+        assert_eq!(event.breadcrumbs.len(), 1);
+
+        let sent = serde_json::to_string(event).unwrap();
+        assert!(!sent.contains("2348090000502"), "{}", sent);
+    }
+
+    #[test]
+    fn the_dsn_must_name_a_key_host_and_project() {
+        assert!(SentryReporter::from_dsn("https://public@o1.ingest.sentry.io/42").is_ok());
+        for dsn in [
+            "https://o1.ingest.sentry.io/42",
+            "not a dsn",
+            "https://public@host",
+        ] {
+            assert!(SentryReporter::from_dsn(dsn).is_err(), "{}", dsn);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::error::{ErrorBadRequest, InternalError};
+use actix_web::{
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, dev::Payload, http::header, web,
+};
+
+pub const FORM: &str = "application/x-www-form-urlencoded";
+pub const JSON: &str = "application/json";
+
+/// `MAX_BODY_BYTES`, default 64 KiB: the most a request body may be once decompressed.
+/// Twilio's webhooks are a few KiB, so anything near this is a mistake or a zip bomb.
+pub fn payload_config() -> web::PayloadConfig {
+    let limit = std::env::var("MAX_BODY_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|bytes| *bytes > 0)
+        .unwrap_or(64 * 1024);
+    web::PayloadConfig::new(limit)
+}
+
+fn unsupported(req: &HttpRequest, reason: String) -> Error {
+    eprintln!("Rejected request to {}: {}", req.path(), reason);
+    let response = HttpResponse::UnsupportedMediaType().json(serde_json::json!({
+        "success": false,
+        "message": reason,
+    }));
+    InternalError::from_response("unsupported media type", response).into()
+}
+
+/// Rejects, with 415, a body that isn't `expected` or is compressed in a way we don't
+/// read. gzip and deflate bodies are inflated by actix's `Bytes` extractor, within the
+/// `MAX_BODY_BYTES` limit.
+pub fn check_headers(req: &HttpRequest, expected: &str) -> Result<(), Error> {
+    match req.mime_type() {
+        Ok(Some(mime)) if mime.essence_str() == expected => {}
+        Ok(Some(mime)) => {
+            return Err(unsupported(
+                req,
+                format!("Content-Type must be {}, not {}", expected, mime),
+            ));
+        }
+        Ok(None) | Err(_) => {
+            return Err(unsupported(
+                req,
+                format!("Content-Type must be {}", expected),
+            ));
+        }
+    }
+
+    let encoding = req
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .map(|v| v.to_str().unwrap_or("").trim().to_lowercase());
+    match encoding.as_deref() {
+        None | Some("identity" | "gzip" | "x-gzip" | "deflate") => Ok(()),
+        Some(other) => Err(unsupported(
+            req,
+            format!("Content-Encoding {} is not supported", other),
+        )),
+    }
+}
+
+/// A Twilio webhook's form fields, read from a urlencoded (optionally gzip or deflate
+/// compressed) body.
+pub struct TwilioForm(pub HashMap<String, String>);
+
+impl FromRequest for TwilioForm {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if let Err(e) = check_headers(req, FORM) {
+            return Box::pin(async move { Err(e) });
+        }
+        let body = web::Bytes::from_request(req, payload);
+        Box::pin(async move {
+            let body = body.await?;
+            serde_urlencoded::from_bytes(&body)
+                .map(TwilioForm)
+                .map_err(|_| ErrorBadRequest("Invalid form data"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, http::StatusCode};
+    use flate2::{Compression, write::GzEncoder};
+
+    use super::*;
+
+    async fn fields(TwilioForm(form): TwilioForm) -> HttpResponse {
+        HttpResponse::Ok().body(form.len().to_string())
+    }
+
+    /// POSTs `body` with the given headers to a route reading a `TwilioForm`.
+    async fn post(headers: &[(&str, &str)], body: Vec<u8>) -> (StatusCode, String) {
+        let app = init_service(
+            App::new()
+                .app_data(payload_config())
+                .route("/", web::post().to(fields)),
+        )
+        .await;
+        let mut request = TestRequest::post().uri("/");
+        for header in headers {
+            request = request.insert_header(*header);
+        }
+        let response = call_service(&app, request.set_payload(body).to_request()).await;
+        let status = response.status();
+        let body = actix_web::test::read_body(response).await;
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    const FORM_BODY: &[u8] = b"From=whatsapp%3A%2B2348010000001&Body=help";
+
+    #[actix_web::test]
+    async fn plain_and_gzipped_forms_are_read() {
+        let (status, body) = post(&[("content-type", FORM)], FORM_BODY.to_vec()).await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "2"));
+
+        let headers = [
+            (
+                "content-type",
+                "application/x-www-form-urlencoded; charset=utf-8",
+            ),
+            ("content-encoding", "gzip"),
+        ];
+        let (status, body) = post(&headers, gzip(FORM_BODY)).await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "2"));
+    }
+
+    #[actix_web::test]
+    async fn bodies_over_the_limit_are_refused_once_inflated() {
+        let huge = format!("Body={}", "a".repeat(64 * 1024));
+        let (status, _) = post(&[("content-type", FORM)], huge.clone().into_bytes()).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // A few hundred bytes on the wire
+        let bomb = gzip(huge.as_bytes());
+        assert!(bomb.len() < 1024, "{}", bomb.len());
+        let headers = [("content-type", FORM), ("content-encoding", "gzip")];
+        let (status, _) = post(&headers, bomb).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn other_types_and_encodings_are_unsupported() {
+        let (status, body) = post(&[("content-type", JSON)], b"{}".to_vec()).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(
+            body.contains("must be application/x-www-form-urlencoded"),
+            "{}",
+            body
+        );
+
+        let (status, _) = post(&[], FORM_BODY.to_vec()).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let headers = [("content-type", FORM), ("content-encoding", "br")];
+        let (status, body) = post(&headers, FORM_BODY.to_vec()).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(body.contains("Content-Encoding br"), "{}", body);
+    }
+}
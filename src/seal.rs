@@ -0,0 +1,350 @@
+use std::sync::LazyLock;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde_json::Value;
+
+use crate::config;
+use crate::model::UserSessions;
+use crate::privacy::mask_phone;
+
+/// Session fields holding full account numbers and names, encrypted before they hit disk.
+const SEALED_FIELDS: [&str; 3] = [
+    "pending_bank_details",
+    "pending_bank_verification",
+    "bank_choices",
+];
+
+/// Marks an encrypted field: `enc:v2:` + base64(nonce || ciphertext || tag), with the
+/// phone and field name as associated data so a value can't be moved to another session
+/// or field. `enc:v1:` fields, sealed without them, still open until they're rewritten.
+const SEALED_PREFIX: &str = "enc:v2:";
+const LEGACY_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+type Key = [u8; 32];
+
+/// Fields are sealed with `primary`; `secondary` only decrypts, so `SESSION_ENC_KEY` can be
+/// rotated by moving the old key to `SESSION_ENC_KEY_SECONDARY`.
+struct SealKeys {
+    primary: Key,
+    secondary: Option<Key>,
+}
+
+impl SealKeys {
+    fn candidates(&self) -> impl Iterator<Item = &Key> {
+        std::iter::once(&self.primary).chain(self.secondary.as_ref())
+    }
+}
+
+fn parse_key(name: &str) -> Result<Option<Key>, String> {
+    let Some(encoded) = config::secret(name)? else {
+        return Ok(None);
+    };
+    let bytes = BASE64
+        .decode(encoded.as_bytes())
+        .map_err(|e| format!("{} is not valid base64: {}", name, e))?;
+    Key::try_from(bytes.as_slice())
+        .map(Some)
+        .map_err(|_| format!("{} must decode to 32 bytes, got {}", name, bytes.len()))
+}
+
+/// `SESSION_ENCRYPTION=off` turns sealing off; otherwise it's on whenever a key is set.
+fn load_keys() -> Result<Option<SealKeys>, String> {
+    if std::env::var("SESSION_ENCRYPTION").is_ok_and(|v| v.eq_ignore_ascii_case("off")) {
+        return Ok(None);
+    }
+
+    let secondary = parse_key("SESSION_ENC_KEY_SECONDARY")?;
+    match parse_key("SESSION_ENC_KEY")? {
+        Some(primary) => Ok(Some(SealKeys { primary, secondary })),
+        None if secondary.is_some() => {
+            Err("SESSION_ENC_KEY_SECONDARY is set without SESSION_ENC_KEY".to_string())
+        }
+        None => Ok(None),
+    }
+}
+
+static KEYS: LazyLock<Result<Option<SealKeys>, String>> = LazyLock::new(load_keys);
+
+fn keys() -> Option<&'static SealKeys> {
+    KEYS.as_ref().ok().and_then(Option::as_ref)
+}
+
+/// Called before opening a store that writes to disk: a malformed key, or no key without
+/// an explicit `SESSION_ENCRYPTION=off`, stops startup rather than writing plaintext.
+pub fn require_for_disk() -> Result<(), String> {
+    match KEYS.as_ref() {
+        Err(e) => Err(e.clone()),
+        Ok(Some(_)) => Ok(()),
+        Ok(None) if std::env::var("SESSION_ENCRYPTION").is_ok() => Ok(()),
+        Ok(None) => Err(
+            "SESSION_ENC_KEY must be set for persistent sessions (or SESSION_ENCRYPTION=off)"
+                .to_string(),
+        ),
+    }
+}
+
+/// What a field's ciphertext is bound to: `phone || 0x00 || field`.
+fn associated_data(phone: &str, field: &str) -> Vec<u8> {
+    [phone.as_bytes(), &[0], field.as_bytes()].concat()
+}
+
+fn seal(key: &Key, aad: &[u8], plaintext: &[u8]) -> Result<String, String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    // `ciphertext || tag`, the layout `open` splits
+    let ciphertext = Aes256Gcm::new(key.into())
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|e| format!("Failed to encrypt session field: {}", e))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(format!("{}{}", SEALED_PREFIX, BASE64.encode(sealed)))
+}
+
+fn open(keys: &SealKeys, sealed: &str, aad: &[u8]) -> Result<Vec<u8>, String> {
+    let (encoded, aad) = match sealed.strip_prefix(SEALED_PREFIX) {
+        Some(encoded) => (encoded, aad),
+        None => (
+            sealed.strip_prefix(LEGACY_PREFIX).unwrap_or(sealed),
+            &[][..],
+        ),
+    };
+    let bytes = BASE64
+        .decode(encoded)
+        .map_err(|_| "encrypted field is not valid base64".to_string())?;
+    if bytes.len() < NONCE_LEN + TAG_LEN {
+        return Err("encrypted field is truncated".to_string());
+    }
+
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    keys.candidates()
+        .find_map(|key| {
+            Aes256Gcm::new(key.into())
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: ciphertext,
+                        aad,
+                    },
+                )
+                .ok()
+        })
+        .ok_or_else(|| "wrong SESSION_ENC_KEY or corrupted data".to_string())
+}
+
+fn is_sealed(field: &str) -> bool {
+    field.starts_with(SEALED_PREFIX) || field.starts_with(LEGACY_PREFIX)
+}
+
+/// Serializes a session for storage, encrypting `SEALED_FIELDS` when a key is configured.
+pub fn seal_session(session: &UserSessions) -> Result<String, String> {
+    let mut value =
+        serde_json::to_value(session).map_err(|e| format!("Failed to encode session: {}", e))?;
+
+    if let Some(keys) = keys()
+        && let Some(fields) = value.as_object_mut()
+    {
+        for name in SEALED_FIELDS {
+            let Some(field) = fields.get_mut(name).filter(|f| !is_empty(f)) else {
+                continue;
+            };
+            let plaintext = serde_json::to_vec(field)
+                .map_err(|e| format!("Failed to encode {}: {}", name, e))?;
+            let aad = associated_data(&session.phone, name);
+            *field = Value::String(seal(&keys.primary, &aad, &plaintext)?);
+        }
+    }
+
+    serde_json::to_string(&value).map_err(|e| format!("Failed to encode session: {}", e))
+}
+
+fn is_empty(field: &Value) -> bool {
+    match field {
+        Value::Null => true,
+        Value::Array(items) => items.is_empty(),
+        _ => false,
+    }
+}
+
+fn open_fields(phone: &str, value: &mut Value) -> Result<(), String> {
+    let Some(fields) = value.as_object_mut() else {
+        return Ok(());
+    };
+    for name in SEALED_FIELDS {
+        // Rows written before encryption was turned on still hold the plain object
+        let Some(field) = fields.get_mut(name) else {
+            continue;
+        };
+        let Some(sealed) = field.as_str().filter(|s| is_sealed(s)) else {
+            continue;
+        };
+        let keys = keys().ok_or("field is encrypted but no SESSION_ENC_KEY is configured")?;
+        let plaintext = open(keys, sealed, &associated_data(phone, name))
+            .map_err(|e| format!("{}: {}", name, e))?;
+        *field = serde_json::from_slice(&plaintext)
+            .map_err(|e| format!("{}: decrypted to invalid JSON: {}", name, e))?;
+    }
+    Ok(())
+}
+
+/// Seals a single value stored outside a session, bound to the row it's stored under
+/// (`context`) and its column. Plain when sealing is off, as sessions are.
+pub fn seal_value(context: &str, field: &str, plaintext: &str) -> Result<String, String> {
+    seal_value_with(keys(), context, field, plaintext)
+}
+
+fn seal_value_with(
+    keys: Option<&SealKeys>,
+    context: &str,
+    field: &str,
+    plaintext: &str,
+) -> Result<String, String> {
+    match keys {
+        Some(keys) => seal(
+            &keys.primary,
+            &associated_data(context, field),
+            plaintext.as_bytes(),
+        ),
+        None => Ok(plaintext.to_string()),
+    }
+}
+
+/// The value `seal_value` stored; values written before sealing was on come back as-is.
+pub fn open_value(context: &str, field: &str, stored: &str) -> Result<String, String> {
+    open_value_with(keys(), context, field, stored)
+}
+
+fn open_value_with(
+    keys: Option<&SealKeys>,
+    context: &str,
+    field: &str,
+    stored: &str,
+) -> Result<String, String> {
+    if !is_sealed(stored) {
+        return Ok(stored.to_string());
+    }
+    let keys = keys.ok_or("value is encrypted but no SESSION_ENC_KEY is configured")?;
+    let plaintext = open(keys, stored, &associated_data(context, field))
+        .map_err(|e| format!("{}: {}", field, e))?;
+    String::from_utf8(plaintext).map_err(|_| format!("{}: decrypted to invalid UTF-8", field))
+}
+
+/// Decodes a stored session. A field that won't decrypt costs the user their in-flight
+/// flow, not their access: the session starts over and we raise an alert.
+pub fn open_session(phone: &str, json: &str) -> Result<UserSessions, String> {
+    let mut value: Value = serde_json::from_str(json)
+        .map_err(|e| format!("Failed to decode stored session for {}: {}", phone, e))?;
+
+    if let Err(e) = open_fields(phone, &mut value) {
+        eprintln!(
+            "ALERT: Could not decrypt stored session for {} ({}); starting a fresh session",
+            mask_phone(phone),
+            e
+        );
+        return Ok(UserSessions::new(phone));
+    }
+
+    serde_json::from_value::<UserSessions>(value)
+        .map(UserSessions::upgrade)
+        .map_err(|e| format!("Failed to decode stored session for {}: {}", phone, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(primary: u8, secondary: Option<u8>) -> SealKeys {
+        SealKeys {
+            primary: [primary; 32],
+            secondary: secondary.map(|byte| [byte; 32]),
+        }
+    }
+
+    /// A field as builds before associated data sealed it.
+    fn seal_v1(key: &Key, plaintext: &[u8]) -> String {
+        let sealed = seal(key, &[], plaintext).unwrap();
+        sealed.replacen(SEALED_PREFIX, LEGACY_PREFIX, 1)
+    }
+
+    #[test]
+    fn fields_round_trip_under_either_key() {
+        let aad = associated_data("+2348012345678", "bank_choices");
+        let sealed = seal(&[1; 32], &aad, b"[1,2,3]").unwrap();
+        assert!(sealed.starts_with("enc:v2:"));
+        assert_eq!(open(&keys(1, None), &sealed, &aad).unwrap(), b"[1,2,3]");
+        // After a rotation the old key is the secondary
+        assert_eq!(open(&keys(2, Some(1)), &sealed, &aad).unwrap(), b"[1,2,3]");
+    }
+
+    #[test]
+    fn the_wrong_key_does_not_open_a_field() {
+        let aad = associated_data("+2348012345678", "bank_choices");
+        let sealed = seal(&[1; 32], &aad, b"{}").unwrap();
+        let err = open(&keys(2, Some(3)), &sealed, &aad).unwrap_err();
+        assert!(err.contains("wrong SESSION_ENC_KEY"), "{}", err);
+    }
+
+    #[test]
+    fn tampered_ciphertext_does_not_open() {
+        let aad = associated_data("+2348012345678", "bank_choices");
+        let sealed = seal(&[1; 32], &aad, b"{\"account\":\"0123456789\"}").unwrap();
+        let mut bytes = BASE64.decode(&sealed[SEALED_PREFIX.len()..]).unwrap();
+        for i in [0, NONCE_LEN, bytes.len() - 1] {
+            bytes[i] ^= 1;
+            let tampered = format!("{}{}", SEALED_PREFIX, BASE64.encode(&bytes));
+            assert!(open(&keys(1, None), &tampered, &aad).is_err(), "byte {}", i);
+            bytes[i] ^= 1;
+        }
+
+        let truncated = format!("{}{}", SEALED_PREFIX, BASE64.encode(&bytes[..NONCE_LEN]));
+        assert!(open(&keys(1, None), &truncated, &aad).is_err());
+    }
+
+    #[test]
+    fn a_field_moved_to_another_session_or_field_does_not_open() {
+        let aad = associated_data("+2348012345678", "pending_bank_details");
+        let sealed = seal(&[1; 32], &aad, b"{}").unwrap();
+        let other_phone = associated_data("+2348012345679", "pending_bank_details");
+        let other_field = associated_data("+2348012345678", "bank_choices");
+        assert!(open(&keys(1, None), &sealed, &other_phone).is_err());
+        assert!(open(&keys(1, None), &sealed, &other_field).is_err());
+    }
+
+    #[test]
+    fn fields_sealed_before_associated_data_still_open() {
+        let sealed = seal_v1(&[1; 32], b"{}");
+        assert!(is_sealed(&sealed));
+        let aad = associated_data("+2348012345678", "bank_choices");
+        assert_eq!(open(&keys(1, None), &sealed, &aad).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn single_values_are_sealed_to_their_row() {
+        let keys = keys(1, None);
+        let sealed = seal_value_with(Some(&keys), "KP-REF-1", "phone", "+2348012345678").unwrap();
+        assert!(sealed.starts_with("enc:v2:"));
+        assert!(!sealed.contains("2348012345678"));
+        assert_eq!(
+            open_value_with(Some(&keys), "KP-REF-1", "phone", &sealed).unwrap(),
+            "+2348012345678"
+        );
+        // Copied onto another withdrawal's row, it doesn't open
+        assert!(open_value_with(Some(&keys), "KP-REF-2", "phone", &sealed).is_err());
+        assert!(open_value_with(None, "KP-REF-1", "phone", &sealed).is_err());
+
+        // Rows from before sealing was turned on
+        assert_eq!(
+            open_value_with(Some(&keys), "KP-REF-1", "phone", "+2348012345678").unwrap(),
+            "+2348012345678"
+        );
+    }
+}
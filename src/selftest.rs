@@ -0,0 +1,191 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde::Serialize;
+use tokio::task::JoinSet;
+
+use crate::model::UserSessions;
+use crate::store::SessionStore;
+use crate::{config, outbound};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Not a real number, so the round trip can never touch a user's session.
+const PROBE_PHONE: &str = "+000000000000";
+
+/// One dependency checked at startup, named by the variable that configures it.
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub variable: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(variable: impl Into<String>, outcome: Result<String, String>) -> Self {
+        let (ok, detail) = match outcome {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        CheckResult {
+            variable: variable.into(),
+            ok,
+            detail,
+        }
+    }
+}
+
+static RESULTS: OnceLock<Vec<CheckResult>> = OnceLock::new();
+
+/// What the startup self-test found, for `/readyz`; `None` when it was skipped.
+pub fn results() -> Option<&'static [CheckResult]> {
+    RESULTS.get().map(Vec::as_slice)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Off,
+    Warn,
+    Strict,
+}
+
+/// `SELF_TEST`: `warn` (the default) logs failures and starts anyway, `strict` refuses to
+/// start, `off` skips the checks.
+fn mode() -> Result<Mode, String> {
+    match std::env::var("SELF_TEST")
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "" | "warn" => Ok(Mode::Warn),
+        "strict" => Ok(Mode::Strict),
+        "off" => Ok(Mode::Off),
+        other => Err(format!(
+            "SELF_TEST must be off, warn or strict, not '{}'",
+            other
+        )),
+    }
+}
+
+/// Every backend URL we'll call: `SERVER_*_ENDPOINT`, the status endpoints and the
+/// support webhook.
+fn endpoint_variables() -> Vec<(String, String)> {
+    let mut endpoints: Vec<(String, String)> = std::env::vars()
+        .filter(|(name, value)| {
+            let backend = name.starts_with("SERVER_") && name.ends_with("_ENDPOINT");
+            let status = name == "TRANSACTION_STATUS_ENDPOINT"
+                || name == "TRANSACTION_STATUS_BATCH_ENDPOINT"
+                || name == "SUPPORT_WEBHOOK_URL";
+            (backend || status) && !value.is_empty()
+        })
+        .collect();
+    endpoints.sort();
+    endpoints
+}
+
+/// Any HTTP response counts: we only want to know the host is there. HEAD keeps it free
+/// of side effects on endpoints that only expect POST.
+async fn probe_endpoint(client: reqwest::Client, url: String) -> Result<String, String> {
+    client
+        .head(&url)
+        .send()
+        .await
+        .map(|response| format!("responded {}", response.status()))
+        .map_err(|e| format!("unreachable: {}", e))
+}
+
+async fn check_twilio(client: &reqwest::Client) -> Result<String, String> {
+    if outbound::dry_run() {
+        return Ok("skipped (TWILIO_DRY_RUN)".to_string());
+    }
+    let (Ok(Some(sid)), Ok(Some(token)), Ok(url)) = (
+        config::secret("T_ACCOUNT_SID"),
+        config::secret("T_AUTH_TOKEN"),
+        std::env::var("T_API_URL"),
+    ) else {
+        return Err("credentials are not configured".to_string());
+    };
+
+    // Listing one message is the cheapest authenticated call on the URL we send to
+    let response = client
+        .get(&url)
+        .query(&[("PageSize", "1")])
+        .header(
+            "Authorization",
+            format!("Basic {}", BASE64.encode(format!("{}:{}", sid, token))),
+        )
+        .send()
+        .await
+        .map_err(|e| format!("unreachable: {}", e))?;
+    match response.status() {
+        status if status.is_success() => Ok("credentials accepted".to_string()),
+        status if status.as_u16() == 401 || status.as_u16() == 403 => {
+            Err(format!("credentials rejected ({})", status))
+        }
+        status => Err(format!("responded {}", status)),
+    }
+}
+
+fn check_store(store: &dyn SessionStore) -> Result<String, String> {
+    store.save(&UserSessions::new(PROBE_PHONE))?;
+    let loaded = store.load(PROBE_PHONE);
+    store.delete(PROBE_PHONE)?;
+    match loaded? {
+        Some(session) if session.phone == PROBE_PHONE => Ok("write/read/delete ok".to_string()),
+        Some(_) => Err("read back a different session".to_string()),
+        None => Err("wrote a session but could not read it back".to_string()),
+    }
+}
+
+/// Exercises the backend, Twilio and the session store before the listener binds, so a
+/// misconfigured endpoint shows up in the deploy log rather than in the first user's chat.
+pub async fn run(store: &dyn SessionStore) -> Result<(), String> {
+    let mode = mode()?;
+    if mode == Mode::Off {
+        return Ok(());
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Failed to build self-test client: {}", e))?;
+
+    let mut probes = JoinSet::new();
+    for (variable, url) in endpoint_variables() {
+        let client = client.clone();
+        probes.spawn(async move { CheckResult::new(variable, probe_endpoint(client, url).await) });
+    }
+
+    let mut results = vec![
+        CheckResult::new("T_AUTH_TOKEN", check_twilio(&client).await),
+        CheckResult::new("SESSION_STORE", check_store(store)),
+    ];
+    while let Some(result) = probes.join_next().await {
+        results.extend(result.ok());
+    }
+    results.sort_by(|a, b| a.variable.cmp(&b.variable));
+
+    for result in &results {
+        let mark = if result.ok { "✅" } else { "❌" };
+        println!("Self-test {} {}: {}", mark, result.variable, result.detail);
+    }
+    let failed: Vec<&str> = results
+        .iter()
+        .filter(|r| !r.ok)
+        .map(|r| r.variable.as_str())
+        .collect();
+    let summary = failed.join(", ");
+    let _ = RESULTS.set(results);
+
+    match (summary.is_empty(), mode) {
+        (true, _) => Ok(()),
+        (false, Mode::Strict) => Err(format!("Startup self-test failed: {}", summary)),
+        (false, _) => {
+            eprintln!(
+                "ALERT: Startup self-test failed for {}; starting anyway (SELF_TEST=strict refuses)",
+                summary
+            );
+            Ok(())
+        }
+    }
+}
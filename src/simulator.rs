@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use serde::Deserialize;
+
+use crate::access_log::WebhookNote;
+use crate::admin;
+use crate::inbound::{self, Inbound};
+use crate::store::{self, SessionStore};
+use crate::twilio;
+use crate::webhook::handle_message;
+
+/// `DEBUG_SIMULATOR=1`: serves `/debug/simulate`, for walking flows on staging without
+/// a WhatsApp number. Never set it in production.
+pub fn enabled() -> bool {
+    matches!(
+        std::env::var("DEBUG_SIMULATOR").as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+/// `SIMULATOR_SETTLE_SECS`, default 30: how long to wait on work a message started in
+/// the background, like account creation, for the messages it sends.
+fn settle() -> Duration {
+    Duration::from_secs(
+        std::env::var("SIMULATOR_SETTLE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct SimulatedMessage {
+    /// Anything Twilio might send as `From`: `+234…`, `234…` or `whatsapp:+234…`.
+    pub phone: String,
+    pub body: String,
+}
+
+/// Runs a message through the webhook's `handle_message`, against the real session
+/// store, and returns what would have been sent instead of sending it. Replies from
+/// transaction polling come later and go out as usual.
+pub async fn simulate(
+    req: HttpRequest,
+    message: web::Json<SimulatedMessage>,
+    sessions: web::Data<dyn SessionStore>,
+) -> Result<HttpResponse> {
+    if let Some(denied) = admin::authorize(&req) {
+        return Ok(denied);
+    }
+
+    let from = if message.phone.starts_with("whatsapp:") {
+        message.phone.clone()
+    } else {
+        format!("whatsapp:+{}", message.phone.trim_start_matches('+'))
+    };
+    let form = HashMap::from([
+        ("From".to_string(), from),
+        ("Body".to_string(), message.body.clone()),
+    ]);
+    let (phone, body) = match inbound::classify(&form) {
+        Inbound::Text { phone, body } | Inbound::Interactive { phone, body } => (phone, body),
+        _ => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "success": false,
+                "message": "Not a message the webhook would handle",
+            })));
+        }
+    };
+
+    let ((), messages) = twilio::capture(
+        handle_message(&phone, &body, sessions.clone(), &WebhookNote::default()),
+        settle(),
+    )
+    .await;
+    let store = sessions.into_inner();
+    let key = phone.clone();
+    let state = store::blocking(move || store.load(&key))
+        .await
+        .ok()
+        .flatten()
+        .map(|session| format!("{:?}", session.state));
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "phone": phone,
+        "state": state,
+        "messages": messages,
+    })))
+}
+
+/// Mounted on every app; registers nothing unless `DEBUG_SIMULATOR` is on.
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    if !enabled() {
+        return;
+    }
+    cfg.route("/debug/simulate", web::post().to(simulate));
+}
@@ -0,0 +1,163 @@
+/// Validates a Starknet address and returns its canonical form: lowercase, `0x`-prefixed
+/// and zero-padded to 64 hex digits, so the same address always displays identically.
+pub fn normalize_address(address: &str) -> Result<String, String> {
+    let trimmed = address.trim();
+    let hex_digits = trimmed
+        .strip_prefix("0x")
+        .or_else(|| trimmed.strip_prefix("0X"))
+        .ok_or_else(|| "address must start with 0x".to_string())?;
+
+    if hex_digits.is_empty() {
+        return Err("address has no hex digits".to_string());
+    }
+    if hex_digits.len() > 64 {
+        return Err(format!(
+            "address has {} hex digits, expected at most 64",
+            hex_digits.len()
+        ));
+    }
+    if !hex_digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("address contains non-hex characters".to_string());
+    }
+    if hex_digits.chars().all(|c| c == '0') {
+        return Err("address is zero".to_string());
+    }
+
+    let canonical = format!("{:0>64}", hex_digits.to_lowercase());
+
+    // Contract addresses live below 2^251, i.e. the top byte is at most 0x07
+    if canonical.as_bytes()[0] != b'0' || canonical.as_bytes()[1] > b'7' {
+        return Err("address is outside the Starknet address range".to_string());
+    }
+
+    Ok(format!("0x{}", canonical))
+}
+
+/// Short form for labels, e.g. `0x0471…938d`.
+pub fn short_address(address: &str) -> String {
+    let chars: Vec<char> = address.chars().collect();
+    if chars.len() <= 10 {
+        return address.to_string();
+    }
+
+    format!(
+        "{}…{}",
+        chars[..6].iter().collect::<String>(),
+        chars[chars.len() - 4..].iter().collect::<String>()
+    )
+}
+
+/// Block explorer for `STARKNET_NETWORK` (`mainnet`, the default, or `sepolia`).
+/// `STARKNET_EXPLORER_URL` overrides it, e.g. to point at Voyager instead of Starkscan.
+pub fn explorer_base_url() -> String {
+    explorer_base(
+        std::env::var("STARKNET_NETWORK").ok().as_deref(),
+        std::env::var("STARKNET_EXPLORER_URL").ok().as_deref(),
+    )
+}
+
+fn explorer_base(network: Option<&str>, override_url: Option<&str>) -> String {
+    if let Some(url) = override_url
+        && !url.trim().is_empty()
+    {
+        return url.trim().trim_end_matches('/').to_string();
+    }
+
+    match network {
+        Some("sepolia") => "https://sepolia.starkscan.co".to_string(),
+        _ => "https://starkscan.co".to_string(),
+    }
+}
+
+/// Explorer page for a transaction, or `None` when the backend gave us no usable hash.
+pub fn explorer_tx_url(tx_hash: &str) -> Option<String> {
+    let tx_hash = tx_hash.trim();
+    if tx_hash.is_empty() {
+        return None;
+    }
+
+    Some(format!("{}/tx/{}", explorer_base_url(), tx_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "0x0471a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d5e6f708192a3b4c5d938d";
+
+    #[test]
+    fn valid_addresses_are_padded_and_lowercased() {
+        assert_eq!(normalize_address(ADDRESS).as_deref(), Ok(ADDRESS));
+        assert_eq!(
+            normalize_address(&format!("  0x{}\n", ADDRESS[2..].to_uppercase())).as_deref(),
+            Ok(ADDRESS)
+        );
+        // Leading zeros dropped by the backend come back
+        assert_eq!(
+            normalize_address("0x471a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d5e6f708192a3b4c5d938d")
+                .as_deref(),
+            Ok(ADDRESS)
+        );
+        assert_eq!(normalize_address("0X1"), Ok(format!("0x{:0>64}", "1")));
+    }
+
+    #[test]
+    fn short_and_overlong_addresses_are_rejected() {
+        assert!(normalize_address("0x").is_err());
+        assert!(normalize_address("").is_err());
+        let overlong = format!("0x{}", "1".repeat(65));
+        assert!(
+            normalize_address(&overlong)
+                .unwrap_err()
+                .contains("65 hex digits")
+        );
+    }
+
+    #[test]
+    fn non_hex_and_unprefixed_addresses_are_rejected() {
+        assert!(normalize_address("0x04g1").unwrap_err().contains("non-hex"));
+        assert!(normalize_address("0x04 71").is_err());
+        assert!(
+            normalize_address(&ADDRESS[2..])
+                .unwrap_err()
+                .contains("start with 0x")
+        );
+    }
+
+    #[test]
+    fn zero_and_out_of_range_addresses_are_rejected() {
+        assert!(normalize_address("0x0").unwrap_err().contains("zero"));
+        assert!(normalize_address(&format!("0x{}", "0".repeat(64))).is_err());
+        let too_big = format!("0x08{}", "0".repeat(62));
+        assert!(normalize_address(&too_big).unwrap_err().contains("range"));
+    }
+
+    #[test]
+    fn labels_use_a_truncated_preview() {
+        assert_eq!(short_address(ADDRESS), "0x0471…938d");
+        assert_eq!(short_address("0x1234"), "0x1234");
+    }
+
+    #[test]
+    fn the_explorer_follows_the_network_unless_overridden() {
+        assert_eq!(explorer_base(None, None), "https://starkscan.co");
+        assert_eq!(explorer_base(Some("mainnet"), None), "https://starkscan.co");
+        assert_eq!(
+            explorer_base(Some("sepolia"), None),
+            "https://sepolia.starkscan.co"
+        );
+        assert_eq!(
+            explorer_base(Some("sepolia"), Some(" https://voyager.online/ ")),
+            "https://voyager.online"
+        );
+        assert_eq!(explorer_base(None, Some("  ")), "https://starkscan.co");
+    }
+
+    #[test]
+    fn a_missing_hash_has_no_explorer_link() {
+        assert_eq!(explorer_tx_url(""), None);
+        assert_eq!(explorer_tx_url("  \n"), None);
+        let url = explorer_tx_url(" 0xabc ").unwrap();
+        assert!(url.ends_with("/tx/0xabc"), "{}", url);
+    }
+}
@@ -0,0 +1,596 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+
+use crate::metrics;
+use crate::model::{StateTransition, UserSessions, UserState};
+use crate::outbound::OutboundMessage;
+use crate::privacy::mask_phone;
+use crate::reporting;
+
+/// What a handler reports once it has done its work; the state machine decides where
+/// the conversation goes next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateEvent {
+    AccountCreationStarted,
+    AccountCreated,
+    QuoteShown,
+    SavedBankFound,
+    /// More than one saved bank and no default: the user picks.
+    SavedBanksFound,
+    SavedBankMissing,
+    BankDetailsVerified,
+    BankDetailsRejected,
+    LargeAmountFlagged,
+    PayoutAmountChanged,
+    BankNameChanged,
+    /// Flow finished or was abandoned; allowed from every state.
+    Reset,
+}
+
+/// Every legal `(from, event) -> to` edge. Anything not listed here is a bug.
+const TRANSITIONS: &[(UserState, StateEvent, UserState)] = &[
+    (
+        UserState::Initial,
+        StateEvent::AccountCreationStarted,
+        UserState::AccountCreation,
+    ),
+    (
+        UserState::AccountCreation,
+        StateEvent::AccountCreated,
+        UserState::Initial,
+    ),
+    (
+        UserState::Initial,
+        StateEvent::QuoteShown,
+        UserState::OfframpConfirmation,
+    ),
+    (
+        UserState::OfframpConfirmation,
+        StateEvent::SavedBankFound,
+        UserState::SavedBankConfirmation,
+    ),
+    (
+        UserState::OfframpConfirmation,
+        StateEvent::SavedBanksFound,
+        UserState::BankSelection,
+    ),
+    (
+        UserState::BankSelection,
+        StateEvent::SavedBankFound,
+        UserState::SavedBankConfirmation,
+    ),
+    (
+        UserState::OfframpConfirmation,
+        StateEvent::SavedBankMissing,
+        UserState::BankDetailsEntry,
+    ),
+    (
+        UserState::BankDetailsEntry,
+        StateEvent::BankDetailsVerified,
+        UserState::BankDetailsConfirmation,
+    ),
+    (
+        UserState::BankDetailsConfirmation,
+        StateEvent::BankDetailsRejected,
+        UserState::BankDetailsEntry,
+    ),
+    (
+        UserState::SavedBankConfirmation,
+        StateEvent::LargeAmountFlagged,
+        UserState::AmountConfirmation,
+    ),
+    (
+        UserState::BankDetailsConfirmation,
+        StateEvent::LargeAmountFlagged,
+        UserState::AmountConfirmation,
+    ),
+    (
+        UserState::SavedBankConfirmation,
+        StateEvent::PayoutAmountChanged,
+        UserState::PayoutApproval,
+    ),
+    (
+        UserState::AmountConfirmation,
+        StateEvent::PayoutAmountChanged,
+        UserState::PayoutApproval,
+    ),
+    (
+        UserState::BankDetailsConfirmation,
+        StateEvent::PayoutAmountChanged,
+        UserState::PayoutApproval,
+    ),
+    (
+        UserState::Initial,
+        StateEvent::BankNameChanged,
+        UserState::BankNameUpdate,
+    ),
+];
+
+/// How often each `From->To` edge has been taken since startup, from the
+/// `state_transitions_total` metric.
+pub fn transition_counts() -> BTreeMap<String, u64> {
+    metrics::STATE_TRANSITIONS
+        .values()
+        .into_iter()
+        .map(|(edge, count)| (edge.join("->"), count))
+        .collect()
+}
+
+/// Moves the session to `to`, noting the move in its history, the error-report
+/// breadcrumbs and the edge counts.
+fn transition(session: &mut UserSessions, to: UserState, event: &str) {
+    let from = session.state;
+    session.state = to;
+    if from == to {
+        return;
+    }
+    let transition = StateTransition {
+        at: Utc::now(),
+        from,
+        to,
+        event: event.to_string(),
+        trigger: reporting::current_trigger(),
+    };
+    reporting::record_transition(&session.phone, &transition);
+    session.record_transition(transition);
+    metrics::STATE_TRANSITIONS.inc(&[&format!("{:?}", from), &format!("{:?}", to)]);
+}
+
+const REPAIR_MESSAGE: &str = "⚠️ Sorry, we lost track of your request.\n\n\
+    Nothing was sent. Type `hi` to start again.";
+
+pub struct StateMachine;
+
+impl StateMachine {
+    pub fn next_state(from: UserState, event: StateEvent) -> Option<UserState> {
+        if event == StateEvent::Reset {
+            return Some(UserState::Initial);
+        }
+
+        TRANSITIONS
+            .iter()
+            .find(|(state, on, _)| *state == from && *on == event)
+            .map(|(_, _, to)| *to)
+    }
+
+    /// Applies `event` to the session. On an illegal edge, or if the new state's
+    /// invariants don't hold, the session is reset and the returned messages carry the
+    /// apology the caller should send instead of its own reply.
+    pub fn advance(
+        session: &mut UserSessions,
+        event: StateEvent,
+    ) -> (Vec<OutboundMessage>, UserState) {
+        let Some(next) = Self::next_state(session.state, event) else {
+            let reason = format!("illegal transition {:?} --{:?}->", session.state, event);
+            return (Self::repair(session, &reason), session.state);
+        };
+
+        if next == UserState::Initial {
+            Self::end_flow(session, &format!("{:?}", event));
+            return (vec![], session.state);
+        }
+
+        transition(session, next, &format!("{:?}", event));
+        match Self::check_invariants(session) {
+            Ok(()) => (vec![], session.state),
+            Err(reason) => (Self::repair(session, &reason), session.state),
+        }
+    }
+
+    /// Data each state relies on. A session loaded from storage in a state whose data
+    /// is missing would otherwise only fail later with a confusing "not found" reply.
+    pub fn check_invariants(session: &UserSessions) -> Result<(), String> {
+        let needs_withdrawal = matches!(
+            session.state,
+            UserState::OfframpConfirmation
+                | UserState::SavedBankConfirmation
+                | UserState::BankSelection
+                | UserState::BankDetailsEntry
+                | UserState::BankDetailsConfirmation
+                | UserState::AmountConfirmation
+                | UserState::PayoutApproval
+        );
+        if needs_withdrawal
+            && (session.pending_amount.is_none() || session.pending_currency.is_none())
+        {
+            return Err(format!(
+                "{:?} requires pending_amount and pending_currency",
+                session.state
+            ));
+        }
+
+        match session.state {
+            UserState::SavedBankConfirmation | UserState::AmountConfirmation
+                if session.pending_bank_details.is_none() =>
+            {
+                Err(format!("{:?} requires pending_bank_details", session.state))
+            }
+            UserState::BankSelection if session.bank_choices.is_empty() => {
+                Err("BankSelection requires bank_choices".to_string())
+            }
+            UserState::BankDetailsConfirmation if session.pending_bank_verification.is_none() => {
+                Err("BankDetailsConfirmation requires pending_bank_verification".to_string())
+            }
+            UserState::PayoutApproval if session.pending_payout.is_none() => {
+                Err("PayoutApproval requires pending_payout".to_string())
+            }
+            UserState::BankNameUpdate
+                if session.pending_bank_details.is_none()
+                    || session.pending_bank_verification.is_none() =>
+            {
+                Err(
+                    "BankNameUpdate requires pending_bank_details and pending_bank_verification"
+                        .to_string(),
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Logs the violation, drops the half-finished flow and returns the apology to send.
+    pub fn repair(session: &mut UserSessions, reason: &str) -> Vec<OutboundMessage> {
+        eprintln!(
+            "ALERT: Session state violation for {}: {}; resetting to Initial",
+            mask_phone(&session.phone),
+            reason
+        );
+        Self::end_flow(session, "Repair");
+        vec![REPAIR_MESSAGE.into()]
+    }
+
+    /// Ends the current flow. `controller_address` is an account-level cache rather than
+    /// flow state, so it survives.
+    pub fn reset(session: &mut UserSessions) {
+        Self::end_flow(session, "Reset");
+    }
+
+    fn end_flow(session: &mut UserSessions, event: &str) {
+        transition(session, UserState::Initial, event);
+        session.pending_amount = None;
+        session.pending_currency = None;
+        session.pending_bank_verification = None;
+        session.pending_bank_details = None;
+        session.bank_choices.clear();
+        session.correlation_id = None;
+        session.reminded = false;
+        session.quoted_rate = None;
+        session.quoted_at = None;
+        session.quoted_naira_amount = None;
+        session.pending_payout = None;
+        session.amount_confirmation_attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        BankDetails, BankVerificationResponse, PendingPayout, TRANSITION_HISTORY_SIZE,
+    };
+
+    const PHONE: &str = "+2348090000401";
+
+    fn bank() -> BankDetails {
+        BankDetails {
+            bank_details_id: "bank-1".to_string(),
+            bank_name: "Opay".to_string(),
+            account_number: "0123456789".to_string(),
+            account_name: "ADA OBI".to_string(),
+            bank_code: Some("999".to_string()),
+        }
+    }
+
+    /// A session in `state` carrying everything any state needs, so only the edge
+    /// itself decides where it goes.
+    fn session_in(state: UserState) -> UserSessions {
+        let mut session = UserSessions::new(PHONE);
+        session.state = state;
+        session.pending_amount = Some(10.0);
+        session.pending_currency = Some("USDT".to_string());
+        session.pending_bank_details = Some(bank());
+        session.pending_bank_verification = Some(BankVerificationResponse {
+            bank_name: "Opay".to_string(),
+            account_number: "0123456789".to_string(),
+            account_name: "ADA OBI".to_string(),
+            bank_code: "999".to_string(),
+        });
+        session.bank_choices = vec![bank(), bank()];
+        session.pending_payout = Some(PendingPayout {
+            reference: "KP-STATE".to_string(),
+            amount: 10.0,
+            currency: "USDT".to_string(),
+            bank_name: "Opay".to_string(),
+            account_name: "ADA OBI".to_string(),
+            crypto_tx_hash: "0xabc".to_string(),
+        });
+        session
+    }
+
+    fn assert_edge(from: UserState, event: StateEvent, to: UserState) {
+        let mut session = session_in(from);
+        let (apology, state) = StateMachine::advance(&mut session, event);
+        assert!(apology.is_empty(), "{:?} --{:?}-> repaired", from, event);
+        assert_eq!(state, to);
+        assert_eq!(session.state, to);
+
+        let taken = session.transitions.back().expect("transition recorded");
+        assert_eq!((taken.from, taken.to), (from, to));
+        assert_eq!(taken.event, format!("{:?}", event));
+    }
+
+    /// An illegal edge resets the flow and apologises instead of moving on.
+    fn assert_repaired(from: UserState, event: StateEvent) {
+        let mut session = session_in(from);
+        let (apology, state) = StateMachine::advance(&mut session, event);
+        assert_eq!(state, UserState::Initial, "{:?} --{:?}->", from, event);
+        assert_eq!(apology.len(), 1);
+        assert_eq!(apology[0].body, REPAIR_MESSAGE);
+        assert!(session.pending_amount.is_none());
+        assert!(session.pending_bank_details.is_none());
+        // Already in Initial there's no move to record
+        let last = session.transitions.back();
+        assert!(last.is_none_or(|taken| taken.event == "Repair"));
+    }
+
+    #[test]
+    fn initial_to_account_creation() {
+        assert_edge(
+            UserState::Initial,
+            StateEvent::AccountCreationStarted,
+            UserState::AccountCreation,
+        );
+    }
+
+    #[test]
+    fn account_creation_to_initial() {
+        let mut session = session_in(UserState::AccountCreation);
+        let (apology, state) = StateMachine::advance(&mut session, StateEvent::AccountCreated);
+        assert!(apology.is_empty());
+        assert_eq!(state, UserState::Initial);
+        // Back to Initial always ends the flow
+        assert!(session.pending_amount.is_none());
+        assert_eq!(session.transitions.back().unwrap().event, "AccountCreated");
+    }
+
+    #[test]
+    fn initial_to_offramp_confirmation() {
+        assert_edge(
+            UserState::Initial,
+            StateEvent::QuoteShown,
+            UserState::OfframpConfirmation,
+        );
+    }
+
+    #[test]
+    fn offramp_confirmation_to_saved_bank_confirmation() {
+        assert_edge(
+            UserState::OfframpConfirmation,
+            StateEvent::SavedBankFound,
+            UserState::SavedBankConfirmation,
+        );
+    }
+
+    #[test]
+    fn offramp_confirmation_to_bank_selection() {
+        assert_edge(
+            UserState::OfframpConfirmation,
+            StateEvent::SavedBanksFound,
+            UserState::BankSelection,
+        );
+    }
+
+    #[test]
+    fn bank_selection_to_saved_bank_confirmation() {
+        assert_edge(
+            UserState::BankSelection,
+            StateEvent::SavedBankFound,
+            UserState::SavedBankConfirmation,
+        );
+    }
+
+    #[test]
+    fn offramp_confirmation_to_bank_details_entry() {
+        assert_edge(
+            UserState::OfframpConfirmation,
+            StateEvent::SavedBankMissing,
+            UserState::BankDetailsEntry,
+        );
+    }
+
+    #[test]
+    fn bank_details_entry_to_bank_details_confirmation() {
+        assert_edge(
+            UserState::BankDetailsEntry,
+            StateEvent::BankDetailsVerified,
+            UserState::BankDetailsConfirmation,
+        );
+    }
+
+    #[test]
+    fn bank_details_confirmation_back_to_bank_details_entry() {
+        assert_edge(
+            UserState::BankDetailsConfirmation,
+            StateEvent::BankDetailsRejected,
+            UserState::BankDetailsEntry,
+        );
+    }
+
+    #[test]
+    fn saved_bank_confirmation_to_amount_confirmation() {
+        assert_edge(
+            UserState::SavedBankConfirmation,
+            StateEvent::LargeAmountFlagged,
+            UserState::AmountConfirmation,
+        );
+    }
+
+    #[test]
+    fn bank_details_confirmation_to_amount_confirmation() {
+        assert_edge(
+            UserState::BankDetailsConfirmation,
+            StateEvent::LargeAmountFlagged,
+            UserState::AmountConfirmation,
+        );
+    }
+
+    #[test]
+    fn saved_bank_confirmation_to_payout_approval() {
+        assert_edge(
+            UserState::SavedBankConfirmation,
+            StateEvent::PayoutAmountChanged,
+            UserState::PayoutApproval,
+        );
+    }
+
+    #[test]
+    fn amount_confirmation_to_payout_approval() {
+        assert_edge(
+            UserState::AmountConfirmation,
+            StateEvent::PayoutAmountChanged,
+            UserState::PayoutApproval,
+        );
+    }
+
+    #[test]
+    fn bank_details_confirmation_to_payout_approval() {
+        assert_edge(
+            UserState::BankDetailsConfirmation,
+            StateEvent::PayoutAmountChanged,
+            UserState::PayoutApproval,
+        );
+    }
+
+    #[test]
+    fn initial_to_bank_name_update() {
+        assert_edge(
+            UserState::Initial,
+            StateEvent::BankNameChanged,
+            UserState::BankNameUpdate,
+        );
+    }
+
+    #[test]
+    fn reset_goes_to_initial_from_every_state() {
+        for (from, _, _) in TRANSITIONS {
+            let mut session = session_in(*from);
+            let (apology, state) = StateMachine::advance(&mut session, StateEvent::Reset);
+            assert!(apology.is_empty());
+            assert_eq!(state, UserState::Initial, "from {:?}", from);
+            assert!(session.pending_payout.is_none());
+            assert!(session.bank_choices.is_empty());
+        }
+    }
+
+    #[test]
+    fn ending_a_flow_keeps_the_cached_address() {
+        let mut session = session_in(UserState::OfframpConfirmation);
+        session.controller_address = Some("0x0abc".to_string());
+        StateMachine::reset(&mut session);
+        assert_eq!(session.controller_address.as_deref(), Some("0x0abc"));
+
+        let mut session = session_in(UserState::OfframpConfirmation);
+        session.controller_address = Some("0x0abc".to_string());
+        StateMachine::repair(&mut session, "test");
+        assert_eq!(session.state, UserState::Initial);
+        assert_eq!(session.controller_address.as_deref(), Some("0x0abc"));
+    }
+
+    #[test]
+    fn every_edge_in_the_table_is_tested_above() {
+        // A new edge needs its own test; bump this once it has one
+        assert_eq!(TRANSITIONS.len(), 15);
+    }
+
+    #[test]
+    fn skipping_ahead_to_a_confirmation_is_repaired() {
+        assert_repaired(UserState::Initial, StateEvent::BankDetailsVerified);
+        assert_repaired(UserState::Initial, StateEvent::SavedBankFound);
+        assert_repaired(UserState::Initial, StateEvent::PayoutAmountChanged);
+    }
+
+    #[test]
+    fn events_from_another_flow_are_repaired() {
+        assert_repaired(UserState::AccountCreation, StateEvent::QuoteShown);
+        assert_repaired(UserState::BankSelection, StateEvent::SavedBankMissing);
+        assert_repaired(UserState::PayoutApproval, StateEvent::LargeAmountFlagged);
+        assert_repaired(UserState::BankNameUpdate, StateEvent::BankDetailsRejected);
+    }
+
+    #[test]
+    fn a_legal_edge_into_a_state_missing_its_data_is_repaired() {
+        let mut session = UserSessions::new(PHONE);
+        let (apology, state) = StateMachine::advance(&mut session, StateEvent::QuoteShown);
+        assert_eq!(state, UserState::Initial);
+        assert_eq!(apology[0].body, REPAIR_MESSAGE);
+
+        let mut session = session_in(UserState::OfframpConfirmation);
+        session.bank_choices.clear();
+        let (apology, state) = StateMachine::advance(&mut session, StateEvent::SavedBanksFound);
+        assert_eq!(state, UserState::Initial);
+        assert!(!apology.is_empty());
+    }
+
+    #[test]
+    fn a_loaded_session_missing_its_states_data_fails_the_invariants() {
+        let mut session = session_in(UserState::PayoutApproval);
+        assert!(StateMachine::check_invariants(&session).is_ok());
+        session.pending_payout = None;
+        assert!(StateMachine::check_invariants(&session).is_err());
+    }
+
+    #[test]
+    fn taken_edges_are_counted_per_edge() {
+        let edge = ["Initial", "AccountCreation"];
+        let before = metrics::STATE_TRANSITIONS.get(&edge);
+        let mut session = UserSessions::new(PHONE);
+
+        let (apology, state) =
+            StateMachine::advance(&mut session, StateEvent::AccountCreationStarted);
+        assert!(apology.is_empty());
+        assert_eq!(state, UserState::AccountCreation);
+
+        // Other tests take this edge too
+        assert!(metrics::STATE_TRANSITIONS.get(&edge) > before);
+        assert!(transition_counts()["Initial->AccountCreation"] > before);
+    }
+
+    #[test]
+    fn the_history_keeps_only_the_latest_transitions() {
+        let mut session = UserSessions::new(PHONE);
+        // Two moves a round, quoted then cancelled, past the bound
+        for _ in 0..TRANSITION_HISTORY_SIZE {
+            session.pending_amount = Some(10.0);
+            session.pending_currency = Some("USDT".to_string());
+            StateMachine::advance(&mut session, StateEvent::QuoteShown);
+            StateMachine::reset(&mut session);
+        }
+        assert_eq!(session.transitions.len(), TRANSITION_HISTORY_SIZE);
+        for (i, taken) in session.transitions.iter().enumerate() {
+            let expected = if i % 2 == 0 {
+                (
+                    UserState::Initial,
+                    UserState::OfframpConfirmation,
+                    "QuoteShown",
+                )
+            } else {
+                (UserState::OfframpConfirmation, UserState::Initial, "Reset")
+            };
+            assert_eq!((taken.from, taken.to, taken.event.as_str()), expected);
+        }
+        // Oldest first
+        assert!(
+            session
+                .transitions
+                .iter()
+                .zip(session.transitions.iter().skip(1))
+                .all(|(earlier, later)| earlier.at <= later.at)
+        );
+
+        // Staying put isn't a transition
+        StateMachine::reset(&mut session);
+        assert_eq!(session.transitions.len(), TRANSITION_HISTORY_SIZE);
+        assert_eq!(session.transitions.back().unwrap().event, "Reset");
+        assert!(session.transitions.back().unwrap().trigger.is_none());
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+
+use crate::backend::{self, Endpoint, TrackedSend};
+use crate::config::PollConfig;
+use crate::model::{BatchStatusResponse, TransactionStatus};
+use crate::supervisor;
+
+/// Lookups waiting for the next batch, keyed by reference so two pollers asking about
+/// the same withdrawal share one slot in the request.
+type Waiters = HashMap<String, Vec<oneshot::Sender<Option<TransactionStatus>>>>;
+
+static WAITING: LazyLock<Mutex<Waiters>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Set once the scheduler is up, so lookups never queue behind a task that isn't there.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+fn batch_endpoint() -> Option<String> {
+    std::env::var("TRANSACTION_STATUS_BATCH_ENDPOINT")
+        .ok()
+        .filter(|url| !url.trim().is_empty())
+}
+
+/// `TRANSACTION_STATUS_BATCH_SIZE`, default 100 references per request.
+fn batch_size() -> usize {
+    std::env::var("TRANSACTION_STATUS_BATCH_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(100)
+}
+
+/// Status of `reference` from the next batch. `None` when batching is off, the batch
+/// call failed or the reference wasn't in the response; the caller then polls it alone.
+pub async fn lookup(reference: &str) -> Option<TransactionStatus> {
+    if !RUNNING.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let (sender, receiver) = oneshot::channel();
+    WAITING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(reference.to_string())
+        .or_default()
+        .push(sender);
+
+    receiver.await.ok().flatten()
+}
+
+/// One task for every pending withdrawal: each cycle it takes whatever references were
+/// asked about, fetches them in chunks and hands each poller its own result.
+pub fn start_batch_task() {
+    let Some(endpoint) = batch_endpoint() else {
+        return;
+    };
+    let interval = std::env::var("TRANSACTION_STATUS_BATCH_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+        .unwrap_or_else(|| PollConfig::from_env().interval);
+    let size = batch_size();
+
+    supervisor::supervise("status_batch", move || {
+        let endpoint = endpoint.clone();
+        async move {
+            let client = backend::client();
+            RUNNING.store(true, Ordering::Relaxed);
+
+            loop {
+                sleep(interval).await;
+
+                let waiting =
+                    std::mem::take(&mut *WAITING.lock().unwrap_or_else(|e| e.into_inner()));
+                if waiting.is_empty() {
+                    continue;
+                }
+
+                let references: Vec<String> = waiting.keys().cloned().collect();
+                let mut results: HashMap<String, TransactionStatus> = HashMap::new();
+                for chunk in references.chunks(size) {
+                    match fetch_batch(&client, &endpoint, chunk).await {
+                        Ok(statuses) => results.extend(
+                            statuses
+                                .into_iter()
+                                .map(|status| (status.reference.clone(), status)),
+                        ),
+                        Err(e) => eprintln!(
+                            "Batch status lookup for {} references failed: {}",
+                            chunk.len(),
+                            e
+                        ),
+                    }
+                }
+
+                let missing = references
+                    .iter()
+                    .filter(|reference| !results.contains_key(*reference))
+                    .count();
+                if missing > 0 {
+                    println!(
+                        "Batch status: {} of {} references missing; falling back to single lookups",
+                        missing,
+                        references.len()
+                    );
+                }
+
+                for (reference, senders) in waiting {
+                    let status = results.remove(&reference);
+                    // Several pollers may wait on one reference; each gets its own copy
+                    for sender in senders {
+                        let _ = sender.send(status.clone());
+                    }
+                }
+            }
+        }
+    });
+}
+
+async fn fetch_batch(
+    client: &reqwest::Client,
+    endpoint: &str,
+    references: &[String],
+) -> Result<Vec<TransactionStatus>, String> {
+    let payload = json!({ "references": references });
+    let response = backend::signed_post(client, endpoint, &payload)
+        .send_tracked(Endpoint::Status)
+        .await
+        .map_err(|e| format!("Batch status request error: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Batch status request failed with status: {}",
+            response.status()
+        ));
+    }
+
+    let body = response
+        .json::<BatchStatusResponse>()
+        .await
+        .map_err(|e| format!("Failed to parse batch status: {}", e))?;
+    if !body.success {
+        return Err(body
+            .message
+            .unwrap_or_else(|| "backend reported failure".to_string()));
+    }
+
+    Ok(body.data)
+}
@@ -0,0 +1,547 @@
+use chrono::{DateTime, Utc};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{OptionalExtension, params};
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard, OnceLock},
+    time::Duration,
+};
+
+use crate::build_info;
+use crate::model::{UserPreferences, UserSessions, UserState};
+use crate::privacy::mask_phone;
+use crate::seal;
+use crate::supervisor;
+
+/// Backing storage for conversation sessions, keyed by the user's phone number.
+pub trait SessionStore: Send + Sync {
+    fn load(&self, phone: &str) -> Result<Option<UserSessions>, String>;
+    fn save(&self, session: &UserSessions) -> Result<(), String>;
+    /// Removes one session; its preferences stay.
+    fn delete(&self, phone: &str) -> Result<(), String>;
+    /// Drops sessions that haven't been saved within `ttl`, returning how many were removed.
+    fn purge_idle(&self, ttl: Duration) -> Result<usize, String>;
+    /// Sessions stuck mid-flow for longer than `idle` that haven't been nudged yet.
+    fn stalled(&self, idle: Duration) -> Result<Vec<UserSessions>, String>;
+    /// Sessions outside `Initial` that haven't been saved within `idle`, nudged or not.
+    fn mid_flow(&self, idle: Duration) -> Result<Vec<UserSessions>, String>;
+    /// Preferences live in their own keyspace: purging or overwriting a session leaves
+    /// them alone.
+    fn load_preferences(&self, phone: &str) -> Result<Option<UserPreferences>, String>;
+    fn save_preferences(&self, prefs: &UserPreferences) -> Result<(), String>;
+}
+
+/// A panic while a store lock was held must not take every later webhook down with it.
+/// The maps are only ever written in single statements, so the data behind a poisoned
+/// lock is still consistent.
+fn lock<'a, T>(mutex: &'a Mutex<T>, store: &str) -> MutexGuard<'a, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        eprintln!(
+            "ALERT: {} session store lock was poisoned by a panic; recovering",
+            store
+        );
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+fn needs_reminder(session: &UserSessions) -> bool {
+    session.state != UserState::Initial && !session.reminded
+}
+
+fn in_flow(session: &UserSessions) -> bool {
+    session.state != UserState::Initial
+}
+
+type SessionShard = HashMap<String, (UserSessions, DateTime<Utc>)>;
+
+/// Sessions split across `SESSION_SHARDS` maps (default 16) by a hash of the phone, so
+/// users only contend with the few others in their shard, and background scans lock one
+/// shard at a time instead of freezing everyone.
+pub struct MemorySessionStore {
+    shards: Vec<Mutex<SessionShard>>,
+    preferences: Mutex<HashMap<String, UserPreferences>>,
+}
+
+impl Default for MemorySessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        let count = std::env::var("SESSION_SHARDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|count| *count > 0)
+            .unwrap_or(16);
+        Self::with_shards(count)
+    }
+
+    /// `count` shards regardless of `SESSION_SHARDS`; at least one.
+    pub fn with_shards(count: usize) -> Self {
+        let count = count.max(1);
+        MemorySessionStore {
+            shards: (0..count).map(|_| Mutex::new(HashMap::new())).collect(),
+            preferences: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn shard(&self, phone: &str) -> MutexGuard<'_, SessionShard> {
+        let mut hasher = DefaultHasher::new();
+        phone.hash(&mut hasher);
+        let index = (hasher.finish() % self.shards.len() as u64) as usize;
+        lock(&self.shards[index], "memory")
+    }
+
+    fn idle_matching(
+        &self,
+        idle: Duration,
+        wanted: fn(&UserSessions) -> bool,
+    ) -> Vec<UserSessions> {
+        let cutoff = idle_cutoff(idle);
+        let mut matching = Vec::new();
+        for shard in &self.shards {
+            let sessions = lock(shard, "memory");
+            matching.extend(
+                sessions
+                    .values()
+                    .filter(|(session, updated_at)| *updated_at < cutoff && wanted(session))
+                    .map(|(session, _)| session.clone()),
+            );
+        }
+        matching
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn load(&self, phone: &str) -> Result<Option<UserSessions>, String> {
+        let sessions = self.shard(phone);
+        Ok(sessions.get(phone).map(|(session, _)| session.clone()))
+    }
+
+    fn save(&self, session: &UserSessions) -> Result<(), String> {
+        let mut sessions = self.shard(&session.phone);
+        sessions.insert(session.phone.clone(), (session.clone(), Utc::now()));
+        Ok(())
+    }
+
+    fn delete(&self, phone: &str) -> Result<(), String> {
+        self.shard(phone).remove(phone);
+        Ok(())
+    }
+
+    fn purge_idle(&self, ttl: Duration) -> Result<usize, String> {
+        let cutoff = idle_cutoff(ttl);
+        let mut removed = 0;
+        for shard in &self.shards {
+            let mut sessions = lock(shard, "memory");
+            let before = sessions.len();
+            sessions.retain(|_, (_, updated_at)| *updated_at >= cutoff);
+            removed += before - sessions.len();
+        }
+        Ok(removed)
+    }
+
+    fn stalled(&self, idle: Duration) -> Result<Vec<UserSessions>, String> {
+        Ok(self.idle_matching(idle, needs_reminder))
+    }
+
+    fn mid_flow(&self, idle: Duration) -> Result<Vec<UserSessions>, String> {
+        Ok(self.idle_matching(idle, in_flow))
+    }
+
+    fn load_preferences(&self, phone: &str) -> Result<Option<UserPreferences>, String> {
+        Ok(lock(&self.preferences, "memory").get(phone).cloned())
+    }
+
+    fn save_preferences(&self, prefs: &UserPreferences) -> Result<(), String> {
+        lock(&self.preferences, "memory").insert(prefs.phone.clone(), prefs.clone());
+        Ok(())
+    }
+}
+
+type SqliteConnection = PooledConnection<SqliteConnectionManager>;
+
+/// `SESSION_DB_POOL_SIZE`, default 8: connections open at once. WAL lets readers run
+/// alongside the one writer, so loads don't queue behind each other.
+fn pool_size() -> u32 {
+    std::env::var("SESSION_DB_POOL_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(8)
+}
+
+/// Stores each session as a JSON document, so rows written by older builds still load
+/// as long as new fields on `UserSessions` are `#[serde(default)]`. Every call blocks on
+/// the database; from async code, go through `blocking`.
+pub struct SqliteSessionStore {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl SqliteSessionStore {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let manager = SqliteConnectionManager::file(path)
+            .with_init(|conn| conn.execute_batch("PRAGMA busy_timeout = 5000;"));
+        let pool = Pool::builder()
+            .max_size(pool_size())
+            .connection_timeout(Duration::from_secs(5))
+            .build(manager)
+            .map_err(|e| format!("Failed to open session database {}: {}", path, e))?;
+
+        let conn = pool
+            .get()
+            .map_err(|e| format!("Failed to open session database {}: {}", path, e))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS sessions (
+                 phone TEXT PRIMARY KEY,
+                 data TEXT NOT NULL,
+                 updated_at INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS sessions_updated_at ON sessions (updated_at);
+             CREATE TABLE IF NOT EXISTS preferences (
+                 phone TEXT PRIMARY KEY,
+                 data TEXT NOT NULL
+             );",
+        )
+        .map_err(|e| format!("Failed to create session schema: {}", e))?;
+
+        Ok(SqliteSessionStore { pool })
+    }
+
+    fn conn(&self) -> Result<SqliteConnection, String> {
+        self.pool
+            .get()
+            .map_err(|e| format!("No session database connection free: {}", e))
+    }
+
+    fn idle_matching(
+        &self,
+        idle: Duration,
+        wanted: fn(&UserSessions) -> bool,
+    ) -> Result<Vec<UserSessions>, String> {
+        let cutoff = idle_cutoff(idle).timestamp();
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT phone, data FROM sessions WHERE updated_at < ?1")
+            .map_err(|e| format!("Failed to query idle sessions: {}", e))?;
+        let rows = stmt
+            .query_map(params![cutoff], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(|e| format!("Failed to query idle sessions: {}", e))?;
+
+        let mut matching = Vec::new();
+        for row in rows {
+            let (phone, json) = row.map_err(|e| format!("Failed to read session row: {}", e))?;
+            match seal::open_session(&phone, &json) {
+                Ok(session) if wanted(&session) => matching.push(session),
+                Ok(_) => {}
+                Err(e) => eprintln!(
+                    "Skipping undecodable session for {}: {}",
+                    mask_phone(&phone),
+                    e
+                ),
+            }
+        }
+        Ok(matching)
+    }
+}
+
+impl SessionStore for SqliteSessionStore {
+    fn load(&self, phone: &str) -> Result<Option<UserSessions>, String> {
+        let conn = self.conn()?;
+        let row: Option<(String, i64)> = conn
+            .query_row(
+                "SELECT data, updated_at FROM sessions WHERE phone = ?1",
+                params![phone],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| format!("Failed to load session: {}", e))?;
+
+        let Some((json, updated_at)) = row else {
+            return Ok(None);
+        };
+        let mut session = seal::open_session(phone, &json)?;
+        session.restored = updated_at < build_info::started_at().timestamp();
+        Ok(Some(session))
+    }
+
+    fn save(&self, session: &UserSessions) -> Result<(), String> {
+        let json = seal::seal_session(session)?;
+
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT INTO sessions (phone, data, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(phone) DO UPDATE SET data = excluded.data, updated_at = excluded.updated_at",
+            params![session.phone, json, Utc::now().timestamp()],
+        )
+        .map_err(|e| format!("Failed to save session: {}", e))?;
+        Ok(())
+    }
+
+    fn delete(&self, phone: &str) -> Result<(), String> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM sessions WHERE phone = ?1", params![phone])
+            .map_err(|e| format!("Failed to delete session: {}", e))?;
+        Ok(())
+    }
+
+    fn purge_idle(&self, ttl: Duration) -> Result<usize, String> {
+        let cutoff = idle_cutoff(ttl).timestamp();
+        let conn = self.conn()?;
+        conn.execute(
+            "DELETE FROM sessions WHERE updated_at < ?1",
+            params![cutoff],
+        )
+        .map_err(|e| format!("Failed to purge idle sessions: {}", e))
+    }
+
+    fn stalled(&self, idle: Duration) -> Result<Vec<UserSessions>, String> {
+        self.idle_matching(idle, needs_reminder)
+    }
+
+    fn mid_flow(&self, idle: Duration) -> Result<Vec<UserSessions>, String> {
+        self.idle_matching(idle, in_flow)
+    }
+
+    fn load_preferences(&self, phone: &str) -> Result<Option<UserPreferences>, String> {
+        let conn = self.conn()?;
+        let data: Option<String> = conn
+            .query_row(
+                "SELECT data FROM preferences WHERE phone = ?1",
+                params![phone],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| format!("Failed to load preferences: {}", e))?;
+
+        data.map(|json| {
+            serde_json::from_str(&json)
+                .map_err(|e| format!("Failed to decode preferences for {}: {}", phone, e))
+        })
+        .transpose()
+    }
+
+    fn save_preferences(&self, prefs: &UserPreferences) -> Result<(), String> {
+        let json = serde_json::to_string(prefs)
+            .map_err(|e| format!("Failed to encode preferences: {}", e))?;
+
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT INTO preferences (phone, data) VALUES (?1, ?2)
+             ON CONFLICT(phone) DO UPDATE SET data = excluded.data",
+            params![prefs.phone, json],
+        )
+        .map_err(|e| format!("Failed to save preferences: {}", e))?;
+        Ok(())
+    }
+}
+
+fn idle_cutoff(ttl: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(ttl)
+        .ok()
+        .and_then(|ttl| Utc::now().checked_sub_signed(ttl))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// Runs store calls on tokio's blocking pool, so a slow disk or a busy database holds up
+/// one blocking thread instead of an async worker and every connection on it.
+pub async fn blocking<T, F>(work: F) -> Result<T, String>
+where
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| format!("Session store call failed: {}", e))?
+}
+
+static SHARED: OnceLock<Arc<dyn SessionStore>> = OnceLock::new();
+
+/// Makes the store reachable from senders that aren't handed it, like the poller.
+pub fn share(store: Arc<dyn SessionStore>) {
+    let _ = SHARED.set(store);
+}
+
+pub fn shared() -> Option<&'static Arc<dyn SessionStore>> {
+    SHARED.get()
+}
+
+/// Sessions are keyed by `+` and the digits; callers hold `234…` or `+234…`.
+pub fn session_key(phone: &str) -> String {
+    let digits: String = phone.chars().filter(char::is_ascii_digit).collect();
+    format!("+{}", digits)
+}
+
+/// The stored session for `phone`, however it's formatted.
+pub fn load_shared(phone: &str) -> Option<UserSessions> {
+    SHARED.get()?.load(&session_key(phone)).ok()?
+}
+
+/// Builds the store selected by `SESSION_STORE` (`memory`, the default, or `sqlite`).
+/// SQLite needs `SESSION_ENC_KEY` (see `seal`) unless `SESSION_ENCRYPTION=off`.
+pub fn from_env() -> Result<Arc<dyn SessionStore>, String> {
+    let kind = std::env::var("SESSION_STORE").unwrap_or_else(|_| "memory".to_string());
+
+    match kind.to_lowercase().as_str() {
+        "memory" => Ok(Arc::new(MemorySessionStore::new())),
+        "sqlite" => {
+            seal::require_for_disk()?;
+            let path =
+                std::env::var("SESSION_DB_PATH").unwrap_or_else(|_| "sessions.db".to_string());
+            Ok(Arc::new(SqliteSessionStore::open(&path)?))
+        }
+        "redis" => Err("SESSION_STORE=redis is not supported by this build".to_string()),
+        other => Err(format!("Unknown SESSION_STORE value: {}", other)),
+    }
+}
+
+/// Periodically removes sessions idle for longer than `SESSION_TTL_SECS` (default 24h).
+pub fn start_vacuum_task(store: Arc<dyn SessionStore>) {
+    let ttl = Duration::from_secs(
+        std::env::var("SESSION_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(24 * 60 * 60),
+    );
+    let interval = Duration::from_secs(
+        std::env::var("SESSION_VACUUM_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10 * 60),
+    );
+
+    supervisor::supervise("session_vacuum", move || {
+        let store = store.clone();
+        async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                let purging = store.clone();
+                match blocking(move || purging.purge_idle(ttl)).await {
+                    Ok(0) => {}
+                    Ok(removed) => println!("Removed {} idle sessions", removed),
+                    Err(e) => eprintln!("Session vacuum failed: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Panics on another thread while holding `mutex`, the way a handler bug would.
+    fn poison<T: Send>(mutex: &Mutex<T>) {
+        std::thread::scope(|scope| {
+            let panicked = scope
+                .spawn(|| {
+                    let _held = mutex.lock();
+                    panic!("handler bug");
+                })
+                .join();
+            assert!(panicked.is_err());
+        });
+        assert!(mutex.is_poisoned());
+    }
+
+    #[test]
+    fn a_panic_holding_the_lock_does_not_take_later_messages_down() {
+        let store = MemorySessionStore::with_shards(1);
+        let mut session = UserSessions::new("+2348090001601");
+        session.state = UserState::AccountCreation;
+        store.save(&session).unwrap();
+        poison(&store.shards[0]);
+        poison(&store.preferences);
+
+        // What the webhook does with each message: load, handle, save
+        let mut loaded = store.load("+2348090001601").unwrap().expect("kept");
+        assert_eq!(loaded.state, UserState::AccountCreation);
+        loaded.state = UserState::Initial;
+        store.save(&loaded).unwrap();
+        store.save(&UserSessions::new("+2348090001602")).unwrap();
+        assert_eq!(
+            store.load("+2348090001601").unwrap().unwrap().state,
+            UserState::Initial
+        );
+        store
+            .save_preferences(&UserPreferences::new("+2348090001601"))
+            .unwrap();
+        assert!(store.load_preferences("+2348090001601").unwrap().is_some());
+
+        // Recovered once, not on every later lock
+        assert!(!store.shards[0].is_poisoned());
+        assert!(!store.preferences.is_poisoned());
+        assert_eq!(store.purge_idle(Duration::from_secs(3600)).unwrap(), 0);
+    }
+
+    #[test]
+    fn scans_see_sessions_in_every_shard() {
+        let store = MemorySessionStore::with_shards(8);
+        let phones: Vec<String> = (0..40).map(|n| format!("+23480900017{:02}", n)).collect();
+        for phone in &phones {
+            let mut session = UserSessions::new(phone);
+            session.state = UserState::AccountCreation;
+            store.save(&session).unwrap();
+        }
+        let used = store
+            .shards
+            .iter()
+            .filter(|shard| !shard.lock().unwrap().is_empty())
+            .count();
+        assert!(used > 1, "all 40 sessions in one shard");
+
+        assert_eq!(store.mid_flow(Duration::ZERO).unwrap().len(), 40);
+        assert_eq!(store.stalled(Duration::ZERO).unwrap().len(), 40);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(store.purge_idle(Duration::from_millis(1)).unwrap(), 40);
+        assert!(
+            phones
+                .iter()
+                .all(|phone| store.load(phone).unwrap().is_none())
+        );
+    }
+
+    #[test]
+    fn a_busy_shard_only_holds_up_its_own_users() {
+        let store = MemorySessionStore::with_shards(16);
+        let busy = "+2348090001801";
+        let index = |phone: &str| {
+            let mut hasher = DefaultHasher::new();
+            phone.hash(&mut hasher);
+            hasher.finish() % 16
+        };
+        let elsewhere = (2..100)
+            .map(|n| format!("+23480900018{:02}", n))
+            .find(|phone| index(phone) != index(busy))
+            .unwrap();
+
+        let held = store.shard(busy);
+        std::thread::scope(|scope| {
+            let (done, finished) = std::sync::mpsc::channel();
+            let elsewhere = &elsewhere;
+            let store = &store;
+            scope.spawn(move || done.send(store.save(&UserSessions::new(elsewhere))));
+            let saved = finished.recv_timeout(Duration::from_secs(5));
+            drop(held);
+            // Would time out if every phone shared the lock held above
+            saved.expect("not held up").unwrap();
+        });
+    }
+
+    #[test]
+    fn there_is_always_at_least_one_shard() {
+        let store = MemorySessionStore::with_shards(0);
+        assert_eq!(store.shards.len(), 1);
+        store.save(&UserSessions::new("+2348090001901")).unwrap();
+        assert!(store.load("+2348090001901").unwrap().is_some());
+    }
+}
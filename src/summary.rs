@@ -0,0 +1,353 @@
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::audit::{self, AuditEntry, AuditEvent};
+use crate::server::send_twilio_message;
+use crate::supervisor;
+
+#[derive(Debug, Serialize)]
+pub struct DailySummary {
+    pub date: NaiveDate,
+    pub utc_offset: String,
+    pub withdrawals_initiated: usize,
+    pub withdrawals_completed: usize,
+    pub withdrawals_failed: usize,
+    pub volume_by_token: BTreeMap<String, f64>,
+    pub average_completion_seconds: Option<i64>,
+    pub failed_message_sends: usize,
+    pub polls_timed_out: usize,
+    pub ratings_requested: usize,
+    pub ratings_received: usize,
+    pub average_rating: Option<f64>,
+    /// Bank accounts whose name didn't match the user's profile, for compliance review.
+    pub name_mismatches: usize,
+    /// Webhook payloads of a shape we don't handle yet.
+    pub unrecognized_callbacks: usize,
+    /// Sessions found stuck mid-flow and reset to the start.
+    pub stale_flows_reset: usize,
+}
+
+/// Offset used for the day boundary, from `SUMMARY_UTC_OFFSET` (e.g. `+01:00`, the default).
+pub fn summary_offset() -> FixedOffset {
+    std::env::var("SUMMARY_UTC_OFFSET")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| FixedOffset::east_opt(3600).expect("valid offset"))
+}
+
+pub fn yesterday(offset: FixedOffset) -> NaiveDate {
+    (Utc::now().with_timezone(&offset) - Duration::days(1)).date_naive()
+}
+
+/// How long until `hour`:00 comes round next, local to `SUMMARY_UTC_OFFSET`.
+pub fn until_local_hour(hour: u32) -> std::time::Duration {
+    let offset = summary_offset();
+    let now = Utc::now().with_timezone(&offset);
+    let mut next = offset
+        .from_local_datetime(
+            &now.date_naive()
+                .and_hms_opt(hour, 0, 0)
+                .expect("valid hour"),
+        )
+        .single()
+        .expect("fixed offsets have no ambiguous times");
+    if next <= now {
+        next += Duration::days(1);
+    }
+    (next - now).to_std().unwrap_or_default()
+}
+
+fn day_bounds(date: NaiveDate, offset: FixedOffset) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = offset
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).expect("midnight exists"))
+        .single()
+        .expect("fixed offsets have no ambiguous times")
+        .with_timezone(&Utc);
+    (start, start + Duration::days(1))
+}
+
+pub fn summarize(entries: &[AuditEntry], date: NaiveDate, offset: FixedOffset) -> DailySummary {
+    let (start, end) = day_bounds(date, offset);
+    let in_day = |entry: &&AuditEntry| entry.timestamp >= start && entry.timestamp < end;
+
+    // Initiation times are looked up across the whole log so a withdrawal started late
+    // the previous day still gets a completion time.
+    let initiated_at: HashMap<&str, DateTime<Utc>> = entries
+        .iter()
+        .filter(|e| matches!(e.event, AuditEvent::OfframpInitiated { .. }))
+        .filter_map(|e| e.reference.as_deref().map(|r| (r, e.timestamp)))
+        .collect();
+
+    let mut summary = DailySummary {
+        date,
+        utc_offset: offset.to_string(),
+        withdrawals_initiated: 0,
+        withdrawals_completed: 0,
+        withdrawals_failed: 0,
+        volume_by_token: BTreeMap::new(),
+        average_completion_seconds: None,
+        failed_message_sends: 0,
+        polls_timed_out: 0,
+        ratings_requested: 0,
+        ratings_received: 0,
+        average_rating: None,
+        name_mismatches: 0,
+        unrecognized_callbacks: 0,
+        stale_flows_reset: 0,
+    };
+    let mut completion_seconds = Vec::new();
+    let mut ratings = Vec::new();
+
+    for entry in entries.iter().filter(in_day) {
+        match &entry.event {
+            AuditEvent::OfframpInitiated { amount, token } => {
+                summary.withdrawals_initiated += 1;
+                *summary.volume_by_token.entry(token.clone()).or_insert(0.0) += amount;
+            }
+            AuditEvent::TerminalStatus { status } => match status.to_lowercase().as_str() {
+                "completed" | "successful" => {
+                    summary.withdrawals_completed += 1;
+                    if let Some(started) =
+                        entry.reference.as_deref().and_then(|r| initiated_at.get(r))
+                    {
+                        completion_seconds.push((entry.timestamp - *started).num_seconds());
+                    }
+                }
+                "failed" | "cancelled" => summary.withdrawals_failed += 1,
+                _ => {}
+            },
+            AuditEvent::MessageSendFailed { .. } => summary.failed_message_sends += 1,
+            AuditEvent::PollTimedOut { .. } => summary.polls_timed_out += 1,
+            AuditEvent::FeedbackRequested => summary.ratings_requested += 1,
+            AuditEvent::FeedbackRated { rating } => ratings.push(*rating as f64),
+            AuditEvent::AccountNameMismatch { .. } => summary.name_mismatches += 1,
+            AuditEvent::UnrecognizedCallback { .. } => summary.unrecognized_callbacks += 1,
+            AuditEvent::StaleFlowReset { .. } => summary.stale_flows_reset += 1,
+            _ => {}
+        }
+    }
+
+    if !completion_seconds.is_empty() {
+        summary.average_completion_seconds =
+            Some(completion_seconds.iter().sum::<i64>() / completion_seconds.len() as i64);
+    }
+    summary.ratings_received = ratings.len();
+    if !ratings.is_empty() {
+        summary.average_rating = Some(ratings.iter().sum::<f64>() / ratings.len() as f64);
+    }
+
+    summary
+}
+
+pub fn format_digest(summary: &DailySummary) -> String {
+    let volume = if summary.volume_by_token.is_empty() {
+        "• None".to_string()
+    } else {
+        summary
+            .volume_by_token
+            .iter()
+            .map(|(token, amount)| format!("• {:.2} {}", amount, token))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let average = summary
+        .average_completion_seconds
+        .map(|secs| format!("{} min {} sec", secs / 60, secs % 60))
+        .unwrap_or_else(|| "n/a".to_string());
+    let rating = summary
+        .average_rating
+        .map(|avg| format!("{:.1}/5", avg))
+        .unwrap_or_else(|| "n/a".to_string());
+
+    format!(
+        "📊 *Kharon Pay Daily Summary*\n\
+        📅 {} (UTC{})\n\n\
+        💸 Initiated: {}\n\
+        ✅ Completed: {}\n\
+        ❌ Failed: {}\n\n\
+        💰 *Volume:*\n{}\n\n\
+        ⏱️ Avg completion: {}\n\
+        📵 Failed message sends: {}\n\
+        ⌛ Status polls timed out: {}\n\
+        ⭐ Avg rating: {} ({} of {} asked)\n\
+        🪪 Account name mismatches: {}\n\
+        ❓ Unrecognized callbacks: {}\n\
+        🔄 Stale flows reset: {}",
+        summary.date,
+        summary.utc_offset,
+        summary.withdrawals_initiated,
+        summary.withdrawals_completed,
+        summary.withdrawals_failed,
+        volume,
+        average,
+        summary.failed_message_sends,
+        summary.polls_timed_out,
+        rating,
+        summary.ratings_received,
+        summary.ratings_requested,
+        summary.name_mismatches,
+        summary.unrecognized_callbacks,
+        summary.stale_flows_reset
+    )
+}
+
+/// Sends yesterday's summary to every number in `ADMIN_PHONES` at `SUMMARY_DIGEST_HOUR`
+/// (local to `SUMMARY_UTC_OFFSET`, default 08:00). Does nothing when no admins are set.
+pub fn start_digest_task() {
+    let admin_phones: Vec<String> = std::env::var("ADMIN_PHONES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect();
+    if admin_phones.is_empty() {
+        return;
+    }
+
+    let digest_hour: u32 = std::env::var("SUMMARY_DIGEST_HOUR")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|h| *h < 24)
+        .unwrap_or(8);
+
+    supervisor::supervise("daily_digest", move || {
+        let admin_phones = admin_phones.clone();
+        async move {
+            loop {
+                tokio::time::sleep(until_local_hour(digest_hour)).await;
+                let offset = summary_offset();
+
+                let entries = match audit::read_entries().await {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        eprintln!("Failed to build daily digest: {}", e);
+                        continue;
+                    }
+                };
+
+                let digest = format_digest(&summarize(&entries, yesterday(offset), offset));
+                for phone in &admin_phones {
+                    send_twilio_message(phone, &digest).await;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(rfc3339: &str, reference: Option<&str>, event: AuditEvent) -> AuditEntry {
+        AuditEntry {
+            timestamp: DateTime::parse_from_rfc3339(rfc3339).unwrap().into(),
+            phone: "2348012345678".to_string(),
+            correlation_id: None,
+            reference: reference.map(str::to_string),
+            event,
+        }
+    }
+
+    fn initiated(amount: f64, token: &str) -> AuditEvent {
+        AuditEvent::OfframpInitiated {
+            amount,
+            token: token.to_string(),
+        }
+    }
+
+    fn terminal(status: &str) -> AuditEvent {
+        AuditEvent::TerminalStatus {
+            status: status.to_string(),
+        }
+    }
+
+    /// 14 March in Lagos (UTC+1) runs from 23:00 UTC on the 13th to 23:00 UTC on the 14th.
+    #[test]
+    fn a_seeded_day_adds_up() {
+        let lagos = FixedOffset::east_opt(3600).unwrap();
+        let entries = [
+            // Started the evening before, so only its completion counts for the day
+            at("2026-03-13T22:50:00Z", Some("KP-1"), initiated(5.0, "USDT")),
+            at(
+                "2026-03-13T23:00:00Z",
+                Some("KP-2"),
+                initiated(10.0, "USDT"),
+            ),
+            at("2026-03-14T08:00:00Z", Some("KP-3"), initiated(2.5, "USDT")),
+            at(
+                "2026-03-14T09:00:00Z",
+                Some("KP-4"),
+                initiated(20.0, "USDC"),
+            ),
+            at("2026-03-13T23:10:00Z", Some("KP-1"), terminal("completed")),
+            at("2026-03-13T23:05:00Z", Some("KP-2"), terminal("Successful")),
+            at("2026-03-14T09:30:00Z", Some("KP-4"), terminal("failed")),
+            at("2026-03-14T10:00:00Z", Some("KP-3"), terminal("processing")),
+            at(
+                "2026-03-14T12:00:00Z",
+                None,
+                AuditEvent::MessageSendFailed {
+                    error: "timeout".to_string(),
+                },
+            ),
+            at(
+                "2026-03-14T13:00:00Z",
+                Some("KP-1"),
+                AuditEvent::FeedbackRequested,
+            ),
+            at(
+                "2026-03-14T13:00:00Z",
+                Some("KP-2"),
+                AuditEvent::FeedbackRequested,
+            ),
+            at(
+                "2026-03-14T13:05:00Z",
+                Some("KP-1"),
+                AuditEvent::FeedbackRated { rating: 4 },
+            ),
+            // The next Lagos day
+            at(
+                "2026-03-14T23:00:00Z",
+                Some("KP-5"),
+                initiated(99.0, "USDT"),
+            ),
+        ];
+
+        let date = NaiveDate::from_ymd_opt(2026, 3, 14).unwrap();
+        let summary = summarize(&entries, date, lagos);
+        assert_eq!(summary.utc_offset, "+01:00");
+        assert_eq!(summary.withdrawals_initiated, 3);
+        assert_eq!(summary.withdrawals_completed, 2);
+        assert_eq!(summary.withdrawals_failed, 1);
+        assert_eq!(
+            summary.volume_by_token,
+            BTreeMap::from([("USDC".to_string(), 20.0), ("USDT".to_string(), 12.5)])
+        );
+        // KP-1 took 20 minutes, KP-2 five
+        assert_eq!(summary.average_completion_seconds, Some(750));
+        assert_eq!(summary.failed_message_sends, 1);
+        assert_eq!(summary.ratings_requested, 2);
+        assert_eq!(summary.ratings_received, 1);
+        assert_eq!(summary.average_rating, Some(4.0));
+
+        // The same entries with a UTC day boundary
+        let utc = summarize(&entries, date, FixedOffset::east_opt(0).unwrap());
+        assert_eq!(utc.withdrawals_initiated, 3);
+        assert_eq!(utc.withdrawals_completed, 0);
+    }
+
+    #[test]
+    fn a_quiet_day_has_no_averages() {
+        let date = NaiveDate::from_ymd_opt(2026, 3, 14).unwrap();
+        let summary = summarize(&[], date, FixedOffset::east_opt(3600).unwrap());
+        assert_eq!(summary.withdrawals_initiated, 0);
+        assert_eq!(summary.average_completion_seconds, None);
+        assert_eq!(summary.average_rating, None);
+
+        let digest = format_digest(&summary);
+        assert!(digest.contains("💰 *Volume:*\n• None"), "{}", digest);
+        assert!(digest.contains("Avg completion: n/a"), "{}", digest);
+    }
+}
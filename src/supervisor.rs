@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::reporting::{self, ReportContext};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Running,
+    Restarting,
+    /// Gave up after `TASK_MAX_RESTARTS`; needs a redeploy.
+    Dead,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskState {
+    pub status: TaskStatus,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub started_at: DateTime<Utc>,
+}
+
+static TASKS: LazyLock<Mutex<BTreeMap<&'static str, TaskState>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// A run this long counts as healthy, so the next failure starts the backoff over.
+const STABLE_AFTER: Duration = Duration::from_secs(5 * 60);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+fn max_restarts() -> u32 {
+    std::env::var("TASK_MAX_RESTARTS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10)
+}
+
+fn update(name: &'static str, change: impl FnOnce(&mut TaskState)) {
+    let mut tasks = TASKS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(state) = tasks.get_mut(name) {
+        change(state);
+    }
+}
+
+/// Runs a long-lived background task and restarts it, with backoff, if it panics or
+/// returns. `make` builds a fresh future for every run.
+pub fn supervise<F, Fut>(name: &'static str, make: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    TASKS.lock().unwrap_or_else(|e| e.into_inner()).insert(
+        name,
+        TaskState {
+            status: TaskStatus::Running,
+            restarts: 0,
+            last_error: None,
+            started_at: Utc::now(),
+        },
+    );
+
+    tokio::spawn(async move {
+        let mut backoff = Duration::from_secs(1);
+        let mut restarts = 0;
+
+        loop {
+            let started = tokio::time::Instant::now();
+            let error = match tokio::spawn(reporting::scoped(ReportContext::for_task(name), make()))
+                .await
+            {
+                Ok(()) => "exited".to_string(),
+                Err(e) if e.is_panic() => {
+                    let payload = e.into_panic();
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    format!("panicked: {}", message)
+                }
+                Err(e) => e.to_string(),
+            };
+
+            if started.elapsed() >= STABLE_AFTER {
+                backoff = Duration::from_secs(1);
+            }
+
+            restarts += 1;
+            if restarts > max_restarts() {
+                eprintln!(
+                    "ALERT: Background task {} {}; giving up after {} restarts",
+                    name,
+                    error,
+                    restarts - 1
+                );
+                update(name, |state| {
+                    state.status = TaskStatus::Dead;
+                    state.last_error = Some(error);
+                });
+                return;
+            }
+
+            eprintln!(
+                "ALERT: Background task {} {}; restarting in {}s",
+                name,
+                error,
+                backoff.as_secs()
+            );
+            update(name, |state| {
+                state.status = TaskStatus::Restarting;
+                state.restarts = restarts;
+                state.last_error = Some(error);
+            });
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            update(name, |state| {
+                state.status = TaskStatus::Running;
+                state.started_at = Utc::now();
+            });
+        }
+    });
+}
+
+pub fn snapshot() -> BTreeMap<&'static str, TaskState> {
+    TASKS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn a_task_that_panics_once_is_restarted_and_reported() {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        supervise("test_flaky_once", || async {
+            if RUNS.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first run fails");
+            }
+            std::future::pending::<()>().await;
+        });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let state = snapshot()["test_flaky_once"].clone();
+        assert_eq!(state.status, TaskStatus::Restarting);
+        assert_eq!(
+            state.last_error.as_deref(),
+            Some("panicked: first run fails")
+        );
+
+        // The first backoff is a second
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        let state = snapshot()["test_flaky_once"].clone();
+        assert_eq!(state.status, TaskStatus::Running);
+        assert_eq!(state.restarts, 1);
+        assert_eq!(RUNS.load(Ordering::SeqCst), 2);
+
+        let text = metrics::render();
+        assert!(text.contains("background_task_up{task=\"test_flaky_once\"} 1"));
+        assert!(text.contains("background_task_restarts_total{task=\"test_flaky_once\"} 1"));
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::i18n::Language;
+use crate::model::{UserSessions, UserState};
+use crate::privacy::{mask_digit_runs, mask_phone};
+
+/// How many of the user's earlier messages go with a ticket.
+const CONTEXT_MESSAGES: usize = 5;
+
+/// What the helpdesk receives. Everything user-identifying is masked: support looks the
+/// user up by reference or correlation id, not by raw phone or account number.
+#[derive(Debug, Clone, Serialize)]
+pub struct SupportTicket {
+    pub phone: String,
+    pub state: UserState,
+    pub language: Language,
+    pub description: Option<String>,
+    /// Oldest first, not counting the `support` message itself.
+    pub recent_messages: Vec<String>,
+    pub pending_reference: Option<String>,
+    pub correlation_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SupportTicket {
+    pub fn new(session: &UserSessions, description: Option<&str>) -> Self {
+        let earlier = session
+            .inbound_history
+            .split_last()
+            .map(|(_, earlier)| earlier)
+            .unwrap_or_default();
+        let recent_messages = earlier
+            .iter()
+            .skip(earlier.len().saturating_sub(CONTEXT_MESSAGES))
+            .map(|message| mask_digit_runs(&message.body))
+            .collect();
+
+        SupportTicket {
+            phone: mask_phone(&session.phone),
+            state: session.state,
+            language: session.language(),
+            description: description
+                .map(str::trim)
+                .filter(|d| !d.is_empty())
+                .map(mask_digit_runs),
+            recent_messages,
+            pending_reference: session
+                .pending_payout
+                .as_ref()
+                .map(|payout| payout.reference.clone()),
+            correlation_id: session.correlation_id.clone(),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+static OPENED: LazyLock<Mutex<HashMap<String, VecDeque<Instant>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(default)
+}
+
+/// `SUPPORT_MAX_TICKETS` per `SUPPORT_WINDOW_HOURS` (defaults 3 per 24h). Returns how long
+/// until the oldest ticket leaves the window when the user is at the limit.
+fn limited(phone: &str, now: Instant) -> Option<Duration> {
+    let max = env_u64("SUPPORT_MAX_TICKETS", 3) as usize;
+    let window = Duration::from_secs(env_u64("SUPPORT_WINDOW_HOURS", 24) * 3600);
+
+    let mut opened = OPENED.lock().unwrap_or_else(|e| e.into_inner());
+    opened.retain(|_, times| times.back().is_some_and(|at| now - *at < window));
+    let times = opened.get_mut(phone)?;
+    while times.front().is_some_and(|at| now - *at >= window) {
+        times.pop_front();
+    }
+    (times.len() >= max).then(|| window - (now - times[0]))
+}
+
+/// Only tickets the helpdesk accepted count towards the limit.
+fn record_opened(phone: &str, now: Instant) {
+    OPENED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(phone.to_string())
+        .or_default()
+        .push_back(now);
+}
+
+/// Helpdesks disagree on where the id goes; take the first of the usual places.
+fn ticket_id(body: &Value) -> Option<String> {
+    ["/ticket_id", "/id", "/data/ticket_id", "/data/id"]
+        .iter()
+        .filter_map(|pointer| body.pointer(pointer))
+        .find_map(|id| match id {
+            Value::String(id) if !id.is_empty() => Some(id.clone()),
+            Value::Number(id) => Some(id.to_string()),
+            _ => None,
+        })
+}
+
+async fn submit(url: &str, ticket: &SupportTicket) -> Result<String, String> {
+    let response = reqwest::Client::new()
+        .post(url)
+        .timeout(Duration::from_secs(10))
+        .json(ticket)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("returned {}", response.status()));
+    }
+    let body: Value = response.json().await.map_err(|e| e.to_string())?;
+    ticket_id(&body).ok_or_else(|| "response carried no ticket id".to_string())
+}
+
+/// `support [description]`: opens a ticket at `SUPPORT_WEBHOOK_URL` with the conversation
+/// so far, and tells the user when to expect an answer (`SUPPORT_RESPONSE_TIME`).
+pub async fn open_ticket(session: &UserSessions, description: Option<&str>) -> String {
+    let Some(url) = std::env::var("SUPPORT_WEBHOOK_URL")
+        .ok()
+        .filter(|url| !url.trim().is_empty())
+    else {
+        eprintln!(
+            "Support requested by {} but SUPPORT_WEBHOOK_URL is not set",
+            mask_phone(&session.phone)
+        );
+        return "❌ We can't open support tickets right now. Please try again later.".to_string();
+    };
+
+    let now = Instant::now();
+    if let Some(wait) = limited(&session.phone, now) {
+        let hours = wait.as_secs().div_ceil(3600);
+        return format!(
+            "⏳ You've already opened several tickets recently, and we're on them. \
+            You can open another in about {} hour{}.",
+            hours,
+            if hours == 1 { "" } else { "s" }
+        );
+    }
+
+    let ticket = SupportTicket::new(session, description);
+    match submit(&url, &ticket).await {
+        Ok(id) => {
+            record_opened(&session.phone, now);
+            println!("Support ticket {} opened for {}", id, ticket.phone);
+            let response_time = std::env::var("SUPPORT_RESPONSE_TIME")
+                .unwrap_or_else(|_| "within 24 hours".to_string());
+            format!(
+                "🎫 *Ticket {} opened*\n\nOur team will get back to you here {}.",
+                id, response_time
+            )
+        }
+        Err(e) => {
+            eprintln!("Support ticket for {} failed: {}", ticket.phone, e);
+            "❌ We couldn't open a ticket just now. Please try `support` again in a few minutes."
+                .to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::PendingPayout;
+    use serde_json::json;
+
+    #[test]
+    fn a_ticket_carries_the_masked_context_of_the_conversation() {
+        let mut session = UserSessions::new("+2348090003001");
+        session.state = UserState::PayoutApproval;
+        session.correlation_id = Some("corr-ticket".to_string());
+        session.pending_payout = Some(PendingPayout {
+            reference: "KP-TICKET-1".to_string(),
+            amount: 15_000.0,
+            currency: "NGN".to_string(),
+            bank_name: "Opay".to_string(),
+            account_name: "ADA OBI".to_string(),
+            crypto_tx_hash: "0xabc".to_string(),
+        });
+        for body in ["hi", "balance", "withdraw 10 USDT", "confirm"] {
+            session.record_inbound(body);
+        }
+        session.record_inbound("Opay, 0123456789");
+        session.record_inbound("yes");
+        session.record_inbound("support stuck");
+
+        let ticket = SupportTicket::new(&session, Some("  paid to 0123456789 but nothing  "));
+        assert_eq!(ticket.phone, mask_phone("+2348090003001"));
+        assert!(!ticket.phone.contains("8090003001"), "{}", ticket.phone);
+        assert_eq!(ticket.state, UserState::PayoutApproval);
+        assert_eq!(ticket.pending_reference.as_deref(), Some("KP-TICKET-1"));
+        assert_eq!(ticket.correlation_id.as_deref(), Some("corr-ticket"));
+        assert_eq!(
+            ticket.description.as_deref(),
+            Some("paid to ******6789 but nothing")
+        );
+        // The last five before the `support` message, oldest first, digits masked
+        assert_eq!(
+            ticket.recent_messages,
+            [
+                "balance",
+                "withdraw 10 USDT",
+                "confirm",
+                "Opay, ******6789",
+                "yes"
+            ]
+        );
+
+        let payload = serde_json::to_value(&ticket).unwrap();
+        for field in [
+            "phone",
+            "state",
+            "language",
+            "recent_messages",
+            "created_at",
+        ] {
+            assert!(
+                payload.get(field).is_some(),
+                "{} missing: {}",
+                field,
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn a_blank_description_is_left_out() {
+        let session = UserSessions::new("+2348090003002");
+        assert_eq!(SupportTicket::new(&session, Some("   ")).description, None);
+        assert!(
+            SupportTicket::new(&session, None)
+                .recent_messages
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn the_ticket_id_is_found_wherever_the_helpdesk_puts_it() {
+        assert_eq!(
+            ticket_id(&json!({ "ticket_id": "T-1" })).as_deref(),
+            Some("T-1")
+        );
+        assert_eq!(ticket_id(&json!({ "id": 42 })).as_deref(), Some("42"));
+        assert_eq!(
+            ticket_id(&json!({ "data": { "id": "T-3" } })).as_deref(),
+            Some("T-3")
+        );
+        assert_eq!(ticket_id(&json!({ "ticket_id": "" })), None);
+        assert_eq!(ticket_id(&json!({ "ok": true })), None);
+    }
+
+    #[test]
+    fn the_fourth_ticket_in_a_day_waits_for_the_first_to_age_out() {
+        let phone = "+2348090003003";
+        let start = Instant::now();
+        for hour in 0..3 {
+            let at = start + Duration::from_secs(hour * 3600);
+            assert_eq!(limited(phone, at), None);
+            record_opened(phone, at);
+        }
+
+        let later = start + Duration::from_secs(3 * 3600);
+        assert_eq!(limited(phone, later), Some(Duration::from_secs(21 * 3600)));
+        // Someone else isn't held back
+        assert_eq!(limited("+2348090003004", later), None);
+        // A day after the first, there's room for one more
+        assert_eq!(limited(phone, start + Duration::from_secs(24 * 3600)), None);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// What the webhook should do with an inbound message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// Sender is cooling down; process-and-drop without replying.
+    Muted,
+    /// This message pushed the sender over a limit; the cooldown starts now.
+    Tripped(String),
+}
+
+#[derive(Default)]
+struct Activity {
+    recent: VecDeque<Instant>,
+    last_body: String,
+    repeats: u32,
+    muted_until: Option<Instant>,
+}
+
+impl Activity {
+    fn idle(&self, now: Instant) -> bool {
+        self.muted_until.is_none_or(|until| until <= now)
+            && self.recent.back().is_none_or(|at| now - *at >= WINDOW)
+    }
+}
+
+struct Limits {
+    max_per_minute: usize,
+    max_identical: u32,
+    cooldown: Duration,
+}
+
+impl Limits {
+    fn from_env() -> Self {
+        Limits {
+            max_per_minute: env_u32("SPAM_MAX_PER_MINUTE", 20) as usize,
+            max_identical: env_u32("SPAM_MAX_IDENTICAL", 5),
+            cooldown: Duration::from_secs(env_u32("SPAM_COOLDOWN_SECS", 600) as u64),
+        }
+    }
+}
+
+/// Read once; a change needs a restart.
+static LIMITS: LazyLock<Limits> = LazyLock::new(Limits::from_env);
+
+/// Everyone who wrote recently. Idle senders are dropped at most once a `WINDOW`, not
+/// on every message.
+#[derive(Default)]
+struct Tracker {
+    senders: HashMap<String, Activity>,
+    pruned_at: Option<Instant>,
+}
+
+static ACTIVITY: LazyLock<Mutex<Tracker>> = LazyLock::new(|| Mutex::new(Tracker::default()));
+
+fn env_u32(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(default)
+}
+
+/// Another bot wired to our number will answer every reply we send. Past
+/// `SPAM_MAX_PER_MINUTE` messages (default 20), or `SPAM_MAX_IDENTICAL` byte-identical
+/// messages in a row within a minute (default 5), we stop replying for
+/// `SPAM_COOLDOWN_SECS` (default 600). A person confirming a withdrawal sends a handful
+/// of short, varied messages and never gets near either limit.
+pub fn check(phone: &str, body: &str) -> Verdict {
+    ACTIVITY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .check(&LIMITS, phone, body, Instant::now())
+}
+
+impl Tracker {
+    fn prune(&mut self, now: Instant) {
+        if self.pruned_at.is_some_and(|at| now - at < WINDOW) {
+            return;
+        }
+        self.senders.retain(|_, activity| !activity.idle(now));
+        self.pruned_at = Some(now);
+    }
+
+    fn check(&mut self, limits: &Limits, phone: &str, body: &str, now: Instant) -> Verdict {
+        self.prune(now);
+
+        let entry = self.senders.entry(phone.to_string()).or_default();
+        if entry.muted_until.is_some_and(|until| until > now) {
+            return Verdict::Muted;
+        }
+        entry.muted_until = None;
+
+        let follows_closely = entry.recent.back().is_some_and(|at| now - *at < WINDOW);
+        if follows_closely && entry.last_body == body {
+            entry.repeats += 1;
+        } else {
+            entry.repeats = 1;
+            entry.last_body = body.to_string();
+        }
+
+        entry.recent.push_back(now);
+        while entry.recent.front().is_some_and(|at| now - *at >= WINDOW) {
+            entry.recent.pop_front();
+        }
+
+        let reason = if entry.recent.len() > limits.max_per_minute {
+            Some(format!(
+                "{} messages in the last minute",
+                entry.recent.len()
+            ))
+        } else if entry.repeats > limits.max_identical {
+            Some(format!("{} identical messages in a row", entry.repeats))
+        } else {
+            None
+        };
+
+        match reason {
+            Some(reason) => {
+                entry.muted_until = Some(now + limits.cooldown);
+                entry.recent.clear();
+                entry.repeats = 0;
+                Verdict::Tripped(reason)
+            }
+            None => Verdict::Allow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        max_per_minute: 4,
+        max_identical: 2,
+        cooldown: Duration::from_secs(600),
+    };
+
+    #[test]
+    fn repeating_one_message_trips_then_mutes_until_the_cooldown_ends() {
+        let mut tracker = Tracker::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(tracker.check(&LIMITS, "+1", "hi", at(0)), Verdict::Allow);
+        assert_eq!(tracker.check(&LIMITS, "+1", "hi", at(1)), Verdict::Allow);
+        assert!(matches!(
+            tracker.check(&LIMITS, "+1", "hi", at(2)),
+            Verdict::Tripped(reason) if reason.contains("identical")
+        ));
+        assert_eq!(
+            tracker.check(&LIMITS, "+1", "balance", at(300)),
+            Verdict::Muted
+        );
+        assert_eq!(
+            tracker.check(&LIMITS, "+1", "balance", at(603)),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn too_many_varied_messages_trip_too() {
+        let mut tracker = Tracker::default();
+        let start = Instant::now();
+        for i in 0..4 {
+            let verdict = tracker.check(&LIMITS, "+2", &i.to_string(), start);
+            assert_eq!(verdict, Verdict::Allow);
+        }
+        assert!(matches!(
+            tracker.check(&LIMITS, "+2", "4", start),
+            Verdict::Tripped(reason) if reason.contains("in the last minute")
+        ));
+        // Someone else is unaffected
+        assert_eq!(tracker.check(&LIMITS, "+3", "hi", start), Verdict::Allow);
+    }
+
+    #[test]
+    fn idle_senders_are_pruned_once_a_window() {
+        let mut tracker = Tracker::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        tracker.check(&LIMITS, "+4", "hi", at(0));
+        tracker.check(&LIMITS, "+5", "hi", at(59));
+        tracker.check(&LIMITS, "+6", "hi", at(70));
+        // +4 went idle and was pruned with the first message a window later
+        assert_eq!(tracker.senders.len(), 2);
+
+        // +5 is idle by now, but the last prune was under a window ago
+        tracker.check(&LIMITS, "+7", "hi", at(125));
+        assert_eq!(tracker.senders.len(), 3);
+
+        tracker.check(&LIMITS, "+7", "hi", at(130));
+        assert_eq!(tracker.senders.len(), 1);
+    }
+}
@@ -0,0 +1,232 @@
+use std::fmt;
+use std::io;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::{Duration, SystemTime};
+
+use actix_http::{HttpService, Protocol, Request, Response, body::MessageBody};
+use actix_service::{
+    IntoServiceFactory, ServiceFactory, ServiceFactoryExt, fn_service, map_config,
+};
+use actix_web::dev::{AppConfig, Server};
+use chrono::{DateTime, Utc};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::supervisor;
+
+/// A client gets this long to finish the handshake before the connection is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct TlsPaths {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl TlsPaths {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        TlsPaths {
+            cert: cert.into(),
+            key: key.into(),
+        }
+    }
+}
+
+/// `TLS_CERT_PATH` (PEM chain, leaf first) and `TLS_KEY_PATH` (PEM key) turn on HTTPS.
+/// Neither set keeps plain HTTP; only one set is a config mistake.
+pub fn from_env() -> Result<Option<TlsPaths>, String> {
+    let cert = std::env::var("TLS_CERT_PATH")
+        .ok()
+        .filter(|v| !v.is_empty());
+    let key = std::env::var("TLS_KEY_PATH").ok().filter(|v| !v.is_empty());
+    match (cert, key) {
+        (Some(cert), Some(key)) => Ok(Some(TlsPaths::new(cert, key))),
+        (None, None) => Ok(None),
+        _ => Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string()),
+    }
+}
+
+static ACCEPTOR: LazyLock<RwLock<Option<Arc<TlsAcceptor>>>> = LazyLock::new(|| RwLock::new(None));
+
+/// Whether we terminate TLS ourselves, so request URLs are `https` even without a proxy.
+pub fn enabled() -> bool {
+    ACCEPTOR.read().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+fn read(path: &Path, what: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Cannot read TLS {} {}: {}", what, path.display(), e))
+}
+
+/// Builds an acceptor from the PEM files, refusing anything we'd regret serving: an
+/// expired leaf, a key that doesn't belong to it, or files that don't parse.
+fn load(paths: &TlsPaths) -> Result<TlsAcceptor, String> {
+    let cert_pem = read(&paths.cert, "certificate")?;
+    let key_pem = read(&paths.key, "key")?;
+
+    let chain = CertificateDer::pem_slice_iter(&cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{} is not a PEM certificate: {}", paths.cert.display(), e))?;
+    let leaf = chain
+        .first()
+        .ok_or_else(|| format!("{} holds no certificates", paths.cert.display()))?;
+    let (_, parsed) = X509Certificate::from_der(leaf)
+        .map_err(|e| format!("{} is not a valid certificate: {}", paths.cert.display(), e))?;
+    let not_after = parsed.validity().not_after.timestamp();
+    if not_after < Utc::now().timestamp() {
+        let expired = DateTime::from_timestamp(not_after, 0).unwrap_or_default();
+        return Err(format!(
+            "TLS certificate {} expired on {}",
+            paths.cert.display(),
+            expired
+        ));
+    }
+
+    // PKCS#1, SEC1 and PKCS#8 keys all load, so certbot's output works as it is
+    let key = PrivateKeyDer::from_pem_slice(&key_pem)
+        .map_err(|e| format!("{} is not a PEM private key: {}", paths.key.display(), e))?;
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(|e| {
+            format!(
+                "{} does not match the certificate in {}: {}",
+                paths.key.display(),
+                paths.cert.display(),
+                e
+            )
+        })?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Validates the certificate without installing it, for `check-config`.
+pub fn check(paths: &TlsPaths) -> Result<(), String> {
+    load(paths).map(|_| ())
+}
+
+/// Loads the certificate at startup; any problem is returned so the server won't start.
+pub fn init(paths: &TlsPaths) -> Result<(), String> {
+    let acceptor = load(paths)?;
+    *ACCEPTOR.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(acceptor));
+    Ok(())
+}
+
+fn reload(paths: &TlsPaths, why: &str) {
+    match load(paths) {
+        Ok(acceptor) => {
+            *ACCEPTOR.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(acceptor));
+            println!("TLS certificate reloaded ({})", why);
+        }
+        Err(e) => eprintln!(
+            "ALERT: TLS certificate reload failed ({}); still serving the previous one: {}",
+            why, e
+        ),
+    }
+}
+
+fn modified(paths: &TlsPaths) -> Option<(SystemTime, SystemTime)> {
+    let cert = std::fs::metadata(&paths.cert)
+        .and_then(|m| m.modified())
+        .ok()?;
+    let key = std::fs::metadata(&paths.key)
+        .and_then(|m| m.modified())
+        .ok()?;
+    Some((cert, key))
+}
+
+/// Picks up renewed certificates without a restart: on SIGHUP, or when either file's
+/// mtime changes (checked every `TLS_WATCH_SECS`, default 60). New connections get the
+/// new certificate; open ones keep theirs.
+pub fn start_reload_task(paths: TlsPaths) {
+    let every = Duration::from_secs(
+        std::env::var("TLS_WATCH_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(60),
+    );
+
+    supervisor::supervise("tls_reload", move || {
+        let paths = paths.clone();
+        async move {
+            let mut hangup =
+                match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                    Ok(signal) => Some(signal),
+                    Err(e) => {
+                        eprintln!("Cannot listen for SIGHUP, relying on the file watch: {}", e);
+                        None
+                    }
+                };
+            let mut seen = modified(&paths);
+            loop {
+                let on_signal = async {
+                    match hangup.as_mut() {
+                        Some(signal) => signal.recv().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    _ = on_signal => {
+                        seen = modified(&paths);
+                        reload(&paths, "SIGHUP");
+                    }
+                    _ = tokio::time::sleep(every) => {
+                        let now = modified(&paths);
+                        if now.is_some() && now != seen {
+                            seen = now;
+                            reload(&paths, "files changed");
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
+
+fn current() -> Option<Arc<TlsAcceptor>> {
+    ACCEPTOR.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Serves the app over HTTPS (HTTP/1.1) on `listener`, what `HttpServer` does for plain HTTP.
+/// Each connection takes the acceptor current at accept time, so reloads apply as they land.
+/// Signals are left to the caller, as with `HttpServer::disable_signals`.
+pub fn serve<F, I, S, B>(make_app: F, listener: TcpListener) -> io::Result<Server>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S, Request>,
+    S: ServiceFactory<Request, Config = AppConfig> + 'static,
+    S::Error: Into<Response<actix_http::body::BoxBody>> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    S::Future: 'static,
+    <S::Service as actix_service::Service<Request>>::Future: 'static,
+    B: MessageBody + 'static,
+{
+    Ok(Server::build()
+        .listen("kharon-https", listener, move || {
+            let handshake = fn_service(|stream: TcpStream| async move {
+                let peer = stream.peer_addr().ok();
+                let acceptor =
+                    current().ok_or_else(|| io::Error::other("TLS is not initialised"))?;
+                let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                    .await
+                    .map_err(|_| {
+                        io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")
+                    })?
+                    .map_err(io::Error::other)?;
+                Ok::<_, actix_http::error::DispatchError>((stream, Protocol::Http1, peer))
+            });
+            let app = map_config(make_app(), |_| AppConfig::default());
+            handshake.and_then(HttpService::build().finish(app))
+        })?
+        .disable_signals()
+        .run())
+}
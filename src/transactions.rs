@@ -0,0 +1,500 @@
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use serde_json::Value;
+
+use crate::i18n::Language;
+use crate::privacy::{hash_phone, mask_phone};
+use crate::seal;
+use crate::store;
+use crate::supervisor;
+
+/// A withdrawal as we know it from initiation on: who it belongs to and where it's paying
+/// out, for anything that only has the reference to go on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedTransaction {
+    pub reference: String,
+    pub phone: String,
+    pub amount: f64,
+    pub token: String,
+    pub bank_name: String,
+    pub account_name: String,
+    /// Only the last digits; the full number stays with the backend.
+    pub masked_account: String,
+    pub initiated_at: DateTime<Utc>,
+    /// `initiated`, `processing` once the payment is triggered, then whatever final
+    /// status the backend reports.
+    pub status: String,
+    pub status_updated_at: DateTime<Utc>,
+    pub correlation_id: Option<String>,
+    pub tx_hash: Option<String>,
+    pub language: Language,
+}
+
+/// Withdrawals by reference, in SQLite at `TRANSACTION_INDEX_DB_PATH`. Rows are found by
+/// `phone_hash`; the number itself is only kept sealed (see `seal::seal_value`), for the
+/// poller to report back to.
+pub struct TransactionIndex {
+    conn: Mutex<Connection>,
+}
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS transactions (
+         reference TEXT PRIMARY KEY,
+         phone TEXT NOT NULL,
+         phone_hash TEXT NOT NULL,
+         amount REAL NOT NULL,
+         token TEXT NOT NULL,
+         bank_name TEXT NOT NULL,
+         account_name TEXT NOT NULL,
+         masked_account TEXT NOT NULL,
+         initiated_at TEXT NOT NULL,
+         status TEXT NOT NULL,
+         status_updated_at TEXT NOT NULL,
+         correlation_id TEXT,
+         tx_hash TEXT,
+         language TEXT NOT NULL
+     );
+     CREATE INDEX IF NOT EXISTS transactions_phone ON transactions (phone_hash, initiated_at);
+     CREATE INDEX IF NOT EXISTS transactions_updated ON transactions (status_updated_at);
+     CREATE TABLE IF NOT EXISTS completions (
+         reference TEXT PRIMARY KEY,
+         bank TEXT NOT NULL,
+         seconds INTEGER NOT NULL,
+         completed_at TEXT NOT NULL
+     );
+     CREATE INDEX IF NOT EXISTS completions_bank ON completions (bank, completed_at);
+     CREATE INDEX IF NOT EXISTS completions_at ON completions (completed_at);";
+
+const COLUMNS: &str = "reference, phone, amount, token, bank_name, account_name, masked_account,
+     initiated_at, status, status_updated_at, correlation_id, tx_hash, language";
+
+pub fn transaction_index_path() -> String {
+    std::env::var("TRANSACTION_INDEX_DB_PATH").unwrap_or_else(|_| "transactions.db".to_string())
+}
+
+/// `TRANSACTION_INDEX_RETENTION_DAYS`, default 90, counted from a row's last status change.
+fn retention() -> chrono::Duration {
+    chrono::Duration::days(
+        std::env::var("TRANSACTION_INDEX_RETENTION_DAYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(90),
+    )
+}
+
+fn language_code(language: Language) -> String {
+    serde_json::to_value(language)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn parse_timestamp(raw: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(raw)
+        .map(|at| at.with_timezone(&Utc))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// A row as stored, with `phone` still sealed; see `reveal`.
+fn from_row(row: &Row) -> rusqlite::Result<IndexedTransaction> {
+    Ok(IndexedTransaction {
+        reference: row.get(0)?,
+        phone: row.get(1)?,
+        amount: row.get(2)?,
+        token: row.get(3)?,
+        bank_name: row.get(4)?,
+        account_name: row.get(5)?,
+        masked_account: row.get(6)?,
+        initiated_at: parse_timestamp(&row.get::<_, String>(7)?),
+        status: row.get(8)?,
+        status_updated_at: parse_timestamp(&row.get::<_, String>(9)?),
+        correlation_id: row.get(10)?,
+        tx_hash: row.get(11)?,
+        language: serde_json::from_value(Value::String(row.get(12)?)).unwrap_or_default(),
+    })
+}
+
+fn reveal(mut tx: IndexedTransaction) -> Result<IndexedTransaction, String> {
+    tx.phone = seal::open_value(&tx.reference, "phone", &tx.phone)
+        .map_err(|e| format!("Failed to open transaction {}: {}", tx.reference, e))?;
+    Ok(tx)
+}
+
+impl TransactionIndex {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let conn = Connection::open(path)
+            .map_err(|e| format!("Failed to open transaction index {}: {}", path, e))?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, String> {
+        conn.execute_batch(&format!(
+            "PRAGMA busy_timeout = 5000;
+             {}",
+            SCHEMA
+        ))
+        .map_err(|e| format!("Failed to initialise transaction index: {}", e))?;
+        let index = TransactionIndex {
+            conn: Mutex::new(conn),
+        };
+        index.seal_plain_phones()?;
+        Ok(index)
+    }
+
+    /// Seals numbers that rows written before sealing was on still hold in the clear.
+    fn seal_plain_phones(&self) -> Result<(), String> {
+        let conn = self.conn();
+        let plain: Vec<(String, String)> = conn
+            .prepare("SELECT reference, phone FROM transactions WHERE phone NOT LIKE 'enc:%'")
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect()
+            })
+            .map_err(|e| format!("Failed to read transaction index: {}", e))?;
+        for (reference, phone) in plain {
+            let sealed = seal::seal_value(&reference, "phone", &phone)?;
+            if sealed == phone {
+                // Sealing is off
+                return Ok(());
+            }
+            conn.execute(
+                "UPDATE transactions SET phone = ?2 WHERE reference = ?1",
+                params![reference, sealed],
+            )
+            .map_err(|e| format!("Failed to seal transaction {}: {}", reference, e))?;
+        }
+        Ok(())
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds a withdrawal, or replaces one the backend handed the same reference again.
+    pub fn insert(&self, tx: &IndexedTransaction) -> Result<(), String> {
+        let sealed_phone = seal::seal_value(&tx.reference, "phone", &tx.phone)?;
+        self.conn()
+            .execute(
+                &format!(
+                    "INSERT OR REPLACE INTO transactions ({}, phone_hash)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                    COLUMNS
+                ),
+                params![
+                    tx.reference,
+                    sealed_phone,
+                    tx.amount,
+                    tx.token,
+                    tx.bank_name,
+                    tx.account_name,
+                    tx.masked_account,
+                    tx.initiated_at.to_rfc3339(),
+                    tx.status,
+                    tx.status_updated_at.to_rfc3339(),
+                    tx.correlation_id,
+                    tx.tx_hash,
+                    language_code(tx.language),
+                    hash_phone(&tx.phone),
+                ],
+            )
+            .map_err(|e| format!("Failed to index transaction {}: {}", tx.reference, e))?;
+        Ok(())
+    }
+
+    /// Returns whether the reference was known.
+    pub fn update_status(&self, reference: &str, status: &str) -> Result<bool, String> {
+        self.conn()
+            .execute(
+                "UPDATE transactions SET status = ?2, status_updated_at = ?3 WHERE reference = ?1",
+                params![reference, status, Utc::now().to_rfc3339()],
+            )
+            .map(|updated| updated > 0)
+            .map_err(|e| format!("Failed to update transaction {}: {}", reference, e))
+    }
+
+    pub fn get(&self, reference: &str) -> Result<Option<IndexedTransaction>, String> {
+        self.conn()
+            .query_row(
+                &format!("SELECT {} FROM transactions WHERE reference = ?1", COLUMNS),
+                params![reference],
+                from_row,
+            )
+            .optional()
+            .map_err(|e| format!("Failed to look up transaction {}: {}", reference, e))?
+            .map(reveal)
+            .transpose()
+    }
+
+    /// The user's withdrawals, newest first.
+    pub fn for_phone(&self, phone: &str) -> Result<Vec<IndexedTransaction>, String> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT {} FROM transactions WHERE phone_hash = ?1 ORDER BY initiated_at DESC",
+                COLUMNS
+            ))
+            .map_err(|e| format!("Failed to query transaction index: {}", e))?;
+        let rows: Vec<IndexedTransaction> = statement
+            .query_map(params![hash_phone(phone)], from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(|e| format!("Failed to query transaction index: {}", e))?;
+        rows.into_iter().map(reveal).collect()
+    }
+
+    /// Keeps how long a withdrawal took to pay out; `bank` is already normalized.
+    pub fn insert_completion(
+        &self,
+        reference: &str,
+        bank: &str,
+        seconds: i64,
+        completed_at: DateTime<Utc>,
+    ) -> Result<(), String> {
+        self.conn()
+            .execute(
+                "INSERT OR REPLACE INTO completions (reference, bank, seconds, completed_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![reference, bank, seconds, completed_at.to_rfc3339()],
+            )
+            .map(|_| ())
+            .map_err(|e| format!("Failed to record completion of {}: {}", reference, e))
+    }
+
+    /// Durations of the latest `limit` completions since `since`, to `bank` or anywhere.
+    pub fn completion_seconds(
+        &self,
+        bank: Option<&str>,
+        since: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<i64>, String> {
+        let conn = self.conn();
+        let mut statement = conn
+            .prepare(
+                "SELECT seconds FROM completions
+                 WHERE completed_at >= ?1 AND (?2 IS NULL OR bank = ?2)
+                 ORDER BY completed_at DESC LIMIT ?3",
+            )
+            .map_err(|e| format!("Failed to query completions: {}", e))?;
+        statement
+            .query_map(params![since.to_rfc3339(), bank, limit as i64], |row| {
+                row.get(0)
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(|e| format!("Failed to query completions: {}", e))
+    }
+
+    /// Drops rows whose status hasn't changed since before `cutoff`, and completions
+    /// older than that.
+    pub fn prune(&self, cutoff: DateTime<Utc>) -> Result<usize, String> {
+        let conn = self.conn();
+        let cutoff = cutoff.to_rfc3339();
+        let transactions = conn
+            .execute(
+                "DELETE FROM transactions WHERE status_updated_at < ?1",
+                params![cutoff],
+            )
+            .map_err(|e| format!("Failed to prune transaction index: {}", e))?;
+        conn.execute(
+            "DELETE FROM completions WHERE completed_at < ?1",
+            params![cutoff],
+        )
+        .map_err(|e| format!("Failed to prune completions: {}", e))?;
+        Ok(transactions)
+    }
+}
+
+/// Falls back to an in-memory database when the file can't be opened, so withdrawals
+/// started in this process can still be tracked.
+static INDEX: LazyLock<TransactionIndex> = LazyLock::new(|| {
+    let path = transaction_index_path();
+    TransactionIndex::open(&path).unwrap_or_else(|e| {
+        eprintln!(
+            "ALERT: {}; transactions will only be indexed until restart",
+            e
+        );
+        Connection::open_in_memory()
+            .map_err(|e| e.to_string())
+            .and_then(TransactionIndex::with_connection)
+            .expect("in-memory SQLite is available")
+    })
+});
+
+/// Indexes a withdrawal at initiation. A failure is logged rather than returned: the
+/// withdrawal goes ahead, it just can't be looked up by reference.
+pub async fn record(tx: &IndexedTransaction) {
+    let indexed = tx.clone();
+    if let Err(e) = store::blocking(move || INDEX.insert(&indexed)).await {
+        eprintln!("ALERT: {} for {}", e, mask_phone(&tx.phone));
+    }
+}
+
+pub async fn set_status(reference: &str, status: &str) {
+    let (key, new_status) = (reference.to_string(), status.to_string());
+    match store::blocking(move || INDEX.update_status(&key, &new_status)).await {
+        Ok(true) => {}
+        Ok(false) => eprintln!(
+            "Transaction {} isn't indexed; status {} not kept",
+            reference, status
+        ),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// Feeds `eta`. Logged rather than returned: the user has their money either way.
+pub fn record_completion(reference: &str, bank: &str, seconds: i64, completed_at: DateTime<Utc>) {
+    if let Err(e) = INDEX.insert_completion(reference, bank, seconds, completed_at) {
+        eprintln!("{}", e);
+    }
+}
+
+pub fn completion_seconds(bank: Option<&str>, since: DateTime<Utc>, limit: usize) -> Vec<i64> {
+    INDEX
+        .completion_seconds(bank, since, limit)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            Vec::new()
+        })
+}
+
+pub async fn lookup(reference: &str) -> Option<IndexedTransaction> {
+    let key = reference.to_string();
+    store::blocking(move || INDEX.get(&key))
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            None
+        })
+}
+
+/// The user's newest withdrawal that hasn't reached a final status, if any.
+pub async fn active_withdrawal(phone: &str) -> Option<IndexedTransaction> {
+    for_phone(phone).await.into_iter().find(|tx| {
+        !matches!(
+            tx.status.as_str(),
+            "completed" | "successful" | "failed" | "cancelled"
+        )
+    })
+}
+
+pub async fn for_phone(phone: &str) -> Vec<IndexedTransaction> {
+    let phone = phone.to_string();
+    store::blocking(move || INDEX.for_phone(&phone))
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            Vec::new()
+        })
+}
+
+/// Opens the index at startup, rather than on the first withdrawal, and prunes it hourly.
+pub fn start_pruning() {
+    LazyLock::force(&INDEX);
+    supervisor::supervise("transaction_index", || async {
+        let mut timer = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            timer.tick().await;
+            let cutoff = Utc::now() - retention();
+            match tokio::task::spawn_blocking(move || INDEX.prune(cutoff)).await {
+                Ok(Ok(0)) => {}
+                Ok(Ok(removed)) => {
+                    println!("Pruned {} indexed transactions past retention", removed)
+                }
+                Ok(Err(e)) => eprintln!("{}", e),
+                Err(e) => eprintln!("Transaction index prune task failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn withdrawal(reference: &str, phone: &str) -> IndexedTransaction {
+        IndexedTransaction {
+            reference: reference.to_string(),
+            phone: phone.to_string(),
+            amount: 10.0,
+            token: "USDT".to_string(),
+            bank_name: "Opay".to_string(),
+            account_name: "ADA OBI".to_string(),
+            masked_account: "6789".to_string(),
+            initiated_at: Utc::now(),
+            status: "initiated".to_string(),
+            status_updated_at: Utc::now(),
+            correlation_id: None,
+            tx_hash: None,
+            language: Language::En,
+        }
+    }
+
+    #[test]
+    fn withdrawals_are_found_by_reference_and_by_owner() {
+        let index =
+            TransactionIndex::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        index
+            .insert(&withdrawal("KP-T-1", "+2348090000201"))
+            .unwrap();
+        index
+            .insert(&withdrawal("KP-T-2", "+2348090000202"))
+            .unwrap();
+
+        let found = index.get("KP-T-1").unwrap().unwrap();
+        assert_eq!(found.phone, "+2348090000201");
+        // However the number is written
+        let owned = index.for_phone("2348090000201").unwrap();
+        assert_eq!(owned.len(), 1);
+        assert_eq!(owned[0].reference, "KP-T-1");
+
+        assert!(index.update_status("KP-T-1", "processing").unwrap());
+        assert!(!index.update_status("KP-T-3", "processing").unwrap());
+        assert_eq!(index.get("KP-T-1").unwrap().unwrap().status, "processing");
+    }
+
+    #[test]
+    fn completions_survive_a_reopen_and_are_read_latest_first() {
+        let path = std::env::temp_dir().join(format!("completions-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_string_lossy().into_owned();
+        let now = Utc::now();
+        {
+            let index = TransactionIndex::open(&path).unwrap();
+            for (n, bank) in ["opay", "gtbank", "opay", "opay"].iter().enumerate() {
+                let at = now - chrono::Duration::minutes(10 - n as i64);
+                index
+                    .insert_completion(&format!("KP-C-{}", n), bank, 60 * (n as i64 + 1), at)
+                    .unwrap();
+            }
+            index
+                .insert_completion("KP-C-OLD", "opay", 999, now - chrono::Duration::days(2))
+                .unwrap();
+        }
+
+        let index = TransactionIndex::open(&path).unwrap();
+        let since = now - chrono::Duration::days(1);
+        assert_eq!(
+            index.completion_seconds(None, since, 50).unwrap(),
+            [240, 180, 120, 60]
+        );
+        assert_eq!(
+            index.completion_seconds(Some("opay"), since, 50).unwrap(),
+            [240, 180, 60]
+        );
+        assert_eq!(
+            index.completion_seconds(Some("opay"), since, 2).unwrap(),
+            [240, 180]
+        );
+
+        index.prune(since).unwrap();
+        assert_eq!(
+            index
+                .completion_seconds(None, now - chrono::Duration::days(3), 50)
+                .unwrap()
+                .len(),
+            4
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,516 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as Engine};
+use reqwest::{StatusCode, header::RETRY_AFTER};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{self, AuditEvent};
+use crate::config;
+use crate::keywords;
+use crate::message_log;
+use crate::outbound::{self, ContentTemplate};
+use crate::prefs;
+use crate::privacy::mask_phone;
+use crate::window::{self, Notice};
+
+/// A message `capture` kept instead of sending.
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedMessage {
+    pub to: String,
+    pub body: String,
+    pub template_sid: Option<String>,
+}
+
+type Capture = Arc<Mutex<Vec<CapturedMessage>>>;
+
+tokio::task_local! {
+    static CAPTURE: Capture;
+}
+
+/// Runs `future` with everything it sends kept rather than sent, and returns the
+/// messages in order. Work it spawns through `with_capture` is given up to `settle` to
+/// finish first.
+pub async fn capture<F: Future>(future: F, settle: Duration) -> (F::Output, Vec<CapturedMessage>) {
+    let sink = Capture::default();
+    let output = CAPTURE.scope(sink.clone(), future).await;
+
+    let deadline = Instant::now() + settle;
+    while Arc::strong_count(&sink) > 1 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let messages = std::mem::take(&mut *sink.lock().unwrap_or_else(|e| e.into_inner()));
+    (output, messages)
+}
+
+/// Carries the current task's `capture`, if any, into `future` before it's spawned.
+pub fn with_capture<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let sink = CAPTURE.try_with(Arc::clone).ok();
+    async move {
+        match sink {
+            Some(sink) => CAPTURE.scope(sink, future).await,
+            None => future.await,
+        }
+    }
+}
+
+fn captured(to: &str, message: &str, content: Option<&ContentTemplate>) -> bool {
+    CAPTURE
+        .try_with(|sink| {
+            sink.lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(CapturedMessage {
+                    to: to.to_string(),
+                    body: message.to_string(),
+                    template_sid: content.map(|template| template.sid.clone()),
+                })
+        })
+        .is_ok()
+}
+
+/// What Twilio's Messages API returns for a message it accepted.
+#[derive(Debug, Deserialize)]
+pub struct TwilioMessageResponse {
+    pub sid: String,
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+/// What it returns instead when it refuses one.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TwilioErrorResponse {
+    #[serde(default)]
+    pub code: Option<u32>,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub more_info: Option<String>,
+}
+
+/// Why a message didn't go out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SendError {
+    /// 21610, or STOP on our side: the user unsubscribed.
+    OptedOut,
+    /// 63016: freeform outside the 24-hour window; only a template gets through.
+    OutsideWindow,
+    /// 20429: still too many requests after backing off.
+    RateLimited,
+    /// 21211: Twilio doesn't consider the number a valid recipient.
+    InvalidNumber,
+    /// Any other code Twilio reported.
+    Twilio {
+        code: Option<u32>,
+        message: String,
+        more_info: Option<String>,
+    },
+    /// Not configured, unreachable, or a reply we couldn't read.
+    Other(String),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::OptedOut => write!(f, "Recipient has opted out"),
+            SendError::OutsideWindow => write!(f, "Recipient is outside the 24h window"),
+            SendError::RateLimited => write!(f, "Rate limited by Twilio"),
+            SendError::InvalidNumber => write!(f, "Recipient is not a valid number"),
+            SendError::Twilio {
+                code,
+                message,
+                more_info,
+            } => {
+                match code {
+                    Some(code) => write!(f, "Twilio error {}: {}", code, message)?,
+                    None => write!(f, "Twilio error: {}", message)?,
+                }
+                match more_info {
+                    Some(more_info) => write!(f, " ({})", more_info),
+                    None => Ok(()),
+                }
+            }
+            SendError::Other(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<TwilioErrorResponse> for SendError {
+    fn from(error: TwilioErrorResponse) -> Self {
+        match error.code {
+            Some(21610) => SendError::OptedOut,
+            Some(63016) => SendError::OutsideWindow,
+            Some(20429) => SendError::RateLimited,
+            Some(21211) => SendError::InvalidNumber,
+            code => SendError::Twilio {
+                code,
+                message: error.message,
+                more_info: error.more_info,
+            },
+        }
+    }
+}
+
+/// Tries for a rate-limited send, doubling the wait from one second each time unless
+/// Twilio's `Retry-After` says otherwise.
+const RATE_LIMIT_ATTEMPTS: u32 = 3;
+
+/// One POST to Twilio. A message it accepted but whose reply we couldn't read is still
+/// sent, just without a SID.
+async fn post_message(
+    request: reqwest::RequestBuilder,
+) -> Result<Option<TwilioMessageResponse>, (SendError, Option<Duration>)> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| (SendError::Other(e.to_string()), None))?;
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs);
+    let body = response.text().await.unwrap_or_default();
+
+    if status.is_success() {
+        return Ok(serde_json::from_str(&body)
+            .inspect_err(|e| eprintln!("WARN: Unreadable Twilio reply to a sent message: {}", e))
+            .ok());
+    }
+    let error = match serde_json::from_str::<TwilioErrorResponse>(&body) {
+        Ok(error) => SendError::from(error),
+        Err(_) if status == StatusCode::TOO_MANY_REQUESTS => SendError::RateLimited,
+        Err(_) => SendError::Other(format!("Twilio returned {}", status)),
+    };
+    Err((error, retry_after))
+}
+
+/// Acts on what a refusal says about the recipient, so the next send doesn't repeat it.
+async fn handle_refusal(to: &str, error: &SendError) {
+    match error {
+        SendError::OptedOut => keywords::record_opt_out_event(to, true).await,
+        SendError::OutsideWindow => window::record_window_closed(to),
+        SendError::InvalidNumber => {
+            if let Err(e) = prefs::update(to, |prefs| prefs.invalid_number = true).await {
+                eprintln!(
+                    "Failed to flag {} as an invalid number: {}",
+                    mask_phone(to),
+                    e
+                );
+            }
+            eprintln!(
+                "ALERT: Twilio rejected {} as an invalid number; not messaging it again until it writes in",
+                mask_phone(to)
+            );
+        }
+        SendError::RateLimited | SendError::Twilio { .. } | SendError::Other(_) => {}
+    }
+}
+
+pub async fn send_twilio_message(to: &str, message: &str) {
+    send_twilio_content(to, message, None).await;
+}
+
+/// Sends a message we started rather than a reply: freeform inside the 24-hour window,
+/// the notice's approved template outside it, and nothing (logged) if there's no template.
+pub async fn send_notice(to: &str, message: &str, notice: Notice) {
+    let prefs = prefs::load(to).await;
+    if !notice.is_essential() && prefs.notifications_off {
+        println!(
+            "Not sending {:?} to {}: notifications are off",
+            notice,
+            mask_phone(to)
+        );
+        return;
+    }
+
+    let plain;
+    let message = if prefs.plain_text {
+        plain = outbound::plain_text(message);
+        plain.as_str()
+    } else {
+        message
+    };
+
+    match window::delivery_for(to, notice, message) {
+        Ok(None) => {
+            println!("Sending {:?} to {} as freeform", notice, mask_phone(to));
+            let _lane = outbound::lane(to).await;
+            // Our record of the window can be behind WhatsApp's; the template still gets through
+            if try_send_twilio_content(to, message, None).await == Err(SendError::OutsideWindow) {
+                match window::delivery_for(to, notice, message) {
+                    Ok(Some(template)) => {
+                        println!(
+                            "Retrying {:?} to {} as template {}",
+                            notice,
+                            mask_phone(to),
+                            template.sid
+                        );
+                        let _ = try_send_twilio_content(to, message, Some(&template)).await;
+                    }
+                    Ok(None) => {}
+                    Err(e) => eprintln!("Not retrying {:?}: {}", notice, e),
+                }
+            }
+        }
+        Ok(Some(template)) => {
+            println!(
+                "{} is outside the 24h window; sending {:?} as template {}",
+                mask_phone(to),
+                notice,
+                template.sid
+            );
+            send_twilio_content(to, message, Some(&template)).await;
+        }
+        Err(e) => {
+            eprintln!("Not sending {:?}: {}", notice, e);
+            message_log::record_sent(to, message, None, "skipped_window");
+        }
+    }
+}
+
+/// Form fields for Twilio's Messages API: a content template with its variables as JSON
+/// when one is given, otherwise the plain body.
+fn twilio_message_form(
+    from: &str,
+    to: &str,
+    body: &str,
+    content: Option<&ContentTemplate>,
+) -> Vec<(&'static str, String)> {
+    let mut form = vec![("From", from.to_string()), ("To", to.to_string())];
+    match content {
+        Some(template) => {
+            form.push(("ContentSid", template.sid.clone()));
+            form.push((
+                "ContentVariables",
+                serde_json::to_string(&template.variables).unwrap_or_default(),
+            ));
+        }
+        None => form.push(("Body", body.to_string())),
+    }
+    form
+}
+
+/// Sends `content` when given, else `message`. The log records `message` either way.
+/// Waits behind anything already going out to `to`; see `outbound::lane`.
+pub async fn send_twilio_content(to: &str, message: &str, content: Option<&ContentTemplate>) {
+    let _lane = outbound::lane(to).await;
+    // Failures are already logged and audited by the time they come back
+    let _ = try_send_twilio_content(to, message, content).await;
+}
+
+/// `send_twilio_content` for callers that need to know whether Twilio took the message,
+/// and its SID when there is one. Doesn't take the user's lane, so callers already
+/// holding it can use it too.
+pub async fn try_send_twilio_content(
+    to: &str,
+    message: &str,
+    content: Option<&ContentTemplate>,
+) -> Result<Option<String>, SendError> {
+    if captured(to, message, content) {
+        return Ok(None);
+    }
+    if keywords::opted_out(to).await {
+        println!("Not sending to {}: opted out", mask_phone(to));
+        message_log::record_sent(to, message, None, "skipped_opted_out");
+        return Err(SendError::OptedOut);
+    }
+    if prefs::load(to).await.invalid_number {
+        println!("Not sending to {}: not a valid number", mask_phone(to));
+        message_log::record_sent(to, message, None, "skipped_invalid_number");
+        return Err(SendError::InvalidNumber);
+    }
+
+    let _sending = outbound::track_send();
+    if outbound::dry_run() {
+        match content {
+            Some(content) => println!("📤 To {} (template {}):\n{}\n", to, content.sid, message),
+            None => println!("📤 To {}:\n{}\n", to, message),
+        }
+        message_log::record_sent(to, message, None, "dry_run");
+        return Ok(None);
+    }
+
+    let (account_sid, auth_token, from_number, url) = match (
+        config::secret("T_ACCOUNT_SID"),
+        config::secret("T_AUTH_TOKEN"),
+        std::env::var("T_WHATSAPP_NUMBER"),
+        std::env::var("T_API_URL"),
+    ) {
+        (Ok(Some(sid)), Ok(Some(token)), Ok(from), Ok(url)) => (sid, token, from, url),
+        _ => {
+            eprintln!(
+                "Cannot send message to {}: Twilio credentials are not configured",
+                to
+            );
+            return Err(SendError::Other(
+                "Twilio credentials are not configured".to_string(),
+            ));
+        }
+    };
+
+    let to_whatsapp = if to.starts_with("whatsapp:") {
+        to.to_string()
+    } else if to.starts_with("+") {
+        format!("whatsapp:{}", to)
+    } else {
+        format!("whatsapp:+{}", to)
+    };
+
+    let auth_string = format!("{}:{}", account_sid, auth_token);
+    let auth_encoded = Engine.encode(auth_string);
+
+    let form_data = twilio_message_form(&from_number, &to_whatsapp, message, content);
+
+    let client = reqwest::Client::new();
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 1;
+    let error = loop {
+        let request = client
+            .post(&url)
+            .header("Authorization", format!("Basic {}", auth_encoded))
+            .form(&form_data);
+        match post_message(request).await {
+            Ok(sent) => {
+                let (sid, status) = match sent {
+                    Some(sent) => (Some(sent.sid), sent.status),
+                    None => (None, None),
+                };
+                message_log::record_sent(
+                    to,
+                    message,
+                    sid.clone(),
+                    status.as_deref().unwrap_or("queued"),
+                );
+                return Ok(sid);
+            }
+            Err((SendError::RateLimited, retry_after)) if attempt < RATE_LIMIT_ATTEMPTS => {
+                let wait = retry_after.unwrap_or(backoff).min(Duration::from_secs(30));
+                println!(
+                    "Rate limited sending to {}; retrying in {}s",
+                    mask_phone(to),
+                    wait.as_secs()
+                );
+                tokio::time::sleep(wait).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err((error, _)) => break error,
+        }
+    };
+
+    eprintln!("Failed to send message to {}: {}", mask_phone(to), error);
+    handle_refusal(to, &error).await;
+    message_log::record_sent(to, message, None, "failed");
+    audit::record(
+        to,
+        None,
+        None,
+        AuditEvent::MessageSendFailed {
+            error: error.to_string(),
+        },
+    );
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const ACCEPTED: &str = include_str!("../tests/fixtures/twilio/send_accepted.json");
+
+    fn refusal(name: &str) -> SendError {
+        let body = match name {
+            "opted_out" => include_str!("../tests/fixtures/twilio/send_opted_out.json"),
+            "outside_window" => include_str!("../tests/fixtures/twilio/send_outside_window.json"),
+            "rate_limited" => include_str!("../tests/fixtures/twilio/send_rate_limited.json"),
+            "invalid_number" => include_str!("../tests/fixtures/twilio/send_invalid_number.json"),
+            "unknown_code" => include_str!("../tests/fixtures/twilio/send_unknown_code.json"),
+            _ => unreachable!("no fixture {}", name),
+        };
+        serde_json::from_str::<TwilioErrorResponse>(body)
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn a_template_goes_out_as_its_sid_and_variables_instead_of_the_body() {
+        let template = ContentTemplate {
+            sid: "HXbanks".to_string(),
+            variables: BTreeMap::from([
+                ("1".to_string(), "Choose a bank".to_string()),
+                ("2".to_string(), "Opay ******6789".to_string()),
+            ]),
+        };
+        let form = twilio_message_form(
+            "whatsapp:+14155238886",
+            "whatsapp:+2348012345678",
+            "1. Opay ******6789",
+            Some(&template),
+        );
+        assert_eq!(
+            form,
+            [
+                ("From", "whatsapp:+14155238886".to_string()),
+                ("To", "whatsapp:+2348012345678".to_string()),
+                ("ContentSid", "HXbanks".to_string()),
+                (
+                    "ContentVariables",
+                    r#"{"1":"Choose a bank","2":"Opay ******6789"}"#.to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_text_goes_out_as_the_body() {
+        let form = twilio_message_form(
+            "whatsapp:+14155238886",
+            "whatsapp:+2348012345678",
+            "hello",
+            None,
+        );
+        assert_eq!(form[2], ("Body", "hello".to_string()));
+        assert!(!form.iter().any(|(field, _)| field.starts_with("Content")));
+    }
+
+    #[test]
+    fn an_accepted_message_carries_its_sid() {
+        let sent: TwilioMessageResponse = serde_json::from_str(ACCEPTED).unwrap();
+        assert_eq!(sent.sid, "SM0123456789abcdef0123456789abcdef");
+        assert_eq!(sent.status.as_deref(), Some("queued"));
+    }
+
+    #[test]
+    fn each_well_known_code_is_its_own_error() {
+        assert_eq!(refusal("opted_out"), SendError::OptedOut);
+        assert_eq!(refusal("outside_window"), SendError::OutsideWindow);
+        assert_eq!(refusal("rate_limited"), SendError::RateLimited);
+        assert_eq!(refusal("invalid_number"), SendError::InvalidNumber);
+    }
+
+    #[test]
+    fn any_other_code_keeps_its_details_for_the_log() {
+        let error = refusal("unknown_code");
+        assert!(matches!(
+            &error,
+            SendError::Twilio { code: Some(21408), message, .. }
+                if message.starts_with("Permission to send an SMS has not been enabled")
+        ));
+        let logged = error.to_string();
+        assert!(logged.starts_with("Twilio error 21408: "), "{}", logged);
+        assert!(
+            logged.ends_with(" (https://www.twilio.com/docs/errors/21408)"),
+            "{}",
+            logged
+        );
+
+        // A reply without a code or link still reads
+        let bare: SendError = serde_json::from_str::<TwilioErrorResponse>(r#"{"message": "Oops"}"#)
+            .unwrap()
+            .into();
+        assert_eq!(bare.to_string(), "Twilio error: Oops");
+    }
+}
@@ -0,0 +1,274 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use chrono::Utc;
+
+use crate::access::{self, Access};
+use crate::access_log::{Handling, WebhookNote};
+use crate::alerts;
+use crate::build_info;
+use crate::conversation::{dispatch_message, resume_restored};
+use crate::features;
+use crate::i18n;
+use crate::inbound::{self, Inbound};
+use crate::inflight;
+use crate::keywords;
+use crate::message_log;
+use crate::metrics;
+use crate::model::{UserSessions, UserState};
+use crate::outbound::{self, OutboundMessage};
+use crate::parser::parse_command;
+use crate::prefs;
+use crate::privacy::mask_phone;
+use crate::reporting::{self, ReportContext};
+use crate::request_body::TwilioForm;
+use crate::state::StateMachine;
+use crate::store::{self, SessionStore};
+use crate::supervisor;
+use crate::throttle::{self, Verdict};
+use crate::twilio::{self, send_twilio_message};
+use crate::window;
+
+pub async fn health_check() -> actix_web::Result<HttpResponse> {
+    let tasks = supervisor::snapshot();
+    let any_dead = tasks
+        .values()
+        .any(|task| task.status == supervisor::TaskStatus::Dead);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": if any_dead { "degraded" } else { "healthy" },
+        "service": "Kharon Pay WhatsApp Bot",
+        "version": build_info::VERSION,
+        "git_sha": build_info::GIT_SHA,
+        "uptime_secs": build_info::uptime_secs(),
+        "flags": build_info::flags(),
+        "feature_flags": features::summary(),
+        "tasks": tasks,
+    })))
+}
+
+/// Acknowledges a webhook without replying through TwiML; replies go out via the API.
+pub fn empty_twiml() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/xml")
+        .body("<?xml version=\"1.0\" encoding=\"UTF-8\"?><Response></Response>")
+}
+
+pub async fn handle_twilio_webhook(
+    req: HttpRequest,
+    TwilioForm(form_data): TwilioForm,
+    sessions: web::Data<dyn SessionStore>,
+) -> Result<HttpResponse> {
+    let note = WebhookNote::from_request(&req);
+    note.handling(Handling::Dropped);
+
+    let inbound = inbound::classify(&form_data);
+    metrics::INBOUND.inc(&[inbound.kind()]);
+    let (user_phone, body_text) = match inbound {
+        Inbound::Text { phone, body } | Inbound::Interactive { phone, body } => (phone, body),
+        Inbound::StatusCallback {
+            message_sid,
+            status,
+            record,
+        } => {
+            note.kind("status_callback");
+            note.handling(Handling::Inline);
+            if record && let Some(message_sid) = message_sid {
+                message_log::record_status(&message_sid, &status);
+            }
+            return Ok(empty_twiml());
+        }
+        Inbound::Media { phone } => {
+            note.phone(&phone);
+            note.kind("media");
+            return Ok(empty_twiml());
+        }
+        Inbound::OptOut { phone, opted_out } => {
+            note.phone(&phone);
+            note.kind("opt_out_event");
+            note.handling(Handling::Inline);
+            keywords::record_opt_out_event(&phone, opted_out).await;
+            return Ok(empty_twiml());
+        }
+        Inbound::Unknown(unknown) => {
+            if let Some(phone) = &unknown.phone {
+                note.phone(phone);
+            }
+            note.kind("unknown");
+            inbound::report_unknown(&unknown);
+            return Ok(empty_twiml());
+        }
+    };
+    note.phone(&user_phone);
+    if !inbound::first_delivery(&form_data) {
+        note.kind("duplicate");
+        return Ok(empty_twiml());
+    }
+
+    // Prevent loops - ignore messages from our own bot number
+    let bot_number = std::env::var("T_WHATSAPP_NUMBER").unwrap_or_default();
+    if user_phone.replace("+", "") == bot_number.replace("whatsapp:", "").replace("+", "") {
+        note.kind("own_number");
+        return Ok(empty_twiml());
+    }
+
+    match throttle::check(&user_phone, &body_text) {
+        Verdict::Allow => {
+            // Its own task, so a request deadline (see `deadline`) stops waiting for the
+            // message without abandoning it halfway
+            let task_note = note.clone();
+            let work = tokio::spawn(twilio::with_capture(async move {
+                handle_message(&user_phone, &body_text, sessions, &task_note).await;
+            }));
+            let _ = work.await;
+            note.handling(Handling::Inline);
+        }
+        Verdict::Muted => note.kind("throttled"),
+        Verdict::Tripped(reason) => {
+            note.kind("throttled");
+            tokio::spawn(async move { alerts::spam_detected(&user_phone, &reason).await });
+        }
+    }
+
+    Ok(empty_twiml())
+}
+
+pub async fn handle_message(
+    user_phone: &str,
+    message_text: &str,
+    sessions: web::Data<dyn SessionStore>,
+    note: &WebhookNote,
+) {
+    match access::check(user_phone, message_text) {
+        Access::Allowed => {}
+        Access::Blocked => {
+            note.kind("blocked");
+            println!(
+                "Ignoring message from denylisted {}",
+                mask_phone(user_phone)
+            );
+            return;
+        }
+        Access::Waitlisted => {
+            note.kind("waitlisted");
+            send_twilio_message(user_phone, access::WAITLIST_MESSAGE).await;
+            return;
+        }
+    }
+
+    if let Some(keyword) = keywords::detect(message_text) {
+        note.kind("channel_keyword");
+        keywords::handle(user_phone, keyword, sessions.clone().into_inner()).await;
+        return;
+    }
+    // Anything we did for them would go unanswered, so do nothing until they opt back in
+    if keywords::opted_out(user_phone).await {
+        note.kind("opted_out");
+        println!("Ignoring message from opted-out {}", mask_phone(user_phone));
+        return;
+    }
+
+    // Held until the replies are sent, so a quick "yes" after "confirm" waits for the
+    // confirmation's outcome instead of racing it on the same session
+    let Some(_turn) = inflight::conversation_turn(user_phone).await else {
+        note.kind("queue_full");
+        println!(
+            "Still handling earlier messages from {}; not queueing another",
+            mask_phone(user_phone)
+        );
+        send_twilio_message(user_phone, inflight::STILL_WORKING_MESSAGE).await;
+        return;
+    };
+
+    let store = sessions.clone().into_inner();
+    let phone = user_phone.to_string();
+    let mut session = match store::blocking(move || store.load(&phone)).await {
+        Ok(Some(session)) => session,
+        Ok(None) => UserSessions::new(user_phone),
+        Err(err) => {
+            note.kind("session_error");
+            eprintln!("Failed to load session for {}: {}", user_phone, err);
+            send_twilio_message(
+                user_phone,
+                "⚠️ We're having trouble right now. Please try again in a moment.",
+            )
+            .await;
+            return;
+        }
+    };
+
+    session.prefs = prefs::load(user_phone).await;
+    // Only what this message changes is saved, so an opt-out recorded meanwhile survives
+    let prefs_before = session.prefs.clone();
+    // Preferences only moved out of the session in v11; older sessions still carry them
+    session.take_legacy_preferences();
+
+    session.record_inbound(message_text);
+    let received_at = Utc::now();
+    session.last_inbound_at = Some(received_at);
+    window::record_inbound(user_phone, received_at);
+    // They just wrote to us, so the number works whatever Twilio said before
+    session.prefs.invalid_number = false;
+    if session.prefs.language.is_none()
+        && let Some(language) = i18n::detect(message_text)
+    {
+        session.prefs.language = Some(language);
+        session.prefs.language_detected = true;
+    }
+
+    let trigger = match session.state {
+        UserState::Initial => format!("command:{}", parse_command(message_text).name()),
+        state => format!("reply:{:?}", state),
+    };
+    note.kind(trigger.clone());
+
+    // Run the handler as its own task so a panic inside it can't take the conversation down
+    let message_owned = message_text.to_string();
+    let mut working_session = session.clone();
+    let context = ReportContext::for_session(&session).with_trigger(&trigger);
+    let outcome = tokio::spawn(twilio::with_capture(reporting::scoped(
+        context,
+        async move {
+            let messages = if working_session.restored {
+                resume_restored(&message_owned, &mut working_session).await
+            } else {
+                dispatch_message(&message_owned, &mut working_session).await
+            };
+            (working_session, messages)
+        },
+    )))
+    .await;
+
+    let (mut session, messages) = match outcome {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!(
+                "Message handler for {} failed in state {:?}: {}",
+                user_phone, session.state, err
+            );
+            let mut session = session;
+            StateMachine::reset(&mut session);
+            (
+                session,
+                vec!["⚠️ Something went wrong while processing your request.\n\nType `hi` to start again.".into()],
+            )
+        }
+    };
+
+    if let Some(reply) = messages.first() {
+        session.record_outcome(&reply.body);
+    }
+    let store = sessions.clone().into_inner();
+    let saved = session.clone();
+    if let Err(err) = store::blocking(move || store.save(&saved)).await {
+        eprintln!("Failed to save session for {}: {}", user_phone, err);
+    }
+    if let Err(err) = prefs::save_changes(&prefs_before, &session.prefs).await {
+        eprintln!("Failed to save preferences for {}: {}", user_phone, err);
+    }
+
+    let messages = if session.prefs.plain_text {
+        messages.into_iter().map(OutboundMessage::plain).collect()
+    } else {
+        messages
+    };
+    outbound::send_sequence(user_phone, &messages).await;
+}
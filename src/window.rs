@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{LazyLock, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::outbound::ContentTemplate;
+use crate::privacy::mask_phone;
+use crate::store;
+
+/// WhatsApp only allows freeform messages within this long of the user's last message.
+const SESSION_WINDOW: Duration = Duration::hours(24);
+
+/// Last inbound message per phone (digits only), in front of `UserSessions::last_inbound_at`.
+static LAST_INBOUND: LazyLock<Mutex<HashMap<String, DateTime<Utc>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn key(phone: &str) -> String {
+    phone.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+pub fn record_inbound(phone: &str, at: DateTime<Utc>) {
+    LAST_INBOUND
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key(phone), at);
+}
+
+/// Twilio says the window is closed (63016), whatever we last saw: treat it as closed
+/// until the user next writes in.
+pub fn record_window_closed(phone: &str) {
+    record_inbound(phone, Utc::now() - SESSION_WINDOW);
+}
+
+fn last_inbound(phone: &str) -> Option<DateTime<Utc>> {
+    let key = key(phone);
+    if let Some(at) = LAST_INBOUND
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&key)
+    {
+        return Some(*at);
+    }
+
+    // After a restart the map is empty, so fall back to the persisted session
+    let at = store::load_shared(&key)?.last_inbound_at?;
+    record_inbound(&key, at);
+    Some(at)
+}
+
+/// Whether a freeform message to `phone` would still be delivered.
+pub fn within_session_window(phone: &str) -> bool {
+    last_inbound(phone).is_some_and(|at| open_since(at, Utc::now()))
+}
+
+/// Open for the 24 hours after `last_inbound`, up to but not including the 24th.
+fn open_since(last_inbound: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    now - last_inbound < SESSION_WINDOW
+}
+
+/// Messages we start ourselves, which may land outside the window.
+#[derive(Debug, Clone, Copy)]
+pub enum Notice {
+    /// Withdrawal updates (progress, completion, failure or polling giving up) and
+    /// deposits arriving.
+    Completion,
+    /// Nudge about an abandoned flow.
+    Reminder,
+    /// Rating request after a completed withdrawal; never sent outside the window.
+    Feedback,
+    /// Pushed by the backend through `/notify`; `critical` ones ignore notifications off.
+    Announcement { critical: bool },
+}
+
+impl Notice {
+    /// Essential notices go out even to users who turned notifications off.
+    pub fn is_essential(self) -> bool {
+        match self {
+            Notice::Completion => true,
+            Notice::Announcement { critical } => critical,
+            Notice::Reminder | Notice::Feedback => false,
+        }
+    }
+
+    /// `T_COMPLETION_TEMPLATE_SID` / `T_REMINDER_TEMPLATE_SID` /
+    /// `T_ANNOUNCEMENT_TEMPLATE_SID`: approved templates whose variable 1 takes the
+    /// message text.
+    fn template_sid(self) -> Option<String> {
+        let var = match self {
+            Notice::Completion => "T_COMPLETION_TEMPLATE_SID",
+            Notice::Reminder => "T_REMINDER_TEMPLATE_SID",
+            Notice::Announcement { .. } => "T_ANNOUNCEMENT_TEMPLATE_SID",
+            Notice::Feedback => return None,
+        };
+        std::env::var(var).ok().filter(|sid| !sid.is_empty())
+    }
+}
+
+/// How to reach `phone` right now: `Ok(None)` to send freeform, `Ok(Some)` for the
+/// notice's template, or `Err` when the window has closed and no template is configured.
+pub fn delivery_for(
+    phone: &str,
+    notice: Notice,
+    message: &str,
+) -> Result<Option<ContentTemplate>, String> {
+    if within_session_window(phone) {
+        return Ok(None);
+    }
+
+    match notice.template_sid() {
+        Some(sid) => Ok(Some(ContentTemplate {
+            sid,
+            variables: BTreeMap::from([("1".to_string(), message.to_string())]),
+        })),
+        None => Err(format!(
+            "{} is outside the 24h window and no {:?} template is configured",
+            mask_phone(phone),
+            notice
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_window_closes_exactly_24_hours_after_the_last_message() {
+        let last = Utc::now();
+        assert!(open_since(last, last));
+        assert!(open_since(last, last + Duration::hours(23)));
+        assert!(open_since(
+            last,
+            last + SESSION_WINDOW - Duration::milliseconds(1)
+        ));
+        assert!(!open_since(last, last + SESSION_WINDOW));
+        assert!(!open_since(last, last + Duration::hours(25)));
+    }
+
+    #[test]
+    fn each_number_has_its_own_window() {
+        record_inbound("+2348090002301", Utc::now() - Duration::hours(1));
+        record_inbound("whatsapp:+2348090002302", Utc::now() - Duration::hours(25));
+        assert!(within_session_window("2348090002301"));
+        assert!(!within_session_window("+2348090002302"));
+        // Never heard from at all
+        assert!(!within_session_window("+2348090002303"));
+    }
+
+    #[test]
+    fn twilio_saying_the_window_closed_closes_it_until_they_write_again() {
+        let phone = "+2348090002304";
+        record_inbound(phone, Utc::now());
+        record_window_closed(phone);
+        assert!(!within_session_window(phone));
+        assert_eq!(
+            delivery_for(phone, Notice::Feedback, "How did we do?").unwrap_err(),
+            format!(
+                "{} is outside the 24h window and no Feedback template is configured",
+                mask_phone(phone)
+            )
+        );
+
+        record_inbound(phone, Utc::now());
+        assert!(within_session_window(phone));
+        assert!(
+            delivery_for(phone, Notice::Feedback, "How did we do?")
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! The denylist and allowlist, managed through the admin endpoints and checked on every
+//! message the webhook takes, with the bot in private-beta (`ACCESS_MODE=allowlist`) mode.
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{App, test as actix_test, web};
+use kharon_pay_whatsapp::access;
+use kharon_pay_whatsapp::request_body::FORM;
+use kharon_pay_whatsapp::store::{MemorySessionStore, SessionStore};
+use kharon_pay_whatsapp::twilio::{self, CapturedMessage};
+use kharon_pay_whatsapp::webhook;
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+
+const ADMIN_KEY: &str = "access-admin-key";
+
+async fn app() -> &'static str {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts, and before any test reads these
+        unsafe {
+            std::env::set_var("ADMIN_API_KEY", ADMIN_KEY);
+            std::env::set_var("ACCESS_MODE", "allowlist");
+        }
+    })
+    .await
+}
+
+/// Calls `/admin/{path}` with the admin key.
+async fn admin(method: reqwest::Method, path: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let base = app().await;
+    let mut request = reqwest::Client::new()
+        .request(method, format!("{}/admin/{}", base, path))
+        .header("x-admin-key", ADMIN_KEY);
+    if let Some(body) = body {
+        request = request.json(&body);
+    }
+    let response = request.send().await.expect("admin answers");
+    let status = response.status();
+    (status, response.json().await.unwrap_or(Value::Null))
+}
+
+async fn listed(list: &str) -> Vec<String> {
+    let (status, body) = admin(reqwest::Method::GET, list, None).await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_value(body["phones"].clone()).unwrap()
+}
+
+async fn add(list: &str, phone: &str) -> (StatusCode, Value) {
+    admin(reqwest::Method::POST, list, Some(json!({ "phone": phone }))).await
+}
+
+async fn remove(list: &str, phone: &str) -> StatusCode {
+    admin(
+        reqwest::Method::DELETE,
+        &format!("{}/{}", list, phone),
+        None,
+    )
+    .await
+    .0
+}
+
+/// What's on disk for `list`, as a restart would read it.
+fn persisted(list: &str) -> Vec<String> {
+    let contents = std::fs::read_to_string(access::access_list_path()).expect("lists persisted");
+    let lists: Value = serde_json::from_str(&contents).unwrap();
+    serde_json::from_value(lists[list].clone()).unwrap()
+}
+
+/// What the bot sends back when `phone` texts `body` to the webhook.
+async fn text(phone: &str, body: &str) -> Vec<CapturedMessage> {
+    app().await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let service = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::from(sessions))
+            .route("/webhook", web::post().to(webhook::handle_twilio_webhook)),
+    )
+    .await;
+    let payload = serde_urlencoded::to_string([
+        (
+            "MessageSid",
+            format!("SM{}{}", phone.trim_start_matches('+'), body.len()),
+        ),
+        ("From", format!("whatsapp:{}", phone)),
+        ("To", "whatsapp:+14155238886".to_string()),
+        ("Body", body.to_string()),
+    ])
+    .unwrap();
+    let req = actix_test::TestRequest::post()
+        .uri("/webhook")
+        .insert_header(("content-type", FORM))
+        .set_payload(payload)
+        .to_request();
+    let (_, sent) = twilio::capture(
+        actix_test::call_service(&service, req),
+        Duration::from_secs(2),
+    )
+    .await;
+    sent
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn list_changes_are_queryable_and_persisted() {
+    let phone = "+2348090001411";
+    // However the number is written, it's one entry
+    let (status, body) = add("blocklist", "whatsapp:+234 809 000 1411").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["added"], true);
+    let (_, body) = add("blocklist", "2348090001411").await;
+    assert_eq!(body["added"], false);
+    assert!(listed("blocklist").await.contains(&phone.to_string()));
+    assert!(persisted("denylist").contains(&phone.to_string()));
+    assert!(!listed("allowlist").await.contains(&phone.to_string()));
+
+    assert_eq!(remove("blocklist", phone).await, StatusCode::OK);
+    assert!(!listed("blocklist").await.contains(&phone.to_string()));
+    assert!(!persisted("denylist").contains(&phone.to_string()));
+    assert_eq!(remove("blocklist", phone).await, StatusCode::NOT_FOUND);
+
+    let (status, _) = add("allowlist", "no digits").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_lists_need_the_admin_key() {
+    let base = app().await;
+    let client = reqwest::Client::new();
+    let unkeyed = client
+        .post(format!("{}/admin/allowlist", base))
+        .json(&json!({ "phone": "+2348090001412" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unkeyed.status(), StatusCode::UNAUTHORIZED);
+    assert!(
+        !listed("allowlist")
+            .await
+            .contains(&"+2348090001412".to_string())
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_denylisted_number_gets_silence_until_removed() {
+    let phone = "+2348090001413";
+    add("allowlist", phone).await;
+    add("blocklist", phone).await;
+    for body in ["hi", "balance"] {
+        let sent = text(phone, body).await;
+        assert!(sent.is_empty(), "{}: {:?}", body, sent);
+    }
+
+    remove("blocklist", phone).await;
+    let sent = text(phone, "help").await;
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    assert_eq!(sent[0].to, phone);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_uninvited_number_is_waitlisted_but_can_still_say_hi() {
+    let phone = "+2348090001414";
+    let sent = text(phone, "balance").await;
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    assert_eq!(sent[0].body, access::WAITLIST_MESSAGE);
+
+    for body in ["hi", "help"] {
+        let sent = text(phone, body).await;
+        assert_eq!(sent.len(), 1, "{}: {:?}", body, sent);
+        assert_ne!(sent[0].body, access::WAITLIST_MESSAGE, "{}", body);
+    }
+
+    // Once invited, financial commands get through too
+    add("allowlist", phone).await;
+    let sent = text(phone, "balance").await;
+    assert!(
+        sent.iter()
+            .all(|message| message.body != access::WAITLIST_MESSAGE),
+        "{:?}",
+        sent
+    );
+}
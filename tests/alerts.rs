@@ -0,0 +1,123 @@
+//! Ops alerts for failed payouts, posted to a stand-in Slack webhook served in-process.
+
+mod common;
+
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use actix_web::{App, HttpResponse, HttpServer, web};
+use kharon_pay_whatsapp::alerts::{self, PayoutAlert};
+use kharon_pay_whatsapp::twilio;
+use serde_json::Value;
+
+const OPS_PHONE: &str = "+2348090000901";
+
+/// Everything posted to the stand-in webhook.
+static POSTED: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+
+/// Serves the stand-in webhook and points the alerts at it, once per test binary.
+fn slack() {
+    static STARTED: OnceLock<()> = OnceLock::new();
+    STARTED.get_or_init(|| {
+        common::setup();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+        let url = format!("http://{}/slack", listener.local_addr().expect("bound"));
+        // SAFETY: set before the server thread starts and before any alert is sent
+        unsafe {
+            std::env::set_var("OPS_SLACK_WEBHOOK_URL", url);
+            std::env::set_var("OPS_ALERT_PHONES", OPS_PHONE);
+        }
+        std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                HttpServer::new(|| {
+                    App::new().route(
+                        "/slack",
+                        web::post().to(|body: web::Json<Value>| async move {
+                            POSTED.lock().unwrap().push(body.into_inner());
+                            HttpResponse::Ok().body("ok")
+                        }),
+                    )
+                })
+                .listen(listener)
+                .expect("listening")
+                .disable_signals()
+                .run()
+                .await
+            })
+        });
+    });
+}
+
+fn posted_for(reference: &str) -> Vec<Value> {
+    POSTED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|post| post["alert"]["reference"] == reference)
+        .cloned()
+        .collect()
+}
+
+fn failed(reference: &str) -> PayoutAlert {
+    let mut alert = PayoutAlert::new(reference, "+2348090000902", "failed");
+    alert.amount = Some(15_000.0);
+    alert.currency = Some("NGN".to_string());
+    alert.bank_name = Some("Opay".to_string());
+    alert
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_failed_payout_posts_the_details_with_the_phone_masked() {
+    slack();
+    let (_, sent) =
+        twilio::capture(alerts::payout_failed(failed("KP-ALERT-1")), Duration::ZERO).await;
+
+    let posts = posted_for("KP-ALERT-1");
+    assert_eq!(posts.len(), 1, "{:?}", posts);
+    let alert = &posts[0]["alert"];
+    assert_eq!(alert["status"], "failed");
+    assert_eq!(alert["amount"], 15_000.0);
+    assert_eq!(alert["currency"], "NGN");
+    assert_eq!(alert["bank_name"], "Opay");
+    let phone = alert["phone"].as_str().unwrap();
+    assert_ne!(phone, "+2348090000902");
+    assert!(phone.ends_with("0902"), "{}", phone);
+
+    let text = posts[0]["text"].as_str().unwrap();
+    for expected in ["Payout failed", "KP-ALERT-1", "₦15,000.00", "Opay", phone] {
+        assert!(
+            text.contains(expected),
+            "{} missing from {}",
+            expected,
+            text
+        );
+    }
+    assert!(!text.contains("+2348090000902"), "{}", text);
+
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    assert_eq!(sent[0].to, OPS_PHONE);
+    assert_eq!(sent[0].body, text);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn repeated_failures_for_one_reference_alert_once() {
+    slack();
+    let (_, sent) = twilio::capture(
+        async {
+            for status in ["failed", "cancelled", "failed"] {
+                let mut alert = failed("KP-ALERT-2");
+                alert.status = status.to_string();
+                alerts::payout_failed(alert).await;
+            }
+            alerts::payout_failed(failed("KP-ALERT-3")).await;
+        },
+        Duration::ZERO,
+    )
+    .await;
+
+    assert_eq!(posted_for("KP-ALERT-2").len(), 1);
+    assert_eq!(posted_for("KP-ALERT-2")[0]["alert"]["status"], "failed");
+    // Another reference isn't held back by the first
+    assert_eq!(posted_for("KP-ALERT-3").len(), 1);
+    assert_eq!(sent.len(), 2, "{:?}", sent);
+}
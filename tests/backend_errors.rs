@@ -0,0 +1,105 @@
+//! What the user is told when the backend refuses a withdrawal, with the mock backend
+//! scripted to refuse it with each error code it might send.
+
+mod common;
+
+use std::time::Duration;
+
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::model::UserSessions;
+use kharon_pay_whatsapp::twilio;
+use serde_json::json;
+
+use common::mock_post;
+
+/// The reply to the final yes of a 10 USDT withdrawal to a saved bank, with the offramp
+/// refused as `offramp_error`.
+async fn refused_with(phone: &str, offramp_error: &str) -> String {
+    common::app().await;
+    let digits = phone.trim_start_matches('+');
+    mock_post(
+        "/banks",
+        json!({
+            "phone": digits,
+            "bank_save_id": format!("save-{}", digits),
+            "bank_name": "Opay",
+            "account_number": "0123456789",
+            "account_name": "ADA OBI",
+            "bank_code": "999",
+        }),
+    )
+    .await;
+    mock_post(
+        "/faults",
+        json!({ "phone": digits, "offramp_error": offramp_error }),
+    )
+    .await;
+
+    let mut session = UserSessions::new(phone);
+    session.controller_address = Some("0x0test".to_string());
+    let mut reply = String::new();
+    for message in ["withdraw 10 USDT", "confirm", "yes"] {
+        let (replies, _) =
+            twilio::capture(dispatch_message(message, &mut session), Duration::ZERO).await;
+        reply = replies[0].body.clone();
+    }
+    reply
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn each_known_error_code_is_explained_with_a_next_step() {
+    for (phone, code, expected) in [
+        (
+            "+2348090002401",
+            "INSUFFICIENT_BALANCE",
+            "Your balance is too low",
+        ),
+        (
+            "+2348090002402",
+            "INVALID_BANK_ACCOUNT",
+            "couldn't find that account",
+        ),
+        (
+            "+2348090002403",
+            "LIMIT_EXCEEDED",
+            "over your withdrawal limit",
+        ),
+        (
+            "+2348090002404",
+            "ACCOUNT_FROZEN",
+            "Your account is on hold",
+        ),
+        (
+            "+2348090002405",
+            "DUPLICATE_REQUEST",
+            "already working on this",
+        ),
+    ] {
+        let reply = refused_with(phone, code).await;
+        assert!(reply.contains(expected), "{}: {}", code, reply);
+        assert!(!reply.contains("Failed to initiate"), "{}: {}", code, reply);
+        assert!(!reply.contains("Support code"), "{}: {}", code, reply);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_codes_and_garbled_bodies_get_the_generic_reply_with_a_support_code() {
+    for (phone, offramp_error) in [
+        ("+2348090002406", "SOMETHING_NEW"),
+        ("+2348090002407", "garbled"),
+    ] {
+        let reply = refused_with(phone, offramp_error).await;
+        assert!(
+            reply.contains("Failed to initiate withdrawal."),
+            "{}: {}",
+            offramp_error,
+            reply
+        );
+        assert!(
+            reply.contains("Support code: `"),
+            "{}: {}",
+            offramp_error,
+            reply
+        );
+    }
+}
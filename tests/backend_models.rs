@@ -0,0 +1,130 @@
+//! Recorded backend replies read into the typed models, the way the handlers read them,
+//! including the drift we've seen and the error envelopes sent with a failure.
+
+mod common;
+
+use kharon_pay_whatsapp::backend::{self, ErrorEnvelope};
+use kharon_pay_whatsapp::model::{
+    AddressResponse, BalanceResponse, BankVerificationEnvelope, RateResponse,
+};
+use serde::de::DeserializeOwned;
+
+/// The recorded reply `name` as a response with `status`.
+fn response(status: u16, name: &str) -> reqwest::Response {
+    http::Response::builder()
+        .status(status)
+        .body(common::backend_fixture(name))
+        .expect("a response")
+        .into()
+}
+
+async fn parse<T: DeserializeOwned>(name: &str) -> Result<T, String> {
+    backend::parse_body(response(200, name), "Fixture").await
+}
+
+#[tokio::test]
+async fn rate() {
+    let rate: RateResponse = parse("rate").await.unwrap();
+    assert_eq!(rate.data.usd_ngn_rate, 1523.45);
+
+    let error = parse::<RateResponse>("rate_as_string").await.unwrap_err();
+    assert!(
+        error.starts_with("Unexpected Fixture response ("),
+        "{}",
+        error
+    );
+    assert!(error.contains("usd_ngn_rate"), "{}", error);
+}
+
+#[tokio::test]
+async fn balance() {
+    let balance: BalanceResponse = parse("balance").await.unwrap();
+    let data = balance.data.expect("data");
+    assert_eq!(data.balance.as_deref(), Some("250.000000"));
+    assert!(data.token.is_some_and(|token| token.starts_with("0x068f")));
+
+    // A wallet that has never held anything
+    let balance: BalanceResponse = parse("balance_never_funded").await.unwrap();
+    assert_eq!(balance.data.expect("data").balance, None);
+
+    let error = parse::<BalanceResponse>("balance_as_number")
+        .await
+        .unwrap_err();
+    assert!(error.contains("invalid type: floating point"), "{}", error);
+}
+
+#[tokio::test]
+async fn address() {
+    let address: AddressResponse = parse("address").await.unwrap();
+    let controller = address.data.and_then(|data| data.controller_address);
+    assert!(controller.is_some_and(|address| address.starts_with("0x04b1")));
+
+    // No account yet
+    let address: AddressResponse = parse("address_no_account").await.unwrap();
+    assert!(address.data.is_none());
+}
+
+#[tokio::test]
+async fn bank_verification() {
+    let verified: BankVerificationEnvelope = parse("bank_verification").await.unwrap();
+    assert_eq!(verified.data.account_name, "ADA OBI");
+    assert_eq!(verified.data.account_number, "0123456789");
+    assert_eq!(verified.data.bank_name, "Opay");
+    assert_eq!(verified.data.bank_code, "999992");
+
+    let error = parse::<BankVerificationEnvelope>("bank_verification_no_name")
+        .await
+        .unwrap_err();
+    assert!(error.contains("missing field `account_name`"), "{}", error);
+    // The snippet of the body in the log has its account number masked
+    assert!(!error.contains("0123456789"), "{}", error);
+}
+
+/// An error envelope where a model was expected fails to parse rather than reading as
+/// an empty success.
+#[tokio::test]
+async fn error_envelopes_are_not_successes() {
+    assert!(
+        parse::<RateResponse>("error_rate_unavailable")
+            .await
+            .is_err()
+    );
+    assert!(
+        parse::<BankVerificationEnvelope>("error_invalid_bank_account")
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn error_envelopes() {
+    for (status, name, code, message) in [
+        (
+            404,
+            "error_invalid_bank_account",
+            Some("INVALID_BANK_ACCOUNT"),
+            Some("Account 0123456789 not found at Opay"),
+        ),
+        (
+            503,
+            "error_rate_unavailable",
+            Some("RATE_UNAVAILABLE"),
+            Some("No rate from any provider"),
+        ),
+        (
+            500,
+            "error_without_code",
+            None,
+            Some("Internal server error"),
+        ),
+    ] {
+        let (read_status, envelope) = backend::read_error(response(status, name), "Fixture").await;
+        assert_eq!(read_status.as_u16(), status, "{}", name);
+        assert_eq!(envelope.error_code.as_deref(), code, "{}", name);
+        assert_eq!(envelope.message.as_deref(), message, "{}", name);
+    }
+
+    // A body that isn't an envelope reads as an empty one
+    let envelope = ErrorEnvelope::parse(&common::backend_fixture("rate"));
+    assert_eq!(envelope.error_code, None);
+}
@@ -0,0 +1,118 @@
+//! Saving a newly verified bank when the save times out, against the mock backend
+//! scripted to answer saves too late for a one-second deadline.
+
+mod common;
+
+use std::time::Duration;
+
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::twilio;
+use serde_json::{Value, json};
+
+use common::mock_post;
+
+async fn app() -> &'static str {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts, and before any test reads it
+        unsafe { std::env::set_var("BANK_TIMEOUT_SECS", "1") };
+    })
+    .await
+}
+
+async fn say(session: &mut UserSessions, message: &str) -> String {
+    let (replies, _) = twilio::capture(dispatch_message(message, session), Duration::ZERO).await;
+    replies[0].body.clone()
+}
+
+/// `phone` with its new Opay account verified, at the yes that saves it, with `faults`
+/// scripted on the backend.
+async fn at_the_new_bank_yes(phone: &str, faults: Value) -> UserSessions {
+    app().await;
+    let mut faults = faults;
+    faults["phone"] = json!(phone.trim_start_matches('+'));
+    mock_post("/faults", faults).await;
+
+    let mut session = UserSessions::new(phone);
+    session.controller_address = Some("0x0test".to_string());
+    for message in ["withdraw 10 USDT", "confirm", "Opay, 0123456789"] {
+        say(&mut session, message).await;
+    }
+    assert_eq!(session.state, UserState::BankDetailsConfirmation);
+    session
+}
+
+/// The banks the backend has saved for `phone`.
+async fn saved_banks(phone: &str) -> Vec<Value> {
+    let url = format!(
+        "{}/mock-backend/banks?phone={}",
+        app().await,
+        phone.trim_start_matches('+')
+    );
+    let body: Value = reqwest::get(url).await.unwrap().json().await.unwrap();
+    body["data"]["banks"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn initiated(reply: &str) -> bool {
+    reply.starts_with("✅ *Withdrawal Successfully Initiated!*")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_clean_save_goes_straight_on_to_the_withdrawal() {
+    let phone = "+2348090002501";
+    let mut session = at_the_new_bank_yes(phone, json!({})).await;
+
+    let reply = say(&mut session, "yes").await;
+    assert!(initiated(&reply), "{}", reply);
+    assert_eq!(saved_banks(phone).await.len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_save_that_times_out_once_is_retried_under_the_same_key() {
+    let phone = "+2348090002502";
+    let mut session = at_the_new_bank_yes(phone, json!({ "bank_save_stalls": 1 })).await;
+
+    let reply = say(&mut session, "yes").await;
+    assert!(initiated(&reply), "{}", reply);
+    // The late first attempt and the retry are one bank
+    assert_eq!(saved_banks(phone).await.len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_save_that_timed_out_but_landed_is_found_in_the_bank_list() {
+    let phone = "+2348090002503";
+    let mut session = at_the_new_bank_yes(phone, json!({ "bank_save_stalls": 2 })).await;
+
+    let reply = say(&mut session, "yes").await;
+    assert!(initiated(&reply), "{}", reply);
+    assert!(reply.contains("Opay"), "{}", reply);
+    assert_eq!(saved_banks(phone).await.len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_save_that_timed_out_and_was_lost_asks_to_try_again() {
+    let phone = "+2348090002504";
+    let mut session = at_the_new_bank_yes(
+        phone,
+        json!({ "bank_save_stalls": 2, "stalled_saves_lost": true }),
+    )
+    .await;
+
+    let reply = say(&mut session, "yes").await;
+    assert!(
+        reply.starts_with("⏳ We couldn't confirm your bank was saved."),
+        "{}",
+        reply
+    );
+    assert_eq!(session.state, UserState::BankDetailsConfirmation);
+    assert!(session.pending_bank_verification.is_some());
+    assert!(saved_banks(phone).await.is_empty());
+
+    // The backend has caught up, so trying again saves it once
+    let reply = say(&mut session, "yes").await;
+    assert!(initiated(&reply), "{}", reply);
+    assert_eq!(saved_banks(phone).await.len(), 1);
+}
@@ -0,0 +1,133 @@
+//! The build and uptime as `/version`, `/health` and the startup line show them, which
+//! deployment tooling and dashboards read, so their shape is checked key for key.
+
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+
+use serde_json::Value;
+
+async fn get(path: &str) -> Value {
+    let response = reqwest::get(format!("{}{}", common::app().await, path))
+        .await
+        .expect("answered");
+    assert_eq!(response.status(), 200);
+    response.json().await.expect("JSON")
+}
+
+fn keys(value: &Value) -> Vec<&str> {
+    let mut keys: Vec<&str> = value
+        .as_object()
+        .expect("an object")
+        .keys()
+        .map(String::as_str)
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// What `build.rs` embedded: a short commit, or "unknown" outside a checkout.
+fn assert_sha(sha: &Value) {
+    let sha = sha.as_str().expect("a string");
+    assert_eq!(sha, env!("GIT_SHA"));
+    assert!(
+        sha == "unknown" || (!sha.is_empty() && sha.chars().all(|c| c.is_ascii_hexdigit())),
+        "{}",
+        sha
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_is_the_build_and_nothing_else() {
+    let body = get("/version").await;
+    assert_eq!(keys(&body), ["git_sha", "version"]);
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert_sha(&body["git_sha"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn health_shows_the_build_uptime_and_flags() {
+    let body = get("/health").await;
+    for key in [
+        "status",
+        "service",
+        "version",
+        "git_sha",
+        "uptime_secs",
+        "flags",
+        "feature_flags",
+        "tasks",
+    ] {
+        assert!(body.get(key).is_some(), "{} missing from {}", key, body);
+    }
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert_sha(&body["git_sha"]);
+    assert!(body["uptime_secs"].is_u64(), "{}", body);
+
+    let flags = &body["flags"];
+    assert_eq!(
+        keys(flags),
+        [
+            "error_reporting",
+            "mock_backend",
+            "session_store",
+            "tls",
+            "twilio_dry_run"
+        ]
+    );
+    // The test app runs on the mock, sending nothing
+    assert_eq!(flags["mock_backend"], true);
+    assert_eq!(flags["twilio_dry_run"], true);
+    assert_eq!(flags["tls"], false);
+    assert!(flags["session_store"].is_string(), "{}", flags);
+    assert!(
+        ["sentry", "log"].contains(&flags["error_reporting"].as_str().unwrap()),
+        "{}",
+        flags
+    );
+
+    // Uptime only goes up
+    let later = get("/health").await;
+    assert!(later["uptime_secs"].as_u64() >= body["uptime_secs"].as_u64());
+}
+
+/// Killed when dropped, so a failed test doesn't leave it holding the port.
+struct Running(Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn the_startup_line_names_the_build_and_flags() {
+    let dir = common::scratch_dir().join("build-info");
+    std::fs::create_dir_all(&dir).expect("build-info dir");
+    // It serves on its fixed port, 6500
+    let mut bot = Running(
+        Command::new(env!("CARGO_BIN_EXE_kharon-pay-whatsapp"))
+            .env_clear()
+            .env("MOCK_BACKEND", "1")
+            .current_dir(dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("binary runs"),
+    );
+    let stdout = BufReader::new(bot.0.stdout.take().expect("stdout"));
+    let line = stdout
+        .lines()
+        .map_while(Result::ok)
+        .find(|line| line.contains("starting on port 6500"))
+        .expect("a startup line");
+
+    let build = format!("v{} ({})", env!("CARGO_PKG_VERSION"), env!("GIT_SHA"));
+    assert!(line.contains(&build), "{}", line);
+    let (_, flags) = line.split_once("flags: ").expect("flags");
+    let flags: Value = serde_json::from_str(flags).expect("flags as JSON");
+    assert_eq!(flags["mock_backend"], true);
+    assert_eq!(flags["twilio_dry_run"], true);
+}
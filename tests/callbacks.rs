@@ -0,0 +1,302 @@
+//! Signed backend callbacks, end to end through the routes.
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use chrono::Utc;
+use kharon_pay_whatsapp::i18n::{self, Language, Msg};
+use kharon_pay_whatsapp::transactions::{self, IndexedTransaction};
+use kharon_pay_whatsapp::window;
+use kharon_pay_whatsapp::{backend, cache};
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+const CALLBACK_KEY: &str = "test-callback-key";
+const ADMIN_KEY: &str = "test-admin-key";
+
+async fn app() -> &'static str {
+    common::app_with(|| {
+        // SAFETY: set before the server thread starts, and only read by it
+        unsafe {
+            std::env::set_var("CALLBACK_HMAC_KEY", CALLBACK_KEY);
+            std::env::set_var("ADMIN_API_KEY", ADMIN_KEY);
+        }
+    })
+    .await
+}
+
+fn nonce() -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    format!("callback-test-{}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// POSTs `body` to `path`, signed under `key` the way the backend signs callbacks.
+async fn post_signed(key: &str, path: &str, body: &Value) -> (StatusCode, Value) {
+    let body = serde_json::to_vec(body).unwrap();
+    let timestamp = Utc::now().timestamp().to_string();
+    let nonce = nonce();
+    let signature = backend::sign(key, "POST", path, &timestamp, &nonce, &body);
+    let response = reqwest::Client::new()
+        .post(format!("{}{}", app().await, path))
+        .header("content-type", "application/json")
+        .header("x-timestamp", timestamp)
+        .header("x-nonce", nonce)
+        .header("x-signature", signature)
+        .body(body)
+        .send()
+        .await
+        .expect("app answers");
+    let status = response.status();
+    (status, response.json().await.unwrap_or(Value::Null))
+}
+
+async fn index(reference: &str, status: &str) {
+    transactions::record(&IndexedTransaction {
+        reference: reference.to_string(),
+        phone: "+2348030000001".to_string(),
+        amount: 10.0,
+        token: "USDT".to_string(),
+        bank_name: "Opay".to_string(),
+        account_name: "ADA OBI".to_string(),
+        masked_account: "6789".to_string(),
+        initiated_at: Utc::now(),
+        status: status.to_string(),
+        status_updated_at: Utc::now(),
+        correlation_id: None,
+        tx_hash: None,
+        language: Language::En,
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn callbacks_signed_with_another_key_are_refused() {
+    let body = json!({"reference": "KP-CB-ANY"});
+    let (status, reply) = post_signed("wrong-key", "/callbacks/transaction-status", &body).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(reply["success"], false);
+
+    // The backend's own request key isn't good for callbacks either
+    let backend_key = backend::hmac_keys().primary.clone();
+    let (status, _) = post_signed(&backend_key, "/callbacks/deposit", &body).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unsigned_callbacks_are_refused() {
+    let response = reqwest::Client::new()
+        .post(format!("{}/callbacks/deposit", app().await))
+        .json(&json!({"phone": "+2348030000001", "token": "USDT", "amount": "5.0"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_callbacks_are_not_found_once_signed() {
+    let (status, _) = post_signed(CALLBACK_KEY, "/callbacks/nope", &json!({})).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_status_callback_for_a_pending_withdrawal_starts_polling() {
+    index("KP-CB-PENDING", "processing").await;
+    let body = json!({"reference": "KP-CB-PENDING"});
+    let (status, reply) = post_signed(CALLBACK_KEY, "/callbacks/transaction-status", &body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(reply["polling"], "started");
+
+    // The poller is registered by then, so the next callback only wakes it
+    let (_, reply) = post_signed(CALLBACK_KEY, "/callbacks/transaction-status", &body).await;
+    assert_eq!(reply["polling"], "woken");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_status_callback_for_a_settled_withdrawal_does_nothing() {
+    index("KP-CB-DONE", "completed").await;
+    let body = json!({"reference": "KP-CB-DONE"});
+    let (status, reply) = post_signed(CALLBACK_KEY, "/callbacks/transaction-status", &body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(reply["polling"], "already_final");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_status_callback_for_an_unknown_reference_is_not_found() {
+    let body = json!({"reference": "KP-CB-UNKNOWN"});
+    let (status, reply) = post_signed(CALLBACK_KEY, "/callbacks/transaction-status", &body).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(reply["success"], false);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn deposit_callbacks_are_checked_and_accepted() {
+    cache::store_balance("+2348030000002", "USDT", "💰 before the deposit");
+    let deposit = json!({"phone": "+2348030000002", "token": "USDT", "amount": "12.5"});
+    let (status, reply) = post_signed(CALLBACK_KEY, "/callbacks/deposit", &deposit).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(reply["success"], true);
+    // The next `balance` goes to the backend
+    assert!(cache::cached_balance("+2348030000002", "USDT").is_none());
+
+    let zero = json!({"phone": "+2348030000002", "token": "USDT", "amount": "0"});
+    let (status, _) = post_signed(CALLBACK_KEY, "/callbacks/deposit", &zero).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let no_phone = json!({"phone": "nobody", "token": "USDT", "amount": "1.0"});
+    let (status, _) = post_signed(CALLBACK_KEY, "/callbacks/deposit", &no_phone).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+async fn reload_keys(admin_key: &str) -> (StatusCode, Value) {
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/keys/reload", app().await))
+        .header("x-admin-key", admin_key)
+        .send()
+        .await
+        .expect("app answers");
+    let status = response.status();
+    (status, response.json().await.unwrap_or(Value::Null))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_rotated_key_is_picked_up_without_a_restart_and_the_old_one_still_works() {
+    let deposit = json!({"phone": "+2348030000003", "token": "USDT", "amount": "1.0"});
+    let next_key = "test-callback-key-next";
+    let (status, _) = post_signed(next_key, "/callbacks/deposit", &deposit).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // The old key stays the secondary, so every other test here keeps signing with it
+    // SAFETY: only read by the reload below
+    unsafe {
+        std::env::set_var("CALLBACK_HMAC_KEY", next_key);
+        std::env::set_var("CALLBACK_HMAC_KEY_SECONDARY", CALLBACK_KEY);
+    }
+    let (status, _) = reload_keys("wrong-admin-key").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, reply) = reload_keys(ADMIN_KEY).await;
+    assert_eq!(status, StatusCode::OK, "{}", reply);
+    assert_eq!(reply["callback_secondary_configured"], true);
+
+    let (status, _) = post_signed(next_key, "/callbacks/deposit", &deposit).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = post_signed(CALLBACK_KEY, "/callbacks/deposit", &deposit).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = post_signed("wrong-key", "/callbacks/deposit", &deposit).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+/// What `/admin/messages` has logged for `phone`, once there are `expected` of them.
+async fn sent_to(phone: &str, expected: usize) -> Vec<Value> {
+    let mut messages = Vec::new();
+    for _ in 0..100 {
+        let response = reqwest::Client::new()
+            .get(format!("{}/admin/messages", app().await))
+            .header("x-admin-key", ADMIN_KEY)
+            .query(&[("phone", phone)])
+            .send()
+            .await
+            .expect("admin answers");
+        let body: Value = response.json().await.unwrap();
+        messages = body["messages"].as_array().cloned().unwrap_or_default();
+        if messages.len() >= expected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    messages
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_notification_from_a_known_template_is_queued_and_sent() {
+    let phone = "+2348030000004";
+    window::record_inbound(phone, Utc::now());
+    let notification = json!({
+        "phone": phone,
+        "template": "settlement_delayed",
+        "variables": {"reference": "KP-NT-1", "eta": "3pm today"},
+    });
+    let (status, reply) = post_signed(CALLBACK_KEY, "/notify", &notification).await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", reply);
+    assert_eq!(reply["success"], true);
+    let message_id = reply["message_id"].as_str().expect("message id");
+    assert!(uuid::Uuid::parse_str(message_id).is_ok(), "{}", message_id);
+
+    let expected = i18n::render(
+        Language::En,
+        Msg::SettlementDelayed,
+        &[("reference", "KP-NT-1"), ("eta", "3pm today")],
+    );
+    assert!(!expected.contains('{'), "{}", expected);
+    let sent = sent_to(phone, 1).await;
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    assert_eq!(
+        sent[0]["body_hash"],
+        hex::encode(Sha256::digest(expected.as_bytes()))
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn notifications_that_are_not_our_copy_are_rejected() {
+    let phone = "+2348030000005";
+    window::record_inbound(phone, Utc::now());
+    for (notification, reason) in [
+        (
+            json!({"phone": phone, "template": "promo_blast", "variables": {}}),
+            "Unknown template promo_blast",
+        ),
+        (
+            json!({"phone": phone, "template": "settlement_delayed",
+                "variables": {"reference": "KP-NT-2"}}),
+            "Missing variables: eta",
+        ),
+        (
+            json!({"phone": phone, "template": "settlement_delayed",
+                "variables": {"reference": "KP-NT-2", "eta": "soon", "link": "x"}}),
+            "Unknown variables: link",
+        ),
+        (
+            json!({"phone": phone, "body": "Click here to claim your prize"}),
+            "Free-form bodies are not accepted; use a template",
+        ),
+        (
+            json!({"template": "account_review", "variables": {"until": "Monday"}}),
+            "Invalid notification",
+        ),
+    ] {
+        let (status, reply) = post_signed(CALLBACK_KEY, "/notify", &notification).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", notification);
+        assert!(
+            reply["message"].as_str().unwrap().starts_with(reason),
+            "{}: {}",
+            notification,
+            reply
+        );
+    }
+    // Nothing reached the user
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(sent_to(phone, 0).await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unsigned_or_wrongly_signed_notifications_are_refused() {
+    let notification = json!({
+        "phone": "+2348030000006",
+        "template": "account_review",
+        "variables": {"until": "Monday"},
+    });
+    let (status, _) = post_signed("wrong-key", "/notify", &notification).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/notify", app().await))
+        .json(&notification)
+        .send()
+        .await
+        .expect("app answers");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
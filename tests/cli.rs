@@ -0,0 +1,64 @@
+//! The command line, run as the built binary with a controlled environment.
+
+mod common;
+
+use std::process::{Command, Output};
+
+/// Runs the binary with only `env` set, from a directory without a `.env` file.
+fn run(args: &[&str], env: &[(&str, &str)]) -> Output {
+    let dir = common::scratch_dir().join("cli");
+    std::fs::create_dir_all(&dir).expect("cli dir");
+    Command::new(env!("CARGO_BIN_EXE_kharon-pay-whatsapp"))
+        .args(args)
+        .env_clear()
+        .envs(env.iter().copied())
+        .current_dir(dir)
+        .output()
+        .expect("binary runs")
+}
+
+const COMPLETE: [(&str, &str); 5] = [
+    ("T_ACCOUNT_SID", "AC-test-sid-value"),
+    ("T_AUTH_TOKEN", "test-auth-token-value"),
+    ("T_WHATSAPP_NUMBER", "+14155238886"),
+    ("T_API_URL", "http://127.0.0.1:9"),
+    ("SESSION_STORE", "memory"),
+];
+
+#[test]
+fn check_config_passes_and_redacts_secrets() {
+    let output = run(&["check-config"], &COMPLETE);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout);
+    assert!(stdout.contains("Configuration OK"));
+    assert!(stdout.contains("+14155238886"));
+    assert!(stdout.contains("set (redacted)"));
+    assert!(!stdout.contains("test-auth-token-value"));
+    assert!(!stdout.contains("AC-test-sid-value"));
+}
+
+#[test]
+fn check_config_lists_every_problem() {
+    let output = run(&["check-config"], &[("SESSION_STORE", "memory")]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.contains("4 problem(s)"), "{}", stdout);
+    assert!(stdout.contains("T_AUTH_TOKEN (or T_AUTH_TOKEN_FILE) must be set"));
+    assert!(stdout.contains("T_API_URL must be set"));
+}
+
+#[test]
+fn a_bad_command_line_exits_2_with_usage() {
+    let output = run(&["send", "--to", "+2348012345678"], &COMPLETE);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--message <MESSAGE>"), "{}", stderr);
+    assert!(stderr.contains("Usage:"), "{}", stderr);
+}
+
+#[test]
+fn help_exits_cleanly() {
+    let output = run(&["--help"], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("check-config"));
+}
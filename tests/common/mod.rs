@@ -0,0 +1,203 @@
+//! Shared setup for the integration tests.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::sync::{Once, OnceLock};
+use std::time::Duration;
+
+use serde_json::{Value, json};
+
+static SETUP: Once = Once::new();
+
+/// A scratch directory for this test binary's databases and logs.
+pub fn scratch_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("kharon-tests-{}", std::process::id()))
+}
+
+/// Keeps every file the bot writes out of the working tree, and every message it sends
+/// off the network. Call first in each test.
+pub fn setup() {
+    SETUP.call_once(|| {
+        let dir = scratch_dir();
+        std::fs::create_dir_all(&dir).expect("scratch dir");
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        // SAFETY: runs once, before any test in this binary touches the environment
+        unsafe {
+            std::env::set_var("ACCESS_LOG", "off");
+            std::env::set_var("TWILIO_DRY_RUN", "1");
+            std::env::set_var("T_WHATSAPP_NUMBER", "whatsapp:+14155238886");
+            std::env::set_var("AUDIT_LOG_PATH", path("audit.log"));
+            std::env::set_var("BACKEND_TRAFFIC_LOG_PATH", path("backend_traffic.log"));
+            std::env::set_var("ACCESS_LIST_PATH", path("access_lists.json"));
+            std::env::set_var("MESSAGE_LOG_DB_PATH", path("messages.db"));
+            std::env::set_var("TRANSACTION_INDEX_DB_PATH", path("transactions.db"));
+            std::env::set_var("SESSION_DB_PATH", path("sessions.db"));
+        }
+    });
+}
+
+/// A golden Twilio payload from `tests/fixtures/twilio`.
+pub fn twilio_fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/twilio")
+        .join(format!("{}.form", name));
+    std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+/// A recorded backend reply from `tests/fixtures/backend`.
+pub fn backend_fixture(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/backend")
+        .join(format!("{}.json", name));
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+/// Compares `actual` with `tests/snapshots/<name>.txt`. `UPDATE_SNAPSHOTS=1` rewrites the
+/// file instead, for reviewing the change in the diff.
+pub fn assert_snapshot(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{}.txt", name));
+    if std::env::var("UPDATE_SNAPSHOTS").is_ok_and(|v| v == "1") {
+        std::fs::create_dir_all(path.parent().unwrap()).expect("snapshot dir");
+        std::fs::write(&path, actual).expect("snapshot written");
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "{}: {} (run with UPDATE_SNAPSHOTS=1 to create it)",
+            path.display(),
+            e
+        )
+    });
+    assert_eq!(
+        actual, expected,
+        "{} changed (run with UPDATE_SNAPSHOTS=1 to accept it)",
+        name
+    );
+}
+
+/// Serves the whole app, mock backend included, on a free local port in a thread of its
+/// own, the way `main` wires it. Returns the base URL.
+pub fn spawn_app() -> String {
+    use actix_web::{App, HttpServer, middleware::from_fn, web};
+    use kharon_pay_whatsapp::{
+        access_log, audit, deadline, message_log, mock_backend, proxy, recording, request_body,
+        server, store,
+    };
+
+    setup();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+    let base = format!("http://{}", listener.local_addr().expect("bound"));
+    // SAFETY: set before the server thread starts, and only read by it
+    unsafe {
+        std::env::set_var("MOCK_BACKEND", "1");
+        std::env::set_var("MOCK_BACKEND_URL", format!("{}/mock-backend", base));
+    }
+    mock_backend::configure_env();
+
+    std::thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            // On this runtime, so they outlive the test that started the app
+            audit::start_writer();
+            message_log::start_writer();
+            recording::start_writer();
+            let sessions: web::Data<dyn store::SessionStore> =
+                web::Data::from(store::from_env().expect("session store"));
+            HttpServer::new(move || {
+                App::new()
+                    .app_data(sessions.clone())
+                    .app_data(request_body::payload_config())
+                    .wrap(from_fn(deadline::limit))
+                    .wrap(from_fn(proxy::attach_client))
+                    .wrap(from_fn(access_log::log))
+                    .configure(server::routes)
+            })
+            .listen(listener)
+            .expect("listening")
+            .disable_signals()
+            .run()
+            .await
+        })
+    });
+    base
+}
+
+/// The app's base URL, started once per test binary and waited on until it answers.
+pub async fn app() -> &'static str {
+    app_with(|| {}).await
+}
+
+/// `app`, with `configure` run first when this call is the one that starts it, for
+/// settings the server reads as it starts.
+pub async fn app_with(configure: impl FnOnce()) -> &'static str {
+    static BASE: OnceLock<String> = OnceLock::new();
+    let base = BASE.get_or_init(|| {
+        setup();
+        configure();
+        spawn_app()
+    });
+    let client = reqwest::Client::new();
+    while client.get(format!("{}/livez", base)).send().await.is_err() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    base
+}
+
+pub async fn mock_post(path: &str, body: Value) -> Value {
+    reqwest::Client::new()
+        .post(format!("{}/mock-backend{}", app().await, path))
+        .json(&body)
+        .send()
+        .await
+        .expect("mock backend answers")
+        .json()
+        .await
+        .expect("mock backend JSON")
+}
+
+/// What the mock backend says `reference` is at.
+pub async fn mock_status(reference: &str) -> String {
+    let url = format!(
+        "{}/mock-backend/transactions/{}/status",
+        app().await,
+        reference
+    );
+    let body: Value = reqwest::get(url).await.unwrap().json().await.unwrap();
+    body["data"]["status"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Starts a withdrawal for `phone` on the mock backend only, awaiting the user's
+/// approval. Returns the reference.
+pub async fn start_on_backend(phone: &str) -> String {
+    let digits = phone.trim_start_matches('+');
+    let saved = mock_post(
+        "/banks",
+        json!({
+            "phone": digits,
+            "bank_save_id": format!("save-{}", digits),
+            "bank_name": "Opay",
+            "account_number": "0123456789",
+            "account_name": "ADA OBI",
+            "bank_code": "999",
+        }),
+    )
+    .await;
+    let started = mock_post(
+        "/offramp",
+        json!({
+            "phone": digits,
+            "amount": 10.0,
+            "token_symbol": "USDT",
+            "bank_account_id": saved["data"]["bank_details_id"],
+        }),
+    )
+    .await;
+    started["reference"]
+        .as_str()
+        .expect("reference")
+        .to_string()
+}
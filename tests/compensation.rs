@@ -0,0 +1,161 @@
+//! What happens when the payment trigger fails after the offramp was created, with the
+//! mock backend scripted to fail.
+
+mod common;
+
+use std::time::Duration;
+
+use kharon_pay_whatsapp::audit::{self, AuditEntry, AuditEvent};
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::twilio::{self, CapturedMessage};
+use serde_json::json;
+
+use common::{mock_post, mock_status};
+
+const OPS_PHONE: &str = "+2348090001001";
+
+/// The app with one retry of the payment trigger, so each test waits a single backoff.
+async fn app() {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts, and before any test reads these
+        unsafe {
+            std::env::set_var("PAYMENT_TRIGGER_ATTEMPTS", "2");
+            std::env::set_var("OPS_ALERT_PHONES", OPS_PHONE);
+        }
+    })
+    .await;
+}
+
+/// `phone` with a saved bank, at the final yes of a 10 USDT withdrawal, with `faults`
+/// scripted on the backend.
+async fn at_the_final_yes(phone: &str, faults: serde_json::Value) -> UserSessions {
+    let digits = phone.trim_start_matches('+');
+    mock_post(
+        "/banks",
+        json!({
+            "phone": digits,
+            "bank_save_id": format!("save-{}", digits),
+            "bank_name": "Opay",
+            "account_number": "0123456789",
+            "account_name": "ADA OBI",
+            "bank_code": "999",
+        }),
+    )
+    .await;
+    let mut faults = faults;
+    faults["phone"] = json!(digits);
+    mock_post("/faults", faults).await;
+
+    let mut session = UserSessions::new(phone);
+    session.controller_address = Some("0x0test".to_string());
+    for message in ["withdraw 10 USDT", "confirm"] {
+        say(&mut session, message).await;
+    }
+    assert_eq!(session.state, UserState::SavedBankConfirmation);
+    session
+}
+
+/// The replies to `message`, and whatever else was sent meanwhile.
+async fn say(session: &mut UserSessions, message: &str) -> (Vec<String>, Vec<CapturedMessage>) {
+    let (replies, sent) = twilio::capture(dispatch_message(message, session), Duration::ZERO).await;
+    (replies.into_iter().map(|reply| reply.body).collect(), sent)
+}
+
+/// The mock backend's reference in a reply.
+fn reference_in(reply: &str) -> String {
+    reply
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .find(|word| word.starts_with("MOCK-"))
+        .expect("a reference")
+        .to_string()
+}
+
+/// The audit entries for `reference`, once the writer has caught up.
+async fn trail(reference: &str) -> Vec<AuditEntry> {
+    for _ in 0..50 {
+        let entries = audit::trail_for_reference(reference).await.unwrap();
+        if entries
+            .iter()
+            .any(|entry| matches!(entry.event, AuditEvent::OfframpCancelled { .. }))
+        {
+            return entries;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    audit::trail_for_reference(reference).await.unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_trigger_that_fails_once_is_retried_and_the_withdrawal_goes_ahead() {
+    app().await;
+    let mut session = at_the_final_yes("+2348090001002", json!({ "payment_failures": 1 })).await;
+
+    let (replies, sent) = say(&mut session, "yes").await;
+    assert!(
+        replies[0].starts_with("✅ *Withdrawal Successfully Initiated!*"),
+        "{:?}",
+        replies
+    );
+    let reference = reference_in(&replies[0]);
+    assert_eq!(mock_status(&reference).await, "processing");
+    assert!(sent.iter().all(|message| message.to != OPS_PHONE));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_trigger_that_keeps_failing_cancels_the_withdrawal() {
+    app().await;
+    let mut session = at_the_final_yes("+2348090001003", json!({ "payment_failures": 2 })).await;
+
+    let (replies, sent) = say(&mut session, "yes").await;
+    let reply = &replies[0];
+    assert!(reply.contains("it has been cancelled"), "{}", reply);
+    assert!(reply.contains("Your funds were not moved"), "{}", reply);
+    let reference = reference_in(reply);
+    assert_eq!(mock_status(&reference).await, "cancelled");
+    assert!(sent.iter().all(|message| message.to != OPS_PHONE));
+
+    let trail = trail(&reference).await;
+    assert!(
+        trail.iter().any(|entry| matches!(
+            entry.event,
+            AuditEvent::OfframpCancelled { success: true, .. }
+        )),
+        "{:?}",
+        trail
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_failed_cancellation_is_escalated_to_ops() {
+    app().await;
+    let mut session = at_the_final_yes(
+        "+2348090001004",
+        json!({ "payment_failures": 2, "cancel_fails": true }),
+    )
+    .await;
+
+    let (replies, sent) = say(&mut session, "yes").await;
+    let reply = &replies[0];
+    assert!(reply.starts_with("❌ *Withdrawal Failed*"), "{}", reply);
+    assert!(!reply.contains("not moved"), "{}", reply);
+    let reference = reference_in(reply);
+    assert_eq!(mock_status(&reference).await, "pending");
+
+    let alert = sent
+        .iter()
+        .find(|message| message.to == OPS_PHONE)
+        .expect("ops alerted");
+    assert!(alert.body.contains(&reference), "{}", alert.body);
+    assert!(alert.body.contains("cancellation failed"), "{}", alert.body);
+
+    let trail = trail(&reference).await;
+    assert!(
+        trail.iter().any(|entry| matches!(
+            entry.event,
+            AuditEvent::OfframpCancelled { success: false, .. }
+        )),
+        "{:?}",
+        trail
+    );
+}
@@ -0,0 +1,1003 @@
+//! Conversations against the mock backend, served in-process, with replies captured
+//! instead of sent.
+
+mod common;
+
+use std::time::Duration;
+
+use chrono::Utc;
+use kharon_pay_whatsapp::audit::{self, AuditEntry, AuditEvent};
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::features::{self, FeatureFlag};
+use kharon_pay_whatsapp::i18n::Language;
+use kharon_pay_whatsapp::inflight::{self, Operation};
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::transactions::{self, IndexedTransaction};
+use kharon_pay_whatsapp::{onboarding, region, twilio};
+use serde_json::json;
+
+use common::{app, mock_post, mock_status, start_on_backend};
+
+/// `start_on_backend`, then indexed as `phone`'s the way initiation does.
+async fn start_withdrawal(phone: &str) -> String {
+    let reference = start_on_backend(phone).await;
+    transactions::record(&IndexedTransaction {
+        reference: reference.clone(),
+        phone: phone.to_string(),
+        amount: 10.0,
+        token: "USDT".to_string(),
+        bank_name: "Opay".to_string(),
+        account_name: "ADA OBI".to_string(),
+        masked_account: "6789".to_string(),
+        initiated_at: Utc::now(),
+        status: "initiated".to_string(),
+        status_updated_at: Utc::now(),
+        correlation_id: None,
+        tx_hash: None,
+        language: Language::En,
+    })
+    .await;
+    reference
+}
+
+fn account_holder(phone: &str) -> UserSessions {
+    let mut session = UserSessions::new(phone);
+    session.controller_address = Some("0x0test".to_string());
+    session
+}
+
+/// The replies to `message`, as their bodies.
+async fn say(session: &mut UserSessions, message: &str) -> Vec<String> {
+    let (replies, _) = twilio::capture(dispatch_message(message, session), Duration::ZERO).await;
+    replies.into_iter().map(|reply| reply.body).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nobody_cancels_someone_elses_withdrawal() {
+    app().await;
+    let reference = start_withdrawal("+2348020000001").await;
+
+    let mut stranger = account_holder("+2348020000002");
+    let replies = say(&mut stranger, &format!("cancel {}", reference)).await;
+    assert!(
+        replies[0].starts_with("❌ No withdrawal found"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(mock_status(&reference).await, "pending");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_withdrawal_we_have_no_record_of_is_not_cancelled() {
+    app().await;
+    let reference = start_on_backend("+2348020000003").await;
+
+    let mut owner = account_holder("+2348020000003");
+    let replies = say(&mut owner, &format!("cancel {}", reference)).await;
+    assert!(
+        replies[0].starts_with("❌ No withdrawal found"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(mock_status(&reference).await, "pending");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_owner_can_cancel_their_withdrawal() {
+    app().await;
+    let reference = start_withdrawal("+2348020000004").await;
+
+    let mut owner = account_holder("+2348020000004");
+    let replies = say(&mut owner, &format!("cancel {}", reference)).await;
+    assert!(
+        replies[0].starts_with("✅ *Withdrawal Cancelled*"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(mock_status(&reference).await, "cancelled");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn withdrawing_more_than_the_balance_is_refused_up_front() {
+    app().await;
+    let mut holder = account_holder("+2348020000005");
+    let replies = say(&mut holder, "withdraw 250.01 USDT").await;
+    assert!(
+        replies[0].starts_with("❌ *Insufficient balance*"),
+        "{:?}",
+        replies
+    );
+    assert!(replies[0].contains("250.00 USDT"), "{:?}", replies);
+    assert!(replies[0].contains("`withdraw all USDT`"), "{:?}", replies);
+    assert_eq!(holder.state, UserState::Initial);
+    assert_eq!(holder.pending_amount, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn withdraw_all_quotes_the_exact_balance() {
+    app().await;
+    let mut holder = account_holder("+2348020000006");
+    let replies = say(&mut holder, "withdraw all USDT").await;
+    assert_eq!(
+        holder.state,
+        UserState::OfframpConfirmation,
+        "{:?}",
+        replies
+    );
+    assert_eq!(holder.pending_amount, Some(250.0));
+    assert_eq!(holder.pending_currency.as_deref(), Some("USDT"));
+
+    let mut unsure = account_holder("+2348020000007");
+    let replies = say(&mut unsure, "withdraw all").await;
+    assert!(replies[0].contains("withdraw all USDT"), "{:?}", replies);
+    assert_eq!(unsure.state, UserState::Initial);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn held_back_commands_are_coming_soon_whatever_their_arguments() {
+    let held_back = FeatureFlag {
+        allowlist: ["+2348020000009".to_string()].into(),
+        ..FeatureFlag::default()
+    };
+    features::set("quote", held_back.clone());
+    features::set("settings", held_back);
+
+    let mut outside = account_holder("+2348020000008");
+    for message in ["quote nonsense", "quote 10 USDT", "settings", "preferences"] {
+        let replies = say(&mut outside, message).await;
+        assert_eq!(replies, [features::COMING_SOON_MESSAGE], "{}", message);
+    }
+
+    // The allowlisted number gets the command, usage errors and all
+    let mut inside = account_holder("+2348020000009");
+    let replies = say(&mut inside, "quote nonsense").await;
+    assert_ne!(replies, [features::COMING_SOON_MESSAGE]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn quotes_are_answered_in_the_users_own_words() {
+    app().await;
+    let mut pidgin = account_holder("+2348020000010");
+    pidgin.prefs.language = Some(Language::Pcm);
+    say(&mut pidgin, "withdraw 10 USDT").await;
+    assert_eq!(pidgin.state, UserState::OfframpConfirmation);
+    let replies = say(&mut pidgin, "No o!").await;
+    assert!(
+        replies[0].starts_with("❌ *Withdrawal Don Cancel*"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(pidgin.state, UserState::Initial);
+
+    let mut english = account_holder("+2348020000011");
+    say(&mut english, "withdraw 10 USDT").await;
+    let replies = say(&mut english, "  Cancel ").await;
+    assert!(
+        replies[0].starts_with("❌ *Withdrawal Cancelled*"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(english.state, UserState::Initial);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_first_withdrawal_verifies_the_bank_and_the_next_reuses_it() {
+    app().await;
+    let mut holder = account_holder("+2348020000012");
+
+    let replies = say(&mut holder, "withdraw 10 USDT").await;
+    assert!(
+        replies[0].starts_with("💸 *Withdraw Request*"),
+        "{:?}",
+        replies
+    );
+    assert!(replies[0].contains("₦15,000.00"), "{:?}", replies);
+
+    let replies = say(&mut holder, "confirm").await;
+    assert_eq!(holder.state, UserState::BankDetailsEntry, "{:?}", replies);
+
+    let replies = say(&mut holder, "Opay, 0123456789").await;
+    assert_eq!(holder.state, UserState::BankDetailsConfirmation);
+    assert!(
+        replies[0].starts_with("✅ *Account Verified!*"),
+        "{:?}",
+        replies
+    );
+    assert!(replies[0].contains("******6789"), "{:?}", replies);
+
+    let replies = say(&mut holder, "yes").await;
+    assert_eq!(holder.state, UserState::Initial);
+    assert!(
+        replies[0].starts_with("✅ *Withdrawal Successfully Initiated!*"),
+        "{:?}",
+        replies
+    );
+    assert!(replies[0].contains("₦15,000.00"), "{:?}", replies);
+    // The on-chain hash is shown, and kept for the completion notice
+    assert!(
+        replies[0].contains("https://starkscan.co/tx/0x"),
+        "{:?}",
+        replies
+    );
+    let indexed = transactions::for_phone("+2348020000012").await;
+    assert!(
+        indexed[0]
+            .tx_hash
+            .as_ref()
+            .is_some_and(|hash| hash.starts_with("0x")),
+        "{:?}",
+        indexed[0].tx_hash
+    );
+
+    // The account was saved, so the next withdrawal goes straight to it
+    say(&mut holder, "withdraw 5 USDT").await;
+    let replies = say(&mut holder, "confirm").await;
+    assert_eq!(holder.state, UserState::SavedBankConfirmation);
+    assert!(
+        replies[0].contains("Proceed with your saved account?"),
+        "{:?}",
+        replies
+    );
+    let replies = say(&mut holder, "yes").await;
+    assert_eq!(holder.state, UserState::Initial);
+    assert!(replies[0].contains("₦7,500.00"), "{:?}", replies);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_final_yes_follows_the_whole_summary() {
+    app().await;
+    mock_post(
+        "/banks",
+        json!({
+            "phone": "2348020000018",
+            "bank_save_id": "summary-0",
+            "bank_name": "Opay",
+            "account_number": "0123456789",
+            "account_name": "ADA OBI",
+            "bank_code": "999",
+        }),
+    )
+    .await;
+    let mut holder = account_holder("+2348020000018");
+    say(&mut holder, "withdraw 10 USDT").await;
+
+    let replies = say(&mut holder, "confirm").await;
+    assert_eq!(holder.state, UserState::SavedBankConfirmation);
+    let prompt = replies.last().expect("a prompt");
+    for part in [
+        "10.00 USDT",
+        "₦1,500.00 per USDT",
+        "₦15,000.00",
+        "Opay",
+        "ADA OBI",
+        "******6789",
+    ] {
+        assert!(
+            prompt.contains(part),
+            "{:?} missing from {:?}",
+            part,
+            prompt
+        );
+    }
+    assert!(!prompt.contains("0123456789"), "{:?}", prompt);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn account_holders_and_newcomers_get_their_own_menus() {
+    app().await;
+    let mut newcomer = UserSessions::new("+2348020000013");
+    let replies = say(&mut newcomer, "hi").await;
+    assert!(replies[0].contains("`create`"), "{:?}", replies);
+
+    let mut holder = account_holder("+2348020000014");
+    let replies = say(&mut holder, "hi").await;
+    assert!(!replies[0].contains("`create`"), "{:?}", replies);
+    let replies = say(&mut holder, "balance").await;
+    assert!(replies[0].starts_with("💰 *Your Balance*"), "{:?}", replies);
+    assert!(replies[0].contains("USDT: 250.00"), "{:?}", replies);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_repeated_balance_is_served_from_the_cache_until_refreshed() {
+    app().await;
+    let mut holder = account_holder("+2348020000022");
+    let fetched = say(&mut holder, "balance").await;
+    assert!(!fetched[0].contains("As of"), "{:?}", fetched);
+
+    let repeated = say(&mut holder, "balance").await;
+    assert!(repeated[0].starts_with(&fetched[0]), "{:?}", repeated);
+    assert!(
+        repeated[0].contains("🕒 As of 0s ago. Type `balance refresh` for the latest."),
+        "{:?}",
+        repeated
+    );
+
+    assert_eq!(say(&mut holder, "balance refresh").await, fetched);
+}
+
+/// Label with a preview, then the bare address alone so tap-to-copy takes only it, then
+/// the network warning.
+fn assert_address_sequence(replies: &[String]) {
+    let [intro, address, warning] = replies else {
+        panic!("expected three messages, got {:?}", replies);
+    };
+    assert!(
+        address.len() == 66
+            && address.starts_with("0x")
+            && address[2..].chars().all(|c| c.is_ascii_hexdigit()),
+        "{:?}",
+        address
+    );
+    let preview = format!("{}…{}", &address[..6], &address[address.len() - 4..]);
+    assert_eq!(
+        intro,
+        &format!(
+            "💳 *Your Wallet Address* ({})\n\n\
+             Your full address is in the next message — tap and hold it to copy.",
+            preview
+        )
+    );
+    assert!(
+        warning.starts_with("⚠️ *Only send USDT/USDC on Starknet to this address.*"),
+        "{:?}",
+        warning
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_address_command_labels_the_address_before_sending_it_alone() {
+    app().await;
+    // Nothing cached yet, so the address comes from the backend
+    let mut holder = UserSessions::new("+2348020000019");
+    let replies = say(&mut holder, "address").await;
+    assert_address_sequence(&replies);
+
+    // and is then served from the session the same way
+    assert_eq!(say(&mut holder, "address").await, replies);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_cached_address_is_served_until_refresh_address() {
+    app().await;
+    let cached = format!("0x{}", "ab".repeat(32));
+    let mut holder = UserSessions::new("+2348020000021");
+    holder.controller_address = Some(cached.clone());
+
+    for message in ["address", "fund", "copy address"] {
+        let replies = say(&mut holder, message).await;
+        assert_eq!(replies[1], cached, "{}", message);
+    }
+
+    let replies = say(&mut holder, "refresh address").await;
+    assert_address_sequence(&replies);
+    let fetched = replies[1].clone();
+    assert_ne!(fetched, cached);
+    assert_eq!(holder.controller_address.as_deref(), Some(fetched.as_str()));
+
+    // Ending a flow leaves the account's address alone
+    say(&mut holder, "withdraw 10 USDT").await;
+    say(&mut holder, "no").await;
+    assert_eq!(holder.state, UserState::Initial);
+    assert_eq!(say(&mut holder, "address").await[1], fetched);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_new_account_gets_the_same_address_sequence_before_the_welcome() {
+    app().await;
+    let mut newcomer = UserSessions::new("+2348020000020");
+    say(&mut newcomer, "create").await;
+    let replies = say(&mut newcomer, "ada_eze").await;
+    let (welcome, address) = replies.split_last().expect("replies");
+    assert_address_sequence(address);
+    assert!(
+        welcome.starts_with("🎉 *Account created successfully!*"),
+        "{:?}",
+        welcome
+    );
+    assert_eq!(
+        newcomer.controller_address.as_deref(),
+        Some(address[1].as_str())
+    );
+
+    // Asking for it later shows the same address
+    let replies = say(&mut newcomer, "address").await;
+    assert_eq!(replies[1], address[1]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_rejected_username_can_be_replaced_until_one_is_accepted() {
+    app().await;
+    let mut newcomer = UserSessions::new("+2348020000015");
+    let replies = say(&mut newcomer, "create").await;
+    assert_eq!(newcomer.state, UserState::AccountCreation);
+    assert!(replies[0].contains("Choose a username"), "{:?}", replies);
+
+    let replies = say(&mut newcomer, "taken_ada").await;
+    assert!(
+        replies[0].starts_with("❌ *That username is taken.*"),
+        "{:?}",
+        replies
+    );
+    assert!(
+        replies[0].contains("taken_ada is already in use"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(newcomer.state, UserState::AccountCreation);
+
+    let replies = say(&mut newcomer, "ada obi!").await;
+    assert!(
+        replies[0].starts_with("❌ *That username won't work.*"),
+        "{:?}",
+        replies
+    );
+    assert!(
+        replies[0].contains("letters, numbers and underscores"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(newcomer.state, UserState::AccountCreation);
+
+    let replies = say(&mut newcomer, "ada_obi").await;
+    assert!(
+        replies
+            .last()
+            .is_some_and(|reply| reply.starts_with("🎉 *Account created successfully!*")),
+        "{:?}",
+        replies
+    );
+    assert_eq!(newcomer.state, UserState::Initial);
+    assert!(newcomer.controller_address.is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_second_create_is_not_taken_as_the_username() {
+    app().await;
+    let mut newcomer = UserSessions::new("+2348020000016");
+    say(&mut newcomer, "create").await;
+    let replies = say(&mut newcomer, "create").await;
+    assert!(
+        replies[0].starts_with("⏳ Your account is already being created."),
+        "{:?}",
+        replies
+    );
+    assert_eq!(newcomer.state, UserState::AccountCreation);
+    assert!(newcomer.controller_address.is_none());
+
+    // A username sent again while the first is still being created
+    let _first = inflight::begin("+2348020000016", Operation::AccountCreation).unwrap();
+    let replies = say(&mut newcomer, "ada_okafor").await;
+    assert!(
+        replies[0].starts_with("⏳ Your account is already being created"),
+        "{:?}",
+        replies
+    );
+    assert!(newcomer.controller_address.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_picked_bank_is_summarised_before_anything_is_sent() {
+    app().await;
+    let mut ids = Vec::new();
+    for (i, bank) in ["Opay", "Kuda"].into_iter().enumerate() {
+        let saved = mock_post(
+            "/banks",
+            json!({
+                "phone": "2348020000017",
+                "bank_save_id": format!("pick-{}", i),
+                "bank_name": bank,
+                "account_number": format!("012345678{}", i),
+                "account_name": "ADA OBI",
+                "bank_code": "999",
+            }),
+        )
+        .await;
+        ids.push(
+            saved["data"]["bank_details_id"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+        );
+    }
+    let mut holder = account_holder("+2348020000017");
+    holder.prefs.set_bank_flag(&ids[1], true);
+
+    say(&mut holder, "withdraw 10 USDT").await;
+    let replies = say(&mut holder, "confirm").await;
+    assert_eq!(holder.state, UserState::BankSelection, "{:?}", replies);
+
+    let replies = say(&mut holder, "2").await;
+    assert_eq!(holder.state, UserState::SavedBankConfirmation);
+    assert!(
+        replies[0].starts_with("⚠️ This account failed its last check"),
+        "{:?}",
+        replies
+    );
+    assert!(
+        replies[0].contains("📋 *Withdrawal Summary*"),
+        "{:?}",
+        replies
+    );
+    assert!(replies[0].contains("Kuda"), "{:?}", replies);
+    assert!(
+        !replies.iter().any(|reply| reply.contains("Initiated")),
+        "{:?}",
+        replies
+    );
+
+    let replies = say(&mut holder, "yes").await;
+    assert!(
+        replies[0].starts_with("✅ *Withdrawal Successfully Initiated!*"),
+        "{:?}",
+        replies
+    );
+}
+
+/// The quote the mock backend was sent with `reference`'s init.
+async fn quote_sent_with(reference: &str) -> serde_json::Value {
+    let url = format!(
+        "{}/mock-backend/transactions/{}/status",
+        app().await,
+        reference
+    );
+    let body: serde_json::Value = reqwest::get(url).await.unwrap().json().await.unwrap();
+    body["data"]["metadata"].clone()
+}
+
+/// `phone`, quoted for 10 USDT and at the final yes to their saved bank, with the quote
+/// then moved to `quoted_naira` so the backend's ₦15,000.00 is that far from it.
+async fn quoted_at_the_final_yes(phone: &str, quoted_naira: f64) -> UserSessions {
+    start_on_backend(phone).await;
+    let mut holder = account_holder(phone);
+    say(&mut holder, "withdraw 10 USDT").await;
+    say(&mut holder, "confirm").await;
+    assert_eq!(holder.state, UserState::SavedBankConfirmation);
+    assert_eq!(holder.quoted_naira_amount, Some(15_000.0));
+    holder.quoted_naira_amount = Some(quoted_naira);
+    holder
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_payout_within_tolerance_of_the_quote_goes_ahead() {
+    // 0.5% off the quote, inside the default 1%
+    let mut holder = quoted_at_the_final_yes("+2348020000023", 15_075.0).await;
+    let quoted_rate = holder.quoted_rate;
+    assert!(quoted_rate.is_some());
+
+    let replies = say(&mut holder, "yes").await;
+    assert!(
+        replies[0].starts_with("✅ *Withdrawal Successfully Initiated!*"),
+        "{:?}",
+        replies
+    );
+    let reference = transactions::for_phone("+2348020000023").await[0]
+        .reference
+        .clone();
+    let quote = quote_sent_with(&reference).await;
+    assert_eq!(quote["quoted_rate"].as_f64(), quoted_rate);
+    assert!(quote["quoted_at"].is_string(), "{:?}", quote);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_payout_beyond_tolerance_waits_for_the_user_to_approve_it() {
+    // 2% off the quote
+    let mut holder = quoted_at_the_final_yes("+2348020000024", 15_300.0).await;
+
+    let replies = say(&mut holder, "yes").await;
+    assert_eq!(holder.state, UserState::PayoutApproval);
+    assert!(
+        replies[0].starts_with("⚠️ *The rate changed*"),
+        "{:?}",
+        replies
+    );
+    assert!(
+        replies[0].contains("quoted ₦15,300.00") && replies[0].contains("now ₦15,000.00"),
+        "{:?}",
+        replies
+    );
+    let reference = holder.pending_payout.as_ref().unwrap().reference.clone();
+    // Nothing is paid until they say so
+    assert_eq!(mock_status(&reference).await, "pending");
+
+    let replies = say(&mut holder, "yes").await;
+    assert!(
+        replies[0].starts_with("✅ *Withdrawal Successfully Initiated!*"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(mock_status(&reference).await, "processing");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_declined_new_amount_cancels_the_withdrawal() {
+    let mut holder = quoted_at_the_final_yes("+2348020000025", 14_000.0).await;
+    say(&mut holder, "yes").await;
+    let reference = holder.pending_payout.as_ref().unwrap().reference.clone();
+
+    let replies = say(&mut holder, "no").await;
+    assert!(
+        replies[0].starts_with("❌ *Withdrawal Cancelled*"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(holder.state, UserState::Initial);
+    assert_eq!(mock_status(&reference).await, "cancelled");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn numbers_outside_nigeria_can_look_around_but_not_sign_up_or_withdraw() {
+    app().await;
+    let mut kenyan = UserSessions::new("+254700000001");
+    let replies = say(&mut kenyan, "create").await;
+    assert_eq!(replies, [region::UNAVAILABLE_MESSAGE]);
+    assert_eq!(kenyan.state, UserState::Initial);
+
+    let mut kenyan_holder = account_holder("+254700000002");
+    for message in ["withdraw 10 USDT", "withdraw all USDT"] {
+        let replies = say(&mut kenyan_holder, message).await;
+        assert_eq!(replies, [region::UNAVAILABLE_MESSAGE], "{}", message);
+        assert_eq!(kenyan_holder.pending_amount, None);
+    }
+    for message in ["hi", "help", "quote 10 USDT"] {
+        let replies = say(&mut kenyan, message).await;
+        assert_ne!(replies, [region::UNAVAILABLE_MESSAGE], "{}", message);
+    }
+
+    // A number too short to place is kept out too
+    let mut malformed = UserSessions::new("+2547");
+    let replies = say(&mut malformed, "create").await;
+    assert_eq!(replies, [region::UNAVAILABLE_MESSAGE]);
+
+    let mut nigerian = UserSessions::new("+2348020000026");
+    let replies = say(&mut nigerian, "create").await;
+    assert!(replies[0].contains("Choose a username"), "{:?}", replies);
+
+    // Each attempt is recorded with where it came from
+    let restricted = |entries: &[AuditEntry]| {
+        entries
+            .iter()
+            .filter_map(|entry| match &entry.event {
+                AuditEvent::RegionRestricted {
+                    calling_code,
+                    action,
+                } => Some((calling_code.clone(), action.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut attempts = Vec::new();
+    for _ in 0..50 {
+        attempts = restricted(&audit::read_entries().await.unwrap());
+        if attempts.len() >= 4 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let code = |code: &str| Some(code.to_string());
+    for expected in [
+        (code("254"), "create".to_string()),
+        (code("254"), "withdraw".to_string()),
+        (None, "create".to_string()),
+    ] {
+        assert!(attempts.contains(&expected), "{:?}", attempts);
+    }
+    assert!(
+        !attempts
+            .iter()
+            .any(|(calling_code, _)| calling_code.as_deref() == Some("234")),
+        "{:?}",
+        attempts
+    );
+}
+
+/// `say`, recorded in the history with its outcome the way the webhook does.
+async fn say_recorded(session: &mut UserSessions, message: &str) -> Vec<String> {
+    session.record_inbound(message);
+    let replies = say(session, message).await;
+    if let Some(reply) = replies.first() {
+        session.record_outcome(reply);
+    }
+    replies
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recap_lists_the_last_few_messages_with_what_came_of_them() {
+    app().await;
+    let mut newcomer = UserSessions::new("+2348020000027");
+    let replies = say_recorded(&mut newcomer, "recap").await;
+    assert!(
+        replies[0].starts_with("🧾 Nothing to recap yet."),
+        "{:?}",
+        replies
+    );
+
+    let phone = "+2348020000028";
+    let reference = start_withdrawal(phone).await;
+    let mut holder = account_holder(phone);
+    for message in [
+        "hi",
+        "help",
+        "balance",
+        "quote 10 USDT",
+        "my account is 0123456789",
+        &format!("status {}", reference),
+    ] {
+        say_recorded(&mut holder, message).await;
+    }
+    let replies = say_recorded(&mut holder, "recap").await;
+    let recap = &replies[0];
+
+    assert!(recap.starts_with("🧾 *Your recent activity*"), "{}", recap);
+    // The last five before the recap itself, oldest first
+    assert!(!recap.contains("— `hi`"), "{}", recap);
+    let shown: Vec<&str> = recap.lines().filter(|line| line.ends_with('`')).collect();
+    assert_eq!(shown.len(), 5, "{}", recap);
+    assert!(shown[0].ends_with("`help`"), "{}", recap);
+    assert!(shown[2].ends_with("`quote 10 USDT`"), "{}", recap);
+    assert!(!recap.contains("0123456789"), "{}", recap);
+    assert!(recap.contains("↳ 💰"), "{}", recap);
+
+    assert!(recap.contains("💸 *Recent withdrawals*"), "{}", recap);
+    assert!(
+        recap.contains(&format!("• `{}` — initiated", reference)),
+        "{}",
+        recap
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_quote_is_indicative_and_points_at_the_real_withdrawal() {
+    app().await;
+    let mut newcomer = UserSessions::new("+2348020000029");
+    let replies = say(&mut newcomer, "quote 75 USDT").await;
+    let quote = &replies[0];
+    assert!(quote.starts_with("📊 *Indicative Quote*"), "{}", quote);
+    for expected in [
+        "Amount: 75.00 USDT",
+        "Rate: ₦1,500.00 per USDT",
+        "You'd receive about: ₦112,500.00",
+        "not a locked rate",
+        "`withdraw 75 USDT`",
+    ] {
+        assert!(
+            quote.contains(expected),
+            "{} missing from {}",
+            expected,
+            quote
+        );
+    }
+    // Nothing is started
+    assert_eq!(newcomer.state, UserState::Initial);
+    assert_eq!(newcomer.pending_amount, None);
+
+    for asked in ["how much is 75 usdt", "How much is 75 USDT in naira?"] {
+        assert_eq!(say(&mut newcomer, asked).await, replies, "{}", asked);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_quote_without_an_amount_or_for_another_token_explains_itself() {
+    app().await;
+    let mut newcomer = UserSessions::new("+2348020000030");
+    for asked in ["quote", "quote USDT", "how much is it"] {
+        let replies = say(&mut newcomer, asked).await;
+        assert!(
+            replies[0].starts_with("📊 *Quote Format:*"),
+            "{}: {:?}",
+            asked,
+            replies
+        );
+    }
+    let replies = say(&mut newcomer, "quote 75 DOGE").await;
+    assert!(
+        replies[0].starts_with("❌ `DOGE` isn't supported yet."),
+        "{:?}",
+        replies
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn without_a_list_template_saved_banks_are_a_numbered_menu() {
+    app().await;
+    for (i, bank) in ["Opay", "Kuda"].into_iter().enumerate() {
+        mock_post(
+            "/banks",
+            json!({
+                "phone": "2348020000031",
+                "bank_save_id": format!("menu-{}", i),
+                "bank_name": bank,
+                "account_number": format!("012345678{}", i),
+                "account_name": "ADA OBI",
+                "bank_code": "999",
+            }),
+        )
+        .await;
+    }
+    let mut holder = account_holder("+2348020000031");
+    say(&mut holder, "withdraw 10 USDT").await;
+    let (replies, _) =
+        twilio::capture(dispatch_message("confirm", &mut holder), Duration::ZERO).await;
+    assert_eq!(holder.state, UserState::BankSelection);
+    let menu = replies.last().unwrap();
+    assert!(menu.content.is_none(), "{:?}", menu.content);
+    assert!(menu.body.contains("1. Opay ******6780"), "{}", menu.body);
+    assert!(menu.body.contains("2. Kuda ******6781"), "{}", menu.body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn without_button_templates_the_confirmation_prompts_are_plain_text() {
+    app().await;
+    mock_post(
+        "/banks",
+        json!({
+            "phone": "2348020000032",
+            "bank_save_id": "plain-saved",
+            "bank_name": "Opay",
+            "account_number": "0123456789",
+            "account_name": "ADA OBI",
+            "bank_code": "999",
+        }),
+    )
+    .await;
+    let mut holder = account_holder("+2348020000032");
+    for (message, state) in [
+        ("withdraw 10 USDT", UserState::OfframpConfirmation),
+        ("confirm", UserState::SavedBankConfirmation),
+    ] {
+        let (replies, _) =
+            twilio::capture(dispatch_message(message, &mut holder), Duration::ZERO).await;
+        assert_eq!(holder.state, state);
+        assert!(
+            replies.iter().all(|reply| reply.content.is_none()),
+            "{}: {:?}",
+            message,
+            replies
+        );
+    }
+
+    let mut newcomer = account_holder("+2348020000033");
+    say(&mut newcomer, "withdraw 10 USDT").await;
+    say(&mut newcomer, "confirm").await;
+    let (replies, _) = twilio::capture(
+        dispatch_message("Opay, 0123456789", &mut newcomer),
+        Duration::ZERO,
+    )
+    .await;
+    assert_eq!(newcomer.state, UserState::BankDetailsConfirmation);
+    assert!(replies.iter().all(|reply| reply.content.is_none()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn support_opens_tickets_until_the_daily_limit() {
+    app().await;
+    let mut session = account_holder("+2348020000034");
+
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        let replies = say(&mut session, "support my withdrawal is stuck").await;
+        assert!(replies[0].starts_with("🎫 *Ticket MOCK-"), "{:?}", replies);
+        assert!(replies[0].contains("within 24 hours"), "{:?}", replies);
+        ids.push(replies[0].clone());
+    }
+    ids.dedup();
+    assert_eq!(ids.len(), 3, "{:?}", ids);
+
+    let replies = say(&mut session, "support").await;
+    assert!(
+        replies[0].starts_with("⏳ You've already opened several tickets recently"),
+        "{:?}",
+        replies
+    );
+    assert!(replies[0].contains("about 24 hours"), "{:?}", replies);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_checklist_follows_the_backend_but_only_once_its_check_is_stale() {
+    app().await;
+    let phone = "+2348020000035";
+    let mut session = account_holder(phone);
+    onboarding::start(&mut session);
+
+    // The mock gives everyone a balance, and this user has no bank yet
+    let replies = say(&mut session, "hi").await;
+    assert!(replies[0].contains("📋 *Getting started*"), "{:?}", replies);
+    assert!(replies[0].contains("✅ Fund your wallet"), "{:?}", replies);
+    assert!(
+        replies[0].contains("⬜ Add a bank account"),
+        "{:?}",
+        replies
+    );
+
+    // A bank saved elsewhere isn't seen until the last check goes stale
+    let digits = phone.trim_start_matches('+');
+    mock_post(
+        "/banks",
+        json!({
+            "phone": digits,
+            "bank_save_id": format!("save-{}", digits),
+            "bank_name": "Opay",
+            "account_number": "0123456789",
+            "account_name": "ADA OBI",
+            "bank_code": "999",
+        }),
+    )
+    .await;
+    let replies = say(&mut session, "help").await;
+    assert!(
+        replies[0].contains("⬜ Add a bank account"),
+        "{:?}",
+        replies
+    );
+
+    let progress = session.prefs.onboarding.as_mut().expect("still onboarding");
+    progress.checked_at = progress
+        .checked_at
+        .map(|at| at - chrono::Duration::hours(1));
+    let replies = say(&mut session, "hi").await;
+    assert!(
+        replies[0].contains("✅ Add a bank account"),
+        "{:?}",
+        replies
+    );
+    assert!(
+        replies[0].contains("⬜ Make your first withdrawal"),
+        "{:?}",
+        replies
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_nudge_during_a_withdrawal_gets_its_status() {
+    app().await;
+    let phone = "+2348020000036";
+    let mut holder = account_holder(phone);
+    // Nothing in progress, so nothing to report
+    for nudge in ["??", "any update?"] {
+        let replies = say(&mut holder, nudge).await;
+        assert!(
+            replies[0].starts_with("❓ I didn't understand that."),
+            "{:?}",
+            replies
+        );
+    }
+    let replies = say(&mut holder, "status").await;
+    assert!(
+        replies[0].contains("Please include your reference"),
+        "{:?}",
+        replies
+    );
+
+    let reference = start_withdrawal(phone).await;
+    for nudge in ["??", "any update?", "where is my money", "status"] {
+        let replies = say(&mut holder, nudge).await;
+        assert_eq!(replies.len(), 1, "{:?}", replies);
+        assert!(
+            replies[0].starts_with("⏳ *Your withdrawal is "),
+            "{}: {:?}",
+            nudge,
+            replies
+        );
+        for detail in [
+            "10.00 USDT",
+            "*To:* Opay (6789)",
+            reference.as_str(),
+            "*Started:* ",
+        ] {
+            assert!(replies[0].contains(detail), "{}: {:?}", detail, replies);
+        }
+    }
+
+    // Commands still mean what they say
+    let replies = say(&mut holder, "balance").await;
+    assert!(replies[0].contains("250.00"), "{:?}", replies);
+    assert!(!replies[0].contains("Your withdrawal is"), "{:?}", replies);
+
+    // And once it's done, a nudge is just a message again
+    transactions::set_status(&reference, "completed").await;
+    let replies = say(&mut holder, "??").await;
+    assert!(
+        replies[0].starts_with("❓ I didn't understand that."),
+        "{:?}",
+        replies
+    );
+}
@@ -0,0 +1,120 @@
+//! The server-side request deadline, on a server whose routes are held up by an extractor
+//! that takes longer than either deadline to finish.
+
+mod common;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    App, FromRequest, HttpRequest, HttpResponse, HttpServer, dev::Payload, middleware::from_fn, web,
+};
+use kharon_pay_whatsapp::deadline;
+
+/// Set by the webhook's background work once it has run to the end.
+static BACKGROUND_FINISHED: AtomicBool = AtomicBool::new(false);
+
+/// An extractor that takes three seconds, past both deadlines. On `/webhook` it first
+/// hands work to the background, the way the webhook does.
+struct Slow;
+
+impl FromRequest for Slow {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if req.path() == "/webhook" {
+            tokio::spawn(async {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                BACKGROUND_FINISHED.store(true, Ordering::SeqCst);
+            });
+        }
+        Box::pin(async {
+            tokio::time::sleep(Duration::from_secs(3)).await;
+            Ok(Slow)
+        })
+    }
+}
+
+async fn slow(_: Slow) -> HttpResponse {
+    HttpResponse::Ok().body("too late")
+}
+
+/// Serves the stand-in routes behind the deadline, once per test binary, with a one
+/// second webhook deadline and a two second one for everything else.
+fn server() -> &'static str {
+    static BASE: OnceLock<String> = OnceLock::new();
+    BASE.get_or_init(|| {
+        common::setup();
+        // SAFETY: set before the server thread starts and before any request is served
+        unsafe {
+            std::env::set_var("WEBHOOK_TIMEOUT_SECS", "1");
+            std::env::set_var("REQUEST_TIMEOUT_SECS", "2");
+        }
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+        let base = format!("http://{}", listener.local_addr().expect("bound"));
+        std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                HttpServer::new(|| {
+                    App::new()
+                        .wrap(from_fn(deadline::limit))
+                        .route("/webhook", web::post().to(slow))
+                        .route("/admin/slow", web::get().to(slow))
+                })
+                .listen(listener)
+                .expect("listening")
+                .disable_signals()
+                .run()
+                .await
+            })
+        });
+        base
+    })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_slow_webhook_gets_empty_twiml_at_its_deadline_and_its_work_carries_on() {
+    let started = Instant::now();
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhook", server()))
+        .body("Body=hi")
+        .send()
+        .await
+        .expect("answered");
+    let elapsed = started.elapsed();
+
+    assert_eq!(response.status(), 200);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("<Response></Response>"), "{}", body);
+    assert!(
+        elapsed >= Duration::from_secs(1) && elapsed < Duration::from_millis(2500),
+        "{:?}",
+        elapsed
+    );
+
+    // The request is gone, but what it started finishes
+    assert!(!BACKGROUND_FINISHED.load(Ordering::SeqCst));
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(BACKGROUND_FINISHED.load(Ordering::SeqCst));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn other_routes_get_a_504_at_their_own_longer_deadline() {
+    let started = Instant::now();
+    let response = reqwest::get(format!("{}/admin/slow", server()))
+        .await
+        .expect("answered");
+    let elapsed = started.elapsed();
+
+    assert_eq!(response.status(), 504);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["message"], "Request timed out");
+    assert!(
+        elapsed >= Duration::from_secs(2) && elapsed < Duration::from_secs(3),
+        "{:?}",
+        elapsed
+    );
+}
@@ -0,0 +1,119 @@
+//! Message handling with nothing configured: no backend, no Twilio credentials, no test
+//! token. Every message still gets an answer and no handler panics.
+
+mod common;
+
+use std::sync::Once;
+use std::time::Duration;
+
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::twilio;
+
+/// Clears the environment, keeping only where the databases go so nothing lands in the
+/// working tree.
+fn empty_env() {
+    static CLEAR: Once = Once::new();
+    CLEAR.call_once(|| {
+        let dir = common::scratch_dir();
+        std::fs::create_dir_all(&dir).expect("scratch dir");
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        // SAFETY: runs once, before any test in this binary reads the environment
+        unsafe {
+            for (name, _) in std::env::vars_os() {
+                std::env::remove_var(name);
+            }
+            std::env::set_var("AUDIT_LOG_PATH", path("audit.log"));
+            std::env::set_var("ACCESS_LIST_PATH", path("access_lists.json"));
+            std::env::set_var("MESSAGE_LOG_DB_PATH", path("messages.db"));
+            std::env::set_var("TRANSACTION_INDEX_DB_PATH", path("transactions.db"));
+            std::env::set_var("SESSION_DB_PATH", path("sessions.db"));
+        }
+    });
+}
+
+/// The replies to `message`, or the panic message if handling it panicked.
+async fn replies(session: UserSessions, message: &str) -> Result<Vec<String>, String> {
+    let message = message.to_string();
+    let handled = tokio::spawn(async move {
+        let mut session = session;
+        let (replies, _) =
+            twilio::capture(dispatch_message(&message, &mut session), Duration::ZERO).await;
+        replies
+            .into_iter()
+            .map(|reply| reply.body)
+            .collect::<Vec<_>>()
+    })
+    .await;
+    handled.map_err(|e| e.to_string())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn every_command_is_answered_without_configuration() {
+    empty_env();
+    let mut holder = UserSessions::new("+2348090000601");
+    holder.controller_address = Some("0x0test".to_string());
+
+    for message in [
+        "hi",
+        "help",
+        "create",
+        "address",
+        "balance",
+        "balance usdt",
+        "withdraw 10 usdt",
+        "withdraw all usdt",
+        "status KP-NOPE",
+        "history",
+        "export",
+        "asdf",
+    ] {
+        let replies = replies(holder.clone(), message)
+            .await
+            .unwrap_or_else(|e| panic!("{:?} panicked: {}", message, e));
+        assert!(!replies.is_empty(), "{:?} went unanswered", message);
+        assert!(
+            replies.iter().all(|reply| !reply.trim().is_empty()),
+            "{:?}: {:?}",
+            message,
+            replies
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn every_flow_step_is_answered_without_configuration() {
+    empty_env();
+    let mut mid_flow = UserSessions::new("+2348090000602");
+    mid_flow.controller_address = Some("0x0test".to_string());
+    mid_flow.pending_amount = Some(10.0);
+    mid_flow.pending_currency = Some("USDT".to_string());
+
+    for state in [
+        UserState::AccountCreation,
+        UserState::OfframpConfirmation,
+        UserState::BankSelection,
+        UserState::BankDetailsEntry,
+        UserState::AmountConfirmation,
+    ] {
+        for message in ["yes", "no", "1", "ada_obi", "0123456789 Opay"] {
+            let mut session = mid_flow.clone();
+            session.state = state;
+            let replies = replies(session, message)
+                .await
+                .unwrap_or_else(|e| panic!("{:?} in {:?} panicked: {}", message, state, e));
+            assert!(
+                !replies.is_empty(),
+                "{:?} in {:?} went unanswered",
+                message,
+                state
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn sending_without_twilio_credentials_only_logs() {
+    empty_env();
+    twilio::send_twilio_message("+2348090000603", "hello").await;
+}
@@ -0,0 +1,69 @@
+//! The arrival estimate in quotes and initiation messages, from completions recorded in
+//! this test binary's own transaction index.
+
+mod common;
+
+use std::time::Duration;
+
+use chrono::Utc;
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::eta;
+use kharon_pay_whatsapp::i18n::Language;
+use kharon_pay_whatsapp::model::UserSessions;
+use kharon_pay_whatsapp::twilio;
+
+async fn quote(phone: &str) -> String {
+    let mut session = UserSessions::new(phone);
+    session.controller_address = Some("0x0test".to_string());
+    let (replies, _) = twilio::capture(
+        dispatch_message("withdraw 10 USDT", &mut session),
+        Duration::ZERO,
+    )
+    .await;
+    replies[0].body.clone()
+}
+
+fn completed(bank: &str, minutes: &[i64]) {
+    for (n, minutes) in minutes.iter().enumerate() {
+        eta::record(
+            &format!("KP-ETA-{}-{}", bank.trim(), n),
+            bank,
+            minutes * 60,
+            Utc::now(),
+        );
+    }
+}
+
+// One test, since every estimate reads the same completions
+#[tokio::test(flavor = "multi_thread")]
+async fn the_estimate_follows_recent_completions_once_there_are_enough() {
+    common::app().await;
+
+    // Nothing to go on yet
+    let usual = "⏱️ The funds should reflect in your account shortly.";
+    assert_eq!(eta::arrival_line(Language::En, None), usual);
+    assert_eq!(eta::arrival_line(Language::En, Some("Opay")), usual);
+    assert!(quote("+2348090003501").await.contains(usual));
+
+    // Four aren't enough, five across banks are
+    completed("GTBank", &[2, 2, 3, 10]);
+    assert_eq!(eta::arrival_line(Language::En, None), usual);
+    completed("Kuda", &[2]);
+    let everywhere =
+        "⏱️ Withdrawals are typically arriving in ~2 min right now (most within ~10 min).";
+    assert_eq!(eta::arrival_line(Language::En, None), everywhere);
+    assert!(quote("+2348090003502").await.contains(everywhere));
+    // A bank without enough of its own gets the overall figure
+    assert_eq!(eta::arrival_line(Language::En, Some("Opay")), everywhere);
+
+    // Once it has enough, its own, however it was typed
+    completed(" OPAY ", &[1, 1, 1, 1, 1]);
+    assert_eq!(
+        eta::arrival_line(Language::En, Some("Opay")),
+        "⏱️ Typically ~60 sec to Opay right now (most within ~60 sec)."
+    );
+    // The quote has no bank yet, so it has everyone's, now Opay's included
+    assert!(quote("+2348090003503").await.contains(
+        "⏱️ Withdrawals are typically arriving in ~60 sec right now (most within ~3 min)."
+    ));
+}
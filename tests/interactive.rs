@@ -0,0 +1,203 @@
+//! Native WhatsApp pickers and buttons, with their content templates configured, and
+//! taps on them read back as what the user would have typed.
+
+mod common;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::inbound::{self, Inbound};
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::outbound::OutboundMessage;
+use kharon_pay_whatsapp::twilio;
+use serde_json::json;
+
+use common::mock_post;
+
+const BANK_LIST_SID: &str = "HXbanklist";
+const QUOTE_SID: &str = "HXquotebuttons";
+const SAVED_BANK_SID: &str = "HXsavedbankbuttons";
+const VERIFIED_BANK_SID: &str = "HXverifiedbankbuttons";
+
+async fn app() {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts, and before any test reads these
+        unsafe {
+            std::env::set_var("T_BANK_LIST_CONTENT_SID", BANK_LIST_SID);
+            std::env::set_var("T_QUOTE_BUTTONS_CONTENT_SID", QUOTE_SID);
+            std::env::set_var("T_SAVED_BANK_BUTTONS_CONTENT_SID", SAVED_BANK_SID);
+            std::env::set_var("T_VERIFIED_BANK_BUTTONS_CONTENT_SID", VERIFIED_BANK_SID);
+        }
+    })
+    .await;
+}
+
+async fn say(session: &mut UserSessions, message: &str) -> Vec<OutboundMessage> {
+    twilio::capture(dispatch_message(message, session), Duration::ZERO)
+        .await
+        .0
+}
+
+/// What a tap posted with `fields` reads as.
+fn tapped(phone: &str, fields: &[(&str, &str)]) -> String {
+    let mut form: HashMap<String, String> = fields
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    form.insert("From".to_string(), format!("whatsapp:{}", phone));
+    form.insert("MessageType".to_string(), "interactive".to_string());
+    match inbound::classify(&form) {
+        Inbound::Interactive { body, .. } => body,
+        other => panic!("not a tap: {:?}", other),
+    }
+}
+
+/// `phone` with `banks` saved, at the bank picker for 10 USDT.
+async fn at_the_bank_picker(phone: &str, banks: &[&str]) -> (UserSessions, OutboundMessage) {
+    app().await;
+    let digits = phone.trim_start_matches('+');
+    for (i, bank) in banks.iter().enumerate() {
+        mock_post(
+            "/banks",
+            json!({
+                "phone": digits,
+                "bank_save_id": format!("picker-{}-{}", digits, i),
+                "bank_name": bank,
+                "account_number": format!("01234567{:02}", i),
+                "account_name": "ADA OBI",
+                "bank_code": "999",
+            }),
+        )
+        .await;
+    }
+    let mut holder = UserSessions::new(phone);
+    holder.controller_address = Some("0x0test".to_string());
+    say(&mut holder, "withdraw 10 USDT").await;
+    let replies = say(&mut holder, "confirm").await;
+    assert_eq!(holder.state, UserState::BankSelection);
+    let picker = replies.last().expect("a picker").clone();
+    (holder, picker)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn saved_banks_are_offered_as_a_list_and_a_tapped_row_picks_its_bank() {
+    let phone = "+2348090002201";
+    let (mut holder, picker) = at_the_bank_picker(phone, &["Opay", "Kuda"]).await;
+
+    let content = picker.content.expect("a list picker");
+    assert_eq!(content.sid, BANK_LIST_SID);
+    assert_eq!(content.variables.len(), 3, "{:?}", content.variables);
+    assert!(
+        content.variables["1"].contains("₦15,000.00"),
+        "{:?}",
+        content.variables
+    );
+    assert_eq!(content.variables["2"], "Opay ******6700");
+    assert_eq!(content.variables["3"], "Kuda ******6701");
+    // The numbered text stays behind it, for the log and for channels without lists
+    assert!(
+        picker.body.contains("2. Kuda ******6701"),
+        "{}",
+        picker.body
+    );
+
+    let typed = tapped(
+        phone,
+        &[("ListId", "bank_2"), ("ListTitle", "Kuda ******6701")],
+    );
+    assert_eq!(typed, "2");
+    let replies = say(&mut holder, &typed).await;
+    assert_eq!(holder.state, UserState::SavedBankConfirmation);
+    assert!(replies[0].body.contains("Kuda"), "{:?}", replies[0].body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn more_banks_than_a_list_holds_fall_back_to_the_numbered_menu() {
+    let banks = [
+        "Opay", "Kuda", "GTBank", "Access", "UBA", "Zenith", "FCMB", "Wema", "Sterling", "Union",
+        "Polaris",
+    ];
+    let (_, picker) = at_the_bank_picker("+2348090002202", &banks).await;
+    assert!(picker.content.is_none());
+    assert!(picker.body.contains("11. Polaris"), "{}", picker.body);
+}
+
+/// Asserts `prompt` goes out through the `sid` template, carrying its text whole.
+fn assert_buttons(prompt: &OutboundMessage, sid: &str) {
+    let content = prompt.content.as_ref().expect("buttons");
+    assert_eq!(content.sid, sid);
+    assert_eq!(
+        content.variables.values().collect::<Vec<_>>(),
+        [&prompt.body]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_quote_and_saved_bank_prompts_have_buttons_whose_taps_go_ahead() {
+    let phone = "+2348090002203";
+    app().await;
+    mock_post(
+        "/banks",
+        json!({
+            "phone": phone.trim_start_matches('+'),
+            "bank_save_id": "buttons-saved",
+            "bank_name": "Opay",
+            "account_number": "0123456789",
+            "account_name": "ADA OBI",
+            "bank_code": "999",
+        }),
+    )
+    .await;
+    let mut holder = UserSessions::new(phone);
+    holder.controller_address = Some("0x0test".to_string());
+
+    let replies = say(&mut holder, "withdraw 10 USDT").await;
+    assert_buttons(&replies[0], QUOTE_SID);
+    assert!(
+        replies[0].body.contains("₦15,000.00"),
+        "{}",
+        replies[0].body
+    );
+
+    let confirm = tapped(
+        phone,
+        &[("ButtonPayload", "confirm"), ("ButtonText", "Confirm")],
+    );
+    let replies = say(&mut holder, &confirm).await;
+    assert_eq!(holder.state, UserState::SavedBankConfirmation);
+    let prompt = replies.last().unwrap();
+    assert_buttons(prompt, SAVED_BANK_SID);
+    assert!(prompt.body.contains("Opay"), "{}", prompt.body);
+
+    let yes = tapped(phone, &[("ButtonPayload", "yes"), ("ButtonText", "Yes")]);
+    let replies = say(&mut holder, &yes).await;
+    assert!(
+        replies[0]
+            .body
+            .starts_with("✅ *Withdrawal Successfully Initiated!*"),
+        "{}",
+        replies[0].body
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_verified_new_bank_has_buttons_and_no_asks_for_other_details() {
+    let phone = "+2348090002204";
+    app().await;
+    let mut holder = UserSessions::new(phone);
+    holder.controller_address = Some("0x0test".to_string());
+    say(&mut holder, "withdraw 10 USDT").await;
+    say(&mut holder, "confirm").await;
+    assert_eq!(holder.state, UserState::BankDetailsEntry);
+
+    let replies = say(&mut holder, "Opay, 0123456789").await;
+    assert_eq!(holder.state, UserState::BankDetailsConfirmation);
+    assert_buttons(replies.last().unwrap(), VERIFIED_BANK_SID);
+
+    let no = tapped(phone, &[("ButtonPayload", "no"), ("ButtonText", "No")]);
+    say(&mut holder, &no).await;
+    assert_eq!(holder.state, UserState::BankDetailsEntry);
+    assert!(holder.pending_bank_verification.is_none());
+    assert_eq!(holder.pending_amount, Some(10.0));
+}
@@ -0,0 +1,148 @@
+//! The typed amount confirmation for large withdrawals, against the mock backend with
+//! a threshold of 100 USDT and wallets funded well past the absurd-amount threshold.
+
+mod common;
+
+use std::time::Duration;
+
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::transactions;
+use kharon_pay_whatsapp::twilio;
+
+use common::start_on_backend;
+
+async fn app() {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts, and before any test reads them
+        unsafe {
+            std::env::set_var("LARGE_WITHDRAWAL_THRESHOLD", "100");
+            std::env::set_var("MOCK_BALANCE", "10000000.00");
+        }
+    })
+    .await;
+}
+
+async fn say(session: &mut UserSessions, message: &str) -> Vec<String> {
+    let (replies, _) = twilio::capture(dispatch_message(message, session), Duration::ZERO).await;
+    replies.into_iter().map(|reply| reply.body).collect()
+}
+
+/// `phone` with a saved bank, having said yes to withdrawing `amount` USDT to it.
+async fn after_the_final_yes(phone: &str, amount: &str) -> (UserSessions, Vec<String>) {
+    app().await;
+    start_on_backend(phone).await;
+    let mut holder = UserSessions::new(phone);
+    holder.controller_address = Some("0x0test".to_string());
+    say(&mut holder, &format!("withdraw {} USDT", amount)).await;
+    say(&mut holder, "confirm").await;
+    assert_eq!(holder.state, UserState::SavedBankConfirmation);
+    let replies = say(&mut holder, "yes").await;
+    (holder, replies)
+}
+
+fn initiated(replies: &[String]) -> bool {
+    replies[0].starts_with("✅ *Withdrawal Successfully Initiated!*")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_withdrawal_at_or_below_the_threshold_skips_the_typed_confirmation() {
+    for (phone, amount) in [("+2348090001201", "50"), ("+2348090001202", "100")] {
+        let (holder, replies) = after_the_final_yes(phone, amount).await;
+        assert!(initiated(&replies), "{}: {:?}", amount, replies);
+        assert_eq!(holder.state, UserState::Initial);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn typing_the_amount_back_in_any_format_goes_ahead() {
+    for (phone, typed) in [
+        ("+2348090001203", "200"),
+        ("+2348090001204", "200.00"),
+        ("+2348090001205", " 200.0 "),
+    ] {
+        let (mut holder, replies) = after_the_final_yes(phone, "200").await;
+        assert_eq!(holder.state, UserState::AmountConfirmation);
+        assert!(
+            replies[0].contains("To confirm, please type *200*"),
+            "{:?}",
+            replies
+        );
+        // Nothing has gone to the backend yet
+        assert!(transactions::for_phone(phone).await.is_empty());
+
+        let replies = say(&mut holder, typed).await;
+        assert!(initiated(&replies), "{:?}: {:?}", typed, replies);
+        assert_eq!(holder.state, UserState::Initial);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_mismatched_amount_is_asked_twice_more_then_cancelled() {
+    let phone = "+2348090001206";
+    let (mut holder, _) = after_the_final_yes(phone, "200").await;
+
+    for typed in ["20", "2000"] {
+        let replies = say(&mut holder, typed).await;
+        assert!(
+            replies[0].starts_with("❌ That doesn't match.")
+                && replies[0].contains("To confirm, please type *200*"),
+            "{:?}",
+            replies
+        );
+        assert_eq!(holder.state, UserState::AmountConfirmation);
+    }
+
+    let replies = say(&mut holder, "yes").await;
+    assert!(
+        replies[0].starts_with("❌ *Withdrawal Cancelled*")
+            && replies[0].contains("nothing was sent"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(holder.state, UserState::Initial);
+    assert!(holder.pending_amount.is_none());
+    assert!(transactions::for_phone(phone).await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_absurd_amount_is_spelled_out_before_it_goes_ahead() {
+    let phone = "+2348090001207";
+    let (mut holder, replies) = after_the_final_yes(phone, "5000000").await;
+    assert_eq!(holder.state, UserState::AmountConfirmation);
+    assert!(
+        replies[0].starts_with("🚨 *Unusually large withdrawal*")
+            && replies[0].contains("*five million* USDT")
+            && replies[0].contains("type `cancel`")
+            && replies[0].contains("to confirm, type *5000000*"),
+        "{:?}",
+        replies
+    );
+    assert!(transactions::for_phone(phone).await.is_empty());
+
+    // Read as five, it's asked again in words
+    let replies = say(&mut holder, "5").await;
+    assert!(
+        replies[0].starts_with("❌ That doesn't match.") && replies[0].contains("*five million*"),
+        "{:?}",
+        replies
+    );
+
+    let replies = say(&mut holder, "5,000,000").await;
+    assert!(initiated(&replies), "{:?}", replies);
+    assert_eq!(holder.state, UserState::Initial);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_amount_too_large_to_price_is_not_quoted() {
+    app().await;
+    let mut holder = UserSessions::new("+2348090001208");
+    holder.controller_address = Some("0x0test".to_string());
+    let replies = say(&mut holder, "quote 1000000000000 USDT").await;
+    assert!(
+        replies[0].starts_with("❌ That amount is too large to quote."),
+        "{:?}",
+        replies
+    );
+    assert_eq!(holder.state, UserState::Initial);
+}
@@ -0,0 +1,105 @@
+//! Fires concurrent webhook POSTs and reports latency percentiles. Ignored by default:
+//!
+//! `cargo test --release --test load -- --ignored --nocapture`
+//!
+//! `LOAD_REQUESTS` (default 500) and `LOAD_CONCURRENCY` (default 50) size the run.
+//! Against the app served in-process on the mock backend unless `LOAD_TARGET_URL` points
+//! at a running one (a staging server with `MOCK_BACKEND=1`; never production).
+
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Semaphore;
+
+const MESSAGES: &[&str] = &["hi", "help", "quote 100 USDT", "balance", "rate?"];
+
+fn env_usize(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(default)
+}
+
+/// Nearest-rank percentile of sorted latencies.
+fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+    let rank = (pct * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+async fn wait_until_up(client: &reqwest::Client, base: &str) {
+    for _ in 0..100 {
+        if client.get(format!("{}/livez", base)).send().await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{} never came up", base);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "load test; run explicitly with --ignored"]
+async fn concurrent_webhooks() {
+    let requests = env_usize("LOAD_REQUESTS", 500);
+    let concurrency = env_usize("LOAD_CONCURRENCY", 50);
+    let base = match std::env::var("LOAD_TARGET_URL") {
+        Ok(url) => url.trim_end_matches('/').to_string(),
+        Err(_) => common::spawn_app(),
+    };
+    let client = reqwest::Client::new();
+    wait_until_up(&client, &base).await;
+
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let started = Instant::now();
+    let mut tasks = Vec::with_capacity(requests);
+    for i in 0..requests {
+        let permit = permits.clone().acquire_owned().await.expect("semaphore open");
+        let (client, url) = (client.clone(), format!("{}/webhook", base));
+        // One number per request, so the throttle and per-user turns don't serialise them
+        let form = [
+            ("From", format!("whatsapp:+23470{:08}", i)),
+            ("Body", MESSAGES[i % MESSAGES.len()].to_string()),
+            ("SmsStatus", "received".to_string()),
+            ("MessageSid", format!("SMload{:026}", i)),
+        ];
+        tasks.push(tokio::spawn(async move {
+            let sent = Instant::now();
+            let status = client.post(url).form(&form).send().await.map(|r| r.status());
+            drop(permit);
+            (sent.elapsed(), status)
+        }));
+    }
+
+    let mut latencies = Vec::with_capacity(requests);
+    let mut failures = 0;
+    for task in tasks {
+        let (latency, status) = task.await.expect("request task");
+        match status {
+            Ok(status) if status.is_success() => latencies.push(latency),
+            _ => failures += 1,
+        }
+    }
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+
+    println!(
+        "{} requests, {} concurrent, in {:.2?} ({:.0}/s); {} failed",
+        requests,
+        concurrency,
+        elapsed,
+        requests as f64 / elapsed.as_secs_f64(),
+        failures
+    );
+    if !latencies.is_empty() {
+        println!(
+            "p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}",
+            percentile(&latencies, 50),
+            percentile(&latencies, 90),
+            percentile(&latencies, 99),
+            latencies[latencies.len() - 1]
+        );
+    }
+    assert_eq!(failures, 0, "some webhooks failed");
+}
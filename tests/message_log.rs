@@ -0,0 +1,152 @@
+//! The outbound message log: written as messages go out, updated by Twilio's delivery
+//! callbacks, and read back by support through `/admin/messages`.
+
+mod common;
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use kharon_pay_whatsapp::message_log;
+use kharon_pay_whatsapp::twilio;
+use reqwest::StatusCode;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+const ADMIN_KEY: &str = "message-log-admin-key";
+
+async fn app() -> &'static str {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts, and before any test reads it
+        unsafe { std::env::set_var("ADMIN_API_KEY", ADMIN_KEY) };
+    })
+    .await
+}
+
+/// `/admin/messages` for `phone`, optionally `since`.
+async fn logged(phone: &str, since: Option<DateTime<Utc>>) -> Vec<Value> {
+    let mut query = vec![("phone", phone.to_string())];
+    if let Some(since) = since {
+        query.push(("since", since.to_rfc3339()));
+    }
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/messages", app().await))
+        .header("x-admin-key", ADMIN_KEY)
+        .query(&query)
+        .send()
+        .await
+        .expect("admin answers");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    serde_json::from_value(body["messages"].clone()).unwrap()
+}
+
+/// `logged`, once the writer has caught up with `ready`.
+async fn logged_once(phone: &str, ready: impl Fn(&[Value]) -> bool) -> Vec<Value> {
+    for _ in 0..100 {
+        let messages = logged(phone, None).await;
+        if ready(&messages) {
+            return messages;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    logged(phone, None).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_sent_message_is_logged_by_its_hash_with_the_phone_masked() {
+    let phone = "+2348090002001";
+    app().await;
+    twilio::send_twilio_message(phone, "✅ Your withdrawal is on its way").await;
+
+    let messages = logged_once(phone, |messages| !messages.is_empty()).await;
+    assert_eq!(messages.len(), 1, "{:?}", messages);
+    let message = &messages[0];
+    assert_eq!(
+        message["body_hash"],
+        hex::encode(Sha256::digest(
+            "✅ Your withdrawal is on its way".as_bytes()
+        ))
+    );
+    assert_eq!(message["status"], "dry_run");
+    let stored = message["phone"].as_str().unwrap();
+    assert_ne!(stored, phone);
+    assert!(stored.ends_with("2001"), "{}", stored);
+    // Nobody else's messages
+    assert!(logged("+2348090002002", None).await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_delivery_callback_updates_the_logged_status() {
+    let phone = "+2348090002003";
+    let base = app().await;
+    message_log::record_sent(phone, "🔔 Reminder", Some("SM2003".to_string()), "queued");
+    logged_once(phone, |messages| !messages.is_empty()).await;
+
+    let callback = serde_urlencoded::to_string([
+        ("MessageSid", "SM2003"),
+        ("MessageStatus", "delivered"),
+        ("SmsStatus", "delivered"),
+        ("To", "whatsapp:+2348090002003"),
+        ("From", "whatsapp:+14155238886"),
+    ])
+    .unwrap();
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhook", base))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(callback)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let messages = logged_once(phone, |messages| {
+        messages
+            .first()
+            .is_some_and(|message| message["status"] == "delivered")
+    })
+    .await;
+    assert_eq!(messages[0]["status"], "delivered", "{:?}", messages);
+    assert_eq!(messages[0]["message_sid"], "SM2003");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_log_is_queried_since_a_time_and_within_retention() {
+    let phone = "+2348090002004";
+    app().await;
+    message_log::record_sent(phone, "first", Some("SM2004-1".to_string()), "sent");
+    logged_once(phone, |messages| messages.len() == 1).await;
+    let between = Utc::now();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    message_log::record_sent(phone, "second", Some("SM2004-2".to_string()), "sent");
+    let messages = logged_once(phone, |messages| messages.len() == 2).await;
+    // Newest first
+    assert_eq!(messages[0]["message_sid"], "SM2004-2");
+    assert_eq!(messages[1]["message_sid"], "SM2004-1");
+
+    let since = logged(phone, Some(between)).await;
+    assert_eq!(since.len(), 1, "{:?}", since);
+    assert_eq!(since[0]["message_sid"], "SM2004-2");
+
+    // A message older than the retention window, as if pruning hadn't run yet
+    let conn = rusqlite::Connection::open(message_log::message_log_path()).unwrap();
+    conn.execute(
+        "INSERT INTO outbound_messages
+             (phone, phone_hash, body_hash, sent_at, message_sid, status, status_updated_at)
+         SELECT phone, phone_hash, body_hash, ?1, 'SM2004-OLD', status, ?1
+         FROM outbound_messages WHERE message_sid = 'SM2004-1'",
+        [(Utc::now() - chrono::Duration::days(31)).to_rfc3339()],
+    )
+    .unwrap();
+    assert_eq!(logged(phone, None).await.len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_log_needs_the_admin_key() {
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/messages", app().await))
+        .query(&[("phone", "+2348090002001")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,65 @@
+//! `/metrics` on the app as `main` wires it.
+
+mod common;
+
+use std::time::Duration;
+
+async fn wait_until_up(client: &reqwest::Client, base: &str) {
+    for _ in 0..100 {
+        if client.get(format!("{}/livez", base)).send().await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{} never came up", base);
+}
+
+#[tokio::test]
+async fn requests_are_timed_by_route_and_webhooks_by_kind() {
+    let base = common::spawn_app();
+    let client = reqwest::Client::new();
+    wait_until_up(&client, &base).await;
+
+    let response = client
+        .post(format!("{}/webhook", base))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(common::twilio_fixture("status_delivered"))
+        .send()
+        .await
+        .expect("webhook answered");
+    assert!(response.status().is_success());
+    let response = client
+        .get(format!("{}/admin/sessions/+2348012345678/typo", base))
+        .send()
+        .await
+        .expect("404 answered");
+    assert_eq!(response.status(), 404);
+
+    let response = client
+        .get(format!("{}/metrics", base))
+        .send()
+        .await
+        .expect("metrics answered");
+    assert!(response.status().is_success());
+    assert!(
+        response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain")
+    );
+    let text = response.text().await.expect("metrics body");
+
+    assert!(text.contains("# TYPE http_request_duration_seconds histogram"));
+    assert!(text.contains(
+        "http_request_duration_seconds_count{method=\"POST\",route=\"/webhook\",status=\"200\"} 1"
+    ));
+    assert!(text.contains(
+        "http_request_duration_seconds_count{method=\"GET\",route=\"unmatched\",status=\"404\"} 1"
+    ));
+    assert!(text.contains(
+        "webhook_duration_seconds_count{kind=\"status_callback\",handling=\"inline\"} 1"
+    ));
+    assert!(text.contains("webhook_inbound_total{kind=\"status_callback\"} 1"));
+    // Numbers from the URL never become label values
+    assert!(!text.contains("2348012345678"));
+}
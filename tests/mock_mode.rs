@@ -0,0 +1,132 @@
+//! The built binary run with `MOCK_BACKEND=1` and nothing else, the way a contributor
+//! runs it locally: a whole conversation, from greeting to completed withdrawal, read
+//! back from what it prints instead of sending. It serves on its fixed port, 6500.
+
+mod common;
+
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const PHONE: &str = "+2348090002601";
+
+/// The running binary and everything it has printed so far; killed when dropped, so a
+/// failed test doesn't leave it holding the port.
+struct Bot {
+    child: Child,
+    printed: Arc<Mutex<String>>,
+}
+
+impl Bot {
+    fn start() -> Self {
+        let dir = common::scratch_dir().join("mock-mode");
+        std::fs::create_dir_all(&dir).expect("mock-mode dir");
+        let mut child = Command::new(env!("CARGO_BIN_EXE_kharon-pay-whatsapp"))
+            .env_clear()
+            .env("MOCK_BACKEND", "1")
+            .env("MOCK_COMPLETE_AFTER_SECS", "1")
+            .env("POLL_INTERVAL_SECS", "1")
+            .current_dir(dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("binary runs");
+
+        let printed = Arc::new(Mutex::new(String::new()));
+        let mut stdout = child.stdout.take().expect("stdout");
+        let sink = printed.clone();
+        std::thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            while let Ok(read) = stdout.read(&mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                sink.lock()
+                    .unwrap()
+                    .push_str(&String::from_utf8_lossy(&buffer[..read]));
+            }
+        });
+        Bot { child, printed }
+    }
+
+    /// Sends `body` from `PHONE` as Twilio would, then waits for `expected` to be printed
+    /// after it.
+    async fn say(&self, body: &str, expected: &str) {
+        let from = self.printed.lock().unwrap().len();
+        let client = reqwest::Client::new();
+        let started = Instant::now();
+        loop {
+            let sent = client
+                .post("http://127.0.0.1:6500/webhook")
+                .form(&[
+                    ("From", format!("whatsapp:{}", PHONE)),
+                    ("Body", body.to_string()),
+                ])
+                .send()
+                .await;
+            match sent {
+                Ok(response) => {
+                    assert!(response.status().is_success(), "{}", response.status());
+                    break;
+                }
+                // Still starting up
+                Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                Err(e) => panic!("the bot never answered: {}", e),
+            }
+        }
+        self.wait_for(from, expected).await;
+    }
+
+    async fn wait_for(&self, from: usize, expected: &str) {
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(15) {
+            if self.printed.lock().unwrap()[from..].contains(expected) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!(
+            "{:?} never printed after {:?}; printed:\n{}",
+            expected,
+            from,
+            self.printed.lock().unwrap()
+        );
+    }
+}
+
+impl Drop for Bot {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_whole_withdrawal_runs_against_the_built_in_mock() {
+    let bot = Bot::start();
+    bot.say("hi", "Welcome to *Kharon Pay*").await;
+    bot.say("create", "Choose a username").await;
+    bot.say("mockuser", "Account created successfully").await;
+    bot.say("balance", "USDT: 250.00").await;
+    bot.say("withdraw 10 USDT", "You'll receive: ₦15,000.00")
+        .await;
+    bot.say("confirm", "Bank Details Required").await;
+    bot.say("Opay, 0123456789", "Account Verified").await;
+
+    let from = bot.printed.lock().unwrap().len();
+    bot.say("yes", "Withdrawal Successfully Initiated").await;
+    // The mock settles it a second after the payment, and the poller announces it
+    bot.wait_for(from, "Withdrawal Completed Successfully")
+        .await;
+
+    let printed = bot.printed.lock().unwrap().clone();
+    assert!(printed.contains("MOCK_BACKEND is on"), "{}", printed);
+    assert!(
+        printed.contains(&format!("📤 To {}:", PHONE)),
+        "{}",
+        printed
+    );
+}
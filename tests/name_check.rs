@@ -0,0 +1,108 @@
+//! The name check on a newly entered bank account, against the mock backend, whose banks
+//! all verify to `MOCK ACCOUNT HOLDER` and whose profiles carry `MOCK_PROFILE_NAME`.
+
+mod common;
+
+use std::time::Duration;
+
+use kharon_pay_whatsapp::audit::{self, AuditEvent};
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::namecheck;
+use kharon_pay_whatsapp::twilio;
+
+async fn say(session: &mut UserSessions, message: &str) -> String {
+    let (replies, _) = twilio::capture(dispatch_message(message, session), Duration::ZERO).await;
+    replies
+        .into_iter()
+        .map(|reply| reply.body)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// What `phone` is told after entering a new bank account for a withdrawal.
+async fn enter_bank(phone: &str) -> (UserSessions, String) {
+    let mut session = UserSessions::new(phone);
+    session.controller_address = Some("0x0test".to_string());
+    say(&mut session, "withdraw 10 USDT").await;
+    let reply = say(&mut session, "confirm").await;
+    assert!(reply.contains("Bank Details Required"), "{}", reply);
+    let reply = say(&mut session, "Opay, 0123456789").await;
+    (session, reply)
+}
+
+/// The `(similarity, blocked)` of every mismatch audited so far.
+async fn mismatches(expected: usize) -> Vec<(f64, bool)> {
+    let mut found = Vec::new();
+    for _ in 0..50 {
+        found = audit::read_entries()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|entry| match entry.event {
+                AuditEvent::AccountNameMismatch {
+                    similarity,
+                    blocked,
+                    ..
+                } => Some((similarity, blocked)),
+                _ => None,
+            })
+            .collect();
+        if found.len() >= expected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    found
+}
+
+// One test, since the check reads its settings and the mock its profile name on every
+// message
+#[tokio::test(flavor = "multi_thread")]
+async fn a_different_name_warns_or_blocks_and_is_audited_either_way() {
+    common::app().await;
+
+    // The profile name, however it's written, is the account's
+    // SAFETY: this test is the only one in the process that reads them
+    unsafe { std::env::set_var("MOCK_PROFILE_NAME", "Holder, Mock A.") };
+    let (session, reply) = enter_bank("+2348090002801").await;
+    assert!(reply.contains("Account Verified"), "{}", reply);
+    assert!(!reply.contains(namecheck::MISMATCH_WARNING), "{}", reply);
+    assert_eq!(session.state, UserState::BankDetailsConfirmation);
+    assert!(mismatches(0).await.is_empty());
+
+    // Someone else's, by default a warning over the usual confirmation
+    // SAFETY: as above
+    unsafe { std::env::set_var("MOCK_PROFILE_NAME", "Musa Ibrahim") };
+    let (mut session, reply) = enter_bank("+2348090002802").await;
+    assert!(reply.starts_with(namecheck::MISMATCH_WARNING), "{}", reply);
+    assert!(reply.contains("Account Verified"), "{}", reply);
+    assert_eq!(session.state, UserState::BankDetailsConfirmation);
+    let reply = say(&mut session, "yes").await;
+    assert!(
+        reply.contains("Withdrawal Successfully Initiated"),
+        "{}",
+        reply
+    );
+    assert_eq!(mismatches(1).await, [(0.0, false)]);
+
+    // Sharing only a surname is below the default threshold
+    // SAFETY: as above
+    unsafe {
+        std::env::set_var("MOCK_PROFILE_NAME", "Ada Ngozi Holder");
+        std::env::set_var("NAME_MISMATCH_ACTION", "block");
+    }
+    let (session, reply) = enter_bank("+2348090002803").await;
+    assert_eq!(reply, namecheck::MISMATCH_BLOCKED);
+    assert_eq!(session.state, UserState::BankDetailsEntry);
+    assert!(session.pending_bank_verification.is_none());
+    assert_eq!(mismatches(2).await, [(0.0, false), (1.0 / 3.0, true)]);
+
+    // Unless the threshold says otherwise
+    // SAFETY: as above
+    unsafe { std::env::set_var("NAME_MATCH_THRESHOLD", "0.3") };
+    let (session, reply) = enter_bank("+2348090002804").await;
+    assert!(reply.contains("Account Verified"), "{}", reply);
+    assert_eq!(session.state, UserState::BankDetailsConfirmation);
+    assert_eq!(mismatches(2).await.len(), 2);
+}
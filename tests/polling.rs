@@ -0,0 +1,297 @@
+//! Status polling against the mock backend, for withdrawals that settle and ones that
+//! never do.
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use kharon_pay_whatsapp::audit::{self, AuditEvent};
+use kharon_pay_whatsapp::cache;
+use kharon_pay_whatsapp::config::PollConfig;
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::i18n::Language;
+use kharon_pay_whatsapp::metrics;
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::store::{MemorySessionStore, SessionStore};
+use kharon_pay_whatsapp::transactions::{self, IndexedTransaction};
+use kharon_pay_whatsapp::{polling, prefs, reminder};
+use kharon_pay_whatsapp::{twilio, window};
+
+use common::{mock_post, mock_status, start_on_backend};
+
+/// The app, with paid withdrawals completing as soon as they're next checked, and a
+/// polling budget for `PollConfig::from_env` to read.
+async fn app() {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts and before any test reads these; the mock
+        // reads the first on every status check
+        unsafe {
+            std::env::set_var("MOCK_COMPLETE_AFTER_SECS", "0");
+            std::env::set_var("POLL_MAX_WAIT_MINUTES", "45");
+            std::env::set_var("POLL_INTERVAL_SECS", "3");
+            std::env::set_var("SLOW_POLL_HOURS", "0");
+            std::env::set_var("PROGRESS_UPDATE_AFTER_SECS", "0");
+            std::env::set_var(
+                "PROGRESS_UPDATE_MESSAGE",
+                "Still on it: {reference}\\nHang on",
+            );
+        }
+    })
+    .await;
+}
+
+/// Polling fast for a few checks and slow for a few more, with no interim update.
+fn short_config() -> PollConfig {
+    PollConfig {
+        interval: Duration::from_millis(50),
+        max_wait: Duration::from_millis(300),
+        slow_interval: Duration::from_millis(100),
+        slow_window: Duration::from_millis(300),
+        progress_after: None,
+        progress_message: String::new(),
+    }
+}
+
+/// An indexed withdrawal for `phone`. The backend says `pending` until it's paid, so
+/// for good unless the test pays it.
+async fn unsettled_withdrawal(phone: &str) -> String {
+    app().await;
+    let reference = start_on_backend(phone).await;
+    transactions::record(&IndexedTransaction {
+        reference: reference.clone(),
+        phone: phone.to_string(),
+        amount: 10.0,
+        token: "USDT".to_string(),
+        bank_name: "Opay".to_string(),
+        account_name: "ADA OBI".to_string(),
+        masked_account: "6789".to_string(),
+        initiated_at: Utc::now(),
+        status: "initiated".to_string(),
+        status_updated_at: Utc::now(),
+        correlation_id: None,
+        tx_hash: None,
+        language: Language::En,
+    })
+    .await;
+    reference
+}
+
+#[tokio::test]
+async fn the_polling_budget_is_read_in_its_own_units() {
+    app().await;
+    let config = PollConfig::from_env();
+    assert_eq!(config.max_wait, Duration::from_secs(45 * 60));
+    assert_eq!(config.interval, Duration::from_secs(3));
+    assert!(config.slow_window.is_zero());
+    assert_eq!(config.progress_after, None);
+    assert_eq!(config.progress_message, "Still on it: {reference}\nHang on");
+    // Unset, so the default
+    assert_eq!(config.slow_interval, Duration::from_secs(10 * 60));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_withdrawal_that_never_settles_is_reported_once_then_given_up_on() {
+    let phone = "+2348090000801";
+    let reference = unsettled_withdrawal(phone).await;
+    window::record_inbound(phone, Utc::now());
+    let fast = metrics::POLL_TIMEOUTS.get(&["fast"]);
+    let slow = metrics::POLL_TIMEOUTS.get(&["slow"]);
+
+    let polling = async {
+        polling::start_transaction_polling_task_with(reference.clone(), short_config()).await
+    };
+    let (finished, sent) = twilio::capture(polling, Duration::ZERO).await;
+    finished.expect("polling finished");
+
+    assert_eq!(mock_status(&reference).await, "pending");
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    assert_eq!(sent[0].to, phone);
+    assert!(
+        sent[0].body.contains("taking longer than usual"),
+        "{}",
+        sent[0].body
+    );
+    assert!(
+        sent[0].body.contains(&format!("`status {}`", reference)),
+        "{}",
+        sent[0].body
+    );
+    assert_eq!(metrics::POLL_TIMEOUTS.get(&["fast"]), fast + 1);
+    assert_eq!(metrics::POLL_TIMEOUTS.get(&["slow"]), slow + 1);
+
+    let mut trail = Vec::new();
+    for _ in 0..50 {
+        trail = audit::trail_for_reference(&reference).await.unwrap();
+        if !trail.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(
+        trail
+            .iter()
+            .any(|entry| matches!(entry.event, AuditEvent::PollTimedOut { .. })),
+        "{:?}",
+        trail
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn without_a_slow_lane_polling_stops_at_the_first_timeout() {
+    let phone = "+2348090000802";
+    let reference = unsettled_withdrawal(phone).await;
+    window::record_inbound(phone, Utc::now());
+    let config = PollConfig {
+        slow_window: Duration::ZERO,
+        ..short_config()
+    };
+
+    let started = std::time::Instant::now();
+    let polling = async { polling::start_transaction_polling_task_with(reference, config).await };
+    let (finished, sent) = twilio::capture(polling, Duration::ZERO).await;
+    finished.expect("polling finished");
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    assert!(sent[0].body.contains("taking longer than usual"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_completed_withdrawal_is_announced_and_drops_the_cached_balance() {
+    let phone = "+2348090000803";
+    let reference = unsettled_withdrawal(phone).await;
+    window::record_inbound(phone, Utc::now());
+    mock_post("/payments", serde_json::json!({ "reference": reference })).await;
+    cache::store_balance(phone, "USDT", "💰 before the payout");
+
+    let polling = async {
+        polling::start_transaction_polling_task_with(reference.clone(), short_config()).await
+    };
+    let (finished, sent) = twilio::capture(polling, Duration::ZERO).await;
+    finished.expect("polling finished");
+
+    assert!(
+        sent.iter()
+            .any(|message| message.body.contains("Withdrawal Completed")
+                && message.body.contains(&reference)),
+        "{:?}",
+        sent
+    );
+    assert!(
+        !sent
+            .iter()
+            .any(|message| message.body.contains("taking longer")),
+        "{:?}",
+        sent
+    );
+    assert!(cache::cached_balance(phone, "USDT").is_none());
+}
+
+/// `short_config`, with the interim update due after `after` and no slow lane.
+fn with_progress(after: Duration) -> PollConfig {
+    PollConfig {
+        slow_window: Duration::ZERO,
+        progress_after: Some(after),
+        progress_message: "Still processing {reference}".to_string(),
+        ..short_config()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_slow_withdrawal_gets_one_progress_update() {
+    let phone = "+2348090000804";
+    let reference = unsettled_withdrawal(phone).await;
+    window::record_inbound(phone, Utc::now());
+
+    let config = with_progress(Duration::from_millis(100));
+    let polling =
+        async { polling::start_transaction_polling_task_with(reference.clone(), config).await };
+    let (finished, sent) = twilio::capture(polling, Duration::ZERO).await;
+    finished.expect("polling finished");
+
+    let bodies: Vec<&str> = sent.iter().map(|message| message.body.as_str()).collect();
+    assert_eq!(bodies.len(), 2, "{:?}", bodies);
+    assert_eq!(bodies[0], format!("Still processing {}", reference));
+    assert!(
+        bodies[1].contains("taking longer than usual"),
+        "{:?}",
+        bodies
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_withdrawal_that_completes_first_gets_no_progress_update() {
+    let phone = "+2348090000805";
+    let reference = unsettled_withdrawal(phone).await;
+    window::record_inbound(phone, Utc::now());
+    mock_post("/payments", serde_json::json!({ "reference": reference })).await;
+
+    // Due straight away, but the first check already finds it completed
+    let config = with_progress(Duration::ZERO);
+    let polling =
+        async { polling::start_transaction_polling_task_with(reference.clone(), config).await };
+    let (finished, sent) = twilio::capture(polling, Duration::ZERO).await;
+    finished.expect("polling finished");
+
+    assert!(
+        sent.iter()
+            .any(|message| message.body.contains("Withdrawal Completed")),
+        "{:?}",
+        sent
+    );
+    assert!(
+        !sent
+            .iter()
+            .any(|message| message.body.contains("Still processing")),
+        "{:?}",
+        sent
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn notifications_off_stops_reminders_but_not_the_completion_notice() {
+    let phone = "+2348090000806";
+    let reference = unsettled_withdrawal(phone).await;
+    window::record_inbound(phone, Utc::now());
+
+    // `notifications off`, saved the way the webhook saves what a message changed
+    let mut session = UserSessions::new(phone);
+    session.prefs = prefs::load(phone).await;
+    let before = session.prefs.clone();
+    let (replies, _) = twilio::capture(
+        dispatch_message("notifications off", &mut session),
+        Duration::ZERO,
+    )
+    .await;
+    assert!(replies[0].body.contains("off"), "{}", replies[0].body);
+    prefs::save_changes(&before, &session.prefs).await.unwrap();
+
+    // Left mid-withdrawal, which would normally earn a nudge
+    session.state = UserState::OfframpConfirmation;
+    session.pending_amount = Some(10.0);
+    session.pending_currency = Some("USDT".to_string());
+    let store: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    store.save(&session).unwrap();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let (_, nudges) = twilio::capture(
+        reminder::remind_stalled(&store, Duration::ZERO),
+        Duration::ZERO,
+    )
+    .await;
+    assert!(nudges.is_empty(), "{:?}", nudges);
+
+    mock_post("/payments", serde_json::json!({ "reference": reference })).await;
+    let polling = async {
+        polling::start_transaction_polling_task_with(reference.clone(), short_config()).await
+    };
+    let (finished, sent) = twilio::capture(polling, Duration::ZERO).await;
+    finished.expect("polling finished");
+    assert!(
+        sent.iter()
+            .any(|message| message.to == phone && message.body.contains("Withdrawal Completed")),
+        "{:?}",
+        sent
+    );
+}
@@ -0,0 +1,154 @@
+//! Properties over the code that reads whatever users and the network send us: none of
+//! it may panic, and what it accepts has to round-trip.
+
+use std::collections::HashMap;
+
+use kharon_pay_whatsapp::backend;
+use kharon_pay_whatsapp::inbound::{self, Inbound};
+use kharon_pay_whatsapp::money::{self, TokenAmount};
+use kharon_pay_whatsapp::parser::{self, Command, parse_command};
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn parse_command_never_panics(input in any::<String>()) {
+        let _ = parse_command(&input);
+    }
+
+    #[test]
+    fn parse_command_reads_typed_looking_input(
+        words in prop::collection::vec("[a-zA-Z0-9#.,₦$?!-]{0,12}", 0..6),
+    ) {
+        let _ = parse_command(&words.join(" "));
+    }
+
+    #[test]
+    fn withdraw_keeps_the_amount_it_was_given(amount in 1u32..10_000_000, token in "(?i)usdt|usdc") {
+        let command = parse_command(&format!("withdraw {} {}", amount, token));
+        match command {
+            Command::Withdraw { amount: parsed, token: parsed_token, .. } => {
+                prop_assert_eq!(parsed, amount as f64);
+                prop_assert_eq!(parsed_token, token.to_uppercase());
+            }
+            other => prop_assert!(false, "parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn status_nudge_never_panics(input in any::<String>()) {
+        let _ = parser::is_status_nudge(&input);
+    }
+
+    #[test]
+    fn parsed_amounts_are_positive_and_finite(raw in any::<String>()) {
+        if let Some(amount) = parser::parse_amount(&raw) {
+            prop_assert!(amount.is_finite() && amount > 0.0);
+        }
+    }
+
+    #[test]
+    fn token_amount_parse_never_panics(raw in any::<String>(), decimals in 0u32..48) {
+        let _ = TokenAmount::parse(&raw, decimals);
+        let _ = TokenAmount::from_backend(&raw, decimals);
+    }
+
+    #[test]
+    fn token_amount_round_trips_through_display(units in any::<u64>(), decimals in 0u32..19) {
+        let amount = TokenAmount { units: units as u128, decimals };
+        let shown = amount.display().replace(',', "");
+        prop_assert_eq!(TokenAmount::from_backend(&shown, decimals), Ok(amount));
+    }
+
+    #[test]
+    fn typed_integers_are_whole_tokens(whole in 0u64..1_000_000_000, decimals in 0u32..19) {
+        let parsed = TokenAmount::parse(&whole.to_string(), decimals).unwrap();
+        prop_assert_eq!(parsed.units, whole as u128 * 10u128.pow(decimals));
+    }
+
+    #[test]
+    fn typed_amounts_with_too_many_decimals_are_refused(
+        whole in 0u64..1_000_000,
+        fraction in "[0-9]{7,20}",
+    ) {
+        let typed = format!("{}.{}", whole, fraction);
+        prop_assert!(TokenAmount::parse(&typed, 6).is_err());
+    }
+
+    #[test]
+    fn withdraw_all_never_asks_for_more_than_the_balance(units in any::<u64>(), decimals in 0u32..19) {
+        let balance = TokenAmount { units: units as u128, decimals };
+        let amount = balance.at_most_f64();
+        prop_assert!(amount >= 0.0);
+        prop_assert!(TokenAmount::from_whole_tokens(amount, decimals).unwrap() <= balance);
+    }
+
+    #[test]
+    fn token_amount_never_rounds_up(whole in 0u64..1_000_000, fraction in "[0-9]{1,30}") {
+        let parsed = TokenAmount::from_backend(&format!("{}.{}", whole, fraction), 6).unwrap();
+        let exact = whole as f64 + format!("0.{}", fraction).parse::<f64>().unwrap();
+        prop_assert!(parsed.whole_tokens() <= exact + 1e-9);
+    }
+
+    #[test]
+    fn money_formatting_never_panics(amount in any::<f64>(), currency in "[A-Za-z]{0,5}") {
+        let _ = money::format_money(amount, &currency);
+        let _ = money::in_words(amount);
+        let _ = money::naira_for(amount, amount);
+        let _ = money::looks_unscaled(amount, 6, None);
+    }
+
+    #[test]
+    fn signatures_verify_only_what_was_signed(
+        key in "[ -~]{1,64}",
+        method in "GET|POST",
+        path in "/[a-z/?=&0-9]{0,40}",
+        timestamp in "[0-9]{10}",
+        nonce in "[0-9a-f]{32}",
+        body in prop::collection::vec(any::<u8>(), 0..256),
+        flip in any::<prop::sample::Index>(),
+    ) {
+        let signature = backend::sign(&key, &method, &path, &timestamp, &nonce, &body);
+        prop_assert!(backend::verify(&key, &method, &path, &timestamp, &nonce, &body, &signature));
+
+        let mut tampered = body.clone();
+        if tampered.is_empty() {
+            tampered.push(0);
+        } else {
+            let i = flip.index(tampered.len());
+            tampered[i] ^= 0x01;
+        }
+        prop_assert!(!backend::verify(&key, &method, &path, &timestamp, &nonce, &tampered, &signature));
+        let other_key = format!("{}x", key);
+        prop_assert!(!backend::verify(&other_key, &method, &path, &timestamp, &nonce, &body, &signature));
+    }
+
+    #[test]
+    fn verify_never_panics_on_a_garbage_signature(signature in any::<String>()) {
+        prop_assert!(!backend::verify("key", "POST", "/", "0", "n", b"", &signature));
+    }
+
+    #[test]
+    fn webhook_forms_always_classify(body in any::<Vec<u8>>()) {
+        if let Ok(form) = serde_urlencoded::from_bytes::<HashMap<String, String>>(&body) {
+            let _ = inbound::classify(&form);
+        }
+    }
+
+    #[test]
+    fn typed_messages_keep_their_sender_and_body(
+        digits in "[0-9]{10,13}",
+        body in "[^\u{0}]{1,200}",
+    ) {
+        prop_assume!(!body.trim().is_empty());
+        let form: HashMap<String, String> = [
+            ("From".to_string(), format!("whatsapp:+{}", digits)),
+            ("Body".to_string(), body.clone()),
+            ("SmsStatus".to_string(), "received".to_string()),
+        ]
+        .into();
+        prop_assert_eq!(
+            inbound::classify(&form),
+            Inbound::Text { phone: format!("+{}", digits), body }
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! `/livez` and `/readyz`: against the app in-process while healthy and with the backend
+//! down, and against the built binary while it drains for shutdown.
+
+mod common;
+
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+async fn get(url: &str) -> (u16, Value) {
+    let response = reqwest::get(url).await.expect("answered");
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap_or_default())
+}
+
+// One test, since the backend probe reads `BACKEND_HEALTH_URL` on every request
+#[tokio::test(flavor = "multi_thread")]
+async fn readiness_follows_the_backend_while_liveness_ignores_it() {
+    let base = common::app().await;
+
+    // SAFETY: this test is the only one in the process that reads it
+    unsafe { std::env::set_var("BACKEND_HEALTH_URL", format!("{}/livez", base)) };
+    let (status, body) = get(&format!("{}/readyz", base)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["status"], "ready");
+    for check in ["session_store", "backend", "background_tasks", "outbound"] {
+        assert_eq!(body["checks"][check]["status"], "ok", "{}: {}", check, body);
+    }
+    assert!(body["outbound_in_flight"].is_u64(), "{}", body);
+
+    // Nothing listens on the discard port
+    // SAFETY: as above
+    unsafe { std::env::set_var("BACKEND_HEALTH_URL", "http://127.0.0.1:9/health") };
+    let (status, body) = get(&format!("{}/readyz", base)).await;
+    assert_eq!(status, 503, "{}", body);
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["checks"]["backend"]["status"], "down");
+    assert!(body["checks"]["backend"]["error"].is_string(), "{}", body);
+    assert_eq!(body["checks"]["session_store"]["status"], "ok");
+
+    let (status, body) = get(&format!("{}/livez", base)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["status"], "alive");
+}
+
+/// The built binary in mock mode, killed when dropped. It serves on its fixed port, 6500.
+struct Bot(Child);
+
+impl Drop for Bot {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn readiness_turns_503_on_sigterm_while_the_listener_drains() {
+    let dir = common::scratch_dir().join("readiness");
+    std::fs::create_dir_all(&dir).expect("readiness dir");
+    let mut bot = Bot(Command::new(env!("CARGO_BIN_EXE_kharon-pay-whatsapp"))
+        .env_clear()
+        .env("MOCK_BACKEND", "1")
+        .env("SHUTDOWN_DRAIN_SECS", "2")
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("binary runs"));
+    let base = "http://127.0.0.1:6500";
+    let started = Instant::now();
+    while reqwest::get(format!("{}/livez", base)).await.is_err() {
+        assert!(started.elapsed() < Duration::from_secs(10), "never started");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(get(&format!("{}/readyz", base)).await.0, 200);
+
+    let killed = Command::new("kill")
+        .args(["-TERM", &bot.0.id().to_string()])
+        .status()
+        .expect("kill runs");
+    assert!(killed.success());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // Still listening, but telling the load balancer to go elsewhere
+    let (status, body) = get(&format!("{}/readyz", base)).await;
+    assert_eq!(status, 503, "{}", body);
+    assert_eq!(body["status"], "draining");
+    assert_eq!(get(&format!("{}/livez", base)).await.0, 200);
+
+    // Then it stops by itself once the drain is over
+    let started = Instant::now();
+    loop {
+        if let Some(exit) = bot.0.try_wait().unwrap() {
+            assert!(exit.success(), "{:?}", exit);
+            break;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "never stopped");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
@@ -0,0 +1,91 @@
+//! Stale-flow reconciliation over a seeded session database, with the mock backend.
+
+mod common;
+
+use std::sync::Arc;
+
+use kharon_pay_whatsapp::metrics;
+use kharon_pay_whatsapp::model::{PendingPayout, UserSessions, UserState};
+use kharon_pay_whatsapp::reconcile;
+use kharon_pay_whatsapp::store::{SessionStore, SqliteSessionStore};
+use rusqlite::{Connection, params};
+
+use common::{app, mock_status, start_on_backend};
+
+/// Writes `session` as last saved `hours_ago`, the way a crashed or idle build left it.
+fn seed(path: &str, session: &UserSessions, hours_ago: i64) {
+    let conn = Connection::open(path).unwrap();
+    conn.execute(
+        "INSERT OR REPLACE INTO sessions (phone, data, updated_at) VALUES (?1, ?2, ?3)",
+        params![
+            session.phone,
+            serde_json::to_string(session).unwrap(),
+            chrono::Utc::now().timestamp() - hours_ago * 3600
+        ],
+    )
+    .unwrap();
+}
+
+fn in_state(phone: &str, state: UserState) -> UserSessions {
+    let mut session = UserSessions::new(phone);
+    session.state = state;
+    session.pending_amount = Some(10.0);
+    session.pending_currency = Some("USDT".to_string());
+    session
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn only_flows_left_past_the_timeout_are_reset() {
+    app().await;
+    let path = common::scratch_dir().join("reconcile.db");
+    let path = path.to_string_lossy().into_owned();
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+    let sessions = SqliteSessionStore::open(&path).expect("store opens");
+
+    let reference = start_on_backend("+2348040000001").await;
+    let mut approving = in_state("+2348040000001", UserState::PayoutApproval);
+    approving.pending_payout = Some(PendingPayout {
+        reference: reference.clone(),
+        amount: 10.0,
+        currency: "USDT".to_string(),
+        bank_name: "Opay".to_string(),
+        account_name: "ADA OBI".to_string(),
+        crypto_tx_hash: "0xabc".to_string(),
+    });
+    seed(&path, &approving, 7);
+    seed(
+        &path,
+        &in_state("+2348040000002", UserState::OfframpConfirmation),
+        7,
+    );
+    // Idle, but not mid-flow
+    seed(&path, &UserSessions::new("+2348040000003"), 7);
+    // Mid-flow, but recent
+    seed(
+        &path,
+        &in_state("+2348040000004", UserState::OfframpConfirmation),
+        1,
+    );
+
+    let store: Arc<dyn SessionStore> = Arc::new(sessions);
+    let counted = metrics::STALE_SESSIONS_RESET.get(&[]);
+    assert_eq!(reconcile::reconcile(&store).await, 2);
+    assert_eq!(metrics::STALE_SESSIONS_RESET.get(&[]), counted + 2);
+
+    let load = |phone: &str| store.load(phone).unwrap().expect("still stored");
+    for phone in ["+2348040000001", "+2348040000002"] {
+        let session = load(phone);
+        assert_eq!(session.state, UserState::Initial, "{}", phone);
+        assert_eq!(session.pending_amount, None, "{}", phone);
+        assert!(session.pending_payout.is_none(), "{}", phone);
+    }
+    assert_eq!(load("+2348040000003").state, UserState::Initial);
+    assert_eq!(load("+2348040000004").state, UserState::OfframpConfirmation);
+    // The payout it was waiting to approve won't go out
+    assert_eq!(mock_status(&reference).await, "cancelled");
+
+    // Nothing is left for a second pass
+    assert_eq!(reconcile::reconcile(&store).await, 0);
+}
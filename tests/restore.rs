@@ -0,0 +1,370 @@
+//! The first message after a restart, for a session left in each mid-flow state: saved,
+//! aged past the process start and loaded back, the way a deploy leaves it.
+
+mod common;
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use kharon_pay_whatsapp::build_info;
+use kharon_pay_whatsapp::conversation::{dispatch_message, resume_restored};
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::store::{MemorySessionStore, SessionStore, SqliteSessionStore};
+use kharon_pay_whatsapp::twilio;
+use rusqlite::{Connection, params};
+use serde_json::json;
+
+use common::{mock_post, start_on_backend};
+
+const RECAP: &str = "🔄 Picking up where you left off.";
+
+/// The app, with withdrawals over 100 needing their amount typed back so one fits in the
+/// mock's balance.
+async fn app() {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts, and before any test reads it
+        unsafe { std::env::set_var("LARGE_WITHDRAWAL_THRESHOLD", "100") };
+    })
+    .await;
+}
+
+/// One database for this test binary, and its path.
+fn sqlite() -> &'static (SqliteSessionStore, String) {
+    static STORE: OnceLock<(SqliteSessionStore, String)> = OnceLock::new();
+    STORE.get_or_init(|| {
+        common::setup();
+        build_info::mark_started();
+        let path = common::scratch_dir().join("restore.db");
+        let path = path.to_string_lossy().into_owned();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+        let store = SqliteSessionStore::open(&path).expect("store opens");
+        (store, path)
+    })
+}
+
+/// `session` as the store hands it back after a restart: saved an hour before this
+/// process started.
+fn restore(session: &UserSessions) -> UserSessions {
+    let (store, path) = sqlite();
+    store.save(session).expect("saved");
+    Connection::open(path)
+        .unwrap()
+        .execute(
+            "UPDATE sessions SET updated_at = ?1 WHERE phone = ?2",
+            params![build_info::started_at().timestamp() - 3600, session.phone],
+        )
+        .unwrap();
+    let restored = store.load(&session.phone).unwrap().expect("loaded");
+    assert!(restored.restored);
+    assert_eq!(restored.state, session.state);
+    restored
+}
+
+async fn say(session: &mut UserSessions, message: &str) -> Vec<String> {
+    let (replies, _) = twilio::capture(dispatch_message(message, session), Duration::ZERO).await;
+    replies.into_iter().map(|reply| reply.body).collect()
+}
+
+/// The replies to the first message after the restart.
+async fn resume(session: &mut UserSessions, message: &str) -> Vec<String> {
+    let (replies, _) = twilio::capture(resume_restored(message, session), Duration::ZERO).await;
+    replies.into_iter().map(|reply| reply.body).collect()
+}
+
+fn account_holder(phone: &str) -> UserSessions {
+    let mut session = UserSessions::new(phone);
+    session.controller_address = Some("0x0test".to_string());
+    session
+}
+
+/// Saves an Opay account for `phone` on the mock backend.
+async fn save_bank(phone: &str, account_name: &str) {
+    let digits = phone.trim_start_matches('+');
+    mock_post(
+        "/banks",
+        json!({
+            "phone": digits,
+            "bank_save_id": format!("save-{}", digits),
+            "bank_name": "Opay",
+            "account_number": "0123456789",
+            "account_name": account_name,
+            "bank_code": "999",
+        }),
+    )
+    .await;
+}
+
+fn assert_recap(replies: &[String], prompt: &str) {
+    assert!(replies.len() >= 2, "{:?}", replies);
+    assert!(replies[0].starts_with(RECAP), "{:?}", replies);
+    assert!(replies[0].contains(prompt), "{:?}", replies);
+}
+
+#[test]
+fn only_rows_saved_before_this_process_started_are_restored() {
+    let (store, _) = sqlite();
+    let session = UserSessions::new("+2348090002901");
+    store.save(&session).unwrap();
+    assert!(!store.load(&session.phone).unwrap().unwrap().restored);
+
+    assert!(restore(&session).restored);
+
+    let memory = MemorySessionStore::new();
+    memory.save(&session).unwrap();
+    assert!(!memory.load(&session.phone).unwrap().unwrap().restored);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_fresh_quote_is_recapped_and_confirmed_as_usual() {
+    app().await;
+    let mut session = account_holder("+2348090002902");
+    say(&mut session, "withdraw 10 USDT").await;
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "confirm").await;
+    assert_recap(
+        &replies,
+        "You were withdrawing 10.00 USDT — reply `confirm` to continue or `cancel` to stop.",
+    );
+    assert!(
+        replies[1].contains("Bank Details Required"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(session.state, UserState::BankDetailsEntry);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_stale_quote_is_quoted_again_instead() {
+    app().await;
+    let mut session = account_holder("+2348090002903");
+    say(&mut session, "withdraw 10 USDT").await;
+    session.quoted_at = session.quoted_at.map(|at| at - chrono::Duration::hours(1));
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "confirm").await;
+    assert!(
+        replies[0].contains("Your earlier quote has expired"),
+        "{:?}",
+        replies
+    );
+    assert!(replies[1].contains("You'll receive"), "{:?}", replies);
+    assert!(!replies.iter().any(|reply| reply.contains("Bank Details")));
+    assert_eq!(session.state, UserState::OfframpConfirmation);
+    assert!(
+        session
+            .quoted_at
+            .is_some_and(|at| chrono::Utc::now() - at < chrono::Duration::minutes(1))
+    );
+
+    // The fresh quote is confirmed as usual
+    let replies = say(&mut session, "confirm").await;
+    assert!(
+        replies[0].contains("Bank Details Required"),
+        "{:?}",
+        replies
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_stale_quote_can_still_be_cancelled() {
+    app().await;
+    let mut session = account_holder("+2348090002904");
+    say(&mut session, "withdraw 10 USDT").await;
+    session.quoted_at = None;
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "cancel").await;
+    assert_recap(&replies, "reply `confirm` to continue");
+    assert!(replies[1].contains("Cancelled"), "{:?}", replies);
+    assert_eq!(session.state, UserState::Initial);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bank_details_entry_takes_the_details_after_the_recap() {
+    app().await;
+    let mut session = account_holder("+2348090002905");
+    say(&mut session, "withdraw 10 USDT").await;
+    say(&mut session, "confirm").await;
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "Opay, 0123456789").await;
+    assert_recap(
+        &replies,
+        "send your bank details as `Bank Name, Account Number` to continue.",
+    );
+    assert!(replies[1].contains("Account Verified"), "{:?}", replies);
+    assert_eq!(session.state, UserState::BankDetailsConfirmation);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_verified_new_bank_names_it_and_goes_ahead_on_yes() {
+    app().await;
+    let mut session = account_holder("+2348090002906");
+    say(&mut session, "withdraw 10 USDT").await;
+    say(&mut session, "confirm").await;
+    say(&mut session, "Opay, 0123456789").await;
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "yes").await;
+    assert_recap(
+        &replies,
+        "You were withdrawing 10.00 USDT to Opay — reply `yes` if the account details are \
+         correct",
+    );
+    assert!(
+        replies[1].contains("Withdrawal Successfully Initiated"),
+        "{:?}",
+        replies
+    );
+    assert_eq!(session.state, UserState::Initial);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_saved_bank_confirmation_goes_ahead_on_yes() {
+    app().await;
+    let phone = "+2348090002907";
+    save_bank(phone, "ADA OBI").await;
+    let mut session = account_holder(phone);
+    say(&mut session, "withdraw 10 USDT").await;
+    say(&mut session, "confirm").await;
+    assert_eq!(session.state, UserState::SavedBankConfirmation);
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "yes").await;
+    assert_recap(
+        &replies,
+        "You were withdrawing 10.00 USDT to Opay — reply `yes` to use your saved bank",
+    );
+    assert!(
+        replies[1].contains("Withdrawal Successfully Initiated"),
+        "{:?}",
+        replies
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_bank_pick_takes_the_number_after_the_recap() {
+    app().await;
+    let digits = "2348090002908";
+    for (i, bank) in ["Opay", "Kuda"].into_iter().enumerate() {
+        mock_post(
+            "/banks",
+            json!({
+                "phone": digits,
+                "bank_save_id": format!("pick-{}", i),
+                "bank_name": bank,
+                "account_number": format!("012345678{}", i),
+                "account_name": "ADA OBI",
+                "bank_code": "999",
+            }),
+        )
+        .await;
+    }
+    let mut session = account_holder(&format!("+{}", digits));
+    say(&mut session, "withdraw 10 USDT").await;
+    say(&mut session, "confirm").await;
+    assert_eq!(session.state, UserState::BankSelection);
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "2").await;
+    assert_recap(&replies, "reply with the number of the bank to use");
+    assert!(replies[1].contains("Kuda"), "{:?}", replies);
+    assert_eq!(session.state, UserState::SavedBankConfirmation);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_large_withdrawal_takes_the_typed_amount_after_the_recap() {
+    app().await;
+    let phone = "+2348090002909";
+    save_bank(phone, "ADA OBI").await;
+    let mut session = account_holder(phone);
+    say(&mut session, "withdraw 200 USDT").await;
+    say(&mut session, "confirm").await;
+    say(&mut session, "yes").await;
+    assert_eq!(session.state, UserState::AmountConfirmation);
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "200").await;
+    assert_recap(&replies, "type the amount to confirm");
+    assert!(
+        replies[1].contains("Withdrawal Successfully Initiated"),
+        "{:?}",
+        replies
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_changed_payout_is_approved_after_the_recap() {
+    app().await;
+    let phone = "+2348090002910";
+    start_on_backend(phone).await;
+    let mut session = account_holder(phone);
+    say(&mut session, "withdraw 10 USDT").await;
+    say(&mut session, "confirm").await;
+    // 2% away from what the backend will pay
+    session.quoted_naira_amount = Some(15_300.0);
+    say(&mut session, "yes").await;
+    assert_eq!(session.state, UserState::PayoutApproval);
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "yes").await;
+    assert_recap(&replies, "the payout amount changed");
+    assert!(
+        replies[1].contains("Withdrawal Successfully Initiated"),
+        "{:?}",
+        replies
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn account_creation_takes_the_username_after_the_recap() {
+    app().await;
+    let mut session = UserSessions::new("+2348090002911");
+    say(&mut session, "create").await;
+    assert_eq!(session.state, UserState::AccountCreation);
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "restoreduser").await;
+    assert_recap(&replies, "Reply with a username to finish creating it.");
+    // After the wallet address
+    assert!(
+        replies
+            .last()
+            .is_some_and(|reply| reply.contains("Account created successfully")),
+        "{:?}",
+        replies
+    );
+    assert!(session.controller_address.is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_bank_name_update_is_answered_after_the_recap() {
+    app().await;
+    let phone = "+2348090002912";
+    save_bank(phone, "ADA OBI").await;
+    let mut session = account_holder(phone);
+    say(&mut session, "verify 1").await;
+    assert_eq!(session.state, UserState::BankNameUpdate);
+    let mut session = restore(&session);
+
+    let replies = resume(&mut session, "no").await;
+    assert_recap(&replies, "Reply `yes` to update your saved details");
+    assert!(
+        replies[1].contains("Kept your saved details"),
+        "{:?}",
+        replies
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nothing_is_recapped_outside_a_flow() {
+    app().await;
+    let mut session = restore(&account_holder("+2348090002913"));
+
+    let replies = resume(&mut session, "balance").await;
+    assert_eq!(replies.len(), 1, "{:?}", replies);
+    assert!(replies[0].contains("250.00"), "{:?}", replies);
+}
@@ -0,0 +1,114 @@
+//! Secret lookups across their three sources, with `_FILE` mounts in the scratch
+//! directory and a stand-in secrets endpoint served in-process.
+
+mod common;
+
+use std::path::PathBuf;
+
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+use kharon_pay_whatsapp::config;
+use serde_json::json;
+
+const TOKEN: &str = "vault-token";
+
+/// Answers with a Vault KV v2 document to the right bearer token, 403 to anything else.
+async fn vault(request: HttpRequest) -> HttpResponse {
+    let authorized = request
+        .headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        == Some(format!("Bearer {}", TOKEN).as_str());
+    if !authorized {
+        return HttpResponse::Forbidden().finish();
+    }
+    HttpResponse::Ok().json(json!({
+        "data": {
+            "data": {
+                "SECRETS_TEST_ENV_WINS": "from-remote",
+                "SECRETS_TEST_FILE_WINS": "from-remote",
+                "SECRETS_TEST_REMOTE_ONLY": "from-remote",
+                "SECRETS_TEST_EMPTY_ENV": "from-remote",
+            },
+            "metadata": { "version": 1 },
+        }
+    }))
+}
+
+/// The stand-in endpoint's base URL.
+fn serve() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+    let base = format!("http://{}", listener.local_addr().expect("bound"));
+    std::thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            HttpServer::new(|| App::new().route("/v1/secret/kharon", web::get().to(vault)))
+                .listen(listener)
+                .expect("listening")
+                .disable_signals()
+                .run()
+                .await
+        })
+    });
+    base
+}
+
+/// Writes `contents` to a file named `name` in the scratch directory.
+fn mount(name: &str, contents: &str) -> PathBuf {
+    common::setup();
+    let path = common::scratch_dir().join(name);
+    std::fs::write(&path, contents).expect("secret file");
+    path
+}
+
+// One test, since the remote document is loaded once per process
+#[tokio::test(flavor = "multi_thread")]
+async fn the_environment_beats_a_file_which_beats_the_secrets_endpoint() {
+    let base = serve();
+    let token = mount("secrets-token", &format!("{}\n", TOKEN));
+
+    // SAFETY: this binary's only test, and nothing else reads these
+    unsafe {
+        std::env::set_var("SECRETS_URL", format!("{}/v1/secret/kharon", base));
+    }
+    let refused = config::load_remote_secrets().await.unwrap_err();
+    assert!(refused.contains("403"), "{}", refused);
+
+    // SAFETY: as above
+    unsafe {
+        std::env::set_var("SECRETS_TOKEN_FILE", &token);
+        std::env::set_var("SECRETS_TEST_ENV_WINS", "from-env");
+        std::env::set_var("SECRETS_TEST_ENV_WINS_FILE", mount("env-wins", "from-file"));
+        std::env::set_var(
+            "SECRETS_TEST_FILE_WINS_FILE",
+            mount("file-wins", "  from-file\n"),
+        );
+        std::env::set_var("SECRETS_TEST_EMPTY_ENV", "");
+        std::env::set_var("SECRETS_TEST_UNREADABLE_FILE", mount("gone", "x"));
+    }
+    config::load_remote_secrets().await.expect("secrets loaded");
+
+    let secret = |name: &str| config::secret(name).unwrap();
+    assert_eq!(secret("SECRETS_TEST_ENV_WINS").as_deref(), Some("from-env"));
+    assert_eq!(
+        secret("SECRETS_TEST_FILE_WINS").as_deref(),
+        Some("from-file")
+    );
+    assert_eq!(
+        secret("SECRETS_TEST_REMOTE_ONLY").as_deref(),
+        Some("from-remote")
+    );
+    // An empty variable counts as unset
+    assert_eq!(
+        secret("SECRETS_TEST_EMPTY_ENV").as_deref(),
+        Some("from-remote")
+    );
+    assert_eq!(secret("SECRETS_TEST_NOWHERE"), None);
+
+    // A rotated file is picked up on the next lookup
+    mount("file-wins", "rotated");
+    assert_eq!(secret("SECRETS_TEST_FILE_WINS").as_deref(), Some("rotated"));
+
+    // A file that can't be read is an error, not a fallthrough
+    std::fs::remove_file(common::scratch_dir().join("gone")).unwrap();
+    let error = config::secret("SECRETS_TEST_UNREADABLE").unwrap_err();
+    assert!(error.contains("SECRETS_TEST_UNREADABLE_FILE"), "{}", error);
+}
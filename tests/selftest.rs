@@ -0,0 +1,126 @@
+//! The startup self-test, run as the built binary with one backend endpoint reachable and
+//! one pointed at a port nothing listens on.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+/// A stand-in endpoint that answers anything with an empty 200. Returns its base URL.
+fn reachable() -> &'static str {
+    static BASE: OnceLock<String> = OnceLock::new();
+    BASE.get_or_init(|| {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+        let base = format!("http://{}", listener.local_addr().expect("bound"));
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0; 1024]);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+        base
+    })
+}
+
+fn bot(self_test: &str) -> Command {
+    let dir = common::scratch_dir().join(format!("selftest-{}", self_test));
+    std::fs::create_dir_all(&dir).expect("selftest dir");
+    let mut command = Command::new(env!("CARGO_BIN_EXE_kharon-pay-whatsapp"));
+    command
+        .env_clear()
+        .envs([
+            ("T_ACCOUNT_SID", "AC-test-sid-value"),
+            ("T_AUTH_TOKEN", "test-auth-token-value"),
+            ("T_WHATSAPP_NUMBER", "+14155238886"),
+            ("T_API_URL", "http://127.0.0.1:9"),
+            ("TWILIO_DRY_RUN", "1"),
+            ("SESSION_STORE", "memory"),
+            ("SELF_TEST", self_test),
+            // Nothing listens on the discard port
+            ("SERVER_RATE_ENDPOINT", "http://127.0.0.1:9/rate"),
+        ])
+        .env(
+            "SERVER_BALANCE_ENDPOINT",
+            format!("{}/balance", reachable()),
+        )
+        .current_dir(dir);
+    command
+}
+
+#[test]
+fn strict_mode_refuses_to_start_and_names_the_unreachable_variable() {
+    let Output { status, stdout, .. } = bot("strict").output().expect("binary runs");
+    let stdout = String::from_utf8_lossy(&stdout);
+
+    assert_eq!(status.code(), Some(1), "{}", stdout);
+    assert!(
+        stdout.contains("Self-test ❌ SERVER_RATE_ENDPOINT: unreachable"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Self-test ✅ SERVER_BALANCE_ENDPOINT: responded 200"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Self-test ✅ SESSION_STORE: write/read/delete ok"),
+        "{}",
+        stdout
+    );
+}
+
+/// Killed when dropped, so a failed test doesn't leave it holding the port.
+struct Running(Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn warn_mode_starts_anyway_and_reports_the_results_on_readyz() {
+    // It serves on its fixed port, 6500
+    let _bot = Running(
+        bot("warn")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("binary runs"),
+    );
+    let started = Instant::now();
+    let response = loop {
+        if let Ok(response) = reqwest::get("http://127.0.0.1:6500/readyz").await {
+            break response;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "never started");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+    let body: Value = response.json().await.unwrap();
+
+    let results = body["startup_self_test"].as_array().expect("results");
+    let result = |variable: &str| {
+        results
+            .iter()
+            .find(|result| result["variable"] == variable)
+            .unwrap_or_else(|| panic!("{} missing from {}", variable, body))
+    };
+    assert_eq!(result("SERVER_RATE_ENDPOINT")["ok"], false);
+    assert!(
+        result("SERVER_RATE_ENDPOINT")["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("unreachable"),
+        "{}",
+        body
+    );
+    assert_eq!(result("SERVER_BALANCE_ENDPOINT")["ok"], true);
+    assert_eq!(result("SESSION_STORE")["ok"], true);
+    assert_eq!(result("T_AUTH_TOKEN")["detail"], "skipped (TWILIO_DRY_RUN)");
+}
@@ -0,0 +1,116 @@
+//! `/debug/simulate`, walking a whole conversation over HTTP the way staging QA does,
+//! with what the bot would have sent returned rather than sent.
+
+mod common;
+
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+
+const ADMIN_KEY: &str = "simulator-admin-key";
+
+async fn app() -> &'static str {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts, and before any test reads them
+        unsafe {
+            std::env::set_var("DEBUG_SIMULATOR", "1");
+            std::env::set_var("ADMIN_API_KEY", ADMIN_KEY);
+            std::env::set_var("SIMULATOR_SETTLE_SECS", "5");
+        }
+    })
+    .await
+}
+
+async fn post(key: &str, phone: &str, body: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/debug/simulate", app().await))
+        .header("x-admin-key", key)
+        .json(&json!({ "phone": phone, "body": body }))
+        .send()
+        .await
+        .expect("simulator answers")
+}
+
+/// Says `body` as `phone`, returning the state it was left in and what it was told.
+async fn simulate(phone: &str, body: &str) -> (String, Vec<String>) {
+    let response = post(ADMIN_KEY, phone, body).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let reply: Value = response.json().await.expect("JSON");
+    assert_eq!(reply["success"], true, "{}", reply);
+    let messages = reply["messages"]
+        .as_array()
+        .expect("messages")
+        .iter()
+        .map(|message| {
+            assert!(message["template_sid"].is_null(), "{}", message);
+            message["body"].as_str().expect("a body").to_string()
+        })
+        .collect();
+    (
+        reply["state"].as_str().expect("a state").to_string(),
+        messages,
+    )
+}
+
+fn assert_said(messages: &[String], text: &str) {
+    assert!(
+        messages.iter().any(|message| message.contains(text)),
+        "{:?} doesn't say {:?}",
+        messages,
+        text
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_new_user_creates_an_account_and_withdraws() {
+    // However Twilio might write the number, it's the same conversation
+    let (state, messages) = simulate("2348090003201", "create").await;
+    assert_eq!(state, "AccountCreation");
+    assert_said(&messages, "Choose a username");
+
+    let (state, messages) = simulate("+2348090003201", "sim_ada").await;
+    assert_eq!(state, "Initial");
+    assert_said(&messages, "Account created successfully");
+
+    let (state, messages) = simulate("whatsapp:+2348090003201", "withdraw 10 USDT").await;
+    assert_eq!(state, "OfframpConfirmation");
+    assert_said(&messages, "₦15,000.00");
+
+    let (state, messages) = simulate("+2348090003201", "confirm").await;
+    assert_eq!(state, "BankDetailsEntry");
+    assert_said(&messages, "Bank Details Required");
+
+    let (state, messages) = simulate("+2348090003201", "Opay, 0123456789").await;
+    assert_eq!(state, "BankDetailsConfirmation");
+    assert_said(&messages, "Account Verified");
+    assert_said(&messages, "MOCK ACCOUNT HOLDER");
+
+    let (state, messages) = simulate("+2348090003201", "yes").await;
+    assert_eq!(state, "Initial");
+    assert_said(&messages, "Withdrawal Successfully Initiated");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replies_name_the_normalized_phone() {
+    let response = post(ADMIN_KEY, "whatsapp:+2348090003202", "help").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let reply: Value = response.json().await.expect("JSON");
+    assert_eq!(reply["phone"], "+2348090003202");
+    let to: Vec<&str> = reply["messages"]
+        .as_array()
+        .expect("messages")
+        .iter()
+        .map(|message| message["to"].as_str().expect("a recipient"))
+        .collect();
+    assert!(!to.is_empty());
+    assert!(
+        to.iter().all(|to| to.ends_with("+2348090003202")),
+        "{:?}",
+        to
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn it_needs_the_admin_key() {
+    let response = post("not-the-key", "+2348090003203", "help").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
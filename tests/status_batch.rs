@@ -0,0 +1,233 @@
+//! Batched status lookups against a stand-in backend whose batch endpoint answers each
+//! reference by its prefix: `BATCH-DONE-` completed, `BATCH-FAIL-` failed, `BATCH-PEND-`
+//! pending, and `BATCH-GONE-` left out, to be found by a single lookup instead.
+
+mod common;
+
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use actix_web::{App, HttpResponse, HttpServer, web};
+use chrono::Utc;
+use kharon_pay_whatsapp::config::PollConfig;
+use kharon_pay_whatsapp::i18n::Language;
+use kharon_pay_whatsapp::transactions::{self, IndexedTransaction};
+use kharon_pay_whatsapp::{polling, status_batch, twilio, window};
+use serde_json::{Value, json};
+
+/// The references in each batch request, and each single lookup's reference.
+static BATCHES: Mutex<Vec<Vec<String>>> = Mutex::new(Vec::new());
+static SINGLES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn status(reference: &str, status: &str) -> Value {
+    json!({
+        "transaction_id": format!("tx-{}", reference),
+        "reference": reference,
+        "status": status,
+        "amount": 15000.0,
+        "currency": "NGN",
+        "last_updated": Utc::now(),
+        "metadata": null,
+    })
+}
+
+async fn batch(body: web::Json<Value>) -> HttpResponse {
+    let references: Vec<String> = serde_json::from_value(body["references"].clone()).unwrap();
+    BATCHES.lock().unwrap().push(references.clone());
+    let statuses: Vec<Value> = references
+        .iter()
+        .filter_map(|reference| {
+            let answer = match reference.split('-').nth(1)? {
+                "DONE" => "completed",
+                "FAIL" => "failed",
+                "PEND" => "pending",
+                _ => return None,
+            };
+            Some(status(reference, answer))
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({ "success": true, "data": statuses }))
+}
+
+async fn single(path: web::Path<String>) -> HttpResponse {
+    let reference = path.into_inner();
+    SINGLES.lock().unwrap().push(reference.clone());
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "message": "ok",
+        "data": status(&reference, "completed"),
+    }))
+}
+
+/// Serves the stand-in backend and runs the batch scheduler against it, in chunks of two
+/// once a second, until the first batch has been answered.
+async fn batching() {
+    static STARTED: OnceLock<()> = OnceLock::new();
+    STARTED.get_or_init(|| {
+        common::setup();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+        let base = format!("http://{}", listener.local_addr().expect("bound"));
+        // SAFETY: set before the server thread starts and before any test reads them
+        unsafe {
+            std::env::set_var("TRANSACTION_STATUS_ENDPOINT", &base);
+            std::env::set_var(
+                "TRANSACTION_STATUS_BATCH_ENDPOINT",
+                format!("{}/batch", base),
+            );
+            std::env::set_var("TRANSACTION_STATUS_BATCH_INTERVAL_SECS", "1");
+            std::env::set_var("TRANSACTION_STATUS_BATCH_SIZE", "2");
+        }
+        std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                // On this runtime, so it outlives the test that started it
+                status_batch::start_batch_task();
+                HttpServer::new(|| {
+                    App::new()
+                        .route("/batch", web::post().to(batch))
+                        .route("/transactions/{reference}/status", web::get().to(single))
+                })
+                .listen(listener)
+                .expect("listening")
+                .disable_signals()
+                .run()
+                .await
+            })
+        });
+    });
+    while status_batch::lookup("BATCH-DONE-PROBE").await.is_none() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn one_cycle_answers_every_waiting_poller_in_capped_chunks() {
+    batching().await;
+    let references = [
+        "BATCH-DONE-1",
+        "BATCH-FAIL-1",
+        "BATCH-PEND-1",
+        "BATCH-GONE-1",
+        "BATCH-DONE-1",
+    ];
+    let lookups: Vec<_> = references
+        .iter()
+        .map(|reference| tokio::spawn(status_batch::lookup(reference)))
+        .collect();
+    let mut answers = Vec::new();
+    for lookup in lookups {
+        answers.push(lookup.await.unwrap().map(|status| status.status));
+    }
+
+    assert_eq!(
+        answers,
+        [
+            Some("completed".to_string()),
+            Some("failed".to_string()),
+            Some("pending".to_string()),
+            None,
+            Some("completed".to_string()),
+        ]
+    );
+
+    let batches = BATCHES.lock().unwrap().clone();
+    assert!(
+        batches.iter().all(|batch| batch.len() <= 2),
+        "{:?}",
+        batches
+    );
+    // Two pollers waiting on one reference share its slot
+    let asked: Vec<&String> = batches
+        .iter()
+        .flatten()
+        .filter(|reference| reference.ends_with("-1"))
+        .collect();
+    assert_eq!(asked.len(), 4, "{:?}", batches);
+}
+
+/// Polls `reference`, indexed for `phone`, until it settles or a few batch cycles pass.
+async fn poll(phone: &str, reference: &str) -> Vec<String> {
+    transactions::record(&IndexedTransaction {
+        reference: reference.to_string(),
+        phone: phone.to_string(),
+        amount: 10.0,
+        token: "USDT".to_string(),
+        bank_name: "Opay".to_string(),
+        account_name: "ADA OBI".to_string(),
+        masked_account: "6789".to_string(),
+        initiated_at: Utc::now(),
+        status: "processing".to_string(),
+        status_updated_at: Utc::now(),
+        correlation_id: None,
+        tx_hash: None,
+        language: Language::En,
+    })
+    .await;
+    window::record_inbound(phone, Utc::now());
+    let config = PollConfig {
+        interval: Duration::from_millis(100),
+        max_wait: Duration::from_secs(5),
+        slow_interval: Duration::from_secs(1),
+        slow_window: Duration::ZERO,
+        progress_after: None,
+        progress_message: String::new(),
+    };
+    let polling =
+        async { polling::start_transaction_polling_task_with(reference.to_string(), config).await };
+    let (finished, sent) = twilio::capture(polling, Duration::ZERO).await;
+    finished.expect("polling finished");
+    sent.into_iter()
+        .filter(|message| message.to == phone)
+        .map(|message| message.body)
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batched_results_complete_and_fail_withdrawals() {
+    batching().await;
+    let completed = poll("+2348090001301", "BATCH-DONE-2").await;
+    assert!(
+        completed[0].contains("Withdrawal Completed"),
+        "{:?}",
+        completed
+    );
+
+    let failed = poll("+2348090001302", "BATCH-FAIL-2").await;
+    assert!(
+        failed[0].starts_with("❌ *Withdrawal Failed*"),
+        "{:?}",
+        failed
+    );
+
+    // Both came from the batch alone
+    let singles = SINGLES.lock().unwrap().clone();
+    assert!(
+        !singles.iter().any(|reference| reference.ends_with("-2")),
+        "{:?}",
+        singles
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_reference_missing_from_the_batch_is_looked_up_alone() {
+    batching().await;
+    let completed = poll("+2348090001303", "BATCH-GONE-3").await;
+    assert!(
+        completed[0].contains("Withdrawal Completed"),
+        "{:?}",
+        completed
+    );
+    assert!(
+        SINGLES
+            .lock()
+            .unwrap()
+            .contains(&"BATCH-GONE-3".to_string())
+    );
+    assert!(
+        BATCHES
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .any(|reference| reference == "BATCH-GONE-3")
+    );
+}
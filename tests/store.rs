@@ -0,0 +1,176 @@
+//! The SQLite session store: many callers at once, and rows written by older builds.
+
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use kharon_pay_whatsapp::i18n::Language;
+use kharon_pay_whatsapp::model::{SESSION_SCHEMA_VERSION, UserSessions, UserState};
+use kharon_pay_whatsapp::store::{self, SessionStore, SqliteSessionStore};
+use rusqlite::{Connection, params};
+
+/// A fresh database file for one test.
+fn open(name: &str) -> (SqliteSessionStore, String) {
+    common::setup();
+    let path = common::scratch_dir().join(format!("store-{}.db", name));
+    let path = path.to_string_lossy().into_owned();
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+    (SqliteSessionStore::open(&path).expect("store opens"), path)
+}
+
+/// Writes `json` as the stored row for `phone`, the way an older build left it an hour ago.
+fn write_raw(path: &str, phone: &str, json: &str) {
+    let conn = Connection::open(path).unwrap();
+    conn.execute(
+        "INSERT INTO sessions (phone, data, updated_at) VALUES (?1, ?2, ?3)",
+        params![phone, json, chrono::Utc::now().timestamp() - 3600],
+    )
+    .unwrap();
+}
+
+#[test]
+fn sessions_saved_from_many_threads_all_land() {
+    let (sessions, _) = open("threads");
+    let sessions = Arc::new(sessions);
+
+    let workers: Vec<_> = (0..16)
+        .map(|worker| {
+            let sessions = sessions.clone();
+            std::thread::spawn(move || {
+                for i in 0..25 {
+                    let mut session = UserSessions::new(&format!("+23480{:02}{:06}", worker, i));
+                    session.state = UserState::OfframpConfirmation;
+                    session.pending_amount = Some(i as f64);
+                    sessions.save(&session).expect("saved");
+                    let loaded = sessions.load(&session.phone).expect("loaded");
+                    assert_eq!(loaded.map(|s| s.pending_amount), Some(Some(i as f64)));
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("worker finished");
+    }
+
+    for worker in 0..16 {
+        for i in 0..25 {
+            let phone = format!("+23480{:02}{:06}", worker, i);
+            let session = sessions.load(&phone).unwrap().expect("stored");
+            assert_eq!(session.state, UserState::OfframpConfirmation);
+        }
+    }
+    // Every one was saved just now, so none has sat idle for a minute
+    let idle = sessions.mid_flow(Duration::from_secs(60)).unwrap();
+    assert!(idle.is_empty());
+}
+
+#[test]
+fn a_locked_database_does_not_stall_the_async_worker() {
+    let (sessions, path) = open("locked");
+    let sessions: Arc<dyn SessionStore> = Arc::new(sessions);
+
+    // Another writer holds the database for a while
+    let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+    let holder = std::thread::spawn(move || {
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch("BEGIN IMMEDIATE").unwrap();
+        locked_tx.send(()).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        conn.execute_batch("COMMIT").unwrap();
+    });
+    locked_rx.recv().unwrap();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let ticks = Arc::new(AtomicUsize::new(0));
+    runtime.block_on(async {
+        let counter = ticks.clone();
+        let ticker = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let session = UserSessions::new("+2348099999999");
+        let store = sessions.clone();
+        store::blocking(move || store.save(&session))
+            .await
+            .expect("saved once the lock is released");
+        ticker.abort();
+    });
+    holder.join().unwrap();
+
+    // The one worker thread kept running timers while the save waited on the lock
+    assert!(ticks.load(Ordering::Relaxed) >= 10, "{:?}", ticks);
+    assert!(sessions.load("+2348099999999").unwrap().is_some());
+}
+
+#[test]
+fn a_session_from_before_versioning_loads_with_its_preferences() {
+    let (sessions, path) = open("v1");
+    write_raw(
+        &path,
+        "+2348011111111",
+        r#"{"phone":"+2348011111111","state":"OfframpConfirmation","pending_amount":25.0,
+            "language":"pcm","language_detected":true,"notifications_off":true}"#,
+    );
+
+    let mut session = sessions.load("+2348011111111").unwrap().expect("stored");
+    assert_eq!(session.schema_version, SESSION_SCHEMA_VERSION);
+    assert_eq!(session.state, UserState::OfframpConfirmation);
+    assert_eq!(session.pending_amount, Some(25.0));
+    assert!(session.inbound_history.is_empty());
+    assert!(session.transitions.is_empty());
+    assert!(session.restored);
+
+    assert!(session.take_legacy_preferences());
+    assert_eq!(session.prefs.language, Some(Language::Pcm));
+    assert!(session.prefs.language_detected);
+    assert!(session.prefs.notifications_off);
+}
+
+#[test]
+fn a_bank_pick_saved_under_the_old_layout_resumes_as_a_pick() {
+    let (sessions, path) = open("v12");
+    let bank = r#"{"bank_details_id":"b1","bank_name":"Opay","bank_account_number":"0123456789","account_name":"ADA OBI"}"#;
+    write_raw(
+        &path,
+        "+2348022222222",
+        &format!(
+            r#"{{"schema_version":12,"phone":"+2348022222222","state":"SavedBankConfirmation",
+                "pending_bank_details":{bank},"bank_choices":[{bank}]}}"#,
+        ),
+    );
+
+    let session = sessions.load("+2348022222222").unwrap().expect("stored");
+    assert_eq!(session.state, UserState::BankSelection);
+    assert!(session.pending_bank_details.is_none());
+    assert_eq!(session.bank_choices.len(), 1);
+    assert_eq!(session.bank_choices[0].bank_code, None);
+}
+
+#[test]
+fn fields_and_states_from_a_newer_build_do_not_lose_the_session() {
+    let (sessions, path) = open("newer");
+    write_raw(
+        &path,
+        "+2348033333333",
+        r#"{"schema_version":99,"phone":"+2348033333333","state":"SomeFutureState",
+            "controller_address":"0x0abc","field_from_the_future":{"x":1}}"#,
+    );
+
+    let session = sessions.load("+2348033333333").unwrap().expect("stored");
+    assert_eq!(session.state, UserState::Initial);
+    assert_eq!(session.controller_address.as_deref(), Some("0x0abc"));
+
+    // Saving it again writes the current layout
+    sessions.save(&session).unwrap();
+    let again = sessions.load("+2348033333333").unwrap().unwrap();
+    assert_eq!(again.schema_version, SESSION_SCHEMA_VERSION);
+}
@@ -0,0 +1,393 @@
+//! The copy users see most, pinned so a change to it shows up in review. Each is checked
+//! as sent, in plain text, in another language, and against WhatsApp's length limits.
+
+mod common;
+
+use chrono::{TimeZone, Utc};
+use kharon_pay_whatsapp::conversation;
+use kharon_pay_whatsapp::i18n::{self, Language, Msg};
+use kharon_pay_whatsapp::model::{BankDetails, TransactionStatus, UserSessions};
+use kharon_pay_whatsapp::outbound::{self, MAX_BODY_CHARS, MAX_TEMPLATE_CHARS};
+use kharon_pay_whatsapp::parser::{COMMANDS, command_spec, parse_command};
+use kharon_pay_whatsapp::polling;
+
+const PHONE: &str = "+2348012345678";
+
+/// `name` as sent, as plain text, both within WhatsApp's limits. Prompts that can go out
+/// as a quick-reply template carry their whole text in one variable, so they get the
+/// template's tighter limit.
+fn check(name: &str, body: &str, templated: bool) {
+    let limit = if templated {
+        MAX_TEMPLATE_CHARS
+    } else {
+        MAX_BODY_CHARS
+    };
+    assert!(
+        body.chars().count() <= limit,
+        "{} is {} characters, over {}",
+        name,
+        body.chars().count(),
+        limit
+    );
+    let plain = outbound::plain_text(body);
+    let markers = plain.split("***").any(|part| part.contains('*'));
+    assert!(!markers, "{} keeps bold markers", name);
+    common::assert_snapshot(name, body);
+    common::assert_snapshot(&format!("{}.plain", name), &plain);
+}
+
+fn session(language: Language) -> UserSessions {
+    let mut session = UserSessions::new(PHONE);
+    session.prefs.language = Some(language);
+    session.pending_amount = Some(250.0);
+    session.pending_currency = Some("USDT".to_string());
+    session.quoted_rate = Some(1523.45);
+    session.quoted_naira_amount = Some(380_862.5);
+    session
+}
+
+#[test]
+fn quote() {
+    common::setup();
+    for (language, suffix) in [(Language::En, "en"), (Language::Pcm, "pcm")] {
+        let quote = conversation::quote_prompt(language, 250.0, "USDT", 1523.45, 380_862.5);
+        check(&format!("quote.{}", suffix), &quote, true);
+    }
+}
+
+/// Amounts at both ends of what users move, where the separators matter most.
+#[test]
+fn quote_small_and_large() {
+    common::setup();
+    check(
+        "quote.small",
+        &conversation::quote_prompt(Language::En, 0.5, "USDC", 1523.45, 761.725),
+        true,
+    );
+    check(
+        "quote.large",
+        &conversation::quote_prompt(Language::En, 100_000.0, "USDT", 1523.45, 152_345_000.0),
+        true,
+    );
+}
+
+#[test]
+fn bank_confirmation() {
+    common::setup();
+    for (language, suffix) in [(Language::En, "en"), (Language::Pcm, "pcm")] {
+        let prompt = conversation::bank_confirmation_prompt(
+            &session(language),
+            "Access Bank",
+            "ADA OBI",
+            "0123456789",
+        );
+        assert!(!prompt.contains("0123456789"), "account number shown whole");
+        check(&format!("bank_confirmation.{}", suffix), &prompt, true);
+    }
+}
+
+#[test]
+fn saved_bank_confirmation() {
+    common::setup();
+    let bank = BankDetails {
+        bank_details_id: "bank-1".to_string(),
+        bank_name: "Access Bank".to_string(),
+        account_number: "0123456789".to_string(),
+        account_name: "ADA OBI".to_string(),
+        bank_code: None,
+    };
+    for (language, suffix) in [(Language::En, "en"), (Language::Pcm, "pcm")] {
+        let prompt = conversation::saved_bank_prompt(&session(language), &bank).body;
+        assert!(!prompt.contains("0123456789"), "account number shown whole");
+        check(
+            &format!("saved_bank_confirmation.{}", suffix),
+            &prompt,
+            true,
+        );
+    }
+}
+
+/// A bank confirmed before a quote was stored says so, rather than `₦0.00`.
+#[test]
+fn bank_confirmation_without_a_quote() {
+    common::setup();
+    let mut session = session(Language::En);
+    session.pending_amount = None;
+    session.quoted_rate = None;
+    session.quoted_naira_amount = None;
+    let prompt =
+        conversation::bank_confirmation_prompt(&session, "Access Bank", "ADA OBI", "0123456789");
+    assert!(!prompt.contains("0.00"), "{}", prompt);
+    check("bank_confirmation.no_quote", &prompt, true);
+}
+
+#[test]
+fn status() {
+    let status = TransactionStatus {
+        transaction_id: "tx_1".to_string(),
+        reference: "KP-7F3A9C".to_string(),
+        status: "processing".to_string(),
+        amount: Some(250.0),
+        currency: Some("USDT".to_string()),
+        last_updated: Utc.with_ymd_and_hms(2026, 3, 14, 9, 26, 53).unwrap(),
+        metadata: None,
+    };
+    check("status.en", &conversation::status_reply(&status), false);
+
+    let unknown_amount = TransactionStatus {
+        amount: None,
+        ..status
+    };
+    check(
+        "status.unknown_amount",
+        &conversation::status_reply(&unknown_amount),
+        false,
+    );
+}
+
+#[test]
+fn help() {
+    common::setup();
+    for (language, suffix) in [(Language::En, "en"), (Language::Sw, "sw")] {
+        check(
+            &format!("help.{}.new_user", suffix),
+            &i18n::help(language, false, PHONE),
+            false,
+        );
+        check(
+            &format!("help.{}.account", suffix),
+            &i18n::help(language, true, PHONE),
+            false,
+        );
+    }
+}
+
+#[test]
+fn completion() {
+    common::setup();
+    let completed = TransactionStatus {
+        transaction_id: "tx_1".to_string(),
+        reference: "KP-7F3A9C".to_string(),
+        status: "completed".to_string(),
+        amount: Some(1_250_000.5),
+        currency: Some("NGN".to_string()),
+        last_updated: Utc.with_ymd_and_hms(2026, 3, 14, 9, 26, 53).unwrap(),
+        metadata: None,
+    };
+    let message = |status: &TransactionStatus| {
+        polling::completion_message(
+            Language::En,
+            status,
+            "Access Bank",
+            "ADA OBI",
+            "",
+            "2 min 5 sec",
+            "2026-03-14 09:26:53",
+        )
+    };
+    check("completion.large", &message(&completed), false);
+    assert!(!message(&completed).contains("Tx:"));
+
+    let hash = "0x04b1f3c2d5e6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1d2e3f4a5b6c7d8e9f0a1b2";
+    let with_hash = polling::completion_message(
+        Language::En,
+        &completed,
+        "Access Bank",
+        "ADA OBI",
+        hash,
+        "2 min 5 sec",
+        "2026-03-14 09:26:53",
+    );
+    assert!(with_hash.contains("`0x04b1…a1b2`"), "{}", with_hash);
+    assert!(
+        with_hash.contains(&format!("https://starkscan.co/tx/{}", hash)),
+        "{}",
+        with_hash
+    );
+
+    let unknown_amount = TransactionStatus {
+        amount: None,
+        ..completed
+    };
+    let message = message(&unknown_amount);
+    assert!(!message.contains("0.00"), "{}", message);
+    check("completion.unknown_amount", &message, false);
+}
+
+/// Whatever a menu tells users to type in backticks has to be something we understand.
+#[test]
+fn every_command_the_menus_mention_parses() {
+    common::setup();
+    for language in [Language::En, Language::Fr, Language::Sw, Language::Pcm] {
+        for has_account in [false, true] {
+            let menus = [
+                i18n::welcome(language, has_account, PHONE),
+                i18n::help(language, has_account, PHONE),
+                i18n::command_snippet(language, &["address", "fund", "withdraw"]),
+            ];
+            for menu in menus {
+                for typed in menu.split('`').skip(1).step_by(2) {
+                    let command = parse_command(typed);
+                    assert_ne!(command.name(), "unknown", "{:?} in {:?}", typed, language);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn every_visible_command_is_in_help_with_its_usage() {
+    common::setup();
+    for language in Language::ALL {
+        for has_account in [false, true] {
+            let help = i18n::help(language, has_account, PHONE);
+            // `• `send [amount]...` - what it does`, by the command its first word names
+            let listed: Vec<&str> = help
+                .lines()
+                .filter_map(|line| line.strip_prefix("• `")?.split_once("` - "))
+                .filter_map(|(usage, _)| command_spec(usage.split(' ').next()?))
+                .map(|spec| spec.name)
+                .collect();
+            let visible: Vec<&str> = COMMANDS
+                .iter()
+                .filter(|spec| spec.visible_to(has_account) && spec.available_to(PHONE))
+                .map(|spec| spec.name)
+                .collect();
+            assert_eq!(
+                listed, visible,
+                "{:?}, has_account {}:\n{}",
+                language, has_account, help
+            );
+            if language == Language::En {
+                for spec in COMMANDS.iter().filter(|spec| visible.contains(&spec.name)) {
+                    assert!(
+                        help.contains(&format!("• `{}` - ", spec.usage)),
+                        "{}",
+                        spec.usage
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Every message in `i18n`; the match fails to compile when one is added without being
+/// listed here.
+fn every_message() -> Vec<Msg> {
+    let all = vec![
+        Msg::Welcome,
+        Msg::Help,
+        Msg::LanguageSet,
+        Msg::WithdrawRequest,
+        Msg::SummaryTitle,
+        Msg::SummaryRate,
+        Msg::SummaryReceive,
+        Msg::SummaryBank,
+        Msg::SavedBankPrompt,
+        Msg::BankDetailsRequired,
+        Msg::QuoteCancelled,
+        Msg::ConfirmOrCancel,
+        Msg::AccountVerified,
+        Msg::WithdrawalCancelled,
+        Msg::YesOrNo,
+        Msg::ReenterBankDetails,
+        Msg::YesOrReenter,
+        Msg::LargeWithdrawal,
+        Msg::AbsurdWithdrawal,
+        Msg::AmountMismatch,
+        Msg::AmountMismatchCancelled,
+        Msg::WithdrawalInitiated,
+        Msg::WithdrawalCompleted,
+        Msg::WithdrawalFailed,
+        Msg::ChooseBank,
+        Msg::ChooseBankReply,
+        Msg::NotificationsOn,
+        Msg::NotificationsOff,
+        Msg::RatingRequest,
+        Msg::RatingThanks,
+        Msg::SandboxJoined,
+        Msg::OptedOut,
+        Msg::OptedIn,
+        Msg::SettlementDelayed,
+        Msg::AccountReview,
+        Msg::ArrivalEstimate,
+        Msg::ArrivalEstimateToBank,
+        Msg::ArrivalUsual,
+    ];
+    for msg in &all {
+        match msg {
+            Msg::Welcome
+            | Msg::Help
+            | Msg::LanguageSet
+            | Msg::WithdrawRequest
+            | Msg::SummaryTitle
+            | Msg::SummaryRate
+            | Msg::SummaryReceive
+            | Msg::SummaryBank
+            | Msg::SavedBankPrompt
+            | Msg::BankDetailsRequired
+            | Msg::QuoteCancelled
+            | Msg::ConfirmOrCancel
+            | Msg::AccountVerified
+            | Msg::WithdrawalCancelled
+            | Msg::YesOrNo
+            | Msg::ReenterBankDetails
+            | Msg::YesOrReenter
+            | Msg::LargeWithdrawal
+            | Msg::AbsurdWithdrawal
+            | Msg::AmountMismatch
+            | Msg::AmountMismatchCancelled
+            | Msg::WithdrawalInitiated
+            | Msg::WithdrawalCompleted
+            | Msg::WithdrawalFailed
+            | Msg::ChooseBank
+            | Msg::ChooseBankReply
+            | Msg::NotificationsOn
+            | Msg::NotificationsOff
+            | Msg::RatingRequest
+            | Msg::RatingThanks
+            | Msg::SandboxJoined
+            | Msg::OptedOut
+            | Msg::OptedIn
+            | Msg::SettlementDelayed
+            | Msg::AccountReview
+            | Msg::ArrivalEstimate
+            | Msg::ArrivalEstimateToBank
+            | Msg::ArrivalUsual => {}
+        }
+    }
+    all
+}
+
+/// Replies to a question the bot just asked, which aren't commands of their own.
+const FLOW_REPLIES: &[&str] = &["yes", "no", "confirm"];
+
+/// Whatever a message tells users to type in backticks is a registered command, a reply
+/// to the question it asks, or sample bank details.
+#[test]
+fn every_command_the_messages_mention_is_registered() {
+    common::setup();
+    for language in Language::ALL {
+        for msg in every_message() {
+            let text = i18n::text(language, msg);
+            for typed in text.split('`').skip(1).step_by(2) {
+                let word = typed
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                if FLOW_REPLIES.contains(&word.as_str()) || typed.contains(',') {
+                    continue;
+                }
+                assert_ne!(
+                    parse_command(typed).name(),
+                    "unknown",
+                    "{:?} in {:?} ({:?})",
+                    typed,
+                    msg,
+                    language
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Per-endpoint backend deadlines, against a backend that accepts and never answers.
+
+mod common;
+
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use kharon_pay_whatsapp::backend::{self, Endpoint, TrackedSend};
+use kharon_pay_whatsapp::config::TimeoutConfig;
+use kharon_pay_whatsapp::metrics;
+
+/// Deadlines are read once, so both are set before any test reads them.
+fn configure() {
+    static CONFIGURE: Once = Once::new();
+    CONFIGURE.call_once(|| {
+        common::setup();
+        // SAFETY: runs once, before any test in this binary reads the environment
+        unsafe {
+            std::env::set_var("RATE_TIMEOUT_SECS", "1");
+            std::env::set_var("OFFRAMP_TIMEOUT_SECS", "90");
+        }
+    });
+}
+
+#[test]
+fn configured_values_override_a_sensible_default_table() {
+    configure();
+    let timeouts = TimeoutConfig::from_env();
+    assert_eq!(timeouts.rate, Duration::from_secs(1));
+    assert_eq!(timeouts.offramp, Duration::from_secs(90));
+    assert_eq!(
+        timeouts.for_endpoint(Endpoint::OfframpCancel),
+        timeouts.offramp
+    );
+
+    assert_eq!(timeouts.balance, Duration::from_secs(10));
+    assert_eq!(timeouts.account_creation, Duration::from_secs(180));
+    // Lookups give up well before anything that moves money or creates an account
+    for lookup in [Endpoint::Balance, Endpoint::Address, Endpoint::Status] {
+        assert!(timeouts.for_endpoint(lookup) <= Duration::from_secs(15));
+        assert!(timeouts.for_endpoint(lookup) < timeouts.payment);
+    }
+    assert!(timeouts.payment < timeouts.account_creation);
+    // None is as long as the old blanket two minutes, apart from account creation
+    for endpoint in [
+        Endpoint::History,
+        Endpoint::BankVerify,
+        Endpoint::BankList,
+        Endpoint::BankSave,
+        Endpoint::Payment,
+    ] {
+        assert!(timeouts.for_endpoint(endpoint) < Duration::from_secs(120));
+    }
+}
+
+#[tokio::test]
+async fn a_call_past_its_deadline_is_cut_off_and_counted() {
+    configure();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/rate", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            held.push(socket);
+        }
+    });
+
+    let started = Instant::now();
+    let result = backend::client()
+        .get(&url)
+        .send_tracked(Endpoint::Rate)
+        .await;
+    assert!(result.is_err_and(|e| e.is_timeout()));
+    assert!(started.elapsed() < Duration::from_secs(3));
+
+    assert_eq!(metrics::BACKEND_DEADLINES.get(&["Rate"]), 1);
+    assert_eq!(metrics::BACKEND_REQUESTS.count(&["Rate", "timeout"]), 1);
+}
@@ -0,0 +1,120 @@
+//! HTTPS termination with a self-signed certificate.
+
+mod common;
+
+use std::path::PathBuf;
+
+use kharon_pay_whatsapp::tls::{self, TlsPaths};
+use rcgen::{CertificateParams, KeyPair, date_time_ymd};
+
+/// Writes a PEM certificate and key into the scratch dir under `name`.
+fn write_pair(name: &str, cert_pem: &str, key_pem: &str) -> TlsPaths {
+    let dir: PathBuf = common::scratch_dir().join("tls");
+    std::fs::create_dir_all(&dir).expect("tls dir");
+    let cert = dir.join(format!("{}.crt", name));
+    let key = dir.join(format!("{}.key", name));
+    std::fs::write(&cert, cert_pem).expect("cert written");
+    std::fs::write(&key, key_pem).expect("key written");
+    TlsPaths::new(cert, key)
+}
+
+fn self_signed(name: &str) -> (TlsPaths, String) {
+    let generated =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).expect("self-signed");
+    let cert_pem = generated.cert.pem();
+    let paths = write_pair(name, &cert_pem, &generated.signing_key.serialize_pem());
+    (paths, cert_pem)
+}
+
+/// Serves the app over HTTPS on a free local port; returns the port.
+fn spawn_https_app(paths: &TlsPaths) -> u16 {
+    use actix_web::{App, web};
+    use kharon_pay_whatsapp::{request_body, server, store};
+
+    common::setup();
+    tls::init(paths).expect("certificate loads");
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+    let port = listener.local_addr().expect("bound").port();
+    std::thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            let sessions: web::Data<dyn store::SessionStore> =
+                web::Data::from(store::from_env().expect("session store"));
+            let make_app = move || {
+                App::new()
+                    .app_data(sessions.clone())
+                    .app_data(request_body::payload_config())
+                    .configure(server::routes)
+            };
+            tls::serve(make_app, listener).expect("serving").await
+        })
+    });
+    port
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn health_answers_over_a_tls_handshake() {
+    let (paths, cert_pem) = self_signed("localhost");
+    let port = spawn_https_app(&paths);
+    assert!(tls::enabled());
+
+    let client = reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(cert_pem.as_bytes()).unwrap())
+        .build()
+        .unwrap();
+    let url = format!("https://localhost:{}/health", port);
+    let mut response = client.get(&url).send().await;
+    for _ in 0..50 {
+        if response.is_ok() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        response = client.get(&url).send().await;
+    }
+    let response = response.expect("TLS handshake and request");
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["service"], "Kharon Pay WhatsApp Bot");
+
+    // Plain HTTP to the HTTPS port gets nothing back
+    let plain = reqwest::get(format!("http://localhost:{}/health", port)).await;
+    assert!(plain.is_err());
+}
+
+#[test]
+fn an_expired_certificate_is_refused() {
+    let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+    params.not_before = date_time_ymd(2020, 1, 1);
+    params.not_after = date_time_ymd(2021, 1, 1);
+    let key = KeyPair::generate().unwrap();
+    let cert = params.self_signed(&key).unwrap();
+    let paths = write_pair("expired", &cert.pem(), &key.serialize_pem());
+
+    let err = tls::check(&paths).unwrap_err();
+    assert!(err.contains("expired on 2021-01-01"), "{}", err);
+}
+
+#[test]
+fn a_key_for_another_certificate_is_refused() {
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let other_key = KeyPair::generate().unwrap();
+    let paths = write_pair(
+        "mismatched",
+        &generated.cert.pem(),
+        &other_key.serialize_pem(),
+    );
+
+    let err = tls::check(&paths).unwrap_err();
+    assert!(err.contains("does not match"), "{}", err);
+}
+
+#[test]
+fn files_that_are_not_pem_are_refused() {
+    let (good, _) = self_signed("good");
+    let paths = write_pair("garbage", "not a certificate", "not a key");
+    assert!(tls::check(&paths).is_err());
+    assert!(tls::check(&good).is_ok());
+
+    let missing = TlsPaths::new("/nonexistent/cert.pem", "/nonexistent/key.pem");
+    let err = tls::check(&missing).unwrap_err();
+    assert!(err.starts_with("Cannot read TLS certificate"), "{}", err);
+}
@@ -0,0 +1,137 @@
+//! Backend traffic recorded with `RECORD_BACKEND_TRAFFIC=1` and read back by support
+//! through `/admin/backend-traffic/{correlation_id}`.
+
+mod common;
+
+use std::time::Duration;
+
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::model::UserSessions;
+use kharon_pay_whatsapp::reporting::{self, ReportContext};
+use kharon_pay_whatsapp::twilio;
+use reqwest::StatusCode;
+use serde_json::Value;
+
+const ADMIN_KEY: &str = "traffic-admin-key";
+const HMAC_KEY: &str = "traffic-hmac-key";
+const TWILIO_TOKEN: &str = "traffic-twilio-token";
+
+async fn app() -> &'static str {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts, and before any test reads them
+        unsafe {
+            std::env::set_var("RECORD_BACKEND_TRAFFIC", "1");
+            std::env::set_var("ADMIN_API_KEY", ADMIN_KEY);
+            std::env::set_var("HMAC_KEY", HMAC_KEY);
+            std::env::set_var("T_AUTH_TOKEN", TWILIO_TOKEN);
+        }
+    })
+    .await
+}
+
+/// Says `message` as `phone` while handling the flow `correlation_id`, the way the
+/// webhook scopes it.
+async fn say_in_flow(phone: &str, correlation_id: &str, message: &str) {
+    let mut session = UserSessions::new(phone);
+    session.controller_address = Some("0x0test".to_string());
+    session.correlation_id = Some(correlation_id.to_string());
+    let context = ReportContext::for_session(&session);
+    twilio::capture(
+        reporting::scoped(context, dispatch_message(message, &mut session)),
+        Duration::ZERO,
+    )
+    .await;
+}
+
+async fn traffic(correlation_id: &str, limit: Option<usize>, key: &str) -> (StatusCode, Value) {
+    let mut request = reqwest::Client::new()
+        .get(format!(
+            "{}/admin/backend-traffic/{}",
+            app().await,
+            correlation_id
+        ))
+        .header("x-admin-key", key);
+    if let Some(limit) = limit {
+        request = request.query(&[("limit", limit)]);
+    }
+    let response = request.send().await.expect("admin answers");
+    let status = response.status();
+    (status, response.json().await.unwrap_or_default())
+}
+
+/// The exchanges recorded for `correlation_id`, once the writer has caught up with
+/// `expected` of them.
+async fn recorded(correlation_id: &str, expected: usize) -> Vec<Value> {
+    let mut exchanges = Vec::new();
+    for _ in 0..100 {
+        let (status, body) = traffic(correlation_id, None, ADMIN_KEY).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        exchanges = body["exchanges"].as_array().cloned().unwrap_or_default();
+        if exchanges.len() >= expected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    exchanges
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_flows_backend_calls_are_fetched_by_its_correlation_id() {
+    app().await;
+    say_in_flow("+2348090002951", "corr-traffic-1", "withdraw 10 USDT").await;
+    say_in_flow("+2348090002952", "corr-traffic-2", "balance").await;
+
+    // The balance check, then the rate for the quote
+    let exchanges = recorded("corr-traffic-1", 2).await;
+    let endpoints: Vec<&str> = exchanges
+        .iter()
+        .map(|exchange| exchange["endpoint"].as_str().unwrap())
+        .collect();
+    assert_eq!(endpoints, ["Balance", "Rate"], "{:?}", exchanges);
+    for exchange in &exchanges {
+        assert_eq!(exchange["correlation_id"], "corr-traffic-1");
+        assert_eq!(exchange["method"], "GET");
+        assert_eq!(exchange["status"], 200);
+        assert!(exchange["latency_ms"].is_u64(), "{}", exchange);
+    }
+    let balance: Value =
+        serde_json::from_str(exchanges[0]["response_body"].as_str().unwrap()).unwrap();
+    assert_eq!(balance["data"]["balance"], "250.00");
+
+    // The last `limit`, oldest first
+    let (_, body) = traffic("corr-traffic-1", Some(1), ADMIN_KEY).await;
+    assert_eq!(body["recording"], true);
+    assert_eq!(body["exchanges"].as_array().unwrap().len(), 1);
+    assert_eq!(body["exchanges"][0]["endpoint"], "Rate");
+
+    // Nothing from other flows
+    let other = recorded("corr-traffic-2", 1).await;
+    assert_eq!(other.len(), 1, "{:?}", other);
+    assert_eq!(other[0]["endpoint"], "Balance");
+    let (_, body) = traffic("corr-nobody", None, ADMIN_KEY).await;
+    assert_eq!(body["exchanges"], serde_json::json!([]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recorded_requests_carry_no_credentials() {
+    app().await;
+    say_in_flow("+2348090002953", "corr-traffic-3", "balance").await;
+
+    let exchanges = recorded("corr-traffic-3", 1).await;
+    let headers = &exchanges[0]["request_headers"];
+    assert_eq!(headers["x-signature"], "[redacted]", "{}", headers);
+    assert!(headers["x-timestamp"].is_string(), "{}", headers);
+    assert_eq!(headers["x-service"], "whatsapp-bot");
+
+    let log = std::fs::read_to_string(common::scratch_dir().join("backend_traffic.log"))
+        .expect("traffic log");
+    for secret in [HMAC_KEY, TWILIO_TOKEN, ADMIN_KEY] {
+        assert!(!log.contains(secret), "{} recorded", secret);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_traffic_needs_the_admin_key() {
+    let (status, _) = traffic("corr-traffic-1", None, "wrong-key").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
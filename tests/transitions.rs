@@ -0,0 +1,184 @@
+//! Each session's recent state transitions, as a scripted flow through `/debug/simulate`
+//! leaves them on `/admin/sessions/{phone}` and in `/admin/transitions`.
+
+mod common;
+
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+
+const ADMIN_KEY: &str = "transitions-admin-key";
+
+async fn app() -> &'static str {
+    common::app_with(|| {
+        // SAFETY: runs before the server starts, and before any test reads them
+        unsafe {
+            std::env::set_var("DEBUG_SIMULATOR", "1");
+            std::env::set_var("ADMIN_API_KEY", ADMIN_KEY);
+            std::env::set_var("SIMULATOR_SETTLE_SECS", "5");
+        }
+    })
+    .await
+}
+
+async fn say(phone: &str, body: &str) {
+    let response = reqwest::Client::new()
+        .post(format!("{}/debug/simulate", app().await))
+        .header("x-admin-key", ADMIN_KEY)
+        .json(&json!({ "phone": phone, "body": body }))
+        .send()
+        .await
+        .expect("simulator answers");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+async fn admin_get(key: &str, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}{}", app().await, path))
+        .header("x-admin-key", key)
+        .send()
+        .await
+        .expect("admin answers")
+}
+
+/// `(from, to, event, trigger)` for each transition `phone` has taken, oldest first.
+async fn history(phone: &str) -> Vec<(String, String, String, String)> {
+    let path = format!("/admin/sessions/{}", phone.replace('+', "%2B"));
+    let response = admin_get(ADMIN_KEY, &path).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.expect("JSON");
+    body["session"]["transitions"]
+        .as_array()
+        .expect("transitions")
+        .iter()
+        .map(|taken| {
+            assert!(taken["at"].is_string(), "{}", taken);
+            let field = |name: &str| taken[name].as_str().unwrap_or_default().to_string();
+            (field("from"), field("to"), field("event"), field("trigger"))
+        })
+        .collect()
+}
+
+fn edge(from: &str, to: &str, event: &str, trigger: &str) -> (String, String, String, String) {
+    (from.into(), to.into(), event.into(), trigger.into())
+}
+
+async fn edge_counts() -> Value {
+    let response = admin_get(ADMIN_KEY, "/admin/transitions").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.expect("JSON")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_withdrawal_leaves_each_step_and_what_triggered_it() {
+    let phone = "+2348090003301";
+    for message in [
+        "create",
+        "trans_ada",
+        "withdraw 10 USDT",
+        "confirm",
+        "Opay, 0123456789",
+        "yes",
+    ] {
+        say(phone, message).await;
+    }
+
+    assert_eq!(
+        history(phone).await,
+        [
+            edge(
+                "Initial",
+                "AccountCreation",
+                "AccountCreationStarted",
+                "command:create"
+            ),
+            edge(
+                "AccountCreation",
+                "Initial",
+                "AccountCreated",
+                "reply:AccountCreation"
+            ),
+            edge(
+                "Initial",
+                "OfframpConfirmation",
+                "QuoteShown",
+                "command:withdraw"
+            ),
+            edge(
+                "OfframpConfirmation",
+                "BankDetailsEntry",
+                "SavedBankMissing",
+                "reply:OfframpConfirmation"
+            ),
+            edge(
+                "BankDetailsEntry",
+                "BankDetailsConfirmation",
+                "BankDetailsVerified",
+                "reply:BankDetailsEntry"
+            ),
+            edge(
+                "BankDetailsConfirmation",
+                "Initial",
+                "Reset",
+                "reply:BankDetailsConfirmation"
+            ),
+        ]
+    );
+
+    // Each edge it took is counted
+    let counts = edge_counts().await;
+    for taken in [
+        "Initial->AccountCreation",
+        "AccountCreation->Initial",
+        "Initial->OfframpConfirmation",
+        "OfframpConfirmation->BankDetailsEntry",
+        "BankDetailsEntry->BankDetailsConfirmation",
+        "BankDetailsConfirmation->Initial",
+    ] {
+        assert!(counts[taken].as_u64() >= Some(1), "{} in {}", taken, counts);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_stored_history_keeps_the_latest_twenty() {
+    let phone = "+2348090003302";
+    say(phone, "create").await;
+    say(phone, "trans_obi").await;
+    // Two transitions a round, well past the bound
+    for _ in 0..12 {
+        say(phone, "withdraw 10 USDT").await;
+        say(phone, "cancel").await;
+    }
+
+    let history = history(phone).await;
+    assert_eq!(history.len(), 20);
+    // The account creation has dropped off the front
+    assert!(
+        history
+            .iter()
+            .all(|(from, to, ..)| from != "AccountCreation" && to != "AccountCreation")
+    );
+    assert_eq!(
+        history[0],
+        edge(
+            "Initial",
+            "OfframpConfirmation",
+            "QuoteShown",
+            "command:withdraw"
+        )
+    );
+    assert_eq!(
+        history[19],
+        edge(
+            "OfframpConfirmation",
+            "Initial",
+            "Reset",
+            "reply:OfframpConfirmation"
+        )
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_edge_counts_need_the_admin_key() {
+    let response = admin_get("not-the-key", "/admin/transitions").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,205 @@
+//! What a send does with Twilio's reply, against a stand-in Messages API served
+//! in-process that answers each recipient with recorded replies from
+//! `tests/fixtures/twilio/send_*.json`.
+
+mod common;
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex, OnceLock};
+
+use actix_web::http::StatusCode;
+use actix_web::{App, HttpResponse, HttpServer, web};
+use chrono::Utc;
+use kharon_pay_whatsapp::twilio::{self, SendError};
+use kharon_pay_whatsapp::window::{self, Notice};
+use kharon_pay_whatsapp::{keywords, prefs};
+use serde_json::Value;
+
+/// Every form posted to the stand-in, in order.
+static POSTED: Mutex<Vec<HashMap<String, String>>> = Mutex::new(Vec::new());
+
+/// The replies still to give each `To`, by fixture name; the last one repeats.
+static SCRIPTS: LazyLock<Mutex<HashMap<String, VecDeque<&'static str>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn fixture(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/twilio")
+        .join(format!("send_{}.json", name));
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+async fn messages(form: web::Form<HashMap<String, String>>) -> HttpResponse {
+    let form = form.into_inner();
+    let name = {
+        let mut scripts = SCRIPTS.lock().unwrap();
+        let script = scripts.get_mut(&form["To"]).expect("a scripted recipient");
+        if script.len() > 1 {
+            script.pop_front().unwrap()
+        } else {
+            script[0]
+        }
+    };
+    POSTED.lock().unwrap().push(form);
+
+    let body = fixture(name);
+    let reply: Value = serde_json::from_str(&body).expect("fixture JSON");
+    let status = reply["status"]
+        .as_u64()
+        .map_or(StatusCode::CREATED, |status| {
+            StatusCode::from_u16(status as u16).expect("an HTTP status")
+        });
+    let mut response = HttpResponse::build(status);
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        response.insert_header(("Retry-After", "0"));
+    }
+    response.content_type("application/json").body(body)
+}
+
+/// Serves the stand-in and points sends at it, once per test binary.
+fn twilio_api() {
+    static STARTED: OnceLock<()> = OnceLock::new();
+    STARTED.get_or_init(|| {
+        common::setup();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+        let url = format!(
+            "http://{}/2010-04-01/Accounts/ACtest/Messages.json",
+            listener.local_addr().expect("bound")
+        );
+        // SAFETY: set before the server thread starts and before any message is sent
+        unsafe {
+            std::env::set_var("TWILIO_DRY_RUN", "0");
+            std::env::set_var("T_API_URL", url);
+            std::env::set_var("T_ACCOUNT_SID", "ACtest");
+            std::env::set_var("T_AUTH_TOKEN", "twilio-send-token");
+            std::env::set_var("T_REMINDER_TEMPLATE_SID", "HXreminder");
+        }
+        std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                HttpServer::new(|| {
+                    App::new().route(
+                        "/2010-04-01/Accounts/ACtest/Messages.json",
+                        web::post().to(messages),
+                    )
+                })
+                .listen(listener)
+                .expect("listening")
+                .disable_signals()
+                .run()
+                .await
+            })
+        });
+    });
+}
+
+/// Has the stand-in answer `phone` with `replies` in turn.
+fn script(phone: &str, replies: &[&'static str]) {
+    twilio_api();
+    SCRIPTS.lock().unwrap().insert(
+        format!("whatsapp:{}", phone),
+        replies.iter().copied().collect(),
+    );
+}
+
+/// The forms posted for `phone` so far.
+fn posted_to(phone: &str) -> Vec<HashMap<String, String>> {
+    let to = format!("whatsapp:{}", phone);
+    POSTED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|form| form["To"] == to)
+        .cloned()
+        .collect()
+}
+
+async fn send(phone: &str) -> Result<Option<String>, SendError> {
+    twilio::try_send_twilio_content(phone, "Hello", None).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_accepted_message_returns_its_sid() {
+    let phone = "+2348090003401";
+    script(phone, &["accepted"]);
+    assert_eq!(
+        send(phone).await,
+        Ok(Some("SM0123456789abcdef0123456789abcdef".to_string()))
+    );
+    let posted = posted_to(phone);
+    assert_eq!(posted.len(), 1);
+    assert_eq!(posted[0]["Body"], "Hello");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_opted_out_recipient_is_not_messaged_again() {
+    let phone = "+2348090003402";
+    script(phone, &["opted_out"]);
+    assert_eq!(send(phone).await, Err(SendError::OptedOut));
+    assert!(keywords::opted_out(phone).await);
+
+    assert_eq!(send(phone).await, Err(SendError::OptedOut));
+    assert_eq!(posted_to(phone).len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_closed_window_closes_ours_and_the_notice_goes_as_its_template() {
+    let phone = "+2348090003403";
+    script(phone, &["outside_window", "accepted"]);
+    // We think it's open; WhatsApp knows better
+    window::record_inbound(phone, Utc::now());
+
+    twilio::send_notice(phone, "Still there?", Notice::Reminder).await;
+    assert!(!window::within_session_window(phone));
+    let posted = posted_to(phone);
+    assert_eq!(posted.len(), 2, "{:?}", posted);
+    assert_eq!(posted[0]["Body"], "Still there?");
+    assert_eq!(posted[1]["ContentSid"], "HXreminder");
+    assert!(!posted[1].contains_key("Body"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_rate_limit_is_retried_then_given_up() {
+    let phone = "+2348090003404";
+    script(phone, &["rate_limited"]);
+    assert_eq!(send(phone).await, Err(SendError::RateLimited));
+    assert_eq!(posted_to(phone).len(), 3);
+
+    // One that clears goes through on the retry
+    let phone = "+2348090003407";
+    script(phone, &["rate_limited", "accepted"]);
+    assert!(send(phone).await.is_ok_and(|sid| sid.is_some()));
+    assert_eq!(posted_to(phone).len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_invalid_number_is_flagged_and_skipped() {
+    let phone = "+2348090003405";
+    script(phone, &["invalid_number"]);
+    assert_eq!(send(phone).await, Err(SendError::InvalidNumber));
+    assert!(prefs::load(phone).await.invalid_number);
+
+    assert_eq!(send(phone).await, Err(SendError::InvalidNumber));
+    assert_eq!(posted_to(phone).len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn any_other_code_comes_back_with_its_details() {
+    let phone = "+2348090003406";
+    script(phone, &["unknown_code"]);
+    let error = send(phone).await.unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            SendError::Twilio { code: Some(21408), more_info: Some(more_info), .. }
+                if more_info == "https://www.twilio.com/docs/errors/21408"
+        ),
+        "{:?}",
+        error
+    );
+    // Nothing about the recipient changes, so the next send is tried
+    assert!(!keywords::opted_out(phone).await);
+    assert!(!prefs::load(phone).await.invalid_number);
+    assert!(send(phone).await.is_err());
+    assert_eq!(posted_to(phone).len(), 2);
+}
@@ -0,0 +1,144 @@
+//! `verify <n>` against the mock backend, whose banks all verify to `MOCK ACCOUNT HOLDER`
+//! except account numbers starting `000`, which they can't find.
+
+mod common;
+
+use std::time::Duration;
+
+use kharon_pay_whatsapp::conversation::dispatch_message;
+use kharon_pay_whatsapp::model::{UserSessions, UserState};
+use kharon_pay_whatsapp::twilio;
+use serde_json::{Value, json};
+
+use common::mock_post;
+
+async fn say(session: &mut UserSessions, message: &str) -> String {
+    let (replies, _) = twilio::capture(dispatch_message(message, session), Duration::ZERO).await;
+    replies
+        .into_iter()
+        .map(|reply| reply.body)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `phone` with one saved Opay account, saved under `account_name`.
+async fn with_saved_bank(phone: &str, account_number: &str, account_name: &str) -> UserSessions {
+    common::app().await;
+    let digits = phone.trim_start_matches('+');
+    mock_post(
+        "/banks",
+        json!({
+            "phone": digits,
+            "bank_save_id": format!("save-{}", digits),
+            "bank_name": "Opay",
+            "account_number": account_number,
+            "account_name": account_name,
+            "bank_code": "999",
+        }),
+    )
+    .await;
+    let mut session = UserSessions::new(phone);
+    session.controller_address = Some("0x0test".to_string());
+    session
+}
+
+/// The banks the backend has saved for `phone`.
+async fn saved_banks(phone: &str) -> Vec<Value> {
+    let url = format!(
+        "{}/mock-backend/banks?phone={}",
+        common::app().await,
+        phone.trim_start_matches('+')
+    );
+    let body: Value = reqwest::get(url).await.unwrap().json().await.unwrap();
+    body["data"]["banks"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// The prompt for withdrawing to the saved bank.
+async fn withdrawal_prompt(session: &mut UserSessions) -> String {
+    say(session, "withdraw 10 USDT").await;
+    let prompt = say(session, "confirm").await;
+    assert_eq!(session.state, UserState::SavedBankConfirmation);
+    prompt
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_unchanged_account_checks_out_and_withdrawals_do_not_warn() {
+    let phone = "+2348090002701";
+    let mut session = with_saved_bank(phone, "0123456789", "MOCK ACCOUNT HOLDER").await;
+
+    let reply = say(&mut session, "verify 1").await;
+    assert!(reply.contains("checks out"), "{}", reply);
+    assert!(reply.contains("*MOCK ACCOUNT HOLDER*"), "{}", reply);
+    assert_eq!(session.state, UserState::Initial);
+
+    let prompt = withdrawal_prompt(&mut session).await;
+    assert!(!prompt.contains("failed its last check"), "{}", prompt);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_changed_name_is_saved_over_the_old_one_once_confirmed() {
+    let phone = "+2348090002702";
+    let mut session = with_saved_bank(phone, "0123456789", "ADA OBI").await;
+
+    let reply = say(&mut session, "verify 1").await;
+    assert!(
+        reply.contains("now shows") && reply.contains("*MOCK ACCOUNT HOLDER*"),
+        "{}",
+        reply
+    );
+    assert!(reply.contains("saved as *ADA OBI*"), "{}", reply);
+    assert_eq!(session.state, UserState::BankNameUpdate);
+
+    let reply = say(&mut session, "yes").await;
+    assert!(reply.starts_with("✅ Updated."), "{}", reply);
+    assert_eq!(session.state, UserState::Initial);
+    let banks = saved_banks(phone).await;
+    assert_eq!(banks.len(), 1, "{:?}", banks);
+    assert_eq!(banks[0]["account_name"], "MOCK ACCOUNT HOLDER");
+
+    let prompt = withdrawal_prompt(&mut session).await;
+    assert!(!prompt.contains("failed its last check"), "{}", prompt);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_changed_name_kept_as_it_was_is_flagged() {
+    let phone = "+2348090002703";
+    let mut session = with_saved_bank(phone, "0123456789", "ADA OBI").await;
+    say(&mut session, "verify 1").await;
+
+    let reply = say(&mut session, "no").await;
+    assert!(reply.contains("Kept your saved details"), "{}", reply);
+    assert_eq!(saved_banks(phone).await[0]["account_name"], "ADA OBI");
+
+    let prompt = withdrawal_prompt(&mut session).await;
+    assert!(
+        prompt.starts_with("⚠️ This account failed its last check"),
+        "{}",
+        prompt
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_account_the_bank_cannot_find_is_flagged_for_withdrawals() {
+    let phone = "+2348090002704";
+    let mut session = with_saved_bank(phone, "0001234567", "ADA OBI").await;
+
+    let reply = say(&mut session, "verify 1").await;
+    assert!(reply.contains("no longer checks out"), "{}", reply);
+    assert_eq!(session.state, UserState::Initial);
+
+    let prompt = withdrawal_prompt(&mut session).await;
+    assert!(
+        prompt.starts_with("⚠️ This account failed its last check"),
+        "{}",
+        prompt
+    );
+    say(&mut session, "no").await;
+
+    let reply = say(&mut session, "verify").await;
+    assert!(reply.contains("Which bank should we check?"), "{}", reply);
+    assert!(reply.contains("Reply `verify [number]`"), "{}", reply);
+}
@@ -0,0 +1,326 @@
+//! Golden Twilio payloads, as Twilio posts them, classified and run through the webhook.
+
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{App, http::StatusCode, test as actix_test, web};
+use kharon_pay_whatsapp::i18n::{self, Language};
+use kharon_pay_whatsapp::inbound::{self, Inbound};
+use kharon_pay_whatsapp::keywords;
+use kharon_pay_whatsapp::metrics;
+use kharon_pay_whatsapp::model::UserSessions;
+use kharon_pay_whatsapp::request_body::FORM;
+use kharon_pay_whatsapp::store::{MemorySessionStore, SessionStore};
+use kharon_pay_whatsapp::twilio::{self, CapturedMessage};
+use kharon_pay_whatsapp::webhook;
+
+fn classify(name: &str) -> Inbound {
+    let form: HashMap<String, String> =
+        serde_urlencoded::from_bytes(&common::twilio_fixture(name)).expect("fixture decodes");
+    inbound::classify(&form)
+}
+
+fn text(phone: &str, body: &str) -> Inbound {
+    Inbound::Text {
+        phone: phone.to_string(),
+        body: body.to_string(),
+    }
+}
+
+#[test]
+fn typed_messages_carry_the_bare_number_and_body() {
+    assert_eq!(classify("text_message"), text("+2348010000001", "help"));
+    assert_eq!(
+        classify("text_with_symbols"),
+        text("+2348010000002", "withdraw 50 USDT ₦")
+    );
+}
+
+#[test]
+fn taps_read_as_what_the_user_would_have_typed() {
+    assert_eq!(
+        classify("list_reply"),
+        Inbound::Interactive {
+            phone: "+2348010000003".to_string(),
+            body: "2".to_string(),
+        }
+    );
+    assert_eq!(
+        classify("button_reply"),
+        Inbound::Interactive {
+            phone: "+2348010000004".to_string(),
+            body: "confirm".to_string(),
+        }
+    );
+}
+
+#[test]
+fn media_without_a_caption_is_media() {
+    assert_eq!(
+        classify("media_only"),
+        Inbound::Media {
+            phone: "+2348010000005".to_string()
+        }
+    );
+}
+
+#[test]
+fn only_final_delivery_statuses_are_recorded() {
+    assert_eq!(
+        classify("status_delivered"),
+        Inbound::StatusCallback {
+            message_sid: Some("SM66666666666666666666666666666666".to_string()),
+            status: "delivered".to_string(),
+            record: true,
+        }
+    );
+    assert_eq!(
+        classify("status_sent"),
+        Inbound::StatusCallback {
+            message_sid: Some("SM77777777777777777777777777777777".to_string()),
+            status: "sent".to_string(),
+            record: true,
+        }
+    );
+    assert_eq!(
+        classify("status_queued"),
+        Inbound::StatusCallback {
+            message_sid: Some("SM88888888888888888888888888888888".to_string()),
+            status: "queued".to_string(),
+            record: false,
+        }
+    );
+}
+
+#[test]
+fn advanced_opt_out_events_are_not_commands() {
+    assert_eq!(
+        classify("opt_out_stop"),
+        Inbound::OptOut {
+            phone: "+2348010000009".to_string(),
+            opted_out: true,
+        }
+    );
+    assert_eq!(
+        classify("opt_in_start"),
+        Inbound::OptOut {
+            phone: "+2348010000010".to_string(),
+            opted_out: false,
+        }
+    );
+}
+
+#[test]
+fn calls_and_reactions_are_unknown_with_their_shape() {
+    let Inbound::Unknown(call) = classify("voice_call") else {
+        panic!("a voice call should be unknown");
+    };
+    assert_eq!(call.phone.as_deref(), Some("+2348010000012"));
+    assert!(call.fields.contains(&"CallSid".to_string()));
+    assert!(call.hints.contains(&("CallStatus".to_string(), "ringing".to_string())));
+
+    let Inbound::Unknown(reaction) = classify("reaction") else {
+        panic!("a reaction should be unknown");
+    };
+    assert_eq!(reaction.phone.as_deref(), Some("+2348010000013"));
+    assert!(reaction
+        .hints
+        .contains(&("MessageType".to_string(), "reaction".to_string())));
+}
+
+#[test]
+fn fields_from_a_newer_api_are_unknown_not_a_blank_message() {
+    let Inbound::Unknown(poll) = classify("future_event") else {
+        panic!("an unfamiliar message type should be unknown");
+    };
+    assert_eq!(poll.phone.as_deref(), Some("+2348010000015"));
+    assert!(poll.fields.contains(&"PollId".to_string()));
+    assert!(poll
+        .hints
+        .contains(&("MessageType".to_string(), "poll_response".to_string())));
+    // Never the values of fields we don't know
+    assert!(poll.hints.iter().all(|(field, _)| field != "PollId"));
+}
+
+/// Posts a fixture to `/webhook` and returns the status and what would have been sent.
+async fn post(name: &str) -> (StatusCode, Vec<CapturedMessage>) {
+    post_to(
+        Arc::new(MemorySessionStore::new()),
+        common::twilio_fixture(name),
+    )
+    .await
+}
+
+/// Posts `payload` to a `/webhook` backed by `sessions`.
+async fn post_to(
+    sessions: Arc<dyn SessionStore>,
+    payload: Vec<u8>,
+) -> (StatusCode, Vec<CapturedMessage>) {
+    common::setup();
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::from(sessions))
+            .route("/webhook", web::post().to(webhook::handle_twilio_webhook)),
+    )
+    .await;
+    let req = actix_test::TestRequest::post()
+        .uri("/webhook")
+        .insert_header(("content-type", FORM))
+        .set_payload(payload)
+        .to_request();
+    let (res, sent) = twilio::capture(actix_test::call_service(&app, req), Duration::from_secs(2)).await;
+    (res.status(), sent)
+}
+
+#[actix_web::test]
+async fn a_typed_command_is_answered_on_the_senders_number() {
+    let (status, sent) = post("text_message").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    assert_eq!(sent[0].to, "+2348010000001");
+    let help = i18n::help(Language::En, false, "+2348010000001");
+    assert!(sent[0].body.starts_with(&help), "{}", sent[0].body);
+}
+
+#[actix_web::test]
+async fn a_list_tap_is_handled_as_its_row_number() {
+    let (status, sent) = post("list_reply").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    assert_eq!(sent[0].to, "+2348010000003");
+}
+
+#[actix_web::test]
+async fn callbacks_and_media_get_no_reply() {
+    for name in [
+        "media_only",
+        "status_delivered",
+        "status_queued",
+        "voice_call",
+        "reaction",
+        "future_event",
+    ] {
+        let (status, sent) = post(name).await;
+        assert_eq!(status, StatusCode::OK, "{}", name);
+        assert!(sent.is_empty(), "{} sent {:?}", name, sent);
+    }
+}
+
+#[actix_web::test]
+async fn unknown_payloads_are_counted_so_new_shapes_get_noticed() {
+    let before = metrics::INBOUND.get(&["unknown"]);
+    for name in ["voice_call", "future_event"] {
+        let (status, sent) = post(name).await;
+        assert_eq!(status, StatusCode::OK, "{}", name);
+        assert!(sent.is_empty(), "{} sent {:?}", name, sent);
+    }
+    // Other tests here post unknown payloads too
+    assert!(metrics::INBOUND.get(&["unknown"]) >= before + 2);
+}
+
+#[actix_web::test]
+async fn a_stop_event_opts_the_sender_out_silently() {
+    let (status, sent) = post("opt_out_stop").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(sent.is_empty(), "{:?}", sent);
+    assert!(keywords::opted_out("+2348010000009").await);
+}
+
+#[actix_web::test]
+async fn a_twilio_retry_is_answered_once() {
+    let (status, sent) = post("text_retry").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+
+    // The same MessageSid again, as when our first answer was too slow for Twilio
+    let (status, sent) = post("text_retry").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(sent.is_empty(), "{:?}", sent);
+}
+
+#[actix_web::test]
+async fn start_welcomes_account_holders_with_their_own_menu() {
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let mut holder = UserSessions::new("+2348010000020");
+    holder.controller_address = Some("0x0test".to_string());
+    sessions.save(&holder).unwrap();
+
+    for (phone, has_account) in [("+2348010000020", true), ("+2348010000021", false)] {
+        let payload = serde_urlencoded::to_string([
+            ("MessageSid", format!("SM{}", phone.trim_start_matches('+'))),
+            ("From", format!("whatsapp:{}", phone)),
+            ("To", "whatsapp:+14155238886".to_string()),
+            ("Body", "START".to_string()),
+        ])
+        .unwrap();
+        let (status, sent) = post_to(sessions.clone(), payload.into_bytes()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(sent.len(), 1, "{:?}", sent);
+        assert_eq!(sent[0].body, i18n::welcome(Language::En, has_account, phone));
+    }
+}
+
+/// Posts a typed `body` from `phone`, as Twilio would, to a `/webhook` backed by
+/// `sessions`, and returns what was sent back.
+async fn post_text(
+    sessions: &Arc<dyn SessionStore>,
+    phone: &str,
+    body: &str,
+) -> Vec<CapturedMessage> {
+    let sid = format!(
+        "SM{}{:x}",
+        phone.trim_start_matches('+'),
+        body.bytes().map(u64::from).sum::<u64>()
+    );
+    let payload = serde_urlencoded::to_string([
+        ("MessageSid", sid),
+        ("From", format!("whatsapp:{}", phone)),
+        ("To", "whatsapp:+14155238886".to_string()),
+        ("Body", body.to_string()),
+    ])
+    .unwrap();
+    let (status, sent) = post_to(sessions.clone(), payload.into_bytes()).await;
+    assert_eq!(status, StatusCode::OK);
+    sent
+}
+
+#[actix_web::test]
+async fn a_first_greeting_is_answered_in_its_language_until_one_is_chosen() {
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    for (phone, greeting, language) in [
+        ("+2348010000030", "Bonjour", Language::Fr),
+        ("+2348010000031", "Habari yako", Language::Sw),
+        ("+2348010000032", "Hello", Language::En),
+    ] {
+        let sent = post_text(&sessions, phone, greeting).await;
+        let welcome = i18n::welcome(language, false, phone);
+        assert!(
+            sent[0].body.starts_with(&welcome),
+            "{}: {:?}",
+            greeting,
+            sent
+        );
+    }
+
+    // The guess sticks for later messages, commands included
+    let sent = post_text(&sessions, "+2348010000030", "help").await;
+    let help = i18n::help(Language::Fr, false, "+2348010000030");
+    assert!(sent[0].body.starts_with(&help), "{:?}", sent);
+
+    // Until they choose, and then a greeting in another language doesn't move it
+    post_text(&sessions, "+2348010000030", "language english").await;
+    let sent = post_text(&sessions, "+2348010000030", "Habari").await;
+    let welcome = i18n::welcome(Language::En, false, "+2348010000030");
+    assert!(sent[0].body.starts_with(&welcome), "{:?}", sent);
+}
+
+#[actix_web::test]
+async fn a_first_command_is_answered_in_english() {
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let sent = post_text(&sessions, "+2348010000033", "help").await;
+    let help = i18n::help(Language::En, false, "+2348010000033");
+    assert!(sent[0].body.starts_with(&help), "{:?}", sent);
+}
@@ -0,0 +1,63 @@
+//! Messages we start ourselves, inside and outside WhatsApp's 24-hour window, with a
+//! completion template configured and no reminder template.
+
+mod common;
+
+use std::time::Duration;
+
+use chrono::Utc;
+use kharon_pay_whatsapp::twilio::{self, CapturedMessage};
+use kharon_pay_whatsapp::window::{self, Notice};
+
+const COMPLETION_SID: &str = "HXcompletion";
+
+fn setup() {
+    static CONFIGURED: std::sync::Once = std::sync::Once::new();
+    CONFIGURED.call_once(|| {
+        common::setup();
+        // SAFETY: runs once, before any test sends anything
+        unsafe { std::env::set_var("T_COMPLETION_TEMPLATE_SID", COMPLETION_SID) };
+    });
+}
+
+/// What goes out for `notice` to `phone`, whose last message was `hours_ago`.
+async fn notify(phone: &str, hours_ago: i64, notice: Notice) -> Vec<CapturedMessage> {
+    setup();
+    window::record_inbound(phone, Utc::now() - chrono::Duration::hours(hours_ago));
+    let message = "✅ Your withdrawal has completed";
+    let (_, sent) =
+        twilio::capture(twilio::send_notice(phone, message, notice), Duration::ZERO).await;
+    sent
+}
+
+#[tokio::test]
+async fn inside_the_window_notices_go_out_as_freeform() {
+    for notice in [Notice::Completion, Notice::Reminder] {
+        let sent = notify("+2348090002311", 23, notice).await;
+        assert_eq!(sent.len(), 1, "{:?}", sent);
+        assert_eq!(sent[0].template_sid, None);
+        assert_eq!(sent[0].body, "✅ Your withdrawal has completed");
+    }
+}
+
+#[tokio::test]
+async fn outside_the_window_a_notice_goes_out_as_its_template() {
+    let sent = notify("+2348090002312", 25, Notice::Completion).await;
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    assert_eq!(sent[0].template_sid.as_deref(), Some(COMPLETION_SID));
+    assert_eq!(sent[0].body, "✅ Your withdrawal has completed");
+}
+
+#[tokio::test]
+async fn at_exactly_24_hours_the_window_has_closed() {
+    let sent = notify("+2348090002313", 24, Notice::Completion).await;
+    assert_eq!(sent[0].template_sid.as_deref(), Some(COMPLETION_SID));
+}
+
+#[tokio::test]
+async fn outside_the_window_without_a_template_nothing_is_sent() {
+    for notice in [Notice::Reminder, Notice::Feedback] {
+        let sent = notify("+2348090002314", 25, notice).await;
+        assert!(sent.is_empty(), "{:?}: {:?}", notice, sent);
+    }
+}